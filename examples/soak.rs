@@ -0,0 +1,28 @@
+extern crate libbeaglebone;
+
+use libbeaglebone::prelude::*;
+use libbeaglebone::soak::SoakTest;
+use std::env;
+use std::process;
+use std::time::Duration;
+
+fn main() {
+  // Run for the number of seconds given on the command line, or a minute.
+  let seconds = env::args().nth(1).and_then(|s| s.parse().ok()).unwrap_or(60);
+
+  // Adjust the devices to the ones on the board under test.
+  // Make sure nothing is connected to them that minds being toggled.
+  let mut test = SoakTest::new();
+  test.add_gpio(GPIO::new(GPIO_P8_11));
+  test.add_gpio(GPIO::new(GPIO_P8_12));
+  test.add_pwm(PWM::new(0, 0));
+  test.set_outlier_threshold(Duration::from_micros(500));
+
+  let report = test.run(Duration::from_secs(seconds)).unwrap();
+  println!("{}", report);
+
+  // Let scripts tell whether the board passed.
+  if !report.passed() {
+    process::exit(1);
+  }
+}
@@ -0,0 +1,356 @@
+//! The actuator channel module.
+//!
+//! When several threads of a control application command the same outputs,
+//! e.g. a control loop setting a motor's duty cycle while a supervisor
+//! disables it, every command becomes a sysfs write, and a fast loop easily
+//! issues more of them than matter.
+//! An `ActuatorChannel` owns the outputs and writes to them from a single
+//! thread.
+//! Threads send commands through `ActuatorSender`s, which never block on
+//! sysfs:
+//!
+//! * Only the latest command of each kind for an output is kept, so commands
+//!   that are superseded before they are written are dropped, and the queue
+//!   never holds more than one command per output and kind.
+//! * Commands that wouldn't change what was written last are skipped.
+//!
+//! ```no_run
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::actuator::ActuatorChannel;
+//! use std::thread;
+//!
+//! let mut pwm = PWM::new(0, 0);
+//! pwm.set_export(DeviceState::Exported).unwrap();
+//! pwm.set_period(500_000).unwrap();
+//!
+//! let channel = ActuatorChannel::new();
+//! let motor = channel.add_pwm(pwm);
+//!
+//! let sender = channel.sender();
+//! let control = thread::spawn(move || for i in 0..10_000 {
+//!   sender.set_duty_cycle(motor, (i % 100) as f32).unwrap();
+//! });
+//!
+//! channel.sender().set_enabled(motor, true).unwrap();
+//! control.join().unwrap();
+//!
+//! // Wait until the last duty cycle has been written.
+//! channel.flush();
+//! for error in channel.take_errors() {
+//!   println!("{}", error);
+//! }
+//! ```
+//!
+//! For every output, the period is written before the duty cycle and the
+//! duty cycle before enabling it, whatever order the commands were sent in.
+
+use errors::*;
+use gpio::PinState;
+use hal::{DigitalPin, PwmOutput};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::mem;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+
+/// Identifies an output of an `ActuatorChannel`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ActuatorId(usize);
+
+/// A command for an output.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+  /// Sets the period of a PWM output in nanoseconds.
+  Period(u32),
+  /// Sets the duty cycle of a PWM output as a percentage of the period.
+  DutyCycle(f32),
+  /// Enables or disables a PWM output.
+  Enabled(bool),
+  /// Drives a digital output.
+  State(PinState),
+}
+
+/// The kinds of commands, in the order they are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+enum Slot {
+  Period,
+  DutyCycle,
+  Enabled,
+  State,
+}
+
+impl Command {
+  fn slot(&self) -> Slot {
+    match *self {
+      Command::Period(_) => Slot::Period,
+      Command::DutyCycle(_) => Slot::DutyCycle,
+      Command::Enabled(_) => Slot::Enabled,
+      Command::State(_) => Slot::State,
+    }
+  }
+}
+
+enum Actuator {
+  Digital(Box<dyn DigitalPin + Send>),
+  Pwm(Box<dyn PwmOutput + Send>),
+}
+
+impl fmt::Debug for Actuator {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match *self {
+      Actuator::Digital(ref pin) => write!(f, "Digital({})", pin.pin_name()),
+      Actuator::Pwm(_) => write!(f, "Pwm"),
+    }
+  }
+}
+
+impl Actuator {
+  fn apply(&mut self, command: Command) -> Result<()> {
+    match (self, command) {
+      (&mut Actuator::Pwm(ref mut pwm), Command::Period(period_ns)) => pwm.set_period_ns(period_ns),
+      (&mut Actuator::Pwm(ref mut pwm), Command::DutyCycle(percentage)) => {
+        pwm.set_duty_cycle_percent(percentage)
+      }
+      (&mut Actuator::Pwm(ref mut pwm), Command::Enabled(enabled)) => pwm.set_enabled(enabled),
+      (&mut Actuator::Digital(ref mut pin), Command::State(state)) => pin.set_state(state),
+      (_, command) => bail!("{:?} doesn't apply to this output", command),
+    }
+  }
+
+  fn is_pwm(&self) -> bool {
+    match *self {
+      Actuator::Digital(_) => false,
+      Actuator::Pwm(_) => true,
+    }
+  }
+}
+
+#[derive(Debug)]
+struct Queue {
+  // Whether each output is a PWM output, so senders can check commands
+  // without waiting for the writer to release the outputs.
+  is_pwm: Vec<bool>,
+  pending: BTreeMap<(ActuatorId, Slot), Command>,
+  // Whether the writer is writing a batch taken from `pending`.
+  busy: bool,
+  running: bool,
+  errors: Vec<Error>,
+}
+
+#[derive(Debug)]
+struct Shared {
+  queue: Mutex<Queue>,
+  changed: Condvar,
+  actuators: Mutex<Vec<Actuator>>,
+}
+
+impl Shared {
+  fn queue(&self) -> MutexGuard<'_, Queue> {
+    self.queue.lock().unwrap_or_else(|e| e.into_inner())
+  }
+
+  fn actuators(&self) -> MutexGuard<'_, Vec<Actuator>> {
+    self.actuators.lock().unwrap_or_else(|e| e.into_inner())
+  }
+}
+
+/// Serializes commands for a set of outputs into a single writer thread.
+///
+/// Dropping the channel writes the commands still pending and stops the
+/// writer.
+#[derive(Debug)]
+pub struct ActuatorChannel {
+  shared: Arc<Shared>,
+  thread: Option<JoinHandle<()>>,
+}
+
+impl ActuatorChannel {
+  /// Creates a channel without any outputs and starts its writer thread.
+  pub fn new() -> ActuatorChannel {
+    let shared = Arc::new(Shared {
+      queue: Mutex::new(Queue {
+        is_pwm: Vec::new(),
+        pending: BTreeMap::new(),
+        busy: false,
+        running: true,
+        errors: Vec::new(),
+      }),
+      changed: Condvar::new(),
+      actuators: Mutex::new(Vec::new()),
+    });
+    let thread = {
+      let shared = shared.clone();
+      thread::spawn(move || write_commands(&shared))
+    };
+    ActuatorChannel {
+      shared,
+      thread: Some(thread),
+    }
+  }
+
+  /// Adds a digital output, e.g. a `GPIO` configured as an output.
+  pub fn add_digital<P: DigitalPin + Send + 'static>(&self, pin: P) -> ActuatorId {
+    self.add(Actuator::Digital(Box::new(pin)))
+  }
+
+  /// Adds a PWM output, e.g. an exported `PWM`.
+  pub fn add_pwm<P: PwmOutput + Send + 'static>(&self, pwm: P) -> ActuatorId {
+    self.add(Actuator::Pwm(Box::new(pwm)))
+  }
+
+  fn add(&self, actuator: Actuator) -> ActuatorId {
+    let mut actuators = self.shared.actuators();
+    let mut queue = self.shared.queue();
+    queue.is_pwm.push(actuator.is_pwm());
+    actuators.push(actuator);
+    ActuatorId(actuators.len() - 1)
+  }
+
+  /// Returns a sender to command the outputs with, which can be cloned and
+  /// moved to other threads.
+  pub fn sender(&self) -> ActuatorSender {
+    ActuatorSender { shared: self.shared.clone() }
+  }
+
+  /// Blocks until all commands sent so far are written.
+  pub fn flush(&self) {
+    let mut queue = self.shared.queue();
+    while queue.busy || !queue.pending.is_empty() {
+      queue = self.shared.changed.wait(queue).unwrap_or_else(|e| e.into_inner());
+    }
+  }
+
+  /// Returns and clears the errors of the writes that failed.
+  ///
+  /// A failed command is dropped, it isn't retried until it is sent again.
+  pub fn take_errors(&self) -> Vec<Error> {
+    mem::take(&mut self.shared.queue().errors)
+  }
+}
+
+impl Default for ActuatorChannel {
+  fn default() -> ActuatorChannel {
+    ActuatorChannel::new()
+  }
+}
+
+impl Drop for ActuatorChannel {
+  fn drop(&mut self) {
+    self.shared.queue().running = false;
+    self.shared.changed.notify_all();
+    if let Some(thread) = self.thread.take() {
+      let _ = thread.join();
+    }
+  }
+}
+
+/// Sends commands to the outputs of an `ActuatorChannel`.
+#[derive(Debug, Clone)]
+pub struct ActuatorSender {
+  shared: Arc<Shared>,
+}
+
+impl ActuatorSender {
+  /// Queues `command` for the output `id`, replacing a pending command of
+  /// the same kind.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the channel was dropped, or if the command doesn't apply to
+  /// the output, e.g. a duty cycle for a digital output.
+  pub fn send(&self, id: ActuatorId, command: Command) -> Result<()> {
+    let mut queue = self.shared.queue();
+    if !queue.running {
+      bail!("The actuator channel was dropped");
+    }
+    match queue.is_pwm.get(id.0) {
+      Some(&is_pwm) if is_pwm == (command.slot() != Slot::State) => {}
+      Some(_) => bail!("{:?} doesn't apply to output {:?}", command, id),
+      None => bail!("There is no output {:?}", id),
+    }
+    let _ = queue.pending.insert((id, command.slot()), command);
+    self.shared.changed.notify_all();
+    Ok(())
+  }
+
+  /// Drives the digital output `id` to `state`.
+  ///
+  /// # Errors
+  ///
+  /// See `send()`.
+  pub fn set_state(&self, id: ActuatorId, state: PinState) -> Result<()> {
+    self.send(id, Command::State(state))
+  }
+
+  /// Sets the period of the PWM output `id` in nanoseconds.
+  ///
+  /// # Errors
+  ///
+  /// See `send()`.
+  pub fn set_period(&self, id: ActuatorId, period_ns: u32) -> Result<()> {
+    self.send(id, Command::Period(period_ns))
+  }
+
+  /// Sets the duty cycle of the PWM output `id` as a percentage.
+  ///
+  /// # Errors
+  ///
+  /// See `send()`.
+  pub fn set_duty_cycle(&self, id: ActuatorId, percentage: f32) -> Result<()> {
+    self.send(id, Command::DutyCycle(percentage))
+  }
+
+  /// Enables or disables the PWM output `id`.
+  ///
+  /// # Errors
+  ///
+  /// See `send()`.
+  pub fn set_enabled(&self, id: ActuatorId, enabled: bool) -> Result<()> {
+    self.send(id, Command::Enabled(enabled))
+  }
+}
+
+/// Writes the pending commands in batches until the channel is dropped and
+/// nothing is pending anymore.
+fn write_commands(shared: &Shared) {
+  // The command written last for every output and kind.
+  let mut written: HashMap<(ActuatorId, Slot), Command> = HashMap::new();
+  loop {
+    let batch = {
+      let mut queue = shared.queue();
+      while queue.running && queue.pending.is_empty() {
+        queue = shared.changed.wait(queue).unwrap_or_else(|e| e.into_inner());
+      }
+      if queue.pending.is_empty() {
+        return;
+      }
+      queue.busy = true;
+      mem::take(&mut queue.pending)
+    };
+
+    let mut errors = Vec::new();
+    {
+      let mut actuators = shared.actuators();
+      for (key, command) in batch {
+        if written.get(&key) == Some(&command) {
+          continue;
+        }
+        match actuators[(key.0).0].apply(command) {
+          Ok(()) => {
+            let _ = written.insert(key, command);
+          }
+          Err(e) => {
+            // The output may be in any state now.
+            let _ = written.remove(&key);
+            errors.push(e);
+          }
+        }
+      }
+    }
+
+    let mut queue = shared.queue();
+    queue.errors.extend(errors);
+    queue.busy = false;
+    shared.changed.notify_all();
+  }
+}
@@ -12,25 +12,210 @@
 //! *NOTE:* the ADC inputs on the BeagleBone are limited to 1.8V.
 //! Be careful not to exceed this limit or you may damage the BeagleBone (don't
 //! ask me how I know that!).
+//!
+//! Voltages are computed from the raw values with the scale the IIO driver
+//! reports in `in_voltage_scale`, if it does, so they're right on boards
+//! with another reference voltage.
+//! On top of that, an `AdcCalibration` corrects the gain and offset
+//! measured on the individual board, e.g. as kept in a `CalibrationStore`:
+//!
+//! ```no_run
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::adc::AdcCalibration;
+//! use libbeaglebone::calibration::CalibrationStore;
+//!
+//! let store = CalibrationStore::load("/var/lib/calibration").unwrap();
+//! let mut sensor = ADC::new(AIN_0, 0.0);
+//! sensor.set_calibration(AdcCalibration::from_store(&store, "adc0"));
+//! println!("{}V", sensor.read_volts().unwrap());
+//! ```
+//!
+//! The calibration can be replaced at any time, e.g. to compensate for the
+//! temperature of the board.
 
+use board;
+use calibration::CalibrationStore;
 use errors::*;
 use pins::Pin;
+use stats::{OpCounters, OpStats};
+use std::path::Path;
 use util::*;
 
+/// The millivolts per raw count of the BeagleBone's 12-bit ADC at its 1.8V
+/// reference, used if the driver doesn't report a scale.
+pub const DEFAULT_SCALE_MV: f32 = 1800.0 / 4096.0;
+
+/// The gain and offset correcting the voltages of an ADC input.
+///
+/// The corrected voltage is the measured voltage times the gain plus the
+/// offset.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdcCalibration {
+  /// The factor the measured voltage is multiplied with.
+  pub gain: f32,
+  /// The offset added afterwards, in millivolts.
+  pub offset_mv: f32,
+}
+
+impl AdcCalibration {
+  /// Takes the calibration of `device` from `store`, from the values "gain"
+  /// and "offset" in millivolts, each defaulting to no correction.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use libbeaglebone::adc::AdcCalibration;
+  /// use libbeaglebone::calibration::CalibrationStore;
+  ///
+  /// let mut store = CalibrationStore::new();
+  /// store.set("adc0", "gain", 1.02).unwrap();
+  ///
+  /// let calibration = AdcCalibration::from_store(&store, "adc0");
+  /// assert_eq!(calibration.gain, 1.02);
+  /// assert_eq!(calibration.offset_mv, 0.0);
+  /// assert_eq!(calibration.apply(1000.0), 1020.0);
+  /// ```
+  pub fn from_store(store: &CalibrationStore, device: &str) -> AdcCalibration {
+    AdcCalibration {
+      gain: store.get_or(device, "gain", 1.0) as f32,
+      offset_mv: store.get_or(device, "offset", 0.0) as f32,
+    }
+  }
+
+  /// Returns the corrected voltage of `millivolts`.
+  pub fn apply(&self, millivolts: f32) -> f32 {
+    millivolts * self.gain + self.offset_mv
+  }
+}
+
+impl Default for AdcCalibration {
+  fn default() -> AdcCalibration {
+    AdcCalibration {
+      gain: 1.0,
+      offset_mv: 0.0,
+    }
+  }
+}
+
 /// Represents a pin configured as an ADC.
 #[derive(Debug)]
 pub struct ADC {
   adc_num: u16,
+  raw_path: Option<String>,
+  counters: OpCounters,
   scaling_factor: f32,
+  scale_mv: f32,
+  calibration: AdcCalibration,
 }
 
 impl ADC {
   /// Creates a new ADC object.
+  ///
+  /// The scale of the raw values is read from the driver, falling back to
+  /// `DEFAULT_SCALE_MV` if it doesn't report one.
   pub fn new(pin: Pin, scaling_factor: f32) -> ADC {
-    ADC {
-      adc_num: (pin as u16) - 1000,
+    let adc_num = (pin as u16) - 1000;
+    let mut adc = ADC {
+      adc_num,
+      raw_path: board::current().adc_path(adc_num).ok(),
+      counters: OpCounters::new(),
       scaling_factor: scaling_factor,
+      scale_mv: DEFAULT_SCALE_MV,
+      calibration: AdcCalibration::default(),
+    };
+    let _ = adc.refresh_scale();
+    adc
+  }
+
+  /// Returns the number of the ADC input, e.g. 6 for `AIN_6`.
+  pub fn adc_num(&self) -> u16 {
+    self.adc_num
+  }
+
+  /// Returns the ADC's operation statistics, see the `stats` module.
+  pub fn stats(&self) -> OpStats {
+    self.counters.snapshot()
+  }
+
+  /// Resets the ADC's operation statistics.
+  pub fn reset_stats(&self) {
+    self.counters.reset()
+  }
+
+  /// Returns the sysfs file of the raw ADC value, or `None` if the board has
+  /// no ADC.
+  pub fn raw_path(&self) -> Option<&str> {
+    self.raw_path.as_deref()
+  }
+
+  /// Returns the millivolts per raw count.
+  pub fn scale_mv(&self) -> f32 {
+    self.scale_mv
+  }
+
+  /// Overrides the millivolts per raw count, e.g. for an external reference
+  /// the driver doesn't know about.
+  pub fn set_scale_mv(&mut self, scale_mv: f32) {
+    self.scale_mv = scale_mv;
+  }
+
+  /// Reads the millivolts per raw count from the driver again, e.g. after
+  /// its reference was changed.
+  ///
+  /// Uses `DEFAULT_SCALE_MV` if the driver doesn't report a scale.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the reported scale can't be read or isn't positive.
+  pub fn refresh_scale(&mut self) -> Result<()> {
+    let path = match self.raw_path.as_deref().and_then(scale_path) {
+      Some(path) => path,
+      None => {
+        self.scale_mv = DEFAULT_SCALE_MV;
+        return Ok(());
+      }
+    };
+    let scale = path.as_str()
+                    .read_file()
+                    .chain_err(|| format!("Failed to read the scale of ADC #{}", self.adc_num))?
+                    .trim()
+                    .parse::<f32>()
+                    .chain_err(|| format!("Failed to parse the scale of ADC #{}", self.adc_num))?;
+    if scale.is_nan() || scale <= 0.0 {
+      bail!(format!("ADC #{} reports a scale of {}", self.adc_num, scale));
     }
+    self.scale_mv = scale;
+    Ok(())
+  }
+
+  /// Sets the correction applied to the voltages, none by default.
+  pub fn set_calibration(&mut self, calibration: AdcCalibration) {
+    self.calibration = calibration;
+  }
+
+  /// Returns the correction applied to the voltages.
+  pub fn calibration(&self) -> AdcCalibration {
+    self.calibration
+  }
+
+  /// Reads the voltage of the ADC in millivolts, scaled and calibrated.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  ///
+  /// let sensor = ADC::new(AIN_0, 0.0);
+  /// println!("{}mV", sensor.read_millivolts().unwrap());
+  /// ```
+  pub fn read_millivolts(&self) -> Result<f32> {
+    let raw = self.read()?;
+    Ok(self.calibration.apply(raw as f32 * self.scale_mv))
+  }
+
+  /// Reads the voltage of the ADC in volts, scaled and calibrated.
+  pub fn read_volts(&self) -> Result<f32> {
+    Ok(self.read_millivolts()? / 1000.0)
   }
 
   /// Reads the raw voltage of the ADC.
@@ -47,13 +232,14 @@ impl ADC {
   /// sensor.read().unwrap();
   /// ```
   pub fn read(&self) -> Result<u32> {
-    let path = format!(
-      "/sys/bus/iio/devices/iio:device0/in_voltage{}_raw",
-      &self.adc_num
-    );
+    let path = match self.raw_path {
+      Some(ref path) => path.as_str(),
+      None => bail!(format!("ADC #{} isn't available on this board", &self.adc_num)),
+    };
 
     Ok(
-      path.read_file()
+      self.counters
+          .read(|| path.read_file())
           .chain_err(|| format!("Failed to read from ADC #{}", &self.adc_num))?
           .trim()
           .to_string()
@@ -81,13 +267,14 @@ impl ADC {
   /// sensor.scaled_read().unwrap();
   /// ```
   pub fn scaled_read(&self) -> Result<f32> {
-    let path = format!(
-      "/sys/bus/iio/devices/iio:device0/in_voltage{}_raw",
-      &self.adc_num
-    );
+    let path = match self.raw_path {
+      Some(ref path) => path.as_str(),
+      None => bail!(format!("ADC #{} isn't available on this board", &self.adc_num)),
+    };
 
     let raw_value =
-      path.read_file()
+      self.counters
+          .read(|| path.read_file())
           .chain_err(|| format!("Failed to read from ADC #{}", &self.adc_num))?
           .trim()
           .to_string()
@@ -97,3 +284,17 @@ impl ADC {
     Ok(raw_value as f32 * self.scaling_factor)
   }
 }
+
+/// Returns the scale file next to the raw value file `raw_path`, either the
+/// channel's own, e.g. `in_voltage3_scale`, or the one shared by all
+/// channels.
+fn scale_path(raw_path: &str) -> Option<String> {
+  let raw_path = Path::new(raw_path);
+  let channel = raw_path.file_name()?.to_str()?.strip_suffix("_raw")?;
+  let dir = raw_path.parent()?;
+  [format!("{}_scale", channel), "in_voltage_scale".to_string()]
+    .iter()
+    .map(|name| dir.join(name))
+    .find(|path| path.exists())
+    .map(|path| path.display().to_string())
+}
@@ -0,0 +1,318 @@
+//! The analog watch module.
+//!
+//! Many analog inputs only matter when they leave a window, e.g. a battery
+//! voltage dropping too low or a motor current getting too high.
+//! An `AnalogWatch` waits for an `ADC` input to move between the zones
+//! below, inside and above a window:
+//!
+//! * If the IIO driver of the ADC supports threshold events, the kernel
+//!   watches the input and the watch sleeps until it reports a crossing.
+//! * Otherwise, the input is sampled periodically.
+//!
+//! Either way, hysteresis keeps a noisy input near a threshold from
+//! switching zones back and forth, and the debounce count requires a new
+//! zone to be seen in several samples in a row before it is reported.
+//!
+//! ```no_run
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::analog_watch::{AnalogWatch, Zone};
+//! use std::time::Duration;
+//!
+//! let adc = ADC::new(AIN_0, 0.0);
+//!
+//! // Watch for the input leaving 1000..3000, with 50 counts of hysteresis
+//! // and 3 samples of debounce.
+//! let mut watch = AnalogWatch::new(adc, 1000, 3000).unwrap();
+//! watch.set_hysteresis(50).unwrap();
+//! watch.set_debounce(3);
+//! println!("Using IIO events: {}", watch.uses_events());
+//!
+//! loop {
+//!   match watch.wait(Duration::from_secs(1)).unwrap() {
+//!     Some(Zone::Below) => println!("Too low"),
+//!     Some(Zone::Above) => println!("Too high"),
+//!     Some(Zone::Inside) => println!("Back to normal"),
+//!     None => {}
+//!   }
+//! }
+//! ```
+
+use adc::ADC;
+use clock::{self, Clock};
+use errors::*;
+use nix::libc::c_int;
+use nix::poll::{EventFlags, POLLIN, PollFd, poll};
+use std::fs::File;
+use std::io::Read;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use util::*;
+
+// The ioctl on an IIO character device that returns its event fd.
+ioctl!(read iio_get_event_fd with b'i', 0x90; c_int);
+
+/// The size of a `struct iio_event_data`: a u64 event code and an i64
+/// timestamp.
+const IIO_EVENT_SIZE: usize = 16;
+
+/// Where an input is relative to the window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Zone {
+  /// Below the low threshold.
+  Below,
+  /// Between the thresholds.
+  Inside,
+  /// Above the high threshold.
+  Above,
+}
+
+/// The threshold event files of an IIO channel and its event fd.
+#[derive(Debug)]
+struct IioEvents {
+  events: File,
+  rising_en: String,
+  falling_en: String,
+  rising_value: String,
+  falling_value: String,
+}
+
+impl IioEvents {
+  /// Enables the threshold events of the channel read through `raw_path`,
+  /// or returns `None` if the driver doesn't support them.
+  fn open(raw_path: &str) -> Option<IioEvents> {
+    // E.g. /sys/bus/iio/devices/iio:device0/in_voltage3_raw.
+    let raw_path = Path::new(raw_path);
+    let device_dir = raw_path.parent()?;
+    let channel = raw_path.file_name()?.to_str()?.strip_suffix("_raw")?;
+    let event = |name: &str| format!("{}/events/{}_thresh_{}", device_dir.display(), channel, name);
+    let (rising_en, falling_en) = (event("rising_en"), event("falling_en"));
+    if !Path::new(&rising_en).exists() || !Path::new(&falling_en).exists() {
+      return None;
+    }
+
+    let device = File::open(Path::new("/dev").join(device_dir.file_name()?)).ok()?;
+    let mut fd: c_int = -1;
+    let _ = unsafe { iio_get_event_fd(device.as_raw_fd(), &mut fd) }.ok()?;
+    let events = unsafe { File::from_raw_fd(fd) };
+    rising_en.as_str().write_file("1").ok()?;
+    falling_en.as_str().write_file("1").ok()?;
+    Some(IioEvents {
+      events,
+      rising_en,
+      falling_en,
+      rising_value: event("rising_value"),
+      falling_value: event("falling_value"),
+    })
+  }
+
+  /// Sets the thresholds whose crossing is reported.
+  fn arm(&self, falling: u32, rising: u32) -> Result<()> {
+    self.falling_value.as_str().write_file(&falling.to_string())?;
+    self.rising_value.as_str().write_file(&rising.to_string())
+  }
+
+  /// Waits up to `timeout` for events and discards them.
+  ///
+  /// Returns whether any arrived.
+  fn wait(&mut self, timeout: Duration) -> Result<bool> {
+    let mut fds = [PollFd::new(self.events.as_raw_fd(), POLLIN, EventFlags::empty())];
+    let timeout_ms = timeout.as_millis().min(i32::MAX as u128) as i32;
+    if poll(&mut fds, timeout_ms).chain_err(|| "Failed to wait for IIO events")? == 0 {
+      return Ok(false);
+    }
+    let mut event = [0; IIO_EVENT_SIZE];
+    let _ = self.events.read(&mut event).chain_err(|| "Failed to read IIO event")?;
+    Ok(true)
+  }
+}
+
+impl Drop for IioEvents {
+  fn drop(&mut self) {
+    let _ = self.rising_en.as_str().write_file("0");
+    let _ = self.falling_en.as_str().write_file("0");
+  }
+}
+
+/// Waits for an ADC input to move between the zones of a window.
+#[derive(Debug)]
+pub struct AnalogWatch {
+  adc: ADC,
+  clock: Arc<dyn Clock>,
+  events: Option<IioEvents>,
+  low: u32,
+  high: u32,
+  hysteresis: u32,
+  debounce: u32,
+  poll_interval: Duration,
+  zone: Zone,
+  // The zone seen in the last samples but not reported yet, and how often.
+  candidate: Option<(Zone, u32)>,
+}
+
+impl AnalogWatch {
+  /// Watches `adc` for leaving the window between the raw values `low` and
+  /// `high`, using IIO threshold events if the driver supports them.
+  ///
+  /// The zone the input is in to begin with isn't reported, see `zone()`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if `low` isn't below `high` or the ADC can't be read.
+  pub fn new(adc: ADC, low: u32, high: u32) -> Result<AnalogWatch> {
+    if low >= high {
+      bail!(format!("The low threshold {} must be below the high threshold {}", low, high));
+    }
+    let events = adc.raw_path().and_then(IioEvents::open);
+    let mut watch = AnalogWatch {
+      adc,
+      clock: clock::system(),
+      events,
+      low,
+      high,
+      hysteresis: 0,
+      debounce: 1,
+      poll_interval: Duration::from_millis(10),
+      zone: Zone::Inside,
+      candidate: None,
+    };
+    let value = watch.adc.read()?;
+    watch.zone = watch.classify(value);
+    watch.arm()?;
+    Ok(watch)
+  }
+
+  /// Watches `adc` like `new()`, but always by sampling it.
+  ///
+  /// # Errors
+  ///
+  /// Fails if `low` isn't below `high` or the ADC can't be read.
+  pub fn polling(adc: ADC, low: u32, high: u32) -> Result<AnalogWatch> {
+    let mut watch = AnalogWatch::new(adc, low, high)?;
+    watch.events = None;
+    Ok(watch)
+  }
+
+  /// Returns whether the kernel's threshold events are used.
+  pub fn uses_events(&self) -> bool {
+    self.events.is_some()
+  }
+
+  /// Sets by how much the input has to move back past a threshold to leave
+  /// the zone beyond it again, zero by default.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the kernel's thresholds can't be updated.
+  pub fn set_hysteresis(&mut self, hysteresis: u32) -> Result<()> {
+    self.hysteresis = hysteresis;
+    self.arm()
+  }
+
+  /// Sets how many samples in a row have to be in a new zone before it is
+  /// reported, one by default.
+  pub fn set_debounce(&mut self, samples: u32) {
+    self.debounce = samples.max(1);
+  }
+
+  /// Sets the time between samples, 10ms by default.
+  ///
+  /// With IIO events, samples are only taken after an event, to debounce.
+  pub fn set_poll_interval(&mut self, poll_interval: Duration) {
+    self.poll_interval = poll_interval;
+  }
+
+  /// Makes the watch take its time from `clock`, e.g. a `TestClock`.
+  pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+    self.clock = clock;
+  }
+
+  /// Returns the zone reported last, or the one the input was in to begin
+  /// with.
+  pub fn zone(&self) -> Zone {
+    self.zone
+  }
+
+  /// Returns the watched ADC.
+  pub fn adc(&self) -> &ADC {
+    &self.adc
+  }
+
+  /// Waits up to `timeout` for the input to move to another zone.
+  ///
+  /// Returns the new zone, or `None` if the timeout expired.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the ADC or its events can't be read.
+  pub fn wait(&mut self, timeout: Duration) -> Result<Option<Zone>> {
+    let deadline = self.clock.now() + timeout;
+    loop {
+      let value = self.adc.read()?;
+      let zone = self.classify(value);
+      if zone == self.zone {
+        self.candidate = None;
+      } else {
+        let seen = match self.candidate {
+          Some((candidate, seen)) if candidate == zone => seen + 1,
+          _ => 1,
+        };
+        if seen >= self.debounce {
+          self.zone = zone;
+          self.candidate = None;
+          self.arm()?;
+          return Ok(Some(zone));
+        }
+        self.candidate = Some((zone, seen));
+      }
+
+      let remaining = match deadline.checked_sub(self.clock.now()) {
+        Some(remaining) if remaining > Duration::from_secs(0) => remaining,
+        _ => return Ok(None),
+      };
+      match self.events {
+        // Debouncing needs samples, not events.
+        Some(ref mut events) if self.candidate.is_none() => {
+          if !events.wait(remaining)? {
+            return Ok(None);
+          }
+        }
+        _ => self.clock.sleep(self.poll_interval.min(remaining))?,
+      }
+    }
+  }
+
+  /// Returns the zone of `value`, given the zone reported last.
+  fn classify(&self, value: u32) -> Zone {
+    let (low, high) = match self.zone {
+      // Leaving a zone beyond a threshold takes moving back past it by the
+      // hysteresis.
+      Zone::Below => (self.low.saturating_add(self.hysteresis), self.high),
+      Zone::Inside => (self.low, self.high),
+      Zone::Above => (self.low, self.high.saturating_sub(self.hysteresis)),
+    };
+    if value <= low {
+      Zone::Below
+    } else if value >= high {
+      Zone::Above
+    } else {
+      Zone::Inside
+    }
+  }
+
+  /// Sets the kernel's thresholds to the ones leaving the current zone.
+  fn arm(&self) -> Result<()> {
+    let events = match self.events {
+      Some(ref events) => events,
+      None => return Ok(()),
+    };
+    let (falling, rising) = match self.zone {
+      Zone::Below => (self.low, self.low.saturating_add(self.hysteresis)),
+      Zone::Inside => (self.low, self.high),
+      Zone::Above => (self.high.saturating_sub(self.hysteresis), self.high),
+    };
+    events.arm(falling, rising)
+          .chain_err(|| format!("Failed to set the thresholds of ADC #{}", self.adc.adc_num()))
+  }
+}
@@ -0,0 +1,488 @@
+//! The arbitration module.
+//!
+//! A reservation keeps a diagnostic tool from touching a pin the running
+//! application holds, but sometimes that's just what's needed: jogging a
+//! motor or toggling a relay from the command line while the application
+//! keeps running.
+//! Instead of both writing to sysfs behind each other's backs, the
+//! application runs an `Arbiter` for the device, which a diagnostic session
+//! asks for temporary control with a `ControlLease`:
+//!
+//! ```no_run
+//! use libbeaglebone::arbitration::{Arbiter, Decision};
+//! use libbeaglebone::prelude::*;
+//!
+//! // In the application, at priority 10.
+//! let arbiter = Arbiter::new("gpio45", 10).unwrap();
+//! arbiter.set_policy(|request| if request.who.starts_with("service") {
+//!   Decision::Grant
+//! } else {
+//!   Decision::Deny("only service sessions may take over".to_string())
+//! });
+//! let pump = GPIO::new(GPIO_P8_11);
+//! pump.set_export(DeviceState::Exported).unwrap();
+//! pump.set_direction(PinDirection::Out).unwrap();
+//! // Fails with `ErrorKind::ControlYielded` while a session has control.
+//! let mut pump = arbiter.guard_digital(pump);
+//! pump.set_state(PinState::High).unwrap();
+//! ```
+//!
+//! ```no_run
+//! use libbeaglebone::arbitration::ControlLease;
+//! use libbeaglebone::prelude::*;
+//! use std::time::Duration;
+//!
+//! // In the diagnostic tool, at a higher priority.
+//! let lease = ControlLease::request("gpio45", "service cli", 20, Duration::from_secs(60)).unwrap();
+//! let mut pump = GPIO::new(GPIO_P8_11);
+//! pump.write(PinState::Low).unwrap();
+//! // Dropping the lease hands control back.
+//! drop(lease);
+//! ```
+//!
+//! Without a policy, requests of a higher priority than the application's
+//! are granted and the others denied.
+//! Control returns to the application when the lease is dropped, its
+//! diagnostic process exits, or it expires.
+//! Like reservations, arbitration is advisory, and the sockets are kept in
+//! the reservations' lock directory.
+
+use errors::*;
+use gpio::PinState;
+use hal::DigitalPin;
+use reservation::{self, lock_dir};
+use std::fmt;
+use std::fs;
+use std::io::{BufRead, BufReader, ErrorKind as IoErrorKind, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How often the arbiter looks for requests and ended leases.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// How long a request may take to send, or its reply to arrive, which
+/// includes the application's policy deciding.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A diagnostic session's request for control of a device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ControlRequest {
+  /// Who asks, e.g. "service cli".
+  pub who: String,
+  /// The priority of the request, higher ones win.
+  pub priority: u8,
+  /// How long control is asked for.
+  pub duration: Duration,
+}
+
+/// What the application's policy decides on a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+  /// Hand control over.
+  Grant,
+  /// Keep control, telling the session why.
+  Deny(String),
+}
+
+type Policy = Box<dyn FnMut(&ControlRequest) -> Decision + Send>;
+type Handover = Box<dyn FnMut(Option<&ControlRequest>) + Send>;
+
+struct Hooks {
+  policy: Option<Policy>,
+  handover: Option<Handover>,
+}
+
+struct Shared {
+  // The session that has control, if any.
+  holder: Mutex<Option<ControlRequest>>,
+  hooks: Mutex<Hooks>,
+  revoke: AtomicBool,
+  stop: AtomicBool,
+}
+
+impl Shared {
+  /// Fails while a session has control.
+  fn check(holder: &Option<ControlRequest>) -> Result<()> {
+    match *holder {
+      Some(ref request) => Err(ErrorKind::ControlYielded(request.who.clone()).into()),
+      None => Ok(()),
+    }
+  }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+  mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Hands control of a device to diagnostic sessions on request, for as long
+/// as it exists.
+pub struct Arbiter {
+  resource: String,
+  priority: u8,
+  path: PathBuf,
+  shared: Arc<Shared>,
+  thread: Option<JoinHandle<()>>,
+}
+
+impl fmt::Debug for Arbiter {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("Arbiter")
+     .field("resource", &self.resource)
+     .field("priority", &self.priority)
+     .field("path", &self.path)
+     .field("holder", &self.holder())
+     .finish()
+  }
+}
+
+impl Arbiter {
+  /// Starts arbitrating the `resource`, named like a reservation, e.g.
+  /// "gpio45", for an application at `priority`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the name is invalid, if another application arbitrates the
+  /// resource, or if its socket can't be created.
+  pub fn new(resource: &str, priority: u8) -> Result<Arbiter> {
+    reservation::check_name(resource)?;
+    let dir = lock_dir();
+    fs::create_dir_all(&dir)
+      .chain_err(|| format!("Failed to create reservation directory {}", dir.display()))?;
+    let path = socket_path(resource);
+    if path.exists() {
+      if UnixStream::connect(&path).is_ok() {
+        bail!(format!("{} is arbitrated by another application", resource));
+      }
+      // Left behind by an application that didn't exit cleanly.
+      let _ = fs::remove_file(&path);
+    }
+    let listener = UnixListener::bind(&path)
+      .and_then(|listener| listener.set_nonblocking(true).map(|_| listener))
+      .chain_err(|| format!("Failed to create arbitration socket {}", path.display()))?;
+    let shared = Arc::new(Shared {
+      holder: Mutex::new(None),
+      hooks: Mutex::new(Hooks {
+        policy: None,
+        handover: None,
+      }),
+      revoke: AtomicBool::new(false),
+      stop: AtomicBool::new(false),
+    });
+    let thread = {
+      let shared = shared.clone();
+      thread::spawn(move || serve(&listener, &shared, priority))
+    };
+    Ok(Arbiter {
+      resource: resource.to_string(),
+      priority,
+      path,
+      shared,
+      thread: Some(thread),
+    })
+  }
+
+  /// Returns the name of the arbitrated resource.
+  pub fn resource(&self) -> &str {
+    &self.resource
+  }
+
+  /// Returns the application's priority.
+  pub fn priority(&self) -> u8 {
+    self.priority
+  }
+
+  /// Decides on requests with `policy` instead of by priority.
+  ///
+  /// It's called on the arbiter's thread, and only for requests while the
+  /// application has control; others are denied.
+  pub fn set_policy<F>(&self, policy: F)
+    where F: FnMut(&ControlRequest) -> Decision + Send + 'static
+  {
+    lock(&self.shared.hooks).policy = Some(Box::new(policy));
+  }
+
+  /// Calls `handover` with the request when control is handed to a
+  /// session, and with `None` when it returns, e.g. to put the device into
+  /// a known state.
+  pub fn set_on_handover<F>(&self, handover: F)
+    where F: FnMut(Option<&ControlRequest>) + Send + 'static
+  {
+    lock(&self.shared.hooks).handover = Some(Box::new(handover));
+  }
+
+  /// Returns whether the application has control of the device.
+  pub fn has_control(&self) -> bool {
+    lock(&self.shared.holder).is_none()
+  }
+
+  /// Returns the session that has control, if any.
+  pub fn holder(&self) -> Option<ControlRequest> {
+    lock(&self.shared.holder).clone()
+  }
+
+  /// Fails while a session has control of the device.
+  ///
+  /// # Errors
+  ///
+  /// Fails with `ErrorKind::ControlYielded` while a session has control.
+  pub fn check(&self) -> Result<()> {
+    Shared::check(&lock(&self.shared.holder))
+  }
+
+  /// Takes control back from the session that has it, ending its lease.
+  pub fn revoke(&self) {
+    if !self.has_control() {
+      self.shared.revoke.store(true, Ordering::SeqCst);
+    }
+  }
+
+  /// Wraps `pin` so it's only driven while the application has control.
+  pub fn guard_digital<P: DigitalPin>(&self, pin: P) -> ArbitratedPin<P> {
+    ArbitratedPin {
+      pin,
+      shared: self.shared.clone(),
+    }
+  }
+}
+
+impl Drop for Arbiter {
+  fn drop(&mut self) {
+    self.shared.stop.store(true, Ordering::SeqCst);
+    if let Some(thread) = self.thread.take() {
+      let _ = thread.join();
+    }
+    let _ = fs::remove_file(&self.path);
+  }
+}
+
+/// A granted lease and when it expires.
+struct Lease {
+  stream: UnixStream,
+  expires: Instant,
+}
+
+/// Answers requests and returns control when leases end, until stopped.
+fn serve(listener: &UnixListener, shared: &Shared, priority: u8) {
+  let mut lease: Option<Lease> = None;
+  while !shared.stop.load(Ordering::SeqCst) {
+    let ended = match lease {
+      Some(ref mut lease) => {
+        shared.revoke.swap(false, Ordering::SeqCst) || Instant::now() >= lease.expires ||
+        closed(&mut lease.stream)
+      }
+      None => false,
+    };
+    if ended {
+      lease = None;
+      *lock(&shared.holder) = None;
+      if let Some(ref mut handover) = lock(&shared.hooks).handover {
+        handover(None);
+      }
+    }
+    match listener.accept() {
+      Ok((stream, _)) => {
+        if let Some(granted) = answer(stream, shared, priority) {
+          lease = Some(granted);
+        }
+      }
+      Err(_) => thread::sleep(POLL_INTERVAL),
+    }
+  }
+}
+
+/// Returns whether the session closed its end of the lease.
+fn closed(stream: &mut UnixStream) -> bool {
+  let mut buf = [0; 64];
+  match stream.read(&mut buf) {
+    Ok(0) => true,
+    Ok(_) => false,
+    Err(ref e) if e.kind() == IoErrorKind::WouldBlock => false,
+    Err(_) => true,
+  }
+}
+
+/// Reads a request from `stream` and answers it, returning the lease if
+/// it's granted.
+fn answer(stream: UnixStream, shared: &Shared, priority: u8) -> Option<Lease> {
+  let _ = stream.set_nonblocking(false);
+  let _ = stream.set_read_timeout(Some(REPLY_TIMEOUT));
+  let mut line = String::new();
+  if BufReader::new(&stream).read_line(&mut line).is_err() {
+    return None;
+  }
+  let decision = match parse_request(&line) {
+    None => Decision::Deny(format!("invalid request {:?}", line.trim())),
+    Some(request) => {
+      let holder = lock(&shared.holder).clone();
+      let decision = match holder {
+        Some(holder) => Decision::Deny(format!("control is yielded to {} already", holder.who)),
+        None => {
+          let mut hooks = lock(&shared.hooks);
+          match hooks.policy {
+            Some(ref mut policy) => policy(&request),
+            None if request.priority > priority => Decision::Grant,
+            None => Decision::Deny(format!("the application's priority {} isn't lower", priority)),
+          }
+        }
+      };
+      if decision == Decision::Grant {
+        return grant(stream, shared, request);
+      }
+      decision
+    }
+  };
+  if let Decision::Deny(reason) = decision {
+    let _ = writeln!(&stream, "DENIED {}", reason);
+  }
+  None
+}
+
+fn grant(stream: UnixStream, shared: &Shared, request: ControlRequest) -> Option<Lease> {
+  let expires = Instant::now() + request.duration;
+  // Waits for a guarded write in progress, and blocks the next ones.
+  *lock(&shared.holder) = Some(request.clone());
+  shared.revoke.store(false, Ordering::SeqCst);
+  if let Some(ref mut handover) = lock(&shared.hooks).handover {
+    handover(Some(&request));
+  }
+  if writeln!(&stream, "GRANTED").is_err() || stream.set_nonblocking(true).is_err() {
+    // The session is gone already, it gets its lease back on the next loop.
+    return Some(Lease {
+      stream,
+      expires: Instant::now(),
+    });
+  }
+  Some(Lease { stream, expires })
+}
+
+/// Parses "REQUEST <priority> <milliseconds> <who>".
+fn parse_request(line: &str) -> Option<ControlRequest> {
+  let mut fields = line.trim().splitn(4, ' ');
+  if fields.next() != Some("REQUEST") {
+    return None;
+  }
+  let priority = fields.next()?.parse().ok()?;
+  let millis = fields.next()?.parse().ok()?;
+  let who = fields.next().unwrap_or("").to_string();
+  Some(ControlRequest {
+    who,
+    priority,
+    duration: Duration::from_millis(millis),
+  })
+}
+
+fn socket_path(resource: &str) -> PathBuf {
+  lock_dir().join(format!("{}.arbiter", resource))
+}
+
+/// Temporary control of a device arbitrated by an application, handed back
+/// when dropped.
+#[derive(Debug)]
+pub struct ControlLease {
+  resource: String,
+  stream: UnixStream,
+  expires: Instant,
+}
+
+impl ControlLease {
+  /// Asks the application arbitrating `resource` for control for
+  /// `duration`, as `who` at `priority`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if no application arbitrates the resource, or if it denies the
+  /// request, with its reason.
+  pub fn request(resource: &str, who: &str, priority: u8, duration: Duration) -> Result<ControlLease> {
+    reservation::check_name(resource)?;
+    let path = socket_path(resource);
+    let stream = connect(&path).chain_err(|| format!("No application arbitrates {}", resource))?;
+    let millis = duration.as_millis().min(u128::from(u64::MAX));
+    let mut line = String::new();
+    let _ = writeln!(&stream, "REQUEST {} {} {}", priority, millis, who.replace('\n', " "))
+      .and_then(|_| BufReader::new(&stream).read_line(&mut line))
+      .chain_err(|| format!("Failed to request control of {}", resource))?;
+    let expires = Instant::now() + duration;
+    match line.trim().split_once(' ').unwrap_or((line.trim(), "")) {
+      ("GRANTED", _) => {}
+      ("DENIED", reason) => bail!(format!("Control of {} was denied: {}", resource, reason)),
+      _ => bail!(format!("Control of {} wasn't granted", resource)),
+    }
+    let _ = stream.set_nonblocking(true);
+    Ok(ControlLease {
+      resource: resource.to_string(),
+      stream,
+      expires,
+    })
+  }
+
+  /// Returns the name of the controlled resource.
+  pub fn resource(&self) -> &str {
+    &self.resource
+  }
+
+  /// Returns how much of the lease is left.
+  pub fn remaining(&self) -> Duration {
+    self.expires.saturating_duration_since(Instant::now())
+  }
+
+  /// Returns whether the session still has control, i.e. the lease hasn't
+  /// expired and the application didn't revoke it or exit.
+  pub fn is_active(&mut self) -> bool {
+    Instant::now() < self.expires && !closed(&mut self.stream)
+  }
+}
+
+fn connect(path: &Path) -> ::std::io::Result<UnixStream> {
+  let stream = UnixStream::connect(path)?;
+  stream.set_read_timeout(Some(REPLY_TIMEOUT))?;
+  stream.set_write_timeout(Some(REPLY_TIMEOUT))?;
+  Ok(stream)
+}
+
+/// A digital output that is only driven while its application has control.
+pub struct ArbitratedPin<P: DigitalPin> {
+  pin: P,
+  shared: Arc<Shared>,
+}
+
+impl<P: DigitalPin + fmt::Debug> fmt::Debug for ArbitratedPin<P> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("ArbitratedPin")
+     .field("pin", &self.pin)
+     .field("holder", &*lock(&self.shared.holder))
+     .finish()
+  }
+}
+
+impl<P: DigitalPin> ArbitratedPin<P> {
+  /// Unwraps the output.
+  pub fn into_inner(self) -> P {
+    self.pin
+  }
+}
+
+impl<P: DigitalPin> DigitalPin for ArbitratedPin<P> {
+  /// Drives the pin to `state`, unless a session has control.
+  ///
+  /// # Errors
+  ///
+  /// Fails with `ErrorKind::ControlYielded` while a session has control.
+  fn set_state(&mut self, state: PinState) -> Result<()> {
+    // Control isn't handed over while the pin is written.
+    let holder = lock(&self.shared.holder);
+    Shared::check(&holder)?;
+    self.pin.set_state(state)
+  }
+
+  fn state(&self) -> Result<PinState> {
+    self.pin.state()
+  }
+
+  fn pin_name(&self) -> String {
+    self.pin.pin_name()
+  }
+}
@@ -0,0 +1,206 @@
+//! The attribute watch module.
+//!
+//! When several daemons share the same hardware, one of them changing a pin
+//! behind the back of another is hard to track down.
+//! An `AttributeWatcher` uses inotify to report whenever a watched sysfs
+//! attribute, e.g. a GPIO's `value` or a PWM's `duty_cycle`, is written.
+//!
+//! inotify only sees writes made through the file system, by any process
+//! including this one, but not changes made by the kernel itself, e.g. the
+//! level of a GPIO input changing.
+//! Use `EdgeWaiter` to wait for those.
+
+use errors::*;
+use gpio::GPIO;
+use nix::libc;
+use nix::poll::{EventFlags, POLLIN, PollFd, poll};
+use pwm::PWM;
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::RawFd;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use util::*;
+
+/// The attributes of a GPIO that are watched by `watch_gpio()`.
+const GPIO_ATTRIBUTES: &[&str] = &["value", "direction", "edge", "active_low"];
+
+/// The attributes of a PWM that are watched by `watch_pwm()`.
+const PWM_ATTRIBUTES: &[&str] = &["period", "duty_cycle", "enable", "polarity"];
+
+/// The attributes of a LED that are watched by `watch_led()`.
+const LED_ATTRIBUTES: &[&str] = &["brightness", "trigger"];
+
+/// A write to a watched attribute.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttributeEvent {
+  /// The path of the attribute file.
+  pub path: PathBuf,
+  /// The value of the attribute after the write, if it could be read.
+  ///
+  /// The value is read when the event is collected, so several writes in
+  /// quick succession may all report the last value.
+  pub value: Option<String>,
+}
+
+/// Watches sysfs attribute files for writes.
+#[derive(Debug)]
+pub struct AttributeWatcher {
+  fd: RawFd,
+  watches: HashMap<libc::c_int, PathBuf>,
+}
+
+impl AttributeWatcher {
+  /// Creates a new watcher that doesn't watch anything yet.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::attr_watch::AttributeWatcher;
+  /// use libbeaglebone::prelude::*;
+  ///
+  /// let mut watcher = AttributeWatcher::new().unwrap();
+  /// watcher.watch_gpio(&GPIO::new(GPIO_P8_11)).unwrap();
+  /// watcher.watch_pwm(&PWM::new(0, 0)).unwrap();
+  /// watcher.watch_led("beaglebone:green:usr0").unwrap();
+  ///
+  /// loop {
+  ///   for event in watcher.wait(-1).unwrap() {
+  ///     println!("{} was set to {:?}", event.path.display(), event.value);
+  ///   }
+  /// }
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if the inotify instance can't be created, e.g. because the
+  /// per-user limit of instances is reached.
+  pub fn new() -> Result<AttributeWatcher> {
+    let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+    if fd < 0 {
+      bail!(format!("Failed to create inotify instance: {}",
+                    io::Error::last_os_error()));
+    }
+    Ok(AttributeWatcher {
+      fd,
+      watches: HashMap::new(),
+    })
+  }
+
+  /// Watches the attribute file at `path`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the file doesn't exist or can't be watched.
+  pub fn watch<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+    let path = path.as_ref();
+    let c_path = CString::new(path.as_os_str().as_bytes())
+      .chain_err(|| format!("Invalid attribute path {}", path.display()))?;
+    let wd = unsafe {
+      libc::inotify_add_watch(self.fd, c_path.as_ptr(), libc::IN_MODIFY | libc::IN_CLOSE_WRITE)
+    };
+    if wd < 0 {
+      bail!(format!("Failed to watch {}: {}",
+                    path.display(),
+                    io::Error::last_os_error()));
+    }
+    let _ = self.watches.insert(wd, path.to_path_buf());
+    Ok(())
+  }
+
+  /// Watches the `value`, `direction`, `edge` and `active_low` attributes of
+  /// an exported GPIO.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the GPIO isn't exported.
+  pub fn watch_gpio(&mut self, gpio: &GPIO) -> Result<()> {
+    self.watch_attributes(gpio.sysfs_path(), GPIO_ATTRIBUTES)
+  }
+
+  /// Watches the `period`, `duty_cycle`, `enable` and `polarity` attributes
+  /// of an exported PWM.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the PWM isn't exported.
+  pub fn watch_pwm(&mut self, pwm: &PWM) -> Result<()> {
+    self.watch_attributes(&pwm.sysfs_path(), PWM_ATTRIBUTES)
+  }
+
+  /// Watches the `brightness` and `trigger` attributes of the LED called
+  /// `name` in `/sys/class/leds`, e.g. "beaglebone:green:usr0".
+  ///
+  /// # Errors
+  ///
+  /// Fails if there is no such LED.
+  pub fn watch_led(&mut self, name: &str) -> Result<()> {
+    self.watch_attributes(&Path::new("/sys/class/leds").join(name), LED_ATTRIBUTES)
+  }
+
+  /// Waits up to `timeout_ms` milliseconds for writes to the watched
+  /// attributes, or forever if negative, and returns them.
+  ///
+  /// Returns an empty list if the timeout expired.
+  /// Consecutive writes to the same attribute are reported once.
+  pub fn wait(&mut self, timeout_ms: i32) -> Result<Vec<AttributeEvent>> {
+    let mut fds = [PollFd::new(self.fd, POLLIN, EventFlags::empty())];
+    let ready = poll(&mut fds, timeout_ms).chain_err(|| "Failed to wait for attribute changes")?;
+    if ready == 0 {
+      return Ok(Vec::new());
+    }
+
+    let mut changed: Vec<PathBuf> = Vec::new();
+    let mut buf = [0u8; 4096];
+    loop {
+      let len = unsafe { libc::read(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+      if len <= 0 {
+        // Nothing left to read without blocking.
+        break;
+      }
+      let mut offset = 0;
+      while offset + size_of::<libc::inotify_event>() <= len as usize {
+        let event = unsafe {
+          ptr::read_unaligned(buf.as_ptr().add(offset) as *const libc::inotify_event)
+        };
+        offset += size_of::<libc::inotify_event>() + event.len as usize;
+        if let Some(path) = self.watches.get(&event.wd) {
+          if changed.last() != Some(path) {
+            changed.push(path.clone());
+          }
+        }
+      }
+    }
+
+    Ok(changed.into_iter()
+              .map(|path| {
+      let value = path.to_str()
+                      .and_then(|p| p.read_file().ok())
+                      .map(|v| v.trim().to_string());
+      AttributeEvent { path, value }
+    })
+              .collect())
+  }
+
+  /// Watches the attributes of a sysfs device directory that exist.
+  fn watch_attributes(&mut self, dir: &Path, attributes: &[&str]) -> Result<()> {
+    if !dir.exists() {
+      bail!(format!("Can't watch {}, it doesn't exist", dir.display()));
+    }
+    for attribute in attributes {
+      let path = dir.join(attribute);
+      if path.exists() {
+        self.watch(path)?;
+      }
+    }
+    Ok(())
+  }
+}
+
+impl Drop for AttributeWatcher {
+  fn drop(&mut self) {
+    let _ = unsafe { libc::close(self.fd) };
+  }
+}
@@ -0,0 +1,333 @@
+//! The board module.
+//!
+//! The other modules don't hard-code where the kernel exposes the
+//! peripherals, they look it up in the current `Board` description.
+//! The BeagleBone Black is used unless the application selects another
+//! board, so the same GPIO, PWM, ADC, UART, I2C and SPI code runs on other
+//! Linux single-board computers with the same kernel interfaces:
+//!
+//! ```no_run
+//! use libbeaglebone::board::{self, Board};
+//! use libbeaglebone::prelude::*;
+//!
+//! let mut pi = Board::beaglebone_black();
+//! pi.name = "Raspberry Pi".to_string();
+//! pi.uart_device = "/dev/ttyAMA{}".to_string();
+//! pi.adc_raw_file = None;
+//! pi.pinmux_state_file = None;
+//! pi.gpio_pins = vec![("GPIO17".to_string(), 17), ("GPIO27".to_string(), 27)];
+//! board::set_current(pi);
+//!
+//! let led = GPIO::from_name("GPIO17").unwrap();
+//! led.set_export(DeviceState::Exported).unwrap();
+//! ```
+//!
+//! The board has to be selected before any device is created, devices keep
+//! the paths of the board they were created on.
+//! The `Pin` constants and the drivers that access registers directly, e.g.
+//! the `ehrpwm` module, remain specific to the BeagleBone.
+
+use errors::*;
+use pins::Pin;
+use pins::Pin::*;
+use std::fs;
+use stub;
+use std::sync::{Arc, RwLock};
+
+/// The header names and GPIO numbers of the BeagleBone Black's GPIO pins.
+const BEAGLEBONE_GPIO_PINS: &[(&str, Pin)] = &[
+  ("P8_3", GPIO_P8_3), ("P8_4", GPIO_P8_4), ("P8_5", GPIO_P8_5), ("P8_6", GPIO_P8_6),
+  ("P8_7", GPIO_P8_7), ("P8_8", GPIO_P8_8), ("P8_9", GPIO_P8_9), ("P8_10", GPIO_P8_10),
+  ("P8_11", GPIO_P8_11), ("P8_12", GPIO_P8_12), ("P8_13", GPIO_P8_13), ("P8_14", GPIO_P8_14),
+  ("P8_15", GPIO_P8_15), ("P8_16", GPIO_P8_16), ("P8_17", GPIO_P8_17), ("P8_18", GPIO_P8_18),
+  ("P8_19", GPIO_P8_19), ("P8_20", GPIO_P8_20), ("P8_21", GPIO_P8_21), ("P8_22", GPIO_P8_22),
+  ("P8_23", GPIO_P8_23), ("P8_24", GPIO_P8_24), ("P8_25", GPIO_P8_25), ("P8_26", GPIO_P8_26),
+  ("P8_27", GPIO_P8_27), ("P8_28", GPIO_P8_28), ("P8_29", GPIO_P8_29), ("P8_30", GPIO_P8_30),
+  ("P8_31", GPIO_P8_31), ("P8_32", GPIO_P8_32), ("P8_33", GPIO_P8_33), ("P8_34", GPIO_P8_34),
+  ("P8_35", GPIO_P8_35), ("P8_36", GPIO_P8_36), ("P8_37", GPIO_P8_37), ("P8_38", GPIO_P8_38),
+  ("P8_39", GPIO_P8_39), ("P8_40", GPIO_P8_40), ("P8_41", GPIO_P8_41), ("P8_42", GPIO_P8_42),
+  ("P8_43", GPIO_P8_43), ("P8_44", GPIO_P8_44), ("P8_45", GPIO_P8_45), ("P8_46", GPIO_P8_46),
+  ("P9_11", GPIO_P9_11), ("P9_12", GPIO_P9_12), ("P9_13", GPIO_P9_13), ("P9_14", GPIO_P9_14),
+  ("P9_15", GPIO_P9_15), ("P9_16", GPIO_P9_16), ("P9_17", GPIO_P9_17), ("P9_18", GPIO_P9_18),
+  ("P9_21", GPIO_P9_21), ("P9_22", GPIO_P9_22), ("P9_23", GPIO_P9_23), ("P9_24", GPIO_P9_24),
+  ("P9_25", GPIO_P9_25), ("P9_26", GPIO_P9_26), ("P9_27", GPIO_P9_27), ("P9_28", GPIO_P9_28),
+  ("P9_29", GPIO_P9_29), ("P9_30", GPIO_P9_30), ("P9_31", GPIO_P9_31), ("P9_41", GPIO_P9_41),
+  ("P9_42", GPIO_P9_42),
+];
+
+/// The header names of the BeagleBone Black's PWM pins, the addresses of
+/// their PWM controllers and the channels on them.
+const BEAGLEBONE_PWM_PINS: &[(&str, &str, u8)] = &[
+  // EHRPWM0A and B.
+  ("P9_22", "48300200", 0), ("P9_31", "48300200", 0),
+  ("P9_21", "48300200", 1), ("P9_29", "48300200", 1),
+  // EHRPWM1A and B.
+  ("P9_14", "48302200", 0), ("P8_36", "48302200", 0),
+  ("P9_16", "48302200", 1), ("P8_34", "48302200", 1),
+  // EHRPWM2A and B.
+  ("P8_19", "48304200", 0), ("P8_45", "48304200", 0),
+  ("P8_13", "48304200", 1), ("P8_46", "48304200", 1),
+  // ECAPPWM0 and 2.
+  ("P9_42", "48300100", 0),
+  ("P9_28", "48304100", 0),
+];
+
+/// The highest PWM chip number searched for a PWM controller.
+const MAX_PWM_CHIP_NUM: u8 = 63;
+
+/// The board selected by `set_current()`, if any.
+static CURRENT: RwLock<Option<Arc<Board>>> = RwLock::new(None);
+
+/// Describes where the kernel exposes the peripherals of a board.
+///
+/// Paths containing `{}` are templates that the number of the device is
+/// substituted into.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Board {
+  /// The name of the board, e.g. "BeagleBone Black".
+  pub name: String,
+  /// The sysfs GPIO class directory, e.g. `/sys/class/gpio`.
+  pub gpio_dir: String,
+  /// The character device of a GPIO chip, e.g. `/dev/gpiochip{}`.
+  pub gpio_chip_device: String,
+  /// The number of lines per GPIO chip, which maps the kernel's GPIO
+  /// numbers to the chips, e.g. 32 for GPIO 45 being line 13 of chip 1.
+  pub gpio_chip_lines: u8,
+  /// The state file of a pin's pinmux helper, which cape-universal
+  /// overlays create for each header pin, e.g.
+  /// `/sys/devices/platform/ocp/ocp:{}_pinmux/state` for "P8_11", or
+  /// `None` if the board's pinmux can't be set at runtime.
+  pub pinmux_state_file: Option<String>,
+  /// The directory of a PWM chip, e.g. `/sys/class/pwm/pwmchip{}`.
+  pub pwm_chip_dir: String,
+  /// The raw value file of an ADC input, or `None` if the board has no ADC.
+  pub adc_raw_file: Option<String>,
+  /// The device node of a UART, e.g. `/dev/ttyO{}`.
+  pub uart_device: String,
+  /// The device node of an I2C bus, e.g. `/dev/i2c-{}`.
+  pub i2c_device: String,
+  /// The device node of a SPI bus, e.g. `/dev/spidev{}.0`.
+  pub spi_device: String,
+  /// The names of the GPIO pins and their kernel GPIO numbers.
+  pub gpio_pins: Vec<(String, u8)>,
+  /// The names of the PWM pins, the device names of their PWM controllers
+  /// up to the first dot, e.g. "48300200" for `48300200.pwm`, and the
+  /// channels on them.
+  pub pwm_pins: Vec<(String, String, u8)>,
+}
+
+impl Board {
+  /// Returns the description of the BeagleBone Black.
+  pub fn beaglebone_black() -> Board {
+    Board {
+      name: "BeagleBone Black".to_string(),
+      gpio_dir: "/sys/class/gpio".to_string(),
+      gpio_chip_device: "/dev/gpiochip{}".to_string(),
+      gpio_chip_lines: 32,
+      pinmux_state_file: Some("/sys/devices/platform/ocp/ocp:{}_pinmux/state".to_string()),
+      pwm_chip_dir: "/sys/class/pwm/pwmchip{}".to_string(),
+      adc_raw_file: Some("/sys/bus/iio/devices/iio:device0/in_voltage{}_raw".to_string()),
+      uart_device: "/dev/ttyO{}".to_string(),
+      i2c_device: "/dev/i2c-{}".to_string(),
+      spi_device: "/dev/spidev{}.0".to_string(),
+      gpio_pins: BEAGLEBONE_GPIO_PINS.iter()
+                                     .map(|&(name, pin)| (name.to_string(), pin as u8))
+                                     .collect(),
+      pwm_pins: BEAGLEBONE_PWM_PINS.iter()
+                                   .map(|&(name, controller, channel)| {
+                                     (name.to_string(), controller.to_string(), channel)
+                                   })
+                                   .collect(),
+    }
+  }
+
+  /// Looks up the GPIO number of the pin called `name`, e.g. "P8_11".
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use libbeaglebone::board::Board;
+  ///
+  /// assert_eq!(Board::beaglebone_black().gpio_num("P8_11"), Some(45));
+  /// ```
+  pub fn gpio_num(&self, name: &str) -> Option<u8> {
+    self.gpio_pins
+        .iter()
+        .find(|(pin_name, _)| pin_name == name)
+        .map(|&(_, num)| num)
+  }
+
+  /// Looks up the header name of the pin with GPIO number `pin_num`.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use libbeaglebone::board::Board;
+  ///
+  /// assert_eq!(Board::beaglebone_black().gpio_name(45), Some("P8_11"));
+  /// ```
+  pub fn gpio_name(&self, pin_num: u8) -> Option<&str> {
+    self.gpio_pins
+        .iter()
+        .find(|&&(_, num)| num == pin_num)
+        .map(|(name, _)| name.as_str())
+  }
+
+  /// Looks up the PWM controller and channel of the pin called `name`,
+  /// e.g. "P9_14" or "P9.14".
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use libbeaglebone::board::Board;
+  ///
+  /// assert_eq!(Board::beaglebone_black().pwm_channel("P9.14"), Some(("48302200", 0)));
+  /// ```
+  pub fn pwm_channel(&self, name: &str) -> Option<(&str, u8)> {
+    let name = name.replace('.', "_");
+    self.pwm_pins
+        .iter()
+        .find(|(pin_name, _, _)| *pin_name == name)
+        .map(|(_, controller, channel)| (controller.as_str(), *channel))
+  }
+
+  /// Looks up the PWM chip and PWM number of the pin called `name`, e.g.
+  /// "P9_14" or "P9.14".
+  ///
+  /// The kernel numbers the PWM chips in the order it probes the
+  /// controllers, which differs between kernel versions, so the chip is
+  /// found by the controller its `device` links to.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the pin has no PWM, or if its controller has no PWM chip, e.g.
+  /// because it's disabled in the device tree.
+  pub fn pwm_num(&self, name: &str) -> Result<(u8, u8)> {
+    let (controller, channel) = match self.pwm_channel(name) {
+      Some(found) => found,
+      None => bail!(format!("The {} has no PWM pin {}", self.name, name)),
+    };
+    let prefix = format!("{}.", controller);
+    for chip_num in 0..=MAX_PWM_CHIP_NUM {
+      let device = format!("{}/device", self.pwm_chip_path(chip_num));
+      let matches = fs::canonicalize(&device).ok()
+                                             .as_ref()
+                                             .and_then(|path| path.file_name())
+                                             .and_then(|name| name.to_str())
+                                             .is_some_and(|name| name.starts_with(&prefix));
+      if matches {
+        return Ok((chip_num, channel));
+      }
+    }
+    bail!(format!("No PWM chip found for controller {} of pin {}", controller, name))
+  }
+
+  /// Returns the sysfs directory of an exported GPIO, e.g.
+  /// `/sys/class/gpio/gpio45`.
+  pub fn gpio_path(&self, pin_num: u8) -> String {
+    format!("{}/gpio{}", self.gpio_dir, pin_num)
+  }
+
+  /// Returns the character device of the GPIO chip of a GPIO and the
+  /// offset of its line on it.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use libbeaglebone::board::Board;
+  ///
+  /// let (chip, offset) = Board::beaglebone_black().gpio_line(45);
+  /// assert_eq!((chip.as_str(), offset), ("/dev/gpiochip1", 13));
+  /// ```
+  pub fn gpio_line(&self, pin_num: u8) -> (String, u32) {
+    let lines = self.gpio_chip_lines.max(1);
+    (substitute(&self.gpio_chip_device, pin_num / lines), u32::from(pin_num % lines))
+  }
+
+  /// Returns the pinmux state file of the GPIO `pin_num`, or `None` if the
+  /// board has no pinmux helpers or no name for the pin.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use libbeaglebone::board::Board;
+  ///
+  /// assert_eq!(Board::beaglebone_black().pinmux_path(45).as_ref().map(String::as_str),
+  ///            Some("/sys/devices/platform/ocp/ocp:P8_11_pinmux/state"));
+  /// ```
+  pub fn pinmux_path(&self, pin_num: u8) -> Option<String> {
+    match self.pinmux_state_file {
+      Some(ref template) => self.gpio_name(pin_num).map(|name| substitute(template, name)),
+      None => None,
+    }
+  }
+
+  /// Returns the sysfs directory of a PWM chip.
+  pub fn pwm_chip_path(&self, pwm_chip_num: u8) -> String {
+    substitute(&self.pwm_chip_dir, pwm_chip_num)
+  }
+
+  /// Returns the sysfs directory of an exported PWM.
+  pub fn pwm_path(&self, pwm_chip_num: u8, pwm_num: u8) -> String {
+    format!("{}/pwm{}", self.pwm_chip_path(pwm_chip_num), pwm_num)
+  }
+
+  /// Returns the raw value file of an ADC input.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the board has no ADC.
+  pub fn adc_path(&self, adc_num: u16) -> Result<String> {
+    match self.adc_raw_file {
+      Some(ref template) => Ok(substitute(template, adc_num)),
+      None => bail!(format!("The {} has no ADC", self.name)),
+    }
+  }
+
+  /// Returns the device node of a UART.
+  pub fn uart_path(&self, uart_num: u32) -> String {
+    substitute(&self.uart_device, uart_num)
+  }
+
+  /// Returns the device node of an I2C bus.
+  pub fn i2c_path(&self, i2c_num: u8) -> String {
+    substitute(&self.i2c_device, i2c_num)
+  }
+
+  /// Returns the device node of a SPI bus.
+  pub fn spi_path(&self, spi_num: u8) -> String {
+    substitute(&self.spi_device, spi_num)
+  }
+}
+
+fn substitute<T: ToString>(template: &str, num: T) -> String {
+  template.replace("{}", &num.to_string())
+}
+
+/// Returns the current board, the BeagleBone Black unless another one was
+/// selected.
+///
+/// With the `host-stub` feature, the host stub is the default when not
+/// running on a BeagleBone, see the `stub` module.
+pub fn current() -> Arc<Board> {
+  if let Some(ref board) = *CURRENT.read().unwrap_or_else(|e| e.into_inner()) {
+    return board.clone();
+  }
+  let mut current = CURRENT.write().unwrap_or_else(|e| e.into_inner());
+  current.get_or_insert_with(|| Arc::new(default_board())).clone()
+}
+
+fn default_board() -> Board {
+  if cfg!(feature = "host-stub") && !stub::on_beaglebone() {
+    // Without the stub, the BeagleBone's paths fail on first use anyway.
+    if let Ok(board) = stub::board() {
+      return board;
+    }
+  }
+  Board::beaglebone_black()
+}
+
+/// Selects the board that devices created from now on use.
+pub fn set_current(board: Board) {
+  *CURRENT.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(board));
+}
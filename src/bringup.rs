@@ -0,0 +1,335 @@
+//! The bring-up module.
+//!
+//! A health check finds the devices, but not whether the LED is on the pin
+//! the schematic says, or the servo on the PWM it's expected on.
+//! Only someone looking at the board can tell.
+//! Declare what's wired in a `Bringup`, and `run()` walks an operator
+//! through it: it blinks each LED, twitches each servo and shows each
+//! sensor reading, asks whether that was observed, and reports what passed:
+//!
+//! ```no_run
+//! use libbeaglebone::bringup::{Bringup, Console};
+//! use libbeaglebone::prelude::*;
+//!
+//! let bringup = Bringup::new()
+//!   .with_led("status LED", GPIO_P8_11)
+//!   .with_servo("pan servo", 1, 0)
+//!   .with_input("start button", GPIO_P8_12)
+//!   .with_adc("battery divider", AIN_1, Some((1000.0, 1500.0)))
+//!   .with_adc("light sensor", AIN_2, None);
+//!
+//! let report = bringup.run(&mut Console::new()).unwrap();
+//! print!("{}", report);
+//! if !report.passed() {
+//!   std::process::exit(1);
+//! }
+//! ```
+//!
+//! Each step's failure is recorded in the report and the next step run, so
+//! a single run finds all wiring faults; only failing to talk to the
+//! operator aborts it.
+
+use adc::ADC;
+use enums::DeviceState;
+use errors::*;
+use gpio::{self, GPIO, PinDirection, PinState};
+use pins::Pin;
+use servo::Servo;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Someone observing the hardware during a bring-up.
+pub trait Operator {
+  /// Tells the operator what's about to happen or what was measured.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the operator can't be reached.
+  fn inform(&mut self, message: &str) -> Result<()>;
+
+  /// Asks the operator a yes/no question about what they observed.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the operator can't be reached.
+  fn confirm(&mut self, question: &str) -> Result<bool>;
+}
+
+/// An operator at the terminal, on standard input and output.
+#[derive(Debug, Default)]
+pub struct Console;
+
+impl Console {
+  /// Creates an operator on standard input and output.
+  pub fn new() -> Console {
+    Console
+  }
+}
+
+impl Operator for Console {
+  fn inform(&mut self, message: &str) -> Result<()> {
+    println!("{}", message);
+    Ok(())
+  }
+
+  fn confirm(&mut self, question: &str) -> Result<bool> {
+    let stdin = io::stdin();
+    loop {
+      print!("{} [y/n] ", question);
+      io::stdout().flush().chain_err(|| "Failed to write to the console")?;
+      let mut answer = String::new();
+      if stdin.lock().read_line(&mut answer).chain_err(|| "Failed to read from the console")? == 0 {
+        bail!("The console was closed");
+      }
+      match answer.trim().to_lowercase().as_str() {
+        "y" | "yes" => return Ok(true),
+        "n" | "no" => return Ok(false),
+        _ => {}
+      }
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+enum Step {
+  Led(String, Pin),
+  Servo(String, u8, u8),
+  Input(String, Pin),
+  Adc(String, Pin, Option<(f32, f32)>),
+}
+
+/// The hardware to bring up, in the order it's walked through.
+#[derive(Debug, Clone)]
+pub struct Bringup {
+  steps: Vec<Step>,
+  blinks: u32,
+  input_timeout: Duration,
+}
+
+impl Default for Bringup {
+  fn default() -> Bringup {
+    Bringup {
+      steps: Vec::new(),
+      blinks: 5,
+      input_timeout: Duration::from_secs(10),
+    }
+  }
+}
+
+impl Bringup {
+  /// Creates an empty bring-up.
+  pub fn new() -> Bringup {
+    Bringup::default()
+  }
+
+  /// Blinks the LED `name` on the GPIO `pin`.
+  pub fn with_led(mut self, name: &str, pin: Pin) -> Bringup {
+    self.steps.push(Step::Led(name.to_string(), pin));
+    self
+  }
+
+  /// Twitches the servo `name` on PWM `pwm_num` of PWM chip `pwm_chip_num`
+  /// around its center.
+  ///
+  /// The servo's rail has to be powered already.
+  pub fn with_servo(mut self, name: &str, pwm_chip_num: u8, pwm_num: u8) -> Bringup {
+    self.steps.push(Step::Servo(name.to_string(), pwm_chip_num, pwm_num));
+    self
+  }
+
+  /// Asks the operator to actuate the input `name` on the GPIO `pin`, e.g.
+  /// a button, and waits for its level to change.
+  pub fn with_input(mut self, name: &str, pin: Pin) -> Bringup {
+    self.steps.push(Step::Input(name.to_string(), pin));
+    self
+  }
+
+  /// Shows the reading of the sensor `name` on the ADC `pin`.
+  ///
+  /// If `expected` is a range of millivolts, the reading passes if it's in
+  /// the range; otherwise the operator is asked whether it's plausible.
+  pub fn with_adc(mut self, name: &str, pin: Pin, expected: Option<(f32, f32)>) -> Bringup {
+    self.steps.push(Step::Adc(name.to_string(), pin, expected));
+    self
+  }
+
+  /// Sets how often each LED blinks, 5 times by default.
+  pub fn set_blinks(&mut self, blinks: u32) {
+    self.blinks = blinks.max(1);
+  }
+
+  /// Sets how long to wait for each input to change, 10s by default.
+  pub fn set_input_timeout(&mut self, timeout: Duration) {
+    self.input_timeout = timeout;
+  }
+
+  /// Walks `operator` through all steps and reports the outcome of each.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the operator can't be reached; the hardware failing a step
+  /// is recorded in the report instead.
+  pub fn run(&self, operator: &mut dyn Operator) -> Result<Report> {
+    let mut steps = Vec::new();
+    for step in &self.steps {
+      let (subject, outcome) = match *step {
+        Step::Led(ref name, pin) => (format!("LED {}", name), self.blink(name, pin, operator)),
+        Step::Servo(ref name, chip, num) => (format!("Servo {}", name), twitch(name, chip, num, operator)),
+        Step::Input(ref name, pin) => (format!("Input {}", name), self.actuate(name, pin, operator)),
+        Step::Adc(ref name, pin, expected) => (format!("Sensor {}", name), measure(name, pin, expected, operator)),
+      };
+      steps.push(match outcome {
+        Ok((true, detail)) => step_result(subject, StepStatus::Passed, detail),
+        Ok((false, detail)) => step_result(subject, StepStatus::Failed, detail),
+        Err(e) => match *e.kind() {
+          ErrorKind::OperatorUnreachable(_) => return Err(e),
+          _ => step_result(subject, StepStatus::Error, e.to_string()),
+        },
+      });
+    }
+    Ok(Report { steps })
+  }
+
+  fn blink(&self, name: &str, pin: Pin, operator: &mut dyn Operator) -> Result<(bool, String)> {
+    let mut led = gpio::output_driving(pin, PinState::Low)?;
+    ask(operator, |o| o.inform(&format!("Watch {}, it blinks {} times.", name, self.blinks)))?;
+    for _ in 0..self.blinks {
+      led.write(PinState::High)?;
+      thread::sleep(Duration::from_millis(250));
+      led.write(PinState::Low)?;
+      thread::sleep(Duration::from_millis(250));
+    }
+    let seen = ask(operator, |o| o.confirm(&format!("Did {} blink {} times?", name, self.blinks)))?;
+    Ok((seen, format!("GPIO pin #{}", led.pin_num())))
+  }
+
+  fn actuate(&self, name: &str, pin: Pin, operator: &mut dyn Operator) -> Result<(bool, String)> {
+    let input = GPIO::new(pin);
+    input.set_export(DeviceState::Exported)?;
+    input.set_direction(PinDirection::In)?;
+    let idle = input.read()?;
+    ask(operator, |o| {
+      o.inform(&format!("Actuate {} within {}s.", name, self.input_timeout.as_secs()))
+    })?;
+    let deadline = Instant::now() + self.input_timeout;
+    while Instant::now() < deadline {
+      if input.read()? != idle {
+        return Ok((true, format!("GPIO pin #{} changed from {:?}", input.pin_num(), idle)));
+      }
+      thread::sleep(Duration::from_millis(10));
+    }
+    Ok((false, format!("GPIO pin #{} stayed {:?}", input.pin_num(), idle)))
+  }
+}
+
+fn twitch(name: &str, pwm_chip_num: u8, pwm_num: u8, operator: &mut dyn Operator) -> Result<(bool, String)> {
+  let mut servo = Servo::new(pwm_chip_num, pwm_num)?;
+  ask(operator, |o| o.inform(&format!("Watch {}, it twitches around its center.", name)))?;
+  let moved = sweep(&mut servo);
+  let _ = servo.disable();
+  moved?;
+  let seen = ask(operator, |o| o.confirm(&format!("Did {} twitch?", name)))?;
+  Ok((seen, format!("PWM #{}-{}", pwm_chip_num, pwm_num)))
+}
+
+fn sweep(servo: &mut Servo) -> Result<()> {
+  for &degrees in &[80.0, 100.0, 90.0] {
+    servo.set_angle(degrees)?;
+    thread::sleep(Duration::from_millis(500));
+  }
+  Ok(())
+}
+
+fn measure(name: &str,
+           pin: Pin,
+           expected: Option<(f32, f32)>,
+           operator: &mut dyn Operator)
+           -> Result<(bool, String)> {
+  let adc = ADC::new(pin, 1.0);
+  let millivolts = adc.read_millivolts()?;
+  let detail = format!("ADC #{} read {:.0}mV", adc.adc_num(), millivolts);
+  ask(operator, |o| o.inform(&format!("{} reads {:.0}mV.", name, millivolts)))?;
+  let plausible = match expected {
+    Some((min, max)) => (min..=max).contains(&millivolts),
+    None => ask(operator, |o| o.confirm(&format!("Is that plausible for {}?", name)))?,
+  };
+  Ok((plausible, detail))
+}
+
+/// Talks to the operator, marking failures so that they abort the run.
+fn ask<T, F>(operator: &mut dyn Operator, f: F) -> Result<T>
+  where F: FnOnce(&mut dyn Operator) -> Result<T>
+{
+  f(operator).map_err(|e| {
+    let message = e.to_string();
+    Error::with_chain(e, ErrorKind::OperatorUnreachable(message))
+  })
+}
+
+/// The outcome of a single bring-up step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepStatus {
+  /// The operator observed what was expected, or the reading was in range.
+  Passed,
+  /// The operator didn't observe it, or the reading was out of range.
+  Failed,
+  /// The hardware couldn't be driven or read.
+  Error,
+}
+
+/// A single step of a bring-up report.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StepResult {
+  /// What was brought up, e.g. "LED status LED".
+  pub subject: String,
+  /// The outcome of the step.
+  pub status: StepStatus,
+  /// A description of the outcome, e.g. the pin or the reading.
+  pub detail: String,
+}
+
+/// The result of a bring-up.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+  /// All steps that were run, in the order of the bring-up.
+  pub steps: Vec<StepResult>,
+}
+
+impl Report {
+  /// Returns whether all steps passed.
+  pub fn passed(&self) -> bool {
+    self.steps.iter().all(|step| step.status == StepStatus::Passed)
+  }
+
+  /// Returns the steps that didn't pass.
+  pub fn failures(&self) -> Vec<&StepResult> {
+    self.steps
+        .iter()
+        .filter(|step| step.status != StepStatus::Passed)
+        .collect()
+  }
+}
+
+impl fmt::Display for Report {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    for step in &self.steps {
+      let status = match step.status {
+        StepStatus::Passed => "PASS",
+        StepStatus::Failed => "FAIL",
+        StepStatus::Error => "ERROR",
+      };
+      writeln!(f, "[{:>5}] {}: {}", status, step.subject, step.detail)?;
+    }
+    Ok(())
+  }
+}
+
+fn step_result(subject: String, status: StepStatus, detail: String) -> StepResult {
+  StepResult {
+    subject,
+    status,
+    detail,
+  }
+}
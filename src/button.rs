@@ -0,0 +1,343 @@
+//! The button module.
+//!
+//! A user interface wants more from a push button than its level: a press,
+//! a long press and a double click mean different things.
+//! A `Button` debounces a digital input, see the `debounce` module, and
+//! turns its presses into `ButtonEvent`s:
+//!
+//! ```no_run
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::button::{Button, ButtonEvent};
+//!
+//! // A button pulling the pin low when pressed.
+//! let mut button = Button::new(GPIO_P8_11, PinState::Low).unwrap();
+//! button.set_pull(Pull::Up).unwrap();
+//!
+//! let (_subscription, events) = button.events().unwrap();
+//! for event in events {
+//!   match event {
+//!     ButtonEvent::DoubleClick => println!("Next track"),
+//!     ButtonEvent::Held(_) => println!("Power off"),
+//!     _ => {}
+//!   }
+//! }
+//! ```
+//!
+//! Every press is reported as `Pressed`, and `Released` when it ends.
+//! In between, a press that lasts the hold time, 1s by default, is reported
+//! once as `Held`.
+//! A second press starting within the double click time, 300ms by default,
+//! of the release of a press that wasn't held is reported as `DoubleClick`
+//! when it's released, if it wasn't held either.
+
+use clock::{self, Clock};
+use debounce::{DebouncedInput, DebouncedSubscription};
+use errors::*;
+use gpio::{Edge, GPIO, PinState, Pull};
+use hal::DigitalPin;
+use pins::Pin;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// A change of a button.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ButtonEvent {
+  /// The button was pressed.
+  Pressed,
+  /// The button was released.
+  Released,
+  /// The button has been pressed for the contained time, the hold time.
+  Held(Duration),
+  /// The button was released after a second short press.
+  DoubleClick,
+}
+
+/// The presses of a button, turned into events.
+#[derive(Debug, Clone)]
+struct Clicks {
+  hold_time: Duration,
+  double_click_time: Duration,
+  // When the button was pressed, if it is and the press was seen.
+  pressed_at: Option<Duration>,
+  held: bool,
+  // When a short press was released, if it may start a double click.
+  released_at: Option<Duration>,
+  second: bool,
+}
+
+impl Clicks {
+  fn new(hold_time: Duration, double_click_time: Duration) -> Clicks {
+    Clicks {
+      hold_time,
+      double_click_time,
+      pressed_at: None,
+      held: false,
+      released_at: None,
+      second: false,
+    }
+  }
+
+  /// Takes a change of the button at `at`.
+  fn change(&mut self, pressed: bool, at: Duration) -> Vec<ButtonEvent> {
+    if pressed {
+      let double_click_time = self.double_click_time;
+      self.second = self.released_at
+                        .take()
+                        .is_some_and(|released| at - released <= double_click_time);
+      self.pressed_at = Some(at);
+      self.held = false;
+      return vec![ButtonEvent::Pressed];
+    }
+    let mut events = vec![ButtonEvent::Released];
+    if self.pressed_at.take().is_some() && !self.held {
+      if self.second {
+        events.push(ButtonEvent::DoubleClick);
+      } else {
+        self.released_at = Some(at);
+      }
+    }
+    self.second = false;
+    events
+  }
+
+  /// Returns `Held` if the press reached the hold time by `at`.
+  fn tick(&mut self, at: Duration) -> Option<ButtonEvent> {
+    let pressed_at = self.pressed_at?;
+    if self.held || at - pressed_at < self.hold_time {
+      return None;
+    }
+    self.held = true;
+    Some(ButtonEvent::Held(at - pressed_at))
+  }
+
+  /// Returns when the press reaches the hold time, if it's going on.
+  fn next_deadline(&self) -> Option<Duration> {
+    match self.pressed_at {
+      Some(pressed_at) if !self.held => Some(pressed_at + self.hold_time),
+      _ => None,
+    }
+  }
+}
+
+/// A push button on a debounced digital input, by default a GPIO.
+#[derive(Debug)]
+pub struct Button<P: DigitalPin = GPIO> {
+  input: DebouncedInput<P>,
+  active: PinState,
+  clicks: Clicks,
+  pressed: bool,
+  clock: Arc<dyn Clock>,
+  poll_interval: Duration,
+  events: VecDeque<ButtonEvent>,
+}
+
+impl Button<GPIO> {
+  /// Watches a button on the GPIO `pin`, which is exported and made an
+  /// input, pressed at the level `active` and debounced for 20ms.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the pin can't be configured or read.
+  pub fn new(pin: Pin, active: PinState) -> Result<Button> {
+    Button::from_input(DebouncedInput::new(pin, Duration::from_millis(20))?, active)
+  }
+
+  /// Enables the pin's pull-up or pull-down, see `GPIO::set_pull()`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the pull can't be configured.
+  pub fn set_pull(&self, pull: Pull) -> Result<()> {
+    self.input.get_ref().set_pull(pull)
+  }
+
+  /// Calls `callback` on a thread of its own for every event, until the
+  /// returned subscription is dropped.
+  ///
+  /// The presses are taken from the debounced edges, see
+  /// `DebouncedInput::on_edge()`, so they're seen late by the stable time,
+  /// and `poll()` and `wait()` mustn't be used at the same time.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the pin doesn't support edge interrupts, or if the reactor
+  /// can't be started.
+  pub fn on_event<F>(&self, mut callback: F) -> Result<ButtonSubscription>
+    where F: FnMut(ButtonEvent) + Send + 'static
+  {
+    let (subscription, edges) = self.input.edge_events(Edge::Both)?;
+    let mut clicks = self.clicks.clone();
+    let clock = self.clock.clone();
+    let active = self.active;
+    let thread = thread::spawn(move || {
+      loop {
+        let received = match clicks.next_deadline() {
+          Some(deadline) => {
+            edges.recv_timeout(deadline.checked_sub(clock.now()).unwrap_or_default())
+          }
+          None => edges.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match received {
+          Ok(edge) => {
+            for event in clicks.change(edge.state == active, clock.now()) {
+              callback(event);
+            }
+          }
+          Err(RecvTimeoutError::Timeout) => {
+            if let Some(event) = clicks.tick(clock.now()) {
+              callback(event);
+            }
+          }
+          // The subscription was dropped.
+          Err(RecvTimeoutError::Disconnected) => break,
+        }
+      }
+    });
+    Ok(ButtonSubscription {
+      subscription: Some(subscription),
+      thread: Some(thread),
+    })
+  }
+
+  /// Sends the events on a channel, until the returned subscription is
+  /// dropped.
+  ///
+  /// # Errors
+  ///
+  /// See `on_event()`.
+  pub fn events(&self) -> Result<(ButtonSubscription, Receiver<ButtonEvent>)> {
+    let (sender, receiver) = mpsc::channel();
+    let subscription = self.on_event(move |event| {
+      let _ = sender.send(event);
+    })?;
+    Ok((subscription, receiver))
+  }
+}
+
+impl<P: DigitalPin> Button<P> {
+  /// Watches a button on the debounced `input`, pressed at the level
+  /// `active`.
+  ///
+  /// A button that is pressed already only reports its release.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the input can't be read.
+  pub fn from_input(mut input: DebouncedInput<P>, active: PinState) -> Result<Button<P>> {
+    let clock = clock::system();
+    input.set_clock(clock.clone());
+    let pressed = input.read()? == active;
+    Ok(Button {
+      input,
+      active,
+      clicks: Clicks::new(Duration::from_secs(1), Duration::from_millis(300)),
+      pressed,
+      clock,
+      poll_interval: Duration::from_millis(1),
+      events: VecDeque::new(),
+    })
+  }
+
+  /// Sets how long a press lasts to be reported as `Held`, 1s by default.
+  pub fn set_hold_time(&mut self, hold_time: Duration) {
+    self.clicks.hold_time = hold_time;
+  }
+
+  /// Sets how soon after a short press a second one makes a double click,
+  /// 300ms by default.
+  pub fn set_double_click_time(&mut self, double_click_time: Duration) {
+    self.clicks.double_click_time = double_click_time;
+  }
+
+  /// Sets how often `wait()` samples the input, 1ms by default.
+  pub fn set_poll_interval(&mut self, interval: Duration) {
+    self.poll_interval = interval;
+  }
+
+  /// Makes the button and its debounce take their time from `clock`, e.g.
+  /// a `TestClock`.
+  pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+    self.input.set_clock(clock.clone());
+    self.clock = clock;
+  }
+
+  /// Returns whether the button is pressed, as of the last poll.
+  pub fn is_pressed(&self) -> bool {
+    self.pressed
+  }
+
+  /// Samples the input, and returns the next event, if any.
+  ///
+  /// The input has to be sampled more often than the stable time of its
+  /// debounce, see `DebouncedInput::read()`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the input can't be read.
+  pub fn poll(&mut self) -> Result<Option<ButtonEvent>> {
+    if let Some(event) = self.events.pop_front() {
+      return Ok(Some(event));
+    }
+    let pressed = self.input.read()? == self.active;
+    let now = self.clock.now();
+    if let Some(event) = self.clicks.tick(now) {
+      self.events.push_back(event);
+    }
+    if pressed != self.pressed {
+      self.pressed = pressed;
+      self.events.extend(self.clicks.change(pressed, now));
+    }
+    Ok(self.events.pop_front())
+  }
+
+  /// Samples the input until there's an event, or until `timeout` has
+  /// passed.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the input can't be read.
+  pub fn wait(&mut self, timeout: Duration) -> Result<Option<ButtonEvent>> {
+    let deadline = self.clock.now() + timeout;
+    loop {
+      if let Some(event) = self.poll()? {
+        return Ok(Some(event));
+      }
+      match deadline.checked_sub(self.clock.now()) {
+        Some(remaining) if remaining > Duration::from_secs(0) => {
+          self.clock.sleep(self.poll_interval.min(remaining))?
+        }
+        _ => return Ok(None),
+      }
+    }
+  }
+
+  /// Returns the debounced input.
+  pub fn get_ref(&self) -> &DebouncedInput<P> {
+    &self.input
+  }
+
+  /// Unwraps the debounced input.
+  pub fn into_inner(self) -> DebouncedInput<P> {
+    self.input
+  }
+}
+
+/// Calls the callback of a button's events until it's dropped.
+#[derive(Debug)]
+pub struct ButtonSubscription {
+  subscription: Option<DebouncedSubscription>,
+  thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for ButtonSubscription {
+  fn drop(&mut self) {
+    // Dropping the subscription closes the channel the thread waits on.
+    self.subscription = None;
+    if let Some(thread) = self.thread.take() {
+      let _ = thread.join();
+    }
+  }
+}
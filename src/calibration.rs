@@ -0,0 +1,303 @@
+//! The calibration module.
+//!
+//! Servo trims, ADC gains and offsets, IMU biases and load cell factors are
+//! measured once per board and have to survive reboots and software updates.
+//! A `CalibrationStore` keeps them as named values per device, e.g. the
+//! value "offset" of the device "adc0", and saves them either to a file or to
+//! the EEPROM of a cape, so the calibration travels with the hardware.
+//!
+//! The stored data is versioned and protected by a CRC-32, so a store that
+//! was never written, was written by an incompatible version or got
+//! corrupted is rejected instead of silently producing wrong readings.
+//!
+//! Cape EEPROMs are exposed by the at24 driver once the cape is detected, e.g.
+//! at `/sys/bus/i2c/devices/2-0054/eeprom` for the cape in slot 0.
+
+use errors::*;
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::str;
+
+/// Marks the start of stored calibration data.
+const MAGIC: &[u8; 5] = b"BBCAL";
+
+/// The version of the stored format.
+const FORMAT_VERSION: u8 = 1;
+
+/// The size of the magic, version and payload length preceding the payload.
+const HEADER_LEN: usize = 10;
+
+/// The size of the CRC following the payload.
+const CRC_LEN: usize = 4;
+
+/// The offset in a cape EEPROM where calibration data is stored by default,
+/// after the cape header that identifies the cape to the cape manager.
+pub const CAPE_EEPROM_CALIBRATION_OFFSET: u64 = 0x100;
+
+/// Returns the path of the EEPROM of the cape in `slot` (0-3).
+pub fn cape_eeprom_path(slot: u8) -> String {
+  format!("/sys/bus/i2c/devices/2-{:04x}/eeprom", 0x54 + u16::from(slot))
+}
+
+/// Calibration values of several devices.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CalibrationStore {
+  records: BTreeMap<String, BTreeMap<String, f64>>,
+}
+
+impl CalibrationStore {
+  /// Creates an empty store.
+  pub fn new() -> CalibrationStore {
+    CalibrationStore::default()
+  }
+
+  /// Returns the value `key` of `device`, if it's calibrated.
+  pub fn get(&self, device: &str, key: &str) -> Option<f64> {
+    self.records.get(device).and_then(|record| record.get(key)).cloned()
+  }
+
+  /// Returns the value `key` of `device`, or `default` if it isn't
+  /// calibrated.
+  pub fn get_or(&self, device: &str, key: &str, default: f64) -> f64 {
+    self.get(device, key).unwrap_or(default)
+  }
+
+  /// Sets the value `key` of `device`.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use libbeaglebone::calibration::CalibrationStore;
+  ///
+  /// let mut store = CalibrationStore::new();
+  /// store.set("servo0", "trim", 1.5).unwrap();
+  /// assert_eq!(store.get("servo0", "trim"), Some(1.5));
+  /// assert_eq!(store.get_or("servo1", "trim", 0.0), 0.0);
+  ///
+  /// let restored = CalibrationStore::from_bytes(&store.to_bytes()).unwrap();
+  /// assert_eq!(restored, store);
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if `device` or `key` is empty or contains whitespace.
+  pub fn set(&mut self, device: &str, key: &str, value: f64) -> Result<()> {
+    for name in &[device, key] {
+      if name.is_empty() || name.contains(char::is_whitespace) {
+        bail!(format!("Invalid calibration name \"{}\"", name));
+      }
+    }
+    let _ = self.records
+                .entry(device.to_string())
+                .or_default()
+                .insert(key.to_string(), value);
+    Ok(())
+  }
+
+  /// Returns all values of `device`.
+  pub fn record(&self, device: &str) -> Option<&BTreeMap<String, f64>> {
+    self.records.get(device)
+  }
+
+  /// Returns the names of all calibrated devices.
+  pub fn devices(&self) -> Vec<&str> {
+    self.records.keys().map(String::as_str).collect()
+  }
+
+  /// Removes all values of `device`.
+  pub fn remove(&mut self, device: &str) {
+    let _ = self.records.remove(device);
+  }
+
+  /// Encodes the store in the format it is saved in.
+  pub fn to_bytes(&self) -> Vec<u8> {
+    let mut payload = String::new();
+    for (device, record) in &self.records {
+      for (key, value) in record {
+        payload.push_str(&format!("{} {} {}\n", device, key, value));
+      }
+    }
+
+    let mut data = Vec::with_capacity(HEADER_LEN + payload.len() + CRC_LEN);
+    data.extend_from_slice(MAGIC);
+    data.push(FORMAT_VERSION);
+    data.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    data.extend_from_slice(payload.as_bytes());
+    data.extend_from_slice(&crc32(payload.as_bytes()).to_le_bytes());
+    data
+  }
+
+  /// Decodes a store from the format it is saved in.
+  ///
+  /// Trailing data, e.g. the unused rest of an EEPROM, is ignored.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the data doesn't contain calibration data, was saved by an
+  /// unsupported version or is corrupted.
+  pub fn from_bytes(data: &[u8]) -> Result<CalibrationStore> {
+    if data.len() < HEADER_LEN || &data[..MAGIC.len()] != MAGIC {
+      bail!("No calibration data found");
+    }
+    if data[5] != FORMAT_VERSION {
+      bail!(format!("Unsupported calibration data version {}", data[5]));
+    }
+    let len = u32::from_le_bytes([data[6], data[7], data[8], data[9]]) as usize;
+    if data.len() < HEADER_LEN + len + CRC_LEN {
+      bail!("Calibration data is truncated");
+    }
+    let payload = &data[HEADER_LEN..HEADER_LEN + len];
+    let crc = &data[HEADER_LEN + len..HEADER_LEN + len + CRC_LEN];
+    if crc32(payload) != u32::from_le_bytes([crc[0], crc[1], crc[2], crc[3]]) {
+      bail!("Calibration data is corrupted (CRC mismatch)");
+    }
+
+    let payload = str::from_utf8(payload).chain_err(|| "Calibration data is corrupted")?;
+    let mut store = CalibrationStore::new();
+    for line in payload.lines() {
+      let fields: Vec<&str> = line.split(' ').collect();
+      if fields.len() != 3 {
+        bail!(format!("Invalid calibration entry \"{}\"", line));
+      }
+      let value = fields[2]
+        .parse()
+        .chain_err(|| format!("Invalid calibration value \"{}\"", line))?;
+      store.set(fields[0], fields[1], value)?;
+    }
+    Ok(store)
+  }
+
+  /// Loads a store from a file.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::calibration::CalibrationStore;
+  ///
+  /// let path = "/var/lib/robot/calibration";
+  /// let mut store = CalibrationStore::load(path).unwrap_or_default();
+  /// let offset = store.get_or("adc0", "offset", 0.0);
+  ///
+  /// store.set("adc0", "offset", offset + 0.01).unwrap();
+  /// store.save(path).unwrap();
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if the file can't be read or doesn't contain valid calibration
+  /// data.
+  pub fn load<P: AsRef<Path>>(path: P) -> Result<CalibrationStore> {
+    let path = path.as_ref();
+    let data = fs::read(path)
+      .chain_err(|| format!("Failed to read calibration file {}", path.display()))?;
+    CalibrationStore::from_bytes(&data)
+  }
+
+  /// Saves the store to a file, replacing it atomically.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the file can't be written.
+  pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+    let path = path.as_ref();
+    // Write a temporary file first, so a power loss never leaves a
+    // half-written store behind.
+    let tmp_path = path.with_extension("tmp");
+    File::create(&tmp_path)
+      .and_then(|mut file| {
+                  file.write_all(&self.to_bytes())?;
+                  file.sync_all()
+                })
+      .and_then(|_| fs::rename(&tmp_path, path))
+      .chain_err(|| format!("Failed to write calibration file {}", path.display()))
+  }
+
+  /// Loads a store from an EEPROM, starting at `offset`.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::calibration::*;
+  ///
+  /// let eeprom = cape_eeprom_path(0);
+  /// let store = CalibrationStore::load_eeprom(&eeprom, CAPE_EEPROM_CALIBRATION_OFFSET).unwrap();
+  /// println!("Calibrated devices: {:?}", store.devices());
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if the EEPROM can't be read or doesn't contain valid calibration
+  /// data.
+  pub fn load_eeprom<P: AsRef<Path>>(path: P, offset: u64) -> Result<CalibrationStore> {
+    let path = path.as_ref();
+    let mut data = Vec::new();
+    let _ = File::open(path)
+      .and_then(|mut eeprom| {
+                  let _ = eeprom.seek(SeekFrom::Start(offset))?;
+                  eeprom.read_to_end(&mut data)
+                })
+      .chain_err(|| format!("Failed to read EEPROM {}", path.display()))?;
+    CalibrationStore::from_bytes(&data)
+  }
+
+  /// Saves the store to an EEPROM, starting at `offset`.
+  ///
+  /// Only the bytes of the store are written, the rest of the EEPROM is left
+  /// as is.
+  /// Most cape EEPROMs are write protected by a jumper or a test point that
+  /// has to be grounded while writing.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the store doesn't fit into the EEPROM or the EEPROM can't be
+  /// written, e.g. because it's write protected.
+  pub fn save_eeprom<P: AsRef<Path>>(&self, path: P, offset: u64) -> Result<()> {
+    let path = path.as_ref();
+    let data = self.to_bytes();
+    let mut eeprom = OpenOptions::new()
+      .read(true)
+      .write(true)
+      .open(path)
+      .chain_err(|| format!("Failed to open EEPROM {}", path.display()))?;
+    let size = eeprom.metadata()
+                     .chain_err(|| format!("Failed to read EEPROM {}", path.display()))?
+                     .len();
+    if size != 0 && offset + data.len() as u64 > size {
+      bail!(format!("Calibration data ({} bytes) doesn't fit into EEPROM {}",
+                    data.len(),
+                    path.display()));
+    }
+    eeprom.seek(SeekFrom::Start(offset))
+          .and_then(|_| eeprom.write_all(&data))
+          .chain_err(|| format!("Failed to write EEPROM {}", path.display()))?;
+
+    // EEPROMs fail silently when write protected, so read the data back.
+    let mut written = vec![0; data.len()];
+    eeprom.seek(SeekFrom::Start(offset))
+          .and_then(|_| eeprom.read_exact(&mut written))
+          .chain_err(|| format!("Failed to read EEPROM {}", path.display()))?;
+    if written != data {
+      bail!(format!("EEPROM {} didn't accept the calibration data, is it write protected?",
+                    path.display()));
+    }
+    Ok(())
+  }
+}
+
+/// Computes the CRC-32 (IEEE 802.3) of `data`.
+fn crc32(data: &[u8]) -> u32 {
+  let mut crc = 0xFFFF_FFFFu32;
+  for &byte in data {
+    crc ^= u32::from(byte);
+    for _ in 0..8 {
+      crc = if crc & 1 != 0 {
+        (crc >> 1) ^ 0xEDB8_8320
+      } else {
+        crc >> 1
+      };
+    }
+  }
+  !crc
+}
@@ -0,0 +1,266 @@
+//! The cape manager module.
+//!
+//! Overlays reconfigure the pins and peripherals of the BeagleBone, and they
+//! can be applied and removed while the system is running, either by the cape
+//! manager when a cape is plugged in, or by hand, e.g. with
+//! `sudo sh -c "echo 'BB-UART4' > /sys/devices/platform/bone_capemgr/slots"`.
+//!
+//! `loaded_overlays()` lists the overlays that are currently applied,
+//! `apply_overlay()` applies another one, and an `OverlayWatcher` notifies
+//! long-running applications when this changes, so they can re-open devices
+//! that appeared or stop using ones that vanished.
+//!
+//! Both the bone_capemgr slots file and overlays applied through configfs
+//! (`/sys/kernel/config/device-tree/overlays`) are taken into account.
+//! Neither of them supports change notifications, so they are polled.
+
+use errors::*;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use util::*;
+
+/// The slots file of the bone_capemgr driver.
+const SLOTS_PATH: &str = "/sys/devices/platform/bone_capemgr/slots";
+
+/// The directory of overlays applied through configfs.
+const CONFIGFS_OVERLAYS_PATH: &str = "/sys/kernel/config/device-tree/overlays";
+
+/// How long the watcher thread sleeps at most before checking whether it
+/// should stop.
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Parses the contents of the slots file into the names of the loaded
+/// overlays.
+///
+/// # Examples
+///
+/// ```
+/// use libbeaglebone::capemgr::parse_slots;
+///
+/// let slots = " 0: PF----  -1 \n 4: P-O-L-   0 Override Board Name,00A0,Override Manuf,BB-UART1\n";
+/// assert_eq!(parse_slots(slots), ["BB-UART1"]);
+/// ```
+pub fn parse_slots(slots: &str) -> Vec<String> {
+  slots.lines()
+       .filter_map(|line| {
+    // " 4: P-O-L-   0 Override Board Name,00A0,Override Manuf,BB-UART1"
+    let mut fields = line.split_once(':')?.1.split_whitespace();
+    let flags = fields.next()?;
+    let _ = fields.next()?;
+    let description = fields.collect::<Vec<_>>().join(" ");
+    let name = description.rsplit(',').next()?.trim();
+    if flags.contains('L') && !name.is_empty() {
+      Some(name.to_string())
+    } else {
+      None
+    }
+  })
+       .collect()
+}
+
+/// Returns the names of the overlays that are currently applied.
+///
+/// # Examples
+///
+/// ```no_run
+/// use libbeaglebone::capemgr::loaded_overlays;
+///
+/// if !loaded_overlays().unwrap().contains("BB-UART4") {
+///   println!("Enable UART4 first!");
+/// }
+/// ```
+///
+/// # Errors
+///
+/// Fails if the slots file or the configfs overlay directory exist but can't
+/// be read.
+pub fn loaded_overlays() -> Result<BTreeSet<String>> {
+  let mut overlays = BTreeSet::new();
+
+  if Path::new(SLOTS_PATH).exists() {
+    let slots = SLOTS_PATH.read_file().chain_err(|| "Failed to read capemgr slots")?;
+    overlays.extend(parse_slots(&slots));
+  }
+
+  if Path::new(CONFIGFS_OVERLAYS_PATH).exists() {
+    let entries = fs::read_dir(CONFIGFS_OVERLAYS_PATH)
+      .chain_err(|| "Failed to read configfs overlays")?;
+    for entry in entries {
+      let entry = entry.chain_err(|| "Failed to read configfs overlays")?;
+      // An overlay directory is only applied once its status says so.
+      let status = entry.path().join("status");
+      let applied = status.to_str()
+                          .and_then(|path| path.read_file().ok())
+                          .is_none_or(|status| status.trim() == "applied");
+      if applied {
+        let _ = overlays.insert(entry.file_name().to_string_lossy().into_owned());
+      }
+    }
+  }
+
+  Ok(overlays)
+}
+
+/// Applies the overlay `name`, e.g. "BB-UART4", unless it's applied
+/// already.
+///
+/// The overlay is written to the bone_capemgr slots file on kernels that
+/// have one, and applied through configfs from `/lib/firmware/<name>-00A0.dtbo`
+/// otherwise.
+/// Kernels with neither only load overlays at boot, from the
+/// `uboot_overlay_addrN` lines of `/boot/uEnv.txt`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use libbeaglebone::capemgr::apply_overlay;
+///
+/// apply_overlay("BB-UART4").unwrap();
+/// ```
+///
+/// # Errors
+///
+/// Fails if the kernel can't apply overlays at runtime, or if it rejects
+/// the overlay, e.g. because its pins are in use.
+pub fn apply_overlay(name: &str) -> Result<()> {
+  if loaded_overlays()?.contains(name) {
+    return Ok(());
+  }
+  if Path::new(SLOTS_PATH).exists() {
+    return SLOTS_PATH.write_file(name).chain_err(|| format!("The cape manager rejected overlay {}", name));
+  }
+  if Path::new(CONFIGFS_OVERLAYS_PATH).exists() {
+    let dir = format!("{}/{}", CONFIGFS_OVERLAYS_PATH, name);
+    fs::create_dir(&dir).chain_err(|| format!("Failed to create configfs overlay {}", name))?;
+    format!("{}/path", dir).write_file(&format!("{}-00A0.dtbo", name))
+                           .chain_err(|| format!("Failed to apply overlay {}", name))?;
+    let status = format!("{}/status", dir).read_file().unwrap_or_default();
+    if status.trim() != "applied" {
+      let _ = fs::remove_dir(&dir);
+      bail!(format!("The kernel didn't apply overlay {}", name));
+    }
+    return Ok(());
+  }
+  bail!(format!("The kernel can't apply overlays at runtime, add {} to /boot/uEnv.txt", name))
+}
+
+/// A change of the applied overlays.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OverlayEvent {
+  /// The overlay was applied.
+  Applied(String),
+  /// The overlay was removed.
+  Removed(String),
+  /// The overlays couldn't be read; the watcher stops after this.
+  Error(String),
+}
+
+/// Watches for overlays being applied or removed.
+#[derive(Debug)]
+pub struct OverlayWatcher {
+  events: Receiver<OverlayEvent>,
+  running: Arc<AtomicBool>,
+  thread: Option<JoinHandle<()>>,
+}
+
+impl OverlayWatcher {
+  /// Starts watching the applied overlays, checking every `interval`.
+  ///
+  /// Overlays that are applied already don't cause events.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::capemgr::{OverlayEvent, OverlayWatcher};
+  /// use std::time::Duration;
+  ///
+  /// let watcher = OverlayWatcher::new(Duration::from_secs(1)).unwrap();
+  /// loop {
+  ///   match watcher.recv_timeout(Duration::from_secs(60)) {
+  ///     Some(OverlayEvent::Applied(name)) => println!("{} was applied", name),
+  ///     Some(OverlayEvent::Removed(name)) => println!("{} was removed", name),
+  ///     Some(OverlayEvent::Error(e)) => panic!("{}", e),
+  ///     None => {}
+  ///   }
+  /// }
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if the applied overlays can't be read.
+  pub fn new(interval: Duration) -> Result<OverlayWatcher> {
+    let initial = loaded_overlays()?;
+    let (sender, events) = mpsc::channel();
+    let running = Arc::new(AtomicBool::new(true));
+    let thread = {
+      let running = running.clone();
+      thread::spawn(move || watch_overlays(initial, interval, &sender, &running))
+    };
+    Ok(OverlayWatcher {
+      events,
+      running,
+      thread: Some(thread),
+    })
+  }
+
+  /// Returns the next event if there is one.
+  pub fn try_recv(&self) -> Option<OverlayEvent> {
+    self.events.try_recv().ok()
+  }
+
+  /// Waits up to `timeout` for the next event.
+  pub fn recv_timeout(&self, timeout: Duration) -> Option<OverlayEvent> {
+    match self.events.recv_timeout(timeout) {
+      Ok(event) => Some(event),
+      Err(RecvTimeoutError::Timeout) |
+      Err(RecvTimeoutError::Disconnected) => None,
+    }
+  }
+}
+
+impl Drop for OverlayWatcher {
+  fn drop(&mut self) {
+    self.running.store(false, Ordering::SeqCst);
+    if let Some(thread) = self.thread.take() {
+      let _ = thread.join();
+    }
+  }
+}
+
+/// Sends an event for every change of the applied overlays until `running`
+/// is cleared or the overlays can't be read.
+fn watch_overlays(mut known: BTreeSet<String>,
+                  interval: Duration,
+                  events: &Sender<OverlayEvent>,
+                  running: &AtomicBool) {
+  let mut next_check = Instant::now() + interval;
+  while running.load(Ordering::SeqCst) {
+    let now = Instant::now();
+    if now < next_check {
+      thread::sleep((next_check - now).min(STOP_CHECK_INTERVAL));
+      continue;
+    }
+    next_check += interval;
+
+    let current = match loaded_overlays() {
+      Ok(current) => current,
+      Err(e) => {
+        let _ = events.send(OverlayEvent::Error(e.to_string()));
+        return;
+      }
+    };
+    for name in current.difference(&known) {
+      let _ = events.send(OverlayEvent::Applied(name.clone()));
+    }
+    for name in known.difference(&current) {
+      let _ = events.send(OverlayEvent::Removed(name.clone()));
+    }
+    known = current;
+  }
+}
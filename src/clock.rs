@@ -0,0 +1,176 @@
+//! The clock module.
+//!
+//! Minimum relay dwell times, rate and slew limits and periodic timers all
+//! depend on time, which makes them slow and flaky to test with the real
+//! clock.
+//! They take their time from a `Clock`, by default the `SystemClock`, which
+//! can be replaced with a `TestClock` that only advances when told to:
+//!
+//! ```
+//! use libbeaglebone::clock::TestClock;
+//! use libbeaglebone::errors::*;
+//! use libbeaglebone::hal::PwmOutput;
+//! use libbeaglebone::limiter::RateLimited;
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//!
+//! // A PWM output that remembers the duty cycle written last.
+//! #[derive(Debug, Default)]
+//! struct FakePwm(f32);
+//!
+//! impl PwmOutput for FakePwm {
+//!   fn set_period_ns(&mut self, _: u32) -> Result<()> { Ok(()) }
+//!   fn set_duty_cycle_percent(&mut self, percentage: f32) -> Result<()> {
+//!     self.0 = percentage;
+//!     Ok(())
+//!   }
+//!   fn set_enabled(&mut self, _: bool) -> Result<()> { Ok(()) }
+//! }
+//!
+//! let clock = TestClock::new();
+//! let mut pwm = RateLimited::new(FakePwm::default(), Duration::from_millis(20));
+//! pwm.set_clock(Arc::new(clock.clone()));
+//!
+//! pwm.set_duty_cycle_percent(10.0).unwrap();
+//! pwm.set_duty_cycle_percent(20.0).unwrap();
+//! assert_eq!(pwm.get_ref().0, 10.0);
+//!
+//! clock.advance(Duration::from_millis(20));
+//! pwm.update().unwrap();
+//! assert_eq!(pwm.get_ref().0, 20.0);
+//! ```
+
+use errors::*;
+use nix::libc;
+use std::fmt;
+use std::hint;
+use std::ptr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+/// A monotonic source of time.
+///
+/// Times are durations since an arbitrary point, e.g. the boot of the
+/// system, and only meaningful relative to other times of the same clock.
+pub trait Clock: fmt::Debug + Send + Sync {
+  /// Returns the current time.
+  fn now(&self) -> Duration;
+
+  /// Sleeps until the clock reaches `deadline`, possibly later.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the clock can't be slept on.
+  fn sleep_until(&self, deadline: Duration) -> Result<()>;
+
+  /// Busy-waits until the clock reaches `deadline`.
+  fn spin_until(&self, deadline: Duration) {
+    while self.now() < deadline {
+      hint::spin_loop();
+    }
+  }
+
+  /// Sleeps for `duration`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the clock can't be slept on.
+  fn sleep(&self, duration: Duration) -> Result<()> {
+    self.sleep_until(self.now() + duration)
+  }
+
+  /// Returns the time since `earlier`, or zero if it's in the future.
+  fn elapsed_since(&self, earlier: Duration) -> Duration {
+    self.now().checked_sub(earlier).unwrap_or_default()
+  }
+}
+
+/// The monotonic clock of the system.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+  fn now(&self) -> Duration {
+    let mut now = libc::timespec {
+      tv_sec: 0,
+      tv_nsec: 0,
+    };
+    // Can't fail for CLOCK_MONOTONIC with a valid pointer.
+    let _ = unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+    Duration::new(now.tv_sec as u64, now.tv_nsec as u32)
+  }
+
+  fn sleep_until(&self, deadline: Duration) -> Result<()> {
+    let target = libc::timespec {
+      tv_sec: deadline.as_secs() as libc::time_t,
+      tv_nsec: deadline.subsec_nanos() as libc::c_long,
+    };
+    loop {
+      let res = unsafe {
+        libc::clock_nanosleep(libc::CLOCK_MONOTONIC, libc::TIMER_ABSTIME, &target, ptr::null_mut())
+      };
+      match res {
+        0 => return Ok(()),
+        // Interrupted by a signal, the deadline is still the same.
+        libc::EINTR => continue,
+        err => bail!(format!("Failed to sleep until deadline (error {})", err)),
+      }
+    }
+  }
+}
+
+/// Returns the system clock, the default clock of everything taking one.
+pub fn system() -> Arc<dyn Clock> {
+  Arc::new(SystemClock)
+}
+
+/// A clock for tests that only advances when told to.
+///
+/// Sleeping on it advances it to the end of the sleep right away, so code
+/// that sleeps runs without delay.
+/// Clones share the same time, so a test keeps a clone to control the clock
+/// it handed out.
+#[derive(Debug, Clone, Default)]
+pub struct TestClock {
+  now: Arc<Mutex<Duration>>,
+}
+
+impl TestClock {
+  /// Creates a clock at time zero.
+  pub fn new() -> TestClock {
+    TestClock::default()
+  }
+
+  /// Advances the clock by `duration`.
+  pub fn advance(&self, duration: Duration) {
+    *self.lock() += duration;
+  }
+
+  /// Sets the clock to `now`, which may also turn it back.
+  pub fn set(&self, now: Duration) {
+    *self.lock() = now;
+  }
+
+  fn lock(&self) -> MutexGuard<'_, Duration> {
+    self.now.lock().unwrap_or_else(|e| e.into_inner())
+  }
+}
+
+impl Clock for TestClock {
+  fn now(&self) -> Duration {
+    *self.lock()
+  }
+
+  fn sleep_until(&self, deadline: Duration) -> Result<()> {
+    let mut now = self.lock();
+    if *now < deadline {
+      *now = deadline;
+    }
+    Ok(())
+  }
+
+  fn spin_until(&self, deadline: Duration) {
+    // Spinning on a clock that doesn't advance by itself would never end.
+    let _ = self.sleep_until(deadline);
+  }
+}
@@ -0,0 +1,247 @@
+//! The closed-loop motor module.
+//!
+//! A DC motor's speed at a given duty cycle depends on its load and its
+//! supply voltage; holding a speed needs an encoder on the shaft and a
+//! controller closing the loop.
+//! `ClosedLoopMotor` combines a `Motor`, a `RotaryEncoder` and a `Pid`:
+//!
+//! ```no_run
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::closed_loop_motor::ClosedLoopMotor;
+//! use libbeaglebone::encoder::RotaryEncoder;
+//! use libbeaglebone::motor::Motor;
+//! use libbeaglebone::thermostat::Pid;
+//!
+//! let motor = Motor::new(3, 0, GPIO_P8_11, GPIO_P8_12).unwrap();
+//! let mut encoder = RotaryEncoder::new(GPIO_P8_14, GPIO_P8_15).unwrap();
+//! // Every transition counts, 48 per revolution of the shaft.
+//! encoder.set_steps_per_detent(1);
+//!
+//! let mut wheel = ClosedLoopMotor::new(motor, encoder, Pid::new(0.002, 0.01, 0.0), 48);
+//! wheel.set_speed(120.0).unwrap();
+//! loop {
+//!   let telemetry = wheel.update().unwrap();
+//!   println!("{} rpm at {}", telemetry.rpm, telemetry.power);
+//! }
+//! ```
+//!
+//! `update()` samples the encoder, so it has to be called at least once per
+//! transition, and runs the controller every control interval, 50ms by
+//! default.
+//! The gains of the `Pid` are per rpm, its output is the motor's speed from
+//! 0 to 1 in the commanded direction.
+//! A speed of 0 lets the motor coast.
+
+use clock::{self, Clock};
+use encoder::RotaryEncoder;
+use errors::*;
+use gpio::GPIO;
+use hal::DigitalPin;
+use motor::Motor;
+use std::sync::Arc;
+use std::time::Duration;
+use thermostat::Pid;
+
+/// What a closed-loop motor does.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Telemetry {
+  /// The commanded speed in rpm, negative in reverse.
+  pub setpoint_rpm: f32,
+  /// The speed measured over the last control interval in rpm, negative in
+  /// reverse.
+  pub rpm: f32,
+  /// The motor's speed from 0 to 1, as computed by the controller.
+  pub power: f32,
+  /// The encoder's position.
+  pub position: i64,
+}
+
+/// A DC motor whose speed is held by a PID controller reading an encoder.
+#[derive(Debug)]
+pub struct ClosedLoopMotor<P: DigitalPin = GPIO> {
+  motor: Motor,
+  encoder: RotaryEncoder<P>,
+  pid: Pid,
+  counts_per_rev: f32,
+  clock: Arc<dyn Clock>,
+  interval: Duration,
+  setpoint_rpm: f32,
+  rpm: f32,
+  power: f32,
+  // The time and the position at the start of the control interval.
+  last: Option<(Duration, i64)>,
+}
+
+impl<P: DigitalPin> ClosedLoopMotor<P> {
+  /// Controls `motor` with `pid`, measuring its speed with `encoder`, whose
+  /// position changes by `counts_per_rev` per revolution, clockwise being
+  /// forward.
+  ///
+  /// The motor isn't commanded until `set_speed()`.
+  pub fn new(motor: Motor, encoder: RotaryEncoder<P>, pid: Pid, counts_per_rev: u32) -> ClosedLoopMotor<P> {
+    ClosedLoopMotor {
+      motor,
+      encoder,
+      pid,
+      counts_per_rev: counts_per_rev.max(1) as f32,
+      clock: clock::system(),
+      interval: Duration::from_millis(50),
+      setpoint_rpm: 0.0,
+      rpm: 0.0,
+      power: 0.0,
+      last: None,
+    }
+  }
+
+  /// Sets how often the controller runs, 50ms by default; the measured
+  /// speed is averaged over it.
+  pub fn set_interval(&mut self, interval: Duration) {
+    self.interval = interval;
+  }
+
+  /// Makes the motor take its time from `clock`, e.g. a `TestClock`.
+  pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+    self.clock = clock;
+    self.last = None;
+  }
+
+  /// Commands the speed `rpm`, negative in reverse, or coasting for 0.
+  ///
+  /// The controller starts over when the direction changes.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the speed isn't finite, or if the motor can't coast.
+  pub fn set_speed(&mut self, rpm: f32) -> Result<()> {
+    if !rpm.is_finite() {
+      bail!(format!("Motor speed {} rpm is not a number", rpm));
+    }
+    if rpm == 0.0 {
+      self.motor.coast()?;
+      self.power = 0.0;
+    }
+    if rpm == 0.0 || rpm.is_sign_negative() != self.setpoint_rpm.is_sign_negative() {
+      self.pid.reset();
+    }
+    self.setpoint_rpm = rpm;
+    Ok(())
+  }
+
+  /// Samples the encoder and, once per control interval, measures the
+  /// speed and drives the motor with the controller's output.
+  ///
+  /// ```
+  /// use libbeaglebone::prelude::*;
+  /// use libbeaglebone::{board, stub};
+  /// use libbeaglebone::clock::TestClock;
+  /// use libbeaglebone::closed_loop_motor::ClosedLoopMotor;
+  /// use libbeaglebone::encoder::RotaryEncoder;
+  /// use libbeaglebone::errors::*;
+  /// use libbeaglebone::motor::{Motor, MotorState};
+  /// use libbeaglebone::thermostat::Pid;
+  /// use std::sync::{Arc, Mutex};
+  /// use std::time::Duration;
+  ///
+  /// // An input reading a level set by the test.
+  /// struct FakePin(Arc<Mutex<PinState>>);
+  ///
+  /// impl DigitalPin for FakePin {
+  ///   fn set_state(&mut self, _: PinState) -> Result<()> { Ok(()) }
+  ///   fn state(&self) -> Result<PinState> { Ok(*self.0.lock().unwrap()) }
+  ///   fn pin_name(&self) -> String { "fake pin".to_string() }
+  /// }
+  ///
+  /// board::set_current(stub::board().unwrap());
+  /// let motor = Motor::new(0, 0, GPIO_P8_11, GPIO_P8_12).unwrap();
+  /// let (a, b) = (Arc::new(Mutex::new(PinState::High)), Arc::new(Mutex::new(PinState::High)));
+  /// let mut encoder = RotaryEncoder::from_pins(FakePin(a.clone()), FakePin(b.clone())).unwrap();
+  /// encoder.set_steps_per_detent(1);
+  ///
+  /// let clock = TestClock::new();
+  /// let mut wheel = ClosedLoopMotor::new(motor, encoder, Pid::new(0.0078125, 0.0, 0.0), 4);
+  /// wheel.set_clock(Arc::new(clock.clone()));
+  /// wheel.set_speed(64.0).unwrap();
+  /// wheel.update().unwrap();
+  ///
+  /// // Standing still, the motor is driven at 0.0078125 * 64 rpm.
+  /// clock.advance(Duration::from_millis(50));
+  /// assert_eq!(wheel.update().unwrap().power, 0.5);
+  /// assert_eq!(wheel.motor().state(), MotorState::Forward(0.5));
+  ///
+  /// // A revolution in 50ms is 1200 rpm, far too fast.
+  /// for &(level_a, level_b) in &[(PinState::Low, PinState::High), (PinState::Low, PinState::Low),
+  ///                              (PinState::High, PinState::Low), (PinState::High, PinState::High)] {
+  ///   *a.lock().unwrap() = level_a;
+  ///   *b.lock().unwrap() = level_b;
+  ///   wheel.update().unwrap();
+  /// }
+  /// clock.advance(Duration::from_millis(50));
+  /// let telemetry = wheel.update().unwrap();
+  /// assert_eq!((telemetry.rpm, telemetry.power), (1200.0, 0.0));
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if the encoder can't be read or the motor can't be driven.
+  pub fn update(&mut self) -> Result<Telemetry> {
+    let _ = self.encoder.poll()?;
+    let now = self.clock.now();
+    let position = self.encoder.position();
+    let (start, start_position) = match self.last {
+      Some(last) => last,
+      None => {
+        self.last = Some((now, position));
+        return Ok(self.telemetry());
+      }
+    };
+    let elapsed = now.checked_sub(start).unwrap_or_default();
+    if elapsed < self.interval {
+      return Ok(self.telemetry());
+    }
+    self.last = Some((now, position));
+    let revs = (position - start_position) as f32 / self.counts_per_rev;
+    self.rpm = revs / elapsed.as_secs_f32() * 60.0;
+    self.motor.update()?;
+    if self.setpoint_rpm == 0.0 {
+      return Ok(self.telemetry());
+    }
+    // The controller works on the speed in the commanded direction.
+    let (setpoint, measured) = if self.setpoint_rpm < 0.0 {
+      (-self.setpoint_rpm, -self.rpm)
+    } else {
+      (self.setpoint_rpm, self.rpm)
+    };
+    self.power = self.pid.update(setpoint, measured, elapsed);
+    if self.setpoint_rpm < 0.0 {
+      self.motor.reverse(self.power)?;
+    } else {
+      self.motor.forward(self.power)?;
+    }
+    Ok(self.telemetry())
+  }
+
+  /// Returns what the motor does as of the last control interval.
+  pub fn telemetry(&self) -> Telemetry {
+    Telemetry {
+      setpoint_rpm: self.setpoint_rpm,
+      rpm: self.rpm,
+      power: self.power,
+      position: self.encoder.position(),
+    }
+  }
+
+  /// Returns the motor.
+  pub fn motor(&self) -> &Motor {
+    &self.motor
+  }
+
+  /// Returns the encoder.
+  pub fn encoder(&self) -> &RotaryEncoder<P> {
+    &self.encoder
+  }
+
+  /// Unwraps the motor, the encoder and the controller.
+  pub fn into_inner(self) -> (Motor, RotaryEncoder<P>, Pid) {
+    (self.motor, self.encoder, self.pid)
+  }
+}
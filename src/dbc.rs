@@ -0,0 +1,428 @@
+//! The DBC module.
+//!
+//! DBC files describe how the signals of a CAN network are packed into the
+//! data bytes of its frames.
+//! A `Database` loaded from such a file decodes the data of received frames
+//! into named signals in engineering units, and encodes signal values into
+//! frame data for sending.
+//!
+//! Only the message (`BO_`) and signal (`SG_`) definitions are used, including
+//! simple multiplexing; all other sections of the file are ignored.
+//! The module works on plain frame IDs and data bytes, so it can be used with
+//! any CAN interface.
+
+use errors::*;
+use std::fs;
+use std::path::Path;
+
+/// The bit numbering and byte order of a signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteOrder {
+  /// Intel byte order (`@1` in DBC files), least significant byte first.
+  LittleEndian,
+  /// Motorola byte order (`@0` in DBC files), most significant byte first.
+  BigEndian,
+}
+
+/// The role of a signal in a multiplexed message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Multiplex {
+  /// The signal is always present.
+  None,
+  /// The signal selects which multiplexed signals are present (`M`).
+  Multiplexor,
+  /// The signal is only present if the multiplexor has this value (`m<n>`).
+  Multiplexed(u64),
+}
+
+/// A signal definition.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signal {
+  /// The name of the signal.
+  pub name: String,
+  /// The start bit, as numbered by DBC files.
+  pub start_bit: u32,
+  /// The length in bits.
+  pub length: u32,
+  /// The byte order of the raw value.
+  pub byte_order: ByteOrder,
+  /// Whether the raw value is signed.
+  pub signed: bool,
+  /// The factor the raw value is scaled by.
+  pub factor: f64,
+  /// The offset added to the scaled raw value.
+  pub offset: f64,
+  /// The minimum physical value.
+  pub min: f64,
+  /// The maximum physical value.
+  pub max: f64,
+  /// The physical unit, e.g. "rpm".
+  pub unit: String,
+  /// The role of the signal in a multiplexed message.
+  pub multiplex: Multiplex,
+}
+
+impl Signal {
+  /// Extracts the raw value of the signal from frame data.
+  pub fn raw_value(&self, data: &[u8]) -> Result<u64> {
+    let mut raw = 0;
+    for (i, bit) in self.bit_positions().enumerate() {
+      let byte = match data.get((bit / 8) as usize) {
+        Some(&byte) => byte,
+        None => bail!(format!("Frame data is too short for signal {}", self.name)),
+      };
+      let value = u64::from(byte >> (bit % 8) & 1);
+      raw |= match self.byte_order {
+        ByteOrder::LittleEndian => value << i,
+        ByteOrder::BigEndian => value << (self.length as usize - 1 - i),
+      };
+    }
+    Ok(raw)
+  }
+
+  /// Decodes the physical value of the signal from frame data.
+  pub fn decode(&self, data: &[u8]) -> Result<f64> {
+    let raw = self.raw_value(data)?;
+    let raw = if self.signed && self.length < 64 && raw >> (self.length - 1) & 1 == 1 {
+      // Sign-extend the raw value.
+      (raw | !0 << self.length) as i64 as f64
+    } else if self.signed {
+      raw as i64 as f64
+    } else {
+      raw as f64
+    };
+    Ok(raw * self.factor + self.offset)
+  }
+
+  /// Encodes a physical value of the signal into frame data.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the value doesn't fit into the signal, or if `data` is too
+  /// short.
+  pub fn encode(&self, value: f64, data: &mut [u8]) -> Result<()> {
+    let raw = ((value - self.offset) / self.factor).round();
+    let (min, max) = if self.signed {
+      (-(2f64.powi(self.length as i32 - 1)), 2f64.powi(self.length as i32 - 1) - 1.0)
+    } else {
+      (0.0, 2f64.powi(self.length as i32) - 1.0)
+    };
+    if !(min..=max).contains(&raw) {
+      bail!(format!("Value {} doesn't fit into signal {}", value, self.name));
+    }
+    let raw = raw as i64 as u64;
+
+    for (i, bit) in self.bit_positions().enumerate() {
+      let value = match self.byte_order {
+        ByteOrder::LittleEndian => raw >> i & 1,
+        ByteOrder::BigEndian => raw >> (self.length as usize - 1 - i) & 1,
+      };
+      let byte = match data.get_mut((bit / 8) as usize) {
+        Some(byte) => byte,
+        None => bail!(format!("Frame data is too short for signal {}", self.name)),
+      };
+      *byte = *byte & !(1 << (bit % 8)) | (value as u8) << (bit % 8);
+    }
+    Ok(())
+  }
+
+  /// Returns the positions of the signal's bits, starting with the least
+  /// significant bit for Intel and the most significant bit for Motorola
+  /// byte order.
+  fn bit_positions(&self) -> Box<dyn Iterator<Item = u32>> {
+    match self.byte_order {
+      ByteOrder::LittleEndian => Box::new(self.start_bit..self.start_bit.saturating_add(self.length)),
+      ByteOrder::BigEndian => {
+        // Motorola signals run from the start bit towards bit 0 of a byte,
+        // then continue at bit 7 of the next byte.
+        let positions = (0..self.length).scan(self.start_bit, |bit, _| {
+          let current = *bit;
+          *bit = if current % 8 == 0 { current.saturating_add(15) } else { current - 1 };
+          Some(current)
+        });
+        Box::new(positions.collect::<Vec<_>>().into_iter())
+      }
+    }
+  }
+}
+
+/// A message definition.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+  /// The CAN ID, without the extended frame flag.
+  pub id: u32,
+  /// Whether the message uses an extended (29-bit) ID.
+  pub extended: bool,
+  /// The name of the message.
+  pub name: String,
+  /// The data length in bytes.
+  pub size: usize,
+  /// The signals of the message.
+  pub signals: Vec<Signal>,
+}
+
+/// A decoded signal value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SignalValue<'a> {
+  /// The name of the signal.
+  pub name: &'a str,
+  /// The physical value.
+  pub value: f64,
+  /// The physical unit.
+  pub unit: &'a str,
+}
+
+impl Message {
+  /// Returns the signal called `name`.
+  pub fn signal(&self, name: &str) -> Option<&Signal> {
+    self.signals.iter().find(|s| s.name == name)
+  }
+
+  /// Decodes all signals that are present in the frame data.
+  ///
+  /// Multiplexed signals are only decoded if the multiplexor selects them.
+  pub fn decode(&self, data: &[u8]) -> Result<Vec<SignalValue<'_>>> {
+    let selected = match self.signals.iter().find(|s| s.multiplex == Multiplex::Multiplexor) {
+      Some(multiplexor) => Some(multiplexor.raw_value(data)?),
+      None => None,
+    };
+
+    let mut values = Vec::with_capacity(self.signals.len());
+    for signal in &self.signals {
+      if let Multiplex::Multiplexed(id) = signal.multiplex {
+        if selected != Some(id) {
+          continue;
+        }
+      }
+      values.push(SignalValue {
+        name: &signal.name,
+        value: signal.decode(data)?,
+        unit: &signal.unit,
+      });
+    }
+    Ok(values)
+  }
+
+  /// Encodes signal values into frame data of the message's size.
+  ///
+  /// Signals that aren't given are zero.
+  ///
+  /// # Errors
+  ///
+  /// Fails if a signal doesn't exist in the message or a value doesn't fit
+  /// into its signal.
+  pub fn encode(&self, values: &[(&str, f64)]) -> Result<Vec<u8>> {
+    let mut data = vec![0; self.size];
+    for &(name, value) in values {
+      match self.signal(name) {
+        Some(signal) => signal.encode(value, &mut data)?,
+        None => bail!(format!("Message {} has no signal {}", self.name, name)),
+      }
+    }
+    Ok(data)
+  }
+}
+
+/// The message definitions of a DBC file.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Database {
+  /// The messages defined in the file.
+  pub messages: Vec<Message>,
+}
+
+impl Database {
+  /// Loads a DBC file.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::dbc::Database;
+  ///
+  /// let db = Database::open("/home/debian/vehicle.dbc").unwrap();
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if the file can't be read or parsed.
+  pub fn open<P: AsRef<Path>>(path: P) -> Result<Database> {
+    let path = path.as_ref();
+    let contents = fs::read_to_string(path)
+                         .chain_err(|| format!("Failed to read DBC file {}", path.display()))?;
+    Database::parse(&contents).chain_err(|| format!("Failed to parse DBC file {}", path.display()))
+  }
+
+  /// Parses the contents of a DBC file.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use libbeaglebone::dbc::Database;
+  ///
+  /// let db = Database::parse(r#"
+  /// BO_ 2364540158 EEC1: 8 Engine
+  ///  SG_ EngineSpeed : 24|16@1+ (0.125,0) [0|8031.875] "rpm" Vector__XXX
+  /// "#).unwrap();
+  ///
+  /// let values = db.decode(0x0CF004FE, &[0, 0, 0, 0x40, 0x1F, 0, 0, 0]).unwrap();
+  /// assert_eq!(values[0].name, "EngineSpeed");
+  /// assert_eq!(values[0].value, 1000.0);
+  /// assert_eq!(values[0].unit, "rpm");
+  ///
+  /// let data = db.message_by_name("EEC1").unwrap().encode(&[("EngineSpeed", 1000.0)]).unwrap();
+  /// assert_eq!(data, [0, 0, 0, 0x40, 0x1F, 0, 0, 0]);
+  ///
+  /// // Bits 500 to 515 are beyond the end of any frame.
+  /// assert!(Database::parse(r#"
+  /// BO_ 2364540158 EEC1: 8 Engine
+  ///  SG_ EngineSpeed : 500|16@1+ (0.125,0) [0|8031.875] "rpm" Vector__XXX
+  /// "#).is_err());
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if a message or signal definition is malformed, or a signal
+  /// doesn't fit into a 64 byte frame.
+  pub fn parse(contents: &str) -> Result<Database> {
+    let mut db = Database::default();
+    for (line_num, line) in contents.lines().enumerate() {
+      let line = line.trim();
+      if line.starts_with("BO_ ") {
+        db.messages.push(parse_message(line).chain_err(|| format!("Invalid message on line {}", line_num + 1))?);
+      } else if line.starts_with("SG_ ") {
+        let signal = parse_signal(line).chain_err(|| format!("Invalid signal on line {}", line_num + 1))?;
+        match db.messages.last_mut() {
+          Some(message) => message.signals.push(signal),
+          None => bail!(format!("Signal on line {} doesn't belong to a message", line_num + 1)),
+        }
+      }
+    }
+    Ok(db)
+  }
+
+  /// Returns the message with the CAN ID `id`.
+  pub fn message(&self, id: u32) -> Option<&Message> {
+    self.messages.iter().find(|m| m.id == id)
+  }
+
+  /// Returns the message called `name`.
+  pub fn message_by_name(&self, name: &str) -> Option<&Message> {
+    self.messages.iter().find(|m| m.name == name)
+  }
+
+  /// Decodes the signals of a received frame.
+  ///
+  /// # Errors
+  ///
+  /// Fails if there is no message with the CAN ID `id`, or if the data is too
+  /// short for its signals.
+  pub fn decode(&self, id: u32, data: &[u8]) -> Result<Vec<SignalValue<'_>>> {
+    match self.message(id) {
+      Some(message) => message.decode(data),
+      None => bail!(format!("No message with CAN ID 0x{:X} in the DBC file", id)),
+    }
+  }
+}
+
+/// The flag DBC files use to mark extended IDs.
+const EXTENDED_ID_FLAG: u32 = 0x8000_0000;
+
+/// The bits of the largest frame, a CAN FD frame of 64 bytes.
+const MAX_FRAME_BITS: u32 = 512;
+
+/// Parses `BO_ <id> <name>: <size> <transmitter>`.
+fn parse_message(line: &str) -> Result<Message> {
+  let mut fields = line.split_whitespace().skip(1);
+  let id = fields.next()
+                 .and_then(|id| id.parse::<u32>().ok())
+                 .ok_or("Invalid message ID")?;
+  let name = fields.next()
+                   .map(|name| name.trim_end_matches(':'))
+                   .filter(|name| !name.is_empty())
+                   .ok_or("Missing message name")?;
+  let size = fields.next()
+                   .and_then(|size| size.parse().ok())
+                   .ok_or("Invalid message size")?;
+  Ok(Message {
+    id: id & !EXTENDED_ID_FLAG,
+    extended: id & EXTENDED_ID_FLAG != 0,
+    name: name.to_string(),
+    size,
+    signals: Vec::new(),
+  })
+}
+
+/// Parses
+/// `SG_ <name> [M|m<n>] : <start>|<length>@<order><sign> (<factor>,<offset>)
+/// [<min>|<max>] "<unit>" <receivers>`.
+fn parse_signal(line: &str) -> Result<Signal> {
+  let colon = line.find(':').ok_or("Missing ':'")?;
+  let mut head = line[..colon].split_whitespace().skip(1);
+  let name = head.next().ok_or("Missing signal name")?;
+  let multiplex = match head.next() {
+    None => Multiplex::None,
+    Some("M") => Multiplex::Multiplexor,
+    Some(m) if m.starts_with('m') => {
+      Multiplex::Multiplexed(m[1..].parse().chain_err(|| "Invalid multiplexer value")?)
+    }
+    Some(m) => bail!(format!("Invalid multiplexer indicator {}", m)),
+  };
+
+  let rest = line[colon + 1..].trim();
+  let layout = rest.split_whitespace().next().ok_or("Missing signal layout")?;
+  let pipe = layout.find('|').ok_or("Invalid signal layout")?;
+  let at = layout.find('@').ok_or("Invalid signal layout")?;
+  let start_bit: u32 = layout[..pipe].parse().chain_err(|| "Invalid start bit")?;
+  let length: u32 = layout[pipe + 1..at].parse().chain_err(|| "Invalid signal length")?;
+  if length == 0 || length > 64 {
+    bail!(format!("Invalid signal length {}", length));
+  }
+  let byte_order = match &layout[at + 1..] {
+    o if o.starts_with('1') => ByteOrder::LittleEndian,
+    o if o.starts_with('0') => ByteOrder::BigEndian,
+    _ => bail!("Invalid byte order"),
+  };
+  let signed = layout.ends_with('-');
+
+  let (factor, offset) = parse_pair(between(rest, '(', ')')?, ',')?;
+  let (min, max) = parse_pair(between(rest, '[', ']')?, '|')?;
+  let unit = between(rest, '"', '"')?;
+
+  let signal = Signal {
+    name: name.to_string(),
+    start_bit,
+    length,
+    byte_order,
+    signed,
+    factor,
+    offset,
+    min,
+    max,
+    unit: unit.to_string(),
+    multiplex,
+  };
+  let fits = match byte_order {
+    ByteOrder::LittleEndian => start_bit.checked_add(length).is_some_and(|end| end <= MAX_FRAME_BITS),
+    // Checking the start bit first keeps the positions from overflowing.
+    ByteOrder::BigEndian => start_bit < MAX_FRAME_BITS && signal.bit_positions().all(|bit| bit < MAX_FRAME_BITS),
+  };
+  if !fits {
+    bail!(format!("Signal {} at bit {} with {} bits doesn't fit into a 64 byte frame", name, start_bit, length));
+  }
+  Ok(signal)
+}
+
+/// Returns the text between the first `open` and the following `close`.
+fn between(text: &str, open: char, close: char) -> Result<&str> {
+  let start = text.find(open).ok_or_else(|| format!("Missing '{}'", open))? + 1;
+  let len = text[start..].find(close).ok_or_else(|| format!("Missing '{}'", close))?;
+  Ok(&text[start..start + len])
+}
+
+/// Parses two numbers separated by `separator`.
+fn parse_pair(text: &str, separator: char) -> Result<(f64, f64)> {
+  let mut parts = text.splitn(2, separator);
+  let first = parts.next().unwrap_or("").trim();
+  let second = parts.next().ok_or_else(|| format!("Missing '{}'", separator))?.trim();
+  Ok((first.parse().chain_err(|| format!("Invalid number {}", first))?,
+      second.parse().chain_err(|| format!("Invalid number {}", second))?))
+}
@@ -0,0 +1,269 @@
+//! The debounce module.
+//!
+//! Mechanical contacts bounce: a button or reed switch closing reads as a
+//! burst of edges over a few milliseconds, instead of one.
+//! A `DebouncedInput` only takes a new level once the input stayed at it
+//! for a stable time, both when it's read and for its edges:
+//!
+//! ```no_run
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::debounce::DebouncedInput;
+//! use std::time::Duration;
+//!
+//! let mut button = DebouncedInput::new(GPIO_P8_11, Duration::from_millis(20)).unwrap();
+//!
+//! // Polled, e.g. from a control loop.
+//! if button.read().unwrap() == PinState::Low {
+//!   println!("Pressed");
+//! }
+//!
+//! // Or blocking, for the next press.
+//! button.wait_for_edge(Edge::Falling, None).unwrap();
+//!
+//! // Or on a channel, for another button.
+//! let reed = DebouncedInput::new(GPIO_P8_12, Duration::from_millis(5)).unwrap();
+//! let (_subscription, presses) = reed.edge_events(Edge::Falling).unwrap();
+//! for press in presses {
+//!   println!("Pressed at {:?}", press.timestamp);
+//! }
+//! ```
+//!
+//! Polled reads only see bounce that is sampled, so they have to be called
+//! more often than the stable time for the filter to work; the edges are
+//! seen as they happen.
+//! Each debounced edge is reported once the input settled, i.e. late by the
+//! stable time, but timestamped with the last edge of its bounce.
+
+use clock::{self, Clock};
+use enums::DeviceState;
+use errors::*;
+use gpio::{Edge, GPIO, PinDirection, PinState};
+use hal::DigitalPin;
+use pins::Pin;
+use reactor::{self, EdgeEvent, Subscription};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// A digital input that ignores changes shorter than a stable time, by
+/// default on a GPIO.
+#[derive(Debug)]
+pub struct DebouncedInput<P: DigitalPin = GPIO> {
+  pin: P,
+  stable_time: Duration,
+  clock: Arc<dyn Clock>,
+  state: PinState,
+  // A level that differs from `state`, and when it was first seen.
+  candidate: Option<(PinState, Duration)>,
+  edges_enabled: bool,
+}
+
+impl DebouncedInput<GPIO> {
+  /// Debounces the GPIO `pin`, which is exported and made an input.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the pin can't be configured or read.
+  pub fn new(pin: Pin, stable_time: Duration) -> Result<DebouncedInput> {
+    let input = GPIO::new(pin);
+    input.set_export(DeviceState::Exported)?;
+    input.set_direction(PinDirection::In)?;
+    DebouncedInput::from_pin(input, stable_time)
+  }
+
+  /// Blocks until the input settles at a new level after an edge `edge`,
+  /// or until `timeout` has passed, waiting forever for `None`.
+  ///
+  /// Returns the new level, or `None` if the timeout expired.
+  /// The first call enables edge detection on both edges, see
+  /// `GPIO::set_edge()`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if `edge` is `Edge::None`, or if the pin can't be read or waited
+  /// on.
+  pub fn wait_for_edge(&mut self, edge: Edge, timeout: Option<Duration>) -> Result<Option<PinState>> {
+    if edge == Edge::None {
+      bail!("Waiting for Edge::None never returns");
+    }
+    if !self.edges_enabled {
+      self.pin.set_edge(Edge::Both)?;
+      self.edges_enabled = true;
+    }
+    let deadline = timeout.map(|timeout| self.clock.now() + timeout);
+    loop {
+      let remaining = match deadline {
+        Some(deadline) => match deadline.checked_sub(self.clock.now()) {
+          Some(remaining) if remaining > Duration::from_secs(0) => Some(remaining),
+          _ => return Ok(None),
+        },
+        None => None,
+      };
+      if self.pin.wait_for_edge(remaining)?.is_none() {
+        return Ok(None);
+      }
+      // Wait out the bounce.
+      while self.pin.wait_for_edge(Some(self.stable_time))?.is_some() {}
+      let level = self.pin.read()?;
+      self.candidate = None;
+      if level != self.state {
+        self.state = level;
+        if reactor::matches(edge, level) {
+          return Ok(Some(level));
+        }
+      }
+    }
+  }
+
+  /// Calls `callback` on a thread of its own for every debounced edge
+  /// `edge`, until the returned subscription is dropped.
+  ///
+  /// The edges are taken from the shared reactor, see `GPIO::on_edge()`,
+  /// so `wait_for_edge()` mustn't be used at the same time.
+  ///
+  /// # Errors
+  ///
+  /// Fails if `edge` is `Edge::None`, if the pin doesn't support edge
+  /// interrupts, or if the reactor can't be started.
+  pub fn on_edge<F>(&self, edge: Edge, mut callback: F) -> Result<DebouncedSubscription>
+    where F: FnMut(EdgeEvent) + Send + 'static
+  {
+    if edge == Edge::None {
+      bail!("Subscribing to Edge::None never calls back");
+    }
+    let (subscription, raw) = self.pin.edge_events(Edge::Both)?;
+    let stable_time = self.stable_time;
+    let mut state = self.state;
+    let thread = thread::spawn(move || {
+      // The last edge of the bounce going on, if any.
+      let mut pending: Option<EdgeEvent> = None;
+      loop {
+        let received = match pending {
+          Some(_) => raw.recv_timeout(stable_time),
+          None => raw.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match received {
+          Ok(event) => pending = Some(event),
+          Err(RecvTimeoutError::Timeout) => {
+            if let Some(event) = pending.take() {
+              if event.state != state {
+                state = event.state;
+                if reactor::matches(edge, state) {
+                  callback(event);
+                }
+              }
+            }
+          }
+          // The subscription was dropped.
+          Err(RecvTimeoutError::Disconnected) => break,
+        }
+      }
+    });
+    Ok(DebouncedSubscription {
+      subscription: Some(subscription),
+      thread: Some(thread),
+    })
+  }
+
+  /// Sends the debounced edges `edge` on a channel, until the returned
+  /// subscription is dropped.
+  ///
+  /// # Errors
+  ///
+  /// See `on_edge()`.
+  pub fn edge_events(&self, edge: Edge) -> Result<(DebouncedSubscription, Receiver<EdgeEvent>)> {
+    let (sender, receiver) = mpsc::channel();
+    let subscription = self.on_edge(edge, move |event| {
+      let _ = sender.send(event);
+    })?;
+    Ok((subscription, receiver))
+  }
+}
+
+impl<P: DigitalPin> DebouncedInput<P> {
+  /// Debounces a configured input, taking its current level as is.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the input can't be read.
+  pub fn from_pin(pin: P, stable_time: Duration) -> Result<DebouncedInput<P>> {
+    let state = pin.state()?;
+    Ok(DebouncedInput {
+      pin,
+      stable_time,
+      clock: clock::system(),
+      state,
+      candidate: None,
+      edges_enabled: false,
+    })
+  }
+
+  /// Sets how long the input has to stay at a new level to take it.
+  pub fn set_stable_time(&mut self, stable_time: Duration) {
+    self.stable_time = stable_time;
+  }
+
+  /// Makes the input take its time from `clock`, e.g. a `TestClock`.
+  pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+    self.clock = clock;
+  }
+
+  /// Returns the debounced level as of the last read or edge.
+  pub fn state(&self) -> PinState {
+    self.state
+  }
+
+  /// Samples the input and returns the debounced level, which only changes
+  /// once the input was read at a new level for the stable time.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the input can't be read.
+  pub fn read(&mut self) -> Result<PinState> {
+    let level = self.pin.state()?;
+    let now = self.clock.now();
+    if level == self.state {
+      self.candidate = None;
+      return Ok(self.state);
+    }
+    let since = match self.candidate {
+      Some((candidate, since)) if candidate == level => since,
+      _ => now,
+    };
+    if now - since >= self.stable_time {
+      self.state = level;
+      self.candidate = None;
+    } else {
+      self.candidate = Some((level, since));
+    }
+    Ok(self.state)
+  }
+
+  /// Returns the debounced input.
+  pub fn get_ref(&self) -> &P {
+    &self.pin
+  }
+
+  /// Unwraps the debounced input.
+  pub fn into_inner(self) -> P {
+    self.pin
+  }
+}
+
+/// Calls the callback of debounced edges until it's dropped.
+#[derive(Debug)]
+pub struct DebouncedSubscription {
+  subscription: Option<Subscription>,
+  thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for DebouncedSubscription {
+  fn drop(&mut self) {
+    // Dropping the subscription closes the channel the thread waits on.
+    self.subscription = None;
+    if let Some(thread) = self.thread.take() {
+      let _ = thread.join();
+    }
+  }
+}
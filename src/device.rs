@@ -0,0 +1,174 @@
+//! The device module.
+//!
+//! The `Device` trait is implemented by all peripherals, so an application
+//! can keep everything it has claimed in one collection, e.g. to release it
+//! all when shutting down or to list it in a status page, without caring
+//! whether an entry is a GPIO, a PWM or a UART.
+//!
+//! Peripherals that have no notion of exporting, like an ADC or an already
+//! opened UART, treat exporting and unexporting as a no-op.
+
+use adc::ADC;
+use board;
+use enums::DeviceState;
+use errors::*;
+use gpio::{GPIO, PinDirection};
+use i2c::I2C;
+use pwm::{PWM, PWMState};
+use spi::SPI;
+use std::path::Path;
+use uart::UART;
+
+/// A peripheral claimed by the application.
+///
+/// # Examples
+///
+/// ```no_run
+/// use libbeaglebone::prelude::*;
+///
+/// let mut devices: Vec<Box<dyn Device>> = vec![Box::new(GPIO::new(GPIO_P8_11)),
+///                                              Box::new(PWM::new(0, 0)),
+///                                              Box::new(UART::new(4).unwrap())];
+/// for device in &mut devices {
+///   device.export().unwrap();
+/// }
+///
+/// // ... use the devices ...
+///
+/// for device in &mut devices {
+///   if device.is_ready() {
+///     println!("Releasing {}", device.describe());
+///     device.reset().unwrap();
+///     device.unexport().unwrap();
+///   }
+/// }
+/// ```
+pub trait Device {
+  /// Makes the device available, e.g. exports it to sysfs.
+  fn export(&mut self) -> Result<()> {
+    Ok(())
+  }
+
+  /// Releases the device, e.g. unexports it from sysfs.
+  fn unexport(&mut self) -> Result<()> {
+    Ok(())
+  }
+
+  /// Puts the device into a safe, idle state, e.g. turns off an output.
+  fn reset(&mut self) -> Result<()>;
+
+  /// Returns a name for the device to use in messages, e.g. "GPIO pin #45".
+  fn describe(&self) -> String;
+
+  /// Returns whether the device is available for use, e.g. whether it's
+  /// exported.
+  fn is_ready(&self) -> bool;
+}
+
+impl Device for GPIO {
+  fn export(&mut self) -> Result<()> {
+    self.set_export(DeviceState::Exported)
+  }
+
+  fn unexport(&mut self) -> Result<()> {
+    self.set_export(DeviceState::Unexported)
+  }
+
+  /// Turns the pin into an input, so it no longer drives anything.
+  fn reset(&mut self) -> Result<()> {
+    self.set_direction(PinDirection::In)
+  }
+
+  fn describe(&self) -> String {
+    format!("GPIO pin #{}", self.pin_num())
+  }
+
+  fn is_ready(&self) -> bool {
+    self.is_exported()
+  }
+}
+
+impl Device for PWM {
+  fn export(&mut self) -> Result<()> {
+    self.set_export(DeviceState::Exported)
+  }
+
+  fn unexport(&mut self) -> Result<()> {
+    self.set_export(DeviceState::Unexported)
+  }
+
+  /// Disables the output.
+  fn reset(&mut self) -> Result<()> {
+    self.set_state(PWMState::Disabled)
+  }
+
+  fn describe(&self) -> String {
+    format!("PWM #{}-{}", self.pwm_chip_num(), self.pwm_num())
+  }
+
+  fn is_ready(&self) -> bool {
+    self.sysfs_path().exists()
+  }
+}
+
+impl Device for ADC {
+  fn reset(&mut self) -> Result<()> {
+    Ok(())
+  }
+
+  fn describe(&self) -> String {
+    format!("ADC #{}", self.adc_num())
+  }
+
+  /// Returns whether the ADC overlay is loaded.
+  fn is_ready(&self) -> bool {
+    self.raw_path().is_some_and(|path| Path::new(path).exists())
+  }
+}
+
+impl Device for UART {
+  /// Discards pending input and waits for pending output to be sent.
+  fn reset(&mut self) -> Result<()> {
+    self.clear_input()?;
+    self.flush()
+  }
+
+  fn describe(&self) -> String {
+    match self.port_name() {
+      Some(name) => format!("UART {}", name),
+      None => "UART".to_string(),
+    }
+  }
+
+  fn is_ready(&self) -> bool {
+    self.port_name().is_some_and(|name| Path::new(&name).exists())
+  }
+}
+
+impl Device for I2C {
+  fn reset(&mut self) -> Result<()> {
+    Ok(())
+  }
+
+  fn describe(&self) -> String {
+    format!("I2C bus #{}", self.i2c_num())
+  }
+
+  fn is_ready(&self) -> bool {
+    Path::new(&board::current().i2c_path(self.i2c_num())).exists()
+  }
+}
+
+impl Device for SPI {
+  fn reset(&mut self) -> Result<()> {
+    Ok(())
+  }
+
+  fn describe(&self) -> String {
+    format!("SPI bus #{}", self.spi_num())
+  }
+
+  fn is_ready(&self) -> bool {
+    Path::new(&board::current().spi_path(self.spi_num())).exists()
+  }
+}
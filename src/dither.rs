@@ -0,0 +1,177 @@
+//! The dithering module.
+//!
+//! A PWM's duty cycle can only be set in steps of its time base's clock
+//! tick, e.g. 10ns for the eHRPWMs.
+//! At short periods that's coarse: at a 10µs period, there are only 1000
+//! steps, and dimming an LED at the dark end visibly jumps from step to
+//! step.
+//! `Dither` gets in between the steps by alternating between the two
+//! neighbouring duty cycles on a background thread, so the average over
+//! successive updates is the exact one:
+//!
+//! ```no_run
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::dither::Dither;
+//!
+//! let mut led = PWM::new(0, 0);
+//! led.set_export(DeviceState::Exported).unwrap();
+//! led.set_period(10_000).unwrap();
+//! led.set_state(PWMState::Enabled).unwrap();
+//!
+//! // 12.5ns, a quarter of the way from the first to the second step of 10ns.
+//! let dither = Dither::new(led, 10).unwrap();
+//! dither.set_duty_cycle_fraction(0.001_25).unwrap();
+//!
+//! // Stop dithering and take the PWM back.
+//! let led = dither.stop().unwrap();
+//! ```
+//!
+//! The duty cycle can't be changed in sync with the periods through sysfs,
+//! so it's updated once per period, but no more than once per
+//! `MIN_UPDATE_INTERVAL`.
+
+use errors::*;
+use pwm::PWM;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// The shortest time between duty cycle updates, to keep the CPU load of
+/// sysfs writes down.
+pub const MIN_UPDATE_INTERVAL: Duration = Duration::from_millis(1);
+
+/// A PWM whose duty cycle is dithered between the steps of its resolution.
+#[derive(Debug)]
+pub struct Dither {
+  period: u32,
+  // The commanded duty cycle as a fraction of the period.
+  target: Arc<Mutex<f64>>,
+  running: Arc<AtomicBool>,
+  thread: Option<JoinHandle<(PWM, Result<()>)>>,
+}
+
+impl Dither {
+  /// Starts dithering an enabled PWM whose duty cycle can be set in steps of
+  /// `resolution_ns`, keeping its current duty cycle to begin with.
+  ///
+  /// The period mustn't change while dithering.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the resolution is zero or not below the PWM's period, or if
+  /// the PWM can't be read.
+  pub fn new(pwm: PWM, resolution_ns: u32) -> Result<Dither> {
+    let period = pwm.get_period()?;
+    if resolution_ns == 0 || resolution_ns >= period {
+      bail!(format!("PWM resolution {}ns doesn't fit the period of {}ns", resolution_ns, period));
+    }
+    let duty_cycle = pwm.get_duty_cycle()?;
+    let target = Arc::new(Mutex::new(f64::from(duty_cycle) / f64::from(period)));
+    let running = Arc::new(AtomicBool::new(true));
+    let interval = Duration::from_nanos(u64::from(period)).max(MIN_UPDATE_INTERVAL);
+    let thread = {
+      let target = target.clone();
+      let running = running.clone();
+      thread::spawn(move || {
+        let mut pwm = pwm;
+        let result = dither(&mut pwm, period, resolution_ns, interval, &target, &running);
+        (pwm, result)
+      })
+    };
+    Ok(Dither {
+      period,
+      target,
+      running,
+      thread: Some(thread),
+    })
+  }
+
+  /// Sets the duty cycle as a fraction of the period, from 0.0 to 1.0.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the fraction isn't between 0.0 and 1.0.
+  pub fn set_duty_cycle_fraction(&self, fraction: f32) -> Result<()> {
+    if !(0.0..=1.0).contains(&fraction) {
+      bail!(format!("PWM duty cycle fraction {} isn't between 0 and 1", fraction));
+    }
+    *self.target.lock().unwrap_or_else(|e| e.into_inner()) = f64::from(fraction);
+    Ok(())
+  }
+
+  /// Returns the commanded duty cycle as a fraction of the period.
+  pub fn duty_cycle_fraction(&self) -> f32 {
+    *self.target.lock().unwrap_or_else(|e| e.into_inner()) as f32
+  }
+
+  /// Returns the period of the PWM in nanoseconds.
+  pub fn period(&self) -> u32 {
+    self.period
+  }
+
+  /// Stops dithering and returns the PWM, left at the step closest to the
+  /// commanded duty cycle.
+  ///
+  /// # Errors
+  ///
+  /// Fails if a duty cycle couldn't be written, in which case the PWM is
+  /// dropped.
+  pub fn stop(mut self) -> Result<PWM> {
+    self.running.store(false, Ordering::SeqCst);
+    let thread = match self.thread.take() {
+      Some(thread) => thread,
+      None => bail!("The dithering thread is gone"),
+    };
+    match thread.join() {
+      Ok((pwm, Ok(()))) => Ok(pwm),
+      Ok((_, Err(e))) => Err(e),
+      Err(_) => bail!("The dithering thread panicked"),
+    }
+  }
+}
+
+impl Drop for Dither {
+  fn drop(&mut self) {
+    self.running.store(false, Ordering::SeqCst);
+    if let Some(thread) = self.thread.take() {
+      let _ = thread.join();
+    }
+  }
+}
+
+/// Alternates the duty cycle between the steps around the target until
+/// `running` is cleared, then sets the step closest to it.
+fn dither(pwm: &mut PWM,
+          period: u32,
+          resolution: u32,
+          interval: Duration,
+          target: &Mutex<f64>,
+          running: &AtomicBool)
+          -> Result<()> {
+  let target_steps = || {
+    *target.lock().unwrap_or_else(|e| e.into_inner()) * f64::from(period) / f64::from(resolution)
+  };
+  let max_steps = period / resolution;
+  let mut last = None;
+  let mut write = |pwm: &mut PWM, steps: f64| -> Result<()> {
+    let duty_cycle = (steps as u32).min(max_steps) * resolution;
+    if last != Some(duty_cycle) {
+      pwm.set_duty_cycle(duty_cycle)?;
+      last = Some(duty_cycle);
+    }
+    Ok(())
+  };
+
+  // The share of a step owed from the previous updates, which moves the
+  // next update to the higher step once it reaches a whole step.
+  let mut error = 0.0;
+  while running.load(Ordering::SeqCst) {
+    let wanted = target_steps() + error;
+    let steps = wanted.floor();
+    error = wanted - steps;
+    write(pwm, steps)?;
+    thread::sleep(interval);
+  }
+  write(pwm, target_steps().round())
+}
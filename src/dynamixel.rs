@@ -0,0 +1,404 @@
+//! The Dynamixel module.
+//!
+//! Dynamixel smart servos from ROBOTIS are daisy-chained on a half-duplex
+//! serial bus and controlled by reading and writing their control table
+//! registers.
+//! This module implements both versions of the Dynamixel protocol on top of a
+//! `HalfDuplexUART`:
+//!
+//! * Protocol 1.0, used by the AX and older MX series.
+//! * Protocol 2.0, used by the X series, and the MX series with firmware 2.0.
+//!
+//! Register addresses and sizes differ between models, check the servo's
+//! e-manual for its control table.
+
+use errors::*;
+use uart::HalfDuplexUART;
+
+/// The ID that addresses every servo on the bus.
+/// Servos don't respond to broadcast instructions.
+pub const BROADCAST_ID: u8 = 0xFE;
+
+// Instructions common to both protocol versions.
+const PING: u8 = 0x01;
+const READ: u8 = 0x02;
+const WRITE: u8 = 0x03;
+const SYNC_WRITE: u8 = 0x83;
+
+/// The instruction byte of protocol 2.0 status packets.
+const STATUS: u8 = 0x55;
+
+/// The version of the Dynamixel protocol spoken on the bus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+  /// Protocol 1.0
+  V1,
+  /// Protocol 2.0
+  V2,
+}
+
+/// The response to a ping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PingResponse {
+  /// The servo's model number, only reported by protocol 2.0.
+  pub model_number: Option<u16>,
+  /// The servo's firmware version, only reported by protocol 2.0.
+  pub firmware_version: Option<u8>,
+}
+
+/// Represents a bus of Dynamixel servos.
+#[derive(Debug)]
+pub struct Dynamixel {
+  bus: HalfDuplexUART,
+  protocol: Protocol,
+}
+
+impl Dynamixel {
+  /// Creates a new Dynamixel bus on a half-duplex UART.
+  ///
+  /// The UART's baud rate has to match the servos' (1 Mbaud for most
+  /// servos out of the box, 57600 for the X series).
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// # extern crate libbeaglebone;
+  /// # extern crate serialport;
+  /// use libbeaglebone::prelude::*;
+  /// use libbeaglebone::dynamixel::{Dynamixel, Protocol};
+  /// use libbeaglebone::uart::HalfDuplexUART;
+  /// use serialport::BaudRate;
+  ///
+  /// # fn main() {
+  /// let mut uart = UART::new(2).unwrap();
+  /// uart.set_baud_rate(BaudRate::BaudOther(1_000_000)).unwrap();
+  /// let servos = Dynamixel::new(HalfDuplexUART::new(uart), Protocol::V1);
+  /// # }
+  /// ```
+  pub fn new(bus: HalfDuplexUART, protocol: Protocol) -> Dynamixel {
+    Dynamixel { bus, protocol }
+  }
+
+  /// Checks whether a servo responds.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  /// use libbeaglebone::dynamixel::{Dynamixel, Protocol};
+  /// use libbeaglebone::uart::HalfDuplexUART;
+  ///
+  /// let mut servos = Dynamixel::new(HalfDuplexUART::new(UART::new(2).unwrap()), Protocol::V2);
+  ///
+  /// let info = servos.ping(1).unwrap();
+  /// println!("Servo #1 is model {:?}", info.model_number);
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if the servo doesn't respond or reports an error.
+  pub fn ping(&mut self, id: u8) -> Result<PingResponse> {
+    let params = self.request(id, PING, &[])?;
+    if self.protocol == Protocol::V2 && params.len() >= 3 {
+      Ok(PingResponse {
+        model_number: Some(u16::from(params[0]) | u16::from(params[1]) << 8),
+        firmware_version: Some(params[2]),
+      })
+    } else {
+      Ok(PingResponse {
+        model_number: None,
+        firmware_version: None,
+      })
+    }
+  }
+
+  /// Reads `len` bytes from a servo's control table, starting at `address`.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  /// use libbeaglebone::dynamixel::{Dynamixel, Protocol};
+  /// use libbeaglebone::uart::HalfDuplexUART;
+  ///
+  /// let mut servos = Dynamixel::new(HalfDuplexUART::new(UART::new(2).unwrap()), Protocol::V1);
+  ///
+  /// // Read the present position of an AX-12.
+  /// let data = servos.read(1, 36, 2).unwrap();
+  /// println!("Position: {}", u16::from(data[0]) | u16::from(data[1]) << 8);
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if the servo doesn't respond, reports an error, or returns the
+  /// wrong amount of data.
+  pub fn read(&mut self, id: u8, address: u16, len: u16) -> Result<Vec<u8>> {
+    let params = match self.protocol {
+      Protocol::V1 => vec![address as u8, len as u8],
+      Protocol::V2 => {
+        let mut params = address.to_le_bytes().to_vec();
+        params.extend_from_slice(&len.to_le_bytes());
+        params
+      }
+    };
+    let data = self.request(id, READ, &params)?;
+    if data.len() != len as usize {
+      bail!(format!(
+        "Dynamixel #{} returned {} bytes instead of {}",
+        id,
+        data.len(),
+        len
+      ));
+    }
+    Ok(data)
+  }
+
+  /// Writes data to a servo's control table, starting at `address`.
+  ///
+  /// When writing to `BROADCAST_ID`, no response is waited for.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  /// use libbeaglebone::dynamixel::{Dynamixel, Protocol};
+  /// use libbeaglebone::uart::HalfDuplexUART;
+  ///
+  /// let mut servos = Dynamixel::new(HalfDuplexUART::new(UART::new(2).unwrap()), Protocol::V1);
+  ///
+  /// // Enable the torque of an AX-12 and move it to its center position.
+  /// servos.write(1, 24, &[1]).unwrap();
+  /// servos.write(1, 30, &512u16.to_le_bytes()).unwrap();
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if the servo doesn't respond or reports an error.
+  pub fn write(&mut self, id: u8, address: u16, data: &[u8]) -> Result<()> {
+    let mut params = self.address_bytes(address);
+    params.extend_from_slice(data);
+    if id == BROADCAST_ID {
+      self.send(id, WRITE, &params)
+    } else {
+      let _ = self.request(id, WRITE, &params)?;
+      Ok(())
+    }
+  }
+
+  /// Writes data to the same control table registers of several servos with
+  /// a single packet, so they all update at the same time.
+  ///
+  /// Every entry of `data` is a servo ID and the bytes to write to it, which
+  /// all have to be the same length.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  /// use libbeaglebone::dynamixel::{Dynamixel, Protocol};
+  /// use libbeaglebone::uart::HalfDuplexUART;
+  ///
+  /// let mut servos = Dynamixel::new(HalfDuplexUART::new(UART::new(2).unwrap()), Protocol::V1);
+  ///
+  /// // Move two AX-12s at once.
+  /// let (a, b) = (256u16.to_le_bytes(), 768u16.to_le_bytes());
+  /// servos.sync_write(30, &[(1, &a), (2, &b)]).unwrap();
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if `data` is empty or its entries differ in length, or if the
+  /// packet can't be sent.
+  pub fn sync_write(&mut self, address: u16, data: &[(u8, &[u8])]) -> Result<()> {
+    let len = match data.first() {
+      Some(&(_, first)) => first.len(),
+      None => bail!("Dynamixel sync write needs at least one servo"),
+    };
+    if data.iter().any(|&(_, d)| d.len() != len) {
+      bail!("Dynamixel sync write data has to be the same length for every servo");
+    }
+
+    let mut params = self.address_bytes(address);
+    match self.protocol {
+      Protocol::V1 => params.push(len as u8),
+      Protocol::V2 => params.extend_from_slice(&(len as u16).to_le_bytes()),
+    }
+    for &(id, d) in data {
+      params.push(id);
+      params.extend_from_slice(d);
+    }
+    self.send(BROADCAST_ID, SYNC_WRITE, &params)
+  }
+
+  /// Returns a mutable reference to the underlying bus.
+  pub fn bus(&mut self) -> &mut HalfDuplexUART {
+    &mut self.bus
+  }
+
+  /// Encodes a register address for the current protocol version.
+  fn address_bytes(&self, address: u16) -> Vec<u8> {
+    match self.protocol {
+      Protocol::V1 => vec![address as u8],
+      Protocol::V2 => address.to_le_bytes().to_vec(),
+    }
+  }
+
+  /// Sends an instruction packet.
+  fn send(&mut self, id: u8, instruction: u8, params: &[u8]) -> Result<()> {
+    let packet = match self.protocol {
+      Protocol::V1 => encode_v1(id, instruction, params),
+      Protocol::V2 => encode_v2(id, instruction, params),
+    };
+    self.bus.uart().clear_input()?;
+    self.bus
+        .transmit(&packet)
+        .chain_err(|| format!("Failed to send instruction to Dynamixel #{}", id))
+  }
+
+  /// Sends an instruction packet and returns the parameters of the status
+  /// packet the servo responds with.
+  fn request(&mut self, id: u8, instruction: u8, params: &[u8]) -> Result<Vec<u8>> {
+    self.send(id, instruction, params)?;
+    let (status_id, error, params) = match self.protocol {
+      Protocol::V1 => self.receive_v1(),
+      Protocol::V2 => self.receive_v2(),
+    }.chain_err(|| format!("Failed to receive status from Dynamixel #{}", id))?;
+
+    if status_id != id {
+      bail!(format!("Expected status from Dynamixel #{}, got #{}", id, status_id));
+    }
+    if error != 0 {
+      bail!(format!("Dynamixel #{} reported error 0x{:02X}", id, error));
+    }
+    Ok(params)
+  }
+
+  /// Receives a protocol 1.0 status packet, returning the ID, error and
+  /// parameters.
+  fn receive_v1(&mut self) -> Result<(u8, u8, Vec<u8>)> {
+    let mut header = [0; 5];
+    self.bus.receive(&mut header)?;
+    if header[0..2] != [0xFF, 0xFF] || header[3] < 2 {
+      bail!("Invalid Dynamixel status packet header");
+    }
+
+    // The length counts the error byte and checksum.
+    let mut rest = vec![0; header[3] as usize - 1];
+    self.bus.receive(&mut rest)?;
+    let checksum = rest.pop().unwrap();
+    let sum = header[2..].iter().chain(&rest).fold(0u8, |sum, &b| sum.wrapping_add(b));
+    if !sum != checksum {
+      bail!("Dynamixel status packet has a bad checksum");
+    }
+    Ok((header[2], header[4], rest))
+  }
+
+  /// Receives a protocol 2.0 status packet, returning the ID, error and
+  /// parameters.
+  fn receive_v2(&mut self) -> Result<(u8, u8, Vec<u8>)> {
+    let mut header = [0; 7];
+    self.bus.receive(&mut header)?;
+    if header[0..4] != [0xFF, 0xFF, 0xFD, 0x00] {
+      bail!("Invalid Dynamixel status packet header");
+    }
+
+    // The length counts the instruction, error, parameters and CRC.
+    let len = u16::from(header[5]) | u16::from(header[6]) << 8;
+    if len < 4 {
+      bail!("Invalid Dynamixel status packet length");
+    }
+    let mut rest = vec![0; len as usize];
+    self.bus.receive(&mut rest)?;
+
+    let received_crc = u16::from(rest[rest.len() - 2]) | u16::from(rest[rest.len() - 1]) << 8;
+    let body_len = rest.len() - 2;
+    let mut packet = header.to_vec();
+    packet.extend_from_slice(&rest[..body_len]);
+    if crc16(&packet) != received_crc {
+      bail!("Dynamixel status packet has a bad CRC");
+    }
+    if rest[0] != STATUS {
+      bail!("Dynamixel packet isn't a status packet");
+    }
+    Ok((header[4], rest[1], unstuff(&rest[2..body_len])))
+  }
+}
+
+/// Encodes a protocol 1.0 instruction packet.
+///
+/// # Examples
+///
+/// ```
+/// use libbeaglebone::dynamixel::encode_v1;
+///
+/// // Ping servo #1.
+/// assert_eq!(encode_v1(1, 0x01, &[]), vec![0xFF, 0xFF, 0x01, 0x02, 0x01, 0xFB]);
+/// ```
+pub fn encode_v1(id: u8, instruction: u8, params: &[u8]) -> Vec<u8> {
+  let mut packet = vec![0xFF, 0xFF, id, params.len() as u8 + 2, instruction];
+  packet.extend_from_slice(params);
+  let sum = packet[2..].iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+  packet.push(!sum);
+  packet
+}
+
+/// Encodes a protocol 2.0 instruction packet, including byte stuffing.
+///
+/// # Examples
+///
+/// ```
+/// use libbeaglebone::dynamixel::encode_v2;
+///
+/// // Ping servo #1.
+/// assert_eq!(encode_v2(1, 0x01, &[]),
+///            vec![0xFF, 0xFF, 0xFD, 0x00, 0x01, 0x03, 0x00, 0x01, 0x19, 0x4E]);
+/// ```
+pub fn encode_v2(id: u8, instruction: u8, params: &[u8]) -> Vec<u8> {
+  let params = stuff(params);
+  let len = params.len() as u16 + 3;
+  let mut packet = vec![0xFF, 0xFF, 0xFD, 0x00, id];
+  packet.extend_from_slice(&len.to_le_bytes());
+  packet.push(instruction);
+  packet.extend_from_slice(&params);
+  let crc = crc16(&packet);
+  packet.extend_from_slice(&crc.to_le_bytes());
+  packet
+}
+
+/// Computes the CRC-16 used by protocol 2.0 (polynomial 0x8005).
+pub fn crc16(data: &[u8]) -> u16 {
+  data.iter().fold(0, |crc, &b| {
+    (0..8).fold(crc ^ u16::from(b) << 8, |crc, _| if crc & 0x8000 != 0 {
+      crc << 1 ^ 0x8005
+    } else {
+      crc << 1
+    })
+  })
+}
+
+/// Inserts a 0xFD after every 0xFF 0xFF 0xFD sequence, so parameters can't
+/// be mistaken for a packet header.
+fn stuff(data: &[u8]) -> Vec<u8> {
+  let mut out = Vec::with_capacity(data.len());
+  for &b in data {
+    out.push(b);
+    if out.ends_with(&[0xFF, 0xFF, 0xFD]) {
+      out.push(0xFD);
+    }
+  }
+  out
+}
+
+/// Removes the byte stuffing inserted by `stuff`.
+fn unstuff(data: &[u8]) -> Vec<u8> {
+  let mut out = Vec::with_capacity(data.len());
+  let mut i = 0;
+  while i < data.len() {
+    out.push(data[i]);
+    if out.ends_with(&[0xFF, 0xFF, 0xFD]) && data.get(i + 1) == Some(&0xFD) {
+      i += 1;
+    }
+    i += 1;
+  }
+  out
+}
@@ -0,0 +1,177 @@
+//! The eHRPWM module.
+//!
+//! The PWM outputs of the BeagleBone come in pairs (A and B) driven by the
+//! three eHRPWM modules of the PWM subsystem.
+//! Both outputs of a module share one time base, but the time bases of the
+//! modules can be synchronized to each other with a phase offset, e.g. to
+//! interleave the switching of two power converter phases.
+//! The kernel's PWM driver doesn't expose this, so the time-base registers
+//! are accessed directly through `/dev/mem`, which requires root privileges.
+//!
+//! The sync signal is chained from eHRPWM0 to eHRPWM1 to eHRPWM2, so the
+//! master has to come first in the chain, and any module between the master
+//! and a slave has to pass the sync signal on.
+//!
+//! Configure the frequency and duty cycles with the `PWM` type first, which
+//! also makes sure the module's clock is running; the phase configuration is
+//! lost when the PWM is re-configured through sysfs.
+
+use errors::*;
+use mmio::MemoryMap;
+
+/// The base addresses of the PWM subsystems of eHRPWM0-2.
+const PWMSS_BASES: [usize; 3] = [0x4830_0000, 0x4830_2000, 0x4830_4000];
+
+/// The offset of the ePWM registers within a PWM subsystem.
+const EPWM_OFFSET: usize = 0x200;
+
+// Time-base registers.
+const TBCTL: usize = EPWM_OFFSET;
+const TBPHS: usize = EPWM_OFFSET + 0x06;
+const TBPRD: usize = EPWM_OFFSET + 0x0A;
+
+// TBCTL fields.
+const TBCTL_CTRMODE_MASK: u16 = 0x0003;
+const TBCTL_CTRMODE_UP_DOWN: u16 = 0x0002;
+const TBCTL_PHSEN: u16 = 1 << 2;
+const TBCTL_SYNCOSEL_SHIFT: u16 = 4;
+const TBCTL_SYNCOSEL_MASK: u16 = 0x3 << TBCTL_SYNCOSEL_SHIFT;
+const TBCTL_SWFSYNC: u16 = 1 << 6;
+const TBCTL_PHSDIR: u16 = 1 << 13;
+
+/// The source of the sync signal a module passes on down the chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncOutput {
+  /// Pass on the sync signal the module receives.
+  SyncIn,
+  /// Send a sync signal whenever the module's counter is zero, i.e. at the
+  /// start of each period.
+  CounterZero,
+  /// Send a sync signal when the module's counter equals its B output's
+  /// compare value.
+  CounterCompareB,
+  /// Don't send a sync signal.
+  Disabled,
+}
+
+/// Represents the time base of an eHRPWM module.
+#[derive(Debug)]
+pub struct EHRPWM {
+  module: u8,
+  regs: MemoryMap,
+}
+
+impl EHRPWM {
+  /// Maps the registers of eHRPWM module `module` (0-2).
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::ehrpwm::EHRPWM;
+  ///
+  /// let ehrpwm1 = EHRPWM::new(1).unwrap();
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if `module` isn't within 0-2, or if the registers can't be mapped,
+  /// e.g. because the process isn't running as root.
+  pub fn new(module: u8) -> Result<EHRPWM> {
+    let base = match PWMSS_BASES.get(module as usize) {
+      Some(&base) => base,
+      None => bail!(format!("eHRPWM module {} is outside of 0-2", module)),
+    };
+    Ok(EHRPWM {
+      module,
+      regs: MemoryMap::new(base, 0x1000)?,
+    })
+  }
+
+  /// Returns the length of a period in time-base clock counts.
+  pub fn period_counts(&self) -> u32 {
+    let tbprd = u32::from(self.regs.read_u16(TBPRD));
+    if self.regs.read_u16(TBCTL) & TBCTL_CTRMODE_MASK == TBCTL_CTRMODE_UP_DOWN {
+      2 * tbprd
+    } else {
+      tbprd + 1
+    }
+  }
+
+  /// Selects the sync signal the module passes on to the next module.
+  pub fn set_sync_output(&mut self, output: SyncOutput) {
+    let syncosel = match output {
+      SyncOutput::SyncIn => 0,
+      SyncOutput::CounterZero => 1,
+      SyncOutput::CounterCompareB => 2,
+      SyncOutput::Disabled => 3,
+    };
+    self.modify_tbctl(TBCTL_SYNCOSEL_MASK, syncosel << TBCTL_SYNCOSEL_SHIFT);
+  }
+
+  /// Makes the module follow the sync signal it receives, loading its counter
+  /// with `counts` on every sync, or stops following it.
+  ///
+  /// In up-down count mode, the counter counts up after the sync.
+  pub fn set_phase_counts(&mut self, counts: Option<u16>) {
+    match counts {
+      Some(counts) => {
+        self.regs.write_u16(TBPHS, counts);
+        self.modify_tbctl(TBCTL_PHSEN | TBCTL_PHSDIR, TBCTL_PHSEN | TBCTL_PHSDIR);
+      }
+      None => self.modify_tbctl(TBCTL_PHSEN, 0),
+    }
+  }
+
+  /// Makes the module follow the sync signal it receives, lagging the module
+  /// that sends it by `degrees` of a period.
+  ///
+  /// Both modules have to run at the same frequency.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::ehrpwm::{EHRPWM, SyncOutput};
+  ///
+  /// // Set up eHRPWM0 and eHRPWM1 with the same frequency using the PWM
+  /// // type first, then interleave them by 180 degrees.
+  /// let mut master = EHRPWM::new(0).unwrap();
+  /// let mut slave = EHRPWM::new(1).unwrap();
+  ///
+  /// master.set_phase_counts(None);
+  /// master.set_sync_output(SyncOutput::CounterZero);
+  /// slave.set_phase_degrees(180.0).unwrap();
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if `degrees` isn't within 0-360.
+  pub fn set_phase_degrees(&mut self, degrees: f32) -> Result<()> {
+    if !(0.0..=360.0).contains(&degrees) {
+      bail!(format!("eHRPWM{} phase of {} degrees is outside of 0-360", self.module, degrees));
+    }
+    let counts = self.period_counts();
+    // A counter loaded with x is x counts into its period, i.e. it leads by
+    // x counts, so a lag of `degrees` is the rest of the period.
+    let lead = ((360.0 - degrees) / 360.0 * counts as f32).round() as u32 % counts.max(1);
+    let tbprd = u32::from(self.regs.read_u16(TBPRD));
+    if self.regs.read_u16(TBCTL) & TBCTL_CTRMODE_MASK == TBCTL_CTRMODE_UP_DOWN && lead > tbprd {
+      // In the second half of the period the counter counts down.
+      self.regs.write_u16(TBPHS, (counts - lead) as u16);
+      self.modify_tbctl(TBCTL_PHSEN | TBCTL_PHSDIR, TBCTL_PHSEN);
+    } else {
+      self.set_phase_counts(Some(lead as u16));
+    }
+    Ok(())
+  }
+
+  /// Sends a sync signal from this module right away, e.g. to align the
+  /// modules without waiting for the next period.
+  pub fn software_sync(&mut self) {
+    self.modify_tbctl(TBCTL_SWFSYNC, TBCTL_SWFSYNC);
+  }
+
+  fn modify_tbctl(&mut self, mask: u16, value: u16) {
+    let tbctl = self.regs.read_u16(TBCTL);
+    self.regs.write_u16(TBCTL, tbctl & !mask | value & mask);
+  }
+}
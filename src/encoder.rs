@@ -0,0 +1,539 @@
+//! The rotary encoder module.
+//!
+//! Front-panel knobs are mechanical quadrature encoders: two contacts, A and
+//! B, that open and close a quarter cycle apart, the order telling the
+//! direction.
+//! The eQEP peripherals decode them in hardware, but only on a few pins; a
+//! `RotaryEncoder` decodes them on any two GPIOs, with an optional push
+//! button:
+//!
+//! ```no_run
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::encoder::{EncoderEvent, RotaryEncoder};
+//!
+//! let mut knob = RotaryEncoder::new(GPIO_P8_11, GPIO_P8_12).unwrap();
+//! knob.set_button(GPIO_P8_14, PinState::Low).unwrap();
+//!
+//! // Decoded from the pins' edges on the reactor's thread.
+//! let (_subscription, events) = knob.watch().unwrap();
+//! for event in events {
+//!   match event {
+//!     EncoderEvent::Pressed => println!("Selected {}", knob.position()),
+//!     _ => println!("Changed by {}", knob.delta()),
+//!   }
+//! }
+//! ```
+//!
+//! The decoding itself is done by a `QuadratureDecoder`, which takes the
+//! levels of A and B from anywhere:
+//!
+//! ```
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::encoder::{EncoderEvent, QuadratureDecoder};
+//!
+//! let mut decoder = QuadratureDecoder::new(4);
+//! let _ = decoder.update(PinState::High, PinState::High);
+//! // A full clockwise cycle, A leading B, is one detent.
+//! let levels = [(PinState::Low, PinState::High), (PinState::Low, PinState::Low),
+//!               (PinState::High, PinState::Low), (PinState::High, PinState::High)];
+//! let events: Vec<_> = levels.iter().filter_map(|&(a, b)| decoder.update(a, b)).collect();
+//! assert_eq!(events, vec![EncoderEvent::Clockwise]);
+//! assert_eq!(decoder.position(), 1);
+//!
+//! // Bouncing a step back and forth at the detent turns nothing.
+//! for _ in 0..3 {
+//!   assert_eq!(decoder.update(PinState::High, PinState::Low), None);
+//!   assert_eq!(decoder.update(PinState::High, PinState::High), None);
+//! }
+//! assert_eq!(decoder.position(), 1);
+//! ```
+//!
+//! Contact bounce shows as steps back and forth, which cancel out, and a
+//! detent is only turned a full detent away from the last one; a
+//! transition that skips a state is counted as missed instead, see
+//! `QuadratureDecoder::missed()`.
+//!
+//! In electrically noisy environments, spikes on the lines can still add up
+//! to a wrong count.
+//! `RotaryEncoder::set_min_stable_time()` makes the sampled levels only
+//! count once they held for a minimum time:
+//!
+//! ```
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::clock::TestClock;
+//! use libbeaglebone::encoder::{EncoderEvent, RotaryEncoder};
+//! use libbeaglebone::errors::*;
+//! use std::sync::{Arc, Mutex};
+//! use std::time::Duration;
+//!
+//! // An input reading a level set by the test.
+//! struct FakePin(Arc<Mutex<PinState>>);
+//!
+//! impl DigitalPin for FakePin {
+//!   fn set_state(&mut self, _: PinState) -> Result<()> { Ok(()) }
+//!   fn state(&self) -> Result<PinState> { Ok(*self.0.lock().unwrap()) }
+//!   fn pin_name(&self) -> String { "fake pin".to_string() }
+//! }
+//!
+//! let a = Arc::new(Mutex::new(PinState::High));
+//! let b = Arc::new(Mutex::new(PinState::High));
+//! let mut knob = RotaryEncoder::from_pins(FakePin(a.clone()), FakePin(b.clone())).unwrap();
+//! knob.set_steps_per_detent(1);
+//! let clock = TestClock::new();
+//! knob.set_clock(Arc::new(clock.clone()));
+//! knob.set_min_stable_time(Duration::from_millis(1));
+//!
+//! // A 100µs spike on A is ignored.
+//! *a.lock().unwrap() = PinState::Low;
+//! assert_eq!(knob.poll().unwrap(), None);
+//! clock.advance(Duration::from_micros(100));
+//! *a.lock().unwrap() = PinState::High;
+//! assert_eq!(knob.poll().unwrap(), None);
+//! clock.advance(Duration::from_millis(1));
+//! assert_eq!(knob.poll().unwrap(), None);
+//!
+//! // A change that holds for 1ms turns the knob.
+//! *a.lock().unwrap() = PinState::Low;
+//! assert_eq!(knob.poll().unwrap(), None);
+//! clock.advance(Duration::from_millis(1));
+//! assert_eq!(knob.poll().unwrap(), Some(EncoderEvent::Clockwise));
+//! ```
+//!
+//! The filter works on sampled levels, so it applies to `poll()` and
+//! `wait()` only.
+//! Filtering in the eQEP peripherals, with their QCAP prescaler, isn't
+//! supported, there's no eQEP driver yet.
+
+use clock::{self, Clock};
+use enums::DeviceState;
+use errors::*;
+use gpio::{Edge, GPIO, PinDirection, PinState};
+use hal::DigitalPin;
+use pins::Pin;
+use reactor::Subscription;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::mpsc::{self, Receiver};
+use std::time::Duration;
+
+/// The steps of a transition from the levels at the index's upper two bits
+/// to the levels at its lower two, the levels being A in bit 1 and B in
+/// bit 0; 0 for no change or a skipped state.
+const TRANSITIONS: [i8; 16] = [0, -1, 1, 0, 1, 0, 0, -1, -1, 0, 0, 1, 0, 1, -1, 0];
+
+/// A change of a rotary encoder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncoderEvent {
+  /// Turned a detent clockwise, i.e. with A leading B.
+  Clockwise,
+  /// Turned a detent counterclockwise.
+  CounterClockwise,
+  /// The button was pressed.
+  Pressed,
+  /// The button was released.
+  Released,
+}
+
+/// Counts the detents of a quadrature signal.
+#[derive(Debug, Clone)]
+pub struct QuadratureDecoder {
+  steps_per_detent: i64,
+  levels: Option<u8>,
+  steps: i64,
+  position: i64,
+  missed: u64,
+}
+
+impl QuadratureDecoder {
+  /// Creates a decoder at position 0, for an encoder that makes
+  /// `steps_per_detent` transitions per detent: 4 for most knobs, 1 to count
+  /// every transition.
+  pub fn new(steps_per_detent: u32) -> QuadratureDecoder {
+    QuadratureDecoder {
+      steps_per_detent: i64::from(steps_per_detent.max(1)),
+      levels: None,
+      steps: 0,
+      position: 0,
+      missed: 0,
+    }
+  }
+
+  /// Takes the levels of A and B, and returns the detent turned, if any.
+  ///
+  /// The first levels only set where the decoding starts.
+  pub fn update(&mut self, a: PinState, b: PinState) -> Option<EncoderEvent> {
+    let levels = u8::from(a == PinState::High) << 1 | u8::from(b == PinState::High);
+    let previous = match self.levels.replace(levels) {
+      Some(previous) if previous != levels => previous,
+      _ => return None,
+    };
+    let step = TRANSITIONS[usize::from(previous << 2 | levels)];
+    if step == 0 {
+      self.missed += 1;
+      return None;
+    }
+    self.steps += i64::from(step);
+    // A detent is only turned once the steps are a full detent away from
+    // the last one in either direction, so bouncing contacts at rest don't
+    // turn back and forth.
+    let offset = self.steps - self.position * self.steps_per_detent;
+    if offset >= self.steps_per_detent {
+      self.position += 1;
+      Some(EncoderEvent::Clockwise)
+    } else if offset <= -self.steps_per_detent {
+      self.position -= 1;
+      Some(EncoderEvent::CounterClockwise)
+    } else {
+      None
+    }
+  }
+
+  /// Returns the position in detents, clockwise being positive.
+  pub fn position(&self) -> i64 {
+    self.position
+  }
+
+  /// Sets the position in detents.
+  pub fn set_position(&mut self, position: i64) {
+    self.position = position;
+    self.steps = position * self.steps_per_detent;
+  }
+
+  /// Returns how many transitions skipped a state, because the encoder
+  /// turned faster than it was sampled.
+  pub fn missed(&self) -> u64 {
+    self.missed
+  }
+}
+
+/// The button of an encoder.
+#[derive(Debug)]
+struct Button<P> {
+  pin: P,
+  active: PinState,
+}
+
+#[derive(Debug)]
+struct State {
+  decoder: QuadratureDecoder,
+  // The position as of the last `delta()`.
+  reported: i64,
+  button_pressed: bool,
+  // When the button last changed, for the debounce.
+  button_changed: Option<Duration>,
+}
+
+impl State {
+  /// Takes a level of the button, ignoring changes for `debounce` after
+  /// the last one.
+  fn button(&mut self, pressed: bool, now: Duration, debounce: Duration) -> Option<EncoderEvent> {
+    if pressed == self.button_pressed ||
+       self.button_changed.is_some_and(|changed| now - changed < debounce) {
+      return None;
+    }
+    self.button_pressed = pressed;
+    self.button_changed = Some(now);
+    Some(if pressed { EncoderEvent::Pressed } else { EncoderEvent::Released })
+  }
+}
+
+/// A rotary encoder on two digital inputs, by default GPIOs.
+#[derive(Debug)]
+pub struct RotaryEncoder<P: DigitalPin = GPIO> {
+  a: P,
+  b: P,
+  button: Option<Button<P>>,
+  debounce: Duration,
+  clock: Arc<dyn Clock>,
+  poll_interval: Duration,
+  state: Arc<Mutex<State>>,
+  // The second event of a poll, e.g. a turn while the button changed.
+  pending: Option<EncoderEvent>,
+  min_stable: Duration,
+  // The levels of A and B sampled last that differ from the decoded ones,
+  // and since when they held.
+  unstable: Option<((PinState, PinState), Duration)>,
+}
+
+impl RotaryEncoder<GPIO> {
+  /// Decodes an encoder on the GPIOs `a` and `b`, which are exported and
+  /// made inputs.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the pins can't be configured or read.
+  pub fn new(a: Pin, b: Pin) -> Result<RotaryEncoder> {
+    RotaryEncoder::from_pins(input(a)?, input(b)?)
+  }
+
+  /// Adds the push button on the GPIO `pin`, which is exported and made an
+  /// input, pressed at the level `active`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the pin can't be configured or read.
+  pub fn set_button(&mut self, pin: Pin, active: PinState) -> Result<()> {
+    self.set_button_pin(input(pin)?, active)
+  }
+
+  /// Decodes the encoder from the pins' edges on the shared reactor's
+  /// thread, see `GPIO::on_edge()`, and sends its events on a channel,
+  /// until the returned subscription is dropped.
+  ///
+  /// `position()` and `delta()` follow the decoded edges; `poll()` and
+  /// `wait()` mustn't be used at the same time.
+  ///
+  /// # Errors
+  ///
+  /// Fails if a minimum stable time is set, which needs the pins to be
+  /// sampled, if the pins don't support edge interrupts, or if the reactor
+  /// can't be started.
+  pub fn watch(&self) -> Result<(EncoderSubscription, Receiver<EncoderEvent>)> {
+    if self.min_stable > Duration::from_secs(0) {
+      bail!("An encoder with a minimum stable time has to be sampled with poll() or wait(), not watched");
+    }
+    let (sender, receiver) = mpsc::channel();
+    // The reactor reports the level of the pin that changed only.
+    let levels = Arc::new(Mutex::new((self.a.read()?, self.b.read()?)));
+    let mut subscriptions = Vec::new();
+    for &is_a in &[true, false] {
+      let pin = if is_a { &self.a } else { &self.b };
+      let (state, levels, sender) = (self.state.clone(), levels.clone(), sender.clone());
+      subscriptions.push(pin.on_edge(Edge::Both, move |edge| {
+        let mut levels = lock(&levels);
+        if is_a {
+          levels.0 = edge.state;
+        } else {
+          levels.1 = edge.state;
+        }
+        if let Some(event) = lock(&state).decoder.update(levels.0, levels.1) {
+          let _ = sender.send(event);
+        }
+      })?);
+    }
+    if let Some(ref button) = self.button {
+      let (state, clock, debounce, active) = (self.state.clone(), self.clock.clone(), self.debounce, button.active);
+      subscriptions.push(button.pin.on_edge(Edge::Both, move |edge| {
+        if let Some(event) = lock(&state).button(edge.state == active, clock.now(), debounce) {
+          let _ = sender.send(event);
+        }
+      })?);
+    }
+    Ok((EncoderSubscription { _subscriptions: subscriptions }, receiver))
+  }
+}
+
+impl<P: DigitalPin> RotaryEncoder<P> {
+  /// Decodes an encoder on the configured inputs `a` and `b`, at position 0
+  /// with 4 steps per detent.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the inputs can't be read.
+  pub fn from_pins(a: P, b: P) -> Result<RotaryEncoder<P>> {
+    let mut decoder = QuadratureDecoder::new(4);
+    let _ = decoder.update(a.state()?, b.state()?);
+    Ok(RotaryEncoder {
+      a,
+      b,
+      button: None,
+      debounce: Duration::from_millis(20),
+      clock: clock::system(),
+      poll_interval: Duration::from_millis(1),
+      state: Arc::new(Mutex::new(State {
+        decoder,
+        reported: 0,
+        button_pressed: false,
+        button_changed: None,
+      })),
+      pending: None,
+      min_stable: Duration::from_secs(0),
+      unstable: None,
+    })
+  }
+
+  /// Adds the push button on the configured input `pin`, pressed at the
+  /// level `active`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the input can't be read.
+  pub fn set_button_pin(&mut self, pin: P, active: PinState) -> Result<()> {
+    let pressed = pin.state()? == active;
+    lock(&self.state).button_pressed = pressed;
+    self.button = Some(Button { pin, active });
+    Ok(())
+  }
+
+  /// Sets how many transitions make a detent, 4 by default; the position
+  /// starts over at 0.
+  pub fn set_steps_per_detent(&mut self, steps: u32) {
+    let mut state = lock(&self.state);
+    let levels = state.decoder.levels;
+    state.decoder = QuadratureDecoder::new(steps);
+    state.decoder.levels = levels;
+    state.reported = 0;
+  }
+
+  /// Sets how long the button's changes are ignored after it changed, 20ms
+  /// by default.
+  pub fn set_debounce(&mut self, debounce: Duration) {
+    self.debounce = debounce;
+  }
+
+  /// Makes `poll()` and `wait()` only decode levels of A and B that held for
+  /// at least `min_stable`, ignoring shorter spikes; 0, the default, turns
+  /// the filter off.
+  ///
+  /// The pins have to be sampled several times within `min_stable`, and
+  /// the encoder mustn't make more than one transition within it.
+  pub fn set_min_stable_time(&mut self, min_stable: Duration) {
+    self.min_stable = min_stable;
+    self.unstable = None;
+  }
+
+  /// Sets how often `wait()` samples the pins, 1ms by default.
+  pub fn set_poll_interval(&mut self, interval: Duration) {
+    self.poll_interval = interval;
+  }
+
+  /// Makes the encoder take its time from `clock`, e.g. a `TestClock`.
+  pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+    self.clock = clock;
+  }
+
+  /// Returns the position in detents, clockwise being positive.
+  pub fn position(&self) -> i64 {
+    lock(&self.state).decoder.position()
+  }
+
+  /// Sets the position in detents.
+  pub fn set_position(&mut self, position: i64) {
+    let mut state = lock(&self.state);
+    state.decoder.set_position(position);
+    state.reported = position;
+  }
+
+  /// Returns how many detents the encoder turned since the last call,
+  /// clockwise being positive.
+  pub fn delta(&self) -> i64 {
+    let mut state = lock(&self.state);
+    let position = state.decoder.position();
+    let delta = position - state.reported;
+    state.reported = position;
+    delta
+  }
+
+  /// Returns whether the button is pressed, `false` without a button.
+  pub fn is_pressed(&self) -> bool {
+    lock(&self.state).button_pressed
+  }
+
+  /// Returns how many transitions were missed, see
+  /// `QuadratureDecoder::missed()`.
+  pub fn missed(&self) -> u64 {
+    lock(&self.state).decoder.missed()
+  }
+
+  /// Samples the pins, and returns the detent turned or the change of the
+  /// button, if any.
+  ///
+  /// The encoder has to be sampled at least once per transition, i.e. four
+  /// times per detent, or transitions are missed.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the pins can't be read.
+  pub fn poll(&mut self) -> Result<Option<EncoderEvent>> {
+    if let Some(event) = self.pending.take() {
+      return Ok(Some(event));
+    }
+    let (a, b) = (self.a.state()?, self.b.state()?);
+    let pressed = match self.button {
+      Some(ref button) => Some(button.pin.state()? == button.active),
+      None => None,
+    };
+    let now = self.clock.now();
+    let stable = self.stable((a, b), now);
+    let mut state = lock(&self.state);
+    let turned = match stable {
+      Some((a, b)) => state.decoder.update(a, b),
+      None => None,
+    };
+    let button = match pressed {
+      Some(pressed) => state.button(pressed, now, self.debounce),
+      None => None,
+    };
+    match (turned, button) {
+      (Some(turned), Some(button)) => {
+        self.pending = Some(button);
+        Ok(Some(turned))
+      }
+      (turned, button) => Ok(turned.or(button)),
+    }
+  }
+
+  /// Returns the sampled `levels` if they held for the minimum stable time.
+  fn stable(&mut self, levels: (PinState, PinState), now: Duration) -> Option<(PinState, PinState)> {
+    if self.min_stable == Duration::from_secs(0) {
+      return Some(levels);
+    }
+    match self.unstable {
+      Some((unstable, since)) if unstable == levels => {
+        if now.checked_sub(since).unwrap_or_default() >= self.min_stable {
+          self.unstable = None;
+          Some(levels)
+        } else {
+          None
+        }
+      }
+      _ => {
+        self.unstable = Some((levels, now));
+        None
+      }
+    }
+  }
+
+  /// Samples the pins until the encoder turns a detent or the button
+  /// changes, or until `timeout` has passed.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the pins can't be read.
+  pub fn wait(&mut self, timeout: Duration) -> Result<Option<EncoderEvent>> {
+    let deadline = self.clock.now() + timeout;
+    loop {
+      if let Some(event) = self.poll()? {
+        return Ok(Some(event));
+      }
+      match deadline.checked_sub(self.clock.now()) {
+        Some(remaining) if remaining > Duration::from_secs(0) => {
+          self.clock.sleep(self.poll_interval.min(remaining))?
+        }
+        _ => return Ok(None),
+      }
+    }
+  }
+
+  /// Unwraps the pins A and B, and the button's if any.
+  pub fn into_inner(self) -> (P, P, Option<P>) {
+    (self.a, self.b, self.button.map(|button| button.pin))
+  }
+}
+
+/// Decodes the edges of an encoder until it's dropped.
+#[derive(Debug)]
+pub struct EncoderSubscription {
+  _subscriptions: Vec<Subscription>,
+}
+
+/// Exports the GPIO `pin` and makes it an input.
+fn input(pin: Pin) -> Result<GPIO> {
+  let input = GPIO::new(pin);
+  input.set_export(DeviceState::Exported)?;
+  input.set_direction(PinDirection::In)?;
+  Ok(input)
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+  mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
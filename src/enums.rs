@@ -1,7 +1,7 @@
 //! Useful enums.
 
 /// The state in which a module is in, either exported or unexported.
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DeviceState {
   /// Exported and available for use.
   Exported,
@@ -1,3 +1,23 @@
 //! Error-handling setup using error-chain.
 
-error_chain!{}
+error_chain!{
+  errors {
+    /// A command for an actuator guarded by an `EStop` was refused because
+    /// the e-stop is latched.
+    EStopLatched(cause: String) {
+      description("the e-stop is latched")
+      display("The e-stop is latched ({}), it has to be reset before commanding actuators", cause)
+    }
+    /// A bring-up was aborted because its operator couldn't be asked.
+    OperatorUnreachable(cause: String) {
+      description("the bring-up operator can't be reached")
+      display("The bring-up operator can't be reached: {}", cause)
+    }
+    /// A write by the application was refused because it yielded control
+    /// of the device to a diagnostic session, see the `arbitration` module.
+    ControlYielded(holder: String) {
+      description("control of the device was yielded")
+      display("Control of the device is yielded to {}", holder)
+    }
+  }
+}
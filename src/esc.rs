@@ -0,0 +1,252 @@
+//! The ESC module.
+//!
+//! The electronic speed controllers (ESCs) of brushless motors take the
+//! throttle as a servo pulse: 1000µs for stopped to 2000µs for full
+//! throttle, typically at 50Hz.
+//! They only start driving the motor after they have seen the stopped pulse
+//! for a while, which keeps a motor from spinning up at power-on with the
+//! throttle anywhere; `Esc` does this arming, and keeps the throttle at zero
+//! until it's done:
+//!
+//! ```no_run
+//! use libbeaglebone::esc::Esc;
+//!
+//! // An ESC on EHRPWM1A, i.e. pin P9.14 after `config-pin P9.14 pwm`.
+//! let mut esc = Esc::new(3, 0).unwrap();
+//! esc.arm().unwrap();
+//! esc.set_throttle(0.3).unwrap();
+//! // ...
+//! esc.disarm().unwrap();
+//! ```
+//!
+//! An ESC is disarmed when it's dropped.
+//! Keep the propellers off while trying out a new setup.
+
+use clock::{self, Clock};
+use enums::DeviceState;
+use errors::*;
+use hal::PwmOutput;
+use pwm::{PWM, PWMState};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The refresh rate all ESCs accept.
+pub const DEFAULT_REFRESH_RATE_HZ: f32 = 50.0;
+
+/// How long the stopped pulse is sent to arm an ESC by default.
+pub const DEFAULT_ARM_TIME: Duration = Duration::from_secs(2);
+
+/// A brushless motor's ESC driven by a PWM output, by default an on-chip
+/// PWM.
+#[derive(Debug)]
+pub struct Esc<P: PwmOutput = PWM> {
+  pwm: P,
+  clock: Arc<dyn Clock>,
+  period: Duration,
+  min_pulse: Duration,
+  max_pulse: Duration,
+  arm_time: Duration,
+  // The throttle set last, or `None` while disarmed.
+  throttle: Option<f32>,
+}
+
+impl Esc<PWM> {
+  /// Creates a disarmed ESC on PWM `pwm_num` of chip `pwm_chip_num`, with
+  /// 1000µs to 2000µs pulses at 50Hz.
+  ///
+  /// The PWM is exported and configured, but stays disabled until the ESC
+  /// is armed.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the PWM can't be exported or configured.
+  pub fn new(pwm_chip_num: u8, pwm_num: u8) -> Result<Esc> {
+    let mut pwm = PWM::new(pwm_chip_num, pwm_num);
+    pwm.set_export(DeviceState::Exported)?;
+    pwm.set_state(PWMState::Disabled)?;
+    Esc::from_pwm(pwm)
+  }
+}
+
+impl<P: PwmOutput> Esc<P> {
+  /// Creates a disarmed ESC on a disabled PWM output, with 1000µs to 2000µs
+  /// pulses at 50Hz.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the period of the output can't be set.
+  pub fn from_pwm(mut pwm: P) -> Result<Esc<P>> {
+    let period = refresh_period(DEFAULT_REFRESH_RATE_HZ)?;
+    pwm.set_period_ns(period.as_nanos() as u32)?;
+    Ok(Esc {
+      pwm,
+      clock: clock::system(),
+      period,
+      min_pulse: Duration::from_micros(1000),
+      max_pulse: Duration::from_micros(2000),
+      arm_time: DEFAULT_ARM_TIME,
+      throttle: None,
+    })
+  }
+
+  /// Makes the ESC take its time from `clock`, e.g. a `TestClock`.
+  pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+    self.clock = clock;
+  }
+
+  /// Sets the pulse widths of stopped and full throttle.
+  ///
+  /// # Errors
+  ///
+  /// Fails if `min` isn't shorter than `max` or `max` doesn't fit into the
+  /// refresh period.
+  pub fn set_pulse_range(&mut self, min: Duration, max: Duration) -> Result<()> {
+    if min >= max {
+      bail!(format!("ESC pulse range {:?} to {:?} is empty", min, max));
+    }
+    if max >= self.period {
+      bail!(format!("ESC pulse {:?} doesn't fit into the period of {:?}", max, self.period));
+    }
+    self.min_pulse = min;
+    self.max_pulse = max;
+    Ok(())
+  }
+
+  /// Sets how often the pulse is repeated, 50Hz by default.
+  ///
+  /// Many ESCs accept up to 400Hz, which makes them react faster.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the longest pulse doesn't fit into the period, or if the
+  /// period can't be set.
+  pub fn set_refresh_rate(&mut self, hz: f32) -> Result<()> {
+    let period = refresh_period(hz)?;
+    if self.max_pulse >= period {
+      bail!(format!("ESC pulse {:?} doesn't fit into the period of {:?}", self.max_pulse, period));
+    }
+    // The duty cycle may never exceed the period, so the pulse is set to the
+    // shortest one first.
+    if self.throttle.is_some() {
+      self.write_pulse(self.min_pulse)?;
+    }
+    self.pwm.set_period_ns(period.as_nanos() as u32)?;
+    self.period = period;
+    match self.throttle {
+      Some(throttle) => self.write_throttle(throttle),
+      None => Ok(()),
+    }
+  }
+
+  /// Sets how long `arm()` sends the stopped pulse, 2s by default.
+  pub fn set_arm_time(&mut self, arm_time: Duration) {
+    self.arm_time = arm_time;
+  }
+
+  /// Returns whether the ESC is armed.
+  pub fn is_armed(&self) -> bool {
+    self.throttle.is_some()
+  }
+
+  /// Returns the throttle set last, or `None` while disarmed.
+  pub fn throttle(&self) -> Option<f32> {
+    self.throttle
+  }
+
+  /// Arms the ESC by sending the stopped pulse for the arm time, returning
+  /// when it's done.
+  ///
+  /// Arming an armed ESC stops the motor and arms it again.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the PWM can't be written.
+  pub fn arm(&mut self) -> Result<()> {
+    self.write_pulse(self.min_pulse)?;
+    self.pwm.set_enabled(true)?;
+    self.throttle = None;
+    self.clock.sleep(self.arm_time)?;
+    self.throttle = Some(0.0);
+    Ok(())
+  }
+
+  /// Sets the throttle, from 0.0 for stopped to 1.0 for full throttle.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the ESC isn't armed, the throttle is outside of 0.0 to 1.0, or
+  /// the PWM can't be written.
+  pub fn set_throttle(&mut self, throttle: f32) -> Result<()> {
+    if self.throttle.is_none() {
+      bail!("The ESC has to be armed before setting the throttle");
+    }
+    if !(0.0..=1.0).contains(&throttle) {
+      bail!(format!("ESC throttle {} is outside of 0 to 1", throttle));
+    }
+    self.write_throttle(throttle)?;
+    self.throttle = Some(throttle);
+    Ok(())
+  }
+
+  /// Stops the motor and the pulses, which disarms the ESC.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the PWM can't be written; the ESC counts as disarmed anyway.
+  pub fn disarm(&mut self) -> Result<()> {
+    self.throttle = None;
+    self.write_pulse(self.min_pulse)?;
+    self.pwm.set_enabled(false)
+  }
+
+  /// Teaches the ESC the pulse range, taking twice `wait` plus the time the
+  /// ESC needs to be powered on.
+  ///
+  /// Call it with the ESC unpowered and power it on right after: it sees the
+  /// full throttle pulse for `wait`, which makes most ESCs enter calibration
+  /// and beep, and then the stopped pulse for `wait`, which they confirm
+  /// with another beep.
+  /// The ESC is disarmed afterwards.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the PWM can't be written.
+  pub fn calibrate(&mut self, wait: Duration) -> Result<()> {
+    self.throttle = None;
+    self.write_pulse(self.max_pulse)?;
+    self.pwm.set_enabled(true)?;
+    self.clock.sleep(wait)?;
+    self.write_pulse(self.min_pulse)?;
+    self.clock.sleep(wait)?;
+    self.disarm()
+  }
+
+  /// Returns the PWM output.
+  pub fn get_ref(&self) -> &P {
+    &self.pwm
+  }
+
+  fn write_throttle(&mut self, throttle: f32) -> Result<()> {
+    let span = self.max_pulse - self.min_pulse;
+    self.write_pulse(self.min_pulse + span.mul_f32(throttle))
+  }
+
+  fn write_pulse(&mut self, width: Duration) -> Result<()> {
+    let percentage = width.as_secs_f32() / self.period.as_secs_f32() * 100.0;
+    self.pwm.set_duty_cycle_percent(percentage)
+  }
+}
+
+impl<P: PwmOutput> Drop for Esc<P> {
+  fn drop(&mut self) {
+    let _ = self.disarm();
+  }
+}
+
+/// Returns the period of a refresh rate.
+fn refresh_period(hz: f32) -> Result<Duration> {
+  if !(hz > 0.25 && hz <= 10_000.0) {
+    bail!(format!("ESC refresh rate {}Hz is out of range", hz));
+  }
+  Ok(Duration::from_secs_f32(1.0 / hz))
+}
@@ -0,0 +1,387 @@
+//! The e-stop module.
+//!
+//! An emergency stop has to stop every actuator, whatever the rest of the
+//! program is doing, and keep them stopped until someone has checked the
+//! machine.
+//! An `EStop` guards actuators: they are registered with it and then used
+//! through the `GuardedPin` and `GuardedPwm` it returns, which work like
+//! any other `DigitalPin` or `PwmOutput`.
+//! The e-stop is triggered by an input, e.g. a mushroom button, or by the
+//! program itself:
+//!
+//! ```no_run
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::estop::EStop;
+//! use libbeaglebone::servo::Servo;
+//!
+//! // A normally-closed button pulling the input low while it isn't pressed.
+//! let estop = EStop::with_input(GPIO::new(GPIO_P8_11), PinState::High).unwrap();
+//!
+//! let heater = GPIO::new(GPIO_P8_12);
+//! heater.set_export(DeviceState::Exported).unwrap();
+//! heater.set_direction(PinDirection::Out).unwrap();
+//! let mut heater = estop.guard_digital(heater, PinState::Low).unwrap();
+//!
+//! let mut pwm = PWM::new(3, 0);
+//! pwm.set_export(DeviceState::Exported).unwrap();
+//! pwm.set_state(PWMState::Disabled).unwrap();
+//! let mut arm = Servo::from_pwm(estop.guard_pwm(pwm).unwrap()).unwrap();
+//!
+//! heater.set_high().unwrap();
+//! arm.set_angle(90.0).unwrap();
+//!
+//! // Somewhere else, e.g. when a sensor stops answering.
+//! estop.trigger("temperature sensor lost").unwrap();
+//!
+//! // Fails with `ErrorKind::EStopLatched` until the e-stop is reset.
+//! assert!(heater.set_high().is_err());
+//! ```
+//!
+//! When the e-stop is triggered, digital outputs are driven to their safe
+//! state, and PWM outputs get a duty cycle of 0 and are disabled.
+//! It stays latched until `reset()`, which fails while the input is still
+//! asserted; the actuators stay in their safe states until they are
+//! commanded again.
+//!
+//! If the input can't be watched anymore, the e-stop triggers itself.
+//!
+//! Note: a software e-stop doesn't replace one that cuts the power to the
+//! actuators in hardware, it only makes the program notice.
+
+use enums::DeviceState;
+use errors::*;
+use gpio::{Edge, GPIO, PinDirection, PinState};
+use hal::{DigitalPin, PwmOutput};
+use std::fmt;
+use std::mem;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// The longest time the input thread waits before checking whether it
+/// should stop.
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Drives an actuator to its safe state.
+type SafeAction = Box<dyn FnMut() -> Result<()> + Send>;
+
+struct Shared {
+  // What triggered the e-stop, while it's latched.
+  cause: Mutex<Option<String>>,
+  safe_actions: Mutex<Vec<SafeAction>>,
+  // The errors of triggers by the input thread.
+  errors: Mutex<Vec<Error>>,
+}
+
+impl fmt::Debug for Shared {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("Shared")
+     .field("cause", &*lock(&self.cause))
+     .field("actuators", &lock(&self.safe_actions).len())
+     .finish()
+  }
+}
+
+impl Shared {
+  /// Latches the e-stop, keeping the first cause, and drives all actuators
+  /// to their safe states, trying all of them.
+  fn trigger(&self, cause: &str) -> Result<()> {
+    {
+      let mut latched = lock(&self.cause);
+      if latched.is_none() {
+        *latched = Some(cause.to_string());
+      }
+    }
+    let mut first_error = None;
+    for action in lock(&self.safe_actions).iter_mut() {
+      if let Err(e) = action() {
+        first_error = first_error.or(Some(e));
+      }
+    }
+    match first_error {
+      Some(e) => Err(e).chain_err(|| "Failed to stop all actuators"),
+      None => Ok(()),
+    }
+  }
+
+  /// Fails with `ErrorKind::EStopLatched` while the e-stop is latched.
+  fn check(&self) -> Result<()> {
+    match *lock(&self.cause) {
+      Some(ref cause) => Err(ErrorKind::EStopLatched(cause.clone()).into()),
+      None => Ok(()),
+    }
+  }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+  mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Forces the actuators registered with it to their safe states when
+/// triggered, and refuses their commands until reset.
+///
+/// Dropping it stops watching the input; the guarded actuators keep
+/// checking whether it was latched.
+#[derive(Debug)]
+pub struct EStop {
+  shared: Arc<Shared>,
+  // Whether the input is asserted, as the input thread saw it last, if
+  // there's an input.
+  asserted: Option<Arc<AtomicBool>>,
+  running: Arc<AtomicBool>,
+  thread: Option<JoinHandle<()>>,
+}
+
+impl EStop {
+  /// Creates an e-stop that is only triggered by `trigger()`.
+  pub fn new() -> EStop {
+    EStop {
+      shared: Arc::new(Shared {
+        cause: Mutex::new(None),
+        safe_actions: Mutex::new(Vec::new()),
+        errors: Mutex::new(Vec::new()),
+      }),
+      asserted: None,
+      running: Arc::new(AtomicBool::new(false)),
+      thread: None,
+    }
+  }
+
+  /// Creates an e-stop that is triggered when `input` is in state `active`,
+  /// as well as by `trigger()`.
+  ///
+  /// The input is exported, made an input and watched with edge interrupts
+  /// on a background thread.
+  /// If it's asserted already, the e-stop starts out latched.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the input can't be configured or watched.
+  pub fn with_input(mut input: GPIO, active: PinState) -> Result<EStop> {
+    input.set_export(DeviceState::Exported)?;
+    input.set_direction(PinDirection::In)?;
+    input.set_edge(Edge::Both)?;
+    let mut estop = EStop::new();
+    let asserted = Arc::new(AtomicBool::new(input.read()? == active));
+    if asserted.load(Ordering::SeqCst) {
+      estop.shared.trigger("e-stop input asserted")?;
+    }
+
+    estop.running.store(true, Ordering::SeqCst);
+    let thread = {
+      let shared = estop.shared.clone();
+      let asserted = asserted.clone();
+      let running = estop.running.clone();
+      thread::spawn(move || while running.load(Ordering::SeqCst) {
+        let result = match input.wait_for_edge(Some(STOP_CHECK_INTERVAL)) {
+          Ok(Some(state)) if state == active => {
+            asserted.store(true, Ordering::SeqCst);
+            shared.trigger("e-stop input asserted")
+          }
+          Ok(Some(_)) => {
+            asserted.store(false, Ordering::SeqCst);
+            Ok(())
+          }
+          Ok(None) => Ok(()),
+          Err(e) => {
+            // Without the input, nothing can stop the machine anymore.
+            lock(&shared.errors).push(e);
+            running.store(false, Ordering::SeqCst);
+            shared.trigger("e-stop input failed")
+          }
+        };
+        if let Err(e) = result {
+          lock(&shared.errors).push(e);
+        }
+      })
+    };
+    estop.asserted = Some(asserted);
+    estop.thread = Some(thread);
+    Ok(estop)
+  }
+
+  /// Guards a digital output, which is driven to `safe` when the e-stop is
+  /// triggered.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the e-stop is latched and the output can't be driven to its
+  /// safe state.
+  pub fn guard_digital<P>(&self, pin: P, safe: PinState) -> Result<GuardedPin<P>>
+    where P: DigitalPin + Send + 'static
+  {
+    let pin = Arc::new(Mutex::new(pin));
+    let action = {
+      let pin = pin.clone();
+      Box::new(move || lock(&pin).set_state(safe))
+    };
+    self.register(action)?;
+    Ok(GuardedPin {
+      pin,
+      shared: self.shared.clone(),
+    })
+  }
+
+  /// Guards a PWM output, which gets a duty cycle of 0 and is disabled when
+  /// the e-stop is triggered.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the e-stop is latched and the output can't be disabled.
+  pub fn guard_pwm<P>(&self, pwm: P) -> Result<GuardedPwm<P>>
+    where P: PwmOutput + Send + 'static
+  {
+    let pwm = Arc::new(Mutex::new(pwm));
+    let action = {
+      let pwm = pwm.clone();
+      Box::new(move || {
+        let mut pwm = lock(&pwm);
+        // Disabling alone leaves some outputs at their last level.
+        let zeroed = pwm.set_duty_cycle_percent(0.0);
+        pwm.set_enabled(false).and(zeroed)
+      })
+    };
+    self.register(action)?;
+    Ok(GuardedPwm {
+      pwm,
+      shared: self.shared.clone(),
+    })
+  }
+
+  /// Adds the safe action of an actuator, running it right away if the
+  /// e-stop is latched.
+  fn register(&self, mut action: SafeAction) -> Result<()> {
+    let mut actions = lock(&self.shared.safe_actions);
+    if lock(&self.shared.cause).is_some() {
+      action()?;
+    }
+    actions.push(action);
+    Ok(())
+  }
+
+  /// Triggers the e-stop because of `cause`, e.g. "watchdog expired", and
+  /// drives all actuators to their safe states before returning.
+  ///
+  /// Triggering a latched e-stop drives them again but keeps the first
+  /// cause.
+  ///
+  /// # Errors
+  ///
+  /// Fails if an actuator can't be driven to its safe state, after trying
+  /// all of them; the e-stop is latched anyway.
+  pub fn trigger(&self, cause: &str) -> Result<()> {
+    self.shared.trigger(cause)
+  }
+
+  /// Returns whether the e-stop is latched.
+  pub fn is_latched(&self) -> bool {
+    lock(&self.shared.cause).is_some()
+  }
+
+  /// Returns what triggered the e-stop, while it's latched.
+  pub fn cause(&self) -> Option<String> {
+    lock(&self.shared.cause).clone()
+  }
+
+  /// Releases the e-stop, so the actuators accept commands again.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the input is still asserted, or if it can't be watched
+  /// anymore.
+  pub fn reset(&self) -> Result<()> {
+    if let Some(ref asserted) = self.asserted {
+      if !self.running.load(Ordering::SeqCst) {
+        bail!("The e-stop input can't be watched anymore, see take_errors()");
+      }
+      if asserted.load(Ordering::SeqCst) {
+        bail!("The e-stop input is still asserted");
+      }
+    }
+    *lock(&self.shared.cause) = None;
+    Ok(())
+  }
+
+  /// Returns and clears the errors of watching the input, and of the
+  /// triggers by it.
+  pub fn take_errors(&self) -> Vec<Error> {
+    mem::take(&mut *lock(&self.shared.errors))
+  }
+}
+
+impl Default for EStop {
+  fn default() -> EStop {
+    EStop::new()
+  }
+}
+
+impl Drop for EStop {
+  fn drop(&mut self) {
+    self.running.store(false, Ordering::SeqCst);
+    if let Some(thread) = self.thread.take() {
+      let _ = thread.join();
+    }
+  }
+}
+
+/// A digital output guarded by an `EStop`.
+#[derive(Debug)]
+pub struct GuardedPin<P: DigitalPin> {
+  pin: Arc<Mutex<P>>,
+  shared: Arc<Shared>,
+}
+
+impl<P: DigitalPin> DigitalPin for GuardedPin<P> {
+  /// Drives the pin to `state`, unless the e-stop is latched.
+  ///
+  /// # Errors
+  ///
+  /// Fails with `ErrorKind::EStopLatched` while the e-stop is latched.
+  fn set_state(&mut self, state: PinState) -> Result<()> {
+    // The pin is locked before checking, so a trigger can't slip in between
+    // and be overwritten.
+    let mut pin = lock(&self.pin);
+    self.shared.check()?;
+    pin.set_state(state)
+  }
+
+  fn state(&self) -> Result<PinState> {
+    lock(&self.pin).state()
+  }
+
+  fn pin_name(&self) -> String {
+    lock(&self.pin).pin_name()
+  }
+}
+
+/// A PWM output guarded by an `EStop`.
+#[derive(Debug)]
+pub struct GuardedPwm<P: PwmOutput> {
+  pwm: Arc<Mutex<P>>,
+  shared: Arc<Shared>,
+}
+
+impl<P: PwmOutput> GuardedPwm<P> {
+  fn command<F>(&mut self, command: F) -> Result<()>
+    where F: FnOnce(&mut P) -> Result<()>
+  {
+    let mut pwm = lock(&self.pwm);
+    self.shared.check()?;
+    command(&mut pwm)
+  }
+}
+
+impl<P: PwmOutput> PwmOutput for GuardedPwm<P> {
+  fn set_period_ns(&mut self, period_ns: u32) -> Result<()> {
+    self.command(|pwm| pwm.set_period_ns(period_ns))
+  }
+
+  fn set_duty_cycle_percent(&mut self, percentage: f32) -> Result<()> {
+    self.command(|pwm| pwm.set_duty_cycle_percent(percentage))
+  }
+
+  fn set_enabled(&mut self, enabled: bool) -> Result<()> {
+    self.command(|pwm| pwm.set_enabled(enabled))
+  }
+}
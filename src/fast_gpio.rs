@@ -0,0 +1,178 @@
+//! The fast GPIO module.
+//!
+//! Every sysfs or character device access is a system call, which limits
+//! toggling a GPIO to a few kHz.
+//! `FastGPIO` writes the AM335x's GPIO registers directly instead, mapped
+//! from `/dev/mem`, which toggles pins at several MHz for bit-banged
+//! protocols and signal generation:
+//!
+//! ```no_run
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::fast_gpio::FastGPIO;
+//!
+//! // Configure the pin through sysfs as usual, then take the fast path.
+//! let pin = GPIO::new(GPIO_P8_11);
+//! pin.set_export(DeviceState::Exported).unwrap();
+//! pin.set_direction(PinDirection::Out).unwrap();
+//! let fast = FastGPIO::from_gpio(&pin).unwrap();
+//!
+//! for _ in 0..1_000_000 {
+//!   fast.fast_write(PinState::High);
+//!   fast.fast_write(PinState::Low);
+//! }
+//! ```
+//!
+//! Pins written this way bypass the kernel: it doesn't know their state,
+//! and the journal, VCD traces and statistics don't see them either.
+//! The direction is best configured through `GPIO` first, which also makes
+//! the kernel enable the clock of the pin's GPIO bank; the registers of a
+//! bank whose clock is off read as zero and ignore writes.
+//! `/dev/mem` requires root privileges, see the `mmio` module.
+
+use errors::*;
+use gpio::{GPIO, PinDirection, PinState};
+use hal::DigitalPin;
+use mmio::MemoryMap;
+use pins::Pin;
+
+/// The physical addresses of the register blocks of the GPIO banks 0 to 3.
+pub const GPIO_BANKS: [usize; 4] = [0x44E0_7000, 0x4804_C000, 0x481A_C000, 0x481A_E000];
+
+/// The size of the register block of a GPIO bank.
+const GPIO_BANK_LEN: usize = 0x1000;
+
+// Register offsets within a bank.
+const GPIO_OE: usize = 0x134;
+pub(crate) const GPIO_DATAIN: usize = 0x138;
+pub(crate) const GPIO_DATAOUT: usize = 0x13C;
+const GPIO_CLEARDATAOUT: usize = 0x190;
+const GPIO_SETDATAOUT: usize = 0x194;
+
+/// A GPIO accessed through its bank's registers.
+#[derive(Debug)]
+pub struct FastGPIO {
+  pin_num: u8,
+  mask: u32,
+  regs: MemoryMap,
+}
+
+impl FastGPIO {
+  /// Maps the registers of `pin`'s GPIO bank.
+  ///
+  /// The pin isn't configured, see `from_gpio()`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the registers can't be mapped, e.g. because the process isn't
+  /// running as root.
+  pub fn new(pin: Pin) -> Result<FastGPIO> {
+    FastGPIO::from_num(pin as u8)
+  }
+
+  /// Maps the registers of the GPIO bank of the kernel's GPIO `pin_num`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if there's no such GPIO on the AM335x, or if the registers can't
+  /// be mapped.
+  pub fn from_num(pin_num: u8) -> Result<FastGPIO> {
+    Ok(FastGPIO {
+      pin_num,
+      mask: 1 << (pin_num % 32),
+      regs: map_bank(pin_num)?,
+    })
+  }
+
+  /// Maps the registers of the bank of `gpio`, which was configured through
+  /// the kernel.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the registers can't be mapped.
+  pub fn from_gpio(gpio: &GPIO) -> Result<FastGPIO> {
+    FastGPIO::from_num(gpio.pin_num())
+  }
+
+  /// Returns the kernel's number of the pin.
+  pub fn pin_num(&self) -> u8 {
+    self.pin_num
+  }
+
+  /// Makes the pin an input or output.
+  ///
+  /// The other pins of the bank share the register, so this races with
+  /// the kernel configuring them at the same time; prefer
+  /// `GPIO::set_direction()`.
+  pub fn set_direction(&self, direction: PinDirection) {
+    let enabled = self.regs.read_u32(GPIO_OE);
+    // A set bit disables the output driver.
+    let enabled = match direction {
+      PinDirection::In => enabled | self.mask,
+      PinDirection::Out => enabled & !self.mask,
+    };
+    self.regs.write_u32(GPIO_OE, enabled);
+  }
+
+  /// Returns the direction of the pin.
+  pub fn direction(&self) -> PinDirection {
+    if self.regs.read_u32(GPIO_OE) & self.mask != 0 {
+      PinDirection::In
+    } else {
+      PinDirection::Out
+    }
+  }
+
+  /// Drives the output to `state`.
+  ///
+  /// The set and clear registers only affect this pin, so this doesn't race
+  /// with writes to the other pins of the bank.
+  pub fn fast_write(&self, state: PinState) {
+    let register = match state {
+      PinState::High => GPIO_SETDATAOUT,
+      PinState::Low => GPIO_CLEARDATAOUT,
+    };
+    self.regs.write_u32(register, self.mask);
+  }
+
+  /// Reads the level of the pin, of an input or an output alike.
+  pub fn fast_read(&self) -> PinState {
+    if self.regs.read_u32(GPIO_DATAIN) & self.mask != 0 {
+      PinState::High
+    } else {
+      PinState::Low
+    }
+  }
+
+  /// Returns the state the output is driven to.
+  pub fn driven_state(&self) -> PinState {
+    if self.regs.read_u32(GPIO_DATAOUT) & self.mask != 0 {
+      PinState::High
+    } else {
+      PinState::Low
+    }
+  }
+}
+
+impl DigitalPin for FastGPIO {
+  fn set_state(&mut self, state: PinState) -> Result<()> {
+    self.fast_write(state);
+    Ok(())
+  }
+
+  fn state(&self) -> Result<PinState> {
+    Ok(self.fast_read())
+  }
+
+  fn pin_name(&self) -> String {
+    format!("fast GPIO pin #{}", self.pin_num)
+  }
+}
+
+/// Maps the registers of the GPIO bank of the kernel's GPIO `pin_num`.
+pub(crate) fn map_bank(pin_num: u8) -> Result<MemoryMap> {
+  let base = match GPIO_BANKS.get(usize::from(pin_num / 32)) {
+    Some(&base) => base,
+    None => bail!(format!("The AM335x has no GPIO #{}", pin_num)),
+  };
+  MemoryMap::new(base, GPIO_BANK_LEN).chain_err(|| format!("Failed to map the GPIO bank of pin #{}", pin_num))
+}
@@ -0,0 +1,143 @@
+//! The follow module.
+//!
+//! Many setups only need an output to copy an input: a relay that follows a
+//! switch, a level shifter made from two pins, or a quick check during
+//! bring-up that an input is wired up right.
+//! `follow()` mirrors a GPIO input to any `DigitalPin` on a background
+//! thread, optionally inverted and delayed:
+//!
+//! ```no_run
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::follow::follow;
+//! use std::time::Duration;
+//!
+//! let switch = GPIO::new(GPIO_P8_11);
+//! let relay = GPIO::new(GPIO_P8_12);
+//! relay.set_export(DeviceState::Exported).unwrap();
+//! relay.set_direction(PinDirection::Out).unwrap();
+//!
+//! // The relay follows the switch 50ms later.
+//! let follower = follow(switch, relay, false, Duration::from_millis(50)).unwrap();
+//! // ...
+//! follower.stop().unwrap();
+//! ```
+//!
+//! The input is watched with edge interrupts, so the thread sleeps while
+//! it doesn't change.
+//! With a delay, every edge is replayed after it, so even pulses shorter
+//! than the delay come through.
+
+use enums::DeviceState;
+use errors::*;
+use gpio::{Edge, GPIO, PinDirection, PinState};
+use hal::DigitalPin;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// The longest time the thread waits before checking whether it should
+/// stop.
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Mirrors an input to an output on a background thread.
+///
+/// Dropping it stops the thread.
+#[derive(Debug)]
+pub struct Follower {
+  running: Arc<AtomicBool>,
+  thread: Option<JoinHandle<Result<()>>>,
+}
+
+impl Follower {
+  /// Returns whether the output still follows the input, i.e. the thread
+  /// hasn't stopped because of an error.
+  pub fn is_running(&self) -> bool {
+    self.thread.as_ref().is_some_and(|thread| !thread.is_finished())
+  }
+
+  /// Stops following the input, leaving the output as it is.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the thread stopped earlier because the input couldn't be
+  /// watched or the output couldn't be written.
+  pub fn stop(mut self) -> Result<()> {
+    self.running.store(false, Ordering::SeqCst);
+    match self.thread.take().map(JoinHandle::join) {
+      Some(Ok(result)) => result,
+      Some(Err(_)) => bail!("The follow thread panicked"),
+      None => Ok(()),
+    }
+  }
+}
+
+impl Drop for Follower {
+  fn drop(&mut self) {
+    self.running.store(false, Ordering::SeqCst);
+    if let Some(thread) = self.thread.take() {
+      let _ = thread.join();
+    }
+  }
+}
+
+/// Makes `output` follow `input`, inverted if `invert` is set, `delay`
+/// after each change.
+///
+/// The input is exported and made an input; the output has to be
+/// configured already.
+/// It's set to the input's current state right away.
+///
+/// # Errors
+///
+/// Fails if the input can't be configured or watched, or if the output
+/// can't be set to begin with.
+pub fn follow<P>(mut input: GPIO, mut output: P, invert: bool, delay: Duration) -> Result<Follower>
+  where P: DigitalPin + Send + 'static
+{
+  input.set_export(DeviceState::Exported)?;
+  input.set_direction(PinDirection::In)?;
+  input.set_edge(Edge::Both)?;
+  let mirror = move |state: PinState| if (state == PinState::High) != invert {
+    PinState::High
+  } else {
+    PinState::Low
+  };
+  output.set_state(mirror(input.read()?))?;
+
+  let running = Arc::new(AtomicBool::new(true));
+  let thread = {
+    let running = running.clone();
+    thread::spawn(move || {
+      // The changes waiting for their delay to pass.
+      let mut pending: VecDeque<(Instant, PinState)> = VecDeque::new();
+      while running.load(Ordering::SeqCst) {
+        let now = Instant::now();
+        while let Some(&(due, state)) = pending.front() {
+          if due > now {
+            break;
+          }
+          output.set_state(state)?;
+          let _ = pending.pop_front();
+        }
+        let timeout = match pending.front() {
+          Some(&(due, _)) => (due - now).min(STOP_CHECK_INTERVAL),
+          None => STOP_CHECK_INTERVAL,
+        };
+        if let Some(state) = input.wait_for_edge(Some(timeout))? {
+          if delay == Duration::from_secs(0) {
+            output.set_state(mirror(state))?;
+          } else {
+            pending.push_back((Instant::now() + delay, mirror(state)));
+          }
+        }
+      }
+      Ok(())
+    })
+  };
+  Ok(Follower {
+       running,
+       thread: Some(thread),
+     })
+}
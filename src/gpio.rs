@@ -20,17 +20,34 @@
 //! tree overlay, as they are used for other interfaces such as HDMI.
 //! You may need to change the overlay from the default to access these blocked
 //! pins.
+//!
+//! The pins are accessed through sysfs by default, which newer kernels
+//! deprecate in favour of the GPIO character devices `/dev/gpiochipN`.
+//! `GPIO::with_backend()` or building with the `gpio-cdev` feature selects
+//! those instead, behind the same API.
 
+use board;
+use clock;
 use enums::DeviceState;
 use errors::*;
+use gpio_cdev::{self, Chip, LineEvents, LineHandle};
+use journal::{self, Entry};
 use pins::Pin;
+use reactor::{self, EdgeEvent, Subscription};
+use stats::{OpCounters, OpStats};
+use stub;
 use std::fs::File;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
 use util::*;
+use vcd;
 
 /// The direction of the pin, which can be either an input or output.
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PinDirection {
   /// GPIO in
   In,
@@ -39,7 +56,7 @@ pub enum PinDirection {
 }
 
 /// The logic level of an output GPIO pin, either high or low.
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PinState {
   /// GPIO logic high
   High,
@@ -47,11 +64,87 @@ pub enum PinState {
   Low,
 }
 
+/// The edges of an input that `GPIO::wait_for_edge()` waits for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edge {
+  /// No edges, edge detection is disabled.
+  None,
+  /// Changes from low to high.
+  Rising,
+  /// Changes from high to low.
+  Falling,
+  /// All changes.
+  Both,
+}
+
+/// The internal pull resistor of an input, see `GPIO::set_pull()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pull {
+  /// No pull resistor, the input floats unless driven.
+  None,
+  /// A pull-up resistor, the input reads high unless driven low.
+  Up,
+  /// A pull-down resistor, the input reads low unless driven high.
+  Down,
+}
+
+/// How a `GPIO` talks to the kernel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GpioBackend {
+  /// The files in `/sys/class/gpio`, deprecated but supported by all
+  /// kernels so far.
+  Sysfs,
+  /// The character devices `/dev/gpiochipN`, see the `gpio_cdev` module.
+  Cdev,
+}
+
+impl Default for GpioBackend {
+  /// Returns the backend of `GPIO::new()`: `Cdev` if the crate is built
+  /// with the `gpio-cdev` feature, `Sysfs` otherwise or on the host stub.
+  fn default() -> GpioBackend {
+    if cfg!(feature = "gpio-cdev") && !stub::is_active() {
+      GpioBackend::Cdev
+    } else {
+      GpioBackend::Sysfs
+    }
+  }
+}
+
+/// The line of a GPIO on the character device backend, as requested last.
+#[derive(Debug)]
+enum Line {
+  Released,
+  Handle(LineHandle),
+  Events(LineEvents),
+}
+
+// The cached direction of a pin, kept in an `AtomicU8` because the
+// direction is set through a shared reference.
+const DIRECTION_UNKNOWN: u8 = 0;
+const DIRECTION_IN: u8 = 1;
+const DIRECTION_OUT: u8 = 2;
+
+// Likewise the state written last, which unexporting forgets.
+const STATE_UNKNOWN: u8 = 0;
+const STATE_LOW: u8 = 1;
+const STATE_HIGH: u8 = 2;
+
 /// Represents a pin configured as a GPIO.
 #[derive(Debug)]
 pub struct GPIO {
   pin_num: u8,
   pin_path: PathBuf,
+  counters: OpCounters,
+  direction: AtomicU8,
+  state: AtomicU8,
+  waiter: Option<EdgeWaiter>,
+  backend: GpioBackend,
+  chip_path: String,
+  line_offset: u32,
+  line: Mutex<Line>,
+  pinmux_path: Option<String>,
+  pull: Mutex<Option<Pull>>,
+  consumer: Option<String>,
 }
 
 impl GPIO {
@@ -78,14 +171,126 @@ impl GPIO {
   ///
   /// Fails if the `pin_num` is invalid, i.e. a nonexistent pin.
   pub fn new(pin: Pin) -> GPIO {
+    GPIO::from_num(pin as u8)
+  }
+
+  /// Creates a new GPIO pin object from the kernel's number of the pin, e.g.
+  /// for boards other than the BeagleBone.
+  pub fn from_num(pin_num: u8) -> GPIO {
+    GPIO::from_num_with_backend(pin_num, GpioBackend::default())
+  }
+
+  /// Creates a new GPIO pin object that talks to the kernel through
+  /// `backend`, instead of the default one.
+  ///
+  /// The API is the same for both backends.
+  /// On the character device backend, making the pin an input or output
+  /// or setting its edges requests the line, which holds it until the
+  /// object is dropped or the pin unexported.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use libbeaglebone::prelude::*;
+  /// use libbeaglebone::gpio::GpioBackend;
+  ///
+  /// let pin = GPIO::with_backend(GPIO_P8_11, GpioBackend::Cdev);
+  /// assert_eq!(pin.backend(), GpioBackend::Cdev);
+  /// ```
+  pub fn with_backend(pin: Pin, backend: GpioBackend) -> GPIO {
+    GPIO::from_num_with_backend(pin as u8, backend)
+  }
+
+  /// Creates a new GPIO pin object from the kernel's number of the pin
+  /// that talks to the kernel through `backend`.
+  pub fn from_num_with_backend(pin_num: u8, backend: GpioBackend) -> GPIO {
+    let board = board::current();
+    let (chip_path, line_offset) = board.gpio_line(pin_num);
     GPIO {
-      pin_num: pin as u8,
-      pin_path: PathBuf::from(format!("/sys/class/gpio/gpio{}", pin as u8)),
+      pin_num,
+      pin_path: PathBuf::from(board.gpio_path(pin_num)),
+      counters: OpCounters::new(),
+      direction: AtomicU8::new(DIRECTION_UNKNOWN),
+      state: AtomicU8::new(STATE_UNKNOWN),
+      waiter: None,
+      backend,
+      chip_path,
+      line_offset,
+      line: Mutex::new(Line::Released),
+      pinmux_path: board.pinmux_path(pin_num),
+      pull: Mutex::new(None),
+      consumer: None,
+    }
+  }
+
+  /// Creates a new GPIO pin object from the name of the pin in the current
+  /// board's pin table, e.g. "P8_11".
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use libbeaglebone::prelude::*;
+  ///
+  /// let pin = GPIO::from_name("P8_11").unwrap();
+  /// assert_eq!(pin.pin_num(), 45);
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if the board has no pin with that name.
+  pub fn from_name(name: &str) -> Result<GPIO> {
+    let board = board::current();
+    match board.gpio_num(name) {
+      Some(pin_num) => Ok(GPIO::from_num(pin_num)),
+      None => bail!(format!("The {} has no GPIO pin {}", board.name, name)),
+    }
+  }
+
+  /// Returns the kernel's number of the pin, e.g. 45 for `GPIO_P8_11`.
+  pub fn pin_num(&self) -> u8 {
+    self.pin_num
+  }
+
+  /// Returns how the pin talks to the kernel.
+  pub fn backend(&self) -> GpioBackend {
+    self.backend
+  }
+
+  /// Returns the pin's operation statistics, see the `stats` module.
+  pub fn stats(&self) -> OpStats {
+    self.counters.snapshot()
+  }
+
+  /// Resets the pin's operation statistics.
+  pub fn reset_stats(&self) {
+    self.counters.reset()
+  }
+
+  /// Returns the sysfs directory of the pin, e.g. `/sys/class/gpio/gpio45`.
+  ///
+  /// It only exists on the sysfs backend, once the pin is exported.
+  pub fn sysfs_path(&self) -> &Path {
+    &self.pin_path
+  }
+
+  /// Returns whether the pin is exported: on the sysfs backend, whether its
+  /// directory exists, and on the character device backend, whether the
+  /// object holds the line, which it does once it was made an input or
+  /// output.
+  pub fn is_exported(&self) -> bool {
+    match self.backend {
+      GpioBackend::Sysfs => self.pin_path.exists(),
+      GpioBackend::Cdev => match *self.line() {
+        Line::Released => false,
+        Line::Handle(_) | Line::Events(_) => true,
+      },
     }
   }
 
   /// Sets the direction of the pin as either an input or output.
   ///
+  /// An output drives the state written last with `write()`, or low.
+  ///
   /// # Examples
   ///
   /// ```no_run
@@ -105,18 +310,235 @@ impl GPIO {
   /// Fails if the GPIO pin is not configured correctly.
   /// Check the module documentation to see how to configure the pin correctly.
   pub fn set_direction(&self, direction: PinDirection) -> Result<()> {
-    // Write "in" or "out" to the sysfs device file depending on PinDirection
-    let path = format!("/sys/class/gpio/gpio{}/direction", &self.pin_num);
-    path.write_file(match direction {
-      PinDirection::In => "in",
-      PinDirection::Out => "out",
-    })
+    if self.backend == GpioBackend::Cdev {
+      // Keep driving the state written last, if any.
+      let state = self.written_state().unwrap_or(PinState::Low);
+      let pull = self.pull();
+      self.counters
+          .write(|| {
+                   self.request_line(|chip, offset| match direction {
+                                       PinDirection::In => chip.request_input(offset, pull).map(Line::Handle),
+                                       PinDirection::Out => {
+                                         chip.request_output(offset, state, pull).map(Line::Handle)
+                                       }
+                                     })
+                 })
+          .chain_err(|| format!("Failed to set GPIO pin #{} direction", &self.pin_num))?;
+      self.cache_direction(Some(direction));
+      return Ok(());
+    }
+    // Write "in" or "out" to the sysfs device file depending on PinDirection,
+    // "high" to keep driving high, as "out" drives low.
+    let path = format!("{}/direction", self.pin_path.display());
+    let value = match (direction, self.written_state()) {
+      (PinDirection::In, _) => "in",
+      (PinDirection::Out, Some(PinState::High)) => "high",
+      (PinDirection::Out, _) => "out",
+    };
+    self.counters
+        .write(|| path.write_file(value))
         .chain_err(|| {
       format!("Failed to set GPIO pin #{} direction", &self.pin_num)
     })?;
+    self.cache_direction(Some(direction));
     Ok(())
   }
 
+  /// Configures the pin's internal pull resistor, e.g. a pull-up for a
+  /// button that connects the pin to ground when pressed.
+  ///
+  /// On the sysfs backend, this sets the pin's pinmux state, which needs a
+  /// cape-universal overlay, as `config-pin P8.11 gpio_pu` would.
+  /// On the character device backend, the pull is part of the line request:
+  /// it's applied to a requested line right away, and otherwise when
+  /// `set_direction()` or `set_edge()` request it.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  ///
+  /// let button = GPIO::new(GPIO_P8_11);
+  /// button.set_export(DeviceState::Exported).unwrap();
+  /// button.set_pull(Pull::Up).unwrap();
+  /// button.set_direction(PinDirection::In).unwrap();
+  ///
+  /// // Reads high until the button is pressed, without an external resistor.
+  /// assert_eq!(button.read().unwrap(), PinState::High);
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails on the sysfs backend if the pin has no pinmux helper, e.g.
+  /// because no cape-universal overlay is loaded, and on the character
+  /// device backend if the kernel is older than 5.5.
+  pub fn set_pull(&self, pull: Pull) -> Result<()> {
+    if self.backend == GpioBackend::Cdev {
+      *self.pull.lock().unwrap_or_else(|e| e.into_inner()) = Some(pull);
+      let held = match *self.line() {
+        Line::Released => None,
+        Line::Handle(_) => Some(Edge::None),
+        Line::Events(ref events) => Some(events.edge()),
+      };
+      let direction = self.cached_direction().unwrap_or(PinDirection::In);
+      let state = self.written_state().unwrap_or(PinState::Low);
+      if let Some(edge) = held {
+        self.counters
+            .write(|| {
+                     self.request_line(|chip, offset| match (direction, edge) {
+                                         (PinDirection::Out, _) => {
+                                           chip.request_output(offset, state, Some(pull)).map(Line::Handle)
+                                         }
+                                         (PinDirection::In, Edge::None) => {
+                                           chip.request_input(offset, Some(pull)).map(Line::Handle)
+                                         }
+                                         (PinDirection::In, edge) => {
+                                           chip.request_events(offset, edge, Some(pull)).map(Line::Events)
+                                         }
+                                       })
+                   })
+            .chain_err(|| format!("Failed to set the pull of GPIO pin #{}", &self.pin_num))?;
+      }
+      return Ok(());
+    }
+    let path = match self.pinmux_path {
+      Some(ref path) => path,
+      None => bail!(format!("GPIO pin #{} has no pinmux to set its pull with", &self.pin_num)),
+    };
+    let value = match pull {
+      Pull::None => "gpio",
+      Pull::Up => "gpio_pu",
+      Pull::Down => "gpio_pd",
+    };
+    self.counters
+        .write(|| path.write_file(value))
+        .chain_err(|| format!("Failed to set the pull of GPIO pin #{}", &self.pin_num))?;
+    *self.pull.lock().unwrap_or_else(|e| e.into_inner()) = Some(pull);
+    Ok(())
+  }
+
+  /// Returns the pull resistor as last set, or `None` if it was never set
+  /// and is as the kernel configured it.
+  pub fn pull(&self) -> Option<Pull> {
+    *self.pull.lock().unwrap_or_else(|e| e.into_inner())
+  }
+
+  /// Labels the pin's line as held by `consumer` when it's requested from
+  /// now on, as shown by `gpioinfo`, instead of the program's name.
+  ///
+  /// Only the character device backend has consumers, sysfs pins ignore
+  /// the label.
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  /// use libbeaglebone::gpio::GpioBackend;
+  ///
+  /// let mut pump = GPIO::with_backend(GPIO_P8_11, GpioBackend::Cdev);
+  /// pump.set_consumer("coolant pump");
+  /// pump.set_direction(PinDirection::Out).unwrap();
+  /// ```
+  pub fn set_consumer(&mut self, consumer: &str) {
+    self.consumer = Some(consumer.to_string());
+  }
+
+  /// Returns the label the pin's line is requested with.
+  pub fn consumer(&self) -> String {
+    self.consumer.clone().unwrap_or_else(gpio_cdev::default_consumer)
+  }
+
+  /// Returns the direction of the pin as last set or read, or `None` if it's
+  /// unknown.
+  pub fn cached_direction(&self) -> Option<PinDirection> {
+    match self.direction.load(Ordering::Relaxed) {
+      DIRECTION_IN => Some(PinDirection::In),
+      DIRECTION_OUT => Some(PinDirection::Out),
+      _ => None,
+    }
+  }
+
+  /// Returns the state the pin was driven to last with `write()`, or `None`
+  /// if it wasn't written yet or since `refresh()` or unexporting it.
+  ///
+  /// ```
+  /// use libbeaglebone::prelude::*;
+  /// use libbeaglebone::{board, stub};
+  ///
+  /// board::set_current(stub::board().unwrap());
+  /// let mut pin = GPIO::new(GPIO_P8_12);
+  /// pin.set_direction(PinDirection::Out).unwrap();
+  /// pin.write(PinState::High).unwrap();
+  /// assert_eq!(pin.written_state(), Some(PinState::High));
+  ///
+  /// // Exported again, the pin doesn't drive high by itself.
+  /// pin.set_export(DeviceState::Unexported).unwrap();
+  /// assert_eq!(pin.written_state(), None);
+  /// ```
+  pub fn written_state(&self) -> Option<PinState> {
+    match self.state.load(Ordering::Relaxed) {
+      STATE_LOW => Some(PinState::Low),
+      STATE_HIGH => Some(PinState::High),
+      _ => None,
+    }
+  }
+
+  /// Re-reads the direction from sysfs, e.g. after another process changed
+  /// it, and forgets the state written last.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the GPIO pin isn't exported.
+  pub fn refresh(&mut self) -> Result<()> {
+    let _ = self.direction()?;
+    self.cache_state(None);
+    Ok(())
+  }
+
+  fn cache_state(&self, state: Option<PinState>) {
+    let value = match state {
+      Some(PinState::Low) => STATE_LOW,
+      Some(PinState::High) => STATE_HIGH,
+      None => STATE_UNKNOWN,
+    };
+    self.state.store(value, Ordering::Relaxed);
+  }
+
+  fn cache_direction(&self, direction: Option<PinDirection>) {
+    let value = match direction {
+      Some(PinDirection::In) => DIRECTION_IN,
+      Some(PinDirection::Out) => DIRECTION_OUT,
+      None => DIRECTION_UNKNOWN,
+    };
+    self.direction.store(value, Ordering::Relaxed);
+  }
+
+  /// Reads the direction of the pin.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the GPIO pin isn't exported.
+  pub fn direction(&self) -> Result<PinDirection> {
+    if self.backend == GpioBackend::Cdev {
+      let output = self.counters
+                       .read(|| Ok(Chip::open(&self.chip_path)?.line_info(self.line_offset)?.output))
+                       .chain_err(|| format!("Failed to read GPIO pin #{} direction", &self.pin_num))?;
+      let direction = if output { PinDirection::Out } else { PinDirection::In };
+      self.cache_direction(Some(direction));
+      return Ok(direction);
+    }
+    let path = format!("{}/direction", self.pin_path.display());
+    let value = self.counters
+                    .read(|| path.as_str().read_file())
+                    .chain_err(|| format!("Failed to read GPIO pin #{} direction", &self.pin_num))?;
+    let direction = match value.trim() {
+      "in" => PinDirection::In,
+      // Also written by those making a pin an output driving a level.
+      "out" | "high" | "low" => PinDirection::Out,
+      _ => bail!(format!("Invalid value read from file {}", &path)),
+    };
+    self.cache_direction(Some(direction));
+    Ok(direction)
+  }
+
   /// Exports or unexports a GPIO pin.
   ///
   /// True corresponds to export, false corresponds to unexport.
@@ -142,6 +564,19 @@ impl GPIO {
   /// Fails to export the pin if it isn't configured correctly.
   /// Check the module documentation to see how to configure the pin correctly.
   pub fn set_export(&self, state: DeviceState) -> Result<()> {
+    // Character device lines don't need exporting, only requesting.
+    if self.backend == GpioBackend::Cdev {
+      if state == DeviceState::Exported {
+        let _ = Chip::open(&self.chip_path).and_then(|chip| chip.line_info(self.line_offset))
+                                           .chain_err(|| format!("Failed to export GPIO pin #{}", &self.pin_num))?;
+      } else {
+        // The line is reset when it's requested again.
+        *self.line() = Line::Released;
+        self.cache_direction(None);
+        self.cache_state(None);
+      }
+      return Ok(());
+    }
     // Note: if the pin path exists, the pin is already exported.
     // If the pin path doesn't exist, the pin isn't exported.
     // Exporting/unexporting is done by writing the pin number to the
@@ -149,7 +584,7 @@ impl GPIO {
 
     // The pin path doesn't exist and we want to export, try to write to the file
     if state == DeviceState::Exported && !self.pin_path.exists() {
-      File::create("/sys/class/gpio/export")
+      File::create(self.pin_path.with_file_name("export"))
         .chain_err(|| "Failed to open GPIO export file")?
         .write_all(self.pin_num.to_string().as_bytes())
         .chain_err(|| format!("Failed to export GPIO pin #{}", &self.pin_num))?;
@@ -157,10 +592,13 @@ impl GPIO {
     }
     // Try to unexport if the path exists, otherwise the pin is unexported and there's nothing to do
     else if state == DeviceState::Unexported && self.pin_path.exists() {
-      File::create("/sys/class/gpio/unexport")
+      File::create(self.pin_path.with_file_name("unexport"))
         .chain_err(|| "Failed to open GPIO unexport file")?
         .write_all(self.pin_num.to_string().as_bytes())
         .chain_err(|| format!("Failed to unexport GPIO pin #{}", &self.pin_num))?;
+      // The kernel resets the pin when it's exported again.
+      self.cache_direction(None);
+      self.cache_state(None);
     }
     Ok(())
   }
@@ -187,15 +625,27 @@ impl GPIO {
   ///
   /// # Errors
   ///
+  /// Fails if the pin was made an input with `set_direction()`.
   /// Fails to write to the pin if the pin isn't configured correctly.
   /// Check the module documentation to see how to configure the pin correctly.
   pub fn write(&mut self, state: PinState) -> Result<()> {
-    let path = format!("/sys/class/gpio/gpio{}/value", &self.pin_num);
+    if self.cached_direction() == Some(PinDirection::In) {
+      bail!(format!(
+        "GPIO pin #{} is an input, it has to be made an output before it's written",
+        &self.pin_num
+      ));
+    }
+    let path = format!("{}/value", self.pin_path.display());
     // Write a "0" or "1" to the pin's "value" device file depending on PinState
-    path.write_file(match state {
+    let value = match state {
       PinState::High => "1",
       PinState::Low => "0",
-    })
+    };
+    self.counters
+        .write(|| match self.backend {
+                 GpioBackend::Sysfs => path.write_file(value),
+                 GpioBackend::Cdev => self.set_line(state),
+               })
         .chain_err(|| {
       format!(
         "Failed to set GPIO pin #{} state to {:?}",
@@ -203,6 +653,12 @@ impl GPIO {
         state
       )
     })?;
+    vcd::record(format_args!("gpio{}", self.pin_num), 1, (state == PinState::High) as u64);
+    journal::record(Entry::GpioWrite {
+                      pin: self.pin_num,
+                      state,
+                    });
+    self.cache_state(Some(state));
     Ok(())
   }
 
@@ -230,12 +686,405 @@ impl GPIO {
   /// Fails to read from the pin if the pin isn't configured correctly.
   /// Check the module documentation to see how to configure the pin correctly.
   pub fn read(&self) -> Result<(PinState)> {
-    let path = format!("/sys/class/gpio/gpio{}/value", &self.pin_num);
+    if self.backend == GpioBackend::Cdev {
+      return self.counters
+                 .read(|| self.get_line())
+                 .chain_err(|| format!("Failed to read GPIO pin #{}", &self.pin_num));
+    }
+    let path = format!("{}/value", self.pin_path.display());
     // Read from the file and match the resulting bool to a PinState
-    match path.read_file().unwrap().trim() {
+    let value = self.counters
+                    .read(|| path.as_str().read_file())
+                    .chain_err(|| format!("Failed to read GPIO pin #{}", &self.pin_num))?;
+    match value.trim() {
       "1" => Ok(PinState::High),
       "0" => Ok(PinState::Low),
       _ => bail!(format!("Invalid value read from file {}", &path)),
     }
   }
+
+  /// Sets the edges of the input that `wait_for_edge()` waits for, or
+  /// disables edge detection with `Edge::None`.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  /// use std::time::Duration;
+  ///
+  /// let mut button = GPIO::new(GPIO_P8_11);
+  /// button.set_export(DeviceState::Exported).unwrap();
+  /// button.set_direction(PinDirection::In).unwrap();
+  ///
+  /// // Block until the button is pressed, pulling the input low.
+  /// button.set_edge(Edge::Falling).unwrap();
+  /// button.wait_for_edge(None).unwrap();
+  ///
+  /// // Or give up after a second.
+  /// if button.wait_for_edge(Some(Duration::from_secs(1))).unwrap().is_none() {
+  ///   println!("Not pressed");
+  /// }
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if the pin isn't an exported input, or doesn't support edge
+  /// interrupts.
+  pub fn set_edge(&mut self, edge: Edge) -> Result<()> {
+    let value = match edge {
+      Edge::None => "none",
+      Edge::Rising => "rising",
+      Edge::Falling => "falling",
+      Edge::Both => "both",
+    };
+    self.waiter = None;
+    if self.backend == GpioBackend::Cdev {
+      let pull = self.pull();
+      self.request_line(|chip, offset| match edge {
+                          Edge::None => chip.request_input(offset, pull).map(Line::Handle),
+                          _ => chip.request_events(offset, edge, pull).map(Line::Events),
+                        })
+          .chain_err(|| format!("Failed to set the edges of GPIO pin #{}", &self.pin_num))?;
+      self.cache_direction(Some(PinDirection::In));
+      return Ok(());
+    }
+    if edge == Edge::None {
+      let path = format!("{}/edge", self.pin_path.display());
+      self.counters
+          .write(|| path.write_file(value))
+          .chain_err(|| format!("Failed to disable edge detection on GPIO pin #{}", &self.pin_num))?;
+    } else {
+      self.waiter = Some(EdgeWaiter::new(self.pin_num, value)?);
+    }
+    Ok(())
+  }
+
+  /// Blocks until one of the edges set with `set_edge()` occurs, or until
+  /// `timeout` has passed, waiting forever for `None`.
+  ///
+  /// The thread sleeps while waiting, it doesn't poll the input.
+  /// Returns the state of the pin right after the edge, or `None` if the
+  /// timeout expired.
+  ///
+  /// # Errors
+  ///
+  /// Fails if edge detection isn't enabled with `set_edge()`, or the pin
+  /// can't be read anymore, e.g. because it was unexported.
+  pub fn wait_for_edge(&mut self, timeout: Option<Duration>) -> Result<Option<PinState>> {
+    Ok(self.wait_for_edge_timestamped(timeout)?.map(|(state, _)| state))
+  }
+
+  /// Like `wait_for_edge()`, but also returns when the edge occurred.
+  ///
+  /// On a character device line that's the kernel's timestamp, taken in its
+  /// interrupt handler, so it doesn't include the latency of waking the
+  /// thread; it's on the realtime or monotonic clock depending on the kernel
+  /// version, so only differences between timestamps are meaningful.
+  /// Over sysfs, which doesn't timestamp edges, it falls back to the time
+  /// the wait returned on the system clock.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  ///
+  /// let mut input = GPIO::new(GPIO_P8_11);
+  /// input.set_export(DeviceState::Exported).unwrap();
+  /// input.set_direction(PinDirection::In).unwrap();
+  /// input.set_edge(Edge::Both).unwrap();
+  ///
+  /// let (_, rise) = input.wait_for_edge_timestamped(None).unwrap().unwrap();
+  /// let (_, fall) = input.wait_for_edge_timestamped(None).unwrap().unwrap();
+  /// println!("High for {:?}", fall - rise);
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if edge detection isn't enabled with `set_edge()`, or the pin
+  /// can't be read anymore, e.g. because it was unexported.
+  pub fn wait_for_edge_timestamped(&mut self, timeout: Option<Duration>) -> Result<Option<(PinState, Duration)>> {
+    let timeout_ms = match timeout {
+      // Round up, so the wait doesn't end before the timeout.
+      Some(timeout) => timeout.as_nanos().div_ceil(1_000_000).min(i32::MAX as u128) as i32,
+      None => -1,
+    };
+    let pin_num = self.pin_num;
+    if let Line::Events(ref mut events) = *self.line.get_mut().unwrap_or_else(|e| e.into_inner()) {
+      let event = events.wait(timeout_ms)
+                        .chain_err(|| format!("Failed to wait for an edge on GPIO pin #{}", pin_num))?;
+      if let Some(event) = event {
+        journal::record(Entry::GpioEdge {
+                          pin: pin_num,
+                          state: event.state,
+                        });
+      }
+      return Ok(event.map(|event| (event.state, event.timestamp)));
+    }
+    let waiter = match self.waiter {
+      Some(ref mut waiter) => waiter,
+      None => bail!(format!("Edge detection isn't enabled on GPIO pin #{}, see set_edge()", &self.pin_num)),
+    };
+    Ok(waiter.wait(timeout_ms)?
+             .map(|high| (if high { PinState::High } else { PinState::Low }, clock::system().now())))
+  }
+
+  /// Calls `callback` on the shared reactor's thread for every edge `edge`
+  /// on the input, until the returned subscription is dropped.
+  ///
+  /// Unlike `wait_for_edge()`, this doesn't block, and any number of pins
+  /// and callbacks can be watched at once; see the `reactor` module.
+  /// Don't use both on the same pin, they'd take each other's edges.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  /// use std::sync::atomic::{AtomicUsize, Ordering};
+  /// use std::sync::Arc;
+  ///
+  /// let button = GPIO::new(GPIO_P8_11);
+  /// button.set_export(DeviceState::Exported).unwrap();
+  /// button.set_direction(PinDirection::In).unwrap();
+  ///
+  /// let presses = Arc::new(AtomicUsize::new(0));
+  /// let counted = presses.clone();
+  /// let _subscription = button.on_edge(Edge::Falling, move |_| {
+  ///   let _ = counted.fetch_add(1, Ordering::SeqCst);
+  /// }).unwrap();
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if `edge` is `Edge::None`, if the pin isn't an exported input
+  /// supporting edge interrupts or on the character device backend, or if
+  /// the reactor can't be started.
+  pub fn on_edge<F>(&self, edge: Edge, callback: F) -> Result<Subscription>
+    where F: FnMut(EdgeEvent) + Send + 'static
+  {
+    self.check_reactor_backend()?;
+    reactor::shared()?.on_edge(self.pin_num, edge, callback)
+  }
+
+  /// Sends the edges `edge` on the input on a channel, until the returned
+  /// subscription is dropped; the events' timestamps tell the widths of
+  /// pulses.
+  ///
+  /// # Errors
+  ///
+  /// See `on_edge()`.
+  pub fn edge_events(&self, edge: Edge) -> Result<(Subscription, Receiver<EdgeEvent>)> {
+    self.check_reactor_backend()?;
+    reactor::shared()?.edge_events(self.pin_num, edge)
+  }
+
+  /// Fails unless the pin is on the sysfs backend, whose edge files the
+  /// reactor waits on.
+  fn check_reactor_backend(&self) -> Result<()> {
+    if self.backend == GpioBackend::Cdev {
+      bail!(format!("GPIO pin #{} is a character device line, use wait_for_edge() to wait for its edges",
+                    &self.pin_num));
+    }
+    Ok(())
+  }
+
+  fn line(&self) -> MutexGuard<'_, Line> {
+    self.line.lock().unwrap_or_else(|e| e.into_inner())
+  }
+
+  /// Releases the line of the pin and requests it anew with `request`.
+  fn request_line<F: FnOnce(&Chip, u32) -> Result<Line>>(&self, request: F) -> Result<()> {
+    let mut line = self.line();
+    // The kernel doesn't let the line be requested while it's held.
+    *line = Line::Released;
+    let mut chip = Chip::open(&self.chip_path)?;
+    if let Some(ref consumer) = self.consumer {
+      chip.set_consumer(consumer);
+    }
+    *line = request(&chip, self.line_offset)?;
+    Ok(())
+  }
+
+  fn get_line(&self) -> Result<PinState> {
+    match *self.line() {
+      Line::Handle(ref handle) => handle.get(),
+      Line::Events(ref events) => events.get(),
+      Line::Released => bail!(self.released_error()),
+    }
+  }
+
+  fn set_line(&self, state: PinState) -> Result<()> {
+    match *self.line() {
+      Line::Handle(ref handle) => handle.set(state),
+      Line::Events(_) => bail!(format!("GPIO pin #{} is an input, it reports edges", &self.pin_num)),
+      Line::Released => bail!(self.released_error()),
+    }
+  }
+
+  fn released_error(&self) -> String {
+    format!("Line {} of {} isn't requested, see set_direction()", self.line_offset, self.chip_path)
+  }
+}
+
+/// Exports the GPIO on `pin` and makes it an output driving `state` in one
+/// go, so it doesn't glitch to low on the way.
+pub(crate) fn output_driving(pin: Pin, state: PinState) -> Result<GPIO> {
+  configure_output(GPIO::new(pin), state)
+}
+
+/// Exports `gpio` and makes it an output driving `state`, see
+/// `output_driving()`.
+pub(crate) fn configure_output(gpio: GPIO, state: PinState) -> Result<GPIO> {
+  gpio.set_export(DeviceState::Exported)?;
+  gpio.cache_state(Some(state));
+  if gpio.backend == GpioBackend::Cdev {
+    let pull = gpio.pull();
+    gpio.request_line(|chip, offset| chip.request_output(offset, state, pull).map(Line::Handle))
+        .chain_err(|| format!("Failed to set GPIO pin #{} direction", &gpio.pin_num))?;
+    gpio.cache_direction(Some(PinDirection::Out));
+    return Ok(gpio);
+  }
+  let path = format!("{}/direction", gpio.pin_path.display());
+  let value = match state {
+    PinState::High => "high",
+    PinState::Low => "low",
+  };
+  gpio.counters
+      .write(|| path.write_file(value))
+      .chain_err(|| format!("Failed to set GPIO pin #{} direction", &gpio.pin_num))?;
+  gpio.cache_direction(Some(PinDirection::Out));
+  Ok(gpio)
+}
+
+/// Exports and configures a GPIO in one go, without the pin driving or
+/// floating at a level it wasn't configured for on the way.
+///
+/// # Examples
+///
+/// ```no_run
+/// use libbeaglebone::prelude::*;
+/// use libbeaglebone::gpio::GPIOBuilder;
+///
+/// // An active-low enable, which must never be driven low by accident.
+/// let enable = GPIOBuilder::new(GPIO_P8_12)
+///   .with_initial_state(PinState::High)
+///   .build()
+///   .unwrap();
+///
+/// // A button input with its pull-up on before the pin is an input.
+/// let button = GPIOBuilder::new(GPIO_P8_11)
+///   .with_direction(PinDirection::In)
+///   .with_pull(Pull::Up)
+///   .with_edge(Edge::Falling)
+///   .build()
+///   .unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct GPIOBuilder {
+  pin_num: u8,
+  backend: GpioBackend,
+  direction: Option<PinDirection>,
+  initial_state: Option<PinState>,
+  pull: Option<Pull>,
+  edge: Option<Edge>,
+  consumer: Option<String>,
+}
+
+impl GPIOBuilder {
+  /// Creates a builder for the GPIO on `pin`, on the default backend, which
+  /// only exports it by default.
+  pub fn new(pin: Pin) -> GPIOBuilder {
+    GPIOBuilder::from_num(pin as u8)
+  }
+
+  /// Creates a builder for the GPIO with the kernel's number `pin_num`, see
+  /// `GPIO::from_num()`.
+  pub fn from_num(pin_num: u8) -> GPIOBuilder {
+    GPIOBuilder {
+      pin_num,
+      backend: GpioBackend::default(),
+      direction: None,
+      initial_state: None,
+      pull: None,
+      edge: None,
+      consumer: None,
+    }
+  }
+
+  /// Talks to the kernel through `backend`.
+  pub fn with_backend(mut self, backend: GpioBackend) -> GPIOBuilder {
+    self.backend = backend;
+    self
+  }
+
+  /// Makes the pin an input or an output, the latter driving low unless
+  /// `with_initial_state()` is given.
+  pub fn with_direction(mut self, direction: PinDirection) -> GPIOBuilder {
+    self.direction = Some(direction);
+    self
+  }
+
+  /// Makes the pin an output driving `state` from the moment it's an
+  /// output.
+  pub fn with_initial_state(mut self, state: PinState) -> GPIOBuilder {
+    self.direction = Some(PinDirection::Out);
+    self.initial_state = Some(state);
+    self
+  }
+
+  /// Sets the pull resistor before the direction, see `GPIO::set_pull()`.
+  pub fn with_pull(mut self, pull: Pull) -> GPIOBuilder {
+    self.pull = Some(pull);
+    self
+  }
+
+  /// Makes the pin an input reporting the edges `edge`, see
+  /// `GPIO::set_edge()`.
+  pub fn with_edge(mut self, edge: Edge) -> GPIOBuilder {
+    self.direction = Some(PinDirection::In);
+    self.edge = Some(edge);
+    self
+  }
+
+  /// Labels the pin's line with `consumer`, see `GPIO::set_consumer()`.
+  pub fn with_consumer(mut self, consumer: &str) -> GPIOBuilder {
+    self.consumer = Some(consumer.to_string());
+    self
+  }
+
+  /// Exports and configures the GPIO.
+  ///
+  /// The pull is set first, and an output is made one together with its
+  /// initial state: by writing "high" or "low" as the sysfs direction, or by
+  /// requesting the line with its value from the character device.
+  ///
+  /// # Errors
+  ///
+  /// Fails if both an initial state and edges are given, or if the pin
+  /// can't be exported or configured.
+  pub fn build(self) -> Result<GPIO> {
+    if self.initial_state.is_some() && self.edge.is_some() {
+      bail!(format!("GPIO pin #{} can't be an output with edges", self.pin_num));
+    }
+    let mut gpio = GPIO::from_num_with_backend(self.pin_num, self.backend);
+    if let Some(ref consumer) = self.consumer {
+      gpio.set_consumer(consumer);
+    }
+    if let Some(pull) = self.pull {
+      gpio.set_pull(pull)?;
+    }
+    match self.direction {
+      Some(PinDirection::Out) => {
+        return configure_output(gpio, self.initial_state.unwrap_or(PinState::Low));
+      }
+      Some(PinDirection::In) => {
+        gpio.set_export(DeviceState::Exported)?;
+        gpio.set_direction(PinDirection::In)?;
+        if let Some(edge) = self.edge {
+          gpio.set_edge(edge)?;
+        }
+      }
+      None => gpio.set_export(DeviceState::Exported)?,
+    }
+    Ok(gpio)
+  }
 }
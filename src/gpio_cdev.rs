@@ -0,0 +1,584 @@
+//! The GPIO character device module.
+//!
+//! The sysfs GPIO interface is deprecated, newer kernels expose GPIOs
+//! through character devices instead, one `/dev/gpiochipN` per GPIO
+//! controller.
+//! Lines on a chip are requested as inputs, outputs or event sources, and
+//! stay claimed by the process until the returned handle is dropped, so
+//! two programs can't drive the same pin by accident.
+//!
+//! A `GPIO` made with `GpioBackend::Cdev` uses this module behind its
+//! usual API, which is the easiest way to use it:
+//!
+//! ```no_run
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::gpio::GpioBackend;
+//!
+//! let mut led = GPIO::with_backend(GPIO_P8_11, GpioBackend::Cdev);
+//! led.set_export(DeviceState::Exported).unwrap();
+//! led.set_direction(PinDirection::Out).unwrap();
+//! led.write(PinState::High).unwrap();
+//! ```
+//!
+//! The chips and lines can be used directly as well, e.g. to find out which
+//! process holds a line:
+//!
+//! ```no_run
+//! use libbeaglebone::gpio_cdev::Chip;
+//!
+//! let chip = Chip::open("/dev/gpiochip1").unwrap();
+//! for (offset, line) in chip.lines().unwrap().iter().enumerate() {
+//!   if line.used {
+//!     println!("line {} is used by {:?}", offset, line.consumer);
+//!   }
+//! }
+//! ```
+//!
+//! Requested lines show the program's name as their consumer, as listed by
+//! `gpioinfo`, unless a chip or `GPIO` is given another label with
+//! `set_consumer()`.
+//!
+//! This uses version 1 of the kernel's GPIO ABI, which every kernel since
+//! 4.8 supports; requesting a line with a pull resistor needs 5.5 or later.
+
+use errors::*;
+use gpio::{Edge, PinState, Pull};
+use nix::libc::{c_char, c_int};
+use nix::poll::{EventFlags, POLLIN, PollFd, poll};
+use std::env;
+use std::ffi::CStr;
+use std::fs::{File, OpenOptions};
+use std::io::Read;
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::Path;
+use std::time::Duration;
+
+/// The consumer label if the program's name is unknown.
+const DEFAULT_CONSUMER: &str = "libbeaglebone";
+
+const GPIO_IOC_MAGIC: u8 = 0xB4;
+
+const GPIOLINE_FLAG_KERNEL: u32 = 1 << 0;
+const GPIOLINE_FLAG_IS_OUT: u32 = 1 << 1;
+const GPIOLINE_FLAG_ACTIVE_LOW: u32 = 1 << 2;
+const GPIOLINE_FLAG_BIAS_PULL_UP: u32 = 1 << 5;
+const GPIOLINE_FLAG_BIAS_PULL_DOWN: u32 = 1 << 6;
+const GPIOLINE_FLAG_BIAS_DISABLE: u32 = 1 << 7;
+
+const GPIOHANDLE_REQUEST_INPUT: u32 = 1 << 0;
+const GPIOHANDLE_REQUEST_OUTPUT: u32 = 1 << 1;
+const GPIOHANDLE_REQUEST_BIAS_PULL_UP: u32 = 1 << 5;
+const GPIOHANDLE_REQUEST_BIAS_PULL_DOWN: u32 = 1 << 6;
+const GPIOHANDLE_REQUEST_BIAS_DISABLE: u32 = 1 << 7;
+
+const GPIOEVENT_REQUEST_RISING_EDGE: u32 = 1 << 0;
+const GPIOEVENT_REQUEST_FALLING_EDGE: u32 = 1 << 1;
+
+const GPIOEVENT_EVENT_RISING_EDGE: u32 = 0x01;
+
+/// The size of a `struct gpioevent_data`: a u64 timestamp and a u32 event
+/// id, padded to the alignment of the timestamp.
+const GPIOEVENT_DATA_SIZE: usize = 16;
+
+#[derive(Debug)]
+#[repr(C)]
+struct GpioChipInfo {
+  name: [c_char; 32],
+  label: [c_char; 32],
+  lines: u32,
+}
+
+#[derive(Debug)]
+#[repr(C)]
+struct GpioLineInfo {
+  line_offset: u32,
+  flags: u32,
+  name: [c_char; 32],
+  consumer: [c_char; 32],
+}
+
+#[derive(Debug)]
+#[repr(C)]
+struct GpioHandleRequest {
+  line_offsets: [u32; 64],
+  flags: u32,
+  default_values: [u8; 64],
+  consumer_label: [c_char; 32],
+  lines: u32,
+  fd: c_int,
+}
+
+#[derive(Debug)]
+#[repr(C)]
+struct GpioHandleData {
+  values: [u8; 64],
+}
+
+#[derive(Debug)]
+#[repr(C)]
+struct GpioEventRequest {
+  line_offset: u32,
+  handle_flags: u32,
+  event_flags: u32,
+  consumer_label: [c_char; 32],
+  fd: c_int,
+}
+
+ioctl!(read gpio_get_chipinfo with GPIO_IOC_MAGIC, 0x01; GpioChipInfo);
+ioctl!(readwrite gpio_get_lineinfo with GPIO_IOC_MAGIC, 0x02; GpioLineInfo);
+ioctl!(readwrite gpio_get_linehandle with GPIO_IOC_MAGIC, 0x03; GpioHandleRequest);
+ioctl!(readwrite gpio_get_lineevent with GPIO_IOC_MAGIC, 0x04; GpioEventRequest);
+ioctl!(readwrite gpiohandle_get_line_values with GPIO_IOC_MAGIC, 0x08; GpioHandleData);
+ioctl!(readwrite gpiohandle_set_line_values with GPIO_IOC_MAGIC, 0x09; GpioHandleData);
+
+/// What a GPIO chip tells about itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChipInfo {
+  /// The kernel's name of the chip, e.g. "gpiochip1".
+  pub name: String,
+  /// The label of the chip, e.g. "gpio-32-63".
+  pub label: String,
+  /// The number of lines of the chip.
+  pub lines: u32,
+}
+
+/// What a GPIO chip tells about one of its lines.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineInfo {
+  /// The name of the line, if the device tree gives it one.
+  pub name: Option<String>,
+  /// The label of whoever holds the line, if anyone.
+  pub consumer: Option<String>,
+  /// Whether the line is held, by the kernel or a process.
+  pub used: bool,
+  /// Whether the line is an output.
+  pub output: bool,
+  /// Whether the line is active-low.
+  pub active_low: bool,
+  /// The line's pull resistor, or `None` if the kernel doesn't tell, e.g.
+  /// because it's older than 5.5.
+  pub pull: Option<Pull>,
+}
+
+/// An edge reported by the kernel on a line requested for events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineEvent {
+  /// The state of the line right after the edge, i.e. `High` for a rising
+  /// edge.
+  pub state: PinState,
+  /// When the kernel saw the edge, taken in its interrupt handler, on the
+  /// realtime or monotonic clock depending on the kernel version.
+  pub timestamp: Duration,
+}
+
+/// A GPIO chip, e.g. `/dev/gpiochip0`.
+#[derive(Debug)]
+pub struct Chip {
+  path: String,
+  file: File,
+  consumer: String,
+}
+
+impl Chip {
+  /// Opens the chip at `path`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the chip doesn't exist or can't be opened.
+  pub fn open(path: &str) -> Result<Chip> {
+    let file = OpenOptions::new().read(true)
+                                 .write(true)
+                                 .open(path)
+                                 .chain_err(|| format!("Failed to open GPIO chip {}", path))?;
+    Ok(Chip {
+      path: path.to_string(),
+      file,
+      consumer: default_consumer(),
+    })
+  }
+
+  /// Returns the path of the chip.
+  pub fn path(&self) -> &str {
+    &self.path
+  }
+
+  /// Labels the lines requested from now on as held by `consumer` instead of
+  /// the program's name; the kernel keeps the first 31 bytes.
+  pub fn set_consumer(&mut self, consumer: &str) {
+    self.consumer = consumer.to_string();
+  }
+
+  /// Returns the label that requested lines show as their consumer.
+  pub fn consumer(&self) -> &str {
+    &self.consumer
+  }
+
+  /// Asks the chip about itself.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the device isn't a GPIO chip.
+  pub fn info(&self) -> Result<ChipInfo> {
+    let mut info: GpioChipInfo = unsafe { mem::zeroed() };
+    let _ = unsafe { gpio_get_chipinfo(self.file.as_raw_fd(), &mut info) }
+      .chain_err(|| format!("Failed to get the info of GPIO chip {}", self.path))?;
+    Ok(ChipInfo {
+      name: c_string(&info.name).unwrap_or_default(),
+      label: c_string(&info.label).unwrap_or_default(),
+      lines: info.lines,
+    })
+  }
+
+  /// Asks the chip about the line `offset`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the chip has no such line.
+  pub fn line_info(&self, offset: u32) -> Result<LineInfo> {
+    let mut info: GpioLineInfo = unsafe { mem::zeroed() };
+    info.line_offset = offset;
+    let _ = unsafe { gpio_get_lineinfo(self.file.as_raw_fd(), &mut info) }
+      .chain_err(|| format!("Failed to get the info of line {} of GPIO chip {}", offset, self.path))?;
+    Ok(LineInfo {
+      name: c_string(&info.name),
+      consumer: c_string(&info.consumer),
+      used: info.flags & GPIOLINE_FLAG_KERNEL != 0,
+      output: info.flags & GPIOLINE_FLAG_IS_OUT != 0,
+      active_low: info.flags & GPIOLINE_FLAG_ACTIVE_LOW != 0,
+      pull: if info.flags & GPIOLINE_FLAG_BIAS_PULL_UP != 0 {
+        Some(Pull::Up)
+      } else if info.flags & GPIOLINE_FLAG_BIAS_PULL_DOWN != 0 {
+        Some(Pull::Down)
+      } else if info.flags & GPIOLINE_FLAG_BIAS_DISABLE != 0 {
+        Some(Pull::None)
+      } else {
+        None
+      },
+    })
+  }
+
+  /// Asks the chip about all of its lines, in the order of their offsets.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the device isn't a GPIO chip.
+  pub fn lines(&self) -> Result<Vec<LineInfo>> {
+    (0..self.info()?.lines).map(|offset| self.line_info(offset)).collect()
+  }
+
+  /// Requests the line `offset` as an input, with the pull resistor
+  /// `pull`, or leaving it as is for `None`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the chip has no such line, or it's held already.
+  pub fn request_input(&self, offset: u32, pull: Option<Pull>) -> Result<LineHandle> {
+    self.request_handle(offset, GPIOHANDLE_REQUEST_INPUT | bias_flags(pull), PinState::Low)
+  }
+
+  /// Requests the line `offset` as an output, driving `state` right away,
+  /// with the pull resistor `pull`, or leaving it as is for `None`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the chip has no such line, or it's held already.
+  pub fn request_output(&self, offset: u32, state: PinState, pull: Option<Pull>) -> Result<LineHandle> {
+    self.request_handle(offset, GPIOHANDLE_REQUEST_OUTPUT | bias_flags(pull), state)
+  }
+
+  /// Requests the lines `offsets` as inputs in one go, with the pull
+  /// resistor `pull`, or leaving them as they are for `None`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if there are more than 64 lines, if the chip lacks one of them,
+  /// or if one of them is held already.
+  pub fn request_inputs(&self, offsets: &[u32], pull: Option<Pull>) -> Result<LineGroup> {
+    let states = vec![PinState::Low; offsets.len()];
+    self.request_group(offsets, GPIOHANDLE_REQUEST_INPUT | bias_flags(pull), &states)
+  }
+
+  /// Requests the lines `offsets` as outputs in one go, each driving its
+  /// state of `states` right away.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the number of states doesn't match, if there are more than
+  /// 64 lines, if the chip lacks one of them, or if one of them is held
+  /// already.
+  pub fn request_outputs(&self, offsets: &[u32], states: &[PinState], pull: Option<Pull>) -> Result<LineGroup> {
+    if states.len() != offsets.len() {
+      bail!(format!("Requesting {} lines of GPIO chip {} with {} states", offsets.len(), self.path, states.len()));
+    }
+    self.request_group(offsets, GPIOHANDLE_REQUEST_OUTPUT | bias_flags(pull), states)
+  }
+
+  fn request_handle(&self, offset: u32, flags: u32, state: PinState) -> Result<LineHandle> {
+    let file = self.request_lines(&[offset], flags, &[state])
+                   .chain_err(|| format!("Failed to request line {} of GPIO chip {}", offset, self.path))?;
+    Ok(LineHandle { offset, file })
+  }
+
+  fn request_group(&self, offsets: &[u32], flags: u32, states: &[PinState]) -> Result<LineGroup> {
+    let file = self.request_lines(offsets, flags, states)
+                   .chain_err(|| format!("Failed to request lines {:?} of GPIO chip {}", offsets, self.path))?;
+    Ok(LineGroup {
+      offsets: offsets.to_vec(),
+      file,
+    })
+  }
+
+  fn request_lines(&self, offsets: &[u32], flags: u32, states: &[PinState]) -> Result<File> {
+    let mut request: GpioHandleRequest = unsafe { mem::zeroed() };
+    if offsets.is_empty() || offsets.len() > request.line_offsets.len() {
+      bail!(format!("A line request takes 1 to {} lines, not {}", request.line_offsets.len(), offsets.len()));
+    }
+    request.line_offsets[..offsets.len()].copy_from_slice(offsets);
+    for (value, &state) in request.default_values.iter_mut().zip(states) {
+      *value = u8::from(state == PinState::High);
+    }
+    request.flags = flags;
+    set_label(&mut request.consumer_label, &self.consumer);
+    request.lines = offsets.len() as u32;
+    let _ = unsafe { gpio_get_linehandle(self.file.as_raw_fd(), &mut request) }
+      .chain_err(|| "The chip refused the line request")?;
+    Ok(unsafe { File::from_raw_fd(request.fd) })
+  }
+
+  /// Requests the line `offset` as an input reporting the edges `edge`,
+  /// with the pull resistor `pull`, or leaving it as is for `None`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if `edge` is `Edge::None`, if the chip has no such line, or if
+  /// it's held already or can't report edges.
+  pub fn request_events(&self, offset: u32, edge: Edge, pull: Option<Pull>) -> Result<LineEvents> {
+    let event_flags = match edge {
+      Edge::None => bail!("Requesting a line for Edge::None never reports any events"),
+      Edge::Rising => GPIOEVENT_REQUEST_RISING_EDGE,
+      Edge::Falling => GPIOEVENT_REQUEST_FALLING_EDGE,
+      Edge::Both => GPIOEVENT_REQUEST_RISING_EDGE | GPIOEVENT_REQUEST_FALLING_EDGE,
+    };
+    let mut request: GpioEventRequest = unsafe { mem::zeroed() };
+    request.line_offset = offset;
+    request.handle_flags = GPIOHANDLE_REQUEST_INPUT | bias_flags(pull);
+    request.event_flags = event_flags;
+    set_label(&mut request.consumer_label, &self.consumer);
+    let _ = unsafe { gpio_get_lineevent(self.file.as_raw_fd(), &mut request) }
+      .chain_err(|| format!("Failed to request events of line {} of GPIO chip {}", offset, self.path))?;
+    Ok(LineEvents {
+      offset,
+      edge,
+      file: unsafe { File::from_raw_fd(request.fd) },
+    })
+  }
+}
+
+/// A requested input or output line, released when dropped.
+#[derive(Debug)]
+pub struct LineHandle {
+  offset: u32,
+  file: File,
+}
+
+impl LineHandle {
+  /// Returns the offset of the line on its chip.
+  pub fn offset(&self) -> u32 {
+    self.offset
+  }
+
+  /// Reads the state of the line.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the chip is gone.
+  pub fn get(&self) -> Result<PinState> {
+    get_value(self.file.as_raw_fd(), self.offset)
+  }
+
+  /// Drives an output line to `state`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the line was requested as an input, or if the chip is gone.
+  pub fn set(&self, state: PinState) -> Result<()> {
+    let mut data = GpioHandleData { values: [0; 64] };
+    data.values[0] = u8::from(state == PinState::High);
+    let _ = unsafe { gpiohandle_set_line_values(self.file.as_raw_fd(), &mut data) }
+      .chain_err(|| format!("Failed to set GPIO line {} to {:?}", self.offset, state))?;
+    Ok(())
+  }
+}
+
+/// Several lines of a chip requested together, which are read and written
+/// in a single call, released when dropped.
+#[derive(Debug)]
+pub struct LineGroup {
+  offsets: Vec<u32>,
+  file: File,
+}
+
+impl LineGroup {
+  /// Returns the offsets of the lines on their chip, in the order of their
+  /// states.
+  pub fn offsets(&self) -> &[u32] {
+    &self.offsets
+  }
+
+  /// Reads the states of all lines at once.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the chip is gone.
+  pub fn get(&self) -> Result<Vec<PinState>> {
+    let mut data = GpioHandleData { values: [0; 64] };
+    let _ = unsafe { gpiohandle_get_line_values(self.file.as_raw_fd(), &mut data) }
+      .chain_err(|| format!("Failed to read GPIO lines {:?}", self.offsets))?;
+    Ok(data.values[..self.offsets.len()]
+         .iter()
+         .map(|&value| if value != 0 { PinState::High } else { PinState::Low })
+         .collect())
+  }
+
+  /// Drives all output lines at once, each to its state of `states`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the number of states doesn't match, if the lines were
+  /// requested as inputs, or if the chip is gone.
+  pub fn set(&self, states: &[PinState]) -> Result<()> {
+    if states.len() != self.offsets.len() {
+      bail!(format!("Setting {} GPIO lines to {} states", self.offsets.len(), states.len()));
+    }
+    let mut data = GpioHandleData { values: [0; 64] };
+    for (value, &state) in data.values.iter_mut().zip(states) {
+      *value = u8::from(state == PinState::High);
+    }
+    let _ = unsafe { gpiohandle_set_line_values(self.file.as_raw_fd(), &mut data) }
+      .chain_err(|| format!("Failed to set GPIO lines {:?}", self.offsets))?;
+    Ok(())
+  }
+}
+
+/// An input line requested for edge events, released when dropped.
+///
+/// The kernel queues the edges with their timestamps, so none are lost
+/// while the process isn't waiting.
+#[derive(Debug)]
+pub struct LineEvents {
+  offset: u32,
+  edge: Edge,
+  file: File,
+}
+
+impl LineEvents {
+  /// Returns the offset of the line on its chip.
+  pub fn offset(&self) -> u32 {
+    self.offset
+  }
+
+  /// Returns the edges the line reports.
+  pub fn edge(&self) -> Edge {
+    self.edge
+  }
+
+  /// Reads the state of the line.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the chip is gone.
+  pub fn get(&self) -> Result<PinState> {
+    get_value(self.file.as_raw_fd(), self.offset)
+  }
+
+  /// Waits up to `timeout_ms` milliseconds for an edge, or forever if
+  /// negative.
+  ///
+  /// Returns the edge, or `None` if the timeout expired.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the events can't be read, e.g. because the chip is gone.
+  pub fn wait(&mut self, timeout_ms: i32) -> Result<Option<LineEvent>> {
+    let mut fds = [PollFd::new(self.file.as_raw_fd(), POLLIN, EventFlags::empty())];
+    let ready = poll(&mut fds, timeout_ms)
+      .chain_err(|| format!("Failed to wait for an edge on GPIO line {}", self.offset))?;
+    if ready == 0 {
+      return Ok(None);
+    }
+    self.read_event().map(Some)
+  }
+
+  /// Reads the next queued edge, blocking until there is one.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the events can't be read, e.g. because the chip is gone.
+  pub fn read_event(&mut self) -> Result<LineEvent> {
+    let mut data = [0; GPIOEVENT_DATA_SIZE];
+    self.file
+        .read_exact(&mut data)
+        .chain_err(|| format!("Failed to read an edge of GPIO line {}", self.offset))?;
+    let mut timestamp = [0; 8];
+    let mut id = [0; 4];
+    timestamp.copy_from_slice(&data[..8]);
+    id.copy_from_slice(&data[8..12]);
+    Ok(LineEvent {
+      state: if u32::from_ne_bytes(id) == GPIOEVENT_EVENT_RISING_EDGE {
+        PinState::High
+      } else {
+        PinState::Low
+      },
+      timestamp: Duration::from_nanos(u64::from_ne_bytes(timestamp)),
+    })
+  }
+}
+
+impl AsRawFd for LineEvents {
+  fn as_raw_fd(&self) -> RawFd {
+    self.file.as_raw_fd()
+  }
+}
+
+fn get_value(fd: RawFd, offset: u32) -> Result<PinState> {
+  let mut data = GpioHandleData { values: [0; 64] };
+  let _ = unsafe { gpiohandle_get_line_values(fd, &mut data) }
+    .chain_err(|| format!("Failed to read GPIO line {}", offset))?;
+  Ok(if data.values[0] != 0 { PinState::High } else { PinState::Low })
+}
+
+/// Returns the request flags of the pull resistor `pull`.
+fn bias_flags(pull: Option<Pull>) -> u32 {
+  match pull {
+    Some(Pull::Up) => GPIOHANDLE_REQUEST_BIAS_PULL_UP,
+    Some(Pull::Down) => GPIOHANDLE_REQUEST_BIAS_PULL_DOWN,
+    Some(Pull::None) => GPIOHANDLE_REQUEST_BIAS_DISABLE,
+    None => 0,
+  }
+}
+
+/// Returns the NUL-terminated string in `chars`, or `None` if it's empty.
+fn c_string(chars: &[c_char; 32]) -> Option<String> {
+  // c_char is i8 or u8 depending on the architecture.
+  let bytes: Vec<u8> = chars.iter().map(|c| c.to_ne_bytes()[0]).collect();
+  let string = CStr::from_bytes_until_nul(&bytes).ok()?.to_string_lossy().into_owned();
+  if string.is_empty() { None } else { Some(string) }
+}
+
+/// Returns the program's name, the default consumer label.
+pub fn default_consumer() -> String {
+  env::args().next()
+             .as_ref()
+             .and_then(|program| Path::new(program).file_name())
+             .map(|name| name.to_string_lossy().into_owned())
+             .filter(|name| !name.is_empty())
+             .unwrap_or_else(|| DEFAULT_CONSUMER.to_string())
+}
+
+/// Copies `consumer` into `label`, cut to leave room for the NUL.
+fn set_label(label: &mut [c_char; 32], consumer: &str) {
+  for (c, &byte) in label.iter_mut().take(31).zip(consumer.as_bytes()) {
+    *c = c_char::from_ne_bytes([byte]);
+  }
+}
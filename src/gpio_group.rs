@@ -0,0 +1,308 @@
+//! The GPIO group module.
+//!
+//! The data lines of a parallel LCD or DAC have to change together; written
+//! one after another, the device sees every intermediate value.
+//! A `GPIOGroup` reads and writes up to 32 pins as the bits of a value, in
+//! as few operations as the backend allows:
+//!
+//! ```no_run
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::gpio_group::GPIOGroup;
+//!
+//! // An 8-bit DAC on GPIO bank 2, the first pin carrying the lowest bit.
+//! let pins = vec![GPIO_P8_45, GPIO_P8_46, GPIO_P8_43, GPIO_P8_44,
+//!                 GPIO_P8_41, GPIO_P8_42, GPIO_P8_39, GPIO_P8_40];
+//! let mut dac = GPIOGroup::mapped(pins, PinDirection::Out).unwrap();
+//! for value in 0..256 {
+//!   dac.write(value).unwrap();
+//! }
+//! ```
+//!
+//! How the pins change depends on the backend:
+//!
+//! * `mapped()` writes the data register of each GPIO bank once, so all
+//!   pins of a bank change at the same instant. It needs root privileges,
+//!   and races with the kernel writing other pins of the same bank.
+//! * The character device backend requests all lines of a GPIO chip
+//!   together, and sets them with one call, which the kernel applies to
+//!   the chip's pins at once.
+//! * The sysfs backend has no bulk access: the pins that change are written
+//!   one after another.
+//!
+//! Pins on different banks or chips are written one bank or chip after
+//! another, so put pins that have to change together on the same one.
+
+use board;
+use enums::DeviceState;
+use errors::*;
+use fast_gpio::{self, GPIO_DATAIN, GPIO_DATAOUT};
+use gpio::{self, GPIO, GpioBackend, PinDirection, PinState};
+use gpio_cdev::{Chip, LineGroup};
+use mmio::MemoryMap;
+use pins::Pin;
+
+/// The pins of one GPIO bank, mapped.
+#[derive(Debug)]
+struct Bank {
+  regs: MemoryMap,
+  // The bit of the group value and the bank register mask of each pin.
+  pins: Vec<(usize, u32)>,
+  mask: u32,
+}
+
+/// The lines of one GPIO chip, requested together.
+#[derive(Debug)]
+struct ChipLines {
+  lines: LineGroup,
+  // The bit of the group value of each line.
+  bits: Vec<usize>,
+}
+
+#[derive(Debug)]
+enum Access {
+  Sysfs(Vec<GPIO>),
+  Cdev(Vec<ChipLines>),
+  Mapped(Vec<Bank>),
+}
+
+/// Up to 32 GPIOs read and written together as the bits of a value.
+#[derive(Debug)]
+pub struct GPIOGroup {
+  pin_nums: Vec<u8>,
+  direction: PinDirection,
+  access: Access,
+  // The value written last, if known.
+  value: Option<u32>,
+}
+
+impl GPIOGroup {
+  /// Creates a group of `pins` on the default backend, with `pins[0]`
+  /// carrying the lowest bit, made inputs or outputs driving low.
+  ///
+  /// # Errors
+  ///
+  /// Fails if there are no or more than 32 pins, if a pin is in the group
+  /// twice, or if the pins can't be configured.
+  pub fn new(pins: Vec<Pin>, direction: PinDirection) -> Result<GPIOGroup> {
+    GPIOGroup::with_backend(pins, direction, GpioBackend::default())
+  }
+
+  /// Creates a group of `pins` like `new()`, that talks to the kernel
+  /// through `backend`.
+  ///
+  /// # Errors
+  ///
+  /// See `new()`.
+  pub fn with_backend(pins: Vec<Pin>, direction: PinDirection, backend: GpioBackend) -> Result<GPIOGroup> {
+    let pin_nums = check_pins(&pins)?;
+    let access = match backend {
+      GpioBackend::Sysfs => Access::Sysfs(configure(&pins, direction)?),
+      GpioBackend::Cdev => Access::Cdev(request(&pin_nums, direction)?),
+    };
+    Ok(GPIOGroup {
+      pin_nums,
+      direction,
+      access,
+      value: None,
+    })
+  }
+
+  /// Creates a group of `pins` like `new()`, accessed through their GPIO
+  /// banks' registers, see the `fast_gpio` module.
+  ///
+  /// The pins are configured through sysfs first.
+  ///
+  /// # Errors
+  ///
+  /// Fails like `new()`, or if the registers can't be mapped, e.g. because
+  /// the process isn't running as root.
+  pub fn mapped(pins: Vec<Pin>, direction: PinDirection) -> Result<GPIOGroup> {
+    let pin_nums = check_pins(&pins)?;
+    let _ = configure(&pins, direction)?;
+    let mut banks: Vec<(u8, Bank)> = Vec::new();
+    for (bit, &pin_num) in pin_nums.iter().enumerate() {
+      let bank_num = pin_num / 32;
+      let mask = 1 << (pin_num % 32);
+      let index = match banks.iter().position(|&(num, _)| num == bank_num) {
+        Some(index) => index,
+        None => {
+          let regs = fast_gpio::map_bank(pin_num)?;
+          banks.push((bank_num,
+                      Bank {
+                        regs,
+                        pins: Vec::new(),
+                        mask: 0,
+                      }));
+          banks.len() - 1
+        }
+      };
+      let bank = &mut banks[index].1;
+      bank.pins.push((bit, mask));
+      bank.mask |= mask;
+    }
+    Ok(GPIOGroup {
+      pin_nums,
+      direction,
+      access: Access::Mapped(banks.into_iter().map(|(_, bank)| bank).collect()),
+      value: None,
+    })
+  }
+
+  /// Returns the number of pins.
+  pub fn width(&self) -> usize {
+    self.pin_nums.len()
+  }
+
+  /// Returns the kernel's numbers of the pins, lowest bit first.
+  pub fn pin_nums(&self) -> &[u8] {
+    &self.pin_nums
+  }
+
+  /// Returns whether the pins are inputs or outputs.
+  pub fn direction(&self) -> PinDirection {
+    self.direction
+  }
+
+  /// Drives pin `i` to bit `i` of `value`; the bits above the width are
+  /// ignored.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the pins are inputs, or if they can't be written.
+  pub fn write(&mut self, value: u32) -> Result<()> {
+    if self.direction == PinDirection::In {
+      bail!(format!("GPIO group {:?} are inputs", self.pin_nums));
+    }
+    let value = value & width_mask(self.width());
+    match self.access {
+      Access::Sysfs(ref mut pins) => {
+        let changed = self.value.map_or(u32::MAX, |last| last ^ value);
+        for (bit, pin) in pins.iter_mut().enumerate() {
+          if changed & (1 << bit) != 0 {
+            pin.write(state(value, bit))?;
+          }
+        }
+      }
+      Access::Cdev(ref chips) => {
+        for chip in chips {
+          let states: Vec<PinState> = chip.bits.iter().map(|&bit| state(value, bit)).collect();
+          chip.lines.set(&states)?;
+        }
+      }
+      Access::Mapped(ref banks) => {
+        for bank in banks {
+          let bits = bank.pins
+                         .iter()
+                         .filter(|&&(bit, _)| value & (1 << bit) != 0)
+                         .fold(0, |bits, &(_, mask)| bits | mask);
+          let out = bank.regs.read_u32(GPIO_DATAOUT);
+          bank.regs.write_u32(GPIO_DATAOUT, (out & !bank.mask) | bits);
+        }
+      }
+    }
+    self.value = Some(value);
+    Ok(())
+  }
+
+  /// Reads the pins, pin `i` into bit `i` of the returned value.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the pins can't be read.
+  pub fn read(&self) -> Result<u32> {
+    let mut value = 0;
+    match self.access {
+      Access::Sysfs(ref pins) => {
+        for (bit, pin) in pins.iter().enumerate() {
+          if pin.read()? == PinState::High {
+            value |= 1 << bit;
+          }
+        }
+      }
+      Access::Cdev(ref chips) => {
+        for chip in chips {
+          for (&bit, state) in chip.bits.iter().zip(chip.lines.get()?) {
+            if state == PinState::High {
+              value |= 1 << bit;
+            }
+          }
+        }
+      }
+      Access::Mapped(ref banks) => {
+        for bank in banks {
+          let levels = bank.regs.read_u32(GPIO_DATAIN);
+          for &(bit, mask) in &bank.pins {
+            if levels & mask != 0 {
+              value |= 1 << bit;
+            }
+          }
+        }
+      }
+    }
+    Ok(value)
+  }
+}
+
+/// Returns the kernel's numbers of `pins`, making sure they fit a group.
+fn check_pins(pins: &[Pin]) -> Result<Vec<u8>> {
+  if pins.is_empty() || pins.len() > 32 {
+    bail!(format!("A GPIO group has 1 to 32 pins, not {}", pins.len()));
+  }
+  let pin_nums: Vec<u8> = pins.iter().map(|&pin| pin as u8).collect();
+  for (i, pin_num) in pin_nums.iter().enumerate() {
+    if pin_nums[..i].contains(pin_num) {
+      bail!(format!("GPIO pin #{} is in the group twice", pin_num));
+    }
+  }
+  Ok(pin_nums)
+}
+
+/// Exports `pins` through sysfs and makes them inputs or outputs driving
+/// low.
+fn configure(pins: &[Pin], direction: PinDirection) -> Result<Vec<GPIO>> {
+  pins.iter()
+      .map(|&pin| {
+             let gpio = GPIO::with_backend(pin, GpioBackend::Sysfs);
+             if direction == PinDirection::Out {
+               return gpio::configure_output(gpio, PinState::Low);
+             }
+             gpio.set_export(DeviceState::Exported)?;
+             gpio.set_direction(PinDirection::In)?;
+             Ok(gpio)
+           })
+      .collect()
+}
+
+/// Requests the lines of the GPIOs `pin_nums`, together per chip.
+fn request(pin_nums: &[u8], direction: PinDirection) -> Result<Vec<ChipLines>> {
+  let board = board::current();
+  let mut chips: Vec<(String, Vec<u32>, Vec<usize>)> = Vec::new();
+  for (bit, &pin_num) in pin_nums.iter().enumerate() {
+    let (path, offset) = board.gpio_line(pin_num);
+    match chips.iter().position(|chip| chip.0 == path) {
+      Some(index) => {
+        chips[index].1.push(offset);
+        chips[index].2.push(bit);
+      }
+      None => chips.push((path, vec![offset], vec![bit])),
+    }
+  }
+  chips.into_iter()
+       .map(|(path, offsets, bits)| {
+              let chip = Chip::open(&path)?;
+              let lines = match direction {
+                PinDirection::In => chip.request_inputs(&offsets, None)?,
+                PinDirection::Out => chip.request_outputs(&offsets, &vec![PinState::Low; offsets.len()], None)?,
+              };
+              Ok(ChipLines { lines, bits })
+            })
+       .collect()
+}
+
+fn state(value: u32, bit: usize) -> PinState {
+  if value & (1 << bit) != 0 { PinState::High } else { PinState::Low }
+}
+
+fn width_mask(width: usize) -> u32 {
+  if width >= 32 { u32::MAX } else { (1 << width) - 1 }
+}
@@ -0,0 +1,163 @@
+//! The hardware abstraction module.
+//!
+//! Outputs don't have to be on-chip pins: a port expander or a PWM controller
+//! chip provides pins that are used the same way.
+//! The `DigitalPin` trait is implemented by the on-chip `GPIO`, by the pins
+//! of an `MCP23017` port expander and by the outputs of a chain of 74HC595
+//! shift registers, see `ShiftRegister::into_pins()`.
+//! The `PwmOutput` trait is implemented by the on-chip `PWM`, by `SoftPWM` and
+//! by the channels of a `PCA9685` PWM controller.
+//! Higher-level drivers like `Relay` and `Servo` accept any of them.
+//!
+//! Likewise, devices on an I2C bus can be reached directly, through a shared
+//! `I2cBusManager` or behind a multiplexer; device drivers like `INA219` are
+//! generic over the `I2cBus` trait to work with all of them.
+
+use errors::*;
+use gpio::{GPIO, PinState};
+use i2c::I2C;
+use i2c_bus::I2cBusManager;
+use i2c_mux::MuxChannel;
+use pwm::{PWM, PWMState};
+
+/// A digital pin whose state can be set and read.
+///
+/// Configuring the pin, e.g. setting its direction, is up to the type
+/// providing it.
+pub trait DigitalPin {
+  /// Drives the pin to `state`.
+  fn set_state(&mut self, state: PinState) -> Result<()>;
+
+  /// Reads the state of the pin.
+  fn state(&self) -> Result<PinState>;
+
+  /// Returns a name for the pin to use in messages, e.g. "GPIO pin #45".
+  fn pin_name(&self) -> String;
+
+  /// Drives the pin high.
+  fn set_high(&mut self) -> Result<()> {
+    self.set_state(PinState::High)
+  }
+
+  /// Drives the pin low.
+  fn set_low(&mut self) -> Result<()> {
+    self.set_state(PinState::Low)
+  }
+}
+
+/// A PWM output.
+pub trait PwmOutput {
+  /// Sets the period in nanoseconds.
+  ///
+  /// Outputs that share their period with other outputs, like the channels of
+  /// a PWM controller chip, may refuse to change it.
+  fn set_period_ns(&mut self, period_ns: u32) -> Result<()>;
+
+  /// Sets the duty cycle as a percentage of the period.
+  fn set_duty_cycle_percent(&mut self, percentage: f32) -> Result<()>;
+
+  /// Enables or disables the output.
+  fn set_enabled(&mut self, enabled: bool) -> Result<()>;
+}
+
+/// An I2C bus with devices at different addresses.
+pub trait I2cBus {
+  /// Writes `data` to the device at `address` in a single transaction.
+  fn write(&self, address: u16, data: &[u8]) -> Result<()>;
+
+  /// Reads from the device at `address` until `buf` is full.
+  fn read(&self, address: u16, buf: &mut [u8]) -> Result<()>;
+
+  /// Writes `data` to the device at `address` and then reads from it until
+  /// `buf` is full, e.g. to read a register.
+  fn write_read(&self, address: u16, data: &[u8], buf: &mut [u8]) -> Result<()> {
+    self.write(address, data)?;
+    self.read(address, buf)
+  }
+}
+
+impl DigitalPin for GPIO {
+  fn set_state(&mut self, state: PinState) -> Result<()> {
+    self.write(state)
+  }
+
+  fn state(&self) -> Result<PinState> {
+    self.read()
+  }
+
+  fn pin_name(&self) -> String {
+    format!("GPIO pin #{}", self.pin_num())
+  }
+}
+
+impl PwmOutput for PWM {
+  fn set_period_ns(&mut self, period_ns: u32) -> Result<()> {
+    self.set_period(period_ns)
+  }
+
+  fn set_duty_cycle_percent(&mut self, percentage: f32) -> Result<()> {
+    self.write(percentage)
+  }
+
+  fn set_enabled(&mut self, enabled: bool) -> Result<()> {
+    self.set_state(if enabled {
+      PWMState::Enabled
+    } else {
+      PWMState::Disabled
+    })
+  }
+}
+
+impl<P: PwmOutput + ?Sized> PwmOutput for Box<P> {
+  fn set_period_ns(&mut self, period_ns: u32) -> Result<()> {
+    (**self).set_period_ns(period_ns)
+  }
+
+  fn set_duty_cycle_percent(&mut self, percentage: f32) -> Result<()> {
+    (**self).set_duty_cycle_percent(percentage)
+  }
+
+  fn set_enabled(&mut self, enabled: bool) -> Result<()> {
+    (**self).set_enabled(enabled)
+  }
+}
+
+impl I2cBus for I2C {
+  fn write(&self, address: u16, data: &[u8]) -> Result<()> {
+    self.set_slave_address(address)?;
+    self.write_bytes(data)
+  }
+
+  fn read(&self, address: u16, buf: &mut [u8]) -> Result<()> {
+    self.set_slave_address(address)?;
+    self.read_bytes(buf)
+  }
+}
+
+impl I2cBus for I2cBusManager {
+  fn write(&self, address: u16, data: &[u8]) -> Result<()> {
+    self.device(address).write_bytes(data)
+  }
+
+  fn read(&self, address: u16, buf: &mut [u8]) -> Result<()> {
+    self.device(address).read_bytes(buf)
+  }
+
+  fn write_read(&self, address: u16, data: &[u8], buf: &mut [u8]) -> Result<()> {
+    self.device(address).write_read(data, buf)
+  }
+}
+
+impl I2cBus for MuxChannel {
+  fn write(&self, address: u16, data: &[u8]) -> Result<()> {
+    self.write_bytes(address, data)
+  }
+
+  fn read(&self, address: u16, buf: &mut [u8]) -> Result<()> {
+    self.read_bytes(address, buf)
+  }
+
+  fn write_read(&self, address: u16, data: &[u8], buf: &mut [u8]) -> Result<()> {
+    MuxChannel::write_read(self, address, data, buf)
+  }
+}
@@ -0,0 +1,274 @@
+//! The health check module.
+//!
+//! A service that finds out halfway through its start-up that an overlay
+//! isn't loaded or that it lacks the permissions for a device file leaves the
+//! hardware in a half-initialized state and an unhelpful error message.
+//! Instead, declare the hardware the service needs in a `Profile` and check
+//! all of it at once with `run()` before touching anything:
+//!
+//! ```no_run
+//! use libbeaglebone::healthcheck::{self, Profile};
+//! use libbeaglebone::prelude::*;
+//!
+//! let profile = Profile::new()
+//!   .with_overlay("BB-UART4")
+//!   .with_gpio(GPIO_P8_11)
+//!   .with_pwm(0, 0)
+//!   .with_adc(AIN_0)
+//!   .with_uart(4)
+//!   .with_i2c(2);
+//!
+//! let report = healthcheck::run(&profile);
+//! if !report.is_healthy() {
+//!   eprintln!("{}", report);
+//!   std::process::exit(1);
+//! }
+//! ```
+//!
+//! The checks only look at the file system; they don't export, open or
+//! otherwise change any device.
+
+use adc::ADC;
+use board;
+use capemgr::loaded_overlays;
+use gpio::GPIO;
+use nix::libc;
+use pins::Pin;
+use std::ffi::CString;
+use std::fmt;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+/// The hardware a service requires.
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+  overlays: Vec<String>,
+  gpios: Vec<Pin>,
+  pwms: Vec<(u8, u8)>,
+  adcs: Vec<Pin>,
+  uarts: Vec<u32>,
+  i2cs: Vec<u8>,
+  spis: Vec<u8>,
+}
+
+impl Profile {
+  /// Creates a profile that doesn't require anything.
+  pub fn new() -> Profile {
+    Profile::default()
+  }
+
+  /// Requires the overlay `name` to be applied, e.g. "BB-UART4".
+  pub fn with_overlay(mut self, name: &str) -> Profile {
+    self.overlays.push(name.to_string());
+    self
+  }
+
+  /// Requires the pin to be usable as a GPIO.
+  pub fn with_gpio(mut self, pin: Pin) -> Profile {
+    self.gpios.push(pin);
+    self
+  }
+
+  /// Requires PWM `pwm_num` of PWM chip `pwm_chip_num`.
+  pub fn with_pwm(mut self, pwm_chip_num: u8, pwm_num: u8) -> Profile {
+    self.pwms.push((pwm_chip_num, pwm_num));
+    self
+  }
+
+  /// Requires the pin to be usable as an ADC input, e.g. `AIN_0`.
+  pub fn with_adc(mut self, pin: Pin) -> Profile {
+    self.adcs.push(pin);
+    self
+  }
+
+  /// Requires UART `uart_num`, e.g. `/dev/ttyO4` on the BeagleBone.
+  pub fn with_uart(mut self, uart_num: u32) -> Profile {
+    self.uarts.push(uart_num);
+    self
+  }
+
+  /// Requires I2C bus `i2c_num`, e.g. `/dev/i2c-2`.
+  pub fn with_i2c(mut self, i2c_num: u8) -> Profile {
+    self.i2cs.push(i2c_num);
+    self
+  }
+
+  /// Requires SPI bus `spi_num`, e.g. `/dev/spidev1.0`.
+  pub fn with_spi(mut self, spi_num: u8) -> Profile {
+    self.spis.push(spi_num);
+    self
+  }
+}
+
+/// The outcome of a single check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+  /// The requirement is met.
+  Passed,
+  /// The device, file or overlay doesn't exist.
+  Missing,
+  /// The file exists, but the process isn't allowed to use it.
+  PermissionDenied,
+}
+
+/// A single check of a health check report.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+  /// What was checked, e.g. "GPIO pin #45".
+  pub subject: String,
+  /// The outcome of the check.
+  pub status: CheckStatus,
+  /// A description of the outcome, e.g. the file that is missing.
+  pub detail: String,
+}
+
+/// The result of checking a profile.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+  /// All checks that were run, in the order of the profile.
+  pub checks: Vec<Check>,
+}
+
+impl Report {
+  /// Returns whether all checks passed.
+  pub fn is_healthy(&self) -> bool {
+    self.checks.iter().all(|check| check.status == CheckStatus::Passed)
+  }
+
+  /// Returns the checks that didn't pass.
+  pub fn failures(&self) -> Vec<&Check> {
+    self.checks
+        .iter()
+        .filter(|check| check.status != CheckStatus::Passed)
+        .collect()
+  }
+}
+
+impl fmt::Display for Report {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    for check in &self.checks {
+      let status = match check.status {
+        CheckStatus::Passed => "ok",
+        CheckStatus::Missing => "MISSING",
+        CheckStatus::PermissionDenied => "DENIED",
+      };
+      writeln!(f, "[{:>7}] {}: {}", status, check.subject, check.detail)?;
+    }
+    Ok(())
+  }
+}
+
+/// Checks everything the profile requires and reports the outcome of each
+/// check.
+pub fn run(profile: &Profile) -> Report {
+  let board = board::current();
+  let mut checks = Vec::new();
+
+  if !profile.overlays.is_empty() {
+    match loaded_overlays() {
+      Ok(loaded) => {
+        for name in &profile.overlays {
+          checks.push(if loaded.contains(name) {
+            check(format!("Overlay {}", name), CheckStatus::Passed, "applied".to_string())
+          } else {
+            check(format!("Overlay {}", name), CheckStatus::Missing, "not applied".to_string())
+          });
+        }
+      }
+      Err(e) => {
+        for name in &profile.overlays {
+          checks.push(check(format!("Overlay {}", name), CheckStatus::Missing, e.to_string()));
+        }
+      }
+    }
+  }
+
+  for &pin in &profile.gpios {
+    let gpio = GPIO::new(pin);
+    let subject = format!("GPIO pin #{}", gpio.pin_num());
+    checks.push(if gpio.sysfs_path().exists() {
+      check_files(subject, gpio.sysfs_path(), &["direction", "value"])
+    } else {
+      check_file(subject, &Path::new(&board.gpio_dir).join("export"), libc::W_OK)
+    });
+  }
+
+  for &(chip, num) in &profile.pwms {
+    let subject = format!("PWM #{}-{}", chip, num);
+    let chip_path = PathBuf::from(board.pwm_chip_path(chip));
+    let pwm_path = PathBuf::from(board.pwm_path(chip, num));
+    checks.push(if pwm_path.exists() {
+      check_files(subject, &pwm_path, &["period", "duty_cycle", "enable"])
+    } else {
+      check_file(subject, &chip_path.join("export"), libc::W_OK)
+    });
+  }
+
+  for &pin in &profile.adcs {
+    let adc = ADC::new(pin, 0.0);
+    let subject = format!("ADC #{}", adc.adc_num());
+    checks.push(match adc.raw_path() {
+      Some(path) => check_file(subject, Path::new(path), libc::R_OK),
+      None => check(subject, CheckStatus::Missing, format!("the {} has no ADC", board.name)),
+    });
+  }
+
+  for &num in &profile.uarts {
+    let path = board.uart_path(num);
+    checks.push(check_file(format!("UART #{}", num), Path::new(&path), libc::R_OK | libc::W_OK));
+  }
+
+  for &num in &profile.i2cs {
+    let path = board.i2c_path(num);
+    checks.push(check_file(format!("I2C bus #{}", num), Path::new(&path), libc::R_OK | libc::W_OK));
+  }
+
+  for &num in &profile.spis {
+    let path = board.spi_path(num);
+    checks.push(check_file(format!("SPI bus #{}", num), Path::new(&path), libc::R_OK | libc::W_OK));
+  }
+
+  Report { checks }
+}
+
+fn check(subject: String, status: CheckStatus, detail: String) -> Check {
+  Check {
+    subject,
+    status,
+    detail,
+  }
+}
+
+/// Checks that the attribute files in `dir` are readable and writable.
+fn check_files(subject: String, dir: &Path, attributes: &[&str]) -> Check {
+  for attribute in attributes {
+    let result = check_file(subject.clone(), &dir.join(attribute), libc::R_OK | libc::W_OK);
+    if result.status != CheckStatus::Passed {
+      return result;
+    }
+  }
+  check(subject, CheckStatus::Passed, dir.display().to_string())
+}
+
+/// Checks that `path` exists and can be accessed with `mode`, a combination
+/// of `R_OK` and `W_OK`.
+fn check_file(subject: String, path: &Path, mode: libc::c_int) -> Check {
+  if !path.exists() {
+    return check(subject, CheckStatus::Missing, format!("{} doesn't exist", path.display()));
+  }
+  let accessible = CString::new(path.as_os_str().as_bytes())
+    .map(|c_path| unsafe { libc::access(c_path.as_ptr(), mode) } == 0)
+    .unwrap_or(false);
+  if accessible {
+    check(subject, CheckStatus::Passed, path.display().to_string())
+  } else {
+    let access = match (mode & libc::R_OK != 0, mode & libc::W_OK != 0) {
+      (true, true) => "read/write",
+      (false, true) => "write",
+      _ => "read",
+    };
+    check(subject,
+          CheckStatus::PermissionDenied,
+          format!("no {} access to {}", access, path.display()))
+  }
+}
@@ -0,0 +1,212 @@
+//! The heartbeat module.
+//!
+//! A headless device's only way to tell how it's doing is often a blinking
+//! LED.
+//! A `Heartbeat` blinks an LED in the background in a pattern that shows the
+//! state of the application:
+//!
+//! | State      | Pattern                                   |
+//! |------------|-------------------------------------------|
+//! | `Starting` | fast, even blinking                       |
+//! | `Healthy`  | a double blink every second, like a pulse |
+//! | `Degraded` | slow, even blinking                       |
+//! | `Panicked` | very fast flickering                      |
+//!
+//! With `install_panic_hook()`, a panic of any thread switches the LED to
+//! the panicked pattern, so crashed worker threads don't go unnoticed.
+//!
+//! The LED can be a GPIO or any other `DigitalPin`, e.g. one of the
+//! BeagleBone's user LEDs through `Led`.
+//! A GPIO has to be exported and configured as an output beforehand.
+
+use errors::*;
+use hal::DigitalPin;
+use gpio::PinState;
+use std::panic;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use util::*;
+
+/// How long the heartbeat thread sleeps at most before checking for a
+/// change of state.
+const CHECK_INTERVAL_MS: u64 = 20;
+
+/// The state of the application.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HealthState {
+  /// The application is starting up.
+  Starting,
+  /// The application works normally.
+  Healthy,
+  /// The application works, but with problems, e.g. a sensor is missing.
+  Degraded,
+  /// A thread of the application panicked.
+  Panicked,
+}
+
+impl HealthState {
+  fn from_index(index: usize) -> HealthState {
+    match index {
+      0 => HealthState::Starting,
+      1 => HealthState::Healthy,
+      2 => HealthState::Degraded,
+      _ => HealthState::Panicked,
+    }
+  }
+
+  fn index(self) -> usize {
+    self as usize
+  }
+
+  /// Returns the blink pattern as pairs of LED state and duration in ms.
+  fn pattern(self) -> &'static [(bool, u64)] {
+    match self {
+      HealthState::Starting => &[(true, 100), (false, 100)],
+      HealthState::Healthy => &[(true, 80), (false, 120), (true, 80), (false, 720)],
+      HealthState::Degraded => &[(true, 500), (false, 500)],
+      HealthState::Panicked => &[(true, 40), (false, 40)],
+    }
+  }
+}
+
+/// One of the LEDs in `/sys/class/leds`, e.g. the BeagleBone's user LEDs.
+#[derive(Debug)]
+pub struct Led {
+  name: String,
+}
+
+impl Led {
+  /// Takes over the LED called `name`, e.g. "beaglebone:green:usr0", by
+  /// disabling its kernel trigger.
+  ///
+  /// # Errors
+  ///
+  /// Fails if there is no such LED or its trigger can't be changed.
+  pub fn new(name: &str) -> Result<Led> {
+    let path = format!("/sys/class/leds/{}/trigger", name);
+    path.write_file("none").chain_err(|| format!("Failed to take over LED {}", name))?;
+    Ok(Led { name: name.to_string() })
+  }
+}
+
+impl DigitalPin for Led {
+  fn set_state(&mut self, state: PinState) -> Result<()> {
+    let path = format!("/sys/class/leds/{}/brightness", self.name);
+    path.write_file(match state {
+      PinState::High => "1",
+      PinState::Low => "0",
+    })
+        .chain_err(|| format!("Failed to set LED {}", self.name))
+  }
+
+  fn state(&self) -> Result<PinState> {
+    let path = format!("/sys/class/leds/{}/brightness", self.name);
+    let brightness = path.read_file().chain_err(|| format!("Failed to read LED {}", self.name))?;
+    Ok(if brightness.trim() == "0" {
+         PinState::Low
+       } else {
+         PinState::High
+       })
+  }
+
+  fn pin_name(&self) -> String {
+    format!("LED {}", self.name)
+  }
+}
+
+/// Blinks an LED according to the state of the application.
+#[derive(Debug)]
+pub struct Heartbeat {
+  state: Arc<AtomicUsize>,
+  running: Arc<AtomicBool>,
+  thread: Option<JoinHandle<()>>,
+}
+
+impl Heartbeat {
+  /// Starts blinking `led` in the `Starting` pattern.
+  ///
+  /// The LED is turned off when the heartbeat is dropped.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::heartbeat::{HealthState, Heartbeat, Led};
+  ///
+  /// let heartbeat = Heartbeat::new(Led::new("beaglebone:green:usr0").unwrap());
+  /// heartbeat.install_panic_hook();
+  ///
+  /// // ... initialize the application ...
+  ///
+  /// heartbeat.set_state(HealthState::Healthy);
+  /// ```
+  pub fn new<P: DigitalPin + Send + 'static>(led: P) -> Heartbeat {
+    let state = Arc::new(AtomicUsize::new(HealthState::Starting.index()));
+    let running = Arc::new(AtomicBool::new(true));
+    let thread = {
+      let state = state.clone();
+      let running = running.clone();
+      thread::spawn(move || blink(led, &state, &running))
+    };
+    Heartbeat {
+      state,
+      running,
+      thread: Some(thread),
+    }
+  }
+
+  /// Returns the state shown.
+  pub fn state(&self) -> HealthState {
+    HealthState::from_index(self.state.load(Ordering::SeqCst))
+  }
+
+  /// Changes the state shown, taking effect right away.
+  pub fn set_state(&self, state: HealthState) {
+    self.state.store(state.index(), Ordering::SeqCst);
+  }
+
+  /// Installs a panic hook that switches the LED to the `Panicked` pattern,
+  /// in addition to the previous hook, e.g. the one printing the panic
+  /// message.
+  ///
+  /// If the panic ends the process, the LED stays in the state it had at
+  /// that moment.
+  pub fn install_panic_hook(&self) {
+    let state = self.state.clone();
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+                               state.store(HealthState::Panicked.index(), Ordering::SeqCst);
+                               previous(info);
+                             }));
+  }
+}
+
+impl Drop for Heartbeat {
+  fn drop(&mut self) {
+    self.running.store(false, Ordering::SeqCst);
+    if let Some(thread) = self.thread.take() {
+      let _ = thread.join();
+    }
+  }
+}
+
+/// Blinks the LED in the pattern of the current state until `running` is
+/// cleared, then turns it off.
+fn blink<P: DigitalPin>(mut led: P, state: &AtomicUsize, running: &AtomicBool) {
+  'patterns: while running.load(Ordering::SeqCst) {
+    let current = state.load(Ordering::SeqCst);
+    for &(on, duration_ms) in HealthState::from_index(current).pattern() {
+      // A failing LED is no reason to bring down the application.
+      let _ = led.set_state(if on { PinState::High } else { PinState::Low });
+      let until = Instant::now() + Duration::from_millis(duration_ms);
+      while Instant::now() < until {
+        if !running.load(Ordering::SeqCst) || state.load(Ordering::SeqCst) != current {
+          continue 'patterns;
+        }
+        thread::sleep(Duration::from_millis(CHECK_INTERVAL_MS));
+      }
+    }
+  }
+  let _ = led.set_low();
+}
@@ -0,0 +1,191 @@
+//! The homing module.
+//!
+//! An axis driven by a motor with an encoder only knows where it is
+//! relative to where it started.
+//! Homing references it: the motor moves the axis until a switch trips,
+//! brakes, and the encoder's position is set to 0 there:
+//!
+//! ```no_run
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::encoder::RotaryEncoder;
+//! use libbeaglebone::homing::Homing;
+//! use libbeaglebone::limits::{LimitKind, Limits, Travel};
+//! use libbeaglebone::motor::Motor;
+//!
+//! let limits = Limits::new();
+//! limits.add("x-min", "x", LimitKind::Min, GPIO_P8_7, PinState::Low).unwrap();
+//! limits.add("x-home", "x", LimitKind::Home, GPIO_P8_9, PinState::Low).unwrap();
+//! let mut motor = Motor::new(3, 0, GPIO_P8_11, GPIO_P8_12).unwrap();
+//! let mut encoder = RotaryEncoder::new(GPIO_P8_14, GPIO_P8_15).unwrap();
+//!
+//! // Towards the minimum at 20% until the home switch trips.
+//! Homing::new(Travel::Negative, 0.2).run(&mut motor, &mut encoder, &limits, "x").unwrap();
+//! assert_eq!(encoder.position(), 0);
+//! ```
+//!
+//! Homing fails, with the motor braked, if the end of travel's limit switch
+//! trips before the home switch, or if no switch trips in time.
+//! Axes without a home switch can home on the limit switch instead, see
+//! `Homing::set_on_limit()`.
+//! Re-referencing on an encoder's index pulse isn't supported, the
+//! encoders decoded in software have none.
+
+use clock::{self, Clock};
+use encoder::RotaryEncoder;
+use errors::*;
+use hal::DigitalPin;
+use limits::{Limits, Travel};
+use motor::Motor;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long homing may take unless changed with `set_timeout()`.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How to home an axis.
+#[derive(Debug, Clone)]
+pub struct Homing {
+  travel: Travel,
+  speed: f32,
+  on_limit: bool,
+  timeout: Duration,
+  poll_interval: Duration,
+  clock: Arc<dyn Clock>,
+}
+
+impl Homing {
+  /// Homes by moving in the direction `travel` at `speed`, from 0.0 to
+  /// 1.0, until the axis' home switch trips.
+  pub fn new(travel: Travel, speed: f32) -> Homing {
+    Homing {
+      travel,
+      speed,
+      on_limit: false,
+      timeout: DEFAULT_TIMEOUT,
+      poll_interval: Duration::from_millis(1),
+      clock: clock::system(),
+    }
+  }
+
+  /// Homes on the limit switch at the end of travel instead of the home
+  /// switch.
+  pub fn set_on_limit(&mut self, on_limit: bool) {
+    self.on_limit = on_limit;
+  }
+
+  /// Sets how long homing may take, 30s by default.
+  pub fn set_timeout(&mut self, timeout: Duration) {
+    self.timeout = timeout;
+  }
+
+  /// Sets how often the switches and the encoder are sampled, 1ms by
+  /// default.
+  pub fn set_poll_interval(&mut self, interval: Duration) {
+    self.poll_interval = interval;
+  }
+
+  /// Makes homing take its time from `clock`, e.g. a `TestClock`.
+  pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+    self.clock = clock;
+  }
+
+  /// Moves `axis` with `motor` until its switch of `limits` trips, brakes
+  /// and sets the position of `encoder` to 0.
+  ///
+  /// The encoder is sampled with `poll()` while the axis moves, so it
+  /// mustn't be watched at the same time; an axis already at its switch
+  /// doesn't move.
+  ///
+  /// ```
+  /// use libbeaglebone::prelude::*;
+  /// use libbeaglebone::{board, stub};
+  /// use libbeaglebone::clock::TestClock;
+  /// use libbeaglebone::encoder::RotaryEncoder;
+  /// use libbeaglebone::homing::Homing;
+  /// use libbeaglebone::limits::{LimitKind, Limits, Travel};
+  /// use libbeaglebone::motor::{Motor, MotorState};
+  /// use std::sync::Arc;
+  /// use std::time::Duration;
+  ///
+  /// board::set_current(stub::board().unwrap());
+  /// let limits = Limits::new();
+  /// limits.add("x-home", "x", LimitKind::Home, GPIO_P8_9, PinState::High).unwrap();
+  /// let mut motor = Motor::new(0, 0, GPIO_P8_11, GPIO_P8_12).unwrap();
+  /// let mut encoder = RotaryEncoder::new(GPIO_P8_14, GPIO_P8_15).unwrap();
+  /// encoder.set_position(42);
+  ///
+  /// let mut homing = Homing::new(Travel::Negative, 0.2);
+  /// homing.set_clock(Arc::new(TestClock::new()));
+  /// homing.set_timeout(Duration::from_secs(1));
+  ///
+  /// // The home switch never trips.
+  /// assert!(homing.run(&mut motor, &mut encoder, &limits, "x").is_err());
+  /// assert_eq!(motor.state(), MotorState::Brake);
+  /// assert_eq!(encoder.position(), 42);
+  ///
+  /// stub::set_input(GPIO_P8_9 as u8, PinState::High).unwrap();
+  /// homing.run(&mut motor, &mut encoder, &limits, "x").unwrap();
+  /// assert_eq!(motor.state(), MotorState::Brake);
+  /// assert_eq!(encoder.position(), 0);
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails with the motor braked if the limit switch at the end of travel
+  /// trips before the home switch, if no switch trips within the timeout,
+  /// or if the motor, a switch or the encoder fails.
+  pub fn run<P: DigitalPin>(&self,
+                            motor: &mut Motor,
+                            encoder: &mut RotaryEncoder<P>,
+                            limits: &Limits,
+                            axis: &str)
+                            -> Result<()> {
+    let deadline = self.clock.now() + self.timeout;
+    let mut moving = false;
+    loop {
+      if let Err(e) = self.sample(encoder, limits) {
+        motor.brake()?;
+        return Err(e);
+      }
+      if let Some(name) = limits.blocking(axis, self.travel) {
+        motor.brake()?;
+        if self.on_limit {
+          break;
+        }
+        bail!(format!("Homing axis {:?} hit limit switch {:?} before the home switch", axis, name));
+      }
+      if !self.on_limit && limits.is_home(axis) {
+        motor.brake()?;
+        break;
+      }
+      if !moving {
+        match self.travel {
+          Travel::Positive => motor.forward(self.speed)?,
+          Travel::Negative => motor.reverse(self.speed)?,
+        }
+        moving = true;
+      }
+      match deadline.checked_sub(self.clock.now()) {
+        Some(remaining) if remaining > Duration::from_secs(0) => {
+          if let Err(e) = self.clock.sleep(self.poll_interval.min(remaining)) {
+            motor.brake()?;
+            return Err(e);
+          }
+        }
+        _ => {
+          motor.brake()?;
+          bail!(format!("Homing axis {:?} didn't reach its switch within {:?}", axis, self.timeout));
+        }
+      }
+    }
+    encoder.set_position(0);
+    Ok(())
+  }
+
+  /// Samples the switches and the encoder.
+  fn sample<P: DigitalPin>(&self, encoder: &mut RotaryEncoder<P>, limits: &Limits) -> Result<()> {
+    limits.update()?;
+    let _ = encoder.poll()?;
+    Ok(())
+  }
+}
@@ -9,8 +9,10 @@
 //! If you wish to enable another I2C, substitute its number for 1 in the
 //! command above.
 
+use board;
 use errors::*;
 use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
 use std::os::unix::io::AsRawFd;
 use util::*;
 
@@ -52,11 +54,16 @@ impl I2C {
       i2c_file: OpenOptions::new()
         .read(true)
         .write(true)
-        .open(&format!("/dev/i2c-{}", i2c_num))
+        .open(board::current().i2c_path(i2c_num))
         .chain_err(|| format!("Failed to open new I2C device #{}.", i2c_num))?,
     })
   }
 
+  /// Returns the number of the I2C bus.
+  pub fn i2c_num(&self) -> u8 {
+    self.i2c_num
+  }
+
   /// Sets the address of the I2C slave device.
   ///
   /// # Examples
@@ -133,4 +140,59 @@ impl I2C {
     let res = self.i2c_file.read_file()?;
     Ok(res.trim().parse::<u8>().unwrap())
   }
+
+  /// Writes a buffer of bytes to an I2C slave in a single transaction.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  ///
+  /// // Create a new I2C interface using BB_I2C1.
+  /// let i2c = I2C::new(1).unwrap();
+  ///
+  /// // Set the slave address to 0x45.
+  /// i2c.set_slave_address(0x45).unwrap();
+  ///
+  /// // Write 0x12 to register 0x01 of the I2C slave.
+  /// i2c.write_bytes(&[0x01, 0x12]).unwrap();
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if the kernel is unable to write the bytes to the device.
+  pub fn write_bytes(&self, data: &[u8]) -> Result<()> {
+    (&self.i2c_file)
+      .write_all(data)
+      .chain_err(|| format!("Failed to write to I2C device #{}.", self.i2c_num))?;
+    Ok(())
+  }
+
+  /// Reads bytes from an I2C slave until `buf` is full.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  ///
+  /// // Create a new I2C interface using BB_I2C1.
+  /// let i2c = I2C::new(1).unwrap();
+  ///
+  /// // Set the slave address to 0x45.
+  /// i2c.set_slave_address(0x45).unwrap();
+  ///
+  /// // Read two bytes from the I2C slave.
+  /// let mut buf = [0; 2];
+  /// i2c.read_bytes(&mut buf).unwrap();
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if the kernel is unable to read from the device.
+  pub fn read_bytes(&self, buf: &mut [u8]) -> Result<()> {
+    (&self.i2c_file)
+      .read_exact(buf)
+      .chain_err(|| format!("Failed to read from I2C device #{}.", self.i2c_num))?;
+    Ok(())
+  }
 }
@@ -0,0 +1,172 @@
+//! The shared I2C bus module.
+//!
+//! An `I2C` interface addresses one slave at a time, so drivers for several
+//! sensors on the same bus would keep changing each other's slave address.
+//! The `I2cBusManager` owns the interface and hands out `I2cDevice` handles
+//! that are bound to one slave address each.
+//! Every handle locks the bus for the duration of a transaction and addresses
+//! its slave first, so the handles can be cloned and used from different
+//! threads.
+//!
+//! The I2C interface has to be enabled beforehand, see the `i2c` module
+//! documentation.
+
+use errors::*;
+use i2c::I2C;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+#[derive(Debug)]
+struct BusState {
+  i2c: I2C,
+  // The slave address that was set last, to skip the ioctl when the same
+  // device is used repeatedly.
+  address: Option<u16>,
+}
+
+/// Shares an I2C interface between several device handles.
+#[derive(Debug, Clone)]
+pub struct I2cBusManager {
+  bus: Arc<Mutex<BusState>>,
+}
+
+impl I2cBusManager {
+  /// Opens an I2C interface and creates a manager for it.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::i2c_bus::I2cBusManager;
+  ///
+  /// let bus = I2cBusManager::new(2).unwrap();
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if the interface can't be opened, see `I2C::new()`.
+  pub fn new(i2c_num: u8) -> Result<I2cBusManager> {
+    Ok(I2cBusManager::from_i2c(I2C::new(i2c_num)?))
+  }
+
+  /// Creates a manager for an already opened I2C interface.
+  pub fn from_i2c(i2c: I2C) -> I2cBusManager {
+    I2cBusManager {
+      bus: Arc::new(Mutex::new(BusState { i2c, address: None })),
+    }
+  }
+
+  /// Returns a handle to the slave at `address`.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::i2c_bus::I2cBusManager;
+  /// use std::thread;
+  ///
+  /// let bus = I2cBusManager::new(2).unwrap();
+  /// let sensor = bus.device(0x40);
+  /// let display = bus.device(0x3C);
+  ///
+  /// // Both devices can be driven from different threads.
+  /// let handle = thread::spawn(move || {
+  ///   let mut reading = [0; 2];
+  ///   sensor.write_read(&[0x00], &mut reading).unwrap();
+  /// });
+  /// display.write_bytes(&[0x00, 0xAF]).unwrap();
+  /// handle.join().unwrap();
+  /// ```
+  pub fn device(&self, address: u16) -> I2cDevice {
+    I2cDevice {
+      bus: self.bus.clone(),
+      address,
+    }
+  }
+}
+
+/// A handle to one slave on a shared I2C bus.
+#[derive(Debug, Clone)]
+pub struct I2cDevice {
+  bus: Arc<Mutex<BusState>>,
+  address: u16,
+}
+
+impl I2cDevice {
+  /// Returns the slave address the handle is bound to.
+  pub fn address(&self) -> u16 {
+    self.address
+  }
+
+  /// Writes a buffer of bytes to the slave in a single transaction.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the slave can't be addressed or the kernel is unable to write
+  /// the bytes to the device.
+  pub fn write_bytes(&self, data: &[u8]) -> Result<()> {
+    self.transaction(|i2c| i2c.write_bytes(data))
+  }
+
+  /// Reads bytes from the slave until `buf` is full.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the slave can't be addressed or the kernel is unable to read
+  /// from the device.
+  pub fn read_bytes(&self, buf: &mut [u8]) -> Result<()> {
+    self.transaction(|i2c| i2c.read_bytes(buf))
+  }
+
+  /// Writes `data` to the slave and then reads from it until `buf` is full,
+  /// e.g. to read a register.
+  ///
+  /// No other handle can use the bus in between.
+  pub fn write_read(&self, data: &[u8], buf: &mut [u8]) -> Result<()> {
+    self.transaction(|i2c| {
+      i2c.write_bytes(data)?;
+      i2c.read_bytes(buf)
+    })
+  }
+
+  /// Runs `f` with exclusive access to the bus, with the slave already
+  /// addressed.
+  ///
+  /// This is useful for drivers that need several steps that must not be
+  /// interleaved with other devices' transactions.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::i2c_bus::I2cBusManager;
+  ///
+  /// let bus = I2cBusManager::new(2).unwrap();
+  /// let eeprom = bus.device(0x50);
+  ///
+  /// let mut page = [0; 32];
+  /// eeprom.transaction(|i2c| {
+  ///   i2c.write_bytes(&[0x00, 0x00])?;
+  ///   i2c.read_bytes(&mut page)
+  /// }).unwrap();
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if the slave can't be addressed, or with the error returned by
+  /// `f`.
+  pub fn transaction<T, F>(&self, f: F) -> Result<T>
+    where F: FnOnce(&I2C) -> Result<T>
+  {
+    let mut bus = self.lock()?;
+    if bus.address != Some(self.address) {
+      bus.address = None;
+      bus.i2c.set_slave_address(self.address)?;
+      bus.address = Some(self.address);
+    }
+    f(&bus.i2c)
+  }
+
+  fn lock(&self) -> Result<MutexGuard<'_, BusState>> {
+    match self.bus.lock() {
+      Ok(bus) => Ok(bus),
+      Err(_) => bail!("I2C bus lock is poisoned"),
+    }
+  }
+}
@@ -0,0 +1,184 @@
+//! The I2C multiplexer module.
+//!
+//! A TCA9548A multiplexer splits one I2C bus into 8 downstream segments, so
+//! several devices with the same fixed address can be connected to one
+//! BeagleBone I2C interface.
+//! Each segment is represented by a `MuxChannel` handle that selects its
+//! segment before every transaction, so the handles can be used as if they
+//! were separate buses.
+//!
+//! The I2C interface has to be enabled beforehand, see the `i2c` module
+//! documentation.
+
+use errors::*;
+use i2c::I2C;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// The default address of a TCA9548A, with A0-A2 tied low.
+pub const TCA9548A_DEFAULT_ADDRESS: u16 = 0x70;
+
+/// The number of downstream channels of a TCA9548A.
+pub const TCA9548A_CHANNELS: u8 = 8;
+
+#[derive(Debug)]
+struct MuxBus {
+  i2c: I2C,
+  address: u16,
+  // The channel that is currently selected, to avoid re-selecting it before
+  // each transaction.
+  selected: Option<u8>,
+}
+
+impl MuxBus {
+  fn select(&mut self, channel: Option<u8>) -> Result<()> {
+    if self.selected == channel {
+      return Ok(());
+    }
+    // Forget the selection until the mux has acknowledged the new one.
+    self.selected = None;
+    self.i2c.set_slave_address(self.address)?;
+    self.i2c
+        .write_bytes(&[channel.map_or(0, |c| 1 << c)])
+        .chain_err(|| format!("Failed to select channel {:?} of the I2C mux", channel))?;
+    self.selected = channel;
+    Ok(())
+  }
+}
+
+/// Represents a TCA9548A I2C multiplexer.
+#[derive(Debug)]
+pub struct TCA9548A {
+  bus: Arc<Mutex<MuxBus>>,
+}
+
+impl TCA9548A {
+  /// Creates a new multiplexer on an I2C interface and disables all of its
+  /// channels.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  /// use libbeaglebone::i2c_mux::{TCA9548A, TCA9548A_DEFAULT_ADDRESS};
+  ///
+  /// let mux = TCA9548A::new(I2C::new(1).unwrap(), TCA9548A_DEFAULT_ADDRESS).unwrap();
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if the multiplexer doesn't respond at `address`.
+  pub fn new(i2c: I2C, address: u16) -> Result<TCA9548A> {
+    i2c.set_slave_address(address)?;
+    i2c.write_bytes(&[0])
+       .chain_err(|| format!("I2C mux doesn't respond at address 0x{:02X}", address))?;
+    let bus = MuxBus {
+      i2c,
+      address,
+      selected: None,
+    };
+    Ok(TCA9548A { bus: Arc::new(Mutex::new(bus)) })
+  }
+
+  /// Returns a handle to one of the downstream channels.
+  ///
+  /// Handles are cheap to clone and can be moved to other threads; their
+  /// transactions are serialized.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  /// use libbeaglebone::i2c_mux::{TCA9548A, TCA9548A_DEFAULT_ADDRESS};
+  ///
+  /// let mux = TCA9548A::new(I2C::new(1).unwrap(), TCA9548A_DEFAULT_ADDRESS).unwrap();
+  ///
+  /// // Two sensors with the same address 0x40, on channels 0 and 1.
+  /// let left = mux.channel(0).unwrap();
+  /// let right = mux.channel(1).unwrap();
+  ///
+  /// let mut left_reading = [0; 2];
+  /// let mut right_reading = [0; 2];
+  /// left.write_read(0x40, &[0x00], &mut left_reading).unwrap();
+  /// right.write_read(0x40, &[0x00], &mut right_reading).unwrap();
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if `channel` isn't within 0-7.
+  pub fn channel(&self, channel: u8) -> Result<MuxChannel> {
+    if channel >= TCA9548A_CHANNELS {
+      bail!(format!("I2C mux channel {} is outside of 0-7", channel));
+    }
+    Ok(MuxChannel {
+      bus: self.bus.clone(),
+      channel,
+    })
+  }
+
+  /// Disconnects all downstream channels from the bus.
+  pub fn disable_all(&self) -> Result<()> {
+    lock(&self.bus)?.select(None)
+  }
+}
+
+/// A downstream channel of a TCA9548A, used like a separate I2C bus.
+#[derive(Debug, Clone)]
+pub struct MuxChannel {
+  bus: Arc<Mutex<MuxBus>>,
+  channel: u8,
+}
+
+impl MuxChannel {
+  /// Returns the number of the channel.
+  pub fn channel(&self) -> u8 {
+    self.channel
+  }
+
+  /// Writes a buffer of bytes to a slave on this channel in a single
+  /// transaction.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the channel can't be selected or the kernel is unable to write
+  /// the bytes to the device.
+  pub fn write_bytes(&self, slave_addr: u16, data: &[u8]) -> Result<()> {
+    let bus = self.select(slave_addr)?;
+    bus.i2c.write_bytes(data)
+  }
+
+  /// Reads bytes from a slave on this channel until `buf` is full.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the channel can't be selected or the kernel is unable to read
+  /// from the device.
+  pub fn read_bytes(&self, slave_addr: u16, buf: &mut [u8]) -> Result<()> {
+    let bus = self.select(slave_addr)?;
+    bus.i2c.read_bytes(buf)
+  }
+
+  /// Writes `data` to a slave on this channel and then reads from it until
+  /// `buf` is full, e.g. to read a register.
+  ///
+  /// No other handle can use the mux in between.
+  pub fn write_read(&self, slave_addr: u16, data: &[u8], buf: &mut [u8]) -> Result<()> {
+    let bus = self.select(slave_addr)?;
+    bus.i2c.write_bytes(data)?;
+    bus.i2c.read_bytes(buf)
+  }
+
+  /// Locks the bus, selects this channel and addresses the slave.
+  fn select(&self, slave_addr: u16) -> Result<MutexGuard<'_, MuxBus>> {
+    let mut bus = lock(&self.bus)?;
+    bus.select(Some(self.channel))?;
+    bus.i2c.set_slave_address(slave_addr)?;
+    Ok(bus)
+  }
+}
+
+fn lock(bus: &Mutex<MuxBus>) -> Result<MutexGuard<'_, MuxBus>> {
+  match bus.lock() {
+    Ok(bus) => Ok(bus),
+    Err(_) => bail!("I2C mux lock is poisoned"),
+  }
+}
@@ -0,0 +1,288 @@
+//! The hardware journal module.
+//!
+//! After an incident, e.g. a robot arm hitting something, the first question
+//! is what the controller commanded and what it saw.
+//! While a `Journal` is open, every GPIO write, every change of a PWM's
+//! period, duty cycle, state and polarity, and every GPIO edge seen by an
+//! `EdgeWaiter` is appended to a file with its wall-clock time:
+//!
+//! ```no_run
+//! use libbeaglebone::journal::{self, Journal};
+//!
+//! let journal = Journal::open("/var/log/robot.journal").unwrap();
+//!
+//! // ... run the controller ...
+//!
+//! // Later, e.g. on another machine.
+//! for record in journal::read("/var/log/robot.journal").unwrap() {
+//!   println!("{:?} {:?}", record.time, record.entry);
+//! }
+//! ```
+//!
+//! Unlike a VCD recording, the journal is written as it goes, so it survives
+//! a crash of the process, and a file can be appended to across restarts.
+//! Each record takes 15 bytes: the time in ns since the Unix epoch as u64,
+//! the kind of entry as u8, two u8 device numbers and a u32 value, all
+//! little-endian.
+//! Only one journal can be open at a time.
+
+use errors::*;
+use gpio::PinState;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The magic bytes and version at the start of a journal file.
+const HEADER: &[u8] = b"BBJNL\x01";
+
+/// The size of a record in bytes.
+const RECORD_SIZE: usize = 15;
+
+/// Whether a journal is open, to skip the lock when it isn't.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// The file of the open journal.
+static FILE: Mutex<Option<File>> = Mutex::new(None);
+
+/// The number of entries that couldn't be written.
+static WRITE_ERRORS: AtomicU64 = AtomicU64::new(0);
+
+/// An event in the journal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Entry {
+  /// A GPIO output was driven to a level.
+  GpioWrite {
+    /// The pin number, see `Pin`.
+    pin: u8,
+    /// The level written.
+    state: PinState,
+  },
+  /// An edge occurred on a GPIO input.
+  GpioEdge {
+    /// The pin number, see `Pin`.
+    pin: u8,
+    /// The level after the edge.
+    state: PinState,
+  },
+  /// The period of a PWM was set.
+  PwmPeriod {
+    /// The PWM chip number.
+    chip: u8,
+    /// The PWM number on the chip.
+    num: u8,
+    /// The period in nanoseconds.
+    period_ns: u32,
+  },
+  /// The duty cycle of a PWM was set.
+  PwmDutyCycle {
+    /// The PWM chip number.
+    chip: u8,
+    /// The PWM number on the chip.
+    num: u8,
+    /// The duty cycle in nanoseconds.
+    duty_cycle_ns: u32,
+  },
+  /// A PWM was enabled or disabled.
+  PwmEnabled {
+    /// The PWM chip number.
+    chip: u8,
+    /// The PWM number on the chip.
+    num: u8,
+    /// Whether the PWM was enabled.
+    enabled: bool,
+  },
+  /// The polarity of a PWM was set.
+  PwmPolarity {
+    /// The PWM chip number.
+    chip: u8,
+    /// The PWM number on the chip.
+    num: u8,
+    /// Whether the polarity is inversed.
+    inversed: bool,
+  },
+  /// An event of the application, e.g. a state change of its controller.
+  User {
+    /// Identifies the kind of event, up to the application.
+    id: u16,
+    /// The value of the event.
+    value: u32,
+  },
+}
+
+impl Entry {
+  fn encode(&self) -> (u8, u8, u8, u32) {
+    let level = |state: PinState| (state == PinState::High) as u32;
+    match *self {
+      Entry::GpioWrite { pin, state } => (1, pin, 0, level(state)),
+      Entry::GpioEdge { pin, state } => (2, pin, 0, level(state)),
+      Entry::PwmPeriod { chip, num, period_ns } => (3, chip, num, period_ns),
+      Entry::PwmDutyCycle { chip, num, duty_cycle_ns } => (4, chip, num, duty_cycle_ns),
+      Entry::PwmEnabled { chip, num, enabled } => (5, chip, num, enabled as u32),
+      Entry::PwmPolarity { chip, num, inversed } => (6, chip, num, inversed as u32),
+      Entry::User { id, value } => (7, (id & 0xFF) as u8, (id >> 8) as u8, value),
+    }
+  }
+
+  fn decode(kind: u8, a: u8, b: u8, value: u32) -> Result<Entry> {
+    let state = if value == 0 { PinState::Low } else { PinState::High };
+    Ok(match kind {
+         1 => Entry::GpioWrite { pin: a, state },
+         2 => Entry::GpioEdge { pin: a, state },
+         3 => Entry::PwmPeriod { chip: a, num: b, period_ns: value },
+         4 => Entry::PwmDutyCycle { chip: a, num: b, duty_cycle_ns: value },
+         5 => Entry::PwmEnabled { chip: a, num: b, enabled: value != 0 },
+         6 => Entry::PwmPolarity { chip: a, num: b, inversed: value != 0 },
+         7 => Entry::User { id: u16::from(a) | u16::from(b) << 8, value },
+         _ => bail!("Unknown journal entry kind {}", kind),
+       })
+  }
+}
+
+/// An entry of the journal with the time it was recorded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Record {
+  /// When the entry was recorded.
+  pub time: SystemTime,
+  /// What happened.
+  pub entry: Entry,
+}
+
+/// Appends `entry` to the journal, if one is open.
+///
+/// The library records GPIO and PWM events by itself; this is for
+/// `Entry::User` events of the application.
+///
+/// # Examples
+///
+/// ```no_run
+/// use libbeaglebone::journal::{self, Entry};
+///
+/// // The controller switched to mode 3.
+/// journal::record(Entry::User { id: 1, value: 3 });
+/// ```
+pub fn record(entry: Entry) {
+  if !ACTIVE.load(Ordering::Relaxed) {
+    return;
+  }
+  let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+  let (kind, a, b, value) = entry.encode();
+  let mut buf = [0; RECORD_SIZE];
+  buf[..8].copy_from_slice(&(time.as_nanos() as u64).to_le_bytes());
+  buf[8] = kind;
+  buf[9] = a;
+  buf[10] = b;
+  buf[11..].copy_from_slice(&value.to_le_bytes());
+
+  let mut file = FILE.lock().unwrap_or_else(|e| e.into_inner());
+  if let Some(ref mut file) = *file {
+    // A single write keeps records whole in the appended file.
+    if file.write_all(&buf).is_err() {
+      let _ = WRITE_ERRORS.fetch_add(1, Ordering::Relaxed);
+    }
+  }
+}
+
+/// Reads all records of a journal file.
+///
+/// A record cut short at the end, e.g. by a power loss, is ignored.
+///
+/// # Errors
+///
+/// Fails if the file can't be read or isn't a journal.
+pub fn read<P: AsRef<Path>>(path: P) -> Result<Vec<Record>> {
+  let path = path.as_ref();
+  let mut data = Vec::new();
+  let _ = File::open(path)
+    .and_then(|mut file| file.read_to_end(&mut data))
+    .chain_err(|| format!("Failed to read journal {}", path.display()))?;
+  if !data.starts_with(HEADER) {
+    bail!("{} isn't a journal", path.display());
+  }
+
+  let mut records = Vec::new();
+  for chunk in data[HEADER.len()..].chunks_exact(RECORD_SIZE) {
+    let mut time = [0; 8];
+    time.copy_from_slice(&chunk[..8]);
+    let mut value = [0; 4];
+    value.copy_from_slice(&chunk[11..]);
+    records.push(Record {
+                   time: UNIX_EPOCH + Duration::from_nanos(u64::from_le_bytes(time)),
+                   entry: Entry::decode(chunk[8], chunk[9], chunk[10], u32::from_le_bytes(value))?,
+                 });
+  }
+  Ok(records)
+}
+
+/// An open journal.
+#[derive(Debug)]
+pub struct Journal {
+  _private: (),
+}
+
+impl Journal {
+  /// Opens the journal file at `path`, appending to it if it exists, and
+  /// starts recording.
+  ///
+  /// # Errors
+  ///
+  /// Fails if another journal is open already, or if the file can't be
+  /// opened or isn't a journal.
+  pub fn open<P: AsRef<Path>>(path: P) -> Result<Journal> {
+    let path = path.as_ref();
+    let mut current = FILE.lock().unwrap_or_else(|e| e.into_inner());
+    if current.is_some() {
+      bail!("A journal is open already");
+    }
+
+    let mut file = OpenOptions::new()
+      .read(true)
+      .append(true)
+      .create(true)
+      .open(path)
+      .chain_err(|| format!("Failed to open journal {}", path.display()))?;
+    let mut header = Vec::new();
+    let _ = (&mut file).take(HEADER.len() as u64)
+                       .read_to_end(&mut header)
+                       .chain_err(|| format!("Failed to read journal {}", path.display()))?;
+    if header.is_empty() {
+      file.write_all(HEADER)
+          .chain_err(|| format!("Failed to write journal {}", path.display()))?;
+    } else if header != HEADER {
+      bail!("{} isn't a journal", path.display());
+    }
+
+    *current = Some(file);
+    ACTIVE.store(true, Ordering::Relaxed);
+    Ok(Journal { _private: () })
+  }
+
+  /// Returns the number of entries that couldn't be written, e.g. because
+  /// the disk is full.
+  pub fn write_errors(&self) -> u64 {
+    WRITE_ERRORS.load(Ordering::Relaxed)
+  }
+
+  /// Waits until the entries so far are stored on disk.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the file can't be synced.
+  pub fn sync(&self) -> Result<()> {
+    match *FILE.lock().unwrap_or_else(|e| e.into_inner()) {
+      Some(ref file) => file.sync_data().chain_err(|| "Failed to sync the journal"),
+      None => Ok(()),
+    }
+  }
+}
+
+impl Drop for Journal {
+  fn drop(&mut self) {
+    ACTIVE.store(false, Ordering::Relaxed);
+    if let Some(file) = FILE.lock().unwrap_or_else(|e| e.into_inner()).take() {
+      let _ = file.sync_data();
+    }
+  }
+}
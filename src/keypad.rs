@@ -0,0 +1,214 @@
+//! The keypad module.
+//!
+//! A common way to read many buttons on one pin is a resistor ladder: each
+//! button connects a different resistor to a divider, so the ADC input
+//! reads a different voltage for each, and another one while none is
+//! pressed.
+//! A `Keypad` maps voltage bands on an `ADC` to named keys and reports when
+//! they are pressed and released:
+//!
+//! ```no_run
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::keypad::{KeyEvent, Keypad};
+//! use std::time::Duration;
+//!
+//! // The five buttons of a common LCD shield, on AIN0.
+//! let mut keypad = Keypad::new(ADC::new(AIN_0, 0.0));
+//! keypad.add_key("right", 0.0, 100.0).unwrap();
+//! keypad.add_key("up", 200.0, 400.0).unwrap();
+//! keypad.add_key("down", 500.0, 700.0).unwrap();
+//! keypad.add_key("left", 800.0, 1000.0).unwrap();
+//! keypad.add_key("select", 1100.0, 1400.0).unwrap();
+//!
+//! loop {
+//!   match keypad.wait(Duration::from_secs(1)).unwrap() {
+//!     Some(KeyEvent::Pressed(key)) => println!("{} pressed", key),
+//!     Some(KeyEvent::Released(key)) => println!("{} released", key),
+//!     None => {}
+//!   }
+//! }
+//! ```
+//!
+//! Readings outside all bands mean no key is pressed.
+//! A key has to be read in several samples in a row to count as pressed,
+//! and no key to count as released, so the voltage passing other bands
+//! while a contact closes or bounces doesn't press them.
+//! Only one key can be pressed at a time; on most ladders, pressing two
+//! reads as the one closer to the divider.
+
+use adc::ADC;
+use clock::{self, Clock};
+use errors::*;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A key of a keypad being pressed or released.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyEvent {
+  /// The named key was pressed.
+  Pressed(String),
+  /// The named key was released.
+  Released(String),
+}
+
+#[derive(Debug)]
+struct Key {
+  name: String,
+  min_mv: f32,
+  max_mv: f32,
+}
+
+/// Buttons on a resistor ladder, read through an ADC input.
+#[derive(Debug)]
+pub struct Keypad {
+  adc: ADC,
+  keys: Vec<Key>,
+  clock: Arc<dyn Clock>,
+  debounce: u32,
+  poll_interval: Duration,
+  // The index of the key reported pressed last.
+  pressed: Option<usize>,
+  // The key, or no key, seen in the last samples but not reported yet, and
+  // how often.
+  candidate: Option<(Option<usize>, u32)>,
+  events: VecDeque<KeyEvent>,
+}
+
+impl Keypad {
+  /// Creates a keypad without keys on `adc`, which debounces over 3
+  /// samples.
+  pub fn new(adc: ADC) -> Keypad {
+    Keypad {
+      adc,
+      keys: Vec::new(),
+      clock: clock::system(),
+      debounce: 3,
+      poll_interval: Duration::from_millis(10),
+      pressed: None,
+      candidate: None,
+      events: VecDeque::new(),
+    }
+  }
+
+  /// Adds the key `name`, which reads between `min_mv` and `max_mv`
+  /// millivolts, both included, while pressed.
+  ///
+  /// # Errors
+  ///
+  /// Fails if there's a key named `name` already, or if the band is empty
+  /// or overlaps the band of another key.
+  pub fn add_key(&mut self, name: &str, min_mv: f32, max_mv: f32) -> Result<()> {
+    if self.keys.iter().any(|key| key.name == name) {
+      bail!(format!("There is a key named {:?} already", name));
+    }
+    if min_mv.is_nan() || max_mv.is_nan() || min_mv > max_mv {
+      bail!(format!("The band {}mV to {}mV of key {:?} is empty", min_mv, max_mv, name));
+    }
+    if let Some(key) = self.keys.iter().find(|key| min_mv <= key.max_mv && key.min_mv <= max_mv) {
+      bail!(format!("The band of key {:?} overlaps the band of key {:?}", name, key.name));
+    }
+    self.keys.push(Key {
+      name: name.to_string(),
+      min_mv,
+      max_mv,
+    });
+    Ok(())
+  }
+
+  /// Sets how many samples in a row have to agree before a key counts as
+  /// pressed or released, 3 by default.
+  pub fn set_debounce(&mut self, samples: u32) {
+    self.debounce = samples.max(1);
+  }
+
+  /// Sets the time between samples in `wait()`, 10ms by default.
+  pub fn set_poll_interval(&mut self, poll_interval: Duration) {
+    self.poll_interval = poll_interval;
+  }
+
+  /// Makes the keypad take its time from `clock`, e.g. a `TestClock`.
+  pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+    self.clock = clock;
+  }
+
+  /// Returns the name of the key reported pressed last, if it wasn't
+  /// released since.
+  pub fn pressed(&self) -> Option<&str> {
+    self.pressed.map(|i| self.keys[i].name.as_str())
+  }
+
+  /// Returns the name of the key whose band `millivolts` is in, if any.
+  pub fn key_at(&self, millivolts: f32) -> Option<&str> {
+    self.band(millivolts).map(|i| self.keys[i].name.as_str())
+  }
+
+  /// Returns the ADC the keypad is read through.
+  pub fn adc(&self) -> &ADC {
+    &self.adc
+  }
+
+  /// Takes one sample, and returns the next event, if any.
+  ///
+  /// Going from one key to another directly gives two events, the second
+  /// is returned by the next call.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the ADC can't be read.
+  pub fn poll(&mut self) -> Result<Option<KeyEvent>> {
+    if let Some(event) = self.events.pop_front() {
+      return Ok(Some(event));
+    }
+    let key = self.band(self.adc.read_millivolts()?);
+    if key == self.pressed {
+      self.candidate = None;
+      return Ok(None);
+    }
+    let seen = match self.candidate {
+      Some((candidate, seen)) if candidate == key => seen + 1,
+      _ => 1,
+    };
+    if seen < self.debounce {
+      self.candidate = Some((key, seen));
+      return Ok(None);
+    }
+    self.candidate = None;
+    if let Some(released) = self.pressed {
+      self.events.push_back(KeyEvent::Released(self.keys[released].name.clone()));
+    }
+    if let Some(pressed) = key {
+      self.events.push_back(KeyEvent::Pressed(self.keys[pressed].name.clone()));
+    }
+    self.pressed = key;
+    Ok(self.events.pop_front())
+  }
+
+  /// Samples the keypad until a key is pressed or released, or until
+  /// `timeout` has passed.
+  ///
+  /// Returns the event, or `None` if the timeout expired.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the ADC can't be read.
+  pub fn wait(&mut self, timeout: Duration) -> Result<Option<KeyEvent>> {
+    let deadline = self.clock.now() + timeout;
+    loop {
+      if let Some(event) = self.poll()? {
+        return Ok(Some(event));
+      }
+      match deadline.checked_sub(self.clock.now()) {
+        Some(remaining) if remaining > Duration::from_secs(0) => {
+          self.clock.sleep(self.poll_interval.min(remaining))?
+        }
+        _ => return Ok(None),
+      }
+    }
+  }
+
+  /// Returns the index of the key whose band `millivolts` is in.
+  fn band(&self, millivolts: f32) -> Option<usize> {
+    self.keys.iter().position(|key| (key.min_mv..=key.max_mv).contains(&millivolts))
+  }
+}
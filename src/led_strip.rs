@@ -0,0 +1,203 @@
+//! The LED strip module.
+//!
+//! Addressable LED strips are drawn by filling a `FrameBuffer` with colors
+//! and showing it on a strip driver, so animations don't depend on the type
+//! of LEDs they run on.
+//!
+//! The `APA102` driver supports APA102 and SK9822 ("DotStar") strips.
+//! Unlike WS2812 LEDs, these have separate clock and data lines, so they work
+//! at any SPI clock speed and aren't upset by the gaps spidev leaves between
+//! transfers.
+//! Connect the strip's clock input to SCLK and data input to MOSI, through a
+//! 5V level shifter.
+//!
+//! The spidev bus has to be enabled beforehand, e.g. with
+//! `sudo config-pin overlay BB-SPIDEV0`.
+
+use errors::*;
+use spi::*;
+
+/// An RGB color with 8 bits per channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Color {
+  /// The red channel.
+  pub r: u8,
+  /// The green channel.
+  pub g: u8,
+  /// The blue channel.
+  pub b: u8,
+}
+
+impl Color {
+  /// Creates a new color.
+  pub fn new(r: u8, g: u8, b: u8) -> Color {
+    Color { r, g, b }
+  }
+}
+
+/// The colors of all LEDs of a strip.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameBuffer {
+  pixels: Vec<Color>,
+}
+
+impl FrameBuffer {
+  /// Creates a frame buffer for `len` LEDs, all of them off.
+  pub fn new(len: usize) -> FrameBuffer {
+    FrameBuffer { pixels: vec![Color::default(); len] }
+  }
+
+  /// Returns the number of LEDs.
+  pub fn len(&self) -> usize {
+    self.pixels.len()
+  }
+
+  /// Returns whether the frame buffer has no LEDs.
+  pub fn is_empty(&self) -> bool {
+    self.pixels.is_empty()
+  }
+
+  /// Sets the color of the LED at `index`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if there is no LED at that index.
+  pub fn set(&mut self, index: usize, color: Color) -> Result<()> {
+    let len = self.pixels.len();
+    match self.pixels.get_mut(index) {
+      Some(pixel) => *pixel = color,
+      None => bail!(format!("LED #{} is outside of the {} LED frame buffer", index, len)),
+    }
+    Ok(())
+  }
+
+  /// Returns the color of the LED at `index`.
+  pub fn get(&self, index: usize) -> Option<Color> {
+    self.pixels.get(index).cloned()
+  }
+
+  /// Sets all LEDs to `color`.
+  pub fn fill(&mut self, color: Color) {
+    for pixel in &mut self.pixels {
+      *pixel = color;
+    }
+  }
+
+  /// Turns all LEDs off.
+  pub fn clear(&mut self) {
+    self.fill(Color::default());
+  }
+
+  /// Returns the colors of all LEDs.
+  pub fn pixels(&self) -> &[Color] {
+    &self.pixels
+  }
+
+  /// Returns the colors of all LEDs for modification.
+  pub fn pixels_mut(&mut self) -> &mut [Color] {
+    &mut self.pixels
+  }
+}
+
+/// A driver that can show a frame buffer on a strip.
+pub trait LedStrip {
+  /// Shows the frame buffer on the strip.
+  fn show(&mut self, frame: &FrameBuffer) -> Result<()>;
+}
+
+/// The largest global brightness of an APA102.
+pub const APA102_MAX_BRIGHTNESS: u8 = 31;
+
+/// Represents an APA102 LED strip on a SPI bus.
+#[derive(Debug)]
+pub struct APA102 {
+  spi: SPI,
+  brightness: u8,
+}
+
+impl APA102 {
+  /// Creates a new APA102 strip driver at full brightness.
+  ///
+  /// The bus is switched to SPI mode 0; its clock speed is left as is.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::led_strip::{APA102, Color, FrameBuffer, LedStrip};
+  /// use libbeaglebone::spi::SPI;
+  ///
+  /// let spi = SPI::new(1).unwrap();
+  /// spi.set_max_speed_hz(8_000_000).unwrap();
+  /// let mut strip = APA102::new(spi).unwrap();
+  /// strip.set_brightness(8).unwrap();
+  ///
+  /// let mut frame = FrameBuffer::new(60);
+  /// frame.fill(Color::new(255, 64, 0));
+  /// frame.set(0, Color::new(0, 0, 255)).unwrap();
+  /// strip.show(&frame).unwrap();
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if the bus's mode can't be set.
+  pub fn new(spi: SPI) -> Result<APA102> {
+    spi.set_mode(SPI_MODE_0)?;
+    Ok(APA102 {
+      spi,
+      brightness: APA102_MAX_BRIGHTNESS,
+    })
+  }
+
+  /// Returns the global brightness.
+  pub fn brightness(&self) -> u8 {
+    self.brightness
+  }
+
+  /// Sets the global brightness (0-31), taking effect with the next frame.
+  ///
+  /// The LEDs dim by lowering their current rather than their PWM, so lower
+  /// brightness keeps the full color resolution.
+  ///
+  /// # Errors
+  ///
+  /// Fails if `brightness` exceeds 31.
+  pub fn set_brightness(&mut self, brightness: u8) -> Result<()> {
+    if brightness > APA102_MAX_BRIGHTNESS {
+      bail!(format!("APA102 brightness {} exceeds 31", brightness));
+    }
+    self.brightness = brightness;
+    Ok(())
+  }
+
+  /// Encodes a frame buffer as it is sent to the strip.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use libbeaglebone::led_strip::{APA102, Color, FrameBuffer};
+  ///
+  /// let mut frame = FrameBuffer::new(1);
+  /// frame.set(0, Color::new(1, 2, 3)).unwrap();
+  /// assert_eq!(APA102::encode(&frame, 31), [0, 0, 0, 0, 0xFF, 3, 2, 1, 0]);
+  /// ```
+  pub fn encode(frame: &FrameBuffer, brightness: u8) -> Vec<u8> {
+    // The data is delayed by half a clock cycle per LED, so the end frame
+    // needs at least half a clock pulse per LED to push it all through.
+    let end_frame = frame.len().div_ceil(16);
+    let mut data = Vec::with_capacity(4 + 4 * frame.len() + end_frame);
+    data.extend_from_slice(&[0; 4]);
+    for pixel in frame.pixels() {
+      data.extend_from_slice(&[0xE0 | brightness & APA102_MAX_BRIGHTNESS, pixel.b, pixel.g, pixel.r]);
+    }
+    data.resize(data.len() + end_frame, 0);
+    data
+  }
+}
+
+impl LedStrip for APA102 {
+  fn show(&mut self, frame: &FrameBuffer) -> Result<()> {
+    self.spi
+        .write_chunked(&APA102::encode(frame, self.brightness))
+        .chain_err(|| "Failed to send frame to APA102 strip")
+  }
+}
@@ -50,6 +50,81 @@ pub mod uart;
 pub mod i2c;
 pub mod spi;
 pub mod pins;
+pub mod tach;
+pub mod power;
+pub mod relay;
+pub mod pwm_input;
+pub mod xbee;
+pub mod dynamixel;
+pub mod motor_controller;
+pub mod i2c_mux;
+pub mod i2c_bus;
+pub mod spi_bus;
+pub mod dbc;
+pub mod lin;
+pub mod mmio;
+pub mod ehrpwm;
+pub mod timing;
+pub mod led_strip;
+pub mod hal;
+pub mod capemgr;
+pub mod attr_watch;
+pub mod device;
+pub mod healthcheck;
+pub mod calibration;
+pub mod board;
+pub mod stats;
+pub mod soak;
+pub mod vcd;
+pub mod heartbeat;
+pub mod actuator;
+pub mod limiter;
+pub mod journal;
+pub mod clock;
+pub mod analog_watch;
+pub mod reservation;
+pub mod servo;
+pub mod ramp;
+pub mod dither;
+pub mod soft_pwm;
+pub mod transaction;
+pub mod pwm_capture;
+pub mod pwm_group;
+pub mod follow;
+pub mod scheduler;
+pub mod tone;
+pub mod esc;
+pub mod servo_bus;
+pub mod motor;
+pub mod estop;
+pub mod limits;
+pub mod units;
+pub mod sweep;
+pub mod parallel_bus;
+pub mod reactor;
+pub mod selector;
+pub mod keypad;
+pub mod gpio_cdev;
+pub mod fast_gpio;
+pub mod servo_rail;
+pub mod debounce;
+pub mod bringup;
+pub mod arbitration;
+pub mod gpio_group;
+pub mod pattern;
+pub mod poller;
+pub mod encoder;
+pub mod button;
+pub mod shift_register;
+pub mod stub;
+pub mod sensors;
+pub mod pulse_dial;
+pub mod w1;
+pub mod thermostat;
+pub mod mcp23017;
+pub mod pca9685;
+pub mod homing;
+pub mod closed_loop_motor;
 
 /// Exports types that might be useful to have in scope.
 ///
@@ -60,10 +135,15 @@ pub mod pins;
 /// ```
 pub mod prelude {
   pub use adc::ADC;
+  pub use device::Device;
   pub use enums::DeviceState;
-  pub use gpio::{GPIO, PinDirection, PinState};
+  pub use gpio::{Edge, GPIO, PinDirection, PinState, Pull};
+  pub use hal::{DigitalPin, PwmOutput};
   pub use i2c::I2C;
-  pub use pwm::{PWM, PWMState};
+  pub use pwm::{PWM, PWMPolarity, PWMState};
+  pub use relay::Relay;
   pub use uart::UART;
+  pub use units::{DutyCycle, Hertz};
   pub use pins::Pin::*;
+  pub use tach::Tachometer;
 }
@@ -0,0 +1,247 @@
+//! The output limiter module.
+//!
+//! Motors, servos and heaters don't take every command well: a bug in the
+//! control logic that flips the duty cycle between 0% and 100% a thousand
+//! times a second strips gears just as well as a correct but aggressive
+//! controller does.
+//! The limiters wrap a `PwmOutput` and enforce limits on the duty cycle
+//! whatever is commanded:
+//!
+//! * `RateLimited` writes the duty cycle at most once per interval.
+//! * `SlewLimited` changes the duty cycle by at most a given number of
+//!   percentage points per second.
+//!
+//! Both are `PwmOutput`s themselves, so they can be stacked and passed to
+//! anything accepting a PWM output:
+//!
+//! ```no_run
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::hal::PwmOutput;
+//! use libbeaglebone::limiter::{RateLimited, SlewLimited};
+//! use std::time::Duration;
+//!
+//! let mut pwm = PWM::new(0, 0);
+//! pwm.set_export(DeviceState::Exported).unwrap();
+//! pwm.set_period(500_000).unwrap();
+//! pwm.set_state(PWMState::Enabled).unwrap();
+//!
+//! // Go from 0% to 100% in no less than two seconds, at 50 updates per
+//! // second.
+//! let slewed = SlewLimited::new(pwm, 50.0);
+//! let mut motor = RateLimited::new(slewed, Duration::from_millis(20));
+//! loop {
+//!   motor.set_duty_cycle_percent(100.0).unwrap();
+//! }
+//! ```
+//!
+//! Period changes and enabling or disabling the output are passed on right
+//! away, so an output can always be stopped.
+
+use clock::{self, Clock};
+use errors::*;
+use hal::PwmOutput;
+use pwm::PWM;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A PWM output whose duty cycle is written at most once per interval.
+///
+/// A duty cycle commanded too soon after the previous write is kept and
+/// written by the first call to `set_duty_cycle_percent()` or `update()`
+/// after the interval has passed, so the latest command always takes effect
+/// eventually.
+#[derive(Debug)]
+pub struct RateLimited<P: PwmOutput = PWM> {
+  output: P,
+  clock: Arc<dyn Clock>,
+  min_interval: Duration,
+  last_write: Option<Duration>,
+  pending: Option<f32>,
+}
+
+impl<P: PwmOutput> RateLimited<P> {
+  /// Wraps `output`, writing its duty cycle at most once per `min_interval`.
+  pub fn new(output: P, min_interval: Duration) -> RateLimited<P> {
+    RateLimited {
+      output,
+      clock: clock::system(),
+      min_interval,
+      last_write: None,
+      pending: None,
+    }
+  }
+
+  /// Makes the limiter take its time from `clock`, e.g. a `TestClock`.
+  pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+    self.clock = clock;
+    self.last_write = None;
+  }
+
+  /// Writes the duty cycle that was held back, if the interval has passed.
+  ///
+  /// Call this from the control loop when it may stop commanding the output
+  /// for a while.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the duty cycle can't be written.
+  pub fn update(&mut self) -> Result<()> {
+    let due = self.last_write.is_none_or(|at| self.clock.elapsed_since(at) >= self.min_interval);
+    if let Some(percentage) = self.pending {
+      if due {
+        self.output.set_duty_cycle_percent(percentage)?;
+        self.pending = None;
+        self.last_write = Some(self.clock.now());
+      }
+    }
+    Ok(())
+  }
+
+  /// Returns whether a duty cycle is held back.
+  pub fn is_pending(&self) -> bool {
+    self.pending.is_some()
+  }
+
+  /// Returns the wrapped output.
+  pub fn get_ref(&self) -> &P {
+    &self.output
+  }
+
+  /// Unwraps the output, dropping a duty cycle that was held back.
+  pub fn into_inner(self) -> P {
+    self.output
+  }
+}
+
+impl<P: PwmOutput> PwmOutput for RateLimited<P> {
+  fn set_period_ns(&mut self, period_ns: u32) -> Result<()> {
+    self.output.set_period_ns(period_ns)
+  }
+
+  fn set_duty_cycle_percent(&mut self, percentage: f32) -> Result<()> {
+    self.pending = Some(percentage);
+    self.update()
+  }
+
+  fn set_enabled(&mut self, enabled: bool) -> Result<()> {
+    self.output.set_enabled(enabled)
+  }
+}
+
+/// A PWM output whose duty cycle changes by at most a given rate.
+///
+/// Each call to `set_duty_cycle_percent()` moves the duty cycle towards the
+/// commanded one as far as the time since the previous write allows, so the
+/// commanded duty cycle is reached by calling it, or `update()`, repeatedly.
+#[derive(Debug)]
+pub struct SlewLimited<P: PwmOutput = PWM> {
+  output: P,
+  clock: Arc<dyn Clock>,
+  max_rate: f32,
+  current: f32,
+  target: f32,
+  last_write: Option<Duration>,
+}
+
+impl<P: PwmOutput> SlewLimited<P> {
+  /// Wraps `output`, changing its duty cycle by at most `max_rate`
+  /// percentage points per second.
+  ///
+  /// The duty cycle is assumed to be 0% to begin with; use `with_current()`
+  /// if it isn't.
+  pub fn new(output: P, max_rate: f32) -> SlewLimited<P> {
+    SlewLimited::with_current(output, max_rate, 0.0)
+  }
+
+  /// Wraps `output` like `new()`, with a duty cycle of `current` percent to
+  /// begin with.
+  pub fn with_current(output: P, max_rate: f32, current: f32) -> SlewLimited<P> {
+    SlewLimited {
+      output,
+      clock: clock::system(),
+      max_rate: max_rate.abs(),
+      current,
+      target: current,
+      last_write: None,
+    }
+  }
+
+  /// Makes the limiter take its time from `clock`, e.g. a `TestClock`.
+  pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+    self.clock = clock;
+    self.last_write = None;
+  }
+
+  /// Moves the duty cycle towards the commanded one.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the duty cycle can't be written.
+  pub fn update(&mut self) -> Result<()> {
+    if self.current == self.target {
+      // Time spent settled mustn't count towards the next change.
+      self.last_write = None;
+      return Ok(());
+    }
+    let now = self.clock.now();
+    // The first step is taken a moment after a change is commanded, so it
+    // doesn't jump.
+    let elapsed = match self.last_write {
+      Some(at) => now.checked_sub(at).unwrap_or_default(),
+      None => {
+        self.last_write = Some(now);
+        return Ok(());
+      }
+    };
+    let max_step = self.max_rate * elapsed.as_secs_f32();
+    let step = (self.target - self.current).max(-max_step).min(max_step);
+    if step == 0.0 {
+      return Ok(());
+    }
+    let next = self.current + step;
+    self.output.set_duty_cycle_percent(next)?;
+    self.current = next;
+    self.last_write = Some(now);
+    Ok(())
+  }
+
+  /// Returns the duty cycle that was written last.
+  pub fn current(&self) -> f32 {
+    self.current
+  }
+
+  /// Returns the commanded duty cycle.
+  pub fn target(&self) -> f32 {
+    self.target
+  }
+
+  /// Returns whether the commanded duty cycle has been reached.
+  pub fn is_settled(&self) -> bool {
+    self.current == self.target
+  }
+
+  /// Returns the wrapped output.
+  pub fn get_ref(&self) -> &P {
+    &self.output
+  }
+
+  /// Unwraps the output.
+  pub fn into_inner(self) -> P {
+    self.output
+  }
+}
+
+impl<P: PwmOutput> PwmOutput for SlewLimited<P> {
+  fn set_period_ns(&mut self, period_ns: u32) -> Result<()> {
+    self.output.set_period_ns(period_ns)
+  }
+
+  fn set_duty_cycle_percent(&mut self, percentage: f32) -> Result<()> {
+    self.target = percentage;
+    self.update()
+  }
+
+  fn set_enabled(&mut self, enabled: bool) -> Result<()> {
+    self.output.set_enabled(enabled)
+  }
+}
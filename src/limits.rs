@@ -0,0 +1,224 @@
+//! The limits module.
+//!
+//! Machines with moving axes have switches at the ends of their travel, and
+//! often one at a home position to reference against.
+//! `Limits` groups these switches by axis and debounces them, so the code
+//! moving an axis only asks whether it may move on:
+//!
+//! ```no_run
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::limits::{LimitKind, Limits, Travel};
+//! use libbeaglebone::motor::Motor;
+//!
+//! let limits = Limits::new();
+//! limits.add("x-min", "x", LimitKind::Min, GPIO_P8_7, PinState::Low).unwrap();
+//! limits.add("x-max", "x", LimitKind::Max, GPIO_P8_8, PinState::Low).unwrap();
+//! limits.add("x-home", "x", LimitKind::Home, GPIO_P8_9, PinState::Low).unwrap();
+//!
+//! // The motor brakes instead of driving into an active limit switch.
+//! let mut motor = Motor::new(3, 0, GPIO_P8_11, GPIO_P8_12).unwrap();
+//! motor.set_limits(limits.clone(), "x");
+//!
+//! // Home by moving towards the minimum until the home switch closes.
+//! motor.reverse(0.2).unwrap();
+//! while !limits.is_home("x") {
+//!   if motor.check_limits().unwrap() {
+//!     panic!("Hit the minimum limit switch before the home switch");
+//!   }
+//! }
+//! motor.brake().unwrap();
+//! assert_eq!(limits.blocking("x", Travel::Positive), None);
+//! ```
+//!
+//! The switches are sampled on `update()`, which the motor calls on every
+//! command and `check_limits()`; a switch only counts as changed after the
+//! same new level was seen in several samples in a row.
+//! A `Limits` is a handle, its clones share the same switches.
+//!
+//! Use normally-closed switches where possible, with `active` being the
+//! level while the switch is open: then a broken wire reads as an active
+//! limit instead of a switch that never trips.
+
+use enums::DeviceState;
+use errors::*;
+use gpio::{GPIO, PinDirection, PinState};
+use pins::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// What a switch marks on its axis.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitKind {
+  /// The end of travel in the negative direction.
+  Min,
+  /// The end of travel in the positive direction.
+  Max,
+  /// The home position, which doesn't block any motion.
+  Home,
+}
+
+/// A direction of travel on an axis.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Travel {
+  /// Towards the `Max` switch.
+  Positive,
+  /// Towards the `Min` switch.
+  Negative,
+}
+
+#[derive(Debug)]
+struct Switch {
+  name: String,
+  axis: String,
+  kind: LimitKind,
+  input: GPIO,
+  active_state: PinState,
+  active: bool,
+  // How often in a row the opposite of `active` was seen.
+  seen: u32,
+}
+
+#[derive(Debug)]
+struct Inner {
+  switches: Vec<Switch>,
+  debounce: u32,
+}
+
+/// Limit and home switches on GPIO inputs, grouped by axis.
+#[derive(Debug, Clone)]
+pub struct Limits {
+  inner: Arc<Mutex<Inner>>,
+}
+
+impl Limits {
+  /// Creates a group without switches, which debounces over 3 samples.
+  pub fn new() -> Limits {
+    Limits {
+      inner: Arc::new(Mutex::new(Inner {
+        switches: Vec::new(),
+        debounce: 3,
+      })),
+    }
+  }
+
+  fn inner(&self) -> MutexGuard<'_, Inner> {
+    self.inner.lock().unwrap_or_else(|e| e.into_inner())
+  }
+
+  /// Adds the switch `name` of kind `kind` on `axis`, on the GPIO `pin`
+  /// reading `active` while the switch is tripped.
+  ///
+  /// The pin is exported and made an input, and its level taken as is,
+  /// without debouncing.
+  ///
+  /// # Errors
+  ///
+  /// Fails if there's a switch named `name` already, or if the pin can't be
+  /// configured or read.
+  pub fn add(&self, name: &str, axis: &str, kind: LimitKind, pin: Pin, active: PinState) -> Result<()> {
+    let mut inner = self.inner();
+    if inner.switches.iter().any(|switch| switch.name == name) {
+      bail!(format!("There is a limit switch named {:?} already", name));
+    }
+    let input = GPIO::new(pin);
+    input.set_export(DeviceState::Exported)?;
+    input.set_direction(PinDirection::In)?;
+    let level = input.read()?;
+    inner.switches.push(Switch {
+      name: name.to_string(),
+      axis: axis.to_string(),
+      kind,
+      input,
+      active_state: active,
+      active: level == active,
+      seen: 0,
+    });
+    Ok(())
+  }
+
+  /// Sets how many samples in a row have to show a switch changed, 3 by
+  /// default.
+  pub fn set_debounce(&self, samples: u32) {
+    self.inner().debounce = samples.max(1);
+  }
+
+  /// Samples all switches.
+  ///
+  /// # Errors
+  ///
+  /// Fails if an input can't be read, after sampling the others.
+  pub fn update(&self) -> Result<()> {
+    let mut inner = self.inner();
+    let debounce = inner.debounce;
+    let mut first_error = None;
+    for switch in &mut inner.switches {
+      match switch.input.read() {
+        Ok(level) if (level == switch.active_state) != switch.active => {
+          switch.seen += 1;
+          if switch.seen >= debounce {
+            switch.active = !switch.active;
+            switch.seen = 0;
+          }
+        }
+        Ok(_) => switch.seen = 0,
+        Err(e) => {
+          let e = Error::with_chain(e, format!("Failed to read limit switch {:?}", switch.name));
+          first_error = first_error.or(Some(e));
+        }
+      }
+    }
+    match first_error {
+      Some(e) => Err(e),
+      None => Ok(()),
+    }
+  }
+
+  /// Returns whether the switch `name` was tripped when last sampled.
+  ///
+  /// # Errors
+  ///
+  /// Fails if there's no switch named `name`.
+  pub fn is_active(&self, name: &str) -> Result<bool> {
+    match self.inner().switches.iter().find(|switch| switch.name == name) {
+      Some(switch) => Ok(switch.active),
+      None => bail!(format!("There is no limit switch named {:?}", name)),
+    }
+  }
+
+  /// Returns whether a home switch of `axis` was tripped when last sampled.
+  pub fn is_home(&self, axis: &str) -> bool {
+    self.inner()
+        .switches
+        .iter()
+        .any(|switch| switch.axis == axis && switch.kind == LimitKind::Home && switch.active)
+  }
+
+  /// Returns the name of a tripped switch that forbids moving `axis` in the
+  /// direction `travel`, if any.
+  pub fn blocking(&self, axis: &str, travel: Travel) -> Option<String> {
+    let kind = match travel {
+      Travel::Positive => LimitKind::Max,
+      Travel::Negative => LimitKind::Min,
+    };
+    self.inner()
+        .switches
+        .iter()
+        .find(|switch| switch.axis == axis && switch.kind == kind && switch.active)
+        .map(|switch| switch.name.clone())
+  }
+
+  /// Returns the names of all switches tripped when last sampled.
+  pub fn tripped(&self) -> Vec<String> {
+    self.inner()
+        .switches
+        .iter()
+        .filter(|switch| switch.active)
+        .map(|switch| switch.name.clone())
+        .collect()
+  }
+}
+
+impl Default for Limits {
+  fn default() -> Limits {
+    Limits::new()
+  }
+}
@@ -0,0 +1,402 @@
+//! The LIN module.
+//!
+//! LIN is a single-wire automotive bus for body electronics like switches,
+//! window lifters and climate control flaps.
+//! `LinMaster` implements the master node of a LIN 2.x cluster on top of a
+//! UART connected to a LIN transceiver such as the TJA1021 or MCP2003, with an
+//! optional GPIO that enables the transceiver.
+//!
+//! The UART hardware can't send the long break that starts every frame, so
+//! it is emulated by sending a 0x00 byte at half the baud rate, which keeps
+//! the bus dominant for 18 bit times.
+//!
+//! The UART has to be enabled beforehand, see the `uart` module
+//! documentation.
+//! Since the transceiver echoes everything onto the RX line, the echo is
+//! checked to detect bus errors.
+
+use errors::*;
+use gpio::{self, GPIO, PinState};
+use pins::Pin;
+use serialport::prelude::*;
+use std::thread;
+use std::time::{Duration, Instant};
+use uart::UART;
+
+/// The sync byte that follows the break of every frame.
+const SYNC: u8 = 0x55;
+
+/// The frame IDs of master request and slave response diagnostic frames,
+/// which always use the classic checksum.
+const DIAGNOSTIC_IDS: [u8; 2] = [0x3C, 0x3D];
+
+/// The checksum model of a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumType {
+  /// LIN 1.x checksum over the data bytes only.
+  Classic,
+  /// LIN 2.x checksum over the protected ID and the data bytes.
+  Enhanced,
+}
+
+/// Returns the protected ID of a frame ID, i.e. the ID with its two parity
+/// bits.
+///
+/// # Examples
+///
+/// ```
+/// use libbeaglebone::lin::protected_id;
+///
+/// assert_eq!(protected_id(0x3C).unwrap(), 0x3C);
+/// assert_eq!(protected_id(0x10).unwrap(), 0x50);
+/// assert!(protected_id(0x40).is_err());
+/// ```
+///
+/// # Errors
+///
+/// Fails if `id` isn't within 0-63.
+pub fn protected_id(id: u8) -> Result<u8> {
+  if id > 0x3F {
+    bail!(format!("LIN frame ID {} is outside of 0-63", id));
+  }
+  let bit = |n: u8| id >> n & 1;
+  let p0 = bit(0) ^ bit(1) ^ bit(2) ^ bit(4);
+  let p1 = !(bit(1) ^ bit(3) ^ bit(4) ^ bit(5)) & 1;
+  Ok(id | p0 << 6 | p1 << 7)
+}
+
+/// Computes the checksum of a frame.
+///
+/// The diagnostic frames 0x3C and 0x3D always use the classic checksum.
+///
+/// # Examples
+///
+/// ```
+/// use libbeaglebone::lin::{checksum, ChecksumType};
+///
+/// assert_eq!(checksum(0x4A, &[0x55, 0x93, 0xE5], ChecksumType::Enhanced), 0xE6);
+/// assert_eq!(checksum(0x4A, &[0x55, 0x93, 0xE5], ChecksumType::Classic), 0x31);
+/// ```
+pub fn checksum(pid: u8, data: &[u8], checksum_type: ChecksumType) -> u8 {
+  let enhanced = checksum_type == ChecksumType::Enhanced &&
+                 !DIAGNOSTIC_IDS.contains(&(pid & 0x3F));
+  let initial = if enhanced { u16::from(pid) } else { 0 };
+  let sum = data.iter().fold(initial, |sum, &b| {
+    // Add with end-around carry.
+    let sum = sum + u16::from(b);
+    if sum > 0xFF { sum - 0xFF } else { sum }
+  });
+  !(sum as u8)
+}
+
+/// Represents the master node of a LIN cluster.
+#[derive(Debug)]
+pub struct LinMaster {
+  uart: UART,
+  baud_rate: BaudRate,
+  break_baud_rate: BaudRate,
+  enable_pin: Option<GPIO>,
+}
+
+impl LinMaster {
+  /// Creates a new LIN master on an UART, with the bus running at
+  /// `baud_rate`, typically 19200 or 9600 baud.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// # extern crate libbeaglebone;
+  /// # extern crate serialport;
+  /// use libbeaglebone::prelude::*;
+  /// use libbeaglebone::lin::LinMaster;
+  /// use serialport::BaudRate;
+  ///
+  /// # fn main() {
+  /// let mut lin = LinMaster::new(UART::new(1).unwrap(), BaudRate::Baud19200).unwrap();
+  /// # }
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if the UART can't be configured for 8N1 at `baud_rate`.
+  pub fn new(mut uart: UART, baud_rate: BaudRate) -> Result<LinMaster> {
+    uart.set_baud_rate(baud_rate)?;
+    uart.set_data_bits(DataBits::Eight)?;
+    uart.set_parity(Parity::None)?;
+    uart.set_stop_bits(StopBits::One)?;
+    uart.set_flow_control(FlowControl::None)?;
+    Ok(LinMaster {
+      uart,
+      baud_rate,
+      break_baud_rate: BaudRate::from_speed(baud_rate.speed() / 2),
+      enable_pin: None,
+    })
+  }
+
+  /// Uses a GPIO to enable the transceiver, e.g. the SLP_N pin of a TJA1021.
+  ///
+  /// The pin is exported, configured as an output and driven high right
+  /// away.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the pin can't be configured as a GPIO output.
+  pub fn set_enable_pin(&mut self, pin: Pin) -> Result<()> {
+    self.enable_pin = Some(gpio::output_driving(pin, PinState::High)?);
+    Ok(())
+  }
+
+  /// Publishes a frame, i.e. sends its header followed by `data`.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// # extern crate libbeaglebone;
+  /// # extern crate serialport;
+  /// use libbeaglebone::prelude::*;
+  /// use libbeaglebone::lin::{ChecksumType, LinMaster};
+  /// use serialport::BaudRate;
+  ///
+  /// # fn main() {
+  /// let mut lin = LinMaster::new(UART::new(1).unwrap(), BaudRate::Baud19200).unwrap();
+  /// lin.set_enable_pin(GPIO_P8_11).unwrap();
+  ///
+  /// lin.send_frame(0x10, &[0x01, 0x80], ChecksumType::Enhanced).unwrap();
+  /// let status = lin.request_frame(0x11, 4, ChecksumType::Enhanced).unwrap();
+  /// # }
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if `id` isn't within 0-63, if `data` isn't 1 to 8 bytes long, or if
+  /// the frame isn't echoed back correctly, e.g. because of a collision or a
+  /// bus fault.
+  pub fn send_frame(&mut self, id: u8, data: &[u8], checksum_type: ChecksumType) -> Result<()> {
+    check_length(data.len())?;
+    let pid = self.send_header(id)?;
+    let mut response = data.to_vec();
+    response.push(checksum(pid, data, checksum_type));
+    self.write_checked(&response)
+        .chain_err(|| format!("Failed to send LIN frame 0x{:02X}", id))
+  }
+
+  /// Requests a frame from a slave, i.e. sends its header and reads `len`
+  /// data bytes.
+  ///
+  /// The UART's timeout limits how long to wait for the slave.
+  ///
+  /// # Errors
+  ///
+  /// Fails if `id` isn't within 0-63, if `len` isn't within 1-8, if no slave
+  /// responds, or if the response has a bad checksum.
+  pub fn request_frame(&mut self, id: u8, len: usize, checksum_type: ChecksumType) -> Result<Vec<u8>> {
+    check_length(len)?;
+    let pid = self.send_header(id)?;
+    let mut response = vec![0; len + 1];
+    self.uart
+        .read_exact(&mut response)
+        .chain_err(|| format!("No response to LIN frame 0x{:02X}", id))?;
+    let received = response.pop().unwrap_or(0);
+    if received != checksum(pid, &response, checksum_type) {
+      bail!(format!("LIN frame 0x{:02X} has a bad checksum", id));
+    }
+    Ok(response)
+  }
+
+  /// Sends the break, sync and protected ID of a frame and returns the
+  /// protected ID.
+  fn send_header(&mut self, id: u8) -> Result<u8> {
+    let pid = protected_id(id)?;
+
+    // A 0x00 at half the baud rate is 9 dominant bits, i.e. 18 bit times at
+    // the bus's baud rate, followed by a 2 bit time break delimiter.
+    self.uart.set_baud_rate(self.break_baud_rate)?;
+    let sent = self.uart.write_bytes(&[0x00]).and_then(|_| self.uart.flush());
+    self.uart.set_baud_rate(self.baud_rate)?;
+    sent.chain_err(|| "Failed to send LIN break")?;
+    // The echo of the break reads as garbage at the bus's baud rate.
+    self.uart.clear_input()?;
+
+    self.write_checked(&[SYNC, pid])
+        .chain_err(|| format!("Failed to send LIN header 0x{:02X}", id))?;
+    Ok(pid)
+  }
+
+  /// Writes bytes to the bus and checks their echo.
+  fn write_checked(&mut self, data: &[u8]) -> Result<()> {
+    self.uart.write_bytes(data)?;
+    let mut echo = vec![0; data.len()];
+    self.uart.read_exact(&mut echo).chain_err(|| "LIN transceiver didn't echo the data")?;
+    if echo != data {
+      bail!("Bus error, the LIN transceiver echoed different data");
+    }
+    Ok(())
+  }
+
+  /// Runs the next entry of a schedule table and waits for the end of its
+  /// slot.
+  ///
+  /// Returns the ID and data of subscribed frames.
+  /// Slots are timed from absolute deadlines, so a schedule keeps its rate
+  /// even if some frames take longer.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// # extern crate libbeaglebone;
+  /// # extern crate serialport;
+  /// use libbeaglebone::prelude::*;
+  /// use libbeaglebone::lin::{ChecksumType, LinMaster, ScheduleTable};
+  /// use serialport::BaudRate;
+  /// use std::time::Duration;
+  ///
+  /// # fn main() {
+  /// let mut lin = LinMaster::new(UART::new(1).unwrap(), BaudRate::Baud19200).unwrap();
+  ///
+  /// let mut schedule = ScheduleTable::new(ChecksumType::Enhanced);
+  /// schedule.publish(0x10, &[0x00, 0x00], Duration::from_millis(10)).unwrap();
+  /// schedule.subscribe(0x11, 4, Duration::from_millis(10)).unwrap();
+  ///
+  /// loop {
+  ///   match lin.run_slot(&mut schedule) {
+  ///     Ok(Some((id, data))) => println!("Frame 0x{:02X}: {:?}", id, data),
+  ///     Ok(None) => {}
+  ///     Err(e) => println!("LIN error: {}", e),
+  ///   }
+  /// }
+  /// # }
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if the schedule table is empty, or if the frame fails.
+  /// The slot is waited for in either case, and the next call continues with
+  /// the following entry.
+  pub fn run_slot(&mut self, table: &mut ScheduleTable) -> Result<Option<(u8, Vec<u8>)>> {
+    if table.entries.is_empty() {
+      bail!("LIN schedule table is empty");
+    }
+
+    let now = Instant::now();
+    let start = match table.next_deadline {
+      // Resynchronize if the schedule fell behind by more than a slot.
+      Some(deadline) if deadline + table.entries[table.next].slot > now => deadline,
+      _ => now,
+    };
+    if start > now {
+      thread::sleep(start - now);
+    }
+
+    let index = table.next;
+    table.next = (index + 1) % table.entries.len();
+    table.next_deadline = Some(start + table.entries[index].slot);
+
+    let checksum_type = table.checksum_type;
+    let entry = &table.entries[index];
+    match entry.frame {
+      ScheduleFrame::Publish(ref data) => {
+        self.send_frame(entry.id, data, checksum_type)?;
+        Ok(None)
+      }
+      ScheduleFrame::Subscribe(len) => {
+        let data = self.request_frame(entry.id, len, checksum_type)?;
+        Ok(Some((entry.id, data)))
+      }
+    }
+  }
+}
+
+fn check_length(len: usize) -> Result<()> {
+  if len == 0 || len > 8 {
+    bail!(format!("LIN frames carry 1 to 8 data bytes, not {}", len));
+  }
+  Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ScheduleFrame {
+  Publish(Vec<u8>),
+  Subscribe(usize),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ScheduleEntry {
+  id: u8,
+  frame: ScheduleFrame,
+  slot: Duration,
+}
+
+/// A LIN schedule table, i.e. the frames the master runs in turn, each in a
+/// slot of fixed length.
+#[derive(Debug, Clone)]
+pub struct ScheduleTable {
+  checksum_type: ChecksumType,
+  entries: Vec<ScheduleEntry>,
+  next: usize,
+  next_deadline: Option<Instant>,
+}
+
+impl ScheduleTable {
+  /// Creates an empty schedule table whose frames use `checksum_type`.
+  pub fn new(checksum_type: ChecksumType) -> ScheduleTable {
+    ScheduleTable {
+      checksum_type,
+      entries: Vec::new(),
+      next: 0,
+      next_deadline: None,
+    }
+  }
+
+  /// Adds a frame the master publishes with `data`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if `id` isn't within 0-63 or `data` isn't 1 to 8 bytes long.
+  pub fn publish(&mut self, id: u8, data: &[u8], slot: Duration) -> Result<()> {
+    let _ = protected_id(id)?;
+    check_length(data.len())?;
+    self.entries.push(ScheduleEntry {
+      id,
+      frame: ScheduleFrame::Publish(data.to_vec()),
+      slot,
+    });
+    Ok(())
+  }
+
+  /// Adds a frame a slave responds to with `len` bytes.
+  ///
+  /// # Errors
+  ///
+  /// Fails if `id` isn't within 0-63 or `len` isn't within 1-8.
+  pub fn subscribe(&mut self, id: u8, len: usize, slot: Duration) -> Result<()> {
+    let _ = protected_id(id)?;
+    check_length(len)?;
+    self.entries.push(ScheduleEntry {
+      id,
+      frame: ScheduleFrame::Subscribe(len),
+      slot,
+    });
+    Ok(())
+  }
+
+  /// Changes the data of the published frames with the ID `id`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if there is no published frame with that ID, or `data` isn't 1 to
+  /// 8 bytes long.
+  pub fn set_data(&mut self, id: u8, data: &[u8]) -> Result<()> {
+    check_length(data.len())?;
+    let mut found = false;
+    for entry in self.entries.iter_mut().filter(|e| e.id == id) {
+      if let ScheduleFrame::Publish(ref mut published) = entry.frame {
+        *published = data.to_vec();
+        found = true;
+      }
+    }
+    if !found {
+      bail!(format!("No published frame 0x{:02X} in the LIN schedule table", id));
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,246 @@
+//! The MCP23017 module.
+//!
+//! The MCP23017 is an I2C port expander with 16 GPIOs, GPA0 to GPA7 and GPB0
+//! to GPB7, numbered 0 to 15 here.
+//! Its pins are `DigitalPin`s, so drivers like `Relay` take them like
+//! on-chip GPIOs:
+//!
+//! ```no_run
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::mcp23017::MCP23017;
+//!
+//! // An MCP23017 at address 0x20 on BB_I2C2.
+//! let expander = MCP23017::new(2, 0x20).unwrap();
+//!
+//! let mut pin = expander.pin(8).unwrap();
+//! pin.make_output(PinState::Low).unwrap();
+//! let relay = Relay::from_pin(pin, false).unwrap();
+//!
+//! let mut button = expander.pin(0).unwrap();
+//! button.make_input(true).unwrap();
+//! println!("{:?}", button.state().unwrap());
+//! ```
+//!
+//! The pins share the chip, so they can be moved to different threads; each
+//! change is a register write over the bus, a few hundred µs at 100kHz.
+//! The chip's interrupt outputs aren't used, inputs have to be polled.
+
+use errors::*;
+use gpio::{PinDirection, PinState};
+use hal::{DigitalPin, I2cBus};
+use i2c::I2C;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+// Register addresses of port A, with IOCON.BANK = 0 as after power-on; the
+// register of port B follows each.
+const IODIR: u8 = 0x00;
+const GPPU: u8 = 0x0C;
+const GPIO: u8 = 0x12;
+const OLAT: u8 = 0x14;
+
+/// An MCP23017 port expander on an I2C bus, by default a bus of its own.
+#[derive(Debug)]
+pub struct MCP23017<B: I2cBus = I2C> {
+  chip: Arc<Mutex<Chip<B>>>,
+}
+
+/// The bus and the registers as written last.
+#[derive(Debug)]
+struct Chip<B: I2cBus> {
+  bus: B,
+  address: u16,
+  // A set bit makes a pin an input.
+  iodir: u16,
+  gppu: u16,
+  olat: u16,
+}
+
+impl<B: I2cBus> Chip<B> {
+  /// Writes the registers of both ports at `register`.
+  fn write_pair(&self, register: u8, value: u16) -> Result<()> {
+    self.bus
+        .write(self.address, &[register, value as u8, (value >> 8) as u8])
+        .chain_err(|| format!("Failed to write MCP23017 0x{:02x} register 0x{:02x}", self.address, register))
+  }
+
+  /// Reads the registers of both ports at `register`.
+  fn read_pair(&self, register: u8) -> Result<u16> {
+    let mut buf = [0; 2];
+    self.bus
+        .write_read(self.address, &[register], &mut buf)
+        .chain_err(|| format!("Failed to read MCP23017 0x{:02x} register 0x{:02x}", self.address, register))?;
+    Ok(u16::from(buf[0]) | u16::from(buf[1]) << 8)
+  }
+}
+
+impl MCP23017<I2C> {
+  /// Talks to the MCP23017 at `address`, 0x20 to 0x27, on the I2C bus
+  /// `i2c_num`, see `with_bus()`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the bus can't be opened or the chip doesn't answer.
+  pub fn new(i2c_num: u8, address: u16) -> Result<MCP23017> {
+    MCP23017::with_bus(I2C::new(i2c_num)?, address)
+  }
+}
+
+impl<B: I2cBus> MCP23017<B> {
+  /// Talks to the MCP23017 at `address` on any I2C bus, e.g. a shared bus
+  /// or a multiplexer channel.
+  ///
+  /// All pins are made inputs without pull-ups, as after power-on, with
+  /// their outputs latched low.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the chip doesn't answer.
+  pub fn with_bus(bus: B, address: u16) -> Result<MCP23017<B>> {
+    let chip = Chip {
+      bus,
+      address,
+      iodir: 0xFFFF,
+      gppu: 0,
+      olat: 0,
+    };
+    chip.write_pair(IODIR, chip.iodir)?;
+    chip.write_pair(GPPU, chip.gppu)?;
+    chip.write_pair(OLAT, chip.olat)?;
+    Ok(MCP23017 { chip: Arc::new(Mutex::new(chip)) })
+  }
+
+  /// Returns pin `num`, 0 to 7 for GPA0 to GPA7 and 8 to 15 for GPB0 to
+  /// GPB7.
+  ///
+  /// # Errors
+  ///
+  /// Fails if there's no such pin.
+  pub fn pin(&self, num: u8) -> Result<Mcp23017Pin<B>> {
+    if num >= 16 {
+      bail!(format!("The MCP23017 has pins 0 to 15, not {}", num));
+    }
+    Ok(Mcp23017Pin {
+      chip: self.chip.clone(),
+      num,
+    })
+  }
+
+  /// Reads the levels of all pins, pin 0 in bit 0.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the chip doesn't answer.
+  pub fn read_all(&self) -> Result<u16> {
+    lock(&self.chip).read_pair(GPIO)
+  }
+
+  /// Drives all outputs at once, pin 0 from bit 0; the bits of inputs are
+  /// latched for when they're made outputs.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the chip doesn't answer.
+  pub fn write_all(&self, levels: u16) -> Result<()> {
+    let mut chip = lock(&self.chip);
+    chip.write_pair(OLAT, levels)?;
+    chip.olat = levels;
+    Ok(())
+  }
+}
+
+/// A pin of an MCP23017.
+#[derive(Debug)]
+pub struct Mcp23017Pin<B: I2cBus = I2C> {
+  chip: Arc<Mutex<Chip<B>>>,
+  num: u8,
+}
+
+impl<B: I2cBus> Mcp23017Pin<B> {
+  /// Returns the number of the pin, 0 to 15.
+  pub fn num(&self) -> u8 {
+    self.num
+  }
+
+  /// Makes the pin an output driving `state`, latching the level before
+  /// the output is enabled so it doesn't glitch.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the chip doesn't answer.
+  pub fn make_output(&mut self, state: PinState) -> Result<()> {
+    let mask = self.mask();
+    let mut chip = lock(&self.chip);
+    let olat = match state {
+      PinState::High => chip.olat | mask,
+      PinState::Low => chip.olat & !mask,
+    };
+    chip.write_pair(OLAT, olat)?;
+    chip.olat = olat;
+    let iodir = chip.iodir & !mask;
+    chip.write_pair(IODIR, iodir)?;
+    chip.iodir = iodir;
+    Ok(())
+  }
+
+  /// Makes the pin an input, with the chip's 100k pull-up if `pull_up` is
+  /// set.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the chip doesn't answer.
+  pub fn make_input(&mut self, pull_up: bool) -> Result<()> {
+    let mask = self.mask();
+    let mut chip = lock(&self.chip);
+    let gppu = if pull_up { chip.gppu | mask } else { chip.gppu & !mask };
+    chip.write_pair(GPPU, gppu)?;
+    chip.gppu = gppu;
+    let iodir = chip.iodir | mask;
+    chip.write_pair(IODIR, iodir)?;
+    chip.iodir = iodir;
+    Ok(())
+  }
+
+  /// Returns the direction of the pin as set last.
+  pub fn direction(&self) -> PinDirection {
+    if lock(&self.chip).iodir & self.mask() != 0 {
+      PinDirection::In
+    } else {
+      PinDirection::Out
+    }
+  }
+
+  fn mask(&self) -> u16 {
+    1 << self.num
+  }
+}
+
+impl<B: I2cBus> DigitalPin for Mcp23017Pin<B> {
+  fn set_state(&mut self, state: PinState) -> Result<()> {
+    if self.direction() == PinDirection::In {
+      bail!(format!("{} is an input, it has to be made an output before it's written", self.pin_name()));
+    }
+    let mask = self.mask();
+    let mut chip = lock(&self.chip);
+    let olat = match state {
+      PinState::High => chip.olat | mask,
+      PinState::Low => chip.olat & !mask,
+    };
+    chip.write_pair(OLAT, olat)?;
+    chip.olat = olat;
+    Ok(())
+  }
+
+  fn state(&self) -> Result<PinState> {
+    let levels = lock(&self.chip).read_pair(GPIO)?;
+    Ok(if levels & self.mask() != 0 { PinState::High } else { PinState::Low })
+  }
+
+  fn pin_name(&self) -> String {
+    let port = if self.num < 8 { 'A' } else { 'B' };
+    format!("MCP23017 0x{:02x} pin GP{}{}", lock(&self.chip).address, port, self.num % 8)
+  }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+  mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
@@ -0,0 +1,128 @@
+//! The memory-mapped I/O module.
+//!
+//! Some peripheral features aren't exposed through sysfs, e.g. the phase
+//! registers of the PWM subsystem.
+//! `MemoryMap` maps a block of peripheral registers from `/dev/mem` into the
+//! process so they can be accessed directly.
+//!
+//! Accessing `/dev/mem` requires root privileges, and the peripheral's clock
+//! has to be running, which is usually ensured by enabling it through its
+//! regular driver first.
+//! Writing to the wrong registers can crash the system, so this is meant as a
+//! building block for the other modules rather than for direct use.
+
+use errors::*;
+use nix::libc;
+use nix::sys::mman::{MAP_SHARED, PROT_READ, PROT_WRITE, mmap, munmap};
+use std::fs::OpenOptions;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::ptr;
+
+/// A block of peripheral registers mapped from `/dev/mem`.
+#[derive(Debug)]
+pub struct MemoryMap {
+  base: usize,
+  ptr: *mut u8,
+  len: usize,
+}
+
+// The mapping is plain memory that isn't tied to the thread that created it.
+unsafe impl Send for MemoryMap {}
+
+impl MemoryMap {
+  /// Maps `len` bytes of physical memory starting at `base`, which has to be
+  /// page aligned.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::mmio::MemoryMap;
+  ///
+  /// // The registers of the GPIO1 bank.
+  /// let gpio1 = MemoryMap::new(0x4804_C000, 0x1000).unwrap();
+  /// println!("GPIO1 inputs: {:08X}", gpio1.read_u32(0x138));
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if `base` isn't page aligned, or if `/dev/mem` can't be opened or
+  /// mapped, e.g. because the process isn't running as root.
+  pub fn new(base: usize, len: usize) -> Result<MemoryMap> {
+    if !base.is_multiple_of(4096) {
+      bail!(format!("Memory map base 0x{:08X} isn't page aligned", base));
+    }
+    let mem = OpenOptions::new()
+      .read(true)
+      .write(true)
+      .custom_flags(libc::O_SYNC)
+      .open("/dev/mem")
+      .chain_err(|| "Failed to open /dev/mem")?;
+    let ptr = mmap(ptr::null_mut(),
+                   len,
+                   PROT_READ | PROT_WRITE,
+                   MAP_SHARED,
+                   mem.as_raw_fd(),
+                   base as libc::off_t)
+      .chain_err(|| format!("Failed to map memory at 0x{:08X}", base))?;
+    Ok(MemoryMap {
+      base,
+      ptr: ptr as *mut u8,
+      len,
+    })
+  }
+
+  /// Returns the physical address the mapping starts at.
+  pub fn base(&self) -> usize {
+    self.base
+  }
+
+  /// Reads the 16-bit register at `offset`.
+  ///
+  /// # Panics
+  ///
+  /// Panics if the register is outside of the mapping or misaligned.
+  pub fn read_u16(&self, offset: usize) -> u16 {
+    unsafe { ptr::read_volatile(self.register(offset, 2) as *const u16) }
+  }
+
+  /// Writes the 16-bit register at `offset`.
+  ///
+  /// # Panics
+  ///
+  /// Panics if the register is outside of the mapping or misaligned.
+  pub fn write_u16(&self, offset: usize, value: u16) {
+    unsafe { ptr::write_volatile(self.register(offset, 2) as *mut u16, value) }
+  }
+
+  /// Reads the 32-bit register at `offset`.
+  ///
+  /// # Panics
+  ///
+  /// Panics if the register is outside of the mapping or misaligned.
+  pub fn read_u32(&self, offset: usize) -> u32 {
+    unsafe { ptr::read_volatile(self.register(offset, 4) as *const u32) }
+  }
+
+  /// Writes the 32-bit register at `offset`.
+  ///
+  /// # Panics
+  ///
+  /// Panics if the register is outside of the mapping or misaligned.
+  pub fn write_u32(&self, offset: usize, value: u32) {
+    unsafe { ptr::write_volatile(self.register(offset, 4) as *mut u32, value) }
+  }
+
+  fn register(&self, offset: usize, size: usize) -> *mut u8 {
+    assert!(offset + size <= self.len && offset.is_multiple_of(size),
+            "Register 0x{:X} is outside of the memory map or misaligned",
+            offset);
+    unsafe { self.ptr.add(offset) }
+  }
+}
+
+impl Drop for MemoryMap {
+  fn drop(&mut self) {
+    let _ = munmap(self.ptr as *mut libc::c_void, self.len);
+  }
+}
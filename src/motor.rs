@@ -0,0 +1,442 @@
+//! The motor module.
+//!
+//! H-bridge drivers like the L298N or the TB6612 drive a DC motor with two
+//! direction inputs and a PWM input setting its speed:
+//!
+//! | IN1  | IN2  | PWM      | Motor                  |
+//! |------|------|----------|------------------------|
+//! | High | Low  | speed    | forward                |
+//! | Low  | High | speed    | reverse                |
+//! | High | High | on       | brake, shorted         |
+//! | Low  | Low  | off      | coast, free-running    |
+//!
+//! `Motor` drives these inputs, with one PWM and two GPIOs:
+//!
+//! ```no_run
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::motor::Motor;
+//!
+//! // PWM on EHRPWM1A, i.e. pin P9.14 after `config-pin P9.14 pwm`.
+//! let mut motor = Motor::new(3, 0, GPIO_P8_11, GPIO_P8_12).unwrap();
+//! motor.forward(0.5).unwrap();
+//! // ...
+//! motor.brake().unwrap();
+//! ```
+//!
+//! The PWM and both GPIOs are claimed with a `Reservation` each, so another
+//! motor or program using one of them is detected right away.
+//! With `set_limits()`, the motor brakes instead of moving into a tripped
+//! limit switch, see the `limits` module.
+//! A motor coasts when it's dropped.
+//! On a TB6612, the STBY pin has to be pulled high, too.
+//!
+//! To protect the driver and the battery, a motor can soft-start: with
+//! `set_slew_rate()`, the speed only changes gradually towards the
+//! commanded one, and with `set_current_limit()`, the speed is folded back
+//! while a current sensor reads more than the limit.
+//! Both act in `update()`, which has to be called from the control loop:
+//!
+//! ```
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::{board, stub};
+//! use libbeaglebone::clock::TestClock;
+//! use libbeaglebone::motor::{Motor, MotorState};
+//! use std::sync::{Arc, Mutex};
+//! use std::time::Duration;
+//!
+//! board::set_current(stub::board().unwrap());
+//! let mut motor = Motor::new(0, 0, GPIO_P8_11, GPIO_P8_12).unwrap();
+//! let clock = TestClock::new();
+//! motor.set_clock(Arc::new(clock.clone()));
+//!
+//! // From standstill to full speed in no less than half a second.
+//! motor.set_slew_rate(Some(2.0));
+//! // At most 1.5A, as read by e.g. an INA219.
+//! let amps = Arc::new(Mutex::new(0.5));
+//! let sensor = amps.clone();
+//! motor.set_current_limit(move || Ok(*sensor.lock().unwrap()), 1.5);
+//!
+//! motor.forward(1.0).unwrap();
+//! clock.advance(Duration::from_millis(100));
+//! motor.update().unwrap();
+//! assert_eq!(motor.state(), MotorState::Forward(0.2));
+//!
+//! // Drawing 3A, the speed is halved.
+//! *amps.lock().unwrap() = 3.0;
+//! clock.advance(Duration::from_millis(100));
+//! motor.update().unwrap();
+//! assert_eq!(motor.state(), MotorState::Forward(0.1));
+//! ```
+//!
+//! Braking and coasting always act right away.
+
+use clock::{self, Clock};
+use enums::DeviceState;
+use errors::*;
+use gpio::{self, GPIO, PinState};
+use limits::{Limits, Travel};
+use pins::Pin;
+use poller::Sensor;
+use pwm::{PWM, PWMState};
+use reservation::Reservation;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The PWM frequency of a motor unless changed with `set_frequency()`,
+/// above the audible range so the motor doesn't whine.
+pub const DEFAULT_FREQUENCY_HZ: f32 = 20_000.0;
+
+/// What a motor does.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MotorState {
+  /// Turning forward at a speed from 0.0 to 1.0.
+  Forward(f32),
+  /// Turning in reverse at a speed from 0.0 to 1.0.
+  Reverse(f32),
+  /// Braking, with the motor's terminals shorted.
+  Brake,
+  /// Running free, with the motor's terminals disconnected.
+  Coast,
+}
+
+/// A current sensor and the current above which a motor is folded back.
+struct CurrentLimit {
+  sensor: Box<dyn Sensor + Send>,
+  amps: f32,
+  // The speed the motor is folded back to, 1.0 if it isn't.
+  max_speed: f32,
+}
+
+impl fmt::Debug for CurrentLimit {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("CurrentLimit")
+     .field("amps", &self.amps)
+     .field("max_speed", &self.max_speed)
+     .finish()
+  }
+}
+
+/// A DC motor on an H-bridge driver with two direction inputs and a PWM
+/// input.
+#[derive(Debug)]
+pub struct Motor {
+  pwm: PWM,
+  in1: GPIO,
+  in2: GPIO,
+  state: MotorState,
+  // The limit switches and the axis the motor moves, forward being positive.
+  limits: Option<(Limits, String)>,
+  clock: Arc<dyn Clock>,
+  // The commanded and the driven speed, negative in reverse.
+  target: f32,
+  speed: f32,
+  slew_rate: Option<f32>,
+  current_limit: Option<CurrentLimit>,
+  last_update: Option<Duration>,
+  // Released when the motor is dropped, after it's made to coast.
+  _reservations: Vec<Reservation>,
+}
+
+impl Motor {
+  /// Creates a coasting motor on PWM `pwm_num` of chip `pwm_chip_num` and
+  /// the direction inputs on `in1` and `in2`, claiming all three.
+  ///
+  /// The GPIOs are exported and made outputs driving low, and the PWM is
+  /// exported and enabled at 20kHz with a duty cycle of 0.
+  ///
+  /// # Errors
+  ///
+  /// Fails if one of the pins is reserved already, or if it can't be
+  /// configured.
+  pub fn new(pwm_chip_num: u8, pwm_num: u8, in1: Pin, in2: Pin) -> Result<Motor> {
+    let reservations = vec![Reservation::pwm(pwm_chip_num, pwm_num)?,
+                            Reservation::gpio(in1 as u8)?,
+                            Reservation::gpio(in2 as u8)?];
+    let in1 = gpio::output_driving(in1, PinState::Low)?;
+    let in2 = gpio::output_driving(in2, PinState::Low)?;
+    let mut pwm = PWM::new(pwm_chip_num, pwm_num);
+    pwm.set_export(DeviceState::Exported)?;
+    pwm.set_state(PWMState::Disabled)?;
+    pwm.set_duty_cycle(0)?;
+    pwm.set_frequency(DEFAULT_FREQUENCY_HZ)?;
+    pwm.set_state(PWMState::Enabled)?;
+    Ok(Motor {
+      pwm,
+      in1,
+      in2,
+      state: MotorState::Coast,
+      limits: None,
+      clock: clock::system(),
+      target: 0.0,
+      speed: 0.0,
+      slew_rate: None,
+      current_limit: None,
+      last_update: None,
+      _reservations: reservations,
+    })
+  }
+
+  /// Sets the PWM frequency, keeping the speed.
+  ///
+  /// The L298N only follows up to about 25kHz, the TB6612 up to 100kHz.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the frequency is out of range or the PWM can't be written.
+  pub fn set_frequency(&mut self, hz: f32) -> Result<()> {
+    self.pwm.set_frequency(hz)
+  }
+
+  /// Makes the motor brake instead of moving `axis` into a tripped switch
+  /// of `limits`, with forward moving it in the positive direction.
+  pub fn set_limits(&mut self, limits: Limits, axis: &str) {
+    self.limits = Some((limits, axis.to_string()));
+  }
+
+  /// Changes the speed by at most `rate` per second, e.g. 2.0 to take half
+  /// a second from standstill to full speed, or right away for `None`.
+  ///
+  /// Reversing slows down to a standstill at the same rate first.
+  pub fn set_slew_rate(&mut self, rate: Option<f32>) {
+    self.slew_rate = rate.map(f32::abs);
+  }
+
+  /// Folds back the speed while `sensor` reads more than `amps`.
+  ///
+  /// Each `update()` samples the sensor; above the limit, the speed is
+  /// scaled down by the limit over the current, and below it, the speed
+  /// recovers at the slew rate, or by full speed per second without one.
+  pub fn set_current_limit<S: Sensor + Send + 'static>(&mut self, sensor: S, amps: f32) {
+    self.current_limit = Some(CurrentLimit {
+      sensor: Box::new(sensor),
+      amps: amps.abs(),
+      max_speed: 1.0,
+    });
+  }
+
+  /// Makes the motor take its time from `clock`, e.g. a `TestClock`.
+  pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+    self.clock = clock;
+    self.last_update = None;
+  }
+
+  /// Moves the speed towards the commanded one as far as the slew rate
+  /// allows, and folds it back if the current limit is exceeded.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the current sensor can't be read or a pin can't be written.
+  pub fn update(&mut self) -> Result<()> {
+    if self.state == MotorState::Brake || self.state == MotorState::Coast {
+      return Ok(());
+    }
+    let now = self.clock.now();
+    // The first step is taken a moment after a change is commanded, so it
+    // doesn't jump.
+    let elapsed = self.last_update.map(|at| now.checked_sub(at).unwrap_or_default().as_secs_f32());
+    self.last_update = Some(now);
+    let mut speed = match (self.slew_rate, elapsed) {
+      (None, _) => self.target,
+      (Some(rate), Some(elapsed)) => {
+        let max_step = rate * elapsed;
+        self.speed + (self.target - self.speed).max(-max_step).min(max_step)
+      }
+      (Some(_), None) => self.speed,
+    };
+    if let Some(ref mut limit) = self.current_limit {
+      let amps = limit.sensor.sample().chain_err(|| "Failed to read the motor current")?.abs();
+      if !amps.is_finite() {
+        bail!(format!("Motor current reading {} is not a number", amps));
+      }
+      if amps > limit.amps {
+        limit.max_speed = limit.max_speed.min(self.speed.abs() * limit.amps / amps);
+      } else if let Some(elapsed) = elapsed {
+        limit.max_speed = (limit.max_speed + self.slew_rate.unwrap_or(1.0) * elapsed).min(1.0);
+      }
+      speed = speed.max(-limit.max_speed).min(limit.max_speed);
+    }
+    if speed == self.target && self.current_limit.as_ref().is_none_or(|limit| limit.max_speed == 1.0) {
+      // Time spent settled mustn't count towards the next change.
+      self.last_update = None;
+    }
+    if state_for(speed) != self.state {
+      self.drive(speed)?;
+    }
+    Ok(())
+  }
+
+  /// Samples the limit switches and brakes if the motor is moving into a
+  /// tripped one, returning whether it did.
+  ///
+  /// Call it from the control loop, as often as the switches have to be
+  /// checked.
+  ///
+  /// # Errors
+  ///
+  /// Fails if a switch can't be read or the motor can't brake.
+  pub fn check_limits(&mut self) -> Result<bool> {
+    let travel = match self.state {
+      MotorState::Forward(speed) if speed > 0.0 => Some(Travel::Positive),
+      MotorState::Reverse(speed) if speed > 0.0 => Some(Travel::Negative),
+      _ => None,
+    };
+    match self.blocking(travel)? {
+      Some(_) => {
+        self.brake()?;
+        Ok(true)
+      }
+      None => Ok(false),
+    }
+  }
+
+  /// Samples the limit switches and returns the one forbidding `travel`, if
+  /// any.
+  fn blocking(&self, travel: Option<Travel>) -> Result<Option<String>> {
+    match self.limits {
+      Some((ref limits, ref axis)) => {
+        limits.update()?;
+        Ok(travel.and_then(|travel| limits.blocking(axis, travel)))
+      }
+      None => Ok(None),
+    }
+  }
+
+  /// Brakes and fails if a limit switch forbids moving at `speed` in the
+  /// direction `travel`.
+  fn check_travel(&mut self, speed: f32, travel: Travel) -> Result<()> {
+    let travel = if speed > 0.0 { Some(travel) } else { None };
+    if let Some(name) = self.blocking(travel)? {
+      self.brake()?;
+      bail!(format!("Motor stopped by limit switch {:?}", name));
+    }
+    Ok(())
+  }
+
+  /// Returns what the motor does, at the speed it's driven at.
+  pub fn state(&self) -> MotorState {
+    self.state
+  }
+
+  /// Returns whether the motor runs at the commanded speed, i.e. it isn't
+  /// slewing or folded back.
+  pub fn is_settled(&self) -> bool {
+    self.speed == self.target
+  }
+
+  /// Turns the motor forward at `speed`, from 0.0 to 1.0, or towards it
+  /// with a slew rate or a current limit, see `update()`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the speed is outside of 0.0 to 1.0 or a pin can't be written.
+  /// Brakes and fails if a limit switch forbids moving forward.
+  pub fn forward(&mut self, speed: f32) -> Result<()> {
+    check_speed(speed)?;
+    self.check_travel(speed, Travel::Positive)?;
+    self.command(speed)
+  }
+
+  /// Turns the motor in reverse at `speed`, from 0.0 to 1.0, or towards it
+  /// with a slew rate or a current limit, see `update()`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the speed is outside of 0.0 to 1.0 or a pin can't be written.
+  /// Brakes and fails if a limit switch forbids moving in reverse.
+  pub fn reverse(&mut self, speed: f32) -> Result<()> {
+    check_speed(speed)?;
+    self.check_travel(speed, Travel::Negative)?;
+    self.command(-speed)
+  }
+
+  /// Commands the signed speed `target` and takes the first step towards
+  /// it.
+  fn command(&mut self, target: f32) -> Result<()> {
+    if self.state == MotorState::Brake || self.state == MotorState::Coast {
+      // Starting from a standstill.
+      self.drive(if target.is_sign_negative() { -0.0 } else { 0.0 })?;
+      self.last_update = None;
+    }
+    self.target = target;
+    self.update()
+  }
+
+  /// Drives the motor at the signed speed `speed`.
+  fn drive(&mut self, speed: f32) -> Result<()> {
+    if speed.is_sign_negative() {
+      self.set_inputs(PinState::Low, PinState::High)?;
+    } else {
+      self.set_inputs(PinState::High, PinState::Low)?;
+    }
+    self.pwm.set_duty_cycle_fraction(speed.abs())?;
+    self.speed = speed;
+    self.state = state_for(speed);
+    Ok(())
+  }
+
+  /// Stops the motor quickly by shorting its terminals.
+  ///
+  /// # Errors
+  ///
+  /// Fails if a pin can't be written.
+  pub fn brake(&mut self) -> Result<()> {
+    self.set_inputs(PinState::High, PinState::High)?;
+    self.pwm.set_duty_cycle_fraction(1.0)?;
+    self.stopped(MotorState::Brake);
+    Ok(())
+  }
+
+  /// Lets the motor run down by disconnecting its terminals.
+  ///
+  /// # Errors
+  ///
+  /// Fails if a pin can't be written.
+  pub fn coast(&mut self) -> Result<()> {
+    self.pwm.set_duty_cycle(0)?;
+    self.set_inputs(PinState::Low, PinState::Low)?;
+    self.stopped(MotorState::Coast);
+    Ok(())
+  }
+
+  fn stopped(&mut self, state: MotorState) {
+    self.state = state;
+    self.target = 0.0;
+    self.speed = 0.0;
+    self.last_update = None;
+  }
+
+  /// Drives the direction inputs, lowering before raising, so the motor is
+  /// never braked on the way from forward to reverse.
+  fn set_inputs(&mut self, in1: PinState, in2: PinState) -> Result<()> {
+    if in1 == PinState::Low {
+      self.in1.write(in1)?;
+      self.in2.write(in2)
+    } else {
+      self.in2.write(in2)?;
+      self.in1.write(in1)
+    }
+  }
+}
+
+impl Drop for Motor {
+  fn drop(&mut self) {
+    let _ = self.coast();
+  }
+}
+
+/// Returns the state of a motor driven at the signed speed `speed`.
+fn state_for(speed: f32) -> MotorState {
+  if speed.is_sign_negative() {
+    MotorState::Reverse(-speed)
+  } else {
+    MotorState::Forward(speed)
+  }
+}
+
+fn check_speed(speed: f32) -> Result<()> {
+  if !(0.0..=1.0).contains(&speed) {
+    bail!(format!("Motor speed {} is outside of 0 to 1", speed));
+  }
+  Ok(())
+}
@@ -0,0 +1,416 @@
+//! The motor controller module.
+//!
+//! Drivers for popular dual-channel motor controllers that are commanded over
+//! a UART:
+//!
+//! * Dimension Engineering Sabertooth, in packetized serial mode.
+//! * Basicmicro RoboClaw, in packet serial mode, including speed and
+//!   position control and telemetry.
+//!
+//! Both controllers can share one UART TX line, each answering to its own
+//! address which is set with the controller's DIP switches (Sabertooth) or
+//! Motion Studio (RoboClaw).
+
+use errors::*;
+use uart::UART;
+
+/// One of the two motor channels of a controller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MotorChannel {
+  /// Motor 1
+  M1,
+  /// Motor 2
+  M2,
+}
+
+/// Converts a speed in -1.0..=1.0 into a direction and a 7-bit magnitude.
+fn seven_bit_speed(speed: f32) -> Result<(bool, u8)> {
+  if !(-1.0..=1.0).contains(&speed) {
+    bail!(format!("Motor speed {} is outside of -1.0 to 1.0", speed));
+  }
+  Ok((speed >= 0.0, (speed.abs() * 127.0).round() as u8))
+}
+
+/// Represents a Sabertooth motor controller in packetized serial mode.
+#[derive(Debug)]
+pub struct Sabertooth {
+  uart: UART,
+  address: u8,
+}
+
+impl Sabertooth {
+  /// Creates a new Sabertooth driver.
+  ///
+  /// `address` is set by the controller's DIP switches and has to be between
+  /// 128 and 135.
+  /// The UART's baud rate has to be 2400, 9600, 19200 or 38400.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  /// use libbeaglebone::motor_controller::Sabertooth;
+  ///
+  /// let mut sabertooth = Sabertooth::new(UART::new(1).unwrap(), 128).unwrap();
+  /// sabertooth.send_bauding_character().unwrap();
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if the address is out of range.
+  pub fn new(uart: UART, address: u8) -> Result<Sabertooth> {
+    if !(128..=135).contains(&address) {
+      bail!(format!("Sabertooth address {} is outside of 128-135", address));
+    }
+    Ok(Sabertooth { uart, address })
+  }
+
+  /// Sends the bauding character the controller uses to detect the baud rate.
+  ///
+  /// Has to be sent once after the controller powers up, before any other
+  /// command.
+  pub fn send_bauding_character(&mut self) -> Result<()> {
+    self.uart
+        .write_bytes(&[0xAA])
+        .chain_err(|| "Failed to send Sabertooth bauding character")
+  }
+
+  /// Drives a motor, with `speed` between -1.0 (full reverse) and 1.0 (full
+  /// forward).
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  /// use libbeaglebone::motor_controller::{MotorChannel, Sabertooth};
+  ///
+  /// let mut sabertooth = Sabertooth::new(UART::new(1).unwrap(), 128).unwrap();
+  /// sabertooth.send_bauding_character().unwrap();
+  ///
+  /// // Half speed forward on motor 1, quarter speed reverse on motor 2.
+  /// sabertooth.drive(MotorChannel::M1, 0.5).unwrap();
+  /// sabertooth.drive(MotorChannel::M2, -0.25).unwrap();
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if `speed` is out of range or the command can't be sent.
+  pub fn drive(&mut self, channel: MotorChannel, speed: f32) -> Result<()> {
+    let (forward, value) = seven_bit_speed(speed)?;
+    let command = match (channel, forward) {
+      (MotorChannel::M1, true) => 0,
+      (MotorChannel::M1, false) => 1,
+      (MotorChannel::M2, true) => 4,
+      (MotorChannel::M2, false) => 5,
+    };
+    self.command(command, value)
+  }
+
+  /// Stops both motors.
+  pub fn stop(&mut self) -> Result<()> {
+    self.drive(MotorChannel::M1, 0.0)?;
+    self.drive(MotorChannel::M2, 0.0)
+  }
+
+  /// Sets the battery voltage below which the controller shuts off the
+  /// motors, between 6V and 30V.
+  pub fn set_min_voltage(&mut self, volts: f32) -> Result<()> {
+    if !(6.0..=30.0).contains(&volts) {
+      bail!(format!("Sabertooth minimum voltage {}V is outside of 6-30V", volts));
+    }
+    self.command(2, ((volts - 6.0) * 5.0).round() as u8)
+  }
+
+  /// Sets the voltage above which the controller stops regenerative braking,
+  /// between 0V and 25V.
+  pub fn set_max_voltage(&mut self, volts: f32) -> Result<()> {
+    if !(0.0..=25.0).contains(&volts) {
+      bail!(format!("Sabertooth maximum voltage {}V is outside of 0-25V", volts));
+    }
+    self.command(3, (volts * 5.12).round() as u8)
+  }
+
+  /// Stops the motors if no command arrives within `timeout_ms`, rounded to
+  /// 100ms steps.
+  ///
+  /// A timeout of 0 disables this.
+  pub fn set_serial_timeout(&mut self, timeout_ms: u32) -> Result<()> {
+    let value = timeout_ms.div_ceil(100);
+    if value > 127 {
+      bail!(format!("Sabertooth serial timeout {}ms exceeds 12.7s", timeout_ms));
+    }
+    self.command(14, value as u8)
+  }
+
+  /// Sets the acceleration ramp, 0 disables ramping.
+  ///
+  /// See the Sabertooth manual for the meaning of the values 1-80.
+  pub fn set_ramping(&mut self, value: u8) -> Result<()> {
+    if value > 80 {
+      bail!(format!("Sabertooth ramping value {} exceeds 80", value));
+    }
+    self.command(16, value)
+  }
+
+  /// Sends a command packet.
+  fn command(&mut self, command: u8, value: u8) -> Result<()> {
+    let checksum = self.address.wrapping_add(command).wrapping_add(value) & 0x7F;
+    self.uart
+        .write_bytes(&[self.address, command, value, checksum])
+        .chain_err(|| format!("Failed to send command {} to Sabertooth", command))
+  }
+}
+
+// RoboClaw packet serial commands.
+const ROBOCLAW_M1_FORWARD: u8 = 0;
+const ROBOCLAW_M1_BACKWARD: u8 = 1;
+const ROBOCLAW_M2_FORWARD: u8 = 4;
+const ROBOCLAW_M2_BACKWARD: u8 = 5;
+const ROBOCLAW_GET_M1_ENCODER: u8 = 16;
+const ROBOCLAW_GET_M2_ENCODER: u8 = 17;
+const ROBOCLAW_GET_M1_SPEED: u8 = 18;
+const ROBOCLAW_GET_M2_SPEED: u8 = 19;
+const ROBOCLAW_RESET_ENCODERS: u8 = 20;
+const ROBOCLAW_GET_MAIN_BATTERY: u8 = 24;
+const ROBOCLAW_M1_SPEED: u8 = 35;
+const ROBOCLAW_M2_SPEED: u8 = 36;
+const ROBOCLAW_GET_CURRENTS: u8 = 49;
+const ROBOCLAW_M1_SPEED_ACCEL_DECCEL_POSITION: u8 = 65;
+const ROBOCLAW_M2_SPEED_ACCEL_DECCEL_POSITION: u8 = 66;
+const ROBOCLAW_GET_TEMPERATURE: u8 = 82;
+
+/// The byte a RoboClaw answers successful write commands with.
+const ROBOCLAW_ACK: u8 = 0xFF;
+
+/// A position move for a RoboClaw motor channel with encoder feedback.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PositionMove {
+  /// The target position in encoder counts.
+  pub position: u32,
+  /// The cruising speed in encoder counts per second.
+  pub speed: u32,
+  /// The acceleration in encoder counts per second squared.
+  pub acceleration: u32,
+  /// The deceleration in encoder counts per second squared.
+  pub deceleration: u32,
+  /// Whether to queue the move after the current one, instead of executing
+  /// it right away.
+  pub buffered: bool,
+}
+
+/// Represents a RoboClaw motor controller in packet serial mode.
+#[derive(Debug)]
+pub struct RoboClaw {
+  uart: UART,
+  address: u8,
+}
+
+impl RoboClaw {
+  /// Creates a new RoboClaw driver.
+  ///
+  /// `address` is configured in Motion Studio and has to be between 128
+  /// (0x80) and 135 (0x87).
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  /// use libbeaglebone::motor_controller::RoboClaw;
+  ///
+  /// let roboclaw = RoboClaw::new(UART::new(1).unwrap(), 0x80).unwrap();
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if the address is out of range.
+  pub fn new(uart: UART, address: u8) -> Result<RoboClaw> {
+    if !(0x80..=0x87).contains(&address) {
+      bail!(format!("RoboClaw address 0x{:02X} is outside of 0x80-0x87", address));
+    }
+    Ok(RoboClaw { uart, address })
+  }
+
+  /// Drives a motor open-loop, with `speed` between -1.0 (full reverse) and
+  /// 1.0 (full forward).
+  ///
+  /// # Errors
+  ///
+  /// Fails if `speed` is out of range or the controller doesn't acknowledge
+  /// the command.
+  pub fn drive(&mut self, channel: MotorChannel, speed: f32) -> Result<()> {
+    let (forward, value) = seven_bit_speed(speed)?;
+    let command = match (channel, forward) {
+      (MotorChannel::M1, true) => ROBOCLAW_M1_FORWARD,
+      (MotorChannel::M1, false) => ROBOCLAW_M1_BACKWARD,
+      (MotorChannel::M2, true) => ROBOCLAW_M2_FORWARD,
+      (MotorChannel::M2, false) => ROBOCLAW_M2_BACKWARD,
+    };
+    self.write(command, &[value])
+  }
+
+  /// Drives a motor at a closed-loop speed in encoder counts per second,
+  /// negative for reverse.
+  ///
+  /// Needs the channel's velocity PID to be tuned in Motion Studio.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  /// use libbeaglebone::motor_controller::{MotorChannel, RoboClaw};
+  ///
+  /// let mut roboclaw = RoboClaw::new(UART::new(1).unwrap(), 0x80).unwrap();
+  /// roboclaw.set_speed(MotorChannel::M1, 2000).unwrap();
+  /// println!("M1 is turning at {} counts/s", roboclaw.speed(MotorChannel::M1).unwrap());
+  /// ```
+  pub fn set_speed(&mut self, channel: MotorChannel, counts_per_sec: i32) -> Result<()> {
+    let command = match channel {
+      MotorChannel::M1 => ROBOCLAW_M1_SPEED,
+      MotorChannel::M2 => ROBOCLAW_M2_SPEED,
+    };
+    self.write(command, &counts_per_sec.to_be_bytes())
+  }
+
+  /// Moves a motor to an encoder position with a trapezoidal speed profile.
+  ///
+  /// Needs the channel's position PID to be tuned in Motion Studio.
+  pub fn move_to(&mut self, channel: MotorChannel, target: &PositionMove) -> Result<()> {
+    let command = match channel {
+      MotorChannel::M1 => ROBOCLAW_M1_SPEED_ACCEL_DECCEL_POSITION,
+      MotorChannel::M2 => ROBOCLAW_M2_SPEED_ACCEL_DECCEL_POSITION,
+    };
+    let mut data = Vec::with_capacity(17);
+    data.extend_from_slice(&target.acceleration.to_be_bytes());
+    data.extend_from_slice(&target.speed.to_be_bytes());
+    data.extend_from_slice(&target.deceleration.to_be_bytes());
+    data.extend_from_slice(&target.position.to_be_bytes());
+    // 0 queues the move, 1 executes it right away.
+    data.push(if target.buffered { 0 } else { 1 });
+    self.write(command, &data)
+  }
+
+  /// Stops both motors.
+  pub fn stop(&mut self) -> Result<()> {
+    self.drive(MotorChannel::M1, 0.0)?;
+    self.drive(MotorChannel::M2, 0.0)
+  }
+
+  /// Reads a motor's encoder count.
+  pub fn encoder(&mut self, channel: MotorChannel) -> Result<u32> {
+    let command = match channel {
+      MotorChannel::M1 => ROBOCLAW_GET_M1_ENCODER,
+      MotorChannel::M2 => ROBOCLAW_GET_M2_ENCODER,
+    };
+    // The count is followed by a status byte.
+    let data = self.read(command, 5)?;
+    Ok(be_u32(&data[0..4]))
+  }
+
+  /// Resets both encoder counts to zero.
+  pub fn reset_encoders(&mut self) -> Result<()> {
+    self.write(ROBOCLAW_RESET_ENCODERS, &[])
+  }
+
+  /// Reads a motor's speed in encoder counts per second, negative for
+  /// reverse.
+  pub fn speed(&mut self, channel: MotorChannel) -> Result<i32> {
+    let command = match channel {
+      MotorChannel::M1 => ROBOCLAW_GET_M1_SPEED,
+      MotorChannel::M2 => ROBOCLAW_GET_M2_SPEED,
+    };
+    // The speed is followed by a direction byte, 1 for reverse.
+    let data = self.read(command, 5)?;
+    let speed = be_u32(&data[0..4]) as i32;
+    Ok(if data[4] == 1 { -speed } else { speed })
+  }
+
+  /// Reads the main battery voltage in volts.
+  pub fn main_battery_voltage(&mut self) -> Result<f32> {
+    let data = self.read(ROBOCLAW_GET_MAIN_BATTERY, 2)?;
+    Ok(f32::from(be_u16(&data)) / 10.0)
+  }
+
+  /// Reads the motor currents of M1 and M2 in amperes.
+  pub fn currents(&mut self) -> Result<(f32, f32)> {
+    let data = self.read(ROBOCLAW_GET_CURRENTS, 4)?;
+    Ok((f32::from(be_u16(&data[0..2])) / 100.0, f32::from(be_u16(&data[2..4])) / 100.0))
+  }
+
+  /// Reads the board temperature in degrees Celsius.
+  pub fn temperature(&mut self) -> Result<f32> {
+    let data = self.read(ROBOCLAW_GET_TEMPERATURE, 2)?;
+    Ok(f32::from(be_u16(&data)) / 10.0)
+  }
+
+  /// Sends a write command and waits for the acknowledgement.
+  fn write(&mut self, command: u8, data: &[u8]) -> Result<()> {
+    let mut packet = vec![self.address, command];
+    packet.extend_from_slice(data);
+    let crc = crc16_ccitt(&packet);
+    packet.extend_from_slice(&crc.to_be_bytes());
+
+    self.uart.clear_input()?;
+    self.uart
+        .write_bytes(&packet)
+        .chain_err(|| format!("Failed to send command {} to RoboClaw", command))?;
+
+    let mut ack = [0];
+    self.uart
+        .read_exact(&mut ack)
+        .chain_err(|| format!("RoboClaw didn't acknowledge command {}", command))?;
+    if ack[0] != ROBOCLAW_ACK {
+      bail!(format!("RoboClaw rejected command {}", command));
+    }
+    Ok(())
+  }
+
+  /// Sends a read command and returns `len` bytes of response data.
+  fn read(&mut self, command: u8, len: usize) -> Result<Vec<u8>> {
+    self.uart.clear_input()?;
+    self.uart
+        .write_bytes(&[self.address, command])
+        .chain_err(|| format!("Failed to send command {} to RoboClaw", command))?;
+
+    let mut response = vec![0; len + 2];
+    self.uart
+        .read_exact(&mut response)
+        .chain_err(|| format!("RoboClaw didn't respond to command {}", command))?;
+
+    // The CRC covers the command as well as the response.
+    let received_crc = be_u16(&response[len..]);
+    response.truncate(len);
+    let mut packet = vec![self.address, command];
+    packet.extend_from_slice(&response);
+    if crc16_ccitt(&packet) != received_crc {
+      bail!(format!("RoboClaw response to command {} has a bad CRC", command));
+    }
+    Ok(response)
+  }
+}
+
+/// Computes the CRC-16 (CCITT, polynomial 0x1021) used by RoboClaw packets.
+///
+/// # Examples
+///
+/// ```
+/// use libbeaglebone::motor_controller::crc16_ccitt;
+///
+/// assert_eq!(crc16_ccitt(b"123456789"), 0x31C3);
+/// ```
+pub fn crc16_ccitt(data: &[u8]) -> u16 {
+  data.iter().fold(0, |crc, &b| {
+    (0..8).fold(crc ^ u16::from(b) << 8, |crc, _| if crc & 0x8000 != 0 {
+      crc << 1 ^ 0x1021
+    } else {
+      crc << 1
+    })
+  })
+}
+
+fn be_u16(bytes: &[u8]) -> u16 {
+  u16::from(bytes[0]) << 8 | u16::from(bytes[1])
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+  bytes.iter().take(4).fold(0, |value, &b| value << 8 | u32::from(b))
+}
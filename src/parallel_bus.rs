@@ -0,0 +1,226 @@
+//! The parallel bus module.
+//!
+//! Character LCDs, older DACs and many legacy peripherals take data over a
+//! parallel bus: the data lines are set up, and a strobe (or enable) line
+//! pulsed to make the device latch them.
+//! `ParallelBus` bit-bangs such write cycles with configurable timing:
+//!
+//! ```no_run
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::parallel_bus::ParallelBus;
+//! use std::time::Duration;
+//!
+//! // An HD44780 LCD in 4-bit mode: D4 to D7, and E as the strobe.
+//! let mut lcd = ParallelBus::new(vec![GPIO_P8_11, GPIO_P8_12, GPIO_P8_14, GPIO_P8_16],
+//!                                GPIO_P8_18)
+//!   .unwrap();
+//! lcd.set_timing(Duration::from_nanos(60), Duration::from_nanos(450), Duration::from_micros(40));
+//!
+//! // Data pin 0 gets bit 0 of the value.
+//! lcd.write(0b0011).unwrap();
+//! lcd.write_all(&[0b0010, 0b1000]).unwrap();
+//! ```
+//!
+//! Only the data lines that change are written, which matters for sysfs
+//! GPIOs, where every write is a system call.
+//! Any `DigitalPin` works as a line; `new_fast()` takes GPIOs written
+//! through their bank's registers instead, see the `fast_gpio` module, for
+//! cycles of well under a microsecond.
+//! Other control lines, e.g. an LCD's register select, are driven as usual
+//! between writes.
+
+use errors::*;
+use fast_gpio::FastGPIO;
+use gpio::{self, GPIO, GpioBackend, PinState};
+use hal::DigitalPin;
+use pins::Pin;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Delays shorter than this are busy-waited, for they would take a lot
+/// longer to sleep.
+const SPIN_THRESHOLD: Duration = Duration::from_micros(100);
+
+/// A bus of up to 32 data lines and a strobe line.
+#[derive(Debug)]
+pub struct ParallelBus<P: DigitalPin = GPIO> {
+  data: Vec<P>,
+  strobe: P,
+  strobe_active: PinState,
+  setup: Duration,
+  pulse: Duration,
+  hold: Duration,
+  // The value on the data lines, if known.
+  value: Option<u32>,
+}
+
+impl ParallelBus<GPIO> {
+  /// Creates a bus on GPIOs, with `data[0]` carrying the lowest bit, and an
+  /// active-high strobe.
+  ///
+  /// The pins are exported and made outputs driving low.
+  ///
+  /// # Errors
+  ///
+  /// Fails if there are no or more than 32 data pins, or if a pin can't be
+  /// configured.
+  pub fn new(data: Vec<Pin>, strobe: Pin) -> Result<ParallelBus> {
+    check_width(data.len())?;
+    let data = data.into_iter().map(|pin| gpio::output_driving(pin, PinState::Low)).collect::<Result<Vec<_>>>()?;
+    let mut bus = ParallelBus::from_pins(data, gpio::output_driving(strobe, PinState::Low)?)?;
+    bus.value = Some(0);
+    Ok(bus)
+  }
+}
+
+impl ParallelBus<FastGPIO> {
+  /// Creates a bus like `new()`, but on GPIOs written through their bank's
+  /// registers, which needs root.
+  ///
+  /// The pins are exported through sysfs and made outputs driving low
+  /// first, which also makes the kernel enable their banks.
+  ///
+  /// # Errors
+  ///
+  /// Fails if there are no or more than 32 data pins, or if a pin can't be
+  /// configured or its registers can't be mapped.
+  pub fn new_fast(data: Vec<Pin>, strobe: Pin) -> Result<ParallelBus<FastGPIO>> {
+    check_width(data.len())?;
+    let data = data.into_iter().map(fast_output).collect::<Result<Vec<_>>>()?;
+    let mut bus = ParallelBus::from_pins(data, fast_output(strobe)?)?;
+    bus.value = Some(0);
+    Ok(bus)
+  }
+}
+
+impl<P: DigitalPin> ParallelBus<P> {
+  /// Creates a bus on configured outputs, with `data[0]` carrying the
+  /// lowest bit, and an active-high strobe, which is driven inactive.
+  ///
+  /// # Errors
+  ///
+  /// Fails if there are no or more than 32 data pins, or if the strobe
+  /// can't be driven.
+  pub fn from_pins(data: Vec<P>, mut strobe: P) -> Result<ParallelBus<P>> {
+    check_width(data.len())?;
+    strobe.set_state(PinState::Low)?;
+    Ok(ParallelBus {
+      data,
+      strobe,
+      strobe_active: PinState::High,
+      setup: Duration::from_secs(0),
+      pulse: Duration::from_micros(1),
+      hold: Duration::from_secs(0),
+      value: None,
+    })
+  }
+
+  /// Returns the number of data lines.
+  pub fn width(&self) -> usize {
+    self.data.len()
+  }
+
+  /// Makes the strobe active-low or active-high, and drives it inactive.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the strobe can't be driven.
+  pub fn set_strobe_active_low(&mut self, active_low: bool) -> Result<()> {
+    self.strobe_active = if active_low { PinState::Low } else { PinState::High };
+    self.strobe.set_state(inverse(self.strobe_active))
+  }
+
+  /// Sets the timing of a write cycle: how long the data lines are stable
+  /// before the strobe, how long the strobe is active and how long the data
+  /// lines are held after it.
+  ///
+  /// By default there's no setup and hold time and the strobe is active for
+  /// 1µs; sysfs GPIOs are slow enough to add a few µs to each anyway.
+  pub fn set_timing(&mut self, setup: Duration, pulse: Duration, hold: Duration) {
+    self.setup = setup;
+    self.pulse = pulse;
+    self.hold = hold;
+  }
+
+  /// Puts `value` on the data lines and strobes it.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the value has more bits than there are data lines, or if a
+  /// line can't be driven.
+  pub fn write(&mut self, value: u32) -> Result<()> {
+    if self.data.len() < 32 && value >> self.data.len() != 0 {
+      bail!(format!("{:#x} doesn't fit on a {}-bit parallel bus", value, self.data.len()));
+    }
+    self.set_data(value)?;
+    delay(self.setup);
+    self.strobe.set_state(self.strobe_active)?;
+    delay(self.pulse);
+    self.strobe.set_state(inverse(self.strobe_active))?;
+    delay(self.hold);
+    Ok(())
+  }
+
+  /// Writes the values one after the other.
+  ///
+  /// # Errors
+  ///
+  /// Fails on the first value that doesn't fit or can't be written.
+  pub fn write_all(&mut self, values: &[u32]) -> Result<()> {
+    for &value in values {
+      self.write(value)?;
+    }
+    Ok(())
+  }
+
+  /// Unwraps the data and strobe lines.
+  pub fn into_inner(self) -> (Vec<P>, P) {
+    (self.data, self.strobe)
+  }
+
+  /// Drives the data lines that differ from `value`.
+  fn set_data(&mut self, value: u32) -> Result<()> {
+    let changed = match self.value {
+      Some(current) => current ^ value,
+      None => u32::MAX,
+    };
+    // Unknown from here on, until all lines are written.
+    self.value = None;
+    for (bit, pin) in self.data.iter_mut().enumerate() {
+      if changed & (1 << bit) != 0 {
+        pin.set_state(if value & (1 << bit) != 0 { PinState::High } else { PinState::Low })?;
+      }
+    }
+    self.value = Some(value);
+    Ok(())
+  }
+}
+
+/// Configures `pin` as a sysfs output driving low, and maps its registers.
+fn fast_output(pin: Pin) -> Result<FastGPIO> {
+  FastGPIO::from_gpio(&gpio::configure_output(GPIO::with_backend(pin, GpioBackend::Sysfs), PinState::Low)?)
+}
+
+fn check_width(width: usize) -> Result<()> {
+  if !(1..=32).contains(&width) {
+    bail!(format!("A parallel bus has 1 to 32 data lines, not {}", width));
+  }
+  Ok(())
+}
+
+fn inverse(state: PinState) -> PinState {
+  match state {
+    PinState::High => PinState::Low,
+    PinState::Low => PinState::High,
+  }
+}
+
+/// Waits for `duration`, busy-waiting if it's short.
+pub(crate) fn delay(duration: Duration) {
+  if duration >= SPIN_THRESHOLD {
+    thread::sleep(duration);
+  } else if duration > Duration::from_secs(0) {
+    let start = Instant::now();
+    while start.elapsed() < duration {}
+  }
+}
@@ -0,0 +1,402 @@
+//! The pattern module.
+//!
+//! Simple devices signal with pulses on a line: a pulse of a certain length,
+//! a number of pulses in quick succession, or a line held active.
+//! A `Matcher` recognizes such patterns in the levels of an input, without
+//! a hand-written state machine:
+//!
+//! ```
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::pattern::{Matcher, Pattern};
+//! use std::time::Duration;
+//!
+//! let ms = Duration::from_millis;
+//! let mut matcher = Matcher::new(PinState::High);
+//! matcher.add_pattern("triple", Pattern::pulses(3, Duration::from_secs(2)));
+//! matcher.add_pattern("sync", Pattern::pulse(ms(10), ms(20)));
+//!
+//! matcher.feed(PinState::Low, ms(0));
+//! // A 15ms pulse.
+//! matcher.feed(PinState::High, ms(100));
+//! assert_eq!(matcher.feed(PinState::Low, ms(115)), vec!["sync"]);
+//! // Two more pulses, too long for a sync.
+//! for &start in &[500, 900] {
+//!   matcher.feed(PinState::High, ms(start));
+//!   let matched = matcher.feed(PinState::Low, ms(start + 50));
+//!   assert_eq!(matched.is_empty(), start == 500);
+//! }
+//! ```
+//!
+//! A `PatternMatcher` feeds a matcher from a pin, either by polling it, or
+//! with the pin's edges for an accurate timing:
+//!
+//! ```no_run
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::pattern::{Pattern, PatternMatcher};
+//! use std::time::Duration;
+//!
+//! let mut signal = PatternMatcher::new(GPIO_P8_11, PinState::Low).unwrap();
+//! signal.matcher_mut().add_pattern("reset", Pattern::held(Duration::from_secs(5)));
+//! let _subscription = signal.on_match(|name| println!("Got {}", name)).unwrap();
+//! ```
+//!
+//! Pulses aren't debounced: a bouncing contact makes several of them, so
+//! debounce it in hardware or through a `DebouncedInput` first.
+
+use clock::{self, Clock};
+use enums::DeviceState;
+use errors::*;
+use gpio::{Edge, GPIO, PinDirection, PinState};
+use hal::DigitalPin;
+use pins::Pin;
+use reactor::Subscription;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::mpsc::RecvTimeoutError;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// A temporal pattern of pulses, a pulse being a stretch of the input at its
+/// active level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pattern {
+  /// `count` pulses, the first starting and the last ending within
+  /// `within`.
+  Pulses {
+    /// The number of pulses.
+    count: usize,
+    /// The time they have to fit in.
+    within: Duration,
+  },
+  /// A single pulse lasting from `min` to `max`.
+  Pulse {
+    /// The shortest pulse that matches.
+    min: Duration,
+    /// The longest pulse that matches.
+    max: Duration,
+  },
+  /// The input held active for at least `min`; matches while it's still
+  /// active.
+  Held {
+    /// How long the input has to be active.
+    min: Duration,
+  },
+}
+
+impl Pattern {
+  /// Matches `count` pulses within `within`.
+  pub fn pulses(count: usize, within: Duration) -> Pattern {
+    Pattern::Pulses {
+      count: count.max(1),
+      within,
+    }
+  }
+
+  /// Matches a pulse lasting from `min` to `max`.
+  pub fn pulse(min: Duration, max: Duration) -> Pattern {
+    Pattern::Pulse { min, max }
+  }
+
+  /// Matches the input held active for `min`.
+  pub fn held(min: Duration) -> Pattern {
+    Pattern::Held { min }
+  }
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+  name: String,
+  pattern: Pattern,
+  // Pulses starting before this don't count anymore, they were matched.
+  since: Duration,
+  // Whether a `Held` pattern matched the current pulse already.
+  fired: bool,
+}
+
+/// Recognizes patterns in the levels of an input, fed with their times.
+#[derive(Debug, Clone)]
+pub struct Matcher {
+  active: PinState,
+  entries: Vec<Entry>,
+  level: Option<PinState>,
+  // When the current pulse started, if it's known.
+  pulse_start: Option<Duration>,
+  // The recent pulses' start and end.
+  pulses: VecDeque<(Duration, Duration)>,
+}
+
+impl Matcher {
+  /// Creates a matcher without patterns, whose pulses are at the level
+  /// `active`.
+  pub fn new(active: PinState) -> Matcher {
+    Matcher {
+      active,
+      entries: Vec::new(),
+      level: None,
+      pulse_start: None,
+      pulses: VecDeque::new(),
+    }
+  }
+
+  /// Adds the pattern `pattern`, reported as `name` when it matches.
+  pub fn add_pattern(&mut self, name: &str, pattern: Pattern) {
+    self.entries.push(Entry {
+      name: name.to_string(),
+      pattern,
+      since: Duration::from_secs(0),
+      fired: false,
+    });
+  }
+
+  /// Forgets the pulses seen so far.
+  pub fn reset(&mut self) {
+    self.level = None;
+    self.pulse_start = None;
+    self.pulses.clear();
+  }
+
+  /// Takes the input's level `level` at the time `at`, and returns the names
+  /// of the patterns that matched.
+  ///
+  /// The times are on any monotonic clock; the level is taken to have
+  /// changed at `at`, so feed changes as soon as they're seen, and the same
+  /// level again for `Held` patterns to match.
+  /// A pulse that's already going on at the first level isn't counted.
+  pub fn feed(&mut self, level: PinState, at: Duration) -> Vec<String> {
+    let mut matched = Vec::new();
+    let was_active = self.level == Some(self.active);
+    let known = self.level.is_some();
+    self.level = Some(level);
+    if level == self.active {
+      if known && !was_active {
+        self.pulse_start = Some(at);
+        for entry in &mut self.entries {
+          entry.fired = false;
+        }
+      }
+      if let Some(start) = self.pulse_start {
+        for entry in &mut self.entries {
+          if let Pattern::Held { min } = entry.pattern {
+            if !entry.fired && at - start >= min {
+              entry.fired = true;
+              matched.push(entry.name.clone());
+            }
+          }
+        }
+      }
+      return matched;
+    }
+    let start = match (was_active, self.pulse_start.take()) {
+      (true, Some(start)) => start,
+      _ => return matched,
+    };
+    self.pulses.push_back((start, at));
+    for entry in &mut self.entries {
+      match entry.pattern {
+        Pattern::Pulse { min, max } => {
+          if (min..=max).contains(&(at - start)) {
+            matched.push(entry.name.clone());
+          }
+        }
+        Pattern::Pulses { count, within } => {
+          let mut recent = self.pulses.iter().rev().take_while(|&&(start, _)| start >= entry.since);
+          if let Some(&(first, _)) = recent.nth(count - 1) {
+            if at - first <= within {
+              entry.since = at;
+              matched.push(entry.name.clone());
+            }
+          }
+        }
+        Pattern::Held { .. } => {}
+      }
+    }
+    // Only keep the pulses that a pattern may still count.
+    let horizon = self.entries
+                      .iter()
+                      .filter_map(|entry| match entry.pattern {
+                                    Pattern::Pulses { within, .. } => Some(within),
+                                    _ => None,
+                                  })
+                      .max()
+                      .unwrap_or_default();
+    while self.pulses.front().is_some_and(|&(start, _)| at - start > horizon) {
+      let _ = self.pulses.pop_front();
+    }
+    matched
+  }
+
+  /// Returns when a `Held` pattern matches next if the input stays active,
+  /// or `None` if it doesn't.
+  pub fn next_deadline(&self) -> Option<Duration> {
+    let start = self.pulse_start?;
+    self.entries
+        .iter()
+        .filter(|entry| !entry.fired)
+        .filter_map(|entry| match entry.pattern {
+                      Pattern::Held { min } => Some(start + min),
+                      _ => None,
+                    })
+        .min()
+  }
+}
+
+/// A `Matcher` fed from a digital input, by default a GPIO.
+#[derive(Debug)]
+pub struct PatternMatcher<P: DigitalPin = GPIO> {
+  pin: P,
+  matcher: Matcher,
+  clock: Arc<dyn Clock>,
+  poll_interval: Duration,
+  matches: VecDeque<String>,
+}
+
+impl PatternMatcher<GPIO> {
+  /// Matches patterns on the GPIO `pin`, which is exported and made an
+  /// input, with its pulses at the level `active`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the pin can't be configured.
+  pub fn new(pin: Pin, active: PinState) -> Result<PatternMatcher> {
+    let input = GPIO::new(pin);
+    input.set_export(DeviceState::Exported)?;
+    input.set_direction(PinDirection::In)?;
+    Ok(PatternMatcher::from_pin(input, active))
+  }
+
+  /// Calls `callback` on a thread of its own with the name of every pattern
+  /// that matches, until the returned subscription is dropped.
+  ///
+  /// The pulses are timed by the pin's edges from the shared reactor, see
+  /// `GPIO::on_edge()`, so they're as accurate as the kernel's interrupt
+  /// latency.
+  /// The matcher is copied, so patterns added later don't take effect.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the pin doesn't support edge interrupts, if it can't be read,
+  /// or if the reactor can't be started.
+  pub fn on_match<F>(&self, mut callback: F) -> Result<MatchSubscription>
+    where F: FnMut(&str) + Send + 'static
+  {
+    let level = self.pin.read()?;
+    let (subscription, edges) = self.pin.edge_events(Edge::Both)?;
+    let mut matcher = self.matcher.clone();
+    let thread = thread::spawn(move || {
+      let epoch = Instant::now();
+      let _ = matcher.feed(level, Duration::from_secs(0));
+      loop {
+        let now = epoch.elapsed();
+        let received = match matcher.next_deadline() {
+          Some(deadline) => edges.recv_timeout(deadline.checked_sub(now).unwrap_or_default()),
+          None => edges.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        let matched = match received {
+          Ok(edge) => matcher.feed(edge.state, edge.timestamp.saturating_duration_since(epoch)),
+          Err(RecvTimeoutError::Timeout) => {
+            let level = matcher.active;
+            matcher.feed(level, epoch.elapsed())
+          }
+          // The subscription was dropped.
+          Err(RecvTimeoutError::Disconnected) => break,
+        };
+        for name in matched {
+          callback(&name);
+        }
+      }
+    });
+    Ok(MatchSubscription {
+      subscription: Some(subscription),
+      thread: Some(thread),
+    })
+  }
+}
+
+impl<P: DigitalPin> PatternMatcher<P> {
+  /// Matches patterns on a configured input, with its pulses at the level
+  /// `active`.
+  pub fn from_pin(pin: P, active: PinState) -> PatternMatcher<P> {
+    PatternMatcher {
+      pin,
+      matcher: Matcher::new(active),
+      clock: clock::system(),
+      poll_interval: Duration::from_millis(1),
+      matches: VecDeque::new(),
+    }
+  }
+
+  /// Returns the matcher, e.g. to add patterns.
+  pub fn matcher_mut(&mut self) -> &mut Matcher {
+    &mut self.matcher
+  }
+
+  /// Sets how often `wait()` samples the input, 1ms by default, which
+  /// limits how accurately polled pulses are timed.
+  pub fn set_poll_interval(&mut self, interval: Duration) {
+    self.poll_interval = interval;
+  }
+
+  /// Makes the matcher take its time from `clock`, e.g. a `TestClock`.
+  pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+    self.clock = clock;
+  }
+
+  /// Samples the input, and returns the name of a pattern that matched, if
+  /// any.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the input can't be read.
+  pub fn poll(&mut self) -> Result<Option<String>> {
+    if let Some(name) = self.matches.pop_front() {
+      return Ok(Some(name));
+    }
+    let level = self.pin.state()?;
+    let matched = self.matcher.feed(level, self.clock.now());
+    self.matches.extend(matched);
+    Ok(self.matches.pop_front())
+  }
+
+  /// Samples the input until a pattern matches, or until `timeout` has
+  /// passed.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the input can't be read.
+  pub fn wait(&mut self, timeout: Duration) -> Result<Option<String>> {
+    let deadline = self.clock.now() + timeout;
+    loop {
+      if let Some(name) = self.poll()? {
+        return Ok(Some(name));
+      }
+      match deadline.checked_sub(self.clock.now()) {
+        Some(remaining) if remaining > Duration::from_secs(0) => {
+          self.clock.sleep(self.poll_interval.min(remaining))?
+        }
+        _ => return Ok(None),
+      }
+    }
+  }
+
+  /// Unwraps the input.
+  pub fn into_inner(self) -> P {
+    self.pin
+  }
+}
+
+/// Calls the callback of matched patterns until it's dropped.
+#[derive(Debug)]
+pub struct MatchSubscription {
+  subscription: Option<Subscription>,
+  thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for MatchSubscription {
+  fn drop(&mut self) {
+    // Dropping the subscription closes the channel the thread waits on.
+    self.subscription = None;
+    if let Some(thread) = self.thread.take() {
+      let _ = thread.join();
+    }
+  }
+}
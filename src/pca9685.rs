@@ -0,0 +1,251 @@
+//! The PCA9685 module.
+//!
+//! The PCA9685 is an I2C PWM controller with 16 channels of 12 bits each,
+//! e.g. on servo and LED driver boards.
+//! Its channels are `PwmOutput`s, so drivers like `Servo` take them like
+//! on-chip PWMs:
+//!
+//! ```no_run
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::pca9685::PCA9685;
+//! use libbeaglebone::servo::Servo;
+//!
+//! // A PCA9685 at address 0x40 on BB_I2C2, driving servos at 50Hz.
+//! let controller = PCA9685::new(2, 0x40).unwrap();
+//! controller.set_period_ns(20_000_000).unwrap();
+//!
+//! let mut servo = Servo::from_pwm(controller.channel(0).unwrap()).unwrap();
+//! servo.set_angle(90.0).unwrap();
+//! ```
+//!
+//! All channels share the period; it's set on the `PCA9685` and a channel
+//! only accepts the period the chip already runs at.
+//! The period comes from the chip's internal 25MHz oscillator, which is only
+//! accurate to a few percent.
+
+use errors::*;
+use hal::{I2cBus, PwmOutput};
+use i2c::I2C;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+const MODE1: u8 = 0x00;
+const MODE2: u8 = 0x01;
+const LED0_ON_L: u8 = 0x06;
+const ALL_LED_ON_L: u8 = 0xFA;
+const PRESCALE: u8 = 0xFE;
+
+const MODE1_RESTART: u8 = 0x80;
+const MODE1_AI: u8 = 0x20;
+const MODE1_SLEEP: u8 = 0x10;
+const MODE2_OUTDRV: u8 = 0x04;
+
+// Set in the high byte of the on or off count, turns a channel fully on or
+// off.
+const FULL: u8 = 0x10;
+
+// The nanoseconds per prescaler step: 4096 counts of the 25MHz oscillator.
+const PRESCALE_STEP_NS: u32 = 163_840;
+const PRESCALE_MIN: u32 = 3;
+const PRESCALE_MAX: u32 = 255;
+
+/// A PCA9685 PWM controller on an I2C bus, by default a bus of its own.
+#[derive(Debug)]
+pub struct PCA9685<B: I2cBus = I2C> {
+  chip: Arc<Mutex<Chip<B>>>,
+}
+
+#[derive(Debug)]
+struct Chip<B: I2cBus> {
+  bus: B,
+  address: u16,
+  prescale: u8,
+}
+
+impl<B: I2cBus> Chip<B> {
+  fn write(&self, register: u8, data: &[u8]) -> Result<()> {
+    let mut buf = Vec::with_capacity(data.len() + 1);
+    buf.push(register);
+    buf.extend_from_slice(data);
+    self.bus
+        .write(self.address, &buf)
+        .chain_err(|| format!("Failed to write PCA9685 0x{:02x} register 0x{:02x}", self.address, register))
+  }
+
+  fn read(&self, register: u8) -> Result<u8> {
+    let mut buf = [0];
+    self.bus
+        .write_read(self.address, &[register], &mut buf)
+        .chain_err(|| format!("Failed to read PCA9685 0x{:02x} register 0x{:02x}", self.address, register))?;
+    Ok(buf[0])
+  }
+
+  /// Sets the prescaler, which only takes while the oscillator sleeps.
+  fn set_prescale(&mut self, prescale: u8) -> Result<()> {
+    self.write(MODE1, &[MODE1_AI | MODE1_SLEEP])?;
+    self.write(PRESCALE, &[prescale])?;
+    self.prescale = prescale;
+    self.wake()
+  }
+
+  /// Starts the oscillator and resumes the channels.
+  fn wake(&self) -> Result<()> {
+    self.write(MODE1, &[MODE1_AI])?;
+    // The oscillator takes up to 500µs to start.
+    thread::sleep(Duration::from_micros(500));
+    self.write(MODE1, &[MODE1_AI | MODE1_RESTART])
+  }
+
+  fn period_ns(&self) -> u32 {
+    (u32::from(self.prescale) + 1) * PRESCALE_STEP_NS
+  }
+}
+
+/// Returns the prescaler for the closest period to `period_ns`.
+fn prescale_for(period_ns: u32) -> Result<u8> {
+  let steps = (period_ns + PRESCALE_STEP_NS / 2) / PRESCALE_STEP_NS;
+  let prescale = steps.saturating_sub(1);
+  if !(PRESCALE_MIN..=PRESCALE_MAX).contains(&prescale) {
+    bail!(format!(
+      "The PCA9685 supports periods from {}ns to {}ns, not {}ns",
+      (PRESCALE_MIN + 1) * PRESCALE_STEP_NS,
+      (PRESCALE_MAX + 1) * PRESCALE_STEP_NS,
+      period_ns
+    ));
+  }
+  Ok(prescale as u8)
+}
+
+impl PCA9685<I2C> {
+  /// Talks to the PCA9685 at `address`, usually 0x40, on the I2C bus
+  /// `i2c_num`, see `with_bus()`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the bus can't be opened or the chip doesn't answer.
+  pub fn new(i2c_num: u8, address: u16) -> Result<PCA9685> {
+    PCA9685::with_bus(I2C::new(i2c_num)?, address)
+  }
+}
+
+impl<B: I2cBus> PCA9685<B> {
+  /// Talks to the PCA9685 at `address` on any I2C bus, e.g. a shared bus
+  /// or a multiplexer channel.
+  ///
+  /// All channels are turned off and made totem pole outputs; the period
+  /// is kept, 5ms after power-on.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the chip doesn't answer.
+  pub fn with_bus(bus: B, address: u16) -> Result<PCA9685<B>> {
+    let mut chip = Chip {
+      bus,
+      address,
+      prescale: 0,
+    };
+    chip.prescale = chip.read(PRESCALE)?;
+    chip.write(MODE2, &[MODE2_OUTDRV])?;
+    chip.write(ALL_LED_ON_L, &[0, 0, 0, FULL])?;
+    chip.wake()?;
+    Ok(PCA9685 { chip: Arc::new(Mutex::new(chip)) })
+  }
+
+  /// Sets the period of all channels to the closest the chip supports, from
+  /// about 0.66ms to 42ms in steps of 164µs.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the period is out of range or the chip doesn't answer.
+  pub fn set_period_ns(&self, period_ns: u32) -> Result<()> {
+    let prescale = prescale_for(period_ns)?;
+    lock(&self.chip).set_prescale(prescale)
+  }
+
+  /// Returns the period of all channels as configured.
+  pub fn period_ns(&self) -> u32 {
+    lock(&self.chip).period_ns()
+  }
+
+  /// Returns channel `num`, 0 to 15; it's off until it's enabled.
+  ///
+  /// # Errors
+  ///
+  /// Fails if there's no such channel.
+  pub fn channel(&self, num: u8) -> Result<Pca9685Channel<B>> {
+    if num >= 16 {
+      bail!(format!("The PCA9685 has channels 0 to 15, not {}", num));
+    }
+    Ok(Pca9685Channel {
+      chip: self.chip.clone(),
+      num,
+      duty_cycle: 0.0,
+      enabled: false,
+    })
+  }
+}
+
+/// A channel of a PCA9685.
+#[derive(Debug)]
+pub struct Pca9685Channel<B: I2cBus = I2C> {
+  chip: Arc<Mutex<Chip<B>>>,
+  num: u8,
+  duty_cycle: f32,
+  enabled: bool,
+}
+
+impl<B: I2cBus> Pca9685Channel<B> {
+  /// Returns the number of the channel, 0 to 15.
+  pub fn num(&self) -> u8 {
+    self.num
+  }
+
+  /// Writes the on and off counts of the duty cycle, or turns the channel
+  /// fully off while it's disabled.
+  fn write_counts(&self) -> Result<()> {
+    let off = if self.enabled {
+      (self.duty_cycle / 100.0 * 4096.0).round() as u16
+    } else {
+      0
+    };
+    let counts = match off {
+      0 => [0, 0, 0, FULL],
+      4096 => [0, FULL, 0, 0],
+      _ => [0, 0, off as u8, (off >> 8) as u8],
+    };
+    lock(&self.chip).write(LED0_ON_L + 4 * self.num, &counts)
+  }
+}
+
+impl<B: I2cBus> PwmOutput for Pca9685Channel<B> {
+  fn set_period_ns(&mut self, period_ns: u32) -> Result<()> {
+    let chip = lock(&self.chip);
+    if prescale_for(period_ns)? != chip.prescale {
+      bail!(format!(
+        "PCA9685 channel {} runs at the chip's period of {}ns, set it on the PCA9685 to change it to {}ns",
+        self.num,
+        chip.period_ns(),
+        period_ns
+      ));
+    }
+    Ok(())
+  }
+
+  fn set_duty_cycle_percent(&mut self, percentage: f32) -> Result<()> {
+    if !(0.0..=100.0).contains(&percentage) {
+      bail!(format!("Duty cycle {}% of PCA9685 channel {} is not between 0% and 100%", percentage, self.num));
+    }
+    self.duty_cycle = percentage;
+    self.write_counts()
+  }
+
+  fn set_enabled(&mut self, enabled: bool) -> Result<()> {
+    self.enabled = enabled;
+    self.write_counts()
+  }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+  mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
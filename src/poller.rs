@@ -0,0 +1,413 @@
+//! The poller module.
+//!
+//! Most data loggers are the same loop: read a few sensors, each at its own
+//! rate, and send the readings somewhere.
+//! A `Poller` runs that loop on a thread of its own, sampling the sensors
+//! registered with it and forwarding every reading to all of its sinks:
+//!
+//! ```no_run
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::poller::{CsvSink, MqttSink, Poller};
+//! use std::sync::mpsc;
+//! use std::time::Duration;
+//!
+//! let mut poller = Poller::new();
+//! // ADC channels are sampled in millivolts.
+//! poller.add_sensor("battery", Duration::from_secs(1), ADC::new(AIN_1, 1.0));
+//! // Any other sensor through a closure, e.g. an I2C driver.
+//! let mut count = 0.0;
+//! poller.add_sensor("uptime", Duration::from_secs(10), move || {
+//!   count += 10.0;
+//!   Ok(count)
+//! });
+//!
+//! poller.add_sink(CsvSink::create("/var/log/readings.csv").unwrap());
+//! poller.add_sink(MqttSink::connect("broker.local:1883", "bbb-1", "greenhouse").unwrap());
+//! let (sender, readings) = mpsc::channel();
+//! poller.add_sink(sender);
+//!
+//! let _running = poller.start();
+//! for reading in readings {
+//!   println!("{}: {}", reading.sensor, reading.value);
+//! }
+//! ```
+//!
+//! A sensor that can't be sampled, or a sink that can't take a reading,
+//! doesn't stop the others; the errors are kept for `take_errors()`.
+//! Readings that are due while a slow sensor is sampled are taken late, and
+//! missed periods are skipped rather than caught up with.
+
+use adc::ADC;
+use errors::*;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::mem;
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How many errors are kept for `take_errors()`; older ones are dropped.
+const MAX_ERRORS: usize = 100;
+
+/// A value sampled from a sensor.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reading {
+  /// The name the sensor was registered with.
+  pub sensor: String,
+  /// The sampled value.
+  pub value: f32,
+  /// When the value was sampled.
+  pub time: SystemTime,
+}
+
+/// Something that can be sampled for a value.
+pub trait Sensor {
+  /// Samples the sensor.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the sensor can't be read.
+  fn sample(&mut self) -> Result<f32>;
+}
+
+impl Sensor for ADC {
+  /// Reads the input in millivolts.
+  fn sample(&mut self) -> Result<f32> {
+    self.read_millivolts()
+  }
+}
+
+impl<F: FnMut() -> Result<f32>> Sensor for F {
+  fn sample(&mut self) -> Result<f32> {
+    self()
+  }
+}
+
+/// Where a poller forwards its readings to.
+pub trait Sink {
+  /// Takes a reading.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the reading can't be stored or sent.
+  fn send(&mut self, reading: &Reading) -> Result<()>;
+}
+
+impl<F: FnMut(&Reading) -> Result<()>> Sink for F {
+  fn send(&mut self, reading: &Reading) -> Result<()> {
+    self(reading)
+  }
+}
+
+impl Sink for Sender<Reading> {
+  /// Sends the reading on the channel.
+  ///
+  /// A closed channel isn't an error, the readings are dropped then.
+  fn send(&mut self, reading: &Reading) -> Result<()> {
+    let _ = Sender::send(self, reading.clone());
+    Ok(())
+  }
+}
+
+/// Appends readings to a CSV file, one "time,sensor,value" row each, with
+/// the time in seconds since the Unix epoch.
+#[derive(Debug)]
+pub struct CsvSink<W: Write = File> {
+  writer: W,
+}
+
+impl CsvSink<File> {
+  /// Appends to the file at `path`, creating it with a header row if it
+  /// doesn't exist or is empty.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the file can't be opened or written.
+  pub fn create<P: AsRef<Path>>(path: P) -> Result<CsvSink> {
+    let path = path.as_ref();
+    let file = OpenOptions::new()
+      .append(true)
+      .create(true)
+      .open(path)
+      .chain_err(|| format!("Failed to open CSV file {}", path.display()))?;
+    let empty = file.metadata().map(|metadata| metadata.len() == 0).unwrap_or(true);
+    let mut sink = CsvSink::new(file);
+    if empty {
+      writeln!(sink.writer, "time,sensor,value")
+        .chain_err(|| format!("Failed to write CSV file {}", path.display()))?;
+    }
+    Ok(sink)
+  }
+}
+
+impl<W: Write> CsvSink<W> {
+  /// Writes rows to `writer`, without a header row.
+  pub fn new(writer: W) -> CsvSink<W> {
+    CsvSink { writer }
+  }
+
+  /// Unwraps the writer.
+  pub fn into_inner(self) -> W {
+    self.writer
+  }
+}
+
+impl<W: Write> Sink for CsvSink<W> {
+  fn send(&mut self, reading: &Reading) -> Result<()> {
+    let time = reading.time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    writeln!(self.writer,
+             "{:.3},{},{}",
+             time.as_secs_f64(),
+             csv_field(&reading.sensor),
+             reading.value)
+      .and_then(|_| self.writer.flush())
+      .chain_err(|| "Failed to write a CSV row")
+  }
+}
+
+/// Quotes `field` if it needs to be.
+fn csv_field(field: &str) -> String {
+  if field.contains([',', '"', '\n']) {
+    format!("\"{}\"", field.replace('"', "\"\""))
+  } else {
+    field.to_string()
+  }
+}
+
+/// Publishes readings to an MQTT broker, each to the topic
+/// "<prefix>/<sensor>".
+///
+/// This is a minimal MQTT 3.1.1 client: it publishes at QoS 0 without
+/// authentication or TLS, and doesn't reconnect.
+#[derive(Debug)]
+pub struct MqttSink {
+  stream: TcpStream,
+  prefix: String,
+}
+
+impl MqttSink {
+  /// Connects to the broker at `address`, e.g. "broker.local:1883", as the
+  /// client `client_id`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the broker can't be reached, or refuses the connection.
+  pub fn connect(address: &str, client_id: &str, prefix: &str) -> Result<MqttSink> {
+    let mut stream = TcpStream::connect(address)
+      .chain_err(|| format!("Failed to connect to MQTT broker {}", address))?;
+    let _ = stream.set_read_timeout(Some(Duration::from_secs(10)));
+    // Protocol "MQTT" level 4, a clean session and no keep-alive.
+    let mut connect = vec![0, 4, b'M', b'Q', b'T', b'T', 4, 0x02, 0, 0];
+    mqtt_string(&mut connect, client_id)?;
+    let mut ack = [0; 4];
+    stream.write_all(&mqtt_packet(0x10, &connect)?)
+          .and_then(|_| stream.read_exact(&mut ack))
+          .chain_err(|| format!("Failed to connect to MQTT broker {}", address))?;
+    if ack[0] != 0x20 || ack[3] != 0 {
+      bail!(format!("MQTT broker {} refused the connection with code {}", address, ack[3]));
+    }
+    Ok(MqttSink {
+      stream,
+      prefix: prefix.trim_end_matches('/').to_string(),
+    })
+  }
+}
+
+impl Sink for MqttSink {
+  fn send(&mut self, reading: &Reading) -> Result<()> {
+    let mut publish = Vec::new();
+    mqtt_string(&mut publish, &format!("{}/{}", self.prefix, reading.sensor))?;
+    publish.extend_from_slice(reading.value.to_string().as_bytes());
+    let packet = mqtt_packet(0x30, &publish)?;
+    self.stream
+        .write_all(&packet)
+        .chain_err(|| format!("Failed to publish reading of {} to MQTT", reading.sensor))
+  }
+}
+
+impl Drop for MqttSink {
+  fn drop(&mut self) {
+    // DISCONNECT, so the broker doesn't treat it as a lost connection.
+    let _ = self.stream.write_all(&[0xE0, 0]);
+  }
+}
+
+/// Appends `string` to `buf` as an MQTT string, prefixed by its length.
+fn mqtt_string(buf: &mut Vec<u8>, string: &str) -> Result<()> {
+  if string.len() > usize::from(u16::MAX) {
+    bail!("MQTT strings are limited to 65535 bytes");
+  }
+  buf.extend_from_slice(&(string.len() as u16).to_be_bytes());
+  buf.extend_from_slice(string.as_bytes());
+  Ok(())
+}
+
+/// Returns an MQTT packet of type `header` with the body `body`.
+fn mqtt_packet(header: u8, body: &[u8]) -> Result<Vec<u8>> {
+  if body.len() >= 1 << 28 {
+    bail!("MQTT packets are limited to 256MB");
+  }
+  let mut packet = vec![header];
+  // The remaining length, 7 bits per byte.
+  let mut len = body.len();
+  loop {
+    let byte = (len % 128) as u8;
+    len /= 128;
+    packet.push(if len > 0 { byte | 0x80 } else { byte });
+    if len == 0 {
+      break;
+    }
+  }
+  packet.extend_from_slice(body);
+  Ok(packet)
+}
+
+struct Source {
+  name: String,
+  interval: Duration,
+  sensor: Box<dyn Sensor + Send>,
+}
+
+/// Samples sensors periodically and forwards their readings to sinks.
+#[derive(Default)]
+pub struct Poller {
+  sources: Vec<Source>,
+  sinks: Vec<Box<dyn Sink + Send>>,
+}
+
+impl fmt::Debug for Poller {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let sensors: Vec<(&str, Duration)> = self.sources
+                                             .iter()
+                                             .map(|source| (source.name.as_str(), source.interval))
+                                             .collect();
+    f.debug_struct("Poller")
+     .field("sensors", &sensors)
+     .field("sinks", &self.sinks.len())
+     .finish()
+  }
+}
+
+impl Poller {
+  /// Creates a poller without sensors or sinks.
+  pub fn new() -> Poller {
+    Poller::default()
+  }
+
+  /// Samples `sensor` every `interval`, with its readings named `name`.
+  pub fn add_sensor<S: Sensor + Send + 'static>(&mut self, name: &str, interval: Duration, sensor: S) {
+    self.sources.push(Source {
+      name: name.to_string(),
+      interval,
+      sensor: Box::new(sensor),
+    });
+  }
+
+  /// Forwards all readings to `sink`, in addition to the other sinks.
+  pub fn add_sink<S: Sink + Send + 'static>(&mut self, sink: S) {
+    self.sinks.push(Box::new(sink));
+  }
+
+  /// Starts sampling on a thread of its own, until the returned handle is
+  /// dropped.
+  ///
+  /// Every sensor is sampled right away, then at its interval.
+  pub fn start(self) -> RunningPoller {
+    let errors = Arc::new(Mutex::new(VecDeque::new()));
+    let (stop, stopped) = mpsc::channel::<()>();
+    let thread = {
+      let errors = errors.clone();
+      let Poller { mut sources, mut sinks } = self;
+      thread::spawn(move || {
+        let now = Instant::now();
+        let mut due: Vec<Instant> = sources.iter().map(|_| now).collect();
+        loop {
+          let now = Instant::now();
+          for (source, due) in sources.iter_mut().zip(&mut due) {
+            if *due > now {
+              continue;
+            }
+            *due += source.interval;
+            if *due <= now {
+              *due = now + source.interval;
+            }
+            poll(source, &mut sinks, &errors);
+          }
+          let wait = match due.iter().min() {
+            Some(next) => stopped.recv_timeout(next.saturating_duration_since(Instant::now())),
+            None => stopped.recv().map_err(|_| RecvTimeoutError::Disconnected),
+          };
+          if wait != Err(RecvTimeoutError::Timeout) {
+            break;
+          }
+        }
+      })
+    };
+    RunningPoller {
+      errors,
+      stop: Some(stop),
+      thread: Some(thread),
+    }
+  }
+}
+
+/// Samples `source` and forwards the reading to `sinks`.
+fn poll(source: &mut Source, sinks: &mut [Box<dyn Sink + Send>], errors: &Mutex<VecDeque<Error>>) {
+  let value = match source.sensor.sample() {
+    Ok(value) => value,
+    Err(e) => return record(errors, Error::with_chain(e, format!("Failed to sample {}", source.name))),
+  };
+  let reading = Reading {
+    sensor: source.name.clone(),
+    value,
+    time: SystemTime::now(),
+  };
+  for sink in sinks {
+    if let Err(e) = sink.send(&reading) {
+      record(errors, e);
+    }
+  }
+}
+
+fn record(errors: &Mutex<VecDeque<Error>>, error: Error) {
+  let mut errors = lock(errors);
+  if errors.len() == MAX_ERRORS {
+    let _ = errors.pop_front();
+  }
+  errors.push_back(error);
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+  mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// A started poller, stopped when dropped.
+#[derive(Debug)]
+pub struct RunningPoller {
+  errors: Arc<Mutex<VecDeque<Error>>>,
+  stop: Option<Sender<()>>,
+  thread: Option<JoinHandle<()>>,
+}
+
+impl RunningPoller {
+  /// Returns and clears the errors of sampling the sensors and forwarding
+  /// the readings, at most the latest 100.
+  pub fn take_errors(&self) -> Vec<Error> {
+    mem::take(&mut *lock(&self.errors)).into_iter().collect()
+  }
+}
+
+impl Drop for RunningPoller {
+  fn drop(&mut self) {
+    // Dropping the sender wakes the thread.
+    self.stop = None;
+    if let Some(thread) = self.thread.take() {
+      let _ = thread.join();
+    }
+  }
+}
@@ -0,0 +1,720 @@
+//! The power module.
+//!
+//! Mobile robots and other battery-powered projects need to know how much
+//! charge is left.
+//! The `Battery` type estimates this from a `BatterySource`, which measures
+//! the battery's voltage (and current, if possible) using one of:
+//!
+//! * `PowerSupply`: a PMIC or fuel gauge exposed by the kernel under
+//!   `/sys/class/power_supply`.
+//! * `INA219`: an INA219 current/voltage monitor on any `I2cBus`.
+//! * `ADCDivider`: a resistor divider feeding one of the ADC inputs.
+//!
+//! The state of charge is estimated from the voltage using a
+//! `DischargeCurve`, which should be adjusted to the battery's chemistry.
+//!
+//! A `LowVoltageGuard` watches the voltage of a source to protect the
+//! battery of an unattended deployment: it reports when the voltage drops
+//! below warning thresholds, and shuts the system down, or calls back, when
+//! it drops below a critical one:
+//!
+//! ```no_run
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::power::{ADCDivider, LowVoltageGuard};
+//!
+//! // A 3S lithium polymer pack, empty below 9.9V.
+//! let mut guard = LowVoltageGuard::new(ADCDivider::new(AIN_1, 11.0), 9.9);
+//! guard.add_warning(10.8).unwrap();
+//! guard.add_warning(10.4).unwrap();
+//! guard.set_on_event(|event| println!("{:?}", event));
+//! guard.set_shutdown_on_critical(true);
+//!
+//! // Checks the voltage every second until dropped.
+//! let _running = guard.start();
+//! ```
+
+use adc::ADC;
+use clock::{self, Clock};
+use errors::*;
+use hal::I2cBus;
+use i2c::I2C;
+use pins::Pin;
+use std::collections::VecDeque;
+use std::fmt;
+use std::mem;
+use std::path::Path;
+use std::process::Command;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use util::*;
+
+/// INA219 shunt voltage register, LSB is 10uV.
+const INA219_SHUNT_VOLTAGE: u8 = 0x01;
+
+/// INA219 bus voltage register, the upper 13 bits have an LSB of 4mV.
+const INA219_BUS_VOLTAGE: u8 = 0x02;
+
+/// The number of errors a running `LowVoltageGuard` keeps.
+const MAX_ERRORS: usize = 100;
+
+type EventCallback = Box<dyn FnMut(&VoltageEvent) + Send>;
+type CriticalCallback = Box<dyn FnMut(f32) + Send>;
+
+/// Something that can measure a battery's voltage and current.
+pub trait BatterySource {
+  /// Reads the battery voltage in volts.
+  fn voltage(&self) -> Result<f32>;
+
+  /// Reads the current drawn from the battery in amperes, positive when
+  /// discharging.
+  ///
+  /// Returns `None` if the source can't measure current.
+  fn current(&self) -> Result<Option<f32>>;
+}
+
+/// A battery monitored by the kernel's power supply class, usually a PMIC or
+/// fuel gauge.
+#[derive(Debug)]
+pub struct PowerSupply {
+  name: String,
+}
+
+impl PowerSupply {
+  /// Creates a new power supply source.
+  ///
+  /// `name` is the name of the supply's directory under
+  /// `/sys/class/power_supply`.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::power::PowerSupply;
+  ///
+  /// let supply = PowerSupply::new("battery");
+  /// ```
+  pub fn new(name: &str) -> PowerSupply {
+    PowerSupply { name: name.to_string() }
+  }
+
+  /// Reads an attribute given in micro-units and converts it to base units.
+  fn read_micro(&self, attribute: &str) -> Result<f32> {
+    let path = format!("/sys/class/power_supply/{}/{}", self.name, attribute);
+    let value = path.read_file()
+                    .chain_err(|| format!("Failed to read power supply {}", &self.name))?
+                    .trim()
+                    .parse::<i64>()
+                    .chain_err(|| format!("Failed to parse {}", &path))?;
+    Ok(value as f32 / 1_000_000.0)
+  }
+}
+
+impl BatterySource for PowerSupply {
+  fn voltage(&self) -> Result<f32> {
+    self.read_micro("voltage_now")
+  }
+
+  fn current(&self) -> Result<Option<f32>> {
+    let path = format!("/sys/class/power_supply/{}/current_now", self.name);
+    if !Path::new(&path).exists() {
+      return Ok(None);
+    }
+    Ok(Some(self.read_micro("current_now")?))
+  }
+}
+
+/// A battery monitored by an INA219 current/voltage sensor.
+///
+/// The battery voltage is measured on the bus side of the shunt resistor.
+#[derive(Debug)]
+pub struct INA219<B: I2cBus = I2C> {
+  bus: B,
+  address: u16,
+  shunt_ohms: f32,
+}
+
+impl INA219<I2C> {
+  /// Creates a new INA219 source.
+  ///
+  /// `shunt_ohms` is the value of the shunt resistor, 0.1 ohms on most
+  /// breakout boards.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::power::INA219;
+  ///
+  /// // Use an INA219 at address 0x40 on BB_I2C2 with a 0.1 ohm shunt.
+  /// let sensor = INA219::new(2, 0x40, 0.1).unwrap();
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if the I2C bus can't be opened.
+  pub fn new(i2c_num: u8, address: u16, shunt_ohms: f32) -> Result<INA219> {
+    Ok(INA219::with_bus(I2C::new(i2c_num)?, address, shunt_ohms))
+  }
+}
+
+impl<B: I2cBus> INA219<B> {
+  /// Creates a new INA219 source on any I2C bus, e.g. a shared bus or a
+  /// multiplexer channel.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::i2c_bus::I2cBusManager;
+  /// use libbeaglebone::power::INA219;
+  ///
+  /// // Share BB_I2C2 between the INA219 and other devices.
+  /// let bus = I2cBusManager::new(2).unwrap();
+  /// let sensor = INA219::with_bus(bus.clone(), 0x40, 0.1);
+  /// ```
+  pub fn with_bus(bus: B, address: u16, shunt_ohms: f32) -> INA219<B> {
+    INA219 {
+      bus,
+      address,
+      shunt_ohms,
+    }
+  }
+
+  /// Reads a 16-bit big-endian register.
+  fn read_register(&self, register: u8) -> Result<u16> {
+    let mut buf = [0; 2];
+    self.bus.write_read(self.address, &[register], &mut buf)?;
+    Ok(u16::from(buf[0]) << 8 | u16::from(buf[1]))
+  }
+}
+
+impl<B: I2cBus> BatterySource for INA219<B> {
+  fn voltage(&self) -> Result<f32> {
+    let raw = self.read_register(INA219_BUS_VOLTAGE)
+                  .chain_err(|| "Failed to read INA219 bus voltage")?;
+    Ok(f32::from(raw >> 3) * 0.004)
+  }
+
+  fn current(&self) -> Result<Option<f32>> {
+    let raw = self.read_register(INA219_SHUNT_VOLTAGE)
+                  .chain_err(|| "Failed to read INA219 shunt voltage")?;
+    let shunt_volts = f32::from(raw as i16) * 0.000_01;
+    Ok(Some(shunt_volts / self.shunt_ohms))
+  }
+}
+
+/// A battery connected to an ADC input through a resistor divider.
+#[derive(Debug)]
+pub struct ADCDivider {
+  adc: ADC,
+  ratio: f32,
+}
+
+impl ADCDivider {
+  /// Creates a new ADC divider source.
+  ///
+  /// `ratio` is the divider's division ratio, i.e. `(R1 + R2) / R2` where R2
+  /// is the resistor between the ADC input and ground.
+  /// Make sure the divided voltage never exceeds 1.8V!
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  /// use libbeaglebone::power::ADCDivider;
+  ///
+  /// // A 10k/1k divider on AIN_1 measures up to 19.8V.
+  /// let divider = ADCDivider::new(AIN_1, 11.0);
+  /// ```
+  pub fn new(pin: Pin, ratio: f32) -> ADCDivider {
+    ADCDivider {
+      adc: ADC::new(pin, 1.0),
+      ratio,
+    }
+  }
+}
+
+impl BatterySource for ADCDivider {
+  fn voltage(&self) -> Result<f32> {
+    Ok(self.adc.read_volts()? * self.ratio)
+  }
+
+  fn current(&self) -> Result<Option<f32>> {
+    Ok(None)
+  }
+}
+
+/// Maps battery voltages to an estimated state of charge.
+///
+/// Voltages between the points of the curve are linearly interpolated, and
+/// voltages outside of it are clamped to the nearest end.
+#[derive(Debug, Clone)]
+pub struct DischargeCurve {
+  points: Vec<(f32, f32)>,
+}
+
+impl DischargeCurve {
+  /// Creates a discharge curve from `(voltage, percentage)` points.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use libbeaglebone::power::DischargeCurve;
+  ///
+  /// // A rough curve for a 2S lithium polymer pack.
+  /// let curve = DischargeCurve::new(&[(6.6, 0.0), (7.4, 50.0), (8.4, 100.0)]).unwrap();
+  /// assert_eq!(curve.state_of_charge(7.4), 50.0);
+  ///
+  /// assert!(DischargeCurve::new(&[(6.6, 0.0), (f32::NAN, 50.0)]).is_err());
+  /// assert!(DischargeCurve::new(&[(6.6, 0.0), (6.6, 50.0)]).is_err());
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if no points are given, if a voltage or percentage isn't a finite
+  /// number, or if two points have the same voltage.
+  pub fn new(points: &[(f32, f32)]) -> Result<DischargeCurve> {
+    if points.is_empty() {
+      bail!("A discharge curve needs at least one point");
+    }
+    if let Some(&(volts, percentage)) = points.iter().find(|&&(volts, percentage)| {
+      !volts.is_finite() || !percentage.is_finite()
+    }) {
+      bail!(format!("The discharge curve point ({}V, {}%) isn't finite", volts, percentage));
+    }
+    let mut points = points.to_vec();
+    points.sort_by(|a, b| a.0.total_cmp(&b.0));
+    if let Some(pair) = points.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+      bail!(format!("The discharge curve has two points at {}V", pair[0].0));
+    }
+    Ok(DischargeCurve { points })
+  }
+
+  /// Creates a discharge curve that is linear between the empty and full
+  /// voltages.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use libbeaglebone::power::DischargeCurve;
+  ///
+  /// let curve = DischargeCurve::linear(9.9, 12.6).unwrap();
+  /// assert_eq!(curve.state_of_charge(9.0), 0.0);
+  /// assert!(curve.state_of_charge(f32::NAN).is_nan());
+  ///
+  /// assert!(DischargeCurve::linear(12.6, 12.6).is_err());
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if a voltage isn't a finite number, or if both are the same.
+  pub fn linear(empty_volts: f32, full_volts: f32) -> Result<DischargeCurve> {
+    DischargeCurve::new(&[(empty_volts, 0.0), (full_volts, 100.0)])
+  }
+
+  /// Returns the estimated state of charge (0-100%) at a given voltage, or
+  /// NaN if the voltage isn't a finite number.
+  pub fn state_of_charge(&self, volts: f32) -> f32 {
+    if !volts.is_finite() {
+      return f32::NAN;
+    }
+    let first = self.points[0];
+    let last = self.points[self.points.len() - 1];
+    if volts <= first.0 {
+      return first.1;
+    }
+    if volts >= last.0 {
+      return last.1;
+    }
+
+    for pair in self.points.windows(2) {
+      let (low, high) = (pair[0], pair[1]);
+      if volts <= high.0 {
+        let fraction = (volts - low.0) / (high.0 - low.0);
+        return low.1 + fraction * (high.1 - low.1);
+      }
+    }
+    last.1
+  }
+}
+
+/// A battery gauge combining a measurement source and a discharge curve.
+#[derive(Debug)]
+pub struct Battery<S: BatterySource> {
+  source: S,
+  curve: DischargeCurve,
+}
+
+impl<S: BatterySource> Battery<S> {
+  /// Creates a new battery gauge.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  /// use libbeaglebone::power::{ADCDivider, Battery, DischargeCurve};
+  ///
+  /// // A 3S lithium polymer pack measured through a 10k/1k divider.
+  /// let source = ADCDivider::new(AIN_1, 11.0);
+  /// let battery = Battery::new(source, DischargeCurve::linear(9.9, 12.6).unwrap());
+  ///
+  /// println!("{}% left", battery.state_of_charge().unwrap());
+  /// ```
+  pub fn new(source: S, curve: DischargeCurve) -> Battery<S> {
+    Battery { source, curve }
+  }
+
+  /// Reads the battery voltage in volts.
+  pub fn voltage(&self) -> Result<f32> {
+    self.source.voltage()
+  }
+
+  /// Reads the battery current in amperes, if the source can measure it.
+  pub fn current(&self) -> Result<Option<f32>> {
+    self.source.current()
+  }
+
+  /// Estimates the battery's state of charge (0-100%) from its voltage.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the voltage can't be read from the source, or isn't a finite
+  /// number.
+  pub fn state_of_charge(&self) -> Result<f32> {
+    let volts = self.source.voltage()?;
+    if !volts.is_finite() {
+      bail!(format!("The battery voltage {}V isn't a finite number", volts));
+    }
+    Ok(self.curve.state_of_charge(volts))
+  }
+
+  /// Returns a reference to the underlying source.
+  pub fn source(&self) -> &S {
+    &self.source
+  }
+}
+
+/// A change of the voltage a `LowVoltageGuard` watches.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VoltageEvent {
+  /// The voltage dropped below the warning threshold `threshold`, the
+  /// lowest one crossed.
+  Warning { threshold: f32, volts: f32 },
+  /// The voltage rose back above the warning threshold `threshold`, the
+  /// highest one recovered, by the hysteresis.
+  Recovered { threshold: f32, volts: f32 },
+  /// The voltage dropped below the critical threshold.
+  Critical { volts: f32 },
+}
+
+/// Watches the voltage of a battery source, reporting warning thresholds
+/// and acting at a critical one.
+///
+/// A threshold only counts as crossed once a few samples in a row are below
+/// it, 3 by default, so a short sag under load doesn't trigger it.
+/// Reaching the critical threshold is latched: the callback and the
+/// shutdown run once, until `reset()`.
+pub struct LowVoltageGuard<S: BatterySource> {
+  source: S,
+  // The warning thresholds, highest first, and the critical one last.
+  thresholds: Vec<f32>,
+  hysteresis: f32,
+  debounce: u32,
+  poll_interval: Duration,
+  clock: Arc<dyn Clock>,
+  // How many thresholds are crossed.
+  level: usize,
+  // A level that differs from `level`, and in how many samples in a row.
+  candidate: Option<(usize, u32)>,
+  volts: Option<f32>,
+  on_event: Option<EventCallback>,
+  on_critical: Option<CriticalCallback>,
+  shutdown: bool,
+}
+
+impl<S: BatterySource> fmt::Debug for LowVoltageGuard<S> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct("LowVoltageGuard")
+     .field("thresholds", &self.thresholds)
+     .field("hysteresis", &self.hysteresis)
+     .field("debounce", &self.debounce)
+     .field("poll_interval", &self.poll_interval)
+     .field("level", &self.level)
+     .field("volts", &self.volts)
+     .field("shutdown", &self.shutdown)
+     .finish()
+  }
+}
+
+impl<S: BatterySource> LowVoltageGuard<S> {
+  /// Watches `source`, acting below `critical_volts`.
+  ///
+  /// By default, there are no warning thresholds and nothing is done at the
+  /// critical one but reporting it, see `set_on_critical()` and
+  /// `set_shutdown_on_critical()`.
+  pub fn new(source: S, critical_volts: f32) -> LowVoltageGuard<S> {
+    LowVoltageGuard {
+      source,
+      thresholds: vec![critical_volts],
+      hysteresis: 0.1,
+      debounce: 3,
+      poll_interval: Duration::from_secs(1),
+      clock: clock::system(),
+      level: 0,
+      candidate: None,
+      volts: None,
+      on_event: None,
+      on_critical: None,
+      shutdown: false,
+    }
+  }
+
+  /// Adds a warning threshold.
+  ///
+  /// # Errors
+  ///
+  /// Fails if `volts` isn't above the critical threshold, or if it's a
+  /// threshold already.
+  pub fn add_warning(&mut self, volts: f32) -> Result<()> {
+    let critical = self.critical_volts();
+    if volts <= critical {
+      bail!(format!("A warning at {}V has to be above the critical {}V", volts, critical));
+    }
+    if self.thresholds.contains(&volts) {
+      bail!(format!("There's a warning at {}V already", volts));
+    }
+    let index = self.thresholds.iter().position(|&threshold| threshold < volts).unwrap_or(0);
+    self.thresholds.insert(index, volts);
+    if index < self.level {
+      self.level += 1;
+    }
+    Ok(())
+  }
+
+  /// Returns the critical threshold in volts.
+  pub fn critical_volts(&self) -> f32 {
+    self.thresholds[self.thresholds.len() - 1]
+  }
+
+  /// Sets by how much the voltage has to rise back above a warning
+  /// threshold to recover from it, 0.1V by default.
+  pub fn set_hysteresis(&mut self, volts: f32) {
+    self.hysteresis = volts.max(0.0);
+  }
+
+  /// Sets how many samples in a row have to cross a threshold before it
+  /// counts, 3 by default.
+  pub fn set_debounce(&mut self, samples: u32) {
+    self.debounce = samples.max(1);
+  }
+
+  /// Sets the time between the samples of `wait()` and `start()`, 1s by
+  /// default.
+  pub fn set_poll_interval(&mut self, poll_interval: Duration) {
+    self.poll_interval = poll_interval;
+  }
+
+  /// Makes the guard take its time from `clock`, e.g. a `TestClock`.
+  pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+    self.clock = clock;
+  }
+
+  /// Calls `callback` with every event, from the thread checking the
+  /// voltage.
+  pub fn set_on_event<F: FnMut(&VoltageEvent) + Send + 'static>(&mut self, callback: F) {
+    self.on_event = Some(Box::new(callback));
+  }
+
+  /// Calls `callback` with the voltage when it drops below the critical
+  /// threshold, e.g. to save state, before any shutdown.
+  pub fn set_on_critical<F: FnMut(f32) + Send + 'static>(&mut self, callback: F) {
+    self.on_critical = Some(Box::new(callback));
+  }
+
+  /// Makes the guard run `shutdown -h now` when the voltage drops below the
+  /// critical threshold, which takes root privileges.
+  pub fn set_shutdown_on_critical(&mut self, shutdown: bool) {
+    self.shutdown = shutdown;
+  }
+
+  /// Returns the voltage sampled last, if any.
+  pub fn voltage(&self) -> Option<f32> {
+    self.volts
+  }
+
+  /// Returns whether the critical threshold was reached.
+  pub fn is_critical(&self) -> bool {
+    self.level == self.thresholds.len()
+  }
+
+  /// Forgets the thresholds crossed, e.g. after the battery was swapped.
+  pub fn reset(&mut self) {
+    self.level = 0;
+    self.candidate = None;
+  }
+
+  /// Returns the watched source.
+  pub fn source(&self) -> &S {
+    &self.source
+  }
+
+  /// Samples the voltage once, and returns the event if a threshold was
+  /// crossed, after running the callbacks and the critical action.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the voltage can't be read, or if the shutdown can't be run.
+  pub fn check(&mut self) -> Result<Option<VoltageEvent>> {
+    let volts = self.source.voltage()?;
+    self.volts = Some(volts);
+    if self.is_critical() {
+      return Ok(None);
+    }
+    let level = self.classify(volts);
+    if level == self.level {
+      self.candidate = None;
+      return Ok(None);
+    }
+    let seen = match self.candidate {
+      Some((candidate, seen)) if candidate == level => seen + 1,
+      _ => 1,
+    };
+    if seen < self.debounce {
+      self.candidate = Some((level, seen));
+      return Ok(None);
+    }
+    self.candidate = None;
+    let event = if level < self.level {
+      VoltageEvent::Recovered {
+        threshold: self.thresholds[level],
+        volts,
+      }
+    } else if level == self.thresholds.len() {
+      VoltageEvent::Critical { volts }
+    } else {
+      VoltageEvent::Warning {
+        threshold: self.thresholds[level - 1],
+        volts,
+      }
+    };
+    self.level = level;
+    if let Some(ref mut on_event) = self.on_event {
+      on_event(&event);
+    }
+    if let VoltageEvent::Critical { volts } = event {
+      if let Some(ref mut on_critical) = self.on_critical {
+        on_critical(volts);
+      }
+      if self.shutdown {
+        shutdown(volts)?;
+      }
+    }
+    Ok(Some(event))
+  }
+
+  /// Samples the voltage until a threshold is crossed, or until `timeout`
+  /// has passed.
+  ///
+  /// # Errors
+  ///
+  /// See `check()`.
+  pub fn wait(&mut self, timeout: Duration) -> Result<Option<VoltageEvent>> {
+    let deadline = self.clock.now() + timeout;
+    loop {
+      if let Some(event) = self.check()? {
+        return Ok(Some(event));
+      }
+      match deadline.checked_sub(self.clock.now()) {
+        Some(remaining) if remaining > Duration::from_secs(0) => {
+          self.clock.sleep(self.poll_interval.min(remaining))?
+        }
+        _ => return Ok(None),
+      }
+    }
+  }
+
+  /// Returns how many thresholds `volts` is below, given the ones crossed
+  /// already.
+  fn classify(&self, volts: f32) -> usize {
+    self.thresholds
+        .iter()
+        .enumerate()
+        .filter(|&(i, &threshold)| {
+                  // Leaving a crossed threshold takes the hysteresis.
+                  let threshold = if i < self.level { threshold + self.hysteresis } else { threshold };
+                  volts < threshold
+                })
+        .count()
+  }
+}
+
+impl<S: BatterySource + Send + 'static> LowVoltageGuard<S> {
+  /// Checks the voltage every poll interval on a thread of its own, until
+  /// the returned guard is dropped.
+  pub fn start(mut self) -> RunningGuard {
+    let errors = Arc::new(Mutex::new(VecDeque::new()));
+    let (stop, stopped) = mpsc::channel::<()>();
+    let thread = {
+      let errors = errors.clone();
+      thread::spawn(move || {
+        loop {
+          if let Err(e) = self.check() {
+            let mut errors = lock(&errors);
+            if errors.len() == MAX_ERRORS {
+              let _ = errors.pop_front();
+            }
+            errors.push_back(e);
+          }
+          if stopped.recv_timeout(self.poll_interval) != Err(RecvTimeoutError::Timeout) {
+            break;
+          }
+        }
+      })
+    };
+    RunningGuard {
+      errors,
+      stop: Some(stop),
+      thread: Some(thread),
+    }
+  }
+}
+
+/// A started low voltage guard, stopped when dropped.
+#[derive(Debug)]
+pub struct RunningGuard {
+  errors: Arc<Mutex<VecDeque<Error>>>,
+  stop: Option<Sender<()>>,
+  thread: Option<JoinHandle<()>>,
+}
+
+impl RunningGuard {
+  /// Returns and clears the errors of checking the voltage, at most the
+  /// latest 100.
+  pub fn take_errors(&self) -> Vec<Error> {
+    mem::take(&mut *lock(&self.errors)).into_iter().collect()
+  }
+}
+
+impl Drop for RunningGuard {
+  fn drop(&mut self) {
+    // Dropping the sender wakes the thread.
+    self.stop = None;
+    if let Some(thread) = self.thread.take() {
+      let _ = thread.join();
+    }
+  }
+}
+
+/// Shuts the system down, the battery being at `volts`.
+fn shutdown(volts: f32) -> Result<()> {
+  let status = Command::new("shutdown").args(["-h", "now"])
+                                       .status()
+                                       .chain_err(|| format!("Failed to shut down at {}V", volts))?;
+  if !status.success() {
+    bail!(format!("Shutting down at {}V failed with {}", volts, status));
+  }
+  Ok(())
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+  mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
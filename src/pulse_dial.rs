@@ -0,0 +1,364 @@
+//! The pulse dial module.
+//!
+//! A rotary telephone dial sends a digit as a train of pulses, one to ten of
+//! them for "1" to "0", about ten per second; simple selectors and
+//! counters signal a number the same way.
+//! A `PulseDial` debounces the contact, see the `debounce` module, and
+//! reports each train once the line stayed idle for the digit gap:
+//!
+//! ```no_run
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::pulse_dial::PulseDial;
+//!
+//! // A dial whose pulse contact pulls the pin low while it's open.
+//! let dial = PulseDial::new(GPIO_P8_11, PinState::Low).unwrap();
+//! dial.get_ref().get_ref().set_pull(Pull::Up).unwrap();
+//!
+//! let (_subscription, trains) = dial.trains().unwrap();
+//! let number: String = trains.iter()
+//!                            .filter_map(|train| train.digit())
+//!                            .take(4)
+//!                            .map(|digit| char::from(b'0' + digit))
+//!                            .collect();
+//! println!("Dialed {}", number);
+//! ```
+//!
+//! A train is only reported if each of its pulses lasted within the pulse
+//! window, 20ms to 150ms by default, so a contact held closed or a glitch
+//! doesn't dial; see `PulseDecoder::rejected()`.
+//!
+//! The decoding is done by a `PulseDecoder`, which takes the levels from
+//! anywhere:
+//!
+//! ```
+//! use libbeaglebone::pulse_dial::PulseDecoder;
+//! use std::time::Duration;
+//!
+//! let ms = Duration::from_millis;
+//! let mut decoder = PulseDecoder::new();
+//! // Three pulses of 60ms, 100ms apart.
+//! for &start in &[0, 100, 200] {
+//!   assert_eq!(decoder.feed(true, ms(start)), None);
+//!   assert_eq!(decoder.feed(false, ms(start + 60)), None);
+//! }
+//! let train = decoder.tick(ms(600)).unwrap();
+//! assert_eq!((train.pulses, train.digit()), (3, Some(3)));
+//! ```
+
+use clock::{self, Clock};
+use debounce::{DebouncedInput, DebouncedSubscription};
+use errors::*;
+use gpio::{Edge, GPIO, PinState};
+use hal::DigitalPin;
+use pins::Pin;
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// A train of pulses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PulseTrain {
+  /// The number of pulses.
+  pub pulses: u32,
+  /// The time from the start of the first pulse to the end of the last.
+  pub duration: Duration,
+}
+
+impl PulseTrain {
+  /// Returns the digit a rotary dial sends with the train: the number of
+  /// pulses, with ten being 0, or `None` for more than ten.
+  pub fn digit(&self) -> Option<u8> {
+    match self.pulses {
+      10 => Some(0),
+      pulses if pulses < 10 => Some(pulses as u8),
+      _ => None,
+    }
+  }
+}
+
+/// Turns the levels of a pulse contact into trains of pulses.
+#[derive(Debug, Clone)]
+pub struct PulseDecoder {
+  min_pulse: Duration,
+  max_pulse: Duration,
+  digit_gap: Duration,
+  // When the train and the pulse going on started, if they did.
+  train_start: Option<Duration>,
+  pulse_start: Option<Duration>,
+  last_release: Option<Duration>,
+  pulses: u32,
+  invalid: bool,
+  rejected: u64,
+}
+
+impl Default for PulseDecoder {
+  fn default() -> PulseDecoder {
+    PulseDecoder::new()
+  }
+}
+
+impl PulseDecoder {
+  /// Creates a decoder with a pulse window of 20ms to 150ms and a digit gap
+  /// of 300ms.
+  pub fn new() -> PulseDecoder {
+    PulseDecoder {
+      min_pulse: Duration::from_millis(20),
+      max_pulse: Duration::from_millis(150),
+      digit_gap: Duration::from_millis(300),
+      train_start: None,
+      pulse_start: None,
+      last_release: None,
+      pulses: 0,
+      invalid: false,
+      rejected: 0,
+    }
+  }
+
+  /// Sets how long a pulse may last, from `min` to `max`.
+  pub fn set_pulse_window(&mut self, min: Duration, max: Duration) {
+    self.min_pulse = min;
+    self.max_pulse = max;
+  }
+
+  /// Sets how long the contact has to be idle after a pulse to end the
+  /// train.
+  pub fn set_digit_gap(&mut self, digit_gap: Duration) {
+    self.digit_gap = digit_gap;
+  }
+
+  /// Returns how many trains were dropped because one of their pulses was
+  /// outside of the pulse window.
+  pub fn rejected(&self) -> u64 {
+    self.rejected
+  }
+
+  /// Takes a change of the contact at `at`, `active` while it pulses, and
+  /// returns the train it ended, if any.
+  pub fn feed(&mut self, active: bool, at: Duration) -> Option<PulseTrain> {
+    let ended = self.tick(at);
+    if active {
+      self.train_start = self.train_start.or(Some(at));
+      self.pulse_start = Some(at);
+    } else if let Some(pulse_start) = self.pulse_start.take() {
+      let pulse = at - pulse_start;
+      if pulse < self.min_pulse || pulse > self.max_pulse {
+        self.invalid = true;
+      }
+      self.pulses += 1;
+      self.last_release = Some(at);
+    }
+    ended
+  }
+
+  /// Returns the train going on if the digit gap passed by `at`.
+  pub fn tick(&mut self, at: Duration) -> Option<PulseTrain> {
+    match self.next_deadline() {
+      Some(deadline) if at >= deadline => {}
+      _ => return None,
+    }
+    let train = PulseTrain {
+      pulses: self.pulses,
+      duration: self.last_release.unwrap_or(at) - self.train_start.unwrap_or(at),
+    };
+    let invalid = self.invalid;
+    self.train_start = None;
+    self.last_release = None;
+    self.pulses = 0;
+    self.invalid = false;
+    if invalid {
+      self.rejected += 1;
+      return None;
+    }
+    Some(train)
+  }
+
+  /// Returns when the train going on ends, unless another pulse starts.
+  pub fn next_deadline(&self) -> Option<Duration> {
+    match (self.pulse_start, self.last_release) {
+      (None, Some(last_release)) => Some(last_release + self.digit_gap),
+      _ => None,
+    }
+  }
+}
+
+/// A pulse dial on a debounced digital input, by default a GPIO.
+#[derive(Debug)]
+pub struct PulseDial<P: DigitalPin = GPIO> {
+  input: DebouncedInput<P>,
+  active: PinState,
+  decoder: PulseDecoder,
+  level: PinState,
+  clock: Arc<dyn Clock>,
+  poll_interval: Duration,
+}
+
+impl PulseDial<GPIO> {
+  /// Decodes a dial on the GPIO `pin`, which is exported and made an input,
+  /// pulsing at the level `active` and debounced for 5ms.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the pin can't be configured or read.
+  pub fn new(pin: Pin, active: PinState) -> Result<PulseDial> {
+    PulseDial::from_input(DebouncedInput::new(pin, Duration::from_millis(5))?, active)
+  }
+
+  /// Calls `callback` on a thread of its own for every train, until the
+  /// returned subscription is dropped.
+  ///
+  /// The pulses are taken from the debounced edges, see
+  /// `DebouncedInput::on_edge()`, so `poll()` and `wait()` mustn't be used
+  /// at the same time.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the pin doesn't support edge interrupts, or if the reactor
+  /// can't be started.
+  pub fn on_train<F>(&self, mut callback: F) -> Result<DialSubscription>
+    where F: FnMut(PulseTrain) + Send + 'static
+  {
+    let (subscription, edges) = self.input.edge_events(Edge::Both)?;
+    let mut decoder = self.decoder.clone();
+    let clock = self.clock.clone();
+    let active = self.active;
+    let thread = thread::spawn(move || {
+      loop {
+        let received = match decoder.next_deadline() {
+          Some(deadline) => {
+            edges.recv_timeout(deadline.checked_sub(clock.now()).unwrap_or_default())
+          }
+          None => edges.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        let train = match received {
+          Ok(edge) => decoder.feed(edge.state == active, clock.now()),
+          Err(RecvTimeoutError::Timeout) => decoder.tick(clock.now()),
+          // The subscription was dropped.
+          Err(RecvTimeoutError::Disconnected) => break,
+        };
+        if let Some(train) = train {
+          callback(train);
+        }
+      }
+    });
+    Ok(DialSubscription {
+      subscription: Some(subscription),
+      thread: Some(thread),
+    })
+  }
+
+  /// Sends the trains on a channel, until the returned subscription is
+  /// dropped.
+  ///
+  /// # Errors
+  ///
+  /// See `on_train()`.
+  pub fn trains(&self) -> Result<(DialSubscription, Receiver<PulseTrain>)> {
+    let (sender, receiver) = mpsc::channel();
+    let subscription = self.on_train(move |train| {
+      let _ = sender.send(train);
+    })?;
+    Ok((subscription, receiver))
+  }
+}
+
+impl<P: DigitalPin> PulseDial<P> {
+  /// Decodes a dial on the debounced `input`, pulsing at the level
+  /// `active`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the input can't be read.
+  pub fn from_input(mut input: DebouncedInput<P>, active: PinState) -> Result<PulseDial<P>> {
+    let clock = clock::system();
+    input.set_clock(clock.clone());
+    let level = input.read()?;
+    Ok(PulseDial {
+      input,
+      active,
+      decoder: PulseDecoder::new(),
+      level,
+      clock,
+      poll_interval: Duration::from_millis(1),
+    })
+  }
+
+  /// Returns the decoder, e.g. to set its timing windows.
+  pub fn decoder_mut(&mut self) -> &mut PulseDecoder {
+    &mut self.decoder
+  }
+
+  /// Sets how often `wait()` samples the input, 1ms by default.
+  pub fn set_poll_interval(&mut self, interval: Duration) {
+    self.poll_interval = interval;
+  }
+
+  /// Makes the dial and its debounce take their time from `clock`, e.g. a
+  /// `TestClock`.
+  pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+    self.input.set_clock(clock.clone());
+    self.clock = clock;
+  }
+
+  /// Samples the input, and returns the train that ended, if any.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the input can't be read.
+  pub fn poll(&mut self) -> Result<Option<PulseTrain>> {
+    let level = self.input.read()?;
+    let now = self.clock.now();
+    if level == self.level {
+      return Ok(self.decoder.tick(now));
+    }
+    self.level = level;
+    Ok(self.decoder.feed(level == self.active, now))
+  }
+
+  /// Samples the input until a train ends, or until `timeout` has passed.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the input can't be read.
+  pub fn wait(&mut self, timeout: Duration) -> Result<Option<PulseTrain>> {
+    let deadline = self.clock.now() + timeout;
+    loop {
+      if let Some(train) = self.poll()? {
+        return Ok(Some(train));
+      }
+      match deadline.checked_sub(self.clock.now()) {
+        Some(remaining) if remaining > Duration::from_secs(0) => {
+          self.clock.sleep(self.poll_interval.min(remaining))?
+        }
+        _ => return Ok(None),
+      }
+    }
+  }
+
+  /// Returns the debounced input.
+  pub fn get_ref(&self) -> &DebouncedInput<P> {
+    &self.input
+  }
+
+  /// Unwraps the debounced input.
+  pub fn into_inner(self) -> DebouncedInput<P> {
+    self.input
+  }
+}
+
+/// Calls the callback of a dial's trains until it's dropped.
+#[derive(Debug)]
+pub struct DialSubscription {
+  subscription: Option<DebouncedSubscription>,
+  thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for DialSubscription {
+  fn drop(&mut self) {
+    // Dropping the subscription closes the channel the thread waits on.
+    self.subscription = None;
+    if let Some(thread) = self.thread.take() {
+      let _ = thread.join();
+    }
+  }
+}
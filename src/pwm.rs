@@ -15,15 +15,31 @@
 //! A convenient list of pin identifiers can be found through an online search
 //! of "BeagleBone pinout".
 
+use board;
+use clock::SystemClock;
 use enums::DeviceState;
 use errors::*;
+use journal::{self, Entry};
+use pins::Pin;
+use ramp::{self, Easing, Ramp};
+use stats::{OpCounters, OpStats};
 use std::fs::File;
 use std::io::Write;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::thread;
+use std::time::{Duration, Instant};
+use units::{DutyCycle, Hertz};
 use util::*;
+use vcd;
+
+/// How long the output stays inactive after a one-shot pulse before the PWM
+/// would start its next period, which is the time available to disable it.
+const PULSE_ONCE_GUARD_NS: u64 = 100_000_000;
 
 /// The state in which the PWM is in, either on or off.
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PWMState {
   /// PWM on
   Enabled,
@@ -31,14 +47,46 @@ pub enum PWMState {
   Disabled,
 }
 
+/// The polarity of the PWM output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PWMPolarity {
+  /// The output is high for the duty cycle, then low for the rest of the
+  /// period.
+  Normal,
+  /// The output is low for the duty cycle, then high for the rest of the
+  /// period, e.g. to drive an active-low load.
+  Inversed,
+}
+
+/// When a `PWM` re-reads its cached period, duty cycle and state from sysfs.
+///
+/// The cache goes stale if another process, e.g. an init script or a second
+/// daemon, changes the PWM, and `write()` would then compute the duty cycle
+/// from the wrong period.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Revalidation {
+  /// Never, the process is the only one using the PWM.
+  Never,
+  /// Before every write.
+  BeforeWrite,
+  /// Before a write if the cache is older than the interval.
+  Interval(Duration),
+}
+
 /// Represents a PWM device.
 #[derive(Debug)]
 pub struct PWM {
   pwm_chip_num: u8,
   pwm_num: u8,
+  pwm_path: PathBuf,
+  counters: OpCounters,
   period: u32,
   duty_cycle: u32,
   state: PWMState,
+  revalidation: Revalidation,
+  validated_at: Option<Instant>,
+  unexport_on_drop: bool,
+  startup_ramp: Duration,
 }
 
 impl PWM {
@@ -66,12 +114,231 @@ impl PWM {
     PWM {
       pwm_chip_num: pwm_chip_num,
       pwm_num: pwm_num,
+      pwm_path: PathBuf::from(board::current().pwm_path(pwm_chip_num, pwm_num)),
+      counters: OpCounters::new(),
       period: 0,
       duty_cycle: 0,
       state: PWMState::Disabled,
+      revalidation: Revalidation::Never,
+      validated_at: None,
+      unexport_on_drop: false,
+      startup_ramp: Duration::from_secs(0),
+    }
+  }
+
+  /// Creates a PWM object for the PWM on the header pin `pin`, e.g.
+  /// `GPIO_P9_14`, looking its PWM chip and number up on the current board.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  ///
+  /// // EHRPWM1A, whichever PWM chip the kernel made of it.
+  /// let mut pwm = PWM::from_pin(GPIO_P9_14).unwrap();
+  /// pwm.set_export(DeviceState::Exported).unwrap();
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if the pin has no PWM or its PWM chip can't be found.
+  pub fn from_pin(pin: Pin) -> Result<PWM> {
+    let board = board::current();
+    match board.gpio_name(pin as u8) {
+      Some(name) => PWM::from_name(name),
+      None => bail!(format!("The {} has no pin with GPIO number {}", board.name, pin as u8)),
     }
   }
 
+  /// Creates a PWM object for the PWM on the pin called `name`, e.g.
+  /// "P9.21" or "P9_21", looking its PWM chip and number up on the current
+  /// board.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  ///
+  /// let mut pwm = PWM::from_name("P9.21").unwrap();
+  /// pwm.set_export(DeviceState::Exported).unwrap();
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if the pin has no PWM or its PWM chip can't be found.
+  pub fn from_name(name: &str) -> Result<PWM> {
+    let (pwm_chip_num, pwm_num) = board::current().pwm_num(name)?;
+    Ok(PWM::new(pwm_chip_num, pwm_num))
+  }
+
+  /// Creates a PWM object for an exported PWM, taking its period, duty cycle
+  /// and state from the hardware, e.g. as configured by a boot script.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  ///
+  /// // Continue with whatever the boot script configured.
+  /// let mut pwm = PWM::from_sysfs(0, 0).unwrap();
+  /// pwm.write(25.0).unwrap();
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if the PWM isn't exported.
+  pub fn from_sysfs(pwm_chip_num: u8, pwm_num: u8) -> Result<PWM> {
+    let mut pwm = PWM::new(pwm_chip_num, pwm_num);
+    pwm.refresh()?;
+    Ok(pwm)
+  }
+
+  /// Returns the number of the PWM chip.
+  pub fn pwm_chip_num(&self) -> u8 {
+    self.pwm_chip_num
+  }
+
+  /// Returns the number of the PWM on its chip.
+  pub fn pwm_num(&self) -> u8 {
+    self.pwm_num
+  }
+
+  /// Returns the PWM's operation statistics, see the `stats` module.
+  pub fn stats(&self) -> OpStats {
+    self.counters.snapshot()
+  }
+
+  /// Resets the PWM's operation statistics.
+  pub fn reset_stats(&self) {
+    self.counters.reset()
+  }
+
+  /// Sets when the cached period, duty cycle and state are re-read from
+  /// sysfs, `Revalidation::Never` by default.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  /// use libbeaglebone::pwm::Revalidation;
+  /// use std::time::Duration;
+  ///
+  /// let mut pwm = PWM::new(0, 0);
+  /// pwm.set_export(DeviceState::Exported).unwrap();
+  ///
+  /// // The period is also set by the init script, so check it now and then.
+  /// pwm.set_revalidation(Revalidation::Interval(Duration::from_secs(1)));
+  /// pwm.write(50.0).unwrap();
+  /// ```
+  pub fn set_revalidation(&mut self, revalidation: Revalidation) {
+    self.revalidation = revalidation;
+  }
+
+  /// Re-reads the period, duty cycle and state from sysfs.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the PWM isn't exported.
+  pub fn refresh(&mut self) -> Result<()> {
+    let period = self.get_period()?;
+    let duty_cycle = self.get_duty_cycle()?;
+    self.state = self.get_state()?;
+    self.period = period;
+    self.duty_cycle = duty_cycle;
+    self.validated_at = Some(Instant::now());
+    Ok(())
+  }
+
+  /// Reads the period in nanoseconds from sysfs.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the PWM isn't exported.
+  pub fn get_period(&self) -> Result<u32> {
+    self.read_attribute("period")
+  }
+
+  /// Reads the duty cycle in nanoseconds from sysfs.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the PWM isn't exported.
+  pub fn get_duty_cycle(&self) -> Result<u32> {
+    self.read_attribute("duty_cycle")
+  }
+
+  /// Reads the state from sysfs.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the PWM isn't exported.
+  pub fn get_state(&self) -> Result<PWMState> {
+    Ok(if self.read_attribute("enable")? == 0 {
+         PWMState::Disabled
+       } else {
+         PWMState::Enabled
+       })
+  }
+
+  /// Refreshes the cache if the revalidation policy asks for it.
+  fn revalidate(&mut self) -> Result<()> {
+    let stale = match self.revalidation {
+      Revalidation::Never => false,
+      Revalidation::BeforeWrite => true,
+      Revalidation::Interval(interval) => {
+        self.validated_at.is_none_or(|at| at.elapsed() >= interval)
+      }
+    };
+    if stale {
+      self.refresh()?;
+    }
+    Ok(())
+  }
+
+  /// Returns the period, reading it from sysfs if it isn't known, e.g.
+  /// because the boot script set it.
+  ///
+  /// Fails if the period isn't set, naming what can't be done with the
+  /// PWM then, e.g. "enabled".
+  fn configured_period(&mut self, action: &str) -> Result<u32> {
+    if self.period == 0 {
+      self.period = self.get_period()?;
+    }
+    if self.period == 0 {
+      bail!(format!(
+        "PWM #{}-{} can't be {} before its period is set with set_period()",
+        &self.pwm_chip_num,
+        &self.pwm_num,
+        action
+      ));
+    }
+    Ok(self.period)
+  }
+
+  fn read_attribute(&self, attribute: &str) -> Result<u32> {
+    let path = format!("{}/{}", self.pwm_path.display(), attribute);
+    self.counters
+        .read(|| path.as_str().read_file())
+        .chain_err(|| format!("Failed to read PWM #{}-{} {}", &self.pwm_chip_num, &self.pwm_num, attribute))?
+        .trim()
+        .parse()
+        .chain_err(|| format!("Failed to parse {}", path))
+  }
+
+  /// Makes dropping the PWM disable and unexport it, also when unwinding
+  /// from a panic.
+  ///
+  /// Off by default, so a program can leave a PWM running on purpose.
+  pub fn set_unexport_on_drop(&mut self, unexport_on_drop: bool) {
+    self.unexport_on_drop = unexport_on_drop;
+  }
+
+  /// Returns the sysfs directory of the PWM, e.g.
+  /// `/sys/class/pwm/pwmchip0/pwm0`.
+  pub fn sysfs_path(&self) -> PathBuf {
+    self.pwm_path.clone()
+  }
+
   /// Exports the PWM.
   ///
   /// # Examples
@@ -91,18 +358,11 @@ impl PWM {
   /// Fails to export to the PWM if it isn't configured correctly or if the
   /// kernel refuses to execute the instruction.
   pub fn set_export(&self, state: DeviceState) -> Result<()> {
-    let path = PathBuf::from(format!(
-      "/sys/class/pwm/pwmchip{}/pwm{}",
-      &self.pwm_chip_num,
-      &self.pwm_num
-    ));
+    let path = &self.pwm_path;
     // If w're trying to export and the pin isn't already exported, try to export
     // it.
     if state == DeviceState::Exported && !path.exists() {
-      File::create(format!(
-        "/sys/class/pwm/pwmchip{}/export",
-        &self.pwm_chip_num
-      ))
+      File::create(self.pwm_path.with_file_name("export"))
       .chain_err(|| "Failed to open PWM export file")?
       .write_all(self.pwm_num.to_string().as_bytes())
       .chain_err(|| {
@@ -116,10 +376,7 @@ impl PWM {
     // Try to unexport if the path exists, otherwise the device is unexported and there's nothing
     // to do.
     else if state == DeviceState::Unexported && path.exists() {
-      File::create(format!(
-        "/sys/class/pwm/pwmchip{}/unexport",
-        &self.pwm_chip_num
-      ))
+      File::create(self.pwm_path.with_file_name("unexport"))
       .chain_err(|| "Failed to open PWM unexport file")?
       .write_all(self.pwm_num.to_string().as_bytes())
       .chain_err(|| {
@@ -152,14 +409,23 @@ impl PWM {
   ///
   /// # Errors
   ///
+  /// Fails if the period is shorter than the duty cycle, which has to be
+  /// shortened first.
   /// Fails if the pin isn't configured correctly.
   pub fn set_period(&mut self, period_ns: u32) -> Result<()> {
-    let path = format!(
-      "/sys/class/pwm/pwmchip{}/pwm{}/period",
-      &self.pwm_chip_num,
-      &self.pwm_num
-    );
-    path.write_file(&format!("{}", period_ns)).chain_err(|| {
+    self.revalidate()?;
+    if period_ns < self.duty_cycle {
+      bail!(format!(
+        "PWM #{}-{} period of {}ns is shorter than its duty cycle of {}ns, which has to be \
+         shortened first",
+        &self.pwm_chip_num,
+        &self.pwm_num,
+        period_ns,
+        self.duty_cycle
+      ));
+    }
+    let path = format!("{}/period", self.pwm_path.display());
+    self.counters.write(|| path.write_file(&format!("{}", period_ns))).chain_err(|| {
       format!(
         "Failed to set PWM #{}-{} period to {}",
         &self.pwm_chip_num,
@@ -167,6 +433,11 @@ impl PWM {
         period_ns
       )
     })?;
+    journal::record(Entry::PwmPeriod {
+                      chip: self.pwm_chip_num,
+                      num: self.pwm_num,
+                      period_ns,
+                    });
     self.period = period_ns;
     Ok(())
   }
@@ -193,17 +464,72 @@ impl PWM {
   ///
   /// # Errors
   ///
+  /// Fails to enable the PWM before its period is set.
   /// Fails to if the pin isn't configured correctly.
   pub fn set_state(&mut self, state: PWMState) -> Result<()> {
-    let path = format!(
-      "/sys/class/pwm/pwmchip{}/pwm{}/enable",
-      &self.pwm_chip_num,
-      &self.pwm_num
-    );
-    path.write_file(match state {
+    self.revalidate()?;
+    if state == PWMState::Enabled {
+      let _ = self.configured_period("enabled")?;
+    }
+    if state == PWMState::Enabled && self.state == PWMState::Disabled &&
+       self.startup_ramp > Duration::from_secs(0) {
+      self.enable_with_ramp()
+    } else {
+      self.write_state(state)
+    }
+  }
+
+  /// Makes enabling the PWM ramp the duty cycle up from 0 to the one set
+  /// over `duration`, instead of switching it on at once, e.g. to limit the
+  /// inrush current of a motor, lamp or heater.
+  ///
+  /// Zero, the default, disables the ramp.
+  /// `set_state()` returns when the ramp is done.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  /// use std::time::Duration;
+  ///
+  /// let mut heater = PWM::new(0, 0);
+  /// heater.set_export(DeviceState::Exported).unwrap();
+  /// heater.set_period(10_000_000).unwrap();
+  /// heater.write(60.0).unwrap();
+  ///
+  /// // Goes from 0% to 60% over two seconds.
+  /// heater.set_startup_ramp(Duration::from_secs(2));
+  /// heater.set_state(PWMState::Enabled).unwrap();
+  /// ```
+  pub fn set_startup_ramp(&mut self, duration: Duration) {
+    self.startup_ramp = duration;
+  }
+
+  /// Enables the PWM at 0% and ramps up to the duty cycle set.
+  fn enable_with_ramp(&mut self) -> Result<()> {
+    let duty_cycle = self.duty_cycle;
+    let percentage = self.duty_cycle_fraction() * 100.0;
+    self.set_duty_cycle(0)?;
+    self.write_state(PWMState::Enabled)?;
+    ramp::run(self,
+              0.0,
+              percentage,
+              self.startup_ramp,
+              Easing::Linear,
+              &SystemClock,
+              &AtomicBool::new(false))?;
+    // The percentages may be off by a nanosecond.
+    self.set_duty_cycle(duty_cycle)
+  }
+
+  fn write_state(&mut self, state: PWMState) -> Result<()> {
+    let path = format!("{}/enable", self.pwm_path.display());
+    let value = match state {
       PWMState::Enabled => "1",
       PWMState::Disabled => "0",
-    })
+    };
+    self.counters
+        .write(|| path.write_file(value))
         .chain_err(|| {
       format!(
         "Failed to set PWM #{}-{} state to {:?}",
@@ -212,10 +538,84 @@ impl PWM {
         state
       )
     })?;
+    vcd::record(format_args!("pwm{}_{}_enable", self.pwm_chip_num, self.pwm_num),
+                1,
+                (state == PWMState::Enabled) as u64);
+    journal::record(Entry::PwmEnabled {
+                      chip: self.pwm_chip_num,
+                      num: self.pwm_num,
+                      enabled: state == PWMState::Enabled,
+                    });
     self.state = state;
     Ok(())
   }
 
+  /// Sets the polarity of the PWM.
+  ///
+  /// Most PWM drivers only allow changing the polarity while the PWM is
+  /// disabled.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  ///
+  /// // Create a new PWM device using PWM chip 0 and PWM 0.
+  /// let mut pwm = PWM::new(0, 0);
+  ///
+  /// // Export the PWM.
+  /// pwm.set_export(DeviceState::Exported).unwrap();
+  ///
+  /// // Drive an active-low LED, which is then lit for 25% of the period.
+  /// pwm.set_polarity(PWMPolarity::Inversed).unwrap();
+  /// pwm.set_period(500_000).unwrap();
+  /// pwm.write(25.0).unwrap();
+  /// pwm.set_state(PWMState::Enabled).unwrap();
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if the pin isn't configured correctly, or if the driver doesn't
+  /// support the polarity or changing it while the PWM is enabled.
+  pub fn set_polarity(&mut self, polarity: PWMPolarity) -> Result<()> {
+    let path = format!("{}/polarity", self.pwm_path.display());
+    let value = match polarity {
+      PWMPolarity::Normal => "normal",
+      PWMPolarity::Inversed => "inversed",
+    };
+    self.counters.write(|| path.write_file(value)).chain_err(|| {
+      format!(
+        "Failed to set PWM #{}-{} polarity to {:?}",
+        &self.pwm_chip_num,
+        &self.pwm_num,
+        polarity
+      )
+    })?;
+    journal::record(Entry::PwmPolarity {
+                      chip: self.pwm_chip_num,
+                      num: self.pwm_num,
+                      inversed: polarity == PWMPolarity::Inversed,
+                    });
+    Ok(())
+  }
+
+  /// Reads the polarity of the PWM.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the pin isn't configured correctly.
+  pub fn polarity(&self) -> Result<PWMPolarity> {
+    let path = format!("{}/polarity", self.pwm_path.display());
+    let value = self.counters
+        .read(|| path.as_str().read_file())
+        .chain_err(|| format!("Failed to read PWM #{}-{} polarity", &self.pwm_chip_num, &self.pwm_num))?;
+    match value.trim() {
+      "normal" => Ok(PWMPolarity::Normal),
+      "inversed" => Ok(PWMPolarity::Inversed),
+      other => bail!("Unknown PWM polarity {:?}", other),
+    }
+  }
+
   /// Sets the duty cycle of the PWM as a percentage of the period.
   ///
   /// # Examples
@@ -243,15 +643,17 @@ impl PWM {
   ///
   /// Fails if the percentage is less than 0 or exceeds 100, i.e. if the duty
   /// cycle isn't in the period.
+  /// Fails if the percentage isn't 0 and the period isn't set.
   /// Fails to if the pin isn't configured correctly.
   pub fn write(&mut self, percentage: f32) -> Result<()> {
-    let path = format!(
-      "/sys/class/pwm/pwmchip{}/pwm{}/duty_cycle",
-      &self.pwm_chip_num,
-      &self.pwm_num
-    );
+    check_percentage(percentage)?;
+    self.revalidate()?;
+    if percentage > 0.0 {
+      let _ = self.configured_period("given a duty cycle")?;
+    }
+    let path = format!("{}/duty_cycle", self.pwm_path.display());
     let new_duty_cycle = ((percentage / 100.0) * (self.period as f32)) as u32;
-    path.write_file(&format!("{}", new_duty_cycle)).chain_err(
+    self.counters.write(|| path.write_file(&format!("{}", new_duty_cycle))).chain_err(
       || {
         format!(
           "Failed to set PWM #{}-{} duty cycle to {}% (aka {}ns)",
@@ -263,6 +665,7 @@ impl PWM {
       },
     )?;
     self.duty_cycle = new_duty_cycle;
+    self.record_duty_cycle();
     Ok(())
   }
 
@@ -291,15 +694,25 @@ impl PWM {
   ///
   /// # Errors
   ///
-  /// Fails if the duty cycle exceeds the period.
+  /// Fails if the duty cycle exceeds the period, or if it isn't 0 and the
+  /// period isn't set.
   /// Fails if the pin isn't configured correctly.
   pub fn set_duty_cycle(&mut self, duty_cycle_ns: u32) -> Result<()> {
-    let path = format!(
-      "/sys/class/pwm/pwmchip{}/pwm{}/duty_cycle",
-      &self.pwm_chip_num,
-      &self.pwm_num
-    );
-    path.write_file(&format!("{}", duty_cycle_ns)).chain_err(
+    self.revalidate()?;
+    if duty_cycle_ns > 0 {
+      let period = self.configured_period("given a duty cycle")?;
+      if duty_cycle_ns > period {
+        bail!(format!(
+          "PWM #{}-{} duty cycle of {}ns exceeds its period of {}ns",
+          &self.pwm_chip_num,
+          &self.pwm_num,
+          duty_cycle_ns,
+          period
+        ));
+      }
+    }
+    let path = format!("{}/duty_cycle", self.pwm_path.display());
+    self.counters.write(|| path.write_file(&format!("{}", duty_cycle_ns))).chain_err(
       || {
         format!(
           "Failed to set PWM #{}-{} duty cycle to {}ns",
@@ -310,6 +723,504 @@ impl PWM {
       },
     )?;
     self.duty_cycle = duty_cycle_ns;
+    self.record_duty_cycle();
     Ok(())
   }
+
+  /// Sets the frequency of the PWM in Hz, keeping the duty cycle as a
+  /// fraction of the period.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  ///
+  /// let mut pwm = PWM::new(0, 0);
+  /// pwm.set_export(DeviceState::Exported).unwrap();
+  ///
+  /// // A 25kHz fan PWM at 40%.
+  /// pwm.set_frequency(25_000.0).unwrap();
+  /// pwm.set_duty_cycle_fraction(0.4).unwrap();
+  /// pwm.set_state(PWMState::Enabled).unwrap();
+  ///
+  /// // Still at 40%, now of a 20kHz period.
+  /// pwm.set_frequency(20_000.0).unwrap();
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if the period of the frequency isn't between 1ns and
+  /// `u32::MAX` ns, i.e. the frequency isn't between about 0.24Hz and 1GHz.
+  /// Fails if the pin isn't configured correctly.
+  pub fn set_frequency(&mut self, hz: f32) -> Result<()> {
+    let period = 1e9 / f64::from(hz);
+    if !(period >= 1.0 && period <= f64::from(u32::MAX)) {
+      bail!(format!("PWM frequency {}Hz is out of range", hz));
+    }
+    self.revalidate()?;
+    let fraction = self.duty_cycle_fraction();
+    let period_ns = period.round() as u32;
+    let duty_cycle_ns = (f64::from(fraction) * f64::from(period_ns)).round() as u32;
+    // The duty cycle may never exceed the period, not even in between.
+    if period_ns >= self.duty_cycle {
+      self.set_period(period_ns)?;
+      self.set_duty_cycle(duty_cycle_ns)
+    } else {
+      self.set_duty_cycle(duty_cycle_ns)?;
+      self.set_period(period_ns)
+    }
+  }
+
+  /// Returns the period of the PWM in nanoseconds as last set, or 0 if it's
+  /// unknown.
+  pub fn period(&self) -> u32 {
+    self.period
+  }
+
+  /// Returns the duty cycle of the PWM in nanoseconds as last set.
+  pub fn duty_cycle(&self) -> u32 {
+    self.duty_cycle
+  }
+
+  /// Returns the frequency of the PWM in Hz as last set, or 0 if the period
+  /// is unknown.
+  pub fn frequency(&self) -> f32 {
+    if self.period == 0 {
+      0.0
+    } else {
+      (1e9 / f64::from(self.period)) as f32
+    }
+  }
+
+  /// Sets the duty cycle of the PWM as a fraction of the period, from 0.0 to
+  /// 1.0.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the fraction isn't between 0.0 and 1.0, or if it isn't zero
+  /// and the period isn't set.
+  /// Fails if the pin isn't configured correctly.
+  pub fn set_duty_cycle_fraction(&mut self, fraction: f32) -> Result<()> {
+    if !(0.0..=1.0).contains(&fraction) {
+      bail!(format!("PWM duty cycle fraction {} isn't between 0 and 1", fraction));
+    }
+    self.revalidate()?;
+    if fraction == 0.0 {
+      return self.set_duty_cycle(0);
+    }
+    let period = self.configured_period("given a duty cycle")?;
+    let duty_cycle_ns = (f64::from(fraction) * f64::from(period)).round() as u32;
+    self.set_duty_cycle(duty_cycle_ns)
+  }
+
+  /// Returns the duty cycle of the PWM as a fraction of the period as last
+  /// set, or 0 if the period is unknown.
+  pub fn duty_cycle_fraction(&self) -> f32 {
+    if self.period == 0 {
+      0.0
+    } else {
+      (f64::from(self.duty_cycle) / f64::from(self.period)) as f32
+    }
+  }
+
+  /// Sets the period of the PWM, keeping the duty cycle in nanoseconds.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  /// use std::time::Duration;
+  ///
+  /// let mut pwm = PWM::new(0, 0);
+  /// pwm.set_export(DeviceState::Exported).unwrap();
+  ///
+  /// // A servo's 20ms period.
+  /// pwm.set_period_duration(Duration::from_millis(20)).unwrap();
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if the period is zero, longer than `u32::MAX` ns or shorter than
+  /// the duty cycle.
+  /// Fails if the pin isn't configured correctly.
+  pub fn set_period_duration(&mut self, period: Duration) -> Result<()> {
+    let period_ns = self.nanoseconds(period, "period")?;
+    if period_ns == 0 {
+      bail!(format!("PWM #{}-{} period can't be zero", &self.pwm_chip_num, &self.pwm_num));
+    }
+    self.set_period(period_ns)
+  }
+
+  /// Returns the period of the PWM as last set, or zero if it's unknown.
+  pub fn period_duration(&self) -> Duration {
+    Duration::from_nanos(u64::from(self.period))
+  }
+
+  /// Sets the duty cycle of the PWM to `width`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the duty cycle exceeds the period, or if it isn't zero and the
+  /// period isn't set.
+  /// Fails if the pin isn't configured correctly.
+  pub fn set_duty_cycle_duration(&mut self, width: Duration) -> Result<()> {
+    let duty_cycle_ns = self.nanoseconds(width, "duty cycle")?;
+    self.set_duty_cycle(duty_cycle_ns)
+  }
+
+  /// Returns the duty cycle of the PWM as last set.
+  pub fn duty_cycle_duration(&self) -> Duration {
+    Duration::from_nanos(u64::from(self.duty_cycle))
+  }
+
+  /// Sets the frequency of the PWM, keeping the duty cycle as a fraction of
+  /// the period, like `set_frequency()`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the frequency isn't between about 0.24Hz and 1GHz.
+  /// Fails if the pin isn't configured correctly.
+  pub fn set_hertz(&mut self, frequency: Hertz) -> Result<()> {
+    self.set_frequency(frequency.value())
+  }
+
+  /// Sets the duty cycle of the PWM relative to the period.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use libbeaglebone::prelude::*;
+  /// use libbeaglebone::{board, stub};
+  ///
+  /// // The host stub's PWMs start out without a period.
+  /// board::set_current(stub::board().unwrap());
+  /// let mut pwm = PWM::new(0, 0);
+  /// assert!(pwm.set_duty(DutyCycle::from_percent(25.0).unwrap()).is_err());
+  ///
+  /// pwm.set_period(1_000_000).unwrap();
+  /// pwm.set_duty(DutyCycle::from_percent(25.0).unwrap()).unwrap();
+  /// assert_eq!(pwm.duty_cycle(), 250_000);
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if the duty cycle isn't zero and the period isn't set.
+  /// Fails if the pin isn't configured correctly.
+  pub fn set_duty(&mut self, duty: DutyCycle) -> Result<()> {
+    self.set_duty_cycle_fraction(duty.fraction())
+  }
+
+  /// Returns the duty cycle of the PWM relative to the period as last set,
+  /// or 0% if the period is unknown.
+  pub fn duty(&self) -> DutyCycle {
+    DutyCycle::clamped(self.duty_cycle_fraction())
+  }
+
+  /// Converts `duration` into nanoseconds, failing if it doesn't fit into
+  /// the sysfs attributes.
+  fn nanoseconds(&self, duration: Duration, what: &str) -> Result<u32> {
+    let ns = duration.as_nanos();
+    if ns > u128::from(u32::MAX) {
+      bail!(format!(
+        "PWM #{}-{} {} of {:?} is longer than {}ns",
+        &self.pwm_chip_num,
+        &self.pwm_num,
+        what,
+        duration,
+        u32::MAX
+      ));
+    }
+    Ok(ns as u32)
+  }
+
+  /// Changes the duty cycle from the current one to `percentage` linearly
+  /// over `duration`, returning when it's reached.
+  ///
+  /// See the `ramp` module for fading with other easings and in the
+  /// background.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  /// use std::time::Duration;
+  ///
+  /// let mut motor = PWM::new(0, 0);
+  /// motor.set_export(DeviceState::Exported).unwrap();
+  /// motor.set_period(50_000).unwrap();
+  /// motor.set_state(PWMState::Enabled).unwrap();
+  ///
+  /// // Soft start to 80% over half a second.
+  /// motor.ramp_to(80.0, Duration::from_millis(500)).unwrap();
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if the percentage is less than 0 or exceeds 100.
+  /// Fails if the pin isn't configured correctly.
+  pub fn ramp_to(&mut self, percentage: f32, duration: Duration) -> Result<()> {
+    self.ramp_with(percentage, duration, Easing::Linear)
+  }
+
+  /// Changes the duty cycle from the current one to `percentage` over
+  /// `duration` with `easing`, returning when it's reached.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the percentage is less than 0 or exceeds 100.
+  /// Fails if the pin isn't configured correctly.
+  pub fn ramp_with(&mut self, percentage: f32, duration: Duration, easing: Easing) -> Result<()> {
+    check_percentage(percentage)?;
+    let start = self.duty_cycle_fraction() * 100.0;
+    ramp::run(self, start, percentage, duration, easing, &SystemClock, &AtomicBool::new(false))
+  }
+
+  /// Changes the duty cycle like `ramp_with()` on a background thread, which
+  /// owns the PWM until the ramp ends.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the percentage is less than 0 or exceeds 100.
+  pub fn ramp_in_background(mut self, percentage: f32, duration: Duration, easing: Easing) -> Result<Ramp> {
+    check_percentage(percentage)?;
+    let start = self.duty_cycle_fraction() * 100.0;
+    let cancel = Arc::new(AtomicBool::new(false));
+    let thread = {
+      let cancel = cancel.clone();
+      thread::spawn(move || {
+        let result = ramp::run(&mut self, start, percentage, duration, easing, &SystemClock, &cancel);
+        (self, result)
+      })
+    };
+    Ok(Ramp::new(cancel, thread))
+  }
+
+  /// Records the duty cycle if a VCD recording is running or a journal is
+  /// open.
+  fn record_duty_cycle(&self) {
+    vcd::record(format_args!("pwm{}_{}_duty_ns", self.pwm_chip_num, self.pwm_num),
+                32,
+                u64::from(self.duty_cycle));
+    journal::record(Entry::PwmDutyCycle {
+                      chip: self.pwm_chip_num,
+                      num: self.pwm_num,
+                      duty_cycle_ns: self.duty_cycle,
+                    });
+  }
+
+  /// Emits a single pulse `width` long and leaves the PWM disabled.
+  ///
+  /// The PWM hardware has no one-shot mode, so the period is stretched to
+  /// `width` plus 100ms and the PWM is disabled during the inactive part of
+  /// the first period.
+  /// The period and duty cycle are restored afterwards.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  /// use std::time::Duration;
+  ///
+  /// // Create a new PWM device using PWM chip 0 and PWM 0.
+  /// let mut pwm = PWM::new(0, 0);
+  ///
+  /// // Export the PWM.
+  /// pwm.set_export(DeviceState::Exported).unwrap();
+  ///
+  /// // Trigger a camera with a 2ms pulse.
+  /// pwm.pulse_once(Duration::from_millis(2)).unwrap();
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if `width` is zero or longer than 4s, or if the pin isn't
+  /// configured correctly.
+  /// If the process is stalled for more than 100ms right after the pulse, a
+  /// second pulse may start before the PWM is disabled.
+  pub fn pulse_once(&mut self, width: Duration) -> Result<()> {
+    let width_ns = width.as_nanos();
+    let period_ns = width_ns + u128::from(PULSE_ONCE_GUARD_NS);
+    if width_ns == 0 || period_ns > u128::from(u32::MAX) {
+      bail!(format!(
+        "PWM #{}-{} pulse width of {:?} is outside of 1ns to 4s",
+        &self.pwm_chip_num,
+        &self.pwm_num,
+        width
+      ));
+    }
+
+    self.revalidate()?;
+    let (period, duty_cycle) = (self.period, self.duty_cycle);
+    self.set_state(PWMState::Disabled)?;
+    // The duty cycle has to fit into the period at every step.
+    self.set_duty_cycle(0)?;
+    self.set_period(period_ns as u32)?;
+    self.set_duty_cycle(width_ns as u32)?;
+
+    // Without the startup ramp, which would stretch the pulse.
+    self.write_state(PWMState::Enabled)?;
+    // Disable halfway into the inactive part, clear of both edges.
+    thread::sleep(width + Duration::from_nanos(PULSE_ONCE_GUARD_NS / 2));
+    let disabled = self.set_state(PWMState::Disabled);
+
+    self.set_duty_cycle(0)?;
+    if period != 0 {
+      self.set_period(period)?;
+      self.set_duty_cycle(duty_cycle)?;
+    }
+    disabled
+  }
+}
+
+/// Checks that a duty cycle percentage is between 0 and 100.
+fn check_percentage(percentage: f32) -> Result<()> {
+  if !(0.0..=100.0).contains(&percentage) {
+    bail!(format!("PWM duty cycle {}% isn't between 0% and 100%", percentage));
+  }
+  Ok(())
+}
+
+impl Drop for PWM {
+  fn drop(&mut self) {
+    if self.unexport_on_drop && self.pwm_path.exists() {
+      let _ = self.set_state(PWMState::Disabled);
+      let _ = self.set_export(DeviceState::Unexported);
+    }
+  }
+}
+
+/// Exports and configures a PWM in one go.
+///
+/// # Examples
+///
+/// ```no_run
+/// use libbeaglebone::prelude::*;
+/// use libbeaglebone::pwm::PWMBuilder;
+///
+/// // A 2kHz PWM at 25%, cleaned up when `pwm` goes out of scope.
+/// let pwm = PWMBuilder::new(0, 0)
+///   .with_period(500_000)
+///   .with_duty_cycle(125_000)
+///   .with_polarity(PWMPolarity::Normal)
+///   .with_enabled(true)
+///   .with_unexport_on_drop(true)
+///   .build()
+///   .unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct PWMBuilder {
+  pwm_chip_num: u8,
+  pwm_num: u8,
+  period: Option<u32>,
+  duty_cycle: Option<u32>,
+  polarity: Option<PWMPolarity>,
+  enabled: Option<bool>,
+  unexport_on_drop: bool,
+}
+
+impl PWMBuilder {
+  /// Creates a builder for PWM `pwm_num` of chip `pwm_chip_num`, which leaves
+  /// everything as it is by default.
+  pub fn new(pwm_chip_num: u8, pwm_num: u8) -> PWMBuilder {
+    PWMBuilder {
+      pwm_chip_num,
+      pwm_num,
+      period: None,
+      duty_cycle: None,
+      polarity: None,
+      enabled: None,
+      unexport_on_drop: false,
+    }
+  }
+
+  /// Sets the period in nanoseconds.
+  pub fn with_period(mut self, period_ns: u32) -> PWMBuilder {
+    self.period = Some(period_ns);
+    self
+  }
+
+  /// Sets the duty cycle in nanoseconds.
+  pub fn with_duty_cycle(mut self, duty_cycle_ns: u32) -> PWMBuilder {
+    self.duty_cycle = Some(duty_cycle_ns);
+    self
+  }
+
+  /// Sets the polarity.
+  pub fn with_polarity(mut self, polarity: PWMPolarity) -> PWMBuilder {
+    self.polarity = Some(polarity);
+    self
+  }
+
+  /// Enables or disables the PWM once it's configured.
+  pub fn with_enabled(mut self, enabled: bool) -> PWMBuilder {
+    self.enabled = Some(enabled);
+    self
+  }
+
+  /// Makes dropping the PWM disable and unexport it, see
+  /// `PWM::set_unexport_on_drop()`.
+  pub fn with_unexport_on_drop(mut self, unexport_on_drop: bool) -> PWMBuilder {
+    self.unexport_on_drop = unexport_on_drop;
+    self
+  }
+
+  /// Exports and configures the PWM.
+  ///
+  /// The period and duty cycle are written in the order the kernel accepts,
+  /// and the PWM is disabled while the polarity changes, and then re-enabled
+  /// unless `with_enabled(false)` was given.
+  /// Either way, the PWM is only enabled once it's configured, and with
+  /// `with_enabled(false)` disabled before, so its output never runs at a
+  /// duty cycle it wasn't built with.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the PWM can't be exported or configured, e.g. because the duty
+  /// cycle exceeds the period.
+  /// With `with_unexport_on_drop(true)`, the PWM is unexported again then.
+  pub fn build(self) -> Result<PWM> {
+    let mut pwm = PWM::new(self.pwm_chip_num, self.pwm_num);
+    pwm.set_unexport_on_drop(self.unexport_on_drop);
+    pwm.set_export(DeviceState::Exported)?;
+    pwm.refresh()?;
+
+    let mut enabled = self.enabled;
+    if let Some(polarity) = self.polarity {
+      if pwm.polarity()? != polarity {
+        if pwm.state == PWMState::Enabled {
+          pwm.set_state(PWMState::Disabled)?;
+          enabled = enabled.or(Some(true));
+        }
+        pwm.set_polarity(polarity)?;
+      }
+    }
+
+    // A PWM that ends up disabled mustn't run at the new duty cycle first.
+    if enabled == Some(false) && pwm.state == PWMState::Enabled {
+      pwm.set_state(PWMState::Disabled)?;
+    }
+
+    let period = self.period.unwrap_or(pwm.period);
+    let duty_cycle = self.duty_cycle.unwrap_or(pwm.duty_cycle);
+    if duty_cycle > period {
+      bail!(format!("PWM duty cycle {}ns exceeds the period of {}ns", duty_cycle, period));
+    }
+    // The duty cycle may never exceed the period, not even in between.
+    if period >= pwm.duty_cycle {
+      pwm.set_period(period)?;
+      pwm.set_duty_cycle(duty_cycle)?;
+    } else {
+      pwm.set_duty_cycle(duty_cycle)?;
+      pwm.set_period(period)?;
+    }
+
+    if let Some(enabled) = enabled {
+      pwm.set_state(if enabled {
+        PWMState::Enabled
+      } else {
+        PWMState::Disabled
+      })?;
+    }
+    Ok(pwm)
+  }
 }
@@ -0,0 +1,187 @@
+//! The PWM capture module.
+//!
+//! The eCAP units of the PWM subsystem timestamp the edges of an input
+//! signal in hardware, with the 100MHz clock of the subsystem.
+//! That measures incoming PWM signals, e.g. from an RC receiver or a fan's
+//! tach output, to 10ns regardless of the system load, unlike the
+//! `pwm_input` module on a GPIO pin.
+//! The kernel's driver only uses the eCAPs as PWM outputs, so the registers
+//! are accessed directly through `/dev/mem`, which requires root privileges:
+//!
+//! ```no_run
+//! use libbeaglebone::pwm_capture::PWMCapture;
+//! use std::time::Duration;
+//!
+//! // eCAP0 on pin P9.42, after `config-pin P9.42 pwm`.
+//! let mut capture = PWMCapture::new(0).unwrap();
+//! match capture.measure(Duration::from_millis(100)).unwrap() {
+//!   Some(m) => println!("{}Hz at {}%", m.frequency_hz, m.duty_cycle),
+//!   None => println!("No signal"),
+//! }
+//! ```
+//!
+//! `Tachometer::from_capture()` turns the captured periods of a tach signal
+//! into a speed.
+//!
+//! Capturing takes over the eCAP unit, so it can't be used as a PWM output
+//! at the same time.
+//! As with the `ehrpwm` module, the subsystem's clock has to be running,
+//! which is the case once its PWM driver is loaded.
+
+use errors::*;
+use mmio::MemoryMap;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The base addresses of the PWM subsystems of eCAP0-2.
+const PWMSS_BASES: [usize; 3] = [0x4830_0000, 0x4830_2000, 0x4830_4000];
+
+/// The rate of the clock the eCAP timestamps count.
+pub const ECAP_CLOCK_HZ: u32 = 100_000_000;
+
+/// How often the capture flags are checked while waiting.
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+// The clock configuration of the PWM subsystem.
+const PWMSS_CLKCONFIG: usize = 0x08;
+const CLKCONFIG_ECAPCLK_EN: u32 = 1 << 0;
+const CLKCONFIG_ECAPCLKSTOP_REQ: u32 = 1 << 1;
+
+/// The offset of the eCAP registers within a PWM subsystem.
+const ECAP_OFFSET: usize = 0x100;
+
+// eCAP registers.
+const CAP1: usize = ECAP_OFFSET + 0x08;
+const CAP2: usize = ECAP_OFFSET + 0x0C;
+const CAP3: usize = ECAP_OFFSET + 0x10;
+const ECCTL1: usize = ECAP_OFFSET + 0x28;
+const ECCTL2: usize = ECAP_OFFSET + 0x2A;
+const ECEINT: usize = ECAP_OFFSET + 0x2C;
+const ECFLG: usize = ECAP_OFFSET + 0x2E;
+const ECCLR: usize = ECAP_OFFSET + 0x30;
+
+// ECCTL1 fields: CAP2 and CAP4 on falling edges, CAP1 and CAP3 on rising
+// ones, loading the capture registers with absolute timestamps.
+const ECCTL1_CAP2POL: u16 = 1 << 2;
+const ECCTL1_CAP4POL: u16 = 1 << 6;
+const ECCTL1_CAPLDEN: u16 = 1 << 8;
+
+// ECCTL2 fields: one-shot capture stopping after CAP4, so the timestamps
+// aren't overwritten before they're read, free-running counter, sync output
+// disabled, capture mode.
+const ECCTL2_CONT_ONESHT: u16 = 1 << 0;
+const ECCTL2_STOP_WRAP_CAP4: u16 = 0x3 << 1;
+const ECCTL2_REARM: u16 = 1 << 3;
+const ECCTL2_TSCTRSTOP: u16 = 1 << 4;
+const ECCTL2_SYNCO_DISABLED: u16 = 0x2 << 6;
+
+// ECFLG and ECCLR bits.
+const ECFLG_CEVT4: u16 = 1 << 4;
+const ECFLG_ALL: u16 = 0x3F;
+
+/// The result of capturing a period of a PWM signal.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Capture {
+  /// The frequency of the signal in Hz.
+  pub frequency_hz: f32,
+  /// The duty cycle of the signal as a percentage.
+  pub duty_cycle: f32,
+  /// The length of the period.
+  pub period: Duration,
+  /// How long the signal was high in the period.
+  pub high_time: Duration,
+}
+
+/// Represents an eCAP unit in capture mode.
+#[derive(Debug)]
+pub struct PWMCapture {
+  module: u8,
+  regs: MemoryMap,
+}
+
+impl PWMCapture {
+  /// Maps the registers of eCAP unit `module` (0-2) and configures it to
+  /// capture a rising, falling, rising and falling edge in a row.
+  ///
+  /// # Errors
+  ///
+  /// Fails if `module` isn't within 0-2, or if the registers can't be mapped,
+  /// e.g. because the process isn't running as root.
+  pub fn new(module: u8) -> Result<PWMCapture> {
+    let base = match PWMSS_BASES.get(module as usize) {
+      Some(&base) => base,
+      None => bail!(format!("eCAP unit {} is outside of 0-2", module)),
+    };
+    let capture = PWMCapture {
+      module,
+      regs: MemoryMap::new(base, 0x1000)?,
+    };
+    let clkconfig = capture.regs.read_u32(PWMSS_CLKCONFIG);
+    capture.regs.write_u32(PWMSS_CLKCONFIG,
+                           clkconfig & !CLKCONFIG_ECAPCLKSTOP_REQ | CLKCONFIG_ECAPCLK_EN);
+
+    // Stop the counter and disable the interrupts while reconfiguring.
+    capture.regs.write_u16(ECCTL2, 0);
+    capture.regs.write_u16(ECEINT, 0);
+    capture.regs.write_u16(ECCTL1, ECCTL1_CAP2POL | ECCTL1_CAP4POL | ECCTL1_CAPLDEN);
+    capture.regs.write_u16(ECCLR, ECFLG_ALL);
+    capture.regs.write_u16(ECCTL2,
+                           ECCTL2_CONT_ONESHT | ECCTL2_STOP_WRAP_CAP4 | ECCTL2_TSCTRSTOP |
+                           ECCTL2_SYNCO_DISABLED | ECCTL2_REARM);
+    Ok(capture)
+  }
+
+  /// Returns the number of the eCAP unit.
+  pub fn module(&self) -> u8 {
+    self.module
+  }
+
+  /// Waits up to `timeout` for a full period of the signal and returns it.
+  ///
+  /// Returns `None` if the signal didn't go through a period in time, e.g.
+  /// because it's stuck high or low, like the tach output of a stopped fan.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the captured timestamps make no sense, which means the unit
+  /// was reconfigured by something else, e.g. the kernel's PWM driver.
+  pub fn measure(&mut self, timeout: Duration) -> Result<Option<Capture>> {
+    // Start a new one-shot capture, so the timestamps are of the period
+    // after this call.
+    self.regs.write_u16(ECCLR, ECFLG_ALL);
+    self.modify_ecctl2(ECCTL2_REARM, ECCTL2_REARM);
+
+    let deadline = Instant::now() + timeout;
+    while self.regs.read_u16(ECFLG) & ECFLG_CEVT4 == 0 {
+      let now = Instant::now();
+      if now >= deadline {
+        return Ok(None);
+      }
+      thread::sleep(POLL_INTERVAL.min(deadline - now));
+    }
+
+    let (rise, fall, next_rise) = (self.regs.read_u32(CAP1),
+                                   self.regs.read_u32(CAP2),
+                                   self.regs.read_u32(CAP3));
+    // The counter may have wrapped around in between.
+    let period_counts = next_rise.wrapping_sub(rise);
+    let high_counts = fall.wrapping_sub(rise);
+    if period_counts == 0 || high_counts > period_counts {
+      bail!(format!("eCAP{} captured inconsistent timestamps", self.module));
+    }
+    let counts_to_duration = |counts: u32| {
+      Duration::from_nanos(u64::from(counts) * 1_000_000_000 / u64::from(ECAP_CLOCK_HZ))
+    };
+    Ok(Some(Capture {
+              frequency_hz: ECAP_CLOCK_HZ as f32 / period_counts as f32,
+              duty_cycle: high_counts as f32 / period_counts as f32 * 100.0,
+              period: counts_to_duration(period_counts),
+              high_time: counts_to_duration(high_counts),
+            }))
+  }
+
+  fn modify_ecctl2(&mut self, mask: u16, value: u16) {
+    let ecctl2 = self.regs.read_u16(ECCTL2);
+    self.regs.write_u16(ECCTL2, ecctl2 & !mask | value & mask);
+  }
+}
@@ -0,0 +1,188 @@
+//! The PWM group module.
+//!
+//! An H-bridge or a robot's drive motors need several PWM channels to change
+//! together; one channel changing a few writes ahead of another makes the
+//! robot swerve or briefly shorts a bridge.
+//! A `PWMGroup` stages the new periods and duty cycles of its channels and
+//! writes them back to back on `commit()`, so they change within a few
+//! microseconds of each other:
+//!
+//! ```no_run
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::pwm_group::PWMGroup;
+//!
+//! let mut motors = PWMGroup::new(vec![PWM::new(0, 0), PWM::new(0, 1)]);
+//! for i in 0..motors.len() {
+//!   motors.get_mut(i).unwrap().set_export(DeviceState::Exported).unwrap();
+//!   motors.set_period(i, 50_000).unwrap();
+//! }
+//! motors.write(0, 75.0).unwrap();
+//! motors.write(1, 25.0).unwrap();
+//! motors.commit().unwrap();
+//! motors.set_state(PWMState::Enabled).unwrap();
+//! ```
+//!
+//! The sysfs interface doesn't allow updates that take effect on the same
+//! PWM cycle, so the channels may still be a cycle apart; the duty cycles of
+//! all channels are written in one go, with only the periods that grow
+//! written before them, to keep that window short.
+
+use errors::*;
+use pwm::{PWM, PWMState};
+use std::mem;
+
+/// The changes staged for a channel.
+#[derive(Debug, Clone, Copy, Default)]
+struct Staged {
+  period: Option<u32>,
+  duty_cycle: Option<u32>,
+}
+
+/// PWM channels whose periods and duty cycles are updated together.
+#[derive(Debug)]
+pub struct PWMGroup {
+  pwms: Vec<PWM>,
+  staged: Vec<Staged>,
+}
+
+impl PWMGroup {
+  /// Groups `pwms`, which are referred to by their index from then on.
+  pub fn new(pwms: Vec<PWM>) -> PWMGroup {
+    let staged = vec![Staged::default(); pwms.len()];
+    PWMGroup { pwms, staged }
+  }
+
+  /// Returns the number of channels.
+  pub fn len(&self) -> usize {
+    self.pwms.len()
+  }
+
+  /// Returns whether the group has no channels.
+  pub fn is_empty(&self) -> bool {
+    self.pwms.is_empty()
+  }
+
+  /// Returns channel `index`.
+  pub fn get(&self, index: usize) -> Option<&PWM> {
+    self.pwms.get(index)
+  }
+
+  /// Returns channel `index` to configure it directly, e.g. to export it.
+  pub fn get_mut(&mut self, index: usize) -> Option<&mut PWM> {
+    self.pwms.get_mut(index)
+  }
+
+  /// Unwraps the channels, discarding the staged changes.
+  pub fn into_inner(self) -> Vec<PWM> {
+    self.pwms
+  }
+
+  /// Stages the period of channel `index` in nanoseconds.
+  ///
+  /// # Errors
+  ///
+  /// Fails if there's no such channel.
+  pub fn set_period(&mut self, index: usize, period_ns: u32) -> Result<()> {
+    self.staged_mut(index)?.period = Some(period_ns);
+    Ok(())
+  }
+
+  /// Stages the duty cycle of channel `index` in nanoseconds.
+  ///
+  /// # Errors
+  ///
+  /// Fails if there's no such channel.
+  pub fn set_duty_cycle(&mut self, index: usize, duty_cycle_ns: u32) -> Result<()> {
+    self.staged_mut(index)?.duty_cycle = Some(duty_cycle_ns);
+    Ok(())
+  }
+
+  /// Stages the duty cycle of channel `index` as a percentage of its period,
+  /// the staged one if there is one.
+  ///
+  /// # Errors
+  ///
+  /// Fails if there's no such channel, or if the percentage is less than 0
+  /// or exceeds 100.
+  pub fn write(&mut self, index: usize, percentage: f32) -> Result<()> {
+    if !(0.0..=100.0).contains(&percentage) {
+      bail!(format!("PWM duty cycle {}% isn't between 0 and 100", percentage));
+    }
+    let (period, _) = self.target(index)?;
+    let duty_cycle_ns = (f64::from(percentage) / 100.0 * f64::from(period)).round() as u32;
+    self.set_duty_cycle(index, duty_cycle_ns)
+  }
+
+  /// Writes the staged changes of all channels back to back.
+  ///
+  /// The staged changes are discarded either way.
+  ///
+  /// # Errors
+  ///
+  /// Fails without writing anything if a channel's duty cycle would exceed
+  /// its period.
+  /// Fails if a PWM can't be written, in which case the channels before it
+  /// in the order of writing have been changed already.
+  pub fn commit(&mut self) -> Result<()> {
+    let targets = (0..self.pwms.len()).map(|i| self.target(i))
+                                      .collect::<Result<Vec<_>>>()?;
+    let staged = mem::replace(&mut self.staged, vec![Staged::default(); self.pwms.len()]);
+    for (i, &(period, duty_cycle)) in targets.iter().enumerate() {
+      if duty_cycle > period {
+        bail!(format!("PWM #{} duty cycle {}ns exceeds the period of {}ns", i, duty_cycle, period));
+      }
+    }
+
+    // The duty cycle may never exceed the period, not even in between, so
+    // periods that grow are set before the duty cycles and ones that shrink
+    // after them.
+    for (pwm, staged) in self.pwms.iter_mut().zip(&staged) {
+      match staged.period {
+        Some(period) if period > pwm.period() => pwm.set_period(period)?,
+        _ => {}
+      }
+    }
+    for (pwm, staged) in self.pwms.iter_mut().zip(&staged) {
+      if let Some(duty_cycle) = staged.duty_cycle {
+        pwm.set_duty_cycle(duty_cycle)?;
+      }
+    }
+    for (pwm, staged) in self.pwms.iter_mut().zip(&staged) {
+      match staged.period {
+        Some(period) if period < pwm.period() => pwm.set_period(period)?,
+        _ => {}
+      }
+    }
+    Ok(())
+  }
+
+  /// Enables or disables all channels back to back.
+  ///
+  /// # Errors
+  ///
+  /// Fails if a PWM can't be written, in which case the channels before it
+  /// have been changed already.
+  pub fn set_state(&mut self, state: PWMState) -> Result<()> {
+    for pwm in &mut self.pwms {
+      pwm.set_state(state)?;
+    }
+    Ok(())
+  }
+
+  /// Returns the period and duty cycle channel `index` will have after a
+  /// commit.
+  fn target(&self, index: usize) -> Result<(u32, u32)> {
+    let (pwm, staged) = match (self.pwms.get(index), self.staged.get(index)) {
+      (Some(pwm), Some(staged)) => (pwm, staged),
+      _ => bail!(format!("PWM group has no channel {}", index)),
+    };
+    Ok((staged.period.unwrap_or(pwm.period()), staged.duty_cycle.unwrap_or(pwm.duty_cycle())))
+  }
+
+  fn staged_mut(&mut self, index: usize) -> Result<&mut Staged> {
+    match self.staged.get_mut(index) {
+      Some(staged) => Ok(staged),
+      None => bail!(format!("PWM group has no channel {}", index)),
+    }
+  }
+}
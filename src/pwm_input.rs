@@ -0,0 +1,170 @@
+//! The PWM input module.
+//!
+//! Fans, ESCs and many sensors report their state as a PWM signal.
+//! The `PWMInput` type measures the frequency and duty cycle of such a signal
+//! on any GPIO pin by timestamping its edges, for pins that aren't connected
+//! to a capture unit.
+//!
+//! On a character device line the edges carry the kernel's timestamps, taken
+//! in its interrupt handler.
+//! Over sysfs, which doesn't timestamp edges, they're timestamped when the
+//! wait returns in userspace, so the measurement is affected by scheduling
+//! latency.
+//! Every measurement comes with an estimate of its error, which will be
+//! large on a busy system or with signals faster than a few hundred Hz.
+//!
+//! As with any GPIO, the pin has to be configured beforehand using the
+//! `config-pin` command, e.g. `sudo config-pin P8.11 gpio`.
+
+use enums::DeviceState;
+use errors::*;
+use gpio::{Edge, GPIO, PinDirection, PinState};
+use pins::Pin;
+use std::time::{Duration, Instant};
+
+/// The result of measuring a PWM signal.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PWMMeasurement {
+  /// The mean frequency of the signal in Hz.
+  pub frequency_hz: f32,
+  /// The mean duty cycle of the signal as a percentage.
+  pub duty_cycle: f32,
+  /// The estimated error (standard error of the mean) of `frequency_hz`.
+  pub frequency_error_hz: f32,
+  /// The estimated error (standard error of the mean) of `duty_cycle`, in
+  /// percentage points.
+  pub duty_cycle_error: f32,
+  /// The number of periods the measurement is based on.
+  pub periods: usize,
+  /// The number of edges that were detected as missed, which forces the
+  /// current period to be discarded.
+  pub missed_edges: usize,
+}
+
+/// Measures an incoming PWM signal on a GPIO pin.
+#[derive(Debug)]
+pub struct PWMInput {
+  gpio: GPIO,
+}
+
+impl PWMInput {
+  /// Creates a new PWM input.
+  ///
+  /// The pin is exported and configured as an input that detects both edges.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  /// use libbeaglebone::pwm_input::PWMInput;
+  ///
+  /// let input = PWMInput::new(GPIO_P8_11).unwrap();
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if the pin can't be configured as an edge-triggered GPIO input.
+  pub fn new(pin: Pin) -> Result<PWMInput> {
+    let mut gpio = GPIO::new(pin);
+    gpio.set_export(DeviceState::Exported)?;
+    gpio.set_direction(PinDirection::In)?;
+    gpio.set_edge(Edge::Both)?;
+
+    Ok(PWMInput { gpio })
+  }
+
+  /// Measures the signal over `periods` full periods.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  /// use libbeaglebone::pwm_input::PWMInput;
+  /// use std::time::Duration;
+  ///
+  /// let mut input = PWMInput::new(GPIO_P8_11).unwrap();
+  ///
+  /// // Average over 50 periods, giving up after a second.
+  /// let m = input.measure(50, Duration::from_secs(1)).unwrap();
+  /// println!("{} Hz (+/- {}), {}% duty (+/- {})",
+  ///          m.frequency_hz, m.frequency_error_hz, m.duty_cycle, m.duty_cycle_error);
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if `periods` is zero, if `timeout` expires before enough periods
+  /// were measured (e.g. the signal is constantly high or low), or if the pin
+  /// can't be read.
+  pub fn measure(&mut self, periods: usize, timeout: Duration) -> Result<PWMMeasurement> {
+    if periods == 0 {
+      bail!("Need to measure at least one period");
+    }
+
+    let deadline = Instant::now() + timeout;
+    let mut period_secs = Vec::with_capacity(periods);
+    let mut duty_cycles = Vec::with_capacity(periods);
+    let mut missed_edges = 0;
+    let mut last_level = None;
+    let mut rise: Option<Duration> = None;
+    let mut fall: Option<Duration> = None;
+
+    while period_secs.len() < periods {
+      let remaining = deadline.saturating_duration_since(Instant::now());
+      let (level, now) = match self.gpio.wait_for_edge_timestamped(Some(remaining))? {
+        Some((state, timestamp)) if remaining > Duration::from_secs(0) => (state == PinState::High, timestamp),
+        _ => {
+          bail!(format!(
+            "Timed out measuring PWM on GPIO pin #{} after {} of {} periods",
+            self.gpio.pin_num(),
+            period_secs.len(),
+            periods
+          ))
+        }
+      };
+
+      // Two edges in a row with the same level mean one was missed, so the
+      // current period can't be trusted.
+      if last_level == Some(level) {
+        missed_edges += 1;
+        rise = None;
+        fall = None;
+      }
+      last_level = Some(level);
+
+      if level {
+        if let (Some(rise), Some(fall)) = (rise, fall) {
+          let period = now.saturating_sub(rise).as_secs_f32();
+          period_secs.push(period);
+          duty_cycles.push(fall.saturating_sub(rise).as_secs_f32() / period * 100.0);
+        }
+        rise = Some(now);
+        fall = None;
+      } else if rise.is_some() {
+        fall = Some(now);
+      }
+    }
+
+    let (period, period_error) = mean_and_error(&period_secs);
+    let (duty_cycle, duty_cycle_error) = mean_and_error(&duty_cycles);
+    Ok(PWMMeasurement {
+      frequency_hz: 1.0 / period,
+      duty_cycle,
+      // Propagate the period's error through f = 1 / T.
+      frequency_error_hz: period_error / (period * period),
+      duty_cycle_error,
+      periods,
+      missed_edges,
+    })
+  }
+}
+
+/// Returns the mean of the samples and its standard error.
+fn mean_and_error(samples: &[f32]) -> (f32, f32) {
+  let n = samples.len() as f32;
+  let mean = samples.iter().sum::<f32>() / n;
+  if samples.len() < 2 {
+    return (mean, 0.0);
+  }
+  let variance = samples.iter().map(|s| (s - mean) * (s - mean)).sum::<f32>() / (n - 1.0);
+  (mean, (variance / n).sqrt())
+}
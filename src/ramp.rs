@@ -0,0 +1,173 @@
+//! The ramp module.
+//!
+//! Fading an LED or starting a motor softly means changing a PWM's duty
+//! cycle in small steps over some time.
+//! `PWM::ramp_to()` does so while the caller waits, and
+//! `PWM::ramp_in_background()` on a thread of its own, returning a `Ramp` to
+//! wait for or cancel:
+//!
+//! ```no_run
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::ramp::Easing;
+//! use std::time::Duration;
+//!
+//! let mut led = PWM::new(0, 0);
+//! led.set_export(DeviceState::Exported).unwrap();
+//! led.set_period(1_000_000).unwrap();
+//! led.set_state(PWMState::Enabled).unwrap();
+//!
+//! // Fade in over a second.
+//! led.ramp_to(100.0, Duration::from_secs(1)).unwrap();
+//!
+//! // Fade out over two seconds while doing something else.
+//! let ramp = led.ramp_in_background(0.0, Duration::from_secs(2), Easing::EaseInOut).unwrap();
+//! // ...
+//! let led = ramp.wait().unwrap();
+//! ```
+//!
+//! The duty cycle is updated every `RAMP_STEP`, which is smooth to the eye
+//! and to most motors.
+
+use clock::Clock;
+use errors::*;
+use pwm::PWM;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// The time between duty cycle updates of a ramp.
+pub const RAMP_STEP: Duration = Duration::from_millis(10);
+
+/// How the duty cycle progresses over the time of a ramp.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Easing {
+  /// At a constant rate.
+  Linear,
+  /// Slowly at first, then faster.
+  EaseIn,
+  /// Fast at first, then slower.
+  EaseOut,
+  /// Slowly at both ends, fastest in the middle.
+  EaseInOut,
+}
+
+impl Easing {
+  /// Returns the progress of the duty cycle, from 0.0 to 1.0, after the
+  /// share `t` of the ramp's time, from 0.0 to 1.0.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use libbeaglebone::ramp::Easing;
+  ///
+  /// assert_eq!(Easing::Linear.apply(0.25), 0.25);
+  /// assert!(Easing::EaseIn.apply(0.25) < 0.25);
+  /// assert!(Easing::EaseOut.apply(0.25) > 0.25);
+  /// assert_eq!(Easing::EaseInOut.apply(0.5), 0.5);
+  /// assert_eq!(Easing::EaseInOut.apply(1.0), 1.0);
+  /// ```
+  pub fn apply(self, t: f32) -> f32 {
+    let t = t.clamp(0.0, 1.0);
+    match self {
+      Easing::Linear => t,
+      Easing::EaseIn => t * t,
+      Easing::EaseOut => t * (2.0 - t),
+      Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+    }
+  }
+}
+
+/// A ramp running on a background thread.
+///
+/// Dropping it cancels the ramp and drops the PWM.
+#[derive(Debug)]
+pub struct Ramp {
+  cancel: Arc<AtomicBool>,
+  thread: Option<JoinHandle<(PWM, Result<()>)>>,
+}
+
+impl Ramp {
+  pub(crate) fn new(cancel: Arc<AtomicBool>, thread: JoinHandle<(PWM, Result<()>)>) -> Ramp {
+    Ramp {
+      cancel,
+      thread: Some(thread),
+    }
+  }
+
+  /// Returns whether the ramp has ended.
+  pub fn is_finished(&self) -> bool {
+    self.thread.as_ref().is_none_or(|thread| thread.is_finished())
+  }
+
+  /// Waits for the ramp to end and returns the PWM.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the duty cycle couldn't be written, in which case the PWM is
+  /// dropped.
+  pub fn wait(mut self) -> Result<PWM> {
+    self.join()
+  }
+
+  /// Stops the ramp at the duty cycle it has reached and returns the PWM.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the duty cycle couldn't be written, in which case the PWM is
+  /// dropped.
+  pub fn cancel(mut self) -> Result<PWM> {
+    self.cancel.store(true, Ordering::SeqCst);
+    self.join()
+  }
+
+  fn join(&mut self) -> Result<PWM> {
+    let thread = match self.thread.take() {
+      Some(thread) => thread,
+      None => bail!("The ramp was joined already"),
+    };
+    match thread.join() {
+      Ok((pwm, Ok(()))) => Ok(pwm),
+      Ok((_, Err(e))) => Err(e),
+      Err(_) => bail!("The ramp thread panicked"),
+    }
+  }
+}
+
+impl Drop for Ramp {
+  fn drop(&mut self) {
+    self.cancel.store(true, Ordering::SeqCst);
+    if let Some(thread) = self.thread.take() {
+      let _ = thread.join();
+    }
+  }
+}
+
+/// Steps the duty cycle of `pwm` from `start` to `target` percent over
+/// `duration`, until done or `cancel` is set.
+pub(crate) fn run(pwm: &mut PWM,
+                  start: f32,
+                  target: f32,
+                  duration: Duration,
+                  easing: Easing,
+                  clock: &dyn Clock,
+                  cancel: &AtomicBool)
+                  -> Result<()> {
+  let began = clock.now();
+  loop {
+    if cancel.load(Ordering::SeqCst) {
+      return Ok(());
+    }
+    let elapsed = clock.elapsed_since(began);
+    let t = if duration == Duration::from_secs(0) {
+      1.0
+    } else {
+      elapsed.as_secs_f32() / duration.as_secs_f32()
+    };
+    pwm.write(start + (target - start) * easing.apply(t))?;
+    if t >= 1.0 {
+      return Ok(());
+    }
+    clock.sleep(RAMP_STEP.min(duration.saturating_sub(elapsed)))?;
+  }
+}
@@ -0,0 +1,376 @@
+//! The reactor module.
+//!
+//! `GPIO::wait_for_edge()` blocks the calling thread, one pin at a time.
+//! A `Reactor` watches any number of inputs on a single background thread
+//! instead, calling closures or sending events on a channel when their edges
+//! occur:
+//!
+//! ```no_run
+//! use libbeaglebone::prelude::*;
+//!
+//! let button = GPIO::new(GPIO_P8_11);
+//! button.set_export(DeviceState::Exported).unwrap();
+//! button.set_direction(PinDirection::In).unwrap();
+//!
+//! let _pressed = button.on_edge(Edge::Falling, |event| {
+//!   println!("Pressed at {:?}", event.timestamp);
+//! }).unwrap();
+//!
+//! // Measure the pulses of a sensor, from rising to falling edge.
+//! let sensor = GPIO::new(GPIO_P8_12);
+//! sensor.set_export(DeviceState::Exported).unwrap();
+//! sensor.set_direction(PinDirection::In).unwrap();
+//! let (_subscription, events) = sensor.edge_events(Edge::Both).unwrap();
+//! let mut rising = None;
+//! for event in events {
+//!   match event.state {
+//!     PinState::High => rising = Some(event.timestamp),
+//!     PinState::Low => if let Some(rising) = rising {
+//!       println!("Pulse of {:?}", event.timestamp - rising);
+//!     },
+//!   }
+//! }
+//! ```
+//!
+//! `GPIO::on_edge()` and `GPIO::edge_events()` use a reactor shared by the
+//! whole process, which is started on first use; separate `Reactor`s keep
+//! slow callbacks of one part of a program from delaying the others.
+//! Dropping a `Subscription` stops its callback or closes its channel.
+//!
+//! The callbacks run on the reactor thread, one after another, so they
+//! should return quickly.
+//! Events are timestamped when the thread wakes up, which is late by the
+//! scheduling latency, typically tens of µs, but the same for every edge.
+
+use errors::*;
+use gpio::{Edge, PinState};
+use nix::fcntl::{O_CLOEXEC, O_NONBLOCK};
+use nix::poll::{EventFlags, POLLERR, POLLIN, POLLPRI, PollFd, poll};
+use nix::unistd::{close, pipe2, read, write};
+use std::collections::{HashMap, hash_map};
+use std::fmt;
+use std::mem;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+use util::EdgeWaiter;
+
+/// An edge on an input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EdgeEvent {
+  /// The kernel's number of the pin.
+  pub pin_num: u8,
+  /// The state of the pin right after the edge, i.e. `High` for a rising
+  /// edge.
+  pub state: PinState,
+  /// When the edge was seen.
+  pub timestamp: Instant,
+}
+
+type Callback = Arc<Mutex<Box<dyn FnMut(EdgeEvent) + Send>>>;
+
+struct Handler {
+  id: u64,
+  edge: Edge,
+  callback: Callback,
+}
+
+/// The pin's edge file is shared by all handlers of a pin, so there's one
+/// waiter per pin, set to both edges, and the handlers filter.
+struct Watched {
+  waiter: Arc<Mutex<EdgeWaiter>>,
+  handlers: Vec<Handler>,
+}
+
+struct Registry {
+  pins: HashMap<u8, Watched>,
+  next_id: u64,
+  errors: Vec<Error>,
+}
+
+struct Shared {
+  registry: Mutex<Registry>,
+  running: AtomicBool,
+  wake_read: RawFd,
+  wake_write: RawFd,
+}
+
+impl fmt::Debug for Shared {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let registry = self.registry();
+    let mut pins: Vec<&u8> = registry.pins.keys().collect();
+    pins.sort();
+    f.debug_struct("Shared").field("pins", &pins).finish()
+  }
+}
+
+impl Shared {
+  fn registry(&self) -> MutexGuard<'_, Registry> {
+    lock(&self.registry)
+  }
+
+  /// Makes the thread pick up changes of the registry.
+  fn wake(&self) {
+    // A full pipe has a wake-up pending already.
+    let _ = write(self.wake_write, &[0]);
+  }
+
+  fn remove(&self, pin_num: u8, id: u64) {
+    {
+      let mut registry = self.registry();
+      let now_unwatched = match registry.pins.get_mut(&pin_num) {
+        Some(watched) => {
+          watched.handlers.retain(|handler| handler.id != id);
+          watched.handlers.is_empty()
+        }
+        None => false,
+      };
+      if now_unwatched {
+        let _ = registry.pins.remove(&pin_num);
+      }
+    }
+    self.wake();
+  }
+}
+
+impl Drop for Shared {
+  fn drop(&mut self) {
+    let _ = close(self.wake_read);
+    let _ = close(self.wake_write);
+  }
+}
+
+fn lock<T: ?Sized>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+  mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Calls the callback of an edge until it's dropped.
+#[derive(Debug)]
+pub struct Subscription {
+  pin_num: u8,
+  id: u64,
+  shared: Arc<Shared>,
+}
+
+impl Subscription {
+  /// Returns the kernel's number of the watched pin.
+  pub fn pin_num(&self) -> u8 {
+    self.pin_num
+  }
+}
+
+impl Drop for Subscription {
+  fn drop(&mut self) {
+    self.shared.remove(self.pin_num, self.id);
+  }
+}
+
+/// Watches inputs for edges on a background thread.
+///
+/// Dropping it stops the thread; its subscriptions don't get any events
+/// anymore then.
+#[derive(Debug)]
+pub struct Reactor {
+  shared: Arc<Shared>,
+  thread: Option<JoinHandle<()>>,
+}
+
+impl Reactor {
+  /// Starts a reactor without any subscriptions.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the pipe to wake up the thread can't be created.
+  pub fn new() -> Result<Reactor> {
+    let (wake_read, wake_write) = pipe2(O_NONBLOCK | O_CLOEXEC).chain_err(|| "Failed to create the reactor's pipe")?;
+    let shared = Arc::new(Shared {
+      registry: Mutex::new(Registry {
+        pins: HashMap::new(),
+        next_id: 0,
+        errors: Vec::new(),
+      }),
+      running: AtomicBool::new(true),
+      wake_read,
+      wake_write,
+    });
+    let thread = {
+      let shared = shared.clone();
+      thread::spawn(move || react(&shared))
+    };
+    Ok(Reactor {
+      shared,
+      thread: Some(thread),
+    })
+  }
+
+  /// Calls `callback` on the reactor thread for every edge `edge` on the
+  /// GPIO input `pin_num`, until the subscription is dropped.
+  ///
+  /// # Errors
+  ///
+  /// Fails if `edge` is `Edge::None`, or if the pin isn't an exported input
+  /// supporting edge interrupts.
+  pub fn on_edge<F>(&self, pin_num: u8, edge: Edge, callback: F) -> Result<Subscription>
+    where F: FnMut(EdgeEvent) + Send + 'static
+  {
+    if edge == Edge::None {
+      bail!("Subscribing to Edge::None never calls back");
+    }
+    let callback: Box<dyn FnMut(EdgeEvent) + Send> = Box::new(callback);
+    let id = {
+      let mut registry = self.shared.registry();
+      let id = registry.next_id;
+      let watched = match registry.pins.entry(pin_num) {
+        hash_map::Entry::Occupied(entry) => entry.into_mut(),
+        hash_map::Entry::Vacant(entry) => {
+          entry.insert(Watched {
+            waiter: Arc::new(Mutex::new(EdgeWaiter::new(pin_num, "both")?)),
+            handlers: Vec::new(),
+          })
+        }
+      };
+      watched.handlers.push(Handler {
+        id,
+        edge,
+        callback: Arc::new(Mutex::new(callback)),
+      });
+      registry.next_id += 1;
+      id
+    };
+    self.shared.wake();
+    Ok(Subscription {
+      pin_num,
+      id,
+      shared: self.shared.clone(),
+    })
+  }
+
+  /// Sends the edges `edge` on the GPIO input `pin_num` on a channel, until
+  /// the subscription is dropped.
+  ///
+  /// # Errors
+  ///
+  /// See `on_edge()`.
+  pub fn edge_events(&self, pin_num: u8, edge: Edge) -> Result<(Subscription, Receiver<EdgeEvent>)> {
+    let (sender, receiver) = mpsc::channel();
+    let subscription = self.on_edge(pin_num, edge, move |event| {
+      let _ = sender.send(event);
+    })?;
+    Ok((subscription, receiver))
+  }
+
+  /// Returns and clears the errors of reading the inputs.
+  ///
+  /// An input that can't be read, e.g. because it was unexported, isn't
+  /// watched anymore.
+  pub fn take_errors(&self) -> Vec<Error> {
+    mem::take(&mut self.shared.registry().errors)
+  }
+}
+
+impl Drop for Reactor {
+  fn drop(&mut self) {
+    self.shared.running.store(false, Ordering::SeqCst);
+    self.shared.wake();
+    if let Some(thread) = self.thread.take() {
+      let _ = thread.join();
+    }
+  }
+}
+
+/// The reactor shared by the whole process, if started.
+static SHARED: Mutex<Option<Arc<Reactor>>> = Mutex::new(None);
+
+/// Returns the reactor shared by the whole process, starting it on first
+/// use.
+///
+/// # Errors
+///
+/// Fails if the reactor can't be started.
+pub fn shared() -> Result<Arc<Reactor>> {
+  let mut reactor = lock(&SHARED);
+  if reactor.is_none() {
+    *reactor = Some(Arc::new(Reactor::new()?));
+  }
+  match *reactor {
+    Some(ref reactor) => Ok(reactor.clone()),
+    None => unreachable!(),
+  }
+}
+
+/// Waits for edges and dispatches them until the reactor is dropped.
+fn react(shared: &Shared) {
+  while shared.running.load(Ordering::SeqCst) {
+    // The waiters are cloned, so their files stay open while being polled
+    // even if their subscriptions are dropped meanwhile.
+    let watched: Vec<(u8, Arc<Mutex<EdgeWaiter>>)> = shared.registry()
+                                                           .pins
+                                                           .iter()
+                                                           .map(|(&pin_num, watched)| {
+                                                             (pin_num, watched.waiter.clone())
+                                                           })
+                                                           .collect();
+    let mut fds = vec![PollFd::new(shared.wake_read, POLLIN, EventFlags::empty())];
+    for (_, waiter) in &watched {
+      fds.push(PollFd::new(lock(waiter).as_raw_fd(), POLLPRI | POLLERR, EventFlags::empty()));
+    }
+    if poll(&mut fds, -1).is_err() {
+      // Interrupted by a signal.
+      continue;
+    }
+    let timestamp = Instant::now();
+
+    if fds[0].revents().is_some_and(|revents| !revents.is_empty()) {
+      let mut buf = [0; 64];
+      let _ = read(shared.wake_read, &mut buf);
+    }
+    for (fd, &(pin_num, ref waiter)) in fds[1..].iter().zip(&watched) {
+      if fd.revents().is_none_or(|revents| revents.is_empty()) {
+        continue;
+      }
+      let state = match lock(waiter).acknowledge() {
+        Ok(true) => PinState::High,
+        Ok(false) => PinState::Low,
+        Err(e) => {
+          let mut registry = shared.registry();
+          registry.errors.push(e);
+          let _ = registry.pins.remove(&pin_num);
+          continue;
+        }
+      };
+      let event = EdgeEvent {
+        pin_num,
+        state,
+        timestamp,
+      };
+      // The callbacks are called without the registry locked, so they can
+      // drop subscriptions.
+      let callbacks: Vec<Callback> = match shared.registry().pins.get(&pin_num) {
+        Some(watched) => {
+          watched.handlers
+                 .iter()
+                 .filter(|handler| matches(handler.edge, state))
+                 .map(|handler| handler.callback.clone())
+                 .collect()
+        }
+        None => Vec::new(),
+      };
+      for callback in callbacks {
+        (*lock(&callback))(event);
+      }
+    }
+  }
+}
+
+/// Returns whether an edge leaving the pin in `state` is an edge `edge`.
+pub(crate) fn matches(edge: Edge, state: PinState) -> bool {
+  match edge {
+    Edge::None => false,
+    Edge::Rising => state == PinState::High,
+    Edge::Falling => state == PinState::Low,
+    Edge::Both => true,
+  }
+}
@@ -0,0 +1,363 @@
+//! The relay module.
+//!
+//! Relay boards are driven by plain GPIO outputs, but using them safely needs
+//! a bit more care than blinking an LED:
+//!
+//! * Many boards are active-low, i.e. the relay is energized when the GPIO is
+//!   driven low.
+//! * Some relays must never be energized at the same time, for example the
+//!   forward and reverse relays of a motor.
+//!   Putting them in an interlock group of a `RelayBank` enforces this.
+//! * Switching contacts too often wears them out and can damage the load, so
+//!   each relay can enforce minimum on and off times.
+//!
+//! Relays are usually driven by GPIOs, but any `DigitalPin` works, e.g. the
+//! pins of a port expander.
+//! As with any GPIO, the pins have to be configured beforehand using the
+//! `config-pin` command, e.g. `sudo config-pin P8.11 gpio`.
+
+use clock::{self, Clock};
+use errors::*;
+use gpio::{self, GPIO, PinState};
+use hal::DigitalPin;
+use pins::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A relay driven by a digital output, by default a GPIO.
+#[derive(Debug)]
+pub struct Relay<P: DigitalPin = GPIO> {
+  pin: P,
+  clock: Arc<dyn Clock>,
+  active_low: bool,
+  min_on_time: Duration,
+  min_off_time: Duration,
+  energized: bool,
+  last_switch: Option<Duration>,
+}
+
+impl Relay<GPIO> {
+  /// Creates a new relay on a GPIO and de-energizes it.
+  ///
+  /// The pin is exported and configured as an output, driving the
+  /// de-energized level right away so the relay doesn't click on while the
+  /// pin is being set up.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  ///
+  /// // An active-low relay board input on pin #45.
+  /// let relay = Relay::new(GPIO_P8_11, true).unwrap();
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if the pin can't be configured as a GPIO output.
+  pub fn new(pin: Pin, active_low: bool) -> Result<Relay> {
+    let gpio = gpio::output_driving(pin, if active_low { PinState::High } else { PinState::Low })?;
+    Ok(Relay::with_state(gpio, active_low))
+  }
+}
+
+impl<P: DigitalPin> Relay<P> {
+  /// Creates a new relay on an already configured output and de-energizes
+  /// it.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  ///
+  /// let gpio = GPIO::new(GPIO_P8_11);
+  /// gpio.set_export(DeviceState::Exported).unwrap();
+  /// gpio.set_direction(PinDirection::Out).unwrap();
+  ///
+  /// let relay = Relay::from_pin(gpio, false).unwrap();
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if the pin can't be driven.
+  pub fn from_pin(mut pin: P, active_low: bool) -> Result<Relay<P>> {
+    pin.set_state(if active_low {
+      PinState::High
+    } else {
+      PinState::Low
+    })?;
+    Ok(Relay::with_state(pin, active_low))
+  }
+
+  fn with_state(pin: P, active_low: bool) -> Relay<P> {
+    Relay {
+      pin,
+      clock: clock::system(),
+      active_low,
+      min_on_time: Duration::from_secs(0),
+      min_off_time: Duration::from_secs(0),
+      energized: false,
+      last_switch: None,
+    }
+  }
+
+  /// Sets the minimum time the relay has to stay energized before it may be
+  /// de-energized.
+  pub fn set_min_on_time(&mut self, min_on_time: Duration) {
+    self.min_on_time = min_on_time;
+  }
+
+  /// Sets the minimum time the relay has to stay de-energized before it may
+  /// be energized again.
+  pub fn set_min_off_time(&mut self, min_off_time: Duration) {
+    self.min_off_time = min_off_time;
+  }
+
+  /// Makes the relay take its time from `clock`, e.g. a `TestClock`.
+  pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+    self.clock = clock;
+    self.last_switch = None;
+  }
+
+  /// Returns whether the relay is currently energized.
+  pub fn is_energized(&self) -> bool {
+    self.energized
+  }
+
+  /// Returns how long to wait before the relay may switch again.
+  ///
+  /// Returns zero if the relay may switch right away.
+  pub fn time_until_switch_allowed(&self) -> Duration {
+    let dwell = if self.energized {
+      self.min_on_time
+    } else {
+      self.min_off_time
+    };
+    match self.last_switch {
+      Some(last_switch) => dwell.checked_sub(self.clock.elapsed_since(last_switch))
+                                .unwrap_or_else(|| Duration::from_secs(0)),
+      None => Duration::from_secs(0),
+    }
+  }
+
+  /// Energizes or de-energizes the relay.
+  ///
+  /// Does nothing if the relay is already in the requested state.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  /// use std::time::Duration;
+  ///
+  /// let mut relay = Relay::new(GPIO_P8_11, true).unwrap();
+  ///
+  /// // Protect the contacts by keeping them closed for at least a second.
+  /// relay.set_min_on_time(Duration::from_secs(1));
+  ///
+  /// relay.set_energized(true).unwrap();
+  ///
+  /// // Fails, the relay has to stay on for another second.
+  /// assert!(relay.set_energized(false).is_err());
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if the minimum on or off time hasn't elapsed yet, or if the GPIO
+  /// can't be written.
+  pub fn set_energized(&mut self, energized: bool) -> Result<()> {
+    if energized == self.energized {
+      return Ok(());
+    }
+
+    let remaining = self.time_until_switch_allowed();
+    if remaining > Duration::from_secs(0) {
+      bail!(format!(
+        "Relay on {} can't switch for another {}ms",
+        self.pin.pin_name(),
+        remaining.as_millis()
+      ));
+    }
+    self.switch(energized)
+  }
+
+  /// De-energizes the relay right away, even if its minimum on time hasn't
+  /// elapsed yet, e.g. to shut down on an emergency stop.
+  ///
+  /// Does nothing if the relay is already de-energized.
+  ///
+  /// ```
+  /// use libbeaglebone::prelude::*;
+  /// use libbeaglebone::errors::*;
+  /// use std::time::Duration;
+  ///
+  /// // An output that can always be driven.
+  /// struct FakePin;
+  ///
+  /// impl DigitalPin for FakePin {
+  ///   fn set_state(&mut self, _: PinState) -> Result<()> { Ok(()) }
+  ///   fn state(&self) -> Result<PinState> { Ok(PinState::Low) }
+  ///   fn pin_name(&self) -> String { "fake pin".to_string() }
+  /// }
+  ///
+  /// let mut relay = Relay::from_pin(FakePin, false).unwrap();
+  /// relay.set_min_on_time(Duration::from_secs(60));
+  /// relay.energize().unwrap();
+  ///
+  /// assert!(relay.de_energize().is_err());
+  /// relay.force_de_energize().unwrap();
+  /// assert!(!relay.is_energized());
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if the output can't be written.
+  pub fn force_de_energize(&mut self) -> Result<()> {
+    if !self.energized {
+      return Ok(());
+    }
+    self.switch(false)
+  }
+
+  /// Drives the output and starts the dwell of the new state.
+  fn switch(&mut self, energized: bool) -> Result<()> {
+    self.pin.set_state(if energized != self.active_low {
+      PinState::High
+    } else {
+      PinState::Low
+    })?;
+    self.energized = energized;
+    self.last_switch = Some(self.clock.now());
+    Ok(())
+  }
+
+  /// Energizes the relay.
+  pub fn energize(&mut self) -> Result<()> {
+    self.set_energized(true)
+  }
+
+  /// De-energizes the relay.
+  pub fn de_energize(&mut self) -> Result<()> {
+    self.set_energized(false)
+  }
+}
+
+/// A group of relays with interlocks between them.
+#[derive(Debug)]
+pub struct RelayBank<P: DigitalPin = GPIO> {
+  relays: Vec<Relay<P>>,
+  interlocks: Vec<Vec<usize>>,
+}
+
+impl<P: DigitalPin> Default for RelayBank<P> {
+  fn default() -> RelayBank<P> {
+    RelayBank {
+      relays: Vec::new(),
+      interlocks: Vec::new(),
+    }
+  }
+}
+
+impl<P: DigitalPin> RelayBank<P> {
+  /// Creates a new, empty relay bank.
+  pub fn new() -> RelayBank<P> {
+    RelayBank::default()
+  }
+
+  /// Adds a relay to the bank and returns its index.
+  pub fn add(&mut self, relay: Relay<P>) -> usize {
+    self.relays.push(relay);
+    self.relays.len() - 1
+  }
+
+  /// Adds an interlock group: at most one of the given relays may be
+  /// energized at any time.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  /// use libbeaglebone::relay::RelayBank;
+  ///
+  /// let mut bank = RelayBank::new();
+  /// let forward = bank.add(Relay::new(GPIO_P8_11, true).unwrap());
+  /// let reverse = bank.add(Relay::new(GPIO_P8_12, true).unwrap());
+  /// bank.add_interlock(&[forward, reverse]).unwrap();
+  ///
+  /// bank.energize(forward).unwrap();
+  ///
+  /// // Fails, the forward relay is still energized.
+  /// assert!(bank.energize(reverse).is_err());
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if an index doesn't refer to a relay in the bank, or if more than
+  /// one of the relays is currently energized.
+  pub fn add_interlock(&mut self, relays: &[usize]) -> Result<()> {
+    for &index in relays {
+      let _ = self.relay(index)?;
+    }
+    if relays.iter().filter(|&&i| self.relays[i].is_energized()).count() > 1 {
+      bail!("More than one relay of the interlock group is energized");
+    }
+    self.interlocks.push(relays.to_vec());
+    Ok(())
+  }
+
+  /// Returns the relay at `index`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if there is no relay at that index.
+  pub fn relay(&self, index: usize) -> Result<&Relay<P>> {
+    match self.relays.get(index) {
+      Some(relay) => Ok(relay),
+      None => bail!(format!("No relay #{} in the relay bank", index)),
+    }
+  }
+
+  /// Energizes the relay at `index`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if another relay of one of its interlock groups is energized, or
+  /// if the relay itself can't switch.
+  pub fn energize(&mut self, index: usize) -> Result<()> {
+    let _ = self.relay(index)?;
+    for group in self.interlocks.iter().filter(|g| g.contains(&index)) {
+      if let Some(&other) = group.iter()
+                                 .find(|&&i| i != index && self.relays[i].is_energized()) {
+        bail!(format!(
+          "Relay #{} can't be energized while interlocked relay #{} is energized",
+          index,
+          other
+        ));
+      }
+    }
+    self.relays[index].energize()
+  }
+
+  /// De-energizes the relay at `index`.
+  pub fn de_energize(&mut self, index: usize) -> Result<()> {
+    let _ = self.relay(index)?;
+    self.relays[index].de_energize()
+  }
+
+  /// De-energizes all of the relays in the bank, even those whose minimum
+  /// on time hasn't elapsed yet, see `Relay::force_de_energize()`.
+  ///
+  /// Tries every relay even if some of them fail, and returns the first
+  /// error.
+  pub fn de_energize_all(&mut self) -> Result<()> {
+    let mut result = Ok(());
+    for relay in &mut self.relays {
+      if let Err(e) = relay.force_de_energize() {
+        if result.is_ok() {
+          result = Err(e);
+        }
+      }
+    }
+    result
+  }
+}
@@ -0,0 +1,169 @@
+//! The pin reservation module.
+//!
+//! Sysfs lets any process export and drive any pin, so two programs that
+//! were both configured to use the same pin fight over it without noticing.
+//! Programs that claim their pins with a `Reservation` do notice: a claim
+//! takes an exclusive `flock()` on a lock file per pin, and a second claim
+//! on the same pin fails with the process holding it, until the first
+//! reservation is dropped or its process exits.
+//!
+//! ```no_run
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::reservation::Reservation;
+//!
+//! // Fails if another program holds the pin, e.g. with
+//! // "GPIO pin #45 is reserved by process 1234 (/usr/bin/fan-control)".
+//! let _reservation = Reservation::gpio(GPIO_P8_11 as u8).unwrap();
+//!
+//! let fan = GPIO::new(GPIO_P8_11);
+//! fan.set_export(DeviceState::Exported).unwrap();
+//! ```
+//!
+//! Reservations are advisory: they only protect against programs that take
+//! them too.
+//! The lock files are kept in `/run/libbeaglebone` by default, which all
+//! cooperating programs need to be able to write to.
+
+use errors::*;
+use nix::fcntl::{FlockArg, flock};
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::RwLock;
+
+/// The directory of the lock files, unless changed with `set_lock_dir()`.
+pub const DEFAULT_LOCK_DIR: &str = "/run/libbeaglebone";
+
+/// The directory of the lock files, if changed.
+static LOCK_DIR: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+/// Changes the directory of the lock files, e.g. when `/run` isn't writable.
+///
+/// All programs that share pins have to use the same directory.
+pub fn set_lock_dir<P: AsRef<Path>>(dir: P) {
+  *LOCK_DIR.write().unwrap_or_else(|e| e.into_inner()) = Some(dir.as_ref().to_path_buf());
+}
+
+/// Returns the directory of the lock files.
+pub fn lock_dir() -> PathBuf {
+  match *LOCK_DIR.read().unwrap_or_else(|e| e.into_inner()) {
+    Some(ref dir) => dir.clone(),
+    None => PathBuf::from(DEFAULT_LOCK_DIR),
+  }
+}
+
+/// An exclusive claim on a pin or other resource, released when dropped.
+#[derive(Debug)]
+pub struct Reservation {
+  resource: String,
+  description: String,
+  file: File,
+}
+
+impl Reservation {
+  /// Claims the GPIO pin with the kernel number `pin_num`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the pin is reserved already, or if the lock file can't be
+  /// created.
+  pub fn gpio(pin_num: u8) -> Result<Reservation> {
+    Reservation::claim(&format!("gpio{}", pin_num), &format!("GPIO pin #{}", pin_num))
+  }
+
+  /// Claims PWM `pwm_num` of chip `pwm_chip_num`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the PWM is reserved already, or if the lock file can't be
+  /// created.
+  pub fn pwm(pwm_chip_num: u8, pwm_num: u8) -> Result<Reservation> {
+    Reservation::claim(&format!("pwm{}-{}", pwm_chip_num, pwm_num),
+                       &format!("PWM #{}-{}", pwm_chip_num, pwm_num))
+  }
+
+  /// Claims ADC input `adc_num`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the input is reserved already, or if the lock file can't be
+  /// created.
+  pub fn adc(adc_num: u16) -> Result<Reservation> {
+    Reservation::claim(&format!("adc{}", adc_num), &format!("ADC #{}", adc_num))
+  }
+
+  /// Claims any other resource by a name made of letters, digits, '-' and
+  /// '_', e.g. "uart4", described as `description` in errors.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the name is invalid, if the resource is reserved already, or if
+  /// the lock file can't be created.
+  pub fn claim(resource: &str, description: &str) -> Result<Reservation> {
+    check_name(resource)?;
+    let dir = lock_dir();
+    fs::create_dir_all(&dir)
+      .chain_err(|| format!("Failed to create reservation directory {}", dir.display()))?;
+    let path = dir.join(format!("{}.lock", resource));
+    let mut file = OpenOptions::new()
+      .read(true)
+      .write(true)
+      .create(true)
+      .truncate(false)
+      .open(&path)
+      .chain_err(|| format!("Failed to open reservation file {}", path.display()))?;
+
+    if flock(file.as_raw_fd(), FlockArg::LockExclusiveNonblock).is_err() {
+      let mut holder = String::new();
+      let _ = file.read_to_string(&mut holder);
+      match holder.trim().split_once(' ') {
+        Some((pid, program)) => {
+          bail!(format!("{} is reserved by process {} ({})", description, pid, program))
+        }
+        None => bail!(format!("{} is reserved by another process", description)),
+      }
+    }
+
+    // Tell whoever tries next who holds the reservation.
+    let program = env::args().next().unwrap_or_default();
+    file.set_len(0)
+        .and_then(|_| file.seek(SeekFrom::Start(0)))
+        .and_then(|_| writeln!(file, "{} {}", process::id(), program))
+        .chain_err(|| format!("Failed to write reservation file {}", path.display()))?;
+    Ok(Reservation {
+      resource: resource.to_string(),
+      description: description.to_string(),
+      file,
+    })
+  }
+
+  /// Returns the name of the reserved resource, e.g. "gpio45".
+  pub fn resource(&self) -> &str {
+    &self.resource
+  }
+
+  /// Returns the description of the reserved resource, e.g. "GPIO pin #45".
+  pub fn description(&self) -> &str {
+    &self.description
+  }
+}
+
+impl Drop for Reservation {
+  fn drop(&mut self) {
+    // The file is kept, removing it would race with other claims; closing it
+    // releases the lock.
+    let _ = self.file.set_len(0);
+  }
+}
+
+/// Fails unless `resource` is made of letters, digits, '-' and '_'.
+pub(crate) fn check_name(resource: &str) -> Result<()> {
+  if resource.is_empty() ||
+     !resource.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+    bail!(format!("Invalid reservation name {:?}", resource));
+  }
+  Ok(())
+}
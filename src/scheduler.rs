@@ -0,0 +1,366 @@
+//! The scheduler module.
+//!
+//! Greenhouse, aquarium and lighting controllers switch their outputs at
+//! times of day, often relative to sunrise or sunset.
+//! A `Scheduler` holds the outputs and their events, and applies the events
+//! that are due whenever `tick()` is called, e.g. from the main loop once a
+//! second:
+//!
+//! ```no_run
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::scheduler::{Action, Scheduler, Time};
+//! use std::thread;
+//! use std::time::Duration;
+//!
+//! let pump = GPIO::new(GPIO_P8_11);
+//! pump.set_export(DeviceState::Exported).unwrap();
+//! pump.set_direction(PinDirection::Out).unwrap();
+//! let mut lamp = PWM::new(0, 0);
+//! lamp.set_export(DeviceState::Exported).unwrap();
+//! lamp.set_period(1_000_000).unwrap();
+//!
+//! let mut scheduler = Scheduler::new();
+//! scheduler.set_location(52.5, 13.4).unwrap();
+//! let pump = scheduler.add_digital(pump);
+//! let lamp = scheduler.add_pwm(lamp);
+//! scheduler.at(pump, Time::clock(7, 30).unwrap(), Action::On).unwrap();
+//! scheduler.at(pump, Time::clock(7, 45).unwrap(), Action::Off).unwrap();
+//! // Dim light from half an hour before sunset, off two hours after it.
+//! scheduler.at(lamp, Time::Sunset(-30), Action::Duty(40.0)).unwrap();
+//! scheduler.at(lamp, Time::Sunset(120), Action::Off).unwrap();
+//!
+//! loop {
+//!   scheduler.tick().unwrap();
+//!   thread::sleep(Duration::from_secs(1));
+//! }
+//! ```
+//!
+//! Times are in the local time zone of the system.
+//! The first tick sets every output as its latest event before then says,
+//! so the outputs are right after a restart, too.
+
+use errors::*;
+use hal::{DigitalPin, PwmOutput};
+use nix::libc;
+use std::f64::consts::PI;
+use std::fmt;
+use std::mem;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Identifies an output of a `Scheduler`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct OutputId(usize);
+
+/// When an event happens each day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Time {
+  /// At minutes after local midnight.
+  Clock(u32),
+  /// At minutes after sunrise, or before it if negative.
+  Sunrise(i32),
+  /// At minutes after sunset, or before it if negative.
+  Sunset(i32),
+}
+
+impl Time {
+  /// Returns the time `hour`:`minute` local time.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the hour isn't below 24 or the minute isn't below 60.
+  pub fn clock(hour: u32, minute: u32) -> Result<Time> {
+    if hour >= 24 || minute >= 60 {
+      bail!(format!("{}:{:02} isn't a time of day", hour, minute));
+    }
+    Ok(Time::Clock(hour * 60 + minute))
+  }
+}
+
+/// What an event does to its output.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+  /// Drives a digital output high or enables a PWM output.
+  On,
+  /// Drives a digital output low or disables a PWM output.
+  Off,
+  /// Sets a PWM output to a duty cycle percentage and enables it.
+  Duty(f32),
+}
+
+enum Output {
+  Digital(Box<dyn DigitalPin + Send>),
+  Pwm(Box<dyn PwmOutput + Send>),
+}
+
+impl fmt::Debug for Output {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match *self {
+      Output::Digital(ref pin) => write!(f, "Digital({})", pin.pin_name()),
+      Output::Pwm(_) => write!(f, "Pwm"),
+    }
+  }
+}
+
+impl Output {
+  fn apply(&mut self, action: Action) -> Result<()> {
+    match (self, action) {
+      (&mut Output::Digital(ref mut pin), Action::On) => pin.set_high(),
+      (&mut Output::Digital(ref mut pin), Action::Off) => pin.set_low(),
+      (&mut Output::Pwm(ref mut pwm), Action::On) => pwm.set_enabled(true),
+      (&mut Output::Pwm(ref mut pwm), Action::Off) => pwm.set_enabled(false),
+      (&mut Output::Pwm(ref mut pwm), Action::Duty(percentage)) => {
+        pwm.set_duty_cycle_percent(percentage)?;
+        pwm.set_enabled(true)
+      }
+      (_, action) => bail!(format!("{:?} doesn't apply to this output", action)),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Event {
+  output: OutputId,
+  time: Time,
+  action: Action,
+}
+
+/// The local date of a point in time.
+#[derive(Debug, Clone, Copy)]
+struct Day {
+  // Local midnight in seconds since the epoch.
+  midnight: i64,
+  // The offset of local time from UTC in seconds.
+  utc_offset: i64,
+  // The day of the year, starting at 0.
+  day_of_year: u32,
+}
+
+impl Day {
+  /// Returns the local day `time`, in seconds since the epoch, falls on.
+  // time_t and the UTC offset are 32 bits on the BeagleBone's ARM, but 64
+  // bits elsewhere.
+  #[allow(trivial_numeric_casts)]
+  fn of(time: i64) -> Day {
+    let mut tm: libc::tm = unsafe { mem::zeroed() };
+    let t = time as libc::time_t;
+    // Can't fail for a valid pointer and a time within the range of a tm.
+    let _ = unsafe { libc::localtime_r(&t, &mut tm) };
+    let seconds_of_day = i64::from(tm.tm_hour) * 3600 + i64::from(tm.tm_min) * 60 +
+                         i64::from(tm.tm_sec);
+    Day {
+      midnight: time - seconds_of_day,
+      utc_offset: tm.tm_gmtoff as i64,
+      day_of_year: tm.tm_yday as u32,
+    }
+  }
+
+  /// Returns the day before.
+  fn previous(&self) -> Day {
+    Day::of(self.midnight - 1)
+  }
+}
+
+/// Switches outputs at times of day.
+#[derive(Debug, Default)]
+pub struct Scheduler {
+  outputs: Vec<Output>,
+  events: Vec<Event>,
+  location: Option<(f64, f64)>,
+  // The time of the last tick in seconds since the epoch.
+  last_tick: Option<i64>,
+}
+
+impl Scheduler {
+  /// Creates a scheduler without outputs.
+  pub fn new() -> Scheduler {
+    Scheduler::default()
+  }
+
+  /// Sets the location that sunrise and sunset are computed for, in degrees
+  /// north and east.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the latitude isn't within -90 to 90 or the longitude isn't
+  /// within -180 to 180.
+  pub fn set_location(&mut self, latitude: f64, longitude: f64) -> Result<()> {
+    if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+      bail!(format!("{}, {} isn't a location", latitude, longitude));
+    }
+    self.location = Some((latitude, longitude));
+    Ok(())
+  }
+
+  /// Adds a digital output, e.g. a `GPIO` configured as an output.
+  pub fn add_digital<P: DigitalPin + Send + 'static>(&mut self, pin: P) -> OutputId {
+    self.outputs.push(Output::Digital(Box::new(pin)));
+    OutputId(self.outputs.len() - 1)
+  }
+
+  /// Adds a PWM output, e.g. an exported `PWM` with its period set.
+  pub fn add_pwm<P: PwmOutput + Send + 'static>(&mut self, pwm: P) -> OutputId {
+    self.outputs.push(Output::Pwm(Box::new(pwm)));
+    OutputId(self.outputs.len() - 1)
+  }
+
+  /// Makes `output` do `action` every day at `time`.
+  ///
+  /// On days without a sunrise or sunset, e.g. in polar summer, the events
+  /// relative to them don't happen.
+  ///
+  /// # Errors
+  ///
+  /// Fails if there's no such output, the action doesn't apply to it, e.g.
+  /// a duty cycle for a digital output, the duty cycle isn't between 0 and
+  /// 100, or the time is relative to the sun and no location was set.
+  pub fn at(&mut self, output: OutputId, time: Time, action: Action) -> Result<()> {
+    match (self.outputs.get(output.0), action) {
+      (None, _) => bail!(format!("The scheduler has no output {:?}", output)),
+      (Some(&Output::Digital(_)), Action::Duty(_)) => {
+        bail!(format!("{:?} doesn't apply to a digital output", action))
+      }
+      (_, Action::Duty(percentage)) if !(0.0..=100.0).contains(&percentage) => {
+        bail!(format!("PWM duty cycle {}% isn't between 0 and 100", percentage))
+      }
+      _ => {}
+    }
+    match time {
+      Time::Clock(minutes) if minutes >= 24 * 60 => {
+        bail!(format!("{} minutes after midnight isn't a time of day", minutes))
+      }
+      Time::Sunrise(_) | Time::Sunset(_) if self.location.is_none() => {
+        bail!("Times relative to the sun need a location")
+      }
+      _ => {}
+    }
+    self.events.push(Event {
+                       output,
+                       time,
+                       action,
+                     });
+    Ok(())
+  }
+
+  /// Applies the events that became due since the last tick.
+  ///
+  /// # Errors
+  ///
+  /// Fails if an output can't be set; the other due events are still
+  /// applied.
+  pub fn tick(&mut self) -> Result<()> {
+    self.tick_at(SystemTime::now())
+  }
+
+  /// Applies the events that became due since the last tick, as if it was
+  /// `now`, e.g. to test a schedule.
+  ///
+  /// # Errors
+  ///
+  /// Fails if an output can't be set; the other due events are still
+  /// applied.
+  pub fn tick_at(&mut self, now: SystemTime) -> Result<()> {
+    let now = match now.duration_since(UNIX_EPOCH) {
+      Ok(since_epoch) => since_epoch.as_secs() as i64,
+      Err(_) => bail!("The time is before 1970"),
+    };
+    // Yesterday's events are needed for the first tick after midnight, and
+    // to find the latest event on the first tick.
+    let today = Day::of(now);
+    let mut due: Vec<(i64, Event)> = Vec::new();
+    for day in &[today.previous(), today] {
+      for event in &self.events {
+        if let Some(time) = self.occurrence(event.time, day) {
+          if time <= now && self.last_tick.is_none_or(|last| time > last) {
+            due.push((time, *event));
+          }
+        }
+      }
+    }
+    due.sort_by_key(|&(time, _)| time);
+    if self.last_tick.is_none() {
+      // Only the latest event of each output counts.
+      let mut latest: Vec<(i64, Event)> = Vec::new();
+      for (time, event) in due.into_iter().rev() {
+        if !latest.iter().any(|&(_, e)| e.output == event.output) {
+          latest.push((time, event));
+        }
+      }
+      latest.reverse();
+      due = latest;
+    }
+    self.last_tick = Some(now);
+
+    let mut first_error = None;
+    for (_, event) in due {
+      if let Err(e) = self.outputs[event.output.0].apply(event.action) {
+        first_error = first_error.or(Some(e));
+      }
+    }
+    match first_error {
+      Some(e) => Err(e).chain_err(|| "Failed to apply a scheduled event"),
+      None => Ok(()),
+    }
+  }
+
+  /// Returns when `time` happens on `day` in seconds since the epoch, if it
+  /// does.
+  ///
+  /// Offsets may move events relative to the sun into the day before or
+  /// after, e.g. two hours after a late sunset.
+  fn occurrence(&self, time: Time, day: &Day) -> Option<i64> {
+    let sun = |offset: i32, sunset: bool| -> Option<i64> {
+      let (latitude, longitude) = self.location?;
+      let (rise, set) = sunrise_sunset(day.day_of_year, latitude, longitude)?;
+      let utc_minutes = if sunset { set } else { rise };
+      let utc_midnight = day.midnight + day.utc_offset;
+      Some(utc_midnight + (utc_minutes * 60.0) as i64 + i64::from(offset) * 60)
+    };
+    match time {
+      Time::Clock(minutes) => Some(day.midnight + i64::from(minutes) * 60),
+      Time::Sunrise(offset) => sun(offset, false),
+      Time::Sunset(offset) => sun(offset, true),
+    }
+  }
+}
+
+/// Computes sunrise and sunset on day `day_of_year` (0-365) at `latitude`
+/// degrees north and `longitude` degrees east, in minutes after midnight
+/// UTC, with NOAA's approximation.
+///
+/// Returns `None` on days where the sun doesn't rise or set.
+///
+/// # Examples
+///
+/// ```
+/// use libbeaglebone::scheduler::sunrise_sunset;
+///
+/// // Around the March equinox on the equator at Greenwich, the day is twelve
+/// // hours and a few minutes long.
+/// let (rise, set) = sunrise_sunset(79, 0.0, 0.0).unwrap();
+/// assert!((360.0..375.0).contains(&rise));
+/// assert!((1085.0..1100.0).contains(&set));
+///
+/// // Midsummer near the North Pole.
+/// assert_eq!(sunrise_sunset(171, 80.0, 0.0), None);
+/// ```
+pub fn sunrise_sunset(day_of_year: u32, latitude: f64, longitude: f64) -> Option<(f64, f64)> {
+  // The fractional year at noon, in radians.
+  let g = 2.0 * PI / 365.0 * f64::from(day_of_year);
+  let equation_of_time = 229.18 *
+                         (0.000_075 + 0.001_868 * g.cos() - 0.032_077 * g.sin() -
+                          0.014_615 * (2.0 * g).cos() - 0.040_849 * (2.0 * g).sin());
+  let declination = 0.006_918 - 0.399_912 * g.cos() + 0.070_257 * g.sin() -
+                    0.006_758 * (2.0 * g).cos() + 0.000_907 * (2.0 * g).sin() -
+                    0.002_697 * (3.0 * g).cos() + 0.001_48 * (3.0 * g).sin();
+  let latitude = latitude.to_radians();
+  // The sun's center 0.833 degrees below the horizon, for its radius and
+  // the refraction of the atmosphere.
+  let cos_hour_angle = 90.833_f64.to_radians().cos() / (latitude.cos() * declination.cos()) -
+                       latitude.tan() * declination.tan();
+  if !(-1.0..=1.0).contains(&cos_hour_angle) {
+    return None;
+  }
+  let hour_angle = cos_hour_angle.acos().to_degrees();
+  Some((720.0 - 4.0 * (longitude + hour_angle) - equation_of_time,
+        720.0 - 4.0 * (longitude - hour_angle) - equation_of_time))
+}
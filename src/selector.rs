@@ -0,0 +1,344 @@
+//! The selector module.
+//!
+//! Rotary selector switches pick one of several positions, e.g. the mode of
+//! a machine or the address of a board.
+//! They are read either through a group of GPIOs, with one pin per position
+//! or a binary or Gray code, or through a single ADC input on a resistor
+//! ladder, giving each position its own voltage.
+//! A `Selector` decodes either kind and reports when the position changes:
+//!
+//! ```no_run
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::selector::{Selector, SelectorEncoding};
+//! use std::time::Duration;
+//!
+//! // A 4-bit BCD switch, pulling its common to ground.
+//! let mut mode = Selector::pins(vec![GPIO_P8_7, GPIO_P8_8, GPIO_P8_9, GPIO_P8_10],
+//!                               PinState::Low,
+//!                               SelectorEncoding::Binary)
+//!   .unwrap();
+//! println!("Starting in mode {:?}", mode.position());
+//!
+//! // Or a 5-position switch on a ladder, read on AIN0.
+//! let ladder = Selector::adc_ladder(ADC::new(AIN_0, 0.0), vec![0, 1000, 2000, 3000, 4000]).unwrap();
+//! println!("Speed {:?} of {}", ladder.position(), ladder.positions());
+//!
+//! loop {
+//!   if let Some(change) = mode.wait(Duration::from_secs(1)).unwrap() {
+//!     println!("Mode {:?} -> {}", change.from, change.to);
+//!   }
+//! }
+//! ```
+//!
+//! While the switch turns, its contacts break before they make and bounce,
+//! so it reads no position, two at once or a code in between for a moment.
+//! Readings that aren't a valid position are ignored, and a new position
+//! is only reported after it was read in several samples in a row.
+
+use adc::ADC;
+use clock::{self, Clock};
+use enums::DeviceState;
+use errors::*;
+use gpio::{GPIO, PinDirection, PinState};
+use pins::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How the pins of a selector encode its position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelectorEncoding {
+  /// Pin `i` is active in position `i`, and no other pin is.
+  OneHot,
+  /// The active pins are the bits of the position, pin 0 the lowest.
+  Binary,
+  /// The active pins are the bits of the position's Gray code, pin 0 the
+  /// lowest, so only one pin changes between neighbouring positions.
+  Gray,
+}
+
+impl SelectorEncoding {
+  /// Returns the position of a switch whose pins read `active`, i.e.
+  /// whether each pin is active, or `None` if it's not a valid position.
+  ///
+  /// ```
+  /// use libbeaglebone::selector::SelectorEncoding;
+  ///
+  /// assert_eq!(SelectorEncoding::OneHot.decode(&[false, true, false]), Some(1));
+  /// assert_eq!(SelectorEncoding::OneHot.decode(&[false, true, true]), None);
+  /// assert_eq!(SelectorEncoding::Binary.decode(&[true, true, false]), Some(3));
+  /// assert_eq!(SelectorEncoding::Gray.decode(&[false, true, false]), Some(3));
+  /// ```
+  pub fn decode(self, active: &[bool]) -> Option<usize> {
+    let code = active.iter().rev().fold(0, |code, &bit| code << 1 | usize::from(bit));
+    match self {
+      SelectorEncoding::OneHot => {
+        if code.count_ones() == 1 {
+          Some(code.trailing_zeros() as usize)
+        } else {
+          None
+        }
+      }
+      SelectorEncoding::Binary => Some(code),
+      SelectorEncoding::Gray => {
+        let mut position = code;
+        let mut shifted = code >> 1;
+        while shifted != 0 {
+          position ^= shifted;
+          shifted >>= 1;
+        }
+        Some(position)
+      }
+    }
+  }
+
+  /// Returns the number of positions `pins` pins can encode.
+  fn positions(self, pins: usize) -> usize {
+    match self {
+      SelectorEncoding::OneHot => pins,
+      SelectorEncoding::Binary | SelectorEncoding::Gray => 1 << pins,
+    }
+  }
+}
+
+/// A change of a selector's position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PositionChange {
+  /// The position before, or `None` if the switch never read a valid one.
+  pub from: Option<usize>,
+  /// The new position.
+  pub to: usize,
+}
+
+#[derive(Debug)]
+enum Source {
+  Pins {
+    pins: Vec<GPIO>,
+    active: PinState,
+    encoding: SelectorEncoding,
+  },
+  Ladder {
+    adc: ADC,
+    levels: Vec<u32>,
+    tolerance: u32,
+  },
+}
+
+/// A multi-position selector switch.
+#[derive(Debug)]
+pub struct Selector {
+  source: Source,
+  clock: Arc<dyn Clock>,
+  debounce: u32,
+  poll_interval: Duration,
+  position: Option<usize>,
+  // The position seen in the last samples but not reported yet, and how
+  // often.
+  candidate: Option<(usize, u32)>,
+}
+
+impl Selector {
+  /// Reads a selector on the GPIOs `pins`, each reading `active` while its
+  /// contact is closed, debouncing over 3 samples.
+  ///
+  /// The pins are exported and made inputs.
+  /// The position the switch is in to begin with isn't reported, see
+  /// `position()`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if there are fewer than 2 pins, or more than 8 for a binary or
+  /// Gray code, or if a pin can't be configured or read.
+  pub fn pins(pins: Vec<Pin>, active: PinState, encoding: SelectorEncoding) -> Result<Selector> {
+    let max = match encoding {
+      SelectorEncoding::OneHot => usize::BITS as usize,
+      SelectorEncoding::Binary | SelectorEncoding::Gray => 8,
+    };
+    if !(2..=max).contains(&pins.len()) {
+      bail!(format!("A selector with this encoding has 2 to {} pins, not {}", max, pins.len()));
+    }
+    let pins = pins.into_iter()
+                   .map(|pin| {
+                     let input = GPIO::new(pin);
+                     input.set_export(DeviceState::Exported)?;
+                     input.set_direction(PinDirection::In)?;
+                     Ok(input)
+                   })
+                   .collect::<Result<Vec<_>>>()?;
+    Selector::start(Source::Pins {
+      pins,
+      active,
+      encoding,
+    })
+  }
+
+  /// Reads a selector on a resistor ladder, whose position `i` reads about
+  /// the raw value `levels[i]` on `adc`, debouncing over 3 samples.
+  ///
+  /// A reading counts as a position if it's within a quarter of the
+  /// smallest distance between two levels, see `set_tolerance()`.
+  /// The position the switch is in to begin with isn't reported, see
+  /// `position()`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if there are fewer than 2 levels or two are the same, or if the
+  /// ADC can't be read.
+  pub fn adc_ladder(adc: ADC, levels: Vec<u32>) -> Result<Selector> {
+    if levels.len() < 2 {
+      bail!(format!("A selector has at least 2 levels, not {}", levels.len()));
+    }
+    let mut sorted = levels.clone();
+    sorted.sort_unstable();
+    let gap = sorted.windows(2).map(|pair| pair[1] - pair[0]).min().unwrap_or(0);
+    if gap == 0 {
+      bail!("The levels of a selector have to differ");
+    }
+    Selector::start(Source::Ladder {
+      adc,
+      levels,
+      tolerance: gap / 4,
+    })
+  }
+
+  fn start(source: Source) -> Result<Selector> {
+    let mut selector = Selector {
+      source,
+      clock: clock::system(),
+      debounce: 3,
+      poll_interval: Duration::from_millis(10),
+      position: None,
+      candidate: None,
+    };
+    selector.position = selector.sample()?;
+    Ok(selector)
+  }
+
+  /// Sets how far from its level a ladder reading may be to count as a
+  /// position.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the selector isn't on a ladder, or if the tolerance is so big
+  /// that a reading could count as two positions.
+  pub fn set_tolerance(&mut self, counts: u32) -> Result<()> {
+    match self.source {
+      Source::Ladder { ref levels,
+                       ref mut tolerance,
+                       .. } => {
+        let overlapping = levels.iter()
+                                .enumerate()
+                                .any(|(i, a)| levels[i + 1..].iter().any(|b| a.abs_diff(*b) <= counts.saturating_mul(2)));
+        if overlapping {
+          bail!(format!("A tolerance of {} makes the levels of the selector overlap", counts));
+        }
+        *tolerance = counts;
+        Ok(())
+      }
+      Source::Pins { .. } => bail!("Only selectors on a resistor ladder have a tolerance"),
+    }
+  }
+
+  /// Sets how many samples in a row have to read a new position before it
+  /// is reported, 3 by default.
+  pub fn set_debounce(&mut self, samples: u32) {
+    self.debounce = samples.max(1);
+  }
+
+  /// Sets the time between samples in `wait()`, 10ms by default.
+  pub fn set_poll_interval(&mut self, poll_interval: Duration) {
+    self.poll_interval = poll_interval;
+  }
+
+  /// Makes the selector take its time from `clock`, e.g. a `TestClock`.
+  pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+    self.clock = clock;
+  }
+
+  /// Returns the number of positions.
+  pub fn positions(&self) -> usize {
+    match self.source {
+      Source::Pins { ref pins, encoding, .. } => encoding.positions(pins.len()),
+      Source::Ladder { ref levels, .. } => levels.len(),
+    }
+  }
+
+  /// Returns the position reported last, or the one the switch was in to
+  /// begin with; `None` if it never read a valid position.
+  pub fn position(&self) -> Option<usize> {
+    self.position
+  }
+
+  /// Reads the position as is, without debouncing, or `None` if the reading
+  /// isn't a valid position.
+  ///
+  /// # Errors
+  ///
+  /// Fails if a pin or the ADC can't be read.
+  pub fn sample(&self) -> Result<Option<usize>> {
+    match self.source {
+      Source::Pins { ref pins, active, encoding } => {
+        let levels = pins.iter().map(|pin| Ok(pin.read()? == active)).collect::<Result<Vec<_>>>()?;
+        Ok(encoding.decode(&levels))
+      }
+      Source::Ladder { ref adc, ref levels, tolerance } => {
+        let value = adc.read()?;
+        Ok(levels.iter().position(|level| level.abs_diff(value) <= tolerance))
+      }
+    }
+  }
+
+  /// Takes one sample, and returns the change if it completes the debounce
+  /// of a new position.
+  ///
+  /// # Errors
+  ///
+  /// Fails if a pin or the ADC can't be read.
+  pub fn poll(&mut self) -> Result<Option<PositionChange>> {
+    let position = match self.sample()? {
+      Some(position) if Some(position) != self.position => position,
+      // Unchanged, or in between positions.
+      _ => {
+        self.candidate = None;
+        return Ok(None);
+      }
+    };
+    let seen = match self.candidate {
+      Some((candidate, seen)) if candidate == position => seen + 1,
+      _ => 1,
+    };
+    if seen < self.debounce {
+      self.candidate = Some((position, seen));
+      return Ok(None);
+    }
+    let change = PositionChange {
+      from: self.position,
+      to: position,
+    };
+    self.position = Some(position);
+    self.candidate = None;
+    Ok(Some(change))
+  }
+
+  /// Samples the switch until its position changes, or until `timeout` has
+  /// passed.
+  ///
+  /// Returns the change, or `None` if the timeout expired.
+  ///
+  /// # Errors
+  ///
+  /// Fails if a pin or the ADC can't be read.
+  pub fn wait(&mut self, timeout: Duration) -> Result<Option<PositionChange>> {
+    let deadline = self.clock.now() + timeout;
+    loop {
+      if let Some(change) = self.poll()? {
+        return Ok(Some(change));
+      }
+      match deadline.checked_sub(self.clock.now()) {
+        Some(remaining) if remaining > Duration::from_secs(0) => {
+          self.clock.sleep(self.poll_interval.min(remaining))?
+        }
+        _ => return Ok(None),
+      }
+    }
+  }
+}
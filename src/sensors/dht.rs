@@ -0,0 +1,288 @@
+//! The DHT module.
+//!
+//! The DHT11 and DHT22 (AM2302) humidity and temperature sensors answer on
+//! a single data line: the host pulls it low to start a reading, and the
+//! sensor sends 40 bits as high pulses of about 27µs for a 0 and 70µs for a
+//! 1.
+//! That's too fast for sysfs, so a `Dht` samples the line through the GPIO
+//! registers, see the `fast_gpio` module, which needs root, and raises the
+//! thread to a real-time priority while it does:
+//!
+//! ```no_run
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::sensors::dht::{Dht, Model};
+//!
+//! let mut sensor = Dht::new(GPIO_P8_11, Model::Dht22).unwrap();
+//! let reading = sensor.read().unwrap();
+//! println!("{}°C, {}%", reading.temperature, reading.humidity);
+//! ```
+//!
+//! Even so, a reading is lost now and then when the kernel interrupts the
+//! thread in the middle of it; the checksum catches that, and `read()`
+//! retries.
+//! The data line needs a pull-up, 4.7k to 10k to 3.3V, unless the module has
+//! one on board.
+
+use enums::DeviceState;
+use errors::*;
+use fast_gpio::FastGPIO;
+use gpio::{GPIO, PinDirection, PinState, Pull};
+use nix::libc;
+use pins::Pin;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long the sensor may take for each level of its response.
+const LEVEL_TIMEOUT: Duration = Duration::from_micros(200);
+
+/// High pulses longer than this are 1 bits.
+const ONE_THRESHOLD: Duration = Duration::from_micros(48);
+
+/// The kind of sensor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Model {
+  /// The DHT11, reading whole degrees and percents.
+  Dht11,
+  /// The DHT22 or AM2302, reading tenths.
+  Dht22,
+}
+
+impl Model {
+  /// Returns how long the host pulls the line low to start a reading.
+  fn start_time(self) -> Duration {
+    match self {
+      Model::Dht11 => Duration::from_millis(20),
+      Model::Dht22 => Duration::from_millis(2),
+    }
+  }
+
+  /// Returns how long the sensor needs between readings.
+  pub fn min_interval(self) -> Duration {
+    match self {
+      Model::Dht11 => Duration::from_secs(1),
+      Model::Dht22 => Duration::from_secs(2),
+    }
+  }
+
+  /// Decodes and checks the 5 bytes a sensor sent.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use libbeaglebone::sensors::dht::Model;
+  ///
+  /// // 65.2% and -10.1°C.
+  /// let reading = Model::Dht22.decode([0x02, 0x8C, 0x80, 0x65, 0x73]).unwrap();
+  /// assert_eq!((reading.humidity, reading.temperature), (65.2, -10.1));
+  /// assert!(Model::Dht22.decode([0x02, 0x8C, 0x80, 0x65, 0x74]).is_err());
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if the checksum doesn't match.
+  pub fn decode(self, data: [u8; 5]) -> Result<Reading> {
+    let sum = data[..4].iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+    if sum != data[4] {
+      bail!(format!("DHT checksum {:#04x} doesn't match the data {:02x?}", data[4], &data[..4]));
+    }
+    let (humidity, temperature) = match self {
+      Model::Dht11 => {
+        let temperature = f32::from(data[2]) + f32::from(data[3] & 0x7F) / 10.0;
+        (f32::from(data[0]) + f32::from(data[1]) / 10.0,
+         if data[3] & 0x80 != 0 { -temperature } else { temperature })
+      }
+      Model::Dht22 => {
+        let temperature = f32::from(u16::from(data[2] & 0x7F) << 8 | u16::from(data[3])) / 10.0;
+        (f32::from(u16::from(data[0]) << 8 | u16::from(data[1])) / 10.0,
+         if data[2] & 0x80 != 0 { -temperature } else { temperature })
+      }
+    };
+    Ok(Reading {
+      temperature,
+      humidity,
+    })
+  }
+}
+
+/// A reading of a DHT sensor.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Reading {
+  /// The temperature in °C.
+  pub temperature: f32,
+  /// The relative humidity in percent.
+  pub humidity: f32,
+}
+
+/// A DHT11 or DHT22 sensor on a GPIO.
+#[derive(Debug)]
+pub struct Dht {
+  gpio: GPIO,
+  fast: FastGPIO,
+  model: Model,
+  retries: u32,
+  realtime: bool,
+  // When the sensor was last started.
+  last_start: Option<Instant>,
+}
+
+impl Dht {
+  /// Talks to a sensor of `model` on the GPIO `pin`, which is exported and
+  /// made an input, with its internal pull-up if it has one.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the pin can't be configured, or if its GPIO registers can't be
+  /// mapped, e.g. because the process isn't running as root.
+  pub fn new(pin: Pin, model: Model) -> Result<Dht> {
+    let gpio = GPIO::new(pin);
+    gpio.set_export(DeviceState::Exported)?;
+    // An external pull-up does without.
+    let _ = gpio.set_pull(Pull::Up);
+    gpio.set_direction(PinDirection::In)?;
+    let fast = FastGPIO::from_gpio(&gpio)?;
+    Ok(Dht {
+      gpio,
+      fast,
+      model,
+      retries: 3,
+      realtime: true,
+      last_start: None,
+    })
+  }
+
+  /// Returns the kind of sensor.
+  pub fn model(&self) -> Model {
+    self.model
+  }
+
+  /// Sets how often a failed reading is retried, 3 times by default.
+  pub fn set_retries(&mut self, retries: u32) {
+    self.retries = retries;
+  }
+
+  /// Sets whether the thread runs at a real-time priority while it samples
+  /// the sensor, which it does by default, if it's allowed to.
+  pub fn set_realtime(&mut self, realtime: bool) {
+    self.realtime = realtime;
+  }
+
+  /// Reads the sensor, retrying failed readings.
+  ///
+  /// Blocks until the sensor's minimum interval since the last reading has
+  /// passed, for each attempt: up to 2s for a DHT22.
+  ///
+  /// # Errors
+  ///
+  /// Fails if no attempt got a reading with a matching checksum.
+  pub fn read(&mut self) -> Result<Reading> {
+    let mut attempt = 0;
+    loop {
+      match self.read_once() {
+        Ok(reading) => return Ok(reading),
+        Err(e) if attempt >= self.retries => {
+          return Err(Error::with_chain(e,
+                                       format!("Failed to read the {:?} on GPIO pin #{} in {} attempts",
+                                               self.model,
+                                               self.gpio.pin_num(),
+                                               attempt + 1)));
+        }
+        Err(_) => attempt += 1,
+      }
+    }
+  }
+
+  /// Reads the sensor once.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the sensor doesn't respond in time, or if the checksum
+  /// doesn't match.
+  pub fn read_once(&mut self) -> Result<Reading> {
+    if let Some(last_start) = self.last_start {
+      let next = last_start + self.model.min_interval();
+      let now = Instant::now();
+      if next > now {
+        thread::sleep(next - now);
+      }
+    }
+    self.last_start = Some(Instant::now());
+
+    // Pull the line low, with the level set before the driver is enabled.
+    self.fast.fast_write(PinState::Low);
+    self.fast.set_direction(PinDirection::Out);
+    thread::sleep(self.model.start_time());
+    let data = {
+      let _priority = if self.realtime { Realtime::enter() } else { None };
+      self.fast.set_direction(PinDirection::In);
+      self.receive()
+    };
+    self.model.decode(data?)
+  }
+
+  /// Unwraps the GPIO.
+  pub fn into_inner(self) -> GPIO {
+    self.gpio
+  }
+
+  /// Receives the response and the 40 bits after the start signal.
+  fn receive(&self) -> Result<[u8; 5]> {
+    // The pull-up takes over, then the sensor responds with 80µs low and
+    // 80µs high.
+    let _ = self.wait_while(PinState::High).chain_err(|| "The DHT doesn't respond")?;
+    let _ = self.wait_while(PinState::Low).chain_err(|| "The DHT doesn't respond")?;
+    let _ = self.wait_while(PinState::High).chain_err(|| "The DHT doesn't respond")?;
+    let mut data = [0; 5];
+    for bit in 0..40 {
+      let _ = self.wait_while(PinState::Low).chain_err(|| format!("The DHT stopped at bit {}", bit))?;
+      let high = self.wait_while(PinState::High).chain_err(|| format!("The DHT stopped at bit {}", bit))?;
+      if high > ONE_THRESHOLD {
+        data[bit / 8] |= 0x80 >> (bit % 8);
+      }
+    }
+    Ok(data)
+  }
+
+  /// Waits for the line to leave `level`, and returns how long it took.
+  fn wait_while(&self, level: PinState) -> Result<Duration> {
+    let start = Instant::now();
+    while self.fast.fast_read() == level {
+      if start.elapsed() > LEVEL_TIMEOUT {
+        bail!(format!("The line stayed {:?} for over {:?}", level, LEVEL_TIMEOUT));
+      }
+    }
+    Ok(start.elapsed())
+  }
+}
+
+/// Runs the thread at the highest FIFO priority until dropped, restoring
+/// its scheduling policy then.
+struct Realtime {
+  policy: libc::c_int,
+  param: libc::sched_param,
+}
+
+impl Realtime {
+  /// Returns `None` if the thread isn't allowed to.
+  fn enter() -> Option<Realtime> {
+    unsafe {
+      let mut param: libc::sched_param = ::std::mem::zeroed();
+      let policy = libc::sched_getscheduler(0);
+      if policy < 0 || libc::sched_getparam(0, &mut param) < 0 {
+        return None;
+      }
+      let realtime = libc::sched_param { sched_priority: libc::sched_get_priority_max(libc::SCHED_FIFO) };
+      if libc::sched_setscheduler(0, libc::SCHED_FIFO, &realtime) < 0 {
+        return None;
+      }
+      Some(Realtime { policy, param })
+    }
+  }
+}
+
+impl Drop for Realtime {
+  fn drop(&mut self) {
+    unsafe {
+      let _ = libc::sched_setscheduler(0, self.policy, &self.param);
+    }
+  }
+}
@@ -0,0 +1,6 @@
+//! The sensors module.
+//!
+//! Drivers for sensors that talk their own protocol on plain GPIOs, rather
+//! than on one of the buses.
+
+pub mod dht;
@@ -0,0 +1,225 @@
+//! The servo module.
+//!
+//! Hobby servos are positioned by the width of a pulse repeated at a fixed
+//! refresh rate, typically a pulse of 1ms to 2ms every 20ms (50Hz) for a
+//! range of 180 degrees.
+//! `Servo` does the conversion from angles to pulses, with the pulse range,
+//! angle range and refresh rate adjustable for servos that don't follow the
+//! typical values:
+//!
+//! ```no_run
+//! use libbeaglebone::servo::Servo;
+//! use std::time::Duration;
+//!
+//! // A servo on EHRPWM1A, i.e. pin P9.14 after `config-pin P9.14 pwm`.
+//! let mut servo = Servo::new(3, 0).unwrap();
+//!
+//! // This one turns further with 0.5ms to 2.5ms pulses.
+//! servo.set_pulse_range(Duration::from_micros(500), Duration::from_micros(2500)).unwrap();
+//!
+//! servo.set_angle(90.0).unwrap();
+//! ```
+//!
+//! Servos are usually driven by the on-chip PWMs, but any `PwmOutput` works,
+//! e.g. a channel of a PWM controller chip.
+
+use enums::DeviceState;
+use errors::*;
+use hal::PwmOutput;
+use pwm::{PWM, PWMState};
+use std::time::Duration;
+
+/// The refresh rate of most analog servos.
+pub const DEFAULT_REFRESH_RATE_HZ: f32 = 50.0;
+
+/// A hobby servo driven by a PWM output, by default an on-chip PWM.
+#[derive(Debug)]
+pub struct Servo<P: PwmOutput = PWM> {
+  pwm: P,
+  period: Duration,
+  min_pulse: Duration,
+  max_pulse: Duration,
+  angle_range: f32,
+  pulse: Option<Duration>,
+}
+
+impl Servo<PWM> {
+  /// Creates a servo on PWM `pwm_num` of chip `pwm_chip_num`, with 1ms to
+  /// 2ms pulses for 0 to 180 degrees at 50Hz.
+  ///
+  /// The PWM is exported and configured, but stays disabled until the first
+  /// angle is set, so the servo doesn't jump to an arbitrary position.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the PWM can't be exported or configured.
+  pub fn new(pwm_chip_num: u8, pwm_num: u8) -> Result<Servo> {
+    let mut pwm = PWM::new(pwm_chip_num, pwm_num);
+    pwm.set_export(DeviceState::Exported)?;
+    pwm.set_state(PWMState::Disabled)?;
+    Servo::from_pwm(pwm)
+  }
+}
+
+impl<P: PwmOutput> Servo<P> {
+  /// Creates a servo on an already configured PWM output, with 1ms to 2ms
+  /// pulses for 0 to 180 degrees at 50Hz.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the period of the output can't be set.
+  pub fn from_pwm(mut pwm: P) -> Result<Servo<P>> {
+    let period = refresh_period(DEFAULT_REFRESH_RATE_HZ)?;
+    pwm.set_period_ns(period.as_nanos() as u32)?;
+    Ok(Servo {
+      pwm,
+      period,
+      min_pulse: Duration::from_millis(1),
+      max_pulse: Duration::from_millis(2),
+      angle_range: 180.0,
+      pulse: None,
+    })
+  }
+
+  /// Sets the pulse widths of the ends of the servo's range.
+  ///
+  /// Check the servo's data sheet; pulses beyond its range can drive it into
+  /// its end stops and damage it.
+  ///
+  /// # Errors
+  ///
+  /// Fails if `min` isn't shorter than `max` or `max` doesn't fit into the
+  /// refresh period.
+  pub fn set_pulse_range(&mut self, min: Duration, max: Duration) -> Result<()> {
+    if min >= max {
+      bail!(format!("Servo pulse range {:?} to {:?} is empty", min, max));
+    }
+    if max >= self.period {
+      bail!(format!("Servo pulse {:?} doesn't fit into the period of {:?}", max, self.period));
+    }
+    self.min_pulse = min;
+    self.max_pulse = max;
+    Ok(())
+  }
+
+  /// Sets the angle in degrees between the ends of the servo's range, 180 by
+  /// default.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the range isn't positive.
+  pub fn set_angle_range(&mut self, degrees: f32) -> Result<()> {
+    if degrees.is_nan() || degrees <= 0.0 {
+      bail!(format!("Servo angle range {} isn't positive", degrees));
+    }
+    self.angle_range = degrees;
+    Ok(())
+  }
+
+  /// Sets how often the pulse is repeated, 50Hz by default.
+  ///
+  /// Digital servos often accept up to 333Hz, which makes them react faster.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the longest pulse doesn't fit into the period, or if the
+  /// period can't be set.
+  pub fn set_refresh_rate(&mut self, hz: f32) -> Result<()> {
+    let period = refresh_period(hz)?;
+    if self.max_pulse >= period {
+      bail!(format!("Servo pulse {:?} doesn't fit into the period of {:?}", self.max_pulse, period));
+    }
+    self.pwm.set_period_ns(period.as_nanos() as u32)?;
+    self.period = period;
+    // The duty cycle is relative to the period, so the pulse has to be set
+    // again.
+    match self.pulse {
+      Some(pulse) => self.write_pulse(pulse),
+      None => Ok(()),
+    }
+  }
+
+  /// Turns the servo to `degrees`, from 0 to the angle range.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the angle is out of range or the PWM can't be written.
+  pub fn set_angle(&mut self, degrees: f32) -> Result<()> {
+    let pulse = self.pulse_width(degrees)?;
+    self.set_pulse_width(pulse)
+  }
+
+  /// Returns the angle in degrees the servo was turned to last, or `None`
+  /// if it's disabled.
+  pub fn angle(&self) -> Option<f32> {
+    self.pulse.map(|pulse| {
+      let span = (self.max_pulse - self.min_pulse).as_secs_f32();
+      (pulse.as_secs_f32() - self.min_pulse.as_secs_f32()) / span * self.angle_range
+    })
+  }
+
+  /// Returns the pulse width for `degrees`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the angle is outside of 0 to the angle range.
+  pub fn pulse_width(&self, degrees: f32) -> Result<Duration> {
+    if !(0.0..=self.angle_range).contains(&degrees) {
+      bail!(format!("Servo angle {} is outside of 0 to {} degrees", degrees, self.angle_range));
+    }
+    let span = self.max_pulse - self.min_pulse;
+    Ok(self.min_pulse + span.mul_f32(degrees / self.angle_range))
+  }
+
+  /// Drives the servo with pulses `width` long, enabling the PWM if needed.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the pulse is outside of the pulse range or the PWM can't be
+  /// written.
+  pub fn set_pulse_width(&mut self, width: Duration) -> Result<()> {
+    if width < self.min_pulse || width > self.max_pulse {
+      bail!(format!("Servo pulse {:?} is outside of {:?} to {:?}", width, self.min_pulse, self.max_pulse));
+    }
+    self.write_pulse(width)?;
+    if self.pulse.is_none() {
+      self.pwm.set_enabled(true)?;
+    }
+    self.pulse = Some(width);
+    Ok(())
+  }
+
+  /// Stops the pulses, which makes most servos stop holding their position.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the PWM can't be disabled.
+  pub fn disable(&mut self) -> Result<()> {
+    self.pwm.set_enabled(false)?;
+    self.pulse = None;
+    Ok(())
+  }
+
+  /// Returns the PWM output.
+  pub fn get_ref(&self) -> &P {
+    &self.pwm
+  }
+
+  /// Unwraps the PWM output.
+  pub fn into_inner(self) -> P {
+    self.pwm
+  }
+
+  fn write_pulse(&mut self, width: Duration) -> Result<()> {
+    let percentage = width.as_secs_f32() / self.period.as_secs_f32() * 100.0;
+    self.pwm.set_duty_cycle_percent(percentage)
+  }
+}
+
+/// Returns the period of a refresh rate.
+fn refresh_period(hz: f32) -> Result<Duration> {
+  if !(hz > 0.25 && hz <= 10_000.0) {
+    bail!(format!("Servo refresh rate {}Hz is out of range", hz));
+  }
+  Ok(Duration::from_secs_f32(1.0 / hz))
+}
@@ -0,0 +1,172 @@
+//! The servo bus module.
+//!
+//! A robot's servos are often spread over several kinds of PWM outputs: the
+//! on-chip PWMs run out quickly, so the rest hang off a `PCA9685` PWM
+//! controller or a `SoftPWM`.
+//! A `ServoBus` numbers them all the same way, so the code moving the robot
+//! doesn't care where each servo is wired:
+//!
+//! ```no_run
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::pca9685::PCA9685;
+//! use libbeaglebone::servo_bus::ServoBus;
+//! use libbeaglebone::soft_pwm::SoftPWM;
+//!
+//! let mut hip = PWM::new(3, 0);
+//! hip.set_export(DeviceState::Exported).unwrap();
+//! hip.set_state(PWMState::Disabled).unwrap();
+//!
+//! let controller = PCA9685::new(2, 0x40).unwrap();
+//! controller.set_period_ns(20_000_000).unwrap();
+//!
+//! let mut legs = ServoBus::new();
+//! let hip = legs.add(hip).unwrap();
+//! let knee = legs.add(controller.channel(0).unwrap()).unwrap();
+//! let ankle = legs.add(SoftPWM::new(GPIO_P8_11).unwrap()).unwrap();
+//!
+//! legs.set_angles(&[(hip, 45.0), (knee, 120.0), (ankle, 90.0)]).unwrap();
+//! ```
+//!
+//! Servos driven by the PRUs aren't supported, there's no PRU PWM output
+//! yet; any other type implementing `PwmOutput` can be added.
+//!
+//! Each servo can still be configured on its own, e.g. its pulse range with
+//! `servo_mut()`.
+
+use errors::*;
+use hal::PwmOutput;
+use servo::Servo;
+use std::fmt;
+
+/// A servo on a bus, driven by any kind of PWM output.
+pub type BusServo = Servo<Box<dyn PwmOutput + Send>>;
+
+/// Servos on different kinds of PWM outputs, numbered in the order they
+/// were added.
+#[derive(Default)]
+pub struct ServoBus {
+  servos: Vec<BusServo>,
+}
+
+impl fmt::Debug for ServoBus {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let angles: Vec<Option<f32>> = self.servos.iter().map(Servo::angle).collect();
+    f.debug_struct("ServoBus").field("angles", &angles).finish()
+  }
+}
+
+impl ServoBus {
+  /// Creates a bus without servos.
+  pub fn new() -> ServoBus {
+    ServoBus::default()
+  }
+
+  /// Adds a servo on `pwm`, a disabled PWM output, with the defaults of
+  /// `Servo::from_pwm()`, and returns its number.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the period of the output can't be set.
+  pub fn add<P: PwmOutput + Send + 'static>(&mut self, pwm: P) -> Result<usize> {
+    let pwm: Box<dyn PwmOutput + Send> = Box::new(pwm);
+    self.servos.push(Servo::from_pwm(pwm)?);
+    Ok(self.servos.len() - 1)
+  }
+
+  /// Returns the number of servos.
+  pub fn len(&self) -> usize {
+    self.servos.len()
+  }
+
+  /// Returns whether the bus has no servos.
+  pub fn is_empty(&self) -> bool {
+    self.servos.is_empty()
+  }
+
+  /// Returns servo `index`.
+  pub fn servo(&self, index: usize) -> Option<&BusServo> {
+    self.servos.get(index)
+  }
+
+  /// Returns servo `index` to configure it, e.g. its pulse range.
+  pub fn servo_mut(&mut self, index: usize) -> Option<&mut BusServo> {
+    self.servos.get_mut(index)
+  }
+
+  /// Turns servo `index` to `degrees`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if there's no such servo, the angle is out of its range or its
+  /// output can't be written.
+  pub fn set_angle(&mut self, index: usize, degrees: f32) -> Result<()> {
+    self.get_mut(index)?
+        .set_angle(degrees)
+        .chain_err(|| format!("Failed to turn servo {}", index))
+  }
+
+  /// Turns several servos, given as number and angle, one after the other.
+  ///
+  /// # Errors
+  ///
+  /// Fails without turning any servo if one of their numbers or angles is
+  /// invalid, or on the first output that can't be written.
+  pub fn set_angles(&mut self, angles: &[(usize, f32)]) -> Result<()> {
+    for &(index, degrees) in angles {
+      let _ = self.get(index)?
+                  .pulse_width(degrees)
+                  .chain_err(|| format!("Can't turn servo {}", index))?;
+    }
+    for &(index, degrees) in angles {
+      self.set_angle(index, degrees)?;
+    }
+    Ok(())
+  }
+
+  /// Returns the angle servo `index` was turned to last, or `None` if it's
+  /// disabled or there's no such servo.
+  pub fn angle(&self, index: usize) -> Option<f32> {
+    self.servos.get(index).and_then(Servo::angle)
+  }
+
+  /// Stops the pulses of servo `index`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if there's no such servo or its output can't be disabled.
+  pub fn disable(&mut self, index: usize) -> Result<()> {
+    self.get_mut(index)?.disable()
+  }
+
+  /// Stops the pulses of all servos.
+  ///
+  /// # Errors
+  ///
+  /// Fails if an output can't be disabled, after trying all of them.
+  pub fn disable_all(&mut self) -> Result<()> {
+    let mut first_error = None;
+    for servo in &mut self.servos {
+      if let Err(e) = servo.disable() {
+        first_error = first_error.or(Some(e));
+      }
+    }
+    match first_error {
+      Some(e) => Err(e),
+      None => Ok(()),
+    }
+  }
+
+  fn get(&self, index: usize) -> Result<&BusServo> {
+    match self.servos.get(index) {
+      Some(servo) => Ok(servo),
+      None => bail!(format!("The servo bus has no servo {}", index)),
+    }
+  }
+
+  fn get_mut(&mut self, index: usize) -> Result<&mut BusServo> {
+    match self.servos.get_mut(index) {
+      Some(servo) => Ok(servo),
+      None => bail!(format!("The servo bus has no servo {}", index)),
+    }
+  }
+}
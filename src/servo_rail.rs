@@ -0,0 +1,264 @@
+//! The servo rail module.
+//!
+//! Servos are usually powered from their own rail, switched by a GPIO, as
+//! on the BeagleBone Blue: the rail is only powered once the servos'
+//! pulses are set up, and shut down when it sags under the load, before
+//! the servos start jittering and the regulator overheating.
+//! A `ServoRail` does this sequencing, and with an `EStop` shuts the rail
+//! down when the e-stop is triggered:
+//!
+//! ```no_run
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::estop::EStop;
+//! use libbeaglebone::power::ADCDivider;
+//! use libbeaglebone::servo::Servo;
+//! use libbeaglebone::servo_rail::ServoRail;
+//!
+//! let estop = EStop::new();
+//! let mut rail = ServoRail::guarded(GPIO_P8_11, false, &estop).unwrap();
+//! // The 6V rail through a 10k/2k divider, which may not sag below 5.5V.
+//! rail.set_sense(ADCDivider::new(AIN_1, 6.0), 5.5);
+//!
+//! // Set up the pulse first, so the servo doesn't jump when powered.
+//! let mut pan = Servo::new(1, 0).unwrap();
+//! pan.set_angle(90.0).unwrap();
+//! rail.power_up().unwrap();
+//!
+//! loop {
+//!   if rail.check().unwrap() {
+//!     panic!("The servo rail browned out at {:?}V", rail.voltage());
+//!   }
+//! }
+//! ```
+//!
+//! The rail is a digital output like any other, so `guarded()` simply
+//! registers its enable pin with the e-stop, which drives it off when
+//! triggered.
+
+use clock::{self, Clock};
+use errors::*;
+use estop::{EStop, GuardedPin};
+use gpio::{self, GPIO, PinState};
+use hal::DigitalPin;
+use pins::Pin;
+use power::BatterySource;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Whether a servo rail is powered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RailState {
+  /// Not powered.
+  Off,
+  /// Powered.
+  On,
+  /// Shut down because its voltage sagged; `power_up()` powers it again.
+  BrownedOut,
+}
+
+/// A servo power rail switched by a digital output, by default a GPIO.
+pub struct ServoRail<P: DigitalPin = GPIO> {
+  enable: P,
+  active_low: bool,
+  clock: Arc<dyn Clock>,
+  sense: Option<Box<dyn BatterySource + Send>>,
+  min_volts: f32,
+  settle: Duration,
+  debounce: u32,
+  // How many samples in a row were below the minimum.
+  low_samples: u32,
+  voltage: Option<f32>,
+  state: RailState,
+}
+
+impl<P: DigitalPin + fmt::Debug> fmt::Debug for ServoRail<P> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("ServoRail")
+     .field("enable", &self.enable)
+     .field("active_low", &self.active_low)
+     .field("sensed", &self.sense.is_some())
+     .field("min_volts", &self.min_volts)
+     .field("voltage", &self.voltage)
+     .field("state", &self.state)
+     .finish()
+  }
+}
+
+impl ServoRail<GPIO> {
+  /// Creates a rail switched by the GPIO `pin`, and keeps it off.
+  ///
+  /// The pin is exported and made an output driving the off level right
+  /// away.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the pin can't be configured.
+  pub fn new(pin: Pin, active_low: bool) -> Result<ServoRail> {
+    ServoRail::from_pin(gpio::output_driving(pin, level(false, active_low))?, active_low)
+  }
+
+  /// Creates a rail switched by the GPIO `pin` like `new()`, which `estop`
+  /// shuts down when triggered.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the pin can't be configured, or if the e-stop is latched and
+  /// the rail can't be shut down.
+  pub fn guarded(pin: Pin, active_low: bool, estop: &EStop) -> Result<ServoRail<GuardedPin<GPIO>>> {
+    let off = level(false, active_low);
+    let enable = estop.guard_digital(gpio::output_driving(pin, off)?, off)?;
+    ServoRail::from_pin(enable, active_low)
+  }
+}
+
+impl<P: DigitalPin> ServoRail<P> {
+  /// Creates a rail switched by a configured output, and turns it off.
+  ///
+  /// There's no voltage sensing until `set_sense()`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the output can't be driven.
+  pub fn from_pin(mut enable: P, active_low: bool) -> Result<ServoRail<P>> {
+    enable.set_state(level(false, active_low))?;
+    Ok(ServoRail {
+      enable,
+      active_low,
+      clock: clock::system(),
+      sense: None,
+      min_volts: 0.0,
+      settle: Duration::from_millis(50),
+      debounce: 3,
+      low_samples: 0,
+      voltage: None,
+      state: RailState::Off,
+    })
+  }
+
+  /// Measures the rail's voltage with `sense`, which has to stay at
+  /// `min_volts` or above while the rail is on.
+  pub fn set_sense<S: BatterySource + Send + 'static>(&mut self, sense: S, min_volts: f32) {
+    self.sense = Some(Box::new(sense));
+    self.min_volts = min_volts;
+  }
+
+  /// Sets how long the rail may take to come up before its voltage is
+  /// checked, 50ms by default.
+  pub fn set_settle_time(&mut self, settle: Duration) {
+    self.settle = settle;
+  }
+
+  /// Sets how many samples in a row have to be below the minimum voltage
+  /// before the rail counts as browned out, 3 by default.
+  pub fn set_debounce(&mut self, samples: u32) {
+    self.debounce = samples.max(1);
+  }
+
+  /// Makes the rail take its time from `clock`, e.g. a `TestClock`.
+  pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+    self.clock = clock;
+  }
+
+  /// Returns whether the rail is powered.
+  pub fn state(&self) -> RailState {
+    self.state
+  }
+
+  /// Returns the voltage measured last, if the rail is sensed.
+  pub fn voltage(&self) -> Option<f32> {
+    self.voltage
+  }
+
+  /// Powers the rail, waits for it to settle and checks its voltage.
+  ///
+  /// The servos' pulses should be set up before, so they don't jump to an
+  /// end of their travel when powered.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the output can't be driven, e.g. because the e-stop is
+  /// latched, or if the rail doesn't reach its minimum voltage; it's shut
+  /// down again then.
+  pub fn power_up(&mut self) -> Result<()> {
+    self.enable.set_state(level(true, self.active_low))?;
+    self.state = RailState::On;
+    self.low_samples = 0;
+    self.clock.sleep(self.settle)?;
+    let volts = match self.measure() {
+      Ok(Some(volts)) => volts,
+      Ok(None) => return Ok(()),
+      Err(e) => {
+        let _ = self.power_down();
+        return Err(e);
+      }
+    };
+    if volts < self.min_volts {
+      self.power_down()?;
+      self.state = RailState::BrownedOut;
+      bail!(format!("The servo rail only came up to {}V, not {}V", volts, self.min_volts));
+    }
+    Ok(())
+  }
+
+  /// Shuts the rail down.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the output can't be driven.
+  pub fn power_down(&mut self) -> Result<()> {
+    self.enable.set_state(level(false, self.active_low))?;
+    self.state = RailState::Off;
+    Ok(())
+  }
+
+  /// Samples the voltage of a powered rail, and shuts it down if it was
+  /// below the minimum in several samples in a row.
+  ///
+  /// Returns whether the rail browned out.
+  /// Call it periodically, e.g. from the control loop.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the voltage can't be measured, or if the rail can't be shut
+  /// down.
+  pub fn check(&mut self) -> Result<bool> {
+    if self.state != RailState::On {
+      return Ok(false);
+    }
+    let volts = match self.measure()? {
+      Some(volts) => volts,
+      None => return Ok(false),
+    };
+    if volts >= self.min_volts {
+      self.low_samples = 0;
+      return Ok(false);
+    }
+    self.low_samples += 1;
+    if self.low_samples < self.debounce {
+      return Ok(false);
+    }
+    self.power_down()?;
+    self.state = RailState::BrownedOut;
+    Ok(true)
+  }
+
+  /// Unwraps the enable output.
+  pub fn into_inner(self) -> P {
+    self.enable
+  }
+
+  fn measure(&mut self) -> Result<Option<f32>> {
+    let volts = match self.sense {
+      Some(ref sense) => sense.voltage().chain_err(|| "Failed to measure the servo rail voltage")?,
+      None => return Ok(None),
+    };
+    self.voltage = Some(volts);
+    Ok(Some(volts))
+  }
+}
+
+/// Returns the level of an enable output that is `on`.
+fn level(on: bool, active_low: bool) -> PinState {
+  if on != active_low { PinState::High } else { PinState::Low }
+}
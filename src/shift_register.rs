@@ -0,0 +1,296 @@
+//! The shift register module.
+//!
+//! A 74HC595 turns three GPIOs into eight outputs, and a chain of them into
+//! as many as needed: each chip's serial output QH' feeds the next chip's
+//! data input, and all chips share the clock (SRCLK) and latch (RCLK).
+//! `ShiftRegister` bit-bangs the chain:
+//!
+//! ```no_run
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::shift_register::ShiftRegister;
+//!
+//! // Two chips: SER on P8_11, SRCLK on P8_12, RCLK on P8_14.
+//! let mut outputs = ShiftRegister::new(GPIO_P8_11, GPIO_P8_12, GPIO_P8_14, 2).unwrap();
+//!
+//! // QA to QH of the first chip, then of the second.
+//! outputs.write_bytes(&[0b0000_0001, 0b1000_0000]).unwrap();
+//! // Output 3 is QD of the first chip.
+//! outputs.set_output(3, true).unwrap();
+//! ```
+//!
+//! The outputs only change when the latch is pulsed, once per write, so
+//! they never show the bits shifting through.
+//! The outputs are numbered along the chain: output 0 is QA of the chip
+//! whose SER is on the data pin, output 8 QA of the next one.
+//! The chips' ~OE and ~SRCLR pins are driven as usual, if they aren't tied
+//! to ground and 3.3V.
+//!
+//! `into_pins()` splits the chain into its outputs, which are `DigitalPin`s
+//! for drivers like `Relay`; each change rewrites the whole chain.
+
+use errors::*;
+use gpio::{self, GPIO, PinState};
+use hal::DigitalPin;
+use parallel_bus::delay;
+use pins::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+/// A chain of 74HC595 shift registers on a data, a clock and a latch line.
+#[derive(Debug)]
+pub struct ShiftRegister<P: DigitalPin = GPIO> {
+  data: P,
+  clock: P,
+  latch: P,
+  pulse: Duration,
+  // The level of the data line, if known.
+  data_level: Option<PinState>,
+  // The outputs, if they're known.
+  outputs: Option<Vec<bool>>,
+  len: usize,
+}
+
+impl ShiftRegister<GPIO> {
+  /// Drives a chain of `chips` shift registers on GPIOs, which are exported
+  /// and made outputs driving low.
+  ///
+  /// # Errors
+  ///
+  /// Fails if there are no chips, or if a pin can't be configured.
+  pub fn new(data: Pin, clock: Pin, latch: Pin, chips: usize) -> Result<ShiftRegister> {
+    let mut register = ShiftRegister::from_pins(gpio::output_driving(data, PinState::Low)?,
+                                                gpio::output_driving(clock, PinState::Low)?,
+                                                gpio::output_driving(latch, PinState::Low)?,
+                                                chips)?;
+    register.data_level = Some(PinState::Low);
+    Ok(register)
+  }
+}
+
+impl<P: DigitalPin> ShiftRegister<P> {
+  /// Drives a chain of `chips` shift registers on configured outputs; the
+  /// clock and latch are driven low.
+  ///
+  /// The outputs are unknown until the chain is written.
+  ///
+  /// # Errors
+  ///
+  /// Fails if there are no chips, or if the clock or latch can't be driven.
+  pub fn from_pins(data: P, mut clock: P, mut latch: P, chips: usize) -> Result<ShiftRegister<P>> {
+    if chips == 0 {
+      bail!("A shift register chain has at least one chip");
+    }
+    clock.set_state(PinState::Low)?;
+    latch.set_state(PinState::Low)?;
+    Ok(ShiftRegister {
+      data,
+      clock,
+      latch,
+      pulse: Duration::from_secs(0),
+      data_level: None,
+      outputs: None,
+      len: chips * 8,
+    })
+  }
+
+  /// Returns the number of outputs, 8 per chip.
+  pub fn len(&self) -> usize {
+    self.len
+  }
+
+  /// Returns whether the chain has no outputs; it always has some.
+  pub fn is_empty(&self) -> bool {
+    self.len == 0
+  }
+
+  /// Sets how long the clock and latch pulses are high, and the data line
+  /// is stable before a clock pulse.
+  ///
+  /// There's no delay by default: sysfs GPIOs are a lot slower than the
+  /// 25ns a 74HC595 needs at 3.3V anyway.
+  pub fn set_pulse(&mut self, pulse: Duration) {
+    self.pulse = pulse;
+  }
+
+  /// Returns the outputs as last written, or `None` if they're unknown.
+  pub fn outputs(&self) -> Option<&[bool]> {
+    self.outputs.as_ref().map(|outputs| &outputs[..])
+  }
+
+  /// Shifts in `bits` and latches them: `bits[0]` ends up on output 0, and
+  /// the outputs there were move along the chain by `bits.len()`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if a line can't be driven.
+  pub fn write_bits(&mut self, bits: &[bool]) -> Result<()> {
+    // Unknown from here on, until the latch is pulsed.
+    let outputs = self.outputs.take();
+    // The bit shifted in first ends up furthest along the chain.
+    for &bit in bits.iter().rev() {
+      self.shift(bit)?;
+    }
+    self.pulse_line(false)?;
+    let len = self.len;
+    self.outputs = if bits.len() >= len {
+      Some(bits[..len].to_vec())
+    } else {
+      outputs.map(|outputs| bits.iter().chain(&outputs).take(len).cloned().collect())
+    };
+    Ok(())
+  }
+
+  /// Shifts in `byte` and latches it: bit 0 ends up on output 0, i.e. QA of
+  /// the first chip, and the outputs there were move on to the next chip.
+  ///
+  /// # Errors
+  ///
+  /// Fails if a line can't be driven.
+  pub fn write_byte(&mut self, byte: u8) -> Result<()> {
+    self.write_bits(&bits(&[byte]))
+  }
+
+  /// Writes a byte to each chip, `bytes[0]` to the first one, with bit 0 on
+  /// QA.
+  ///
+  /// # Errors
+  ///
+  /// Fails if there isn't one byte per chip, or if a line can't be driven.
+  pub fn write_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+    if bytes.len() * 8 != self.len {
+      bail!(format!("A chain of {} shift registers takes {} bytes, not {}",
+                    self.len / 8,
+                    self.len / 8,
+                    bytes.len()));
+    }
+    self.write_bits(&bits(bytes))
+  }
+
+  /// Sets output `index` and rewrites the chain.
+  ///
+  /// # Errors
+  ///
+  /// Fails if there's no output `index`, if the other outputs are unknown,
+  /// or if a line can't be driven.
+  pub fn set_output(&mut self, index: usize, on: bool) -> Result<()> {
+    if index >= self.len {
+      bail!(format!("A chain of {} shift registers has no output {}", self.len / 8, index));
+    }
+    let mut outputs = match self.outputs {
+      Some(ref outputs) => outputs.clone(),
+      None => bail!("The shift register outputs are unknown, they have to be written first"),
+    };
+    outputs[index] = on;
+    self.write_bits(&outputs)
+  }
+
+  /// Turns all outputs off.
+  ///
+  /// # Errors
+  ///
+  /// Fails if a line can't be driven.
+  pub fn clear(&mut self) -> Result<()> {
+    let len = self.len;
+    self.write_bits(&vec![false; len])
+  }
+
+  /// Splits the chain into its outputs, turning them all off first if
+  /// they're unknown.
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  /// use libbeaglebone::shift_register::ShiftRegister;
+  ///
+  /// let outputs = ShiftRegister::new(GPIO_P8_11, GPIO_P8_12, GPIO_P8_14, 1).unwrap();
+  /// let mut pins = outputs.into_pins().unwrap();
+  ///
+  /// let relay = Relay::from_pin(pins.remove(3), false).unwrap();
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if the outputs have to be turned off and a line can't be driven.
+  pub fn into_pins(mut self) -> Result<Vec<ShiftRegisterPin<P>>> {
+    if self.outputs.is_none() {
+      self.clear()?;
+    }
+    let len = self.len;
+    let register = Arc::new(Mutex::new(self));
+    Ok((0..len).map(|index| ShiftRegisterPin {
+                             register: register.clone(),
+                             index,
+                           })
+               .collect())
+  }
+
+  /// Unwraps the data, clock and latch lines.
+  pub fn into_inner(self) -> (P, P, P) {
+    (self.data, self.clock, self.latch)
+  }
+
+  /// Puts `bit` on the data line and pulses the clock.
+  fn shift(&mut self, bit: bool) -> Result<()> {
+    let level = if bit { PinState::High } else { PinState::Low };
+    if self.data_level != Some(level) {
+      self.data_level = None;
+      self.data.set_state(level)?;
+      self.data_level = Some(level);
+      delay(self.pulse);
+    }
+    self.pulse_line(true)
+  }
+
+  /// Pulses the clock, or the latch.
+  fn pulse_line(&mut self, clock: bool) -> Result<()> {
+    let pulse = self.pulse;
+    let line = if clock { &mut self.clock } else { &mut self.latch };
+    line.set_state(PinState::High)?;
+    delay(pulse);
+    line.set_state(PinState::Low)?;
+    delay(pulse);
+    Ok(())
+  }
+}
+
+/// An output of a chain of shift registers, see `ShiftRegister::into_pins()`.
+#[derive(Debug)]
+pub struct ShiftRegisterPin<P: DigitalPin = GPIO> {
+  register: Arc<Mutex<ShiftRegister<P>>>,
+  index: usize,
+}
+
+impl<P: DigitalPin> ShiftRegisterPin<P> {
+  /// Returns the number of the output along the chain.
+  pub fn index(&self) -> usize {
+    self.index
+  }
+}
+
+impl<P: DigitalPin> DigitalPin for ShiftRegisterPin<P> {
+  fn set_state(&mut self, state: PinState) -> Result<()> {
+    lock(&self.register).set_output(self.index, state == PinState::High)
+  }
+
+  /// Returns the state the output was written last; the chips can't be read
+  /// back.
+  fn state(&self) -> Result<PinState> {
+    match lock(&self.register).outputs() {
+      Some(outputs) if outputs[self.index] => Ok(PinState::High),
+      Some(_) => Ok(PinState::Low),
+      None => bail!(format!("The state of shift register output {} is unknown after a failed write", self.index)),
+    }
+  }
+
+  fn pin_name(&self) -> String {
+    format!("shift register output {}", self.index)
+  }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+  mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Returns the bits of `bytes`, lowest bit of the first byte first.
+fn bits(bytes: &[u8]) -> Vec<bool> {
+  bytes.iter().flat_map(|&byte| (0..8).map(move |bit| byte & (1 << bit) != 0)).collect()
+}
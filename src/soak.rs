@@ -0,0 +1,383 @@
+//! The soak test module.
+//!
+//! Before a board or kernel goes into the field, it's worth knowing that its
+//! GPIO and PWM drivers survive hours of use.
+//! A `SoakTest` hammers the configured devices with random but valid
+//! operations for a given time and reports:
+//!
+//! * how many operations failed, with the first error messages,
+//! * how many operations took longer than an outlier threshold,
+//! * the warnings the kernel logged in the meantime.
+//!
+//! GPIOs are driven as outputs and read back, so they must not be connected
+//! to anything that minds being toggled; the same goes for PWM outputs.
+//! Reading kernel warnings from `/dev/kmsg` needs root privileges, they are
+//! left out otherwise.
+//! See `examples/soak.rs` for a command line tool built on this module.
+
+use device::Device;
+use enums::DeviceState;
+use errors::*;
+use gpio::{GPIO, PinDirection, PinState};
+use nix::libc;
+use pwm::{PWM, PWMState};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom};
+use std::os::unix::fs::OpenOptionsExt;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How many error messages are kept per device.
+const MAX_ERRORS_KEPT: usize = 10;
+
+/// The shortest PWM period the test sets, in nanoseconds (10kHz).
+const MIN_PERIOD_NS: u32 = 100_000;
+
+/// The longest PWM period the test sets, in nanoseconds (50Hz).
+const MAX_PERIOD_NS: u32 = 20_000_000;
+
+/// A small xorshift generator, good enough to pick operations.
+#[derive(Debug)]
+struct Rng(u64);
+
+impl Rng {
+  fn next(&mut self) -> u64 {
+    self.0 ^= self.0 << 13;
+    self.0 ^= self.0 >> 7;
+    self.0 ^= self.0 << 17;
+    self.0
+  }
+
+  /// Returns a number in `low..=high`.
+  fn range(&mut self, low: u32, high: u32) -> u32 {
+    low + (self.next() % (u64::from(high - low) + 1)) as u32
+  }
+}
+
+/// The results of one device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceReport {
+  /// The name of the device, e.g. "GPIO pin #45".
+  pub name: String,
+  /// The number of operations.
+  pub operations: u64,
+  /// The number of operations that failed.
+  pub errors: u64,
+  /// The number of operations slower than the outlier threshold.
+  pub outliers: u64,
+  /// The time of the slowest operation.
+  pub max_latency: Duration,
+  /// The total time of all operations.
+  pub total_latency: Duration,
+  /// The first error messages.
+  pub first_errors: Vec<String>,
+}
+
+impl DeviceReport {
+  fn new(name: String) -> DeviceReport {
+    DeviceReport {
+      name,
+      operations: 0,
+      errors: 0,
+      outliers: 0,
+      max_latency: Duration::from_secs(0),
+      total_latency: Duration::from_secs(0),
+      first_errors: Vec::new(),
+    }
+  }
+
+  /// Returns the share of operations that failed.
+  pub fn error_rate(&self) -> f64 {
+    if self.operations == 0 {
+      0.0
+    } else {
+      self.errors as f64 / self.operations as f64
+    }
+  }
+
+  fn record(&mut self, latency: Duration, outlier_threshold: Duration, result: Result<()>) {
+    self.operations += 1;
+    self.total_latency += latency;
+    self.max_latency = self.max_latency.max(latency);
+    if latency > outlier_threshold {
+      self.outliers += 1;
+    }
+    if let Err(e) = result {
+      self.errors += 1;
+      if self.first_errors.len() < MAX_ERRORS_KEPT {
+        self.first_errors.push(e.to_string());
+      }
+    }
+  }
+}
+
+/// The results of a soak test.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SoakReport {
+  /// How long the test ran.
+  pub duration: Duration,
+  /// The results of each device, in the order they were added.
+  pub devices: Vec<DeviceReport>,
+  /// The warnings and errors the kernel logged during the test, or `None`
+  /// if the kernel log couldn't be read.
+  pub kernel_warnings: Option<Vec<String>>,
+}
+
+impl SoakReport {
+  /// Returns whether no operation failed and the kernel didn't log any
+  /// warnings.
+  pub fn passed(&self) -> bool {
+    self.devices.iter().all(|device| device.errors == 0) &&
+    self.kernel_warnings.as_ref().is_none_or(|warnings| warnings.is_empty())
+  }
+}
+
+impl fmt::Display for SoakReport {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    writeln!(f, "Soak test ran for {:?}", self.duration)?;
+    for device in &self.devices {
+      let mean = if device.operations == 0 {
+        Duration::from_secs(0)
+      } else {
+        device.total_latency / device.operations as u32
+      };
+      writeln!(f,
+               "{}: {} operations, {} errors ({:.4}%), {} outliers, mean {:?}, max {:?}",
+               device.name,
+               device.operations,
+               device.errors,
+               device.error_rate() * 100.0,
+               device.outliers,
+               mean,
+               device.max_latency)?;
+      for error in &device.first_errors {
+        writeln!(f, "  error: {}", error)?;
+      }
+    }
+    match self.kernel_warnings {
+      Some(ref warnings) => {
+        writeln!(f, "{} kernel warnings", warnings.len())?;
+        for warning in warnings {
+          writeln!(f, "  kernel: {}", warning)?;
+        }
+      }
+      None => writeln!(f, "Kernel log not available")?,
+    }
+    Ok(())
+  }
+}
+
+/// A soak test of GPIO and PWM devices.
+#[derive(Debug)]
+pub struct SoakTest {
+  gpios: Vec<GPIO>,
+  pwms: Vec<PWM>,
+  outlier_threshold: Duration,
+  seed: u64,
+}
+
+impl SoakTest {
+  /// Creates a soak test without any devices.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  /// use libbeaglebone::soak::SoakTest;
+  /// use std::time::Duration;
+  ///
+  /// let mut test = SoakTest::new();
+  /// test.add_gpio(GPIO::new(GPIO_P8_11));
+  /// test.add_pwm(PWM::new(0, 0));
+  ///
+  /// let report = test.run(Duration::from_secs(3600)).unwrap();
+  /// println!("{}", report);
+  /// assert!(report.passed());
+  /// ```
+  pub fn new() -> SoakTest {
+    SoakTest {
+      gpios: Vec::new(),
+      pwms: Vec::new(),
+      outlier_threshold: Duration::from_millis(1),
+      seed: SystemTime::now().duration_since(UNIX_EPOCH)
+                             .map(|t| t.as_nanos() as u64)
+                             .unwrap_or(0) | 1,
+    }
+  }
+
+  /// Adds a GPIO, which is exported and driven as an output during the test.
+  pub fn add_gpio(&mut self, gpio: GPIO) {
+    self.gpios.push(gpio);
+  }
+
+  /// Adds a PWM, which is exported and driven during the test.
+  pub fn add_pwm(&mut self, pwm: PWM) {
+    self.pwms.push(pwm);
+  }
+
+  /// Sets how long an operation may take before it counts as an outlier.
+  /// Defaults to 1ms.
+  pub fn set_outlier_threshold(&mut self, threshold: Duration) {
+    self.outlier_threshold = threshold;
+  }
+
+  /// Sets the seed of the operation sequence, to repeat a previous run.
+  pub fn set_seed(&mut self, seed: u64) {
+    // xorshift gets stuck at zero.
+    self.seed = seed.max(1);
+  }
+
+  /// Runs the test for `duration` and reports the results.
+  ///
+  /// The devices are exported and configured first and disabled, turned into
+  /// inputs and unexported again afterwards.
+  ///
+  /// # Errors
+  ///
+  /// Fails if a device can't be set up, failures during the test are
+  /// reported instead.
+  pub fn run(&mut self, duration: Duration) -> Result<SoakReport> {
+    for gpio in &self.gpios {
+      gpio.set_export(DeviceState::Exported)?;
+      gpio.set_direction(PinDirection::Out)?;
+    }
+    for pwm in &mut self.pwms {
+      pwm.set_export(DeviceState::Exported)?;
+      pwm.set_duty_cycle(0)?;
+      pwm.set_period(MAX_PERIOD_NS)?;
+    }
+
+    let mut kernel_log = KernelLog::open();
+    let mut rng = Rng(self.seed);
+    let threshold = self.outlier_threshold;
+    let mut gpio_reports: Vec<DeviceReport> =
+      self.gpios.iter().map(|gpio| DeviceReport::new(gpio.describe())).collect();
+    let mut pwm_reports: Vec<DeviceReport> =
+      self.pwms.iter().map(|pwm| DeviceReport::new(pwm.describe())).collect();
+
+    let start = Instant::now();
+    while start.elapsed() < duration {
+      for (gpio, report) in self.gpios.iter_mut().zip(&mut gpio_reports) {
+        let state = if rng.next() & 1 == 0 {
+          PinState::Low
+        } else {
+          PinState::High
+        };
+        let op_start = Instant::now();
+        let result = gpio_operation(gpio, state);
+        report.record(op_start.elapsed(), threshold, result);
+      }
+      for (pwm, report) in self.pwms.iter_mut().zip(&mut pwm_reports) {
+        let op_start = Instant::now();
+        let result = pwm_operation(pwm, &mut rng);
+        report.record(op_start.elapsed(), threshold, result);
+      }
+      // Don't spin without devices.
+      if self.gpios.is_empty() && self.pwms.is_empty() {
+        break;
+      }
+    }
+    let elapsed = start.elapsed();
+
+    for pwm in &mut self.pwms {
+      let _ = pwm.set_state(PWMState::Disabled);
+      let _ = pwm.set_export(DeviceState::Unexported);
+    }
+    for gpio in &self.gpios {
+      let _ = gpio.set_direction(PinDirection::In);
+      let _ = gpio.set_export(DeviceState::Unexported);
+    }
+
+    gpio_reports.extend(pwm_reports);
+    Ok(SoakReport {
+      duration: elapsed,
+      devices: gpio_reports,
+      kernel_warnings: kernel_log.as_mut().map(KernelLog::read_warnings),
+    })
+  }
+}
+
+impl Default for SoakTest {
+  fn default() -> SoakTest {
+    SoakTest::new()
+  }
+}
+
+/// Drives the GPIO and checks that it reads back the same.
+fn gpio_operation(gpio: &mut GPIO, state: PinState) -> Result<()> {
+  let expected = state == PinState::High;
+  gpio.write(state)?;
+  let read = gpio.read()? == PinState::High;
+  if read != expected {
+    bail!(format!("GPIO pin #{} read back {} after writing {}",
+                  gpio.pin_num(),
+                  read as u8,
+                  expected as u8));
+  }
+  Ok(())
+}
+
+/// Applies a random, valid change to the PWM.
+fn pwm_operation(pwm: &mut PWM, rng: &mut Rng) -> Result<()> {
+  match rng.next() % 4 {
+    0 => {
+      // The duty cycle must never exceed the period, so clear it first.
+      pwm.set_duty_cycle(0)?;
+      pwm.set_period(rng.range(MIN_PERIOD_NS, MAX_PERIOD_NS))
+    }
+    1 => {
+      pwm.set_state(if rng.next() & 1 == 0 {
+                      PWMState::Enabled
+                    } else {
+                      PWMState::Disabled
+                    })
+    }
+    _ => pwm.write(rng.range(0, 100) as f32),
+  }
+}
+
+/// The kernel log, positioned at the end when the test started.
+#[derive(Debug)]
+struct KernelLog {
+  file: File,
+}
+
+impl KernelLog {
+  fn open() -> Option<KernelLog> {
+    let mut file = OpenOptions::new()
+      .read(true)
+      .custom_flags(libc::O_NONBLOCK)
+      .open("/dev/kmsg")
+      .ok()?;
+    let _ = file.seek(SeekFrom::End(0)).ok()?;
+    Some(KernelLog { file })
+  }
+
+  /// Reads the warnings and errors logged since the log was opened.
+  fn read_warnings(&mut self) -> Vec<String> {
+    let mut warnings = Vec::new();
+    // Each read returns one record, e.g. "4,1234,5678,-;message".
+    let mut buf = [0u8; 8192];
+    loop {
+      let len = match self.file.read(&mut buf) {
+        Ok(0) => break,
+        Ok(len) => len,
+        // Records were overwritten before they were read, skip them.
+        Err(ref e) if e.raw_os_error() == Some(libc::EPIPE) => continue,
+        // No more records, or the log can't be read.
+        Err(_) => break,
+      };
+      let record = String::from_utf8_lossy(&buf[..len]);
+      let mut parts = record.splitn(2, ';');
+      let header = parts.next().unwrap_or("");
+      let message = parts.next().unwrap_or("").lines().next().unwrap_or("");
+      // The first field holds the facility and the level in its lowest
+      // three bits; levels up to 4 are warnings or worse.
+      let level = header.split(',').next().and_then(|p| p.parse::<u32>().ok()).map(|p| p & 7);
+      if level.is_some_and(|level| level <= 4) {
+        warnings.push(message.to_string());
+      }
+    }
+    warnings
+  }
+}
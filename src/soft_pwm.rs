@@ -0,0 +1,360 @@
+//! The software PWM module.
+//!
+//! Only a handful of pins have a hardware PWM.
+//! `SoftPWM` generates a PWM signal on any GPIO pin instead, by toggling it
+//! from a thread of its own that is timed by a `PeriodicTimer`.
+//! It has the same methods as `PWM`, so code can switch between the two:
+//!
+//! ```no_run
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::soft_pwm::SoftPWM;
+//!
+//! let mut led = SoftPWM::new(GPIO_P8_11).unwrap();
+//!
+//! // 100Hz at 30%.
+//! led.set_period(10_000_000).unwrap();
+//! led.write(30.0).unwrap();
+//! led.set_state(PWMState::Enabled).unwrap();
+//! ```
+//!
+//! The edges are only as precise as the thread is woken up, typically to
+//! some tens of microseconds, and far worse on a loaded system; run the
+//! program with a real-time scheduling policy, e.g. with `chrt -f 50`, for
+//! the best results.
+//! Periods below `MIN_PERIOD_NS` are refused, as the sysfs writes alone take
+//! a good part of them.
+
+use enums::DeviceState;
+use errors::*;
+use gpio::{GPIO, PinDirection, PinState};
+use hal::PwmOutput;
+use pins::Pin;
+use pwm::PWMState;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use timing::PeriodicTimer;
+
+/// The shortest period of a software PWM in nanoseconds, i.e. 10kHz.
+pub const MIN_PERIOD_NS: u32 = 100_000;
+
+/// The period a software PWM starts with, i.e. 1kHz.
+const DEFAULT_PERIOD_NS: u32 = 1_000_000;
+
+/// What the thread generates, in nanoseconds.
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+  period: u32,
+  duty_cycle: u32,
+  enabled: bool,
+}
+
+/// A PWM generated in software on a GPIO pin.
+#[derive(Debug)]
+pub struct SoftPWM {
+  pin_num: u8,
+  settings: Arc<Mutex<Settings>>,
+  // The error that stopped the thread, if any.
+  error: Arc<Mutex<Option<Error>>>,
+  running: Arc<AtomicBool>,
+  thread: Option<JoinHandle<GPIO>>,
+}
+
+impl SoftPWM {
+  /// Creates a software PWM on `pin`, exporting it and making it an output.
+  ///
+  /// It starts disabled, with a period of 1ms and a duty cycle of 0.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the pin can't be exported or configured.
+  pub fn new(pin: Pin) -> Result<SoftPWM> {
+    let gpio = GPIO::new(pin);
+    gpio.set_export(DeviceState::Exported)?;
+    gpio.set_direction(PinDirection::Out)?;
+    SoftPWM::from_gpio(gpio)
+  }
+
+  /// Creates a software PWM on a GPIO pin that is configured as an output
+  /// already.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the pin can't be driven low to begin with.
+  pub fn from_gpio(mut gpio: GPIO) -> Result<SoftPWM> {
+    gpio.write(PinState::Low)?;
+    let pin_num = gpio.pin_num();
+    let settings = Arc::new(Mutex::new(Settings {
+                                         period: DEFAULT_PERIOD_NS,
+                                         duty_cycle: 0,
+                                         enabled: false,
+                                       }));
+    let error = Arc::new(Mutex::new(None));
+    let running = Arc::new(AtomicBool::new(true));
+    let thread = {
+      let settings = settings.clone();
+      let error = error.clone();
+      let running = running.clone();
+      thread::spawn(move || {
+        if let Err(e) = generate(&mut gpio, &settings, &running) {
+          *error.lock().unwrap_or_else(|e| e.into_inner()) = Some(e);
+        }
+        // Leave the pin inactive however the thread ended.
+        let _ = gpio.write(PinState::Low);
+        gpio
+      })
+    };
+    Ok(SoftPWM {
+      pin_num,
+      settings,
+      error,
+      running,
+      thread: Some(thread),
+    })
+  }
+
+  /// Returns the kernel's number of the pin, e.g. 45 for `GPIO_P8_11`.
+  pub fn pin_num(&self) -> u8 {
+    self.pin_num
+  }
+
+  /// Sets the period of the PWM in nanoseconds, taking effect with the next
+  /// period.
+  ///
+  /// The duty cycle is kept in nanoseconds, so it's limited to the new
+  /// period.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the period is below `MIN_PERIOD_NS`, or if the thread has
+  /// stopped because the pin couldn't be written.
+  pub fn set_period(&mut self, period_ns: u32) -> Result<()> {
+    self.check()?;
+    if period_ns < MIN_PERIOD_NS {
+      bail!(format!(
+        "Software PWM period {}ns is below the minimum of {}ns",
+        period_ns,
+        MIN_PERIOD_NS
+      ));
+    }
+    let mut settings = self.lock();
+    settings.period = period_ns;
+    settings.duty_cycle = settings.duty_cycle.min(period_ns);
+    Ok(())
+  }
+
+  /// Enables or disables the PWM; a disabled PWM keeps the pin low.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the thread has stopped because the pin couldn't be written.
+  pub fn set_state(&mut self, state: PWMState) -> Result<()> {
+    self.check()?;
+    self.lock().enabled = state == PWMState::Enabled;
+    Ok(())
+  }
+
+  /// Sets the duty cycle as a percentage of the period.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the percentage isn't between 0 and 100, or if the thread has
+  /// stopped because the pin couldn't be written.
+  pub fn write(&mut self, percentage: f32) -> Result<()> {
+    if !(0.0..=100.0).contains(&percentage) {
+      bail!(format!("Software PWM duty cycle {}% isn't between 0 and 100", percentage));
+    }
+    self.set_duty_cycle_fraction(percentage / 100.0)
+  }
+
+  /// Sets the duty cycle in nanoseconds.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the duty cycle exceeds the period, or if the thread has
+  /// stopped because the pin couldn't be written.
+  pub fn set_duty_cycle(&mut self, duty_cycle_ns: u32) -> Result<()> {
+    self.check()?;
+    let mut settings = self.lock();
+    if duty_cycle_ns > settings.period {
+      bail!(format!(
+        "Software PWM duty cycle {}ns exceeds the period of {}ns",
+        duty_cycle_ns,
+        settings.period
+      ));
+    }
+    settings.duty_cycle = duty_cycle_ns;
+    Ok(())
+  }
+
+  /// Sets the frequency in Hz, keeping the duty cycle as a fraction of the
+  /// period.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the period of the frequency is below `MIN_PERIOD_NS` or above
+  /// `u32::MAX` ns, or if the thread has stopped because the pin couldn't be
+  /// written.
+  pub fn set_frequency(&mut self, hz: f32) -> Result<()> {
+    self.check()?;
+    let period = 1e9 / f64::from(hz);
+    if period.is_nan() || period < f64::from(MIN_PERIOD_NS) || period > f64::from(u32::MAX) {
+      bail!(format!("Software PWM frequency {}Hz is out of range", hz));
+    }
+    let mut settings = self.lock();
+    let fraction = fraction(&settings);
+    settings.period = period as u32;
+    settings.duty_cycle = (fraction * period) as u32;
+    Ok(())
+  }
+
+  /// Returns the frequency in Hz.
+  pub fn frequency(&self) -> f32 {
+    (1e9 / f64::from(self.lock().period)) as f32
+  }
+
+  /// Sets the duty cycle as a fraction of the period, from 0.0 to 1.0.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the fraction isn't between 0.0 and 1.0, or if the thread has
+  /// stopped because the pin couldn't be written.
+  pub fn set_duty_cycle_fraction(&mut self, fraction: f32) -> Result<()> {
+    self.check()?;
+    if !(0.0..=1.0).contains(&fraction) {
+      bail!(format!("Software PWM duty cycle fraction {} isn't between 0 and 1", fraction));
+    }
+    let mut settings = self.lock();
+    settings.duty_cycle = (f64::from(fraction) * f64::from(settings.period)) as u32;
+    Ok(())
+  }
+
+  /// Returns the duty cycle as a fraction of the period.
+  pub fn duty_cycle_fraction(&self) -> f32 {
+    fraction(&self.lock()) as f32
+  }
+
+  /// Returns the period in nanoseconds.
+  pub fn get_period(&self) -> Result<u32> {
+    Ok(self.lock().period)
+  }
+
+  /// Returns the duty cycle in nanoseconds.
+  pub fn get_duty_cycle(&self) -> Result<u32> {
+    Ok(self.lock().duty_cycle)
+  }
+
+  /// Returns whether the PWM is enabled.
+  pub fn get_state(&self) -> Result<PWMState> {
+    Ok(if self.lock().enabled {
+         PWMState::Enabled
+       } else {
+         PWMState::Disabled
+       })
+  }
+
+  /// Stops the thread and returns the pin, driven low.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the thread stopped because the pin couldn't be written, in
+  /// which case the pin is dropped.
+  pub fn stop(mut self) -> Result<GPIO> {
+    self.running.store(false, Ordering::SeqCst);
+    let thread = match self.thread.take() {
+      Some(thread) => thread,
+      None => bail!("The software PWM thread is gone"),
+    };
+    let gpio = match thread.join() {
+      Ok(gpio) => gpio,
+      Err(_) => bail!("The software PWM thread panicked"),
+    };
+    match self.error.lock().unwrap_or_else(|e| e.into_inner()).take() {
+      Some(e) => Err(e),
+      None => Ok(gpio),
+    }
+  }
+
+  /// Returns the error that stopped the thread, once.
+  fn check(&self) -> Result<()> {
+    if let Some(e) = self.error.lock().unwrap_or_else(|e| e.into_inner()).take() {
+      return Err(e).chain_err(|| format!("Software PWM on GPIO pin #{} stopped", self.pin_num));
+    }
+    if self.thread.as_ref().is_none_or(|thread| thread.is_finished()) {
+      bail!(format!("Software PWM on GPIO pin #{} stopped", self.pin_num));
+    }
+    Ok(())
+  }
+
+  fn lock(&self) -> MutexGuard<'_, Settings> {
+    self.settings.lock().unwrap_or_else(|e| e.into_inner())
+  }
+}
+
+impl Drop for SoftPWM {
+  fn drop(&mut self) {
+    self.running.store(false, Ordering::SeqCst);
+    if let Some(thread) = self.thread.take() {
+      let _ = thread.join();
+    }
+  }
+}
+
+impl PwmOutput for SoftPWM {
+  fn set_period_ns(&mut self, period_ns: u32) -> Result<()> {
+    self.set_period(period_ns)
+  }
+
+  fn set_duty_cycle_percent(&mut self, percentage: f32) -> Result<()> {
+    self.write(percentage)
+  }
+
+  fn set_enabled(&mut self, enabled: bool) -> Result<()> {
+    self.set_state(if enabled {
+      PWMState::Enabled
+    } else {
+      PWMState::Disabled
+    })
+  }
+}
+
+/// Returns the duty cycle of `settings` as a fraction of the period.
+fn fraction(settings: &Settings) -> f64 {
+  f64::from(settings.duty_cycle) / f64::from(settings.period)
+}
+
+/// Toggles `gpio` according to `settings` until `running` is cleared.
+fn generate(gpio: &mut GPIO, settings: &Mutex<Settings>, running: &AtomicBool) -> Result<()> {
+  let period = |settings: &Settings| Duration::from_nanos(u64::from(settings.period));
+  let mut timer = PeriodicTimer::new(period(&settings.lock().unwrap_or_else(|e| e.into_inner())))?;
+  // The state written last, so that a constant output isn't rewritten every
+  // period.
+  let mut level = PinState::Low;
+  let mut set = |gpio: &mut GPIO, state: PinState| -> Result<()> {
+    if state != level {
+      gpio.write(state)?;
+      level = state;
+    }
+    Ok(())
+  };
+
+  while running.load(Ordering::SeqCst) {
+    let current = *settings.lock().unwrap_or_else(|e| e.into_inner());
+    if timer.period() != period(&current) {
+      timer.set_period(period(&current))?;
+    }
+    let high_for = if current.enabled { current.duty_cycle } else { 0 };
+    if high_for == 0 {
+      set(gpio, PinState::Low)?;
+    } else if high_for >= current.period {
+      set(gpio, PinState::High)?;
+    } else {
+      set(gpio, PinState::High)?;
+      let _ = timer.wait_offset(Duration::from_nanos(u64::from(high_for)))?;
+      set(gpio, PinState::Low)?;
+    }
+    let _ = timer.wait()?;
+  }
+  Ok(())
+}
@@ -1,7 +1,9 @@
+use board;
 use errors::*;
 use std::fs::{File, OpenOptions};
 use std::marker::PhantomData;
 use std::os::unix::io::AsRawFd;
+use util::*;
 
 // Constants extracted from linux/spi/spidev.h
 bitflags! {
@@ -46,6 +48,14 @@ pub struct spi_ioc_transfer<'a, 'b> {
   tx_buf: u64,
   rx_buf: u64,
   len: u32,
+  speed_hz: u32,
+  delay_usecs: u16,
+  bits_per_word: u8,
+  cs_change: u8,
+  tx_nbits: u8,
+  rx_nbits: u8,
+  word_delay_usecs: u8,
+  pad: u8,
 
   tx_buf_ref: PhantomData<&'a [u8]>,
   rx_buf_ref: PhantomData<&'b mut [u8]>,
@@ -78,6 +88,25 @@ impl<'a, 'b> spi_ioc_transfer<'a, 'b> {
       ..Default::default()
     }
   }
+
+  /// Overrides the device's clock speed for this transfer.
+  pub fn with_speed_hz(mut self, speed_hz: u32) -> Self {
+    self.speed_hz = speed_hz;
+    self
+  }
+
+  /// Overrides the device's word size for this transfer.
+  pub fn with_bits_per_word(mut self, bits_per_word: u8) -> Self {
+    self.bits_per_word = bits_per_word;
+    self
+  }
+
+  /// Delays for `delay_usecs` after this transfer, before the chip select
+  /// changes or the next transfer starts.
+  pub fn with_delay_usecs(mut self, delay_usecs: u16) -> Self {
+    self.delay_usecs = delay_usecs;
+    self
+  }
 }
 
 pub type SpidevTransfer<'a, 'b> = spi_ioc_transfer<'a, 'b>;
@@ -94,7 +123,7 @@ const SPI_IOC_NR_MODE32: u8 = 5;
 // IOCTL functions: these macros expand to safe-ish wrappers for IOCTL, which
 // are then called by the accessors and mutators below
 ioctl!(read get_mode_u8 with SPI_IOC_MAGIC, SPI_IOC_NR_MODE; u8);
-ioctl!(read get_mode_u32 with SPI_IOC_MAGIC, SPI_IOC_NR_MODE; u32);
+ioctl!(read get_mode_u32 with SPI_IOC_MAGIC, SPI_IOC_NR_MODE32; u32);
 ioctl!(write set_mode_u8 with SPI_IOC_MAGIC, SPI_IOC_NR_MODE; u8);
 ioctl!(write set_mode_u32 with SPI_IOC_MAGIC, SPI_IOC_NR_MODE32; u32);
 ioctl!(read  get_lsb_first with SPI_IOC_MAGIC, SPI_IOC_NR_LSB_FIRST; u8);
@@ -105,20 +134,40 @@ ioctl!(read  get_max_speed_hz with SPI_IOC_MAGIC, SPI_IOC_NR_MAX_SPEED_HZ; u32);
 ioctl!(write set_max_speed_hz with SPI_IOC_MAGIC, SPI_IOC_NR_MAX_SPEED_HZ; u32);
 ioctl!(write spidev_transfer with SPI_IOC_MAGIC, SPI_IOC_NR_TRANSFER; spi_ioc_transfer);
 
+/// The largest transfer spidev accepts unless configured otherwise.
+pub const SPIDEV_DEFAULT_BUFSIZ: usize = 4096;
+
+/// Returns the largest transfer spidev accepts, as set by its `bufsiz`
+/// module parameter.
+///
+/// Falls back to `SPIDEV_DEFAULT_BUFSIZ` if the parameter can't be read or
+/// is 0.
+pub fn spidev_bufsiz() -> usize {
+  "/sys/module/spidev/parameters/bufsiz"
+    .read_file()
+    .ok()
+    .and_then(|bufsiz| bufsiz.trim().parse().ok())
+    .filter(|&bufsiz| bufsiz > 0)
+    .unwrap_or(SPIDEV_DEFAULT_BUFSIZ)
+}
+
 /// Represents a SPI interface.
 #[derive(Debug)]
 pub struct SPI {
+  spi_num: u8,
   bits_per_word: u8,
   max_speed_hz: u32,
   lsb_first: bool,
   spi_mode: SPIModeFlags,
   spi_file: File,
+  bufsiz: usize,
 }
 
 impl SPI {
   pub fn new(spi_num: u8) -> Result<SPI> {
-    let spi_file_path = format!("/dev/spidev{}.0", spi_num);
+    let spi_file_path = board::current().spi_path(spi_num);
     Ok(SPI {
+         spi_num,
          bits_per_word: 8,
          max_speed_hz: 10_000,
          lsb_first: false,
@@ -128,9 +177,15 @@ impl SPI {
            .write(true)
            .open(spi_file_path)
            .chain_err(|| format!("Failed to create new SPI device #{}.", spi_num))?,
+         bufsiz: spidev_bufsiz(),
        })
   }
 
+  /// Returns the number of the SPI bus.
+  pub fn spi_num(&self) -> u8 {
+    self.spi_num
+  }
+
   pub fn get_mode(&self) -> Result<u8> {
     let mut mode: u8 = 0;
     unsafe {
@@ -216,4 +271,121 @@ impl SPI {
     };
     Ok(())
   }
+
+  /// Does a transfer in a different SPI mode, restoring the device's mode
+  /// afterwards.
+  ///
+  /// The clock speed and word size can be overridden per transfer with
+  /// `SpidevTransfer::with_speed_hz()` and
+  /// `SpidevTransfer::with_bits_per_word()`, but spidev has no per-transfer
+  /// mode, so it is changed around the transfer.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::spi::*;
+  ///
+  /// let spi = SPI::new(1).unwrap();
+  /// spi.set_mode(SPI_MODE_0).unwrap();
+  /// spi.set_max_speed_hz(24_000_000).unwrap();
+  ///
+  /// // Read the slow mode 3 touch controller on the same bus as a fast
+  /// // mode 0 display.
+  /// let tx = [0x90, 0x00, 0x00];
+  /// let mut rx = [0; 3];
+  /// let mut transfer = SpidevTransfer::read_write(&tx, &mut rx).with_speed_hz(1_000_000);
+  /// spi.transfer_with_mode(&mut transfer, SPI_MODE_3).unwrap();
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if the mode can't be read or changed, or if the transfer fails.
+  /// The mode is restored even if the transfer fails.
+  pub fn transfer_with_mode(&self, transfer: &mut SpidevTransfer, mode: SPIModeFlags) -> Result<()> {
+    let mut previous: u32 = 0;
+    unsafe {
+      let _ = get_mode_u32(self.spi_file.as_raw_fd(), &mut previous)
+        .chain_err(|| "Failed to read SPI mode.")?;
+    };
+    let previous = SPIModeFlags::from_bits_truncate(previous);
+    if previous == mode {
+      return self.transfer(transfer);
+    }
+
+    self.set_mode(mode)?;
+    let result = self.transfer(transfer);
+    self.set_mode(previous)?;
+    result
+  }
+
+  /// Writes `data`, split into several transfers if it is larger than the
+  /// spidev buffer.
+  ///
+  /// The chip select stays asserted between the transfers, so the device sees
+  /// a single transfer.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::spi::SPI;
+  ///
+  /// let spi = SPI::new(1).unwrap();
+  ///
+  /// // Push a whole 320x240 RGB565 framebuffer at once.
+  /// let framebuffer = vec![0; 320 * 240 * 2];
+  /// spi.write_chunked(&framebuffer).unwrap();
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if one of the transfers fails.
+  pub fn write_chunked(&self, data: &[u8]) -> Result<()> {
+    let chunks = data.chunks(self.bufsiz).count();
+    for (i, chunk) in data.chunks(self.bufsiz).enumerate() {
+      self.transfer_chunk(SpidevTransfer::write(chunk), i + 1 == chunks)?;
+    }
+    Ok(())
+  }
+
+  /// Reads until `buf` is full, split into several transfers if it is
+  /// larger than the spidev buffer.
+  ///
+  /// The chip select stays asserted between the transfers.
+  pub fn read_chunked(&self, buf: &mut [u8]) -> Result<()> {
+    let bufsiz = self.bufsiz;
+    let chunks = buf.chunks(bufsiz).count();
+    for (i, chunk) in buf.chunks_mut(bufsiz).enumerate() {
+      self.transfer_chunk(SpidevTransfer::read(chunk), i + 1 == chunks)?;
+    }
+    Ok(())
+  }
+
+  /// Writes `tx` while reading into `rx`, split into several transfers if
+  /// they are larger than the spidev buffer.
+  ///
+  /// The chip select stays asserted between the transfers.
+  ///
+  /// # Errors
+  ///
+  /// Fails if `tx` and `rx` aren't the same length, or if a transfer fails.
+  pub fn read_write_chunked(&self, tx: &[u8], rx: &mut [u8]) -> Result<()> {
+    if tx.len() != rx.len() {
+      bail!(format!("SPI transfer writes {} bytes but reads {}, they have to be the same length",
+                    tx.len(),
+                    rx.len()));
+    }
+    let chunks = tx.chunks(self.bufsiz).count();
+    let pairs = tx.chunks(self.bufsiz).zip(rx.chunks_mut(self.bufsiz));
+    for (i, (tx_chunk, rx_chunk)) in pairs.enumerate() {
+      self.transfer_chunk(SpidevTransfer::read_write(tx_chunk, rx_chunk), i + 1 == chunks)?;
+    }
+    Ok(())
+  }
+
+  fn transfer_chunk(&self, mut transfer: SpidevTransfer, last: bool) -> Result<()> {
+    // On the last transfer of a message, cs_change keeps the chip select
+    // asserted until the next message.
+    transfer.cs_change = if last { 0 } else { 1 };
+    self.transfer(&mut transfer)
+  }
 }
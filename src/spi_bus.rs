@@ -0,0 +1,290 @@
+//! The shared SPI bus module.
+//!
+//! Several SPI devices can share the clock and data lines of one spidev bus
+//! if each of them gets its own chip-select line.
+//! The `SpiBusManager` owns the bus and hands out `SpiDevice` handles that
+//! drive a GPIO pin as their chip select.
+//! Each device has its own mode, clock speed and word size, which are applied
+//! to the bus before its transactions.
+//!
+//! Access to the bus is granted in the order it was requested, so a device
+//! that transfers large amounts of data, like a display, can't starve the
+//! others.
+//!
+//! The spidev bus has to be enabled beforehand, e.g. with
+//! `sudo config-pin overlay BB-SPIDEV0`, and the chip-select pins configured
+//! as GPIOs, e.g. `sudo config-pin P8.11 gpio`.
+//! The bus's hardware chip select toggles with every transaction as well, so
+//! it shouldn't be connected to any of the devices.
+
+use errors::*;
+use gpio::{self, GPIO, PinState};
+use pins::Pin;
+use spi::*;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+/// The settings a device needs the bus to be configured with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpiConfig {
+  /// The SPI mode, e.g. `SPI_MODE_0`.
+  pub mode: SPIModeFlags,
+  /// The clock speed in Hz.
+  pub max_speed_hz: u32,
+  /// The word size in bits.
+  pub bits_per_word: u8,
+}
+
+impl Default for SpiConfig {
+  fn default() -> SpiConfig {
+    SpiConfig {
+      mode: SPI_MODE_0,
+      max_speed_hz: 1_000_000,
+      bits_per_word: 8,
+    }
+  }
+}
+
+#[derive(Debug)]
+struct BusState {
+  spi: SPI,
+  // The configuration that was applied last, to skip the ioctls when the
+  // same device is used repeatedly.
+  config: Option<SpiConfig>,
+}
+
+#[derive(Debug, Default)]
+struct Tickets {
+  next: u64,
+  serving: u64,
+}
+
+/// A ticket lock around the bus, granting access in FIFO order.
+#[derive(Debug)]
+struct SharedBus {
+  tickets: Mutex<Tickets>,
+  turn: Condvar,
+  state: Mutex<BusState>,
+}
+
+impl SharedBus {
+  fn lock(&self) -> Result<BusGuard<'_>> {
+    let mut tickets = match self.tickets.lock() {
+      Ok(tickets) => tickets,
+      Err(_) => bail!("SPI bus lock is poisoned"),
+    };
+    let ticket = tickets.next;
+    tickets.next += 1;
+    while tickets.serving != ticket {
+      tickets = match self.turn.wait(tickets) {
+        Ok(tickets) => tickets,
+        Err(_) => bail!("SPI bus lock is poisoned"),
+      };
+    }
+    drop(tickets);
+
+    // From here on the ticket has to be passed on, even on errors.
+    let ticket = Ticket { bus: self };
+    // Only the holder of the current ticket gets here, so this never blocks.
+    match self.state.lock() {
+      Ok(state) => Ok(BusGuard { state, _ticket: ticket }),
+      Err(_) => bail!("SPI bus lock is poisoned"),
+    }
+  }
+}
+
+/// Passes the bus on to the next ticket when dropped.
+struct Ticket<'a> {
+  bus: &'a SharedBus,
+}
+
+impl<'a> Drop for Ticket<'a> {
+  fn drop(&mut self) {
+    let mut tickets = match self.bus.tickets.lock() {
+      Ok(tickets) => tickets,
+      Err(poisoned) => poisoned.into_inner(),
+    };
+    tickets.serving += 1;
+    self.bus.turn.notify_all();
+  }
+}
+
+/// Exclusive access to the bus.
+struct BusGuard<'a> {
+  // Declared first so it's unlocked before the ticket is passed on.
+  state: MutexGuard<'a, BusState>,
+  _ticket: Ticket<'a>,
+}
+
+/// Shares a spidev bus between several devices with GPIO chip selects.
+#[derive(Debug, Clone)]
+pub struct SpiBusManager {
+  bus: Arc<SharedBus>,
+}
+
+impl SpiBusManager {
+  /// Opens a spidev bus and creates a manager for it.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::spi_bus::SpiBusManager;
+  ///
+  /// let bus = SpiBusManager::new(1).unwrap();
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if the bus can't be opened.
+  pub fn new(spi_num: u8) -> Result<SpiBusManager> {
+    Ok(SpiBusManager::from_spi(SPI::new(spi_num)?))
+  }
+
+  /// Creates a manager for an already opened spidev bus.
+  pub fn from_spi(spi: SPI) -> SpiBusManager {
+    SpiBusManager {
+      bus: Arc::new(SharedBus {
+        tickets: Mutex::new(Tickets::default()),
+        turn: Condvar::new(),
+        state: Mutex::new(BusState { spi, config: None }),
+      }),
+    }
+  }
+
+  /// Adds a device whose chip select is connected to `cs_pin`.
+  ///
+  /// The pin is exported and configured as an output, driving the inactive
+  /// level right away.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  /// use libbeaglebone::spi::SPI_MODE_3;
+  /// use libbeaglebone::spi_bus::{SpiBusManager, SpiConfig};
+  ///
+  /// let bus = SpiBusManager::new(1).unwrap();
+  ///
+  /// // A fast display with the default mode 0, and a slow mode 3 sensor.
+  /// let mut display = bus.device(GPIO_P8_11, false, SpiConfig {
+  ///   max_speed_hz: 24_000_000,
+  ///   ..Default::default()
+  /// }).unwrap();
+  /// let mut sensor = bus.device(GPIO_P8_12, false, SpiConfig {
+  ///   mode: SPI_MODE_3,
+  ///   max_speed_hz: 500_000,
+  ///   ..Default::default()
+  /// }).unwrap();
+  ///
+  /// display.write(&[0x2C, 0xFF, 0xFF]).unwrap();
+  ///
+  /// let mut rx = [0; 3];
+  /// sensor.transfer(&[0x80, 0x00, 0x00], &mut rx).unwrap();
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if the pin can't be configured as a GPIO output.
+  pub fn device(&self, cs_pin: Pin, cs_active_high: bool, config: SpiConfig) -> Result<SpiDevice> {
+    let cs = gpio::output_driving(cs_pin, if cs_active_high { PinState::Low } else { PinState::High })?;
+
+    Ok(SpiDevice {
+      bus: self.bus.clone(),
+      cs,
+      cs_active_high,
+      config,
+    })
+  }
+}
+
+/// A device on a shared SPI bus.
+#[derive(Debug)]
+pub struct SpiDevice {
+  bus: Arc<SharedBus>,
+  cs: GPIO,
+  cs_active_high: bool,
+  config: SpiConfig,
+}
+
+impl SpiDevice {
+  /// Returns the bus settings of the device.
+  pub fn config(&self) -> SpiConfig {
+    self.config
+  }
+
+  /// Changes the bus settings of the device, taking effect with its next
+  /// transaction.
+  pub fn set_config(&mut self, config: SpiConfig) {
+    self.config = config;
+  }
+
+  /// Writes `data` to the device.
+  ///
+  /// Data larger than the spidev buffer is split into several transfers.
+  pub fn write(&mut self, data: &[u8]) -> Result<()> {
+    self.transaction(|spi| spi.write_chunked(data))
+  }
+
+  /// Reads from the device until `buf` is full.
+  ///
+  /// Buffers larger than the spidev buffer are split into several transfers.
+  pub fn read(&mut self, buf: &mut [u8]) -> Result<()> {
+    self.transaction(|spi| spi.read_chunked(buf))
+  }
+
+  /// Writes `tx` to the device while reading the same number of bytes into
+  /// `rx`.
+  ///
+  /// Buffers larger than the spidev buffer are split into several transfers.
+  ///
+  /// # Errors
+  ///
+  /// Fails if `tx` and `rx` differ in length, or if the transfer fails.
+  pub fn transfer(&mut self, tx: &[u8], rx: &mut [u8]) -> Result<()> {
+    if tx.len() != rx.len() {
+      bail!(format!(
+        "SPI transfer buffers differ in length ({} and {})",
+        tx.len(),
+        rx.len()
+      ));
+    }
+    self.transaction(|spi| spi.read_write_chunked(tx, rx))
+  }
+
+  /// Runs `f` with exclusive access to the bus, configured for this device
+  /// and with its chip select asserted.
+  ///
+  /// The chip select stays asserted across all transfers `f` does, e.g. to
+  /// send a command and read the response.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the bus can't be configured or the chip select can't be
+  /// driven, or with the error returned by `f`.
+  pub fn transaction<T, F>(&mut self, f: F) -> Result<T>
+    where F: FnOnce(&SPI) -> Result<T>
+  {
+    let mut guard = self.bus.lock()?;
+    let state = &mut *guard.state;
+
+    if state.config != Some(self.config) {
+      state.config = None;
+      state.spi.set_mode(self.config.mode)?;
+      state.spi.set_max_speed_hz(self.config.max_speed_hz)?;
+      state.spi.set_bits_per_word(self.config.bits_per_word)?;
+      state.config = Some(self.config);
+    }
+
+    let (active, inactive) = if self.cs_active_high {
+      (PinState::High, PinState::Low)
+    } else {
+      (PinState::Low, PinState::High)
+    };
+    self.cs.write(active)?;
+    let result = f(&state.spi);
+    // Release the device even if the transfer failed.
+    let released = self.cs.write(inactive);
+    let value = result?;
+    released?;
+    Ok(value)
+  }
+}
@@ -0,0 +1,149 @@
+//! The statistics module.
+//!
+//! Every sysfs access costs a system call or three, and in a control loop
+//! those add up.
+//! When statistics are enabled, `GPIO`, `PWM` and `ADC` count their reads,
+//! writes and errors and measure how long they took, which is available
+//! through their `stats()` methods:
+//!
+//! ```no_run
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::stats;
+//!
+//! stats::set_enabled(true);
+//!
+//! let mut pwm = PWM::new(0, 0);
+//! for i in 0..1000 {
+//!   pwm.write((i % 100) as f32).unwrap();
+//! }
+//!
+//! let stats = pwm.stats();
+//! println!("{} writes, {:?} on average, {:?} at most",
+//!          stats.writes,
+//!          stats.mean_write_latency(),
+//!          stats.max_latency);
+//! ```
+//!
+//! Statistics are disabled by default, so they cost nothing but a check of
+//! a flag unless they are used.
+
+use errors::*;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Whether operations are counted.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Enables or disables collecting statistics for all devices.
+pub fn set_enabled(enabled: bool) {
+  ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns whether statistics are collected.
+pub fn is_enabled() -> bool {
+  ENABLED.load(Ordering::Relaxed)
+}
+
+/// The operations of a device since its statistics were last reset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct OpStats {
+  /// The number of reads.
+  pub reads: u64,
+  /// The number of writes.
+  pub writes: u64,
+  /// The number of reads and writes that failed.
+  pub errors: u64,
+  /// The total time spent reading.
+  pub read_time: Duration,
+  /// The total time spent writing.
+  pub write_time: Duration,
+  /// The longest time an operation took.
+  pub max_latency: Duration,
+}
+
+impl OpStats {
+  /// Returns the average time a read took.
+  pub fn mean_read_latency(&self) -> Duration {
+    mean(self.read_time, self.reads)
+  }
+
+  /// Returns the average time a write took.
+  pub fn mean_write_latency(&self) -> Duration {
+    mean(self.write_time, self.writes)
+  }
+}
+
+fn mean(total: Duration, count: u64) -> Duration {
+  if count == 0 {
+    Duration::from_secs(0)
+  } else {
+    Duration::from_nanos((total.as_nanos() / u128::from(count)) as u64)
+  }
+}
+
+/// The operation counters of a device.
+///
+/// Drivers wrap their accesses in `read()` or `write()` and hand out
+/// `snapshot()` as their statistics.
+#[derive(Debug, Default)]
+pub struct OpCounters {
+  reads: AtomicU64,
+  writes: AtomicU64,
+  errors: AtomicU64,
+  read_ns: AtomicU64,
+  write_ns: AtomicU64,
+  max_ns: AtomicU64,
+}
+
+impl OpCounters {
+  /// Creates counters without any operations.
+  pub fn new() -> OpCounters {
+    OpCounters::default()
+  }
+
+  /// Runs and, if statistics are enabled, counts a read.
+  pub fn read<T, F: FnOnce() -> Result<T>>(&self, op: F) -> Result<T> {
+    self.track(&self.reads, &self.read_ns, op)
+  }
+
+  /// Runs and, if statistics are enabled, counts a write.
+  pub fn write<T, F: FnOnce() -> Result<T>>(&self, op: F) -> Result<T> {
+    self.track(&self.writes, &self.write_ns, op)
+  }
+
+  /// Returns the operations counted so far.
+  pub fn snapshot(&self) -> OpStats {
+    OpStats {
+      reads: self.reads.load(Ordering::Relaxed),
+      writes: self.writes.load(Ordering::Relaxed),
+      errors: self.errors.load(Ordering::Relaxed),
+      read_time: Duration::from_nanos(self.read_ns.load(Ordering::Relaxed)),
+      write_time: Duration::from_nanos(self.write_ns.load(Ordering::Relaxed)),
+      max_latency: Duration::from_nanos(self.max_ns.load(Ordering::Relaxed)),
+    }
+  }
+
+  /// Resets all counters to zero.
+  pub fn reset(&self) {
+    for counter in &[&self.reads, &self.writes, &self.errors, &self.read_ns, &self.write_ns, &self.max_ns] {
+      counter.store(0, Ordering::Relaxed);
+    }
+  }
+
+  fn track<T, F: FnOnce() -> Result<T>>(&self, count: &AtomicU64, time_ns: &AtomicU64, op: F) -> Result<T> {
+    if !is_enabled() {
+      return op();
+    }
+    let start = Instant::now();
+    let result = op();
+    let elapsed_ns = start.elapsed().as_nanos() as u64;
+
+    let _ = count.fetch_add(1, Ordering::Relaxed);
+    let _ = time_ns.fetch_add(elapsed_ns, Ordering::Relaxed);
+    let _ = self.max_ns.fetch_max(elapsed_ns, Ordering::Relaxed);
+    if result.is_err() {
+      let _ = self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+    result
+  }
+}
@@ -0,0 +1,317 @@
+//! The host stub module.
+//!
+//! Built with the `host-stub` feature, the crate checks whether it runs on
+//! a BeagleBone, and if not, e.g. on a developer's x86 laptop, makes the
+//! default board a stub: a fake sysfs tree in a temporary directory, with
+//! the BeagleBone's GPIO pins exported, its PWM chips and its ADC inputs.
+//! The same binary then runs its logic on the host without any `cfg` in the
+//! application, while on the BeagleBone it talks to the hardware:
+//!
+//! ```no_run
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::stub;
+//!
+//! let mut led = GPIO::new(GPIO_P8_12);
+//! led.set_export(DeviceState::Exported).unwrap();
+//! led.set_direction(PinDirection::Out).unwrap();
+//! led.write(PinState::High).unwrap();
+//!
+//! let button = GPIO::new(GPIO_P8_11);
+//! // Without hardware, the inputs read what the stub is told.
+//! if stub::is_active() {
+//!   stub::set_input(GPIO_P8_11 as u8, PinState::High).unwrap();
+//! }
+//! println!("{:?}", button.read().unwrap());
+//! ```
+//!
+//! The stub only stands in for sysfs: GPIOs use the sysfs backend even with
+//! the `gpio-cdev` feature, edges are never signaled, so waiting for one
+//! times out, and the UARTs, I2C and SPI buses and the memory-mapped
+//! drivers fail to open their device nodes.
+//! The tree is left behind in the temporary directory, see `root()`, to be
+//! looked at after the run.
+//!
+//! `check_pwm()` and `check_gpio()` check that a device's files agree with
+//! what the driver set; with the operations picked by a seeded `Random`,
+//! they drive a device through random sequences that replay from the seed
+//! when one fails.
+//! The stub doesn't remove unexported devices, there's no kernel to do it,
+//! so only exporting can be checked this way.
+
+use board::{self, Board};
+use errors::*;
+use gpio::{GPIO, PinState};
+use pwm::PWM;
+use std::collections::BTreeSet;
+use std::env;
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+use std::process;
+use util::*;
+
+/// The name of the stub board.
+pub const BOARD_NAME: &str = "Host stub";
+
+/// The number of ADC inputs of the stub, as on the BeagleBone.
+const ADC_INPUTS: u16 = 7;
+
+/// Returns whether the process runs on a BeagleBone, as far as the device
+/// tree tells.
+pub fn on_beaglebone() -> bool {
+  cfg!(target_arch = "arm") &&
+  fs::read("/proc/device-tree/model").is_ok_and(|model| String::from_utf8_lossy(&model).contains("BeagleBone"))
+}
+
+/// Returns whether the current board is the stub.
+pub fn is_active() -> bool {
+  board::current().name == BOARD_NAME
+}
+
+/// Returns the directory of the stub's fake sysfs tree, which is unique to
+/// the process.
+pub fn root() -> PathBuf {
+  env::temp_dir().join(format!("libbeaglebone-stub-{}", process::id()))
+}
+
+/// Creates the fake sysfs tree under `root()`, unless it exists already,
+/// and returns the stub board on it.
+///
+/// With the `host-stub` feature, this is the default board when not on a
+/// BeagleBone; it can also be selected with `board::set_current()`.
+///
+/// # Errors
+///
+/// Fails if the tree can't be created.
+pub fn board() -> Result<Board> {
+  let root = root();
+  let beaglebone = Board::beaglebone_black();
+  let path = |relative: &str| format!("{}/{}", root.display(), relative);
+  let board = Board {
+    name: BOARD_NAME.to_string(),
+    gpio_dir: path("sys/class/gpio"),
+    gpio_chip_device: path("dev/gpiochip{}"),
+    pinmux_state_file: Some(path("sys/devices/platform/ocp/ocp:{}_pinmux/state")),
+    pwm_chip_dir: path("sys/class/pwm/pwmchip{}"),
+    adc_raw_file: Some(path("sys/bus/iio/devices/iio:device0/in_voltage{}_raw")),
+    uart_device: path("dev/ttyO{}"),
+    i2c_device: path("dev/i2c-{}"),
+    spi_device: path("dev/spidev{}.0"),
+    ..beaglebone
+  };
+  if !root.exists() {
+    create_tree(&root, &board).chain_err(|| format!("Failed to create the host stub in {}", root.display()))?;
+  }
+  Ok(board)
+}
+
+/// Sets the level the stub's GPIO `pin_num` reads at, while it's an input.
+///
+/// # Errors
+///
+/// Fails if the stub has no such GPIO, or if it's an output, which reads
+/// the level it's driven to.
+pub fn set_input(pin_num: u8, state: PinState) -> Result<()> {
+  let gpio = board::current().gpio_path(pin_num);
+  if stub_file(&format!("{}/direction", gpio))?.as_str().read_file()?.trim() != "in" {
+    bail!(format!("The host stub's GPIO pin #{} is an output, its input can't be set", pin_num));
+  }
+  let path = format!("{}/value", gpio);
+  let value = match state {
+    PinState::High => "1",
+    PinState::Low => "0",
+  };
+  stub_file(&path)?.as_str().write_file(value)
+}
+
+/// Sets the raw value the stub's ADC input `adc_num` reads, 0 to 4095.
+///
+/// # Errors
+///
+/// Fails if the stub has no such ADC input.
+pub fn set_adc(adc_num: u16, raw: u16) -> Result<()> {
+  let path = board::current().adc_path(adc_num)?;
+  stub_file(&path)?.as_str().write_file(&raw.to_string())
+}
+
+/// Checks a PWM's invariants: its duty cycle doesn't exceed its period, and
+/// sysfs holds the period and duty cycle it set last.
+///
+/// ```
+/// use libbeaglebone::prelude::*;
+/// use libbeaglebone::{board, stub};
+///
+/// board::set_current(stub::board().unwrap());
+/// let mut pwm = PWM::new(0, 0);
+/// let mut random = stub::Random::new(245);
+/// for _ in 0..500 {
+///   // The operations may fail, e.g. a period shorter than the duty cycle,
+///   // but never break the invariants.
+///   let _ = match random.below(5) {
+///     0 => pwm.set_period(random.below(2_000_000)),
+///     1 => pwm.set_duty_cycle(random.below(2_000_000)),
+///     2 => pwm.set_duty_cycle_fraction(random.below(101) as f32 / 100.0),
+///     3 => pwm.set_state(if random.below(2) == 0 { PWMState::Enabled } else { PWMState::Disabled }),
+///     _ => {
+///       // Exporting an exported PWM changes nothing.
+///       let (period, duty_cycle) = (pwm.period(), pwm.duty_cycle());
+///       pwm.set_export(DeviceState::Exported).unwrap();
+///       assert_eq!((pwm.period(), pwm.duty_cycle()), (period, duty_cycle));
+///       Ok(())
+///     }
+///   };
+///   stub::check_pwm(&pwm).unwrap();
+/// }
+/// ```
+///
+/// # Errors
+///
+/// Fails if an invariant doesn't hold, or if the PWM can't be read.
+pub fn check_pwm(pwm: &PWM) -> Result<()> {
+  let (period, duty_cycle) = (pwm.get_period()?, pwm.get_duty_cycle()?);
+  if duty_cycle > period {
+    bail!(format!("PWM #{}-{} duty cycle of {}ns exceeds its period of {}ns",
+                  pwm.pwm_chip_num(),
+                  pwm.pwm_num(),
+                  duty_cycle,
+                  period));
+  }
+  if (period, duty_cycle) != (pwm.period(), pwm.duty_cycle()) {
+    bail!(format!("PWM #{}-{} has a period of {}ns and a duty cycle of {}ns, but was set to {}ns and {}ns",
+                  pwm.pwm_chip_num(),
+                  pwm.pwm_num(),
+                  period,
+                  duty_cycle,
+                  pwm.period(),
+                  pwm.duty_cycle()));
+  }
+  Ok(())
+}
+
+/// Checks a GPIO's invariants: it's exported, sysfs holds the direction it
+/// set last, and its level can be read.
+///
+/// ```
+/// use libbeaglebone::prelude::*;
+/// use libbeaglebone::{board, stub};
+///
+/// board::set_current(stub::board().unwrap());
+/// let mut gpio = GPIO::new(GPIO_P8_12);
+/// let mut random = stub::Random::new(245);
+/// let state = |bit| if bit == 0 { PinState::Low } else { PinState::High };
+/// for _ in 0..500 {
+///   let _ = match random.below(4) {
+///     0 => gpio.set_direction(if random.below(2) == 0 { PinDirection::In } else { PinDirection::Out }),
+///     1 => {
+///       // An output reads what it's driven to.
+///       let state = state(random.below(2));
+///       gpio.write(state).map(|()| assert_eq!(gpio.read().unwrap(), state))
+///     }
+///     2 => stub::set_input(GPIO_P8_12 as u8, state(random.below(2))),
+///     _ => gpio.set_export(DeviceState::Exported),
+///   };
+///   stub::check_gpio(&gpio).unwrap();
+/// }
+/// ```
+///
+/// # Errors
+///
+/// Fails if an invariant doesn't hold, or if the GPIO can't be read.
+pub fn check_gpio(gpio: &GPIO) -> Result<()> {
+  if !gpio.is_exported() {
+    bail!(format!("GPIO pin #{} isn't exported", gpio.pin_num()));
+  }
+  let cached = gpio.cached_direction();
+  let direction = gpio.direction()?;
+  if let Some(cached) = cached.filter(|&cached| cached != direction) {
+    bail!(format!("GPIO pin #{} is an {:?} pin, but was made an {:?} pin", gpio.pin_num(), direction, cached));
+  }
+  let _ = gpio.read()?;
+  Ok(())
+}
+
+/// A deterministic sequence of pseudo-random numbers, to pick the
+/// operations of a random sequence that replays from its seed.
+#[derive(Debug, Clone)]
+pub struct Random {
+  state: u64,
+}
+
+impl Random {
+  /// Starts the sequence of `seed`.
+  pub fn new(seed: u64) -> Random {
+    // xorshift gets stuck at 0.
+    Random { state: seed ^ 0x9E37_79B9_7F4A_7C15 }
+  }
+
+  /// Returns the next number, below `bound`.
+  ///
+  /// # Panics
+  ///
+  /// Panics if `bound` is 0.
+  pub fn below(&mut self, bound: u32) -> u32 {
+    self.state ^= self.state << 13;
+    self.state ^= self.state >> 7;
+    self.state ^= self.state << 17;
+    (self.state % u64::from(bound)) as u32
+  }
+}
+
+/// Returns `path`, making sure it's an existing file of the stub.
+fn stub_file(path: &str) -> Result<String> {
+  if !is_active() {
+    bail!("The host stub isn't the current board");
+  }
+  if !Path::new(path).is_file() {
+    bail!(format!("The host stub has no {}", path));
+  }
+  Ok(path.to_string())
+}
+
+/// Creates the files of `board` under `root`.
+fn create_tree(root: &Path, board: &Board) -> Result<()> {
+  let write = |path: &str, value: &str| -> Result<()> {
+    if let Some(dir) = Path::new(path).parent() {
+      fs::create_dir_all(dir).chain_err(|| format!("Failed to create {}", dir.display()))?;
+    }
+    path.write_file(value)
+  };
+
+  // All GPIOs exported, as inputs reading low.
+  write(&format!("{}/export", board.gpio_dir), "")?;
+  write(&format!("{}/unexport", board.gpio_dir), "")?;
+  for &(_, pin_num) in &board.gpio_pins {
+    let gpio = board.gpio_path(pin_num);
+    for &(file, value) in &[("direction", "in"), ("value", "0"), ("edge", "none"), ("active_low", "0")] {
+      write(&format!("{}/{}", gpio, file), value)?;
+    }
+    if let Some(pinmux) = board.pinmux_path(pin_num) {
+      write(&pinmux, "default")?;
+    }
+  }
+
+  // A PWM chip per controller, found by its device link, with both PWMs
+  // exported and disabled.
+  let controllers: BTreeSet<&str> = board.pwm_pins.iter().map(|(_, controller, _)| controller.as_str()).collect();
+  for (chip_num, controller) in controllers.into_iter().enumerate() {
+    let chip_num = chip_num as u8;
+    let chip = board.pwm_chip_path(chip_num);
+    write(&format!("{}/export", chip), "")?;
+    write(&format!("{}/unexport", chip), "")?;
+    write(&format!("{}/npwm", chip), "2")?;
+    let device = root.join(format!("sys/devices/platform/ocp/{}.pwm", controller));
+    fs::create_dir_all(&device).chain_err(|| format!("Failed to create {}", device.display()))?;
+    symlink(&device, format!("{}/device", chip)).chain_err(|| format!("Failed to link the device of {}", chip))?;
+    for pwm_num in 0..2 {
+      let pwm = board.pwm_path(chip_num, pwm_num);
+      for &(file, value) in &[("period", "0"), ("duty_cycle", "0"), ("enable", "0"), ("polarity", "normal")] {
+        write(&format!("{}/{}", pwm, file), value)?;
+      }
+    }
+  }
+
+  for adc_num in 0..ADC_INPUTS {
+    write(&board.adc_path(adc_num)?, "0")?;
+  }
+  Ok(())
+}
@@ -0,0 +1,241 @@
+//! The frequency sweep module.
+//!
+//! Filters, buzzers and mechanical parts respond differently to different
+//! frequencies, and the quickest way to find out how is to try them all.
+//! A `Sweep` steps a PWM output through a range of frequencies at a
+//! constant duty cycle, optionally sampling an ADC at every step, e.g.
+//! the output of an RC filter or a microphone next to a buzzer:
+//!
+//! ```no_run
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::sweep::{Sweep, SweepScale};
+//! use std::time::Duration;
+//!
+//! let mut pwm = PWM::new(0, 0);
+//! pwm.set_export(DeviceState::Exported).unwrap();
+//! let adc = ADC::new(AIN_0, 1.0);
+//!
+//! // 20 steps per decade from 100Hz to 10kHz, 50ms each.
+//! let mut sweep = Sweep::new(100.0, 10_000.0, 41).unwrap();
+//! sweep.set_scale(SweepScale::Logarithmic);
+//! sweep.set_dwell(Duration::from_millis(50));
+//!
+//! for point in sweep.run_with_adc(&mut pwm, &adc, 16).unwrap() {
+//!   let levels = point.adc.unwrap();
+//!   println!("{:.0}Hz: {:.1}mV peak to peak",
+//!            point.frequency_hz,
+//!            levels.max_mv - levels.min_mv);
+//! }
+//! ```
+//!
+//! The output is disabled when the sweep is done.
+
+use adc::ADC;
+use clock::{self, Clock};
+use errors::*;
+use hal::PwmOutput;
+use std::sync::Arc;
+use std::time::Duration;
+use units::DutyCycle;
+
+/// How the frequencies of a sweep are spaced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SweepScale {
+  /// The same number of Hz between steps.
+  Linear,
+  /// The same ratio between steps, i.e. as many steps per octave at the low
+  /// end as at the high end.
+  Logarithmic,
+}
+
+/// The ADC samples taken at one step of a sweep, in millivolts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdcLevels {
+  /// The mean of the samples.
+  pub mean_mv: f32,
+  /// The lowest sample.
+  pub min_mv: f32,
+  /// The highest sample.
+  pub max_mv: f32,
+}
+
+/// One step of a sweep.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SweepPoint {
+  /// The frequency of the step in Hz.
+  pub frequency_hz: f32,
+  /// The ADC samples, if the sweep took any.
+  pub adc: Option<AdcLevels>,
+}
+
+/// Steps a PWM output through a range of frequencies.
+#[derive(Debug)]
+pub struct Sweep {
+  start_hz: f32,
+  stop_hz: f32,
+  steps: usize,
+  scale: SweepScale,
+  duty: DutyCycle,
+  dwell: Duration,
+  clock: Arc<dyn Clock>,
+}
+
+impl Sweep {
+  /// Creates a linear sweep in `steps` steps from `start_hz` to `stop_hz`,
+  /// both included, at a duty cycle of 50% and 100ms per step.
+  ///
+  /// The sweep goes down if `stop_hz` is below `start_hz`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if there are fewer than 2 steps, or if a frequency is out of
+  /// range for a PWM, i.e. isn't between about 0.24Hz and 1GHz.
+  pub fn new(start_hz: f32, stop_hz: f32, steps: usize) -> Result<Sweep> {
+    if steps < 2 {
+      bail!(format!("A sweep needs at least 2 steps, not {}", steps));
+    }
+    let _ = period_ns(start_hz)?;
+    let _ = period_ns(stop_hz)?;
+    Ok(Sweep {
+      start_hz,
+      stop_hz,
+      steps,
+      scale: SweepScale::Linear,
+      duty: DutyCycle::clamped(0.5),
+      dwell: Duration::from_millis(100),
+      clock: clock::system(),
+    })
+  }
+
+  /// Sets how the frequencies are spaced, linearly by default.
+  pub fn set_scale(&mut self, scale: SweepScale) {
+    self.scale = scale;
+  }
+
+  /// Sets the duty cycle, 50% by default.
+  pub fn set_duty(&mut self, duty: DutyCycle) {
+    self.duty = duty;
+  }
+
+  /// Sets how long each frequency is held before the ADC is sampled and the
+  /// next one follows, 100ms by default.
+  pub fn set_dwell(&mut self, dwell: Duration) {
+    self.dwell = dwell;
+  }
+
+  /// Makes the sweep take its time from `clock`, e.g. a `TestClock`.
+  pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+    self.clock = clock;
+  }
+
+  /// Returns the frequencies of the steps in Hz.
+  ///
+  /// ```
+  /// use libbeaglebone::sweep::{Sweep, SweepScale};
+  ///
+  /// let sweep = Sweep::new(100.0, 300.0, 3).unwrap();
+  /// assert_eq!(sweep.frequencies(), vec![100.0, 200.0, 300.0]);
+  ///
+  /// let mut sweep = Sweep::new(10.0, 1000.0, 3).unwrap();
+  /// sweep.set_scale(SweepScale::Logarithmic);
+  /// assert_eq!(sweep.frequencies(), vec![10.0, 100.0, 1000.0]);
+  /// ```
+  pub fn frequencies(&self) -> Vec<f32> {
+    let (start, stop) = (f64::from(self.start_hz), f64::from(self.stop_hz));
+    let last = (self.steps - 1) as f64;
+    (0..self.steps)
+      .map(|step| {
+        let t = step as f64 / last;
+        let hz = match self.scale {
+          SweepScale::Linear => start + (stop - start) * t,
+          SweepScale::Logarithmic => start * (stop / start).powf(t),
+        };
+        // Keep the ends exact despite the rounding of `powf()`.
+        (hz * 1e3).round() as f32 / 1e3
+      })
+      .collect()
+  }
+
+  /// Runs the sweep on `pwm`, a PWM output with any period, and returns the
+  /// steps.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the output can't be written; it's disabled then, too.
+  pub fn run<P: PwmOutput>(&self, pwm: &mut P) -> Result<Vec<SweepPoint>> {
+    self.sweep(pwm, None)
+  }
+
+  /// Runs the sweep on `pwm` like `run()`, taking `samples` samples from
+  /// `adc` at the end of every step.
+  ///
+  /// # Errors
+  ///
+  /// Fails if `samples` is 0, or if the output can't be written or the ADC
+  /// can't be read; the output is disabled then, too.
+  pub fn run_with_adc<P: PwmOutput>(&self, pwm: &mut P, adc: &ADC, samples: usize) -> Result<Vec<SweepPoint>> {
+    if samples == 0 {
+      bail!("A sweep needs at least one ADC sample per step");
+    }
+    self.sweep(pwm, Some((adc, samples)))
+  }
+
+  fn sweep<P: PwmOutput>(&self, pwm: &mut P, adc: Option<(&ADC, usize)>) -> Result<Vec<SweepPoint>> {
+    let result = self.steps(pwm, adc);
+    let disabled = pwm.set_enabled(false);
+    let points = result?;
+    disabled?;
+    Ok(points)
+  }
+
+  fn steps<P: PwmOutput>(&self, pwm: &mut P, adc: Option<(&ADC, usize)>) -> Result<Vec<SweepPoint>> {
+    let mut points = Vec::with_capacity(self.steps);
+    for (step, frequency_hz) in self.frequencies().into_iter().enumerate() {
+      // The duty cycle may never exceed the period, so it's cleared before
+      // the period changes.
+      pwm.set_duty_cycle_percent(0.0)?;
+      pwm.set_period_ns(period_ns(frequency_hz)?)?;
+      pwm.set_duty_cycle_percent(self.duty.percent())?;
+      if step == 0 {
+        pwm.set_enabled(true)?;
+      }
+      self.clock.sleep(self.dwell)?;
+      let levels = match adc {
+        Some((adc, samples)) => Some(sample(adc, samples)?),
+        None => None,
+      };
+      points.push(SweepPoint {
+        frequency_hz,
+        adc: levels,
+      });
+    }
+    Ok(points)
+  }
+}
+
+/// Returns the period of `hz` in nanoseconds.
+fn period_ns(hz: f32) -> Result<u32> {
+  let period = 1e9 / f64::from(hz);
+  if !(1.0..=f64::from(u32::MAX)).contains(&period) {
+    bail!(format!("Sweep frequency {}Hz is out of range", hz));
+  }
+  Ok(period.round() as u32)
+}
+
+/// Takes `samples` samples from `adc`.
+fn sample(adc: &ADC, samples: usize) -> Result<AdcLevels> {
+  let mut sum = 0.0;
+  let mut min_mv = f32::MAX;
+  let mut max_mv = f32::MIN;
+  for _ in 0..samples {
+    let mv = adc.read_millivolts()?;
+    sum += f64::from(mv);
+    min_mv = min_mv.min(mv);
+    max_mv = max_mv.max(mv);
+  }
+  Ok(AdcLevels {
+    mean_mv: (sum / samples as f64) as f32,
+    min_mv,
+    max_mv,
+  })
+}
@@ -0,0 +1,264 @@
+//! The tachometer module.
+//!
+//! Fans and engines commonly report their speed through a tach output, which
+//! pulses a fixed number of times per revolution (twice for most PC fans).
+//! The `Tachometer` type counts these pulses on a GPIO input and converts
+//! them into revolutions per minute.
+//!
+//! Pulses are detected using the kernel's GPIO edge interrupts, so the pin has
+//! to be configured as a GPIO beforehand using the `config-pin` command, e.g.
+//! `sudo config-pin P8.11 gpio`.
+//! The speed is measured between the kernel's timestamps of the pulses on a
+//! character device line, or between the times the pulses reached userspace
+//! over sysfs.
+//!
+//! On the pins of the eCAP units, e.g. P9.42, the pulses can be timestamped
+//! in hardware instead, see `Tachometer::from_capture()`.
+//!
+//! Note: most tach outputs are open-collector and need a pull-up resistor to
+//! 3.3V.
+//! Never connect a 5V or 12V tach line directly to the BeagleBone.
+
+use enums::DeviceState;
+use errors::*;
+use gpio::{Edge, GPIO, PinDirection};
+use pins::Pin;
+use pwm_capture::PWMCapture;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How long the counting thread waits for a pulse before checking whether it
+/// should stop.
+const POLL_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// The time between two pulses, with when it was measured, which tells
+/// whether it's within the window.
+#[derive(Debug, Clone, Copy)]
+struct Period {
+  received: Instant,
+  length: Duration,
+}
+
+/// Measures the speed of a fan or engine from its tach signal.
+#[derive(Debug)]
+pub struct Tachometer {
+  source: String,
+  pulses_per_rev: u32,
+  window: Duration,
+  periods: Arc<Mutex<VecDeque<Period>>>,
+  running: Arc<AtomicBool>,
+  thread: Option<JoinHandle<()>>,
+}
+
+impl Tachometer {
+  /// Creates a new tachometer on a GPIO pin and starts counting pulses.
+  ///
+  /// The pin is exported and configured as an input with rising edge
+  /// detection.
+  /// `pulses_per_rev` is the number of pulses the tach signal emits per
+  /// revolution, and `window` is the time span over which pulses are averaged
+  /// when computing the speed.
+  /// Longer windows give steadier readings but react slower to changes.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  /// use std::time::Duration;
+  ///
+  /// // Count a 2 pulse-per-revolution fan tach on pin #45, averaged over 1s.
+  /// let tach = Tachometer::new(GPIO_P8_11, 2, Duration::from_secs(1)).unwrap();
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if `pulses_per_rev` is zero, or if the pin can't be configured as
+  /// an edge-triggered GPIO input.
+  pub fn new(pin: Pin, pulses_per_rev: u32, window: Duration) -> Result<Tachometer> {
+    check_pulses_per_rev(pulses_per_rev)?;
+    let mut gpio = GPIO::new(pin);
+    gpio.set_export(DeviceState::Exported)?;
+    gpio.set_direction(PinDirection::In)?;
+    gpio.set_edge(Edge::Rising)?;
+
+    Ok(Tachometer::spawn(format!("GPIO pin #{}", pin as u8),
+                         pulses_per_rev,
+                         window,
+                         move |periods, running| count_pulses(gpio, window, periods, running)))
+  }
+
+  /// Creates a new tachometer on an eCAP unit and starts capturing pulses.
+  ///
+  /// The unit timestamps the pulses in hardware to 10ns, so the speed isn't
+  /// affected by interrupt latency; it captures one period at a time, and
+  /// the speed is averaged over the periods captured within the window.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  /// use libbeaglebone::pwm_capture::PWMCapture;
+  /// use std::time::Duration;
+  ///
+  /// // A fan's tach on eCAP0, pin P9.42 after `config-pin P9.42 pwm`.
+  /// let capture = PWMCapture::new(0).unwrap();
+  /// let tach = Tachometer::from_capture(capture, 2, Duration::from_secs(1)).unwrap();
+  /// println!("Fan speed: {} RPM", tach.rpm().unwrap());
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if `pulses_per_rev` is zero.
+  pub fn from_capture(capture: PWMCapture, pulses_per_rev: u32, window: Duration) -> Result<Tachometer> {
+    check_pulses_per_rev(pulses_per_rev)?;
+    Ok(Tachometer::spawn(format!("eCAP{}", capture.module()),
+                         pulses_per_rev,
+                         window,
+                         move |periods, running| capture_pulses(capture, window, periods, running)))
+  }
+
+  /// Starts `measure` on a thread of its own, recording the periods of the
+  /// tach signal.
+  fn spawn<F>(source: String, pulses_per_rev: u32, window: Duration, measure: F) -> Tachometer
+    where F: FnOnce(&Mutex<VecDeque<Period>>, &AtomicBool) + Send + 'static
+  {
+    let periods = Arc::new(Mutex::new(VecDeque::new()));
+    let running = Arc::new(AtomicBool::new(true));
+    let thread = {
+      let periods = periods.clone();
+      let running = running.clone();
+      thread::spawn(move || {
+        measure(&periods, &running);
+        running.store(false, Ordering::SeqCst);
+      })
+    };
+
+    Tachometer {
+      source,
+      pulses_per_rev,
+      window,
+      periods,
+      running,
+      thread: Some(thread),
+    }
+  }
+
+  /// Returns the speed in revolutions per minute, averaged over the window.
+  ///
+  /// Returns 0 if no period between two pulses was measured within the
+  /// window, i.e. the fan or engine is stopped or turning too slowly to
+  /// measure.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  /// use std::time::Duration;
+  ///
+  /// let tach = Tachometer::new(GPIO_P8_11, 2, Duration::from_secs(1)).unwrap();
+  ///
+  /// // Print the fan speed.
+  /// println!("Fan speed: {} RPM", tach.rpm().unwrap());
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if pulses could no longer be read from the pin or the eCAP unit.
+  pub fn rpm(&self) -> Result<f32> {
+    if !self.running.load(Ordering::SeqCst) {
+      bail!(format!(
+        "Tachometer on {} stopped counting pulses",
+        self.source
+      ));
+    }
+
+    let mut periods = self.periods.lock().unwrap();
+    prune(&mut periods, Instant::now(), self.window);
+    if periods.is_empty() {
+      return Ok(0.0);
+    }
+
+    // Measure over the full periods rather than the whole window, so
+    // partial periods at either end don't skew the result.
+    let span = periods.iter().map(|period| period.length).sum::<Duration>().as_secs_f32();
+    let revolutions = periods.len() as f32 / self.pulses_per_rev as f32;
+    Ok(revolutions / span * 60.0)
+  }
+}
+
+impl Drop for Tachometer {
+  fn drop(&mut self) {
+    self.running.store(false, Ordering::SeqCst);
+    if let Some(thread) = self.thread.take() {
+      let _ = thread.join();
+    }
+  }
+}
+
+fn check_pulses_per_rev(pulses_per_rev: u32) -> Result<()> {
+  if pulses_per_rev == 0 {
+    bail!("Tachometer pulses per revolution must be greater than zero");
+  }
+  Ok(())
+}
+
+/// Records the time between consecutive edges until `running` is cleared,
+/// or the pin can no longer be polled.
+fn count_pulses(mut gpio: GPIO, window: Duration, periods: &Mutex<VecDeque<Period>>, running: &AtomicBool) {
+  let mut last = None;
+  while running.load(Ordering::SeqCst) {
+    match gpio.wait_for_edge_timestamped(Some(POLL_TIMEOUT)) {
+      Ok(None) => continue,
+      Ok(Some((_, timestamp))) => {
+        if let Some(last) = last {
+          record(periods, timestamp.saturating_sub(last), window);
+        }
+        last = Some(timestamp);
+      }
+      Err(_) => break,
+    }
+  }
+}
+
+/// Records the periods the eCAP unit captures until `running` is cleared,
+/// or the unit captures nonsense.
+fn capture_pulses(mut capture: PWMCapture,
+                  window: Duration,
+                  periods: &Mutex<VecDeque<Period>>,
+                  running: &AtomicBool) {
+  while running.load(Ordering::SeqCst) {
+    match capture.measure(POLL_TIMEOUT) {
+      Ok(None) => continue,
+      Ok(Some(period)) => record(periods, period.period, window),
+      Err(_) => break,
+    }
+  }
+}
+
+/// Records a period just measured, unless it's longer than `window`, i.e.
+/// it spans a stop of the fan or engine.
+fn record(periods: &Mutex<VecDeque<Period>>, length: Duration, window: Duration) {
+  if length > window {
+    return;
+  }
+  let now = Instant::now();
+  let mut periods = periods.lock().unwrap();
+  periods.push_back(Period {
+                      received: now,
+                      length,
+                    });
+  prune(&mut periods, now, window);
+}
+
+/// Drops periods that were measured longer than `window` ago.
+fn prune(periods: &mut VecDeque<Period>, now: Instant, window: Duration) {
+  while let Some(&oldest) = periods.front() {
+    if now - oldest.received <= window {
+      break;
+    }
+    let _ = periods.pop_front();
+  }
+}
@@ -0,0 +1,766 @@
+//! The thermostat module.
+//!
+//! A `Thermostat` holds a temperature at a setpoint by driving a heater, or
+//! a cooler, from a `TemperatureSource`: a DS18B20 probe, see the `w1`
+//! module, a DHT sensor, an NTC thermistor on an ADC input or a closure
+//! reading anything else.
+//! It drives a `PowerOutput`, either a PWM output, whose duty cycle is the
+//! power, or a digital one switching e.g. a relay or an SSR:
+//!
+//! ```no_run
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::thermostat::{Control, Pid, SwitchedPower, Thermistor, Thermostat};
+//! use std::time::Duration;
+//!
+//! // A 10k NTC thermistor on AIN_0 and a heater behind an SSR on P8_12.
+//! let probe = Thermistor::new(AIN_0, 10_000.0, 10_000.0, 3950.0);
+//! let heater = SwitchedPower::new(GPIO_P8_12, PinState::High).unwrap();
+//! let mut thermostat = Thermostat::new(probe, heater, 55.0, Control::Pid(Pid::new(0.2, 0.005, 1.0)));
+//! thermostat.set_max_celsius(80.0);
+//!
+//! let running = thermostat.start();
+//! loop {
+//!   std::thread::sleep(Duration::from_secs(10));
+//!   println!("{:?}", running.status());
+//! }
+//! ```
+//!
+//! `Control::BangBang` switches the output fully on or off with a
+//! hysteresis, which suits relays and compressors; `Control::Pid` computes
+//! a power between 0 and 1, which a `SwitchedPower` turns into the share of
+//! a time window it's on for.
+//! The setpoint can follow a `Schedule`, e.g. a day's profile.
+//!
+//! The output is turned off whenever the temperature can't be read, and the
+//! thermostat latches a `Fault`, keeping it off until `reset_fault()`, when
+//! the temperature leaves the range set with `set_max_celsius()` and
+//! `set_min_celsius()`, or when the sensor failed several times in a row.
+//! A heater cut off by software can still fail on, e.g. with a welded relay
+//! or a crashed process, so it also needs a thermal fuse.
+
+use adc::ADC;
+use clock::{self, Clock};
+use errors::*;
+use gpio::{self, GPIO, PinState};
+use hal::{DigitalPin, PwmOutput};
+use pins::Pin;
+use pwm::PWM;
+use sensors::dht::Dht;
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use w1::DS18B20;
+
+/// The most errors a running thermostat keeps until they're taken.
+const MAX_ERRORS: usize = 100;
+
+/// The temperature where 0°C is, in kelvins.
+const ZERO_CELSIUS: f32 = 273.15;
+
+/// Something that measures a temperature.
+pub trait TemperatureSource {
+  /// Reads the temperature in °C.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the temperature can't be read.
+  fn celsius(&mut self) -> Result<f32>;
+}
+
+impl TemperatureSource for DS18B20 {
+  fn celsius(&mut self) -> Result<f32> {
+    self.read_celsius()
+  }
+}
+
+impl TemperatureSource for Dht {
+  fn celsius(&mut self) -> Result<f32> {
+    Ok(self.read()?.temperature)
+  }
+}
+
+impl<F: FnMut() -> Result<f32>> TemperatureSource for F {
+  fn celsius(&mut self) -> Result<f32> {
+    self()
+  }
+}
+
+/// An NTC thermistor on an ADC input, with a series resistor from the ADC's
+/// 1.8V reference to the input and the thermistor from the input to ground.
+#[derive(Debug)]
+pub struct Thermistor {
+  adc: ADC,
+  series_ohms: f32,
+  nominal_ohms: f32,
+  beta: f32,
+}
+
+impl Thermistor {
+  /// Reads a thermistor of `nominal_ohms` at 25°C with the B constant
+  /// `beta`, e.g. 3950, on the ADC input `pin` behind `series_ohms`.
+  ///
+  /// A series resistor of the nominal resistance puts 0.9V on the input at
+  /// 25°C.
+  pub fn new(pin: Pin, series_ohms: f32, nominal_ohms: f32, beta: f32) -> Thermistor {
+    Thermistor {
+      adc: ADC::new(pin, 0.0),
+      series_ohms,
+      nominal_ohms,
+      beta,
+    }
+  }
+
+  /// Returns the temperature in °C at which the input reads `volts`, using
+  /// the B parameter equation.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use libbeaglebone::prelude::*;
+  /// use libbeaglebone::thermostat::Thermistor;
+  ///
+  /// let thermistor = Thermistor::new(AIN_0, 10_000.0, 10_000.0, 3950.0);
+  /// assert!((thermistor.celsius_at(0.9) - 25.0).abs() < 0.01);
+  /// // Warmer, the thermistor's resistance and the voltage drop.
+  /// assert!(thermistor.celsius_at(0.5) > 40.0);
+  /// ```
+  pub fn celsius_at(&self, volts: f32) -> f32 {
+    let ohms = self.series_ohms * volts / (1.8 - volts);
+    let kelvins = 1.0 / (1.0 / (25.0 + ZERO_CELSIUS) + (ohms / self.nominal_ohms).ln() / self.beta);
+    kelvins - ZERO_CELSIUS
+  }
+
+  /// Returns the ADC input.
+  pub fn adc(&self) -> &ADC {
+    &self.adc
+  }
+}
+
+impl TemperatureSource for Thermistor {
+  fn celsius(&mut self) -> Result<f32> {
+    let volts = self.adc.read_volts()?;
+    // An open thermistor reads the reference, a shorted one ground.
+    if volts <= 0.0 || volts >= 1.8 {
+      bail!(format!("The thermistor on AIN_{} reads {}V, it's shorted or open", self.adc.adc_num(), volts));
+    }
+    Ok(self.celsius_at(volts))
+  }
+}
+
+/// An output driving a heater or a cooler at a power.
+pub trait PowerOutput {
+  /// Drives the output at `power`, from 0 for off to 1 for fully on.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the output can't be driven.
+  fn set_power(&mut self, power: f32) -> Result<()>;
+}
+
+/// A PWM output driving at a power of its duty cycle.
+#[derive(Debug)]
+pub struct PwmPower<P: PwmOutput = PWM> {
+  pwm: P,
+  enabled: bool,
+}
+
+impl<P: PwmOutput> PwmPower<P> {
+  /// Drives `pwm`, which is enabled at the first power set, with its period
+  /// as already set.
+  pub fn new(pwm: P) -> PwmPower<P> {
+    PwmPower {
+      pwm,
+      enabled: false,
+    }
+  }
+
+  /// Returns the PWM output.
+  pub fn get_ref(&self) -> &P {
+    &self.pwm
+  }
+
+  /// Unwraps the PWM output.
+  pub fn into_inner(self) -> P {
+    self.pwm
+  }
+}
+
+impl<P: PwmOutput> PowerOutput for PwmPower<P> {
+  fn set_power(&mut self, power: f32) -> Result<()> {
+    self.pwm.set_duty_cycle_percent(power.clamp(0.0, 1.0) * 100.0)?;
+    if !self.enabled {
+      self.pwm.set_enabled(true)?;
+      self.enabled = true;
+    }
+    Ok(())
+  }
+}
+
+/// A digital output, by default a GPIO, driving at a power by the share of
+/// a time window it's on for.
+///
+/// The output is switched when the power is set, so the thermostat's poll
+/// interval should be a small part of the window.
+#[derive(Debug)]
+pub struct SwitchedPower<P: DigitalPin = GPIO> {
+  pin: P,
+  active: PinState,
+  window: Duration,
+  window_start: Option<Duration>,
+  clock: Arc<dyn Clock>,
+}
+
+impl SwitchedPower<GPIO> {
+  /// Switches the GPIO `pin`, which is exported and made an output driving
+  /// off, on at the level `active`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the pin can't be configured as an output.
+  pub fn new(pin: Pin, active: PinState) -> Result<SwitchedPower> {
+    let off = match active {
+      PinState::High => PinState::Low,
+      PinState::Low => PinState::High,
+    };
+    Ok(SwitchedPower::from_pin(gpio::output_driving(pin, off)?, active))
+  }
+}
+
+impl<P: DigitalPin> SwitchedPower<P> {
+  /// Switches `pin`, on at the level `active`, in windows of 10s.
+  pub fn from_pin(pin: P, active: PinState) -> SwitchedPower<P> {
+    SwitchedPower {
+      pin,
+      active,
+      window: Duration::from_secs(10),
+      window_start: None,
+      clock: clock::system(),
+    }
+  }
+
+  /// Sets the time window, 10s by default; longer windows switch less
+  /// often.
+  pub fn set_window(&mut self, window: Duration) {
+    self.window = window;
+  }
+
+  /// Makes the output take its time from `clock`, e.g. a `TestClock`.
+  pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+    self.clock = clock;
+  }
+
+  /// Returns the pin.
+  pub fn get_ref(&self) -> &P {
+    &self.pin
+  }
+
+  /// Unwraps the pin.
+  pub fn into_inner(self) -> P {
+    self.pin
+  }
+}
+
+impl<P: DigitalPin> PowerOutput for SwitchedPower<P> {
+  fn set_power(&mut self, power: f32) -> Result<()> {
+    let now = self.clock.now();
+    let start = match self.window_start {
+      Some(start) if now < start + self.window => start,
+      _ => now,
+    };
+    self.window_start = Some(start);
+    let on = power >= 1.0 || (power > 0.0 && (now - start).as_secs_f32() < power * self.window.as_secs_f32());
+    let state = match (on, self.active) {
+      (true, active) => active,
+      (false, PinState::High) => PinState::Low,
+      (false, PinState::Low) => PinState::High,
+    };
+    self.pin.set_state(state)
+  }
+}
+
+/// A PID controller with an output from 0 to 1.
+///
+/// The derivative is taken of the measurement, so changing the setpoint
+/// doesn't kick the output, and the integral is limited to what drives the
+/// output from 0 to 1, so it doesn't wind up while the output is saturated.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pid {
+  kp: f32,
+  ki: f32,
+  kd: f32,
+  integral: f32,
+  last_measured: Option<f32>,
+}
+
+impl Pid {
+  /// Creates a controller with the gains `kp` per °C, `ki` per °C and
+  /// second and `kd` per °C per second.
+  pub fn new(kp: f32, ki: f32, kd: f32) -> Pid {
+    Pid {
+      kp,
+      ki,
+      kd,
+      integral: 0.0,
+      last_measured: None,
+    }
+  }
+
+  /// Returns the output for `measured` at `setpoint`, `dt` after the last
+  /// update.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use libbeaglebone::thermostat::Pid;
+  /// use std::time::Duration;
+  ///
+  /// let mut pid = Pid::new(0.25, 0.0, 0.0);
+  /// assert_eq!(pid.update(50.0, 48.0, Duration::from_secs(1)), 0.5);
+  /// // Limited to fully on, and off above the setpoint.
+  /// assert_eq!(pid.update(50.0, 40.0, Duration::from_secs(1)), 1.0);
+  /// assert_eq!(pid.update(50.0, 52.0, Duration::from_secs(1)), 0.0);
+  /// ```
+  pub fn update(&mut self, setpoint: f32, measured: f32, dt: Duration) -> f32 {
+    let error = setpoint - measured;
+    let secs = dt.as_secs_f32();
+    let derivative = match self.last_measured {
+      Some(last) if secs > 0.0 => (last - measured) / secs,
+      _ => 0.0,
+    };
+    self.last_measured = Some(measured);
+    if self.ki > 0.0 {
+      self.integral = (self.integral + error * secs).clamp(0.0, 1.0 / self.ki);
+    }
+    (self.kp * error + self.ki * self.integral + self.kd * derivative).clamp(0.0, 1.0)
+  }
+
+  /// Clears the integral and the last measurement.
+  pub fn reset(&mut self) {
+    self.integral = 0.0;
+    self.last_measured = None;
+  }
+}
+
+/// How a thermostat computes the power of its output.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Control {
+  /// Fully on below the setpoint by more than half of `hysteresis` (in °C),
+  /// fully off above it by more than half of it, and unchanged in between.
+  BangBang {
+    /// The width of the band around the setpoint.
+    hysteresis: f32,
+  },
+  /// A power from a PID controller.
+  Pid(Pid),
+}
+
+/// Whether a thermostat drives a heater or a cooler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+  /// The output heats, so it's on below the setpoint.
+  Heat,
+  /// The output cools, so it's on above the setpoint.
+  Cool,
+}
+
+/// Setpoints over time, each from an offset after the schedule started on.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Schedule {
+  // Sorted by their offset.
+  setpoints: Vec<(Duration, f32)>,
+  period: Option<Duration>,
+}
+
+impl Schedule {
+  /// Creates an empty schedule, which doesn't repeat.
+  pub fn new() -> Schedule {
+    Schedule::default()
+  }
+
+  /// Adds `celsius` as the setpoint from `offset` after the start on.
+  pub fn add(&mut self, offset: Duration, celsius: f32) {
+    let index = self.setpoints.iter().take_while(|&&(at, _)| at <= offset).count();
+    self.setpoints.insert(index, (offset, celsius));
+  }
+
+  /// Makes the schedule start over every `period`, e.g. a day, or run once
+  /// with `None`.
+  pub fn set_period(&mut self, period: Option<Duration>) {
+    self.period = period;
+  }
+
+  /// Returns the setpoint `elapsed` after the start, if one was set by
+  /// then.
+  ///
+  /// A repeating schedule keeps the last setpoint of a period until the
+  /// first one of the next.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use libbeaglebone::thermostat::Schedule;
+  /// use std::time::Duration;
+  ///
+  /// let hours = |hours: u64| Duration::from_secs(hours * 3600);
+  /// let mut schedule = Schedule::new();
+  /// schedule.add(hours(7), 21.0);
+  /// schedule.add(hours(22), 17.0);
+  /// assert_eq!(schedule.setpoint_at(hours(6)), None);
+  /// assert_eq!(schedule.setpoint_at(hours(12)), Some(21.0));
+  ///
+  /// schedule.set_period(Some(hours(24)));
+  /// assert_eq!(schedule.setpoint_at(hours(24 + 6)), Some(17.0));
+  /// ```
+  pub fn setpoint_at(&self, elapsed: Duration) -> Option<f32> {
+    let elapsed = match self.period {
+      Some(period) if period > Duration::from_secs(0) => {
+        Duration::from_nanos((elapsed.as_nanos() % period.as_nanos()) as u64)
+      }
+      _ => elapsed,
+    };
+    match self.setpoints.iter().rev().find(|&&(at, _)| at <= elapsed) {
+      Some(&(_, celsius)) => Some(celsius),
+      None if self.period.is_some() => self.setpoints.last().map(|&(_, celsius)| celsius),
+      None => None,
+    }
+  }
+
+  /// Returns whether no setpoint was added.
+  pub fn is_empty(&self) -> bool {
+    self.setpoints.is_empty()
+  }
+}
+
+/// Why a thermostat turned its output off until reset.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fault {
+  /// The temperature reached the maximum, at the temperature in °C.
+  OverTemperature(f32),
+  /// The temperature reached the minimum, at the temperature in °C.
+  UnderTemperature(f32),
+  /// The temperature couldn't be read several times in a row.
+  SensorFailure,
+}
+
+/// The state of a thermostat after a step.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Status {
+  /// The temperature in °C.
+  pub temperature: f32,
+  /// The setpoint in °C.
+  pub setpoint: f32,
+  /// The power the output was set to.
+  pub power: f32,
+  /// The latched fault, if any.
+  pub fault: Option<Fault>,
+}
+
+/// Holds a temperature at a setpoint.
+#[derive(Debug)]
+pub struct Thermostat<S: TemperatureSource, O: PowerOutput> {
+  source: S,
+  output: O,
+  control: Control,
+  mode: Mode,
+  setpoint: f32,
+  schedule: Schedule,
+  schedule_start: Duration,
+  max_celsius: Option<f32>,
+  min_celsius: Option<f32>,
+  max_read_failures: u32,
+  poll_interval: Duration,
+  clock: Arc<dyn Clock>,
+  // Whether a bang-bang output is on.
+  on: bool,
+  read_failures: u32,
+  last_step: Option<Duration>,
+  fault: Option<Fault>,
+  status: Option<Status>,
+}
+
+impl<S: TemperatureSource, O: PowerOutput> Thermostat<S, O> {
+  /// Holds the temperature of `source` at `setpoint` in °C, heating with
+  /// `output` as `control` computes.
+  ///
+  /// By default, there are no temperature limits, and three failed
+  /// readings in a row latch a fault.
+  pub fn new(source: S, output: O, setpoint: f32, control: Control) -> Thermostat<S, O> {
+    let clock = clock::system();
+    Thermostat {
+      source,
+      output,
+      control,
+      mode: Mode::Heat,
+      setpoint,
+      schedule: Schedule::new(),
+      schedule_start: clock.now(),
+      max_celsius: None,
+      min_celsius: None,
+      max_read_failures: 3,
+      poll_interval: Duration::from_secs(1),
+      clock,
+      on: false,
+      read_failures: 0,
+      last_step: None,
+      fault: None,
+      status: None,
+    }
+  }
+
+  /// Sets whether the output heats, which it does by default, or cools.
+  pub fn set_mode(&mut self, mode: Mode) {
+    self.mode = mode;
+    self.reset_control();
+  }
+
+  /// Sets how the power is computed.
+  pub fn set_control(&mut self, control: Control) {
+    self.control = control;
+    self.reset_control();
+  }
+
+  /// Sets the setpoint in °C, used when there's no schedule or before its
+  /// first setpoint.
+  pub fn set_setpoint(&mut self, celsius: f32) {
+    self.setpoint = celsius;
+  }
+
+  /// Makes the setpoint follow `schedule`, starting now.
+  pub fn set_schedule(&mut self, schedule: Schedule) {
+    self.schedule = schedule;
+    self.schedule_start = self.clock.now();
+  }
+
+  /// Returns the current setpoint in °C.
+  pub fn setpoint(&self) -> f32 {
+    self.schedule
+        .setpoint_at(self.clock.now() - self.schedule_start)
+        .unwrap_or(self.setpoint)
+  }
+
+  /// Faults at temperatures from `celsius` on, e.g. to protect what's
+  /// heated, or a cooler's sensor stuck on a hot reading.
+  pub fn set_max_celsius(&mut self, celsius: f32) {
+    self.max_celsius = Some(celsius);
+  }
+
+  /// Faults at temperatures down to `celsius`, e.g. to catch a probe that
+  /// fell out of what it heats.
+  pub fn set_min_celsius(&mut self, celsius: f32) {
+    self.min_celsius = Some(celsius);
+  }
+
+  /// Sets after how many failed readings in a row a fault is latched, 3 by
+  /// default; the output is turned off at each.
+  pub fn set_max_read_failures(&mut self, failures: u32) {
+    self.max_read_failures = failures;
+  }
+
+  /// Sets how often a started thermostat steps, every second by default.
+  pub fn set_poll_interval(&mut self, interval: Duration) {
+    self.poll_interval = interval;
+  }
+
+  /// Makes the thermostat take its time from `clock`, e.g. a `TestClock`.
+  pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+    self.schedule_start = clock.now();
+    self.clock = clock;
+  }
+
+  /// Returns the latched fault, if any.
+  pub fn fault(&self) -> Option<Fault> {
+    self.fault
+  }
+
+  /// Clears the latched fault, so the next step drives the output again.
+  pub fn reset_fault(&mut self) {
+    self.fault = None;
+    self.read_failures = 0;
+    self.reset_control();
+  }
+
+  /// Returns the state after the last successful step.
+  pub fn status(&self) -> Option<Status> {
+    self.status
+  }
+
+  /// Reads the temperature, checks the limits, and drives the output.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the temperature can't be read, after turning the output off,
+  /// or if the output can't be driven.
+  pub fn step(&mut self) -> Result<Status> {
+    let now = self.clock.now();
+    let setpoint = self.setpoint();
+    let temperature = match self.source.celsius() {
+      Ok(temperature) if temperature.is_finite() => temperature,
+      read => {
+        self.read_failures += 1;
+        if self.read_failures >= self.max_read_failures {
+          self.fault = Some(Fault::SensorFailure);
+        }
+        self.reset_control();
+        self.output
+            .set_power(0.0)
+            .chain_err(|| "Failed to turn the output off when the temperature couldn't be read")?;
+        return match read {
+          Err(e) => Err(Error::with_chain(e, "Failed to read the temperature, turned the output off")),
+          Ok(temperature) => bail!(format!("Read a temperature of {}, turned the output off", temperature)),
+        };
+      }
+    };
+    self.read_failures = 0;
+
+    if self.fault.is_none() {
+      if self.max_celsius.is_some_and(|max| temperature >= max) {
+        self.fault = Some(Fault::OverTemperature(temperature));
+      } else if self.min_celsius.is_some_and(|min| temperature <= min) {
+        self.fault = Some(Fault::UnderTemperature(temperature));
+      }
+    }
+    let power = if self.fault.is_some() {
+      self.reset_control();
+      0.0
+    } else {
+      let dt = self.last_step.map_or(Duration::from_secs(0), |last| now - last);
+      self.last_step = Some(now);
+      self.power(temperature, setpoint, dt)
+    };
+    self.output.set_power(power)?;
+    let status = Status {
+      temperature,
+      setpoint,
+      power,
+      fault: self.fault,
+    };
+    self.status = Some(status);
+    Ok(status)
+  }
+
+  /// Returns the temperature source.
+  pub fn source(&self) -> &S {
+    &self.source
+  }
+
+  /// Returns the output.
+  pub fn output(&self) -> &O {
+    &self.output
+  }
+
+  /// Unwraps the temperature source and the output.
+  pub fn into_inner(self) -> (S, O) {
+    (self.source, self.output)
+  }
+
+  /// Returns the power to drive at `temperature`.
+  fn power(&mut self, temperature: f32, setpoint: f32, dt: Duration) -> f32 {
+    // Cooling works like heating a negated temperature.
+    let (temperature, setpoint) = match self.mode {
+      Mode::Heat => (temperature, setpoint),
+      Mode::Cool => (-temperature, -setpoint),
+    };
+    match self.control {
+      Control::BangBang { hysteresis } => {
+        if temperature < setpoint - hysteresis / 2.0 {
+          self.on = true;
+        } else if temperature > setpoint + hysteresis / 2.0 {
+          self.on = false;
+        }
+        if self.on { 1.0 } else { 0.0 }
+      }
+      Control::Pid(ref mut pid) => pid.update(setpoint, temperature, dt),
+    }
+  }
+
+  /// Starts the control over, as after the output was off.
+  fn reset_control(&mut self) {
+    self.on = false;
+    self.last_step = None;
+    if let Control::Pid(ref mut pid) = self.control {
+      pid.reset();
+    }
+  }
+}
+
+impl<S: TemperatureSource + Send + 'static, O: PowerOutput + Send + 'static> Thermostat<S, O> {
+  /// Steps every poll interval on a thread of its own, until the returned
+  /// thermostat is dropped, which turns the output off.
+  pub fn start(mut self) -> RunningThermostat {
+    let status = Arc::new(Mutex::new(None));
+    let errors = Arc::new(Mutex::new(VecDeque::new()));
+    let (stop, stopped) = mpsc::channel::<()>();
+    let thread = {
+      let status = status.clone();
+      let errors = errors.clone();
+      thread::spawn(move || {
+        loop {
+          let error = match self.step() {
+            Ok(step) => {
+              *lock(&status) = Some(step);
+              None
+            }
+            Err(e) => Some(e),
+          };
+          if let Some(e) = error {
+            let mut errors = lock(&errors);
+            if errors.len() == MAX_ERRORS {
+              let _ = errors.pop_front();
+            }
+            errors.push_back(e);
+          }
+          if stopped.recv_timeout(self.poll_interval) != Err(RecvTimeoutError::Timeout) {
+            break;
+          }
+        }
+        if let Err(e) = self.output.set_power(0.0) {
+          lock(&errors).push_back(Error::with_chain(e, "Failed to turn the output off when stopping"));
+        }
+      })
+    };
+    RunningThermostat {
+      status,
+      errors,
+      stop: Some(stop),
+      thread: Some(thread),
+    }
+  }
+}
+
+/// A started thermostat, stopped when dropped.
+#[derive(Debug)]
+pub struct RunningThermostat {
+  status: Arc<Mutex<Option<Status>>>,
+  errors: Arc<Mutex<VecDeque<Error>>>,
+  stop: Option<Sender<()>>,
+  thread: Option<JoinHandle<()>>,
+}
+
+impl RunningThermostat {
+  /// Returns the state after the last successful step.
+  pub fn status(&self) -> Option<Status> {
+    *lock(&self.status)
+  }
+
+  /// Returns and clears the errors of the steps, at most the latest 100.
+  pub fn take_errors(&self) -> Vec<Error> {
+    mem::take(&mut *lock(&self.errors)).into_iter().collect()
+  }
+}
+
+impl Drop for RunningThermostat {
+  fn drop(&mut self) {
+    // Dropping the sender wakes the thread.
+    self.stop = None;
+    if let Some(thread) = self.thread.take() {
+      let _ = thread.join();
+    }
+  }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+  mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
@@ -0,0 +1,247 @@
+//! The timing module.
+//!
+//! Software-timed outputs like a software PWM need to wake up at precise
+//! points in time, but a plain `thread::sleep()` in a loop drifts by the time
+//! spent outside of the sleep and wakes up late by a varying scheduler
+//! latency.
+//! `PeriodicTimer` avoids both:
+//!
+//! * It sleeps until absolute deadlines on the monotonic clock, so the time
+//!   spent between waits doesn't accumulate.
+//! * It learns how late the kernel wakes it up, wakes up that much early and
+//!   busy-waits for the rest, trading a bit of CPU time for jitter.
+//! * It keeps jitter statistics, so an application can tell whether the
+//!   system keeps up.
+//!
+//! For the best results, run the timing thread with a real-time scheduling
+//! policy, e.g. with `chrt -f 50`.
+
+use clock::{self, Clock};
+use errors::*;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The share of each new latency measurement in the latency estimate.
+const LATENCY_SMOOTHING: f64 = 0.125;
+
+/// Statistics about how late a `PeriodicTimer` woke up.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct JitterStats {
+  /// The number of wake-ups the statistics are based on.
+  pub wakeups: u64,
+  /// The number of periods that were skipped because a wait started after
+  /// the deadline had already passed by more than a period.
+  pub overruns: u64,
+  /// The smallest lateness.
+  pub min_latency: Duration,
+  /// The largest lateness.
+  pub max_latency: Duration,
+  /// The mean lateness.
+  pub mean_latency: Duration,
+  /// The standard deviation of the lateness, i.e. the jitter.
+  pub jitter: Duration,
+}
+
+/// Wakes up periodically at absolute deadlines.
+#[derive(Debug)]
+pub struct PeriodicTimer {
+  clock: Arc<dyn Clock>,
+  period: Duration,
+  cycle_start: Duration,
+  spin_threshold: Duration,
+  // The estimated time between a deadline and the kernel waking us up.
+  wakeup_latency_ns: f64,
+  wakeups: u64,
+  overruns: u64,
+  min_latency: Duration,
+  max_latency: Duration,
+  // Running mean and sum of squared differences of the lateness in ns.
+  mean_ns: f64,
+  m2_ns: f64,
+}
+
+impl PeriodicTimer {
+  /// Creates a new timer whose first period starts now.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::timing::PeriodicTimer;
+  /// use std::time::Duration;
+  ///
+  /// // Run a control loop at 200Hz.
+  /// let mut timer = PeriodicTimer::new(Duration::from_millis(5)).unwrap();
+  /// loop {
+  ///   // ... do the work of one cycle ...
+  ///   timer.wait().unwrap();
+  /// }
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if `period` is zero.
+  pub fn new(period: Duration) -> Result<PeriodicTimer> {
+    PeriodicTimer::with_clock(period, clock::system())
+  }
+
+  /// Creates a new timer like `new()` that takes its time from `clock`,
+  /// e.g. a `TestClock`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if `period` is zero.
+  pub fn with_clock(period: Duration, clock: Arc<dyn Clock>) -> Result<PeriodicTimer> {
+    if period == Duration::from_secs(0) {
+      bail!("Timer period can't be zero");
+    }
+    let mut timer = PeriodicTimer {
+      cycle_start: clock.now(),
+      clock,
+      period,
+      spin_threshold: Duration::from_secs(0),
+      wakeup_latency_ns: 0.0,
+      wakeups: 0,
+      overruns: 0,
+      min_latency: Duration::from_secs(0),
+      max_latency: Duration::from_secs(0),
+      mean_ns: 0.0,
+      m2_ns: 0.0,
+    };
+    timer.reset_stats();
+    Ok(timer)
+  }
+
+  /// Returns the period of the timer.
+  pub fn period(&self) -> Duration {
+    self.period
+  }
+
+  /// Changes the period, taking effect with the next period.
+  ///
+  /// # Errors
+  ///
+  /// Fails if `period` is zero.
+  pub fn set_period(&mut self, period: Duration) -> Result<()> {
+    if period == Duration::from_secs(0) {
+      bail!("Timer period can't be zero");
+    }
+    self.period = period;
+    Ok(())
+  }
+
+  /// Makes the timer busy-wait for the last `spin_threshold` before each
+  /// deadline, on top of the learned wake-up latency.
+  ///
+  /// Larger values reduce jitter on a busy system, but use more CPU time.
+  /// Defaults to zero.
+  pub fn set_spin_threshold(&mut self, spin_threshold: Duration) {
+    self.spin_threshold = spin_threshold;
+  }
+
+  /// Waits for the start of the next period and returns how late it woke up.
+  ///
+  /// If the deadline passed by more than a period already, e.g. because the
+  /// work of a cycle took too long, the missed periods are skipped instead
+  /// of being run in a burst, and counted as overruns.
+  pub fn wait(&mut self) -> Result<Duration> {
+    let mut next = self.cycle_start + self.period;
+    let now = self.clock.now();
+    while now >= next + self.period {
+      next += self.period;
+      self.overruns += 1;
+    }
+    let latency = self.wait_until(next)?;
+    self.cycle_start = next;
+    Ok(latency)
+  }
+
+  /// Waits until `offset` into the current period and returns how late it
+  /// woke up, e.g. for the falling edge of a software PWM.
+  ///
+  /// Returns right away if that point has passed already.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  /// use libbeaglebone::timing::PeriodicTimer;
+  /// use std::time::Duration;
+  ///
+  /// let mut led = GPIO::new(GPIO_P8_11);
+  /// led.set_export(DeviceState::Exported).unwrap();
+  /// led.set_direction(PinDirection::Out).unwrap();
+  ///
+  /// // A 200Hz software PWM at 30% duty cycle.
+  /// let mut timer = PeriodicTimer::new(Duration::from_millis(5)).unwrap();
+  /// loop {
+  ///   led.write(PinState::High).unwrap();
+  ///   timer.wait_offset(Duration::from_micros(1500)).unwrap();
+  ///   led.write(PinState::Low).unwrap();
+  ///   timer.wait().unwrap();
+  /// }
+  /// ```
+  pub fn wait_offset(&mut self, offset: Duration) -> Result<Duration> {
+    let deadline = self.cycle_start + offset;
+    self.wait_until(deadline)
+  }
+
+  /// Returns the jitter statistics since the timer was created or the
+  /// statistics were reset.
+  pub fn stats(&self) -> JitterStats {
+    let variance = if self.wakeups > 1 {
+      self.m2_ns / (self.wakeups - 1) as f64
+    } else {
+      0.0
+    };
+    JitterStats {
+      wakeups: self.wakeups,
+      overruns: self.overruns,
+      min_latency: if self.wakeups > 0 {
+        self.min_latency
+      } else {
+        Duration::from_secs(0)
+      },
+      max_latency: self.max_latency,
+      mean_latency: Duration::from_nanos(self.mean_ns as u64),
+      jitter: Duration::from_nanos(variance.sqrt() as u64),
+    }
+  }
+
+  /// Resets the jitter statistics.
+  pub fn reset_stats(&mut self) {
+    self.wakeups = 0;
+    self.overruns = 0;
+    self.min_latency = Duration::new(u64::MAX, 0);
+    self.max_latency = Duration::from_secs(0);
+    self.mean_ns = 0.0;
+    self.m2_ns = 0.0;
+  }
+
+  fn wait_until(&mut self, deadline: Duration) -> Result<Duration> {
+    let early = self.spin_threshold + Duration::from_nanos(self.wakeup_latency_ns as u64);
+    let wake = deadline.checked_sub(early).unwrap_or(deadline);
+    if self.clock.now() < wake {
+      self.clock.sleep_until(wake)?;
+      // Learn how late the kernel wakes us up.
+      let overshoot = self.clock.elapsed_since(wake);
+      self.wakeup_latency_ns += (overshoot.as_nanos() as f64 - self.wakeup_latency_ns) *
+                                LATENCY_SMOOTHING;
+    }
+
+    self.clock.spin_until(deadline);
+    let latency = self.clock.elapsed_since(deadline);
+    self.record(latency);
+    Ok(latency)
+  }
+
+  fn record(&mut self, latency: Duration) {
+    self.wakeups += 1;
+    self.min_latency = self.min_latency.min(latency);
+    self.max_latency = self.max_latency.max(latency);
+    // Welford's online algorithm.
+    let ns = latency.as_nanos() as f64;
+    let delta = ns - self.mean_ns;
+    self.mean_ns += delta / self.wakeups as f64;
+    self.m2_ns += delta * (ns - self.mean_ns);
+  }
+}
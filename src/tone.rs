@@ -0,0 +1,170 @@
+//! The tone module.
+//!
+//! A piezo buzzer sounds at the frequency of the square wave driving it,
+//! loudest at a duty cycle of 50%.
+//! `Buzzer` does the period and duty cycle math, so alarms and key clicks
+//! are a matter of frequencies and durations:
+//!
+//! ```no_run
+//! use libbeaglebone::tone::Buzzer;
+//! use std::time::Duration;
+//!
+//! // A buzzer on EHRPWM1A, i.e. pin P9.14 after `config-pin P9.14 pwm`.
+//! let mut buzzer = Buzzer::new(3, 0).unwrap();
+//!
+//! // A short beep at 2kHz.
+//! buzzer.play(2000.0, Duration::from_millis(100)).unwrap();
+//!
+//! // An alarm: two tones with a pause between them.
+//! buzzer.play_sequence(&[(880.0, Duration::from_millis(200)),
+//!                        (0.0, Duration::from_millis(50)),
+//!                        (660.0, Duration::from_millis(200))])
+//!       .unwrap();
+//!
+//! // Or a tone until told otherwise.
+//! buzzer.start(440.0).unwrap();
+//! // ...
+//! buzzer.stop().unwrap();
+//! ```
+
+use clock::{self, Clock};
+use enums::DeviceState;
+use errors::*;
+use hal::PwmOutput;
+use pwm::{PWM, PWMState};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A buzzer driven by a PWM output, by default an on-chip PWM.
+#[derive(Debug)]
+pub struct Buzzer<P: PwmOutput = PWM> {
+  pwm: P,
+  clock: Arc<dyn Clock>,
+  // The frequency sounding, if any.
+  frequency_hz: Option<f32>,
+}
+
+impl Buzzer<PWM> {
+  /// Creates a silent buzzer on PWM `pwm_num` of chip `pwm_chip_num`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the PWM can't be exported or disabled.
+  pub fn new(pwm_chip_num: u8, pwm_num: u8) -> Result<Buzzer> {
+    let mut pwm = PWM::new(pwm_chip_num, pwm_num);
+    pwm.set_export(DeviceState::Exported)?;
+    pwm.set_state(PWMState::Disabled)?;
+    Ok(Buzzer::from_pwm(pwm))
+  }
+}
+
+impl<P: PwmOutput> Buzzer<P> {
+  /// Creates a buzzer on a PWM output, which is assumed to be disabled.
+  pub fn from_pwm(pwm: P) -> Buzzer<P> {
+    Buzzer {
+      pwm,
+      clock: clock::system(),
+      frequency_hz: None,
+    }
+  }
+
+  /// Makes the buzzer take its time from `clock`, e.g. a `TestClock`.
+  pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+    self.clock = clock;
+  }
+
+  /// Returns the frequency sounding in Hz, or `None` if the buzzer is
+  /// silent.
+  pub fn frequency(&self) -> Option<f32> {
+    self.frequency_hz
+  }
+
+  /// Sounds a tone at `frequency_hz` until `stop()` or the next tone.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the period of the frequency isn't between 1ns and `u32::MAX`
+  /// ns, or if the PWM can't be written.
+  pub fn start(&mut self, frequency_hz: f32) -> Result<()> {
+    let period = 1e9 / f64::from(frequency_hz);
+    if !(1.0..=f64::from(u32::MAX)).contains(&period) {
+      bail!(format!("Tone frequency {}Hz is out of range", frequency_hz));
+    }
+    if self.frequency_hz == Some(frequency_hz) {
+      return Ok(());
+    }
+    // The duty cycle may never exceed the period, so it's cleared before
+    // the period changes.
+    self.pwm.set_duty_cycle_percent(0.0)?;
+    self.pwm.set_period_ns(period.round() as u32)?;
+    self.pwm.set_duty_cycle_percent(50.0)?;
+    if self.frequency_hz.is_none() {
+      self.pwm.set_enabled(true)?;
+    }
+    self.frequency_hz = Some(frequency_hz);
+    Ok(())
+  }
+
+  /// Silences the buzzer.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the PWM can't be disabled.
+  pub fn stop(&mut self) -> Result<()> {
+    self.pwm.set_enabled(false)?;
+    self.frequency_hz = None;
+    Ok(())
+  }
+
+  /// Sounds a tone at `frequency_hz` for `duration`, then silences the
+  /// buzzer.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the frequency is out of range, see `start()`, or if the PWM
+  /// can't be written.
+  pub fn play(&mut self, frequency_hz: f32, duration: Duration) -> Result<()> {
+    self.start(frequency_hz)?;
+    self.clock.sleep(duration)?;
+    self.stop()
+  }
+
+  /// Plays tones given as frequency and duration one after the other, with
+  /// a frequency of 0 for a pause, then silences the buzzer.
+  ///
+  /// Consecutive tones follow each other without a gap.
+  ///
+  /// # Errors
+  ///
+  /// Fails if a frequency is out of range, see `start()`, or if the PWM
+  /// can't be written; the buzzer is silenced then, too.
+  pub fn play_sequence(&mut self, tones: &[(f32, Duration)]) -> Result<()> {
+    let result = self.sequence(tones);
+    let stopped = self.stop();
+    result.and(stopped)
+  }
+
+  /// Returns the PWM output.
+  pub fn get_ref(&self) -> &P {
+    &self.pwm
+  }
+
+  /// Unwraps the PWM output.
+  pub fn into_inner(self) -> P {
+    self.pwm
+  }
+
+  fn sequence(&mut self, tones: &[(f32, Duration)]) -> Result<()> {
+    for &(frequency_hz, duration) in tones {
+      if frequency_hz == 0.0 {
+        if self.frequency_hz.is_some() {
+          self.stop()?;
+        }
+      } else {
+        self.start(frequency_hz)?;
+      }
+      self.clock.sleep(duration)?;
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,280 @@
+//! The transaction module.
+//!
+//! Configuring hardware usually takes several steps on several devices, and
+//! an error half-way, e.g. a pin claimed by another overlay, leaves the
+//! hardware in a state nobody intended: a motor driver enabled, but its PWM
+//! not configured.
+//! A `Transaction` stages the changes and applies them all, or, on the first
+//! failure, restores the devices it got to to their earlier configuration:
+//!
+//! ```no_run
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::transaction::{GpioChange, PwmChange, Transaction};
+//!
+//! let mut enable = GPIO::new(GPIO_P8_11);
+//! let mut motor = PWM::new(0, 0);
+//!
+//! let mut transaction = Transaction::new();
+//! transaction.stage(&mut motor, vec![PwmChange::Export(DeviceState::Exported),
+//!                                    PwmChange::Period(50_000),
+//!                                    PwmChange::DutyCycle(0),
+//!                                    PwmChange::State(PWMState::Enabled)]);
+//! transaction.stage(&mut enable, vec![GpioChange::Export(DeviceState::Exported),
+//!                                     GpioChange::Direction(PinDirection::Out),
+//!                                     GpioChange::State(PinState::High)]);
+//! transaction.apply().unwrap();
+//! ```
+//!
+//! Devices are configured in the order they were staged, and restored in
+//! the reverse order.
+//! Other devices can be included by implementing `Transactional` for them.
+
+use enums::DeviceState;
+use errors::*;
+use gpio::{GPIO, PinDirection, PinState};
+use pwm::{PWM, PWMPolarity, PWMState};
+use std::fmt;
+
+/// A device whose configuration can be changed in a transaction.
+pub trait Transactional: fmt::Debug {
+  /// A change of the configuration.
+  type Change: fmt::Debug;
+  /// The configuration before the changes.
+  type Snapshot: fmt::Debug;
+
+  /// Records the current configuration.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the configuration can't be read.
+  fn snapshot(&self) -> Result<Self::Snapshot>;
+
+  /// Applies a change.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the change can't be applied.
+  fn apply(&mut self, change: &Self::Change) -> Result<()>;
+
+  /// Restores the configuration recorded by `snapshot()`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the configuration can't be restored.
+  fn restore(&mut self, snapshot: &Self::Snapshot) -> Result<()>;
+}
+
+/// A change of a GPIO pin's configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GpioChange {
+  /// Exports or unexports the pin.
+  Export(DeviceState),
+  /// Sets the direction.
+  Direction(PinDirection),
+  /// Drives the pin, which has to be an output.
+  State(PinState),
+}
+
+/// The configuration of a GPIO pin before a transaction.
+#[derive(Debug)]
+pub struct GpioSnapshot {
+  // The direction and, for outputs, the state, or `None` if the pin wasn't
+  // exported.
+  exported: Option<(PinDirection, Option<PinState>)>,
+}
+
+impl Transactional for GPIO {
+  type Change = GpioChange;
+  type Snapshot = GpioSnapshot;
+
+  fn snapshot(&self) -> Result<GpioSnapshot> {
+    if !self.is_exported() {
+      return Ok(GpioSnapshot { exported: None });
+    }
+    let direction = self.direction()?;
+    let state = match direction {
+      PinDirection::Out => Some(self.read()?),
+      PinDirection::In => None,
+    };
+    Ok(GpioSnapshot { exported: Some((direction, state)) })
+  }
+
+  fn apply(&mut self, change: &GpioChange) -> Result<()> {
+    match *change {
+      GpioChange::Export(state) => self.set_export(state),
+      GpioChange::Direction(direction) => self.set_direction(direction),
+      GpioChange::State(state) => self.write(state),
+    }
+  }
+
+  fn restore(&mut self, snapshot: &GpioSnapshot) -> Result<()> {
+    let (direction, state) = match snapshot.exported {
+      Some(exported) => exported,
+      None => return self.set_export(DeviceState::Unexported),
+    };
+    self.set_export(DeviceState::Exported)?;
+    self.set_direction(direction)?;
+    match state {
+      Some(state) => self.write(state),
+      None => Ok(()),
+    }
+  }
+}
+
+/// A change of a PWM's configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PwmChange {
+  /// Exports or unexports the PWM.
+  Export(DeviceState),
+  /// Sets the period in nanoseconds.
+  Period(u32),
+  /// Sets the duty cycle in nanoseconds.
+  DutyCycle(u32),
+  /// Sets the polarity.
+  Polarity(PWMPolarity),
+  /// Enables or disables the PWM.
+  State(PWMState),
+}
+
+/// The configuration of a PWM before a transaction.
+#[derive(Debug)]
+pub struct PwmSnapshot {
+  // The period, duty cycle, polarity and state, or `None` if the PWM wasn't
+  // exported.
+  exported: Option<(u32, u32, PWMPolarity, PWMState)>,
+}
+
+impl Transactional for PWM {
+  type Change = PwmChange;
+  type Snapshot = PwmSnapshot;
+
+  fn snapshot(&self) -> Result<PwmSnapshot> {
+    if !self.sysfs_path().exists() {
+      return Ok(PwmSnapshot { exported: None });
+    }
+    Ok(PwmSnapshot {
+         exported: Some((self.get_period()?, self.get_duty_cycle()?, self.polarity()?, self.get_state()?)),
+       })
+  }
+
+  fn apply(&mut self, change: &PwmChange) -> Result<()> {
+    match *change {
+      PwmChange::Export(state) => self.set_export(state),
+      PwmChange::Period(period_ns) => self.set_period(period_ns),
+      PwmChange::DutyCycle(duty_cycle_ns) => self.set_duty_cycle(duty_cycle_ns),
+      PwmChange::Polarity(polarity) => self.set_polarity(polarity),
+      PwmChange::State(state) => self.set_state(state),
+    }
+  }
+
+  fn restore(&mut self, snapshot: &PwmSnapshot) -> Result<()> {
+    let (period, duty_cycle, polarity, state) = match snapshot.exported {
+      Some(exported) => exported,
+      None => {
+        self.set_state(PWMState::Disabled)?;
+        return self.set_export(DeviceState::Unexported);
+      }
+    };
+    self.set_export(DeviceState::Exported)?;
+    // The polarity can only be changed while disabled.
+    self.set_state(PWMState::Disabled)?;
+    self.set_polarity(polarity)?;
+    // The duty cycle may never exceed the period, not even in between.
+    if period >= self.get_duty_cycle()? {
+      self.set_period(period)?;
+      self.set_duty_cycle(duty_cycle)?;
+    } else {
+      self.set_duty_cycle(duty_cycle)?;
+      self.set_period(period)?;
+    }
+    self.set_state(state)
+  }
+}
+
+/// A device staged in a transaction, with its changes.
+trait Staged: fmt::Debug {
+  /// Records the configuration and applies the changes.
+  fn apply(&mut self) -> Result<()>;
+
+  /// Restores the configuration, if it was recorded.
+  fn rollback(&mut self) -> Result<()>;
+}
+
+#[derive(Debug)]
+struct Staging<'a, D: Transactional + 'a> {
+  device: &'a mut D,
+  changes: Vec<D::Change>,
+  snapshot: Option<D::Snapshot>,
+}
+
+impl<'a, D: Transactional> Staged for Staging<'a, D> {
+  fn apply(&mut self) -> Result<()> {
+    self.snapshot = Some(self.device.snapshot()?);
+    for change in &self.changes {
+      self.device
+          .apply(change)
+          .chain_err(|| format!("Failed to apply {:?} to {:?}", change, self.device))?;
+    }
+    Ok(())
+  }
+
+  fn rollback(&mut self) -> Result<()> {
+    match self.snapshot.take() {
+      Some(snapshot) => self.device.restore(&snapshot),
+      None => Ok(()),
+    }
+  }
+}
+
+/// Configuration changes of several devices that are applied all or not at
+/// all.
+#[derive(Debug, Default)]
+pub struct Transaction<'a> {
+  staged: Vec<Box<dyn Staged + 'a>>,
+}
+
+impl<'a> Transaction<'a> {
+  /// Creates an empty transaction.
+  pub fn new() -> Transaction<'a> {
+    Transaction::default()
+  }
+
+  /// Stages `changes` of `device`, to be applied in order.
+  pub fn stage<D: Transactional>(&mut self, device: &'a mut D, changes: Vec<D::Change>) {
+    self.staged.push(Box::new(Staging {
+                                device,
+                                changes,
+                                snapshot: None,
+                              }));
+  }
+
+  /// Applies the staged changes.
+  ///
+  /// # Errors
+  ///
+  /// Fails on the first change that can't be applied, after restoring the
+  /// configuration of the devices changed so far, including the failing one.
+  /// If some couldn't be restored, the error says so, and the rest are
+  /// still restored.
+  pub fn apply(mut self) -> Result<()> {
+    for i in 0..self.staged.len() {
+      let error = match self.staged[i].apply() {
+        Ok(()) => continue,
+        Err(e) => e,
+      };
+      let failures: Vec<String> = self.staged[..=i]
+        .iter_mut()
+        .rev()
+        .filter_map(|staged| staged.rollback().err())
+        .map(|e| e.to_string())
+        .collect();
+      if failures.is_empty() {
+        return Err(error).chain_err(|| "Transaction rolled back");
+      }
+      return Err(error).chain_err(|| {
+        format!("Transaction rolled back, but restoring failed: {}", failures.join("; "))
+      });
+    }
+    Ok(())
+  }
+}
@@ -14,15 +14,28 @@
 //! This is currently a simple wrapper around the `serialport` library due to
 //! time constraints.
 
+use board;
 use errors::*;
-use serialport::open;
+use enums::DeviceState;
+use gpio::{self, Edge, GPIO, PinDirection, PinState};
+use nix::sys::termios::{TCIFLUSH, tcflush};
+use nix::unistd::dup;
+use pins::Pin;
+use serialport::posix::TTYPort;
 use serialport::prelude::*;
-use std::time::Duration;
+use std::io::{ErrorKind, Read, Write};
+use std::collections::VecDeque;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 /// The direction of the pin, which can be either an input or output.
-#[allow(missing_debug_implementations)]
+#[derive(Debug)]
 pub struct UART {
-  port: Box<SerialPort>,
+  port: TTYPort,
 }
 
 impl UART {
@@ -44,13 +57,17 @@ impl UART {
   /// Method fails if `uart_num` is an invalid UART port (i.e. isn't within 0-5)
   /// or if the kernel fails to open the port for some other reason.
   pub fn new(uart_num: u32) -> Result<(UART)> {
-    let port_path = format!("/dev/ttyO{}", uart_num);
+    let port_path = board::current().uart_path(uart_num);
     Ok(UART {
-         port: open(&port_path)
+         port: TTYPort::open(Path::new(&port_path), &Default::default())
            .chain_err(|| format!("Failed to open UART port #{}.", uart_num))?,
        })
   }
 
+  /// Returns the path of the port's device node, e.g. `/dev/ttyO2`.
+  pub fn port_name(&self) -> Option<String> {
+    self.port.port_name()
+  }
 
   /// Write data to a UART port.
   ///
@@ -131,6 +148,67 @@ impl UART {
   }
 
 
+  /// Write raw bytes to a UART port.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  ///
+  /// // Create a new UART using BB_UART2.
+  /// let mut uart = UART::new(2).unwrap();
+  ///
+  /// // Write three bytes to the UART port.
+  /// uart.write_bytes(&[0xFF, 0x01, 0x02]).unwrap();
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Method fails if the kernel rejects outgoing data for some reason.
+  pub fn write_bytes(&mut self, data: &[u8]) -> Result<()> {
+    self.port
+        .write_all(data)
+        .chain_err(|| "Failed to write to UART port.")?;
+    Ok(())
+  }
+
+  /// Blocks until all written data has been transmitted.
+  ///
+  /// # Errors
+  ///
+  /// Method fails if the kernel fails to drain the output buffer.
+  pub fn flush(&mut self) -> Result<()> {
+    self.port
+        .flush()
+        .chain_err(|| "Failed to flush UART port.")?;
+    Ok(())
+  }
+
+  /// Read exactly enough bytes from the UART port to fill `buf`.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  ///
+  /// // Create a new UART using BB_UART2.
+  /// let mut uart = UART::new(2).unwrap();
+  ///
+  /// // Read 4 bytes from the UART port.
+  /// let mut buf = [0; 4];
+  /// uart.read_exact(&mut buf).unwrap();
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Method fails if the port's timeout expires before `buf` is full.
+  pub fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+    self.port
+        .read_exact(buf)
+        .chain_err(|| "Failed to read from UART port.")?;
+    Ok(())
+  }
+
   /// Get the all of the UART port settings.
   pub fn settings(&self) -> SerialPortSettings {
     self.port.settings()
@@ -207,4 +285,599 @@ impl UART {
            .set_timeout(timeout)
            .chain_err(|| "Failed to set UART timeout.")?)
   }
+
+  /// Discards any data that was received but not read yet.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the kernel refuses to flush the port's input buffer.
+  pub fn clear_input(&self) -> Result<()> {
+    tcflush(self.port.as_raw_fd(), TCIFLUSH)
+      .chain_err(|| "Failed to clear UART input buffer.")?;
+    Ok(())
+  }
+
+  /// Detects the baud rate of the device on the other end of the port.
+  ///
+  /// The UART hardware doesn't report the timing of incoming bits, so
+  /// rather than timing the edges, which `measure_baud_rate()` does on the
+  /// RX pin muxed as a GPIO, each candidate baud rate is tried in turn: the
+  /// port listens for
+  /// `sample_time` and passes whatever it received to `is_valid`.
+  /// At a wrong baud rate the peer's data arrives as garbage (or not at all),
+  /// so the first rate whose data `is_valid` accepts is kept.
+  ///
+  /// The peer has to be sending while this runs.
+  /// For text-based devices (GPS receivers, modems, consoles) `is_text` makes
+  /// a good validator, binary protocols will want to check for a known header
+  /// or checksum instead.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// # extern crate libbeaglebone;
+  /// # extern crate serialport;
+  /// use libbeaglebone::prelude::*;
+  /// use libbeaglebone::uart::is_text;
+  /// use serialport::BaudRate;
+  /// use std::time::Duration;
+  ///
+  /// # fn main() {
+  /// let mut uart = UART::new(2).unwrap();
+  ///
+  /// // Find out how fast the NMEA sentences from a GPS are coming in.
+  /// let rates = [BaudRate::Baud4800, BaudRate::Baud9600, BaudRate::Baud38400,
+  ///              BaudRate::Baud115200];
+  /// let rate = uart.auto_baud(&rates, Duration::from_millis(500), is_text).unwrap();
+  /// println!("GPS is running at {} baud", rate.speed());
+  /// # }
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if none of the candidates produced valid data, in which case the
+  /// original baud rate is restored, or if the port can't be configured.
+  pub fn auto_baud<F>(&mut self,
+                      candidates: &[BaudRate],
+                      sample_time: Duration,
+                      is_valid: F)
+                      -> Result<BaudRate>
+    where F: Fn(&[u8]) -> bool
+  {
+    let original_rate = self.port.baud_rate();
+    let original_timeout = self.port.timeout();
+
+    let mut detected = None;
+    for &rate in candidates {
+      self.set_baud_rate(rate)?;
+      // Anything still buffered was received at the previous rate.
+      self.clear_input()?;
+      let sample = self.sample(sample_time)?;
+      if !sample.is_empty() && is_valid(&sample) {
+        detected = Some(rate);
+        break;
+      }
+    }
+
+    self.set_timeout(original_timeout)?;
+    match detected {
+      Some(rate) => Ok(rate),
+      None => {
+        if let Some(rate) = original_rate {
+          self.set_baud_rate(rate)?;
+        }
+        bail!("Failed to detect the UART baud rate.")
+      }
+    }
+  }
+
+  /// Creates a second handle to the same UART port.
+  ///
+  /// Both handles share the port's settings, which is mostly useful for
+  /// reading and writing from different threads.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the port's file descriptor can't be duplicated.
+  pub fn try_clone(&self) -> Result<UART> {
+    let fd = dup(self.port.as_raw_fd()).chain_err(|| "Failed to duplicate UART port.")?;
+    Ok(UART { port: unsafe { TTYPort::from_raw_fd(fd) } })
+  }
+
+  /// Reads everything that arrives within `duration`.
+  fn sample(&mut self, duration: Duration) -> Result<Vec<u8>> {
+    let deadline = Instant::now() + duration;
+    let mut sample = Vec::new();
+    let mut buf = [0; 256];
+
+    loop {
+      let remaining = deadline.saturating_duration_since(Instant::now());
+      if remaining == Duration::from_secs(0) {
+        return Ok(sample);
+      }
+      self.set_timeout(remaining)?;
+      match self.port.read(&mut buf) {
+        Ok(n) => sample.extend_from_slice(&buf[..n]),
+        Err(ref e) if e.kind() == ErrorKind::TimedOut => return Ok(sample),
+        Err(e) => return Err(e).chain_err(|| "Failed to read from UART port."),
+      }
+    }
+  }
+}
+
+/// A UART used as a half-duplex bus, where transmit and receive share a
+/// single wire.
+///
+/// Smart servos and several sensor buses connect TX and RX of the BeagleBone
+/// to the same data line, either directly through a resistor or using a
+/// tri-state buffer whose direction is switched by a GPIO.
+/// `HalfDuplexUART` switches the direction pin around every transmission and
+/// discards the echo of its own transmissions when both lines are tied
+/// together.
+#[derive(Debug)]
+pub struct HalfDuplexUART {
+  uart: UART,
+  direction_pin: Option<(GPIO, PinState)>,
+  echo: bool,
+}
+
+impl HalfDuplexUART {
+  /// Creates a new half-duplex bus on top of an UART.
+  ///
+  /// By default, TX and RX are assumed to be tied together, so every
+  /// transmission is echoed back and discarded, and no direction pin is used.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  /// use libbeaglebone::uart::HalfDuplexUART;
+  ///
+  /// let bus = HalfDuplexUART::new(UART::new(2).unwrap());
+  /// ```
+  pub fn new(uart: UART) -> HalfDuplexUART {
+    HalfDuplexUART {
+      uart,
+      direction_pin: None,
+      echo: true,
+    }
+  }
+
+  /// Uses a GPIO to switch the bus direction, e.g. the enable pin of a
+  /// buffer or an RS-485 transceiver.
+  ///
+  /// The pin is driven to `transmit_state` while transmitting, and to the
+  /// opposite state otherwise.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  /// use libbeaglebone::uart::HalfDuplexUART;
+  ///
+  /// let mut bus = HalfDuplexUART::new(UART::new(2).unwrap());
+  ///
+  /// // The buffer drives the bus while pin #45 is high, and the RX line
+  /// // doesn't see our own transmissions.
+  /// bus.set_direction_pin(GPIO_P8_11, PinState::High).unwrap();
+  /// bus.set_echo(false);
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if the pin can't be configured as a GPIO output.
+  pub fn set_direction_pin(&mut self, pin: Pin, transmit_state: PinState) -> Result<()> {
+    // Start out receiving, so the bus isn't driven while the pin is
+    // configured.
+    let receive_state = match transmit_state {
+      PinState::High => PinState::Low,
+      PinState::Low => PinState::High,
+    };
+    let gpio = gpio::output_driving(pin, receive_state)?;
+
+    self.direction_pin = Some((gpio, transmit_state));
+    Ok(())
+  }
+
+  /// Sets whether transmissions are echoed back on the receive line.
+  pub fn set_echo(&mut self, echo: bool) {
+    self.echo = echo;
+  }
+
+  /// Transmits data on the bus, and returns once the bus is released again.
+  ///
+  /// If transmissions are echoed, the echo is checked against the data sent,
+  /// which detects another device talking at the same time.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the data can't be written, if the echo doesn't arrive within
+  /// the UART's timeout, or if the echo doesn't match the data (a collision).
+  pub fn transmit(&mut self, data: &[u8]) -> Result<()> {
+    self.set_transmitting(true)?;
+    let result = self.uart.write_bytes(data).and_then(|_| self.uart.flush());
+    self.set_transmitting(false)?;
+    result?;
+
+    if self.echo {
+      let mut echo = vec![0; data.len()];
+      self.uart
+          .read_exact(&mut echo)
+          .chain_err(|| "Failed to read back half-duplex transmission.")?;
+      if echo != data {
+        bail!("Half-duplex bus collision: echo doesn't match transmitted data.");
+      }
+    }
+    Ok(())
+  }
+
+  /// Receives exactly enough bytes from the bus to fill `buf`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the UART's timeout expires before `buf` is full.
+  pub fn receive(&mut self, buf: &mut [u8]) -> Result<()> {
+    self.uart.read_exact(buf)
+  }
+
+  /// Discards stale data, transmits a request and waits for a response that
+  /// fills `response`.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  /// use libbeaglebone::uart::HalfDuplexUART;
+  ///
+  /// let mut bus = HalfDuplexUART::new(UART::new(2).unwrap());
+  ///
+  /// let mut response = [0; 6];
+  /// bus.transaction(&[0xFF, 0xFF, 0x01, 0x02, 0x01, 0xFB], &mut response).unwrap();
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if either the transmission or the reception fails.
+  pub fn transaction(&mut self, request: &[u8], response: &mut [u8]) -> Result<()> {
+    self.uart.clear_input()?;
+    self.transmit(request)?;
+    self.receive(response)
+  }
+
+  /// Returns a mutable reference to the underlying UART, e.g. to change its
+  /// baud rate or timeout.
+  pub fn uart(&mut self) -> &mut UART {
+    &mut self.uart
+  }
+
+  /// Drives the direction pin, if there is one.
+  fn set_transmitting(&mut self, transmitting: bool) -> Result<()> {
+    if let Some((ref mut gpio, ref transmit_state)) = self.direction_pin {
+      let state = match (transmitting, transmit_state) {
+        (true, &PinState::High) | (false, &PinState::Low) => PinState::High,
+        _ => PinState::Low,
+      };
+      gpio.write(state)?;
+    }
+    Ok(())
+  }
+}
+
+/// How long the receive thread of a `BufferedUART` blocks in a single read,
+/// which bounds how long dropping it takes.
+const RX_THREAD_TIMEOUT_MS: u64 = 50;
+
+/// Counters describing the traffic through a `BufferedUART`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BufferStats {
+  /// The number of bytes received from the port.
+  pub received: u64,
+  /// The number of received bytes that were dropped because the ring buffer
+  /// was full.
+  pub overflowed: u64,
+  /// The largest number of bytes that were waiting in the ring buffer at any
+  /// one time.
+  pub high_watermark: usize,
+}
+
+/// The ring buffer shared between a `BufferedUART` and its receive thread.
+#[derive(Debug)]
+struct RingBuffer {
+  data: VecDeque<u8>,
+  capacity: usize,
+  stats: BufferStats,
+  error: Option<String>,
+}
+
+/// A UART with a dedicated receive thread feeding a ring buffer.
+///
+/// At high baud rates (1-3 Mbaud GPS raw data or lidar scans) the kernel's
+/// receive buffer fills up in a few milliseconds, so an application that
+/// doesn't read often enough loses data.
+/// `BufferedUART` keeps reading in the background into a ring buffer of a
+/// configurable size, and counts anything that still gets dropped.
+#[derive(Debug)]
+pub struct BufferedUART {
+  uart: UART,
+  ring: Arc<(Mutex<RingBuffer>, Condvar)>,
+  running: Arc<AtomicBool>,
+  thread: Option<JoinHandle<()>>,
+}
+
+impl BufferedUART {
+  /// Starts buffering a UART's incoming data in a ring buffer of `capacity`
+  /// bytes.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  /// use libbeaglebone::uart::BufferedUART;
+  ///
+  /// // Buffer up to 1MB of incoming data from BB_UART4.
+  /// let uart = BufferedUART::new(UART::new(4).unwrap(), 1 << 20).unwrap();
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if `capacity` is zero or the port can't be shared with the
+  /// receive thread.
+  pub fn new(uart: UART, capacity: usize) -> Result<BufferedUART> {
+    if capacity == 0 {
+      bail!("UART ring buffer capacity must be greater than zero.");
+    }
+
+    let mut reader = uart.try_clone()?;
+    reader.set_timeout(Duration::from_millis(RX_THREAD_TIMEOUT_MS))?;
+
+    let ring = Arc::new((Mutex::new(RingBuffer {
+                                      data: VecDeque::with_capacity(capacity),
+                                      capacity,
+                                      stats: BufferStats::default(),
+                                      error: None,
+                                    }),
+                         Condvar::new()));
+    let running = Arc::new(AtomicBool::new(true));
+    let thread = {
+      let ring = ring.clone();
+      let running = running.clone();
+      thread::spawn(move || receive_into(reader, &ring, &running))
+    };
+
+    Ok(BufferedUART {
+      uart,
+      ring,
+      running,
+      thread: Some(thread),
+    })
+  }
+
+  /// Returns the number of bytes waiting in the ring buffer.
+  pub fn available(&self) -> usize {
+    self.ring.0.lock().unwrap().data.len()
+  }
+
+  /// Returns the traffic counters.
+  pub fn stats(&self) -> BufferStats {
+    self.ring.0.lock().unwrap().stats
+  }
+
+  /// Reads up to `buf.len()` bytes, waiting up to `timeout` for at least one
+  /// byte to arrive.
+  ///
+  /// Returns the number of bytes read, which is zero if the timeout expired.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  /// use libbeaglebone::uart::BufferedUART;
+  /// use std::time::Duration;
+  ///
+  /// let mut uart = BufferedUART::new(UART::new(4).unwrap(), 1 << 20).unwrap();
+  ///
+  /// let mut buf = [0; 4096];
+  /// let n = uart.read(&mut buf, Duration::from_millis(100)).unwrap();
+  /// println!("Read {} bytes, {} dropped so far", n, uart.stats().overflowed);
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if the receive thread stopped because the port couldn't be read.
+  pub fn read(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize> {
+    let deadline = Instant::now() + timeout;
+    let (ref lock, ref condvar) = *self.ring;
+    let mut ring = lock.lock().unwrap();
+
+    while ring.data.is_empty() {
+      if let Some(ref error) = ring.error {
+        bail!(format!("UART receive thread stopped: {}", error));
+      }
+      let remaining = deadline.saturating_duration_since(Instant::now());
+      if remaining == Duration::from_secs(0) {
+        return Ok(0);
+      }
+      ring = condvar.wait_timeout(ring, remaining).unwrap().0;
+    }
+
+    let n = buf.len().min(ring.data.len());
+    for (dst, src) in buf.iter_mut().zip(ring.data.drain(..n)) {
+      *dst = src;
+    }
+    Ok(n)
+  }
+
+  /// Reads exactly enough bytes to fill `buf`, waiting up to `timeout` in
+  /// total.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the timeout expires before `buf` is full, in which case the
+  /// bytes that did arrive are lost.
+  pub fn read_exact(&mut self, buf: &mut [u8], timeout: Duration) -> Result<()> {
+    let deadline = Instant::now() + timeout;
+    let mut filled = 0;
+    while filled < buf.len() {
+      let remaining = deadline.saturating_duration_since(Instant::now());
+      let n = self.read(&mut buf[filled..], remaining)?;
+      if n == 0 {
+        bail!(format!(
+          "Timed out reading from UART port after {} of {} bytes.",
+          filled,
+          buf.len()
+        ));
+      }
+      filled += n;
+    }
+    Ok(())
+  }
+
+  /// Discards all buffered data.
+  pub fn clear(&mut self) {
+    self.ring.0.lock().unwrap().data.clear();
+  }
+
+  /// Writes raw bytes to the port.
+  pub fn write_bytes(&mut self, data: &[u8]) -> Result<()> {
+    self.uart.write_bytes(data)
+  }
+}
+
+impl Drop for BufferedUART {
+  fn drop(&mut self) {
+    self.running.store(false, Ordering::SeqCst);
+    if let Some(thread) = self.thread.take() {
+      let _ = thread.join();
+    }
+  }
+}
+
+/// Moves everything received on `uart` into the ring buffer until `running`
+/// is cleared.
+fn receive_into(mut uart: UART, ring: &(Mutex<RingBuffer>, Condvar), running: &AtomicBool) {
+  let (ref lock, ref condvar) = *ring;
+  let mut chunk = [0; 4096];
+
+  while running.load(Ordering::SeqCst) {
+    let n = match uart.port.read(&mut chunk) {
+      Ok(n) => n,
+      Err(ref e) if e.kind() == ErrorKind::TimedOut => continue,
+      Err(e) => {
+        lock.lock().unwrap().error = Some(e.to_string());
+        condvar.notify_all();
+        return;
+      }
+    };
+
+    let mut ring = lock.lock().unwrap();
+    let free = ring.capacity - ring.data.len();
+    let kept = n.min(free);
+    ring.data.extend(&chunk[..kept]);
+    ring.stats.received += n as u64;
+    ring.stats.overflowed += (n - kept) as u64;
+    ring.stats.high_watermark = ring.stats.high_watermark.max(ring.data.len());
+    condvar.notify_all();
+  }
+}
+
+/// The rates `measure_baud_rate()` rounds to.
+const STANDARD_RATES: [BaudRate; 11] = [
+  BaudRate::Baud110,
+  BaudRate::Baud300,
+  BaudRate::Baud600,
+  BaudRate::Baud1200,
+  BaudRate::Baud2400,
+  BaudRate::Baud4800,
+  BaudRate::Baud9600,
+  BaudRate::Baud19200,
+  BaudRate::Baud38400,
+  BaudRate::Baud57600,
+  BaudRate::Baud115200,
+];
+
+/// Measures the baud rate of the signal on a UART's RX pin by timing the
+/// edges of `edges` transitions, with the pin muxed as a GPIO.
+///
+/// The shortest time between two edges is taken as one bit, so the peer
+/// has to send data with single bits, e.g. text, while this runs.
+/// The rate is rounded to a standard one within 5%, or returned as
+/// `BaudOther`.
+/// The pin is unexported afterwards, so it can be muxed back to the UART.
+///
+/// On a character device line the edges carry the kernel's timestamps,
+/// which are good to 19200 baud or so; over sysfs the timestamps are taken
+/// in userspace and only a few hundred baud can be measured.
+/// A glitch on the line makes the rate come out too high.
+///
+/// # Examples
+///
+/// ```no_run
+/// # extern crate libbeaglebone;
+/// use libbeaglebone::prelude::*;
+/// use libbeaglebone::uart::measure_baud_rate;
+/// use std::time::Duration;
+///
+/// # fn main() {
+/// // UART4's RX pin, after `config-pin P9.11 gpio`.
+/// let rate = measure_baud_rate(GPIO_P9_11, 100, Duration::from_secs(2)).unwrap();
+/// println!("The peer sends at {} baud", rate.speed());
+/// // Then `config-pin P9.11 uart` before opening UART4.
+/// # }
+/// ```
+///
+/// # Errors
+///
+/// Fails if `edges` is less than 2, if fewer edges arrived within
+/// `timeout`, or if the pin can't be configured as an edge-triggered GPIO
+/// input.
+pub fn measure_baud_rate(rx: Pin, edges: usize, timeout: Duration) -> Result<BaudRate> {
+  if edges < 2 {
+    bail!("Measuring a baud rate takes at least 2 edges");
+  }
+  let mut gpio = GPIO::new(rx);
+  gpio.set_export(DeviceState::Exported)?;
+  let shortest = gpio.set_direction(PinDirection::In)
+                     .and_then(|_| gpio.set_edge(Edge::Both))
+                     .and_then(|_| shortest_bit(&mut gpio, edges, timeout));
+  gpio.set_export(DeviceState::Unexported)?;
+  let speed = 1.0 / shortest?.as_secs_f64();
+  Ok(STANDARD_RATES.iter()
+                   .cloned()
+                   .find(|rate| (speed / rate.speed() as f64 - 1.0).abs() <= 0.05)
+                   .unwrap_or_else(|| BaudRate::BaudOther(speed.round() as usize)))
+}
+
+/// Returns the shortest time between `edges` consecutive edges of `gpio`.
+fn shortest_bit(gpio: &mut GPIO, edges: usize, timeout: Duration) -> Result<Duration> {
+  let deadline = Instant::now() + timeout;
+  let mut last = None;
+  let mut shortest: Option<Duration> = None;
+  for edge in 0..edges {
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    let timestamp = match gpio.wait_for_edge_timestamped(Some(remaining))? {
+      Some((_, timestamp)) if remaining > Duration::from_secs(0) => timestamp,
+      _ => bail!(format!("Only {} of {} edges arrived on GPIO pin #{} within {:?}",
+                         edge, edges, gpio.pin_num(), timeout)),
+    };
+    if let Some(last) = last {
+      let gap = timestamp.saturating_sub(last);
+      if gap > Duration::from_secs(0) {
+        shortest = Some(shortest.map_or(gap, |shortest| shortest.min(gap)));
+      }
+    }
+    last = Some(timestamp);
+  }
+  shortest.ok_or_else(|| format!("The edges on GPIO pin #{} all had the same timestamp", gpio.pin_num()).into())
+}
+
+/// Returns whether `data` looks like ASCII text.
+///
+/// Accepts data where at least 90% of the bytes are printable characters or
+/// whitespace, which is rarely the case for text received at the wrong baud
+/// rate.
+pub fn is_text(data: &[u8]) -> bool {
+  let printable = data.iter()
+                      .filter(|&&b| (0x20..0x7F).contains(&b) || b"\r\n\t".contains(&b))
+                      .count();
+  !data.is_empty() && printable * 10 >= data.len() * 9
 }
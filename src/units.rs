@@ -0,0 +1,134 @@
+//! The units module.
+//!
+//! PWM periods and duty cycles are plain numbers in sysfs: nanoseconds and,
+//! in this library, percentages.
+//! Passing microseconds where nanoseconds are expected, or a fraction where
+//! a percentage is, compiles fine and makes a servo twitch or a motor stand
+//! still.
+//! The typed setters of `PWM` take a `Duration`, `Hertz` or `DutyCycle`
+//! instead, which say what they are and are checked when they are made:
+//!
+//! ```no_run
+//! use libbeaglebone::prelude::*;
+//! use std::time::Duration;
+//!
+//! let mut pwm = PWM::new(0, 0);
+//! pwm.set_export(DeviceState::Exported).unwrap();
+//!
+//! pwm.set_hertz(Hertz::new(50.0).unwrap()).unwrap();
+//! pwm.set_duty_cycle_duration(Duration::from_micros(1500)).unwrap();
+//! // Or relative to the period.
+//! pwm.set_duty(DutyCycle::from_percent(7.5).unwrap()).unwrap();
+//! pwm.set_state(PWMState::Enabled).unwrap();
+//! ```
+
+use errors::*;
+use std::time::Duration;
+
+/// A frequency, greater than 0.
+///
+/// ```
+/// use libbeaglebone::units::Hertz;
+/// use std::time::Duration;
+///
+/// let hz = Hertz::new(2000.0).unwrap();
+/// assert_eq!(hz.period(), Duration::from_micros(500));
+/// assert_eq!(Hertz::from_period(Duration::from_millis(20)).unwrap().value(), 50.0);
+/// assert!(Hertz::new(0.0).is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Hertz(f32);
+
+impl Hertz {
+  /// Makes a frequency of `hz`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if `hz` isn't a finite number greater than 0.
+  pub fn new(hz: f32) -> Result<Hertz> {
+    if !hz.is_finite() || hz <= 0.0 {
+      bail!(format!("Frequency {}Hz isn't a number greater than 0", hz));
+    }
+    Ok(Hertz(hz))
+  }
+
+  /// Makes the frequency of `period`.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the period is zero.
+  pub fn from_period(period: Duration) -> Result<Hertz> {
+    Hertz::new((1.0 / period.as_secs_f64()) as f32)
+  }
+
+  /// Returns the frequency in Hz.
+  pub fn value(self) -> f32 {
+    self.0
+  }
+
+  /// Returns the period of the frequency.
+  pub fn period(self) -> Duration {
+    Duration::from_secs_f64(1.0 / f64::from(self.0))
+  }
+}
+
+/// A duty cycle, from 0% to 100% of the period.
+///
+/// ```
+/// use libbeaglebone::units::DutyCycle;
+/// use std::time::Duration;
+///
+/// let duty = DutyCycle::from_percent(25.0).unwrap();
+/// assert_eq!(duty.fraction(), 0.25);
+/// assert_eq!(duty.of(Duration::from_millis(20)), Duration::from_millis(5));
+/// assert_eq!(DutyCycle::from_fraction(0.25).unwrap(), duty);
+/// assert!(DutyCycle::from_percent(250.0).is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct DutyCycle(f32);
+
+impl DutyCycle {
+  /// Makes a duty cycle of `percentage` percent.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the percentage isn't between 0 and 100.
+  pub fn from_percent(percentage: f32) -> Result<DutyCycle> {
+    if !(0.0..=100.0).contains(&percentage) {
+      bail!(format!("Duty cycle {}% isn't between 0% and 100%", percentage));
+    }
+    Ok(DutyCycle(percentage / 100.0))
+  }
+
+  /// Makes a duty cycle of `fraction` of the period.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the fraction isn't between 0.0 and 1.0.
+  pub fn from_fraction(fraction: f32) -> Result<DutyCycle> {
+    if !(0.0..=1.0).contains(&fraction) {
+      bail!(format!("Duty cycle fraction {} isn't between 0 and 1", fraction));
+    }
+    Ok(DutyCycle(fraction))
+  }
+
+  /// Makes a duty cycle of `fraction`, clamped to 0.0 to 1.0.
+  pub(crate) fn clamped(fraction: f32) -> DutyCycle {
+    DutyCycle(fraction.clamp(0.0, 1.0))
+  }
+
+  /// Returns the duty cycle in percent.
+  pub fn percent(self) -> f32 {
+    self.0 * 100.0
+  }
+
+  /// Returns the duty cycle as a fraction of the period.
+  pub fn fraction(self) -> f32 {
+    self.0
+  }
+
+  /// Returns how long the output is active in `period`.
+  pub fn of(self, period: Duration) -> Duration {
+    period.mul_f64(f64::from(self.0))
+  }
+}
@@ -1,9 +1,14 @@
 //! General utility functions used internally throughout the crate, such as
 //! writing to sysfs files.
 
+use board;
 use errors::*;
+use gpio::PinState;
+use journal::{self, Entry};
+use nix::poll::{EventFlags, POLLERR, POLLPRI, PollFd, poll};
 use std::fs::File;
-use std::io::{Write, Read};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
 
 pub trait Writeable {
   fn write_file(self, data: &str) -> Result<()>;
@@ -56,3 +61,92 @@ impl Readable for File {
     Ok(value_str)
   }
 }
+
+/// A GPIO value file that can be waited on for edge interrupts.
+///
+/// Writing to the pin's `edge` file makes the kernel signal `POLLPRI` on its
+/// `value` file whenever a matching edge occurs.
+#[derive(Debug)]
+pub struct EdgeWaiter {
+  pin_num: u8,
+  pin_path: String,
+  value_file: File,
+}
+
+impl EdgeWaiter {
+  /// Enables edge detection on an exported GPIO input and opens it for
+  /// waiting.
+  ///
+  /// `edge` is written to the sysfs `edge` file, i.e. it must be one of
+  /// "rising", "falling" or "both".
+  pub fn new(pin_num: u8, edge: &str) -> Result<EdgeWaiter> {
+    let pin_path = board::current().gpio_path(pin_num);
+    let edge_path = format!("{}/edge", pin_path);
+    edge_path.write_file(edge).chain_err(|| {
+      format!("Failed to enable edge detection on GPIO pin #{}", pin_num)
+    })?;
+
+    let mut waiter = EdgeWaiter {
+      pin_num,
+      value_file: File::open(format!("{}/value", pin_path))
+        .chain_err(|| format!("Failed to open GPIO pin #{} for reading", pin_num))?,
+      pin_path,
+    };
+
+    // The value has to be read once before polling, otherwise the first poll
+    // returns immediately.
+    let _ = waiter.read_level()?;
+    Ok(waiter)
+  }
+
+  /// Waits up to `timeout_ms` milliseconds for an edge, or forever if
+  /// negative.
+  ///
+  /// Returns the pin's logic level right after the edge (true for high), or
+  /// `None` if the timeout expired.
+  pub fn wait(&mut self, timeout_ms: i32) -> Result<Option<bool>> {
+    let mut fds = [PollFd::new(self.value_file.as_raw_fd(), POLLPRI | POLLERR, EventFlags::empty())];
+    let ready = poll(&mut fds, timeout_ms)
+      .chain_err(|| format!("Failed to wait for an edge on GPIO pin #{}", self.pin_num))?;
+    if ready == 0 {
+      return Ok(None);
+    }
+    self.acknowledge().map(Some)
+  }
+
+  /// Acknowledges an edge signaled by `POLLPRI` on the file, e.g. by a
+  /// `poll()` over several of them, and returns the pin's logic level right
+  /// after it.
+  pub fn acknowledge(&mut self) -> Result<bool> {
+    // Reading the value also acknowledges the edge.
+    let level = self.read_level()?;
+    journal::record(Entry::GpioEdge {
+                      pin: self.pin_num,
+                      state: if level { PinState::High } else { PinState::Low },
+                    });
+    Ok(level)
+  }
+
+  /// Reads the current level from the start of the value file.
+  fn read_level(&mut self) -> Result<bool> {
+    let mut buf = [0; 2];
+    let _ = self.value_file
+                .seek(SeekFrom::Start(0))
+                .and_then(|_| self.value_file.read(&mut buf))
+                .chain_err(|| format!("Failed to read from GPIO pin #{}", self.pin_num))?;
+    Ok(buf[0] == b'1')
+  }
+}
+
+impl AsRawFd for EdgeWaiter {
+  fn as_raw_fd(&self) -> RawFd {
+    self.value_file.as_raw_fd()
+  }
+}
+
+impl Drop for EdgeWaiter {
+  fn drop(&mut self) {
+    let edge_path = format!("{}/edge", self.pin_path);
+    let _ = edge_path.write_file("none");
+  }
+}
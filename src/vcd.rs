@@ -0,0 +1,192 @@
+//! The VCD recording module.
+//!
+//! When a protocol doesn't work, the first question is what the program
+//! actually drove.
+//! While a `Recording` is running, every level written to a GPIO and every
+//! change of a PWM's state and duty cycle is recorded with a timestamp, and
+//! saved as a value change dump (VCD) file that waveform viewers like GTKWave
+//! display like a logic analyzer capture:
+//!
+//! ```no_run
+//! use libbeaglebone::prelude::*;
+//! use libbeaglebone::vcd::Recording;
+//!
+//! let recording = Recording::start().unwrap();
+//!
+//! let mut clock = GPIO::new(GPIO_P8_11);
+//! clock.set_export(DeviceState::Exported).unwrap();
+//! clock.set_direction(PinDirection::Out).unwrap();
+//! for _ in 0..8 {
+//!   clock.write(PinState::High).unwrap();
+//!   clock.write(PinState::Low).unwrap();
+//! }
+//!
+//! recording.save("clock.vcd").unwrap();
+//! ```
+//!
+//! The timestamps are taken when the writes to sysfs complete, so they show
+//! what the program commanded, not when the pins actually changed.
+//! Only one recording can run at a time.
+
+use errors::*;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+/// Whether a recording is running, to skip the lock when it isn't.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// The trace of the running recording.
+static TRACE: Mutex<Option<Trace>> = Mutex::new(None);
+
+/// The recorded changes.
+#[derive(Debug)]
+struct Trace {
+  start: Instant,
+  // The name and width in bits of every signal, in order of appearance.
+  signals: Vec<(String, u32)>,
+  index: HashMap<String, usize>,
+  // The time in ns, signal index and new value of every change.
+  changes: Vec<(u64, usize, u64)>,
+}
+
+/// Records a change of the signal `name`, which is `width` bits wide, to
+/// `value`, if a recording is running.
+///
+/// The library records GPIO and PWM changes by itself; this is for drivers
+/// that want to add signals of their own, e.g. the state of a protocol
+/// decoder.
+/// The name is only formatted while recording, so this is cheap otherwise.
+///
+/// # Examples
+///
+/// ```no_run
+/// use libbeaglebone::vcd;
+///
+/// let channel = 2;
+/// vcd::record(format_args!("decoder{}_state", channel), 8, 0x15);
+/// ```
+pub fn record(name: fmt::Arguments<'_>, width: u32, value: u64) {
+  if !ACTIVE.load(Ordering::Relaxed) {
+    return;
+  }
+  let name = name.to_string();
+  let mut trace = TRACE.lock().unwrap_or_else(|e| e.into_inner());
+  if let Some(ref mut trace) = *trace {
+    let time = trace.start.elapsed().as_nanos() as u64;
+    let signal = match trace.index.get(&name) {
+      Some(&signal) => signal,
+      None => {
+        trace.signals.push((name.clone(), width));
+        let _ = trace.index.insert(name, trace.signals.len() - 1);
+        trace.signals.len() - 1
+      }
+    };
+    trace.changes.push((time, signal, value));
+  }
+}
+
+/// A running recording of output changes.
+#[derive(Debug)]
+pub struct Recording {
+  saved: bool,
+}
+
+impl Recording {
+  /// Starts recording.
+  ///
+  /// # Errors
+  ///
+  /// Fails if another recording is running already.
+  pub fn start() -> Result<Recording> {
+    let mut trace = TRACE.lock().unwrap_or_else(|e| e.into_inner());
+    if trace.is_some() {
+      bail!("A VCD recording is running already");
+    }
+    *trace = Some(Trace {
+      start: Instant::now(),
+      signals: Vec::new(),
+      index: HashMap::new(),
+      changes: Vec::new(),
+    });
+    ACTIVE.store(true, Ordering::Relaxed);
+    Ok(Recording { saved: false })
+  }
+
+  /// Stops recording and saves the recorded changes as a VCD file.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the file can't be written.
+  pub fn save<P: AsRef<Path>>(mut self, path: P) -> Result<()> {
+    self.saved = true;
+    let trace = stop();
+    let path = path.as_ref();
+    match trace {
+      Some(trace) => {
+        File::create(path)
+          .and_then(|file| write_vcd(&trace, &mut BufWriter::new(file)))
+          .chain_err(|| format!("Failed to write VCD file {}", path.display()))
+      }
+      None => bail!("The VCD recording was lost"),
+    }
+  }
+}
+
+impl Drop for Recording {
+  fn drop(&mut self) {
+    if !self.saved {
+      let _ = stop();
+    }
+  }
+}
+
+fn stop() -> Option<Trace> {
+  ACTIVE.store(false, Ordering::Relaxed);
+  TRACE.lock().unwrap_or_else(|e| e.into_inner()).take()
+}
+
+/// Returns the VCD identifier of the signal with the given index.
+fn identifier(mut index: usize) -> String {
+  // Identifiers are made of the printable ASCII characters '!' to '~'.
+  let mut id = String::new();
+  loop {
+    id.push((b'!' + (index % 94) as u8) as char);
+    index /= 94;
+    if index == 0 {
+      return id;
+    }
+    index -= 1;
+  }
+}
+
+fn write_vcd<W: Write>(trace: &Trace, out: &mut W) -> io::Result<()> {
+  writeln!(out, "$version libbeaglebone $end")?;
+  writeln!(out, "$timescale 1ns $end")?;
+  writeln!(out, "$scope module libbeaglebone $end")?;
+  for (index, &(ref name, width)) in trace.signals.iter().enumerate() {
+    let kind = if width == 1 { "wire" } else { "integer" };
+    writeln!(out, "$var {} {} {} {} $end", kind, width, identifier(index), name)?;
+  }
+  writeln!(out, "$upscope $end")?;
+  writeln!(out, "$enddefinitions $end")?;
+
+  let mut last_time = None;
+  for &(time, signal, value) in &trace.changes {
+    if last_time != Some(time) {
+      writeln!(out, "#{}", time)?;
+      last_time = Some(time);
+    }
+    if trace.signals[signal].1 == 1 {
+      writeln!(out, "{}{}", value & 1, identifier(signal))?;
+    } else {
+      writeln!(out, "b{:b} {}", value, identifier(signal))?;
+    }
+  }
+  out.flush()
+}
@@ -0,0 +1,294 @@
+//! The 1-Wire module.
+//!
+//! The kernel's w1 subsystem runs 1-Wire buses, usually bit-banged on a GPIO
+//! by the w1-gpio driver, searches them for devices and lists those under
+//! `/sys/bus/w1/devices`, named by family code and serial number, e.g.
+//! `28-0316a2791aff` for a DS18B20 temperature probe:
+//!
+//! ```no_run
+//! use libbeaglebone::w1::{self, DS18B20};
+//!
+//! // Run a bus on P9_12, unless the device tree does already.
+//! w1::enable_gpio_bus("P9_12").unwrap();
+//!
+//! for probe in DS18B20::all().unwrap() {
+//!   println!("{}: {}°C", probe.id(), probe.read_celsius().unwrap());
+//! }
+//! ```
+//!
+//! A DS18B20 takes up to 750ms to convert a temperature at 12 bits, and
+//! reading one makes the kernel convert and wait.
+//! With many probes on a bus, `convert_all()` has all of them convert at
+//! once instead, so the readings after it return right away, on kernels
+//! whose driver supports bulk conversions.
+//! The bus needs a 4.7k pull-up to 3.3V on its data line.
+
+use capemgr;
+use errors::*;
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+use util::*;
+
+/// The directory the kernel lists the 1-Wire devices and bus masters in.
+const DEVICES_PATH: &str = "/sys/bus/w1/devices";
+
+/// The family code of the DS18B20.
+pub const DS18B20_FAMILY: u8 = 0x28;
+
+/// How long a DS18B20 takes to convert at 12 bits.
+const CONVERSION_TIME: Duration = Duration::from_millis(750);
+
+/// Applies the overlay running a w1-gpio bus on the header pin `pin`, e.g.
+/// "P9_12" or "P9.12", see `capemgr::apply_overlay()`, and waits for the
+/// kernel to register the bus.
+///
+/// The overlay is `BB-W1-<pin>`, e.g. "BB-W1-P9.12", as shipped in the
+/// BeagleBone's `/lib/firmware`.
+///
+/// # Errors
+///
+/// Fails if the overlay can't be applied, or if no bus shows up.
+pub fn enable_gpio_bus(pin: &str) -> Result<()> {
+  let overlay = format!("BB-W1-{}", pin.replace('_', "."));
+  let before = buses()?.len();
+  let applied_before = capemgr::loaded_overlays()?.contains(&overlay);
+  capemgr::apply_overlay(&overlay)?;
+  if applied_before {
+    return Ok(());
+  }
+  for _ in 0..20 {
+    if buses()?.len() > before {
+      return Ok(());
+    }
+    thread::sleep(Duration::from_millis(100));
+  }
+  bail!(format!("No 1-Wire bus showed up after applying overlay {}", overlay))
+}
+
+/// Returns the names of the bus masters, e.g. "w1_bus_master1".
+///
+/// # Errors
+///
+/// Fails if the devices directory exists but can't be read.
+pub fn buses() -> Result<Vec<String>> {
+  Ok(entries()?.into_iter().filter(|name| name.starts_with("w1_bus_master")).collect())
+}
+
+/// Returns the devices on all buses.
+///
+/// # Errors
+///
+/// Fails if the devices directory exists but can't be read.
+pub fn devices() -> Result<Vec<W1Device>> {
+  Ok(entries()?.into_iter().filter_map(|name| W1Device::new(&name).ok()).collect())
+}
+
+/// Makes all DS18B20s on all buses convert their temperature at once, and
+/// waits for them to finish.
+///
+/// # Errors
+///
+/// Fails if a bus master doesn't support bulk conversions, which takes a
+/// kernel from 5.6 on.
+pub fn convert_all() -> Result<()> {
+  for bus in buses()? {
+    let path = format!("{}/{}/therm_bulk_read", DEVICES_PATH, bus);
+    path.as_str()
+        .write_file("trigger")
+        .chain_err(|| format!("1-Wire bus {} can't convert its DS18B20s at once", bus))?;
+  }
+  thread::sleep(CONVERSION_TIME);
+  Ok(())
+}
+
+/// Parses the `w1_slave` file of a DS18B20 into the temperature in °C.
+///
+/// # Examples
+///
+/// ```
+/// use libbeaglebone::w1::parse_w1_slave;
+///
+/// let w1_slave = "72 01 4b 46 7f ff 0e 10 57 : crc=57 YES\n\
+///                 72 01 4b 46 7f ff 0e 10 57 t=23125\n";
+/// assert_eq!(parse_w1_slave(w1_slave).unwrap(), 23.125);
+/// assert!(parse_w1_slave(&w1_slave.replace("YES", "NO")).is_err());
+/// ```
+///
+/// # Errors
+///
+/// Fails if the CRC didn't match, or if there's no temperature.
+pub fn parse_w1_slave(w1_slave: &str) -> Result<f32> {
+  let mut lines = w1_slave.lines();
+  if !lines.next().is_some_and(|line| line.trim_end().ends_with("YES")) {
+    bail!("The DS18B20's CRC doesn't match");
+  }
+  let millis = lines.next()
+                    .and_then(|line| line.split("t=").nth(1))
+                    .and_then(|value| value.trim().parse::<i32>().ok());
+  match millis {
+    Some(millis) => Ok(millis as f32 / 1000.0),
+    None => bail!(format!("No temperature in {:?}", w1_slave)),
+  }
+}
+
+/// Returns the names in the devices directory, none if there's no w1
+/// subsystem.
+fn entries() -> Result<Vec<String>> {
+  if !Path::new(DEVICES_PATH).exists() {
+    return Ok(Vec::new());
+  }
+  let mut names = Vec::new();
+  for entry in fs::read_dir(DEVICES_PATH).chain_err(|| "Failed to list the 1-Wire devices")? {
+    let entry = entry.chain_err(|| "Failed to list the 1-Wire devices")?;
+    names.push(entry.file_name().to_string_lossy().into_owned());
+  }
+  names.sort();
+  Ok(names)
+}
+
+/// A device on a 1-Wire bus.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct W1Device {
+  id: String,
+  family: u8,
+}
+
+impl W1Device {
+  /// Creates a device from its ID, e.g. "28-0316a2791aff".
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use libbeaglebone::w1::{DS18B20_FAMILY, W1Device};
+  ///
+  /// let device = W1Device::new("28-0316a2791aff").unwrap();
+  /// assert_eq!(device.family(), DS18B20_FAMILY);
+  /// assert!(W1Device::new("w1_bus_master1").is_err());
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if `id` isn't a family code and a serial number.
+  pub fn new(id: &str) -> Result<W1Device> {
+    let family = id.split_once('-')
+                   .filter(|&(_, serial)| !serial.is_empty() && serial.chars().all(|c| c.is_ascii_hexdigit()))
+                   .and_then(|(family, _)| u8::from_str_radix(family, 16).ok());
+    match family {
+      Some(family) => Ok(W1Device {
+        id: id.to_string(),
+        family,
+      }),
+      None => bail!(format!("{:?} isn't a 1-Wire device ID", id)),
+    }
+  }
+
+  /// Returns the ID.
+  pub fn id(&self) -> &str {
+    &self.id
+  }
+
+  /// Returns the family code, e.g. `DS18B20_FAMILY`.
+  pub fn family(&self) -> u8 {
+    self.family
+  }
+
+  /// Returns whether the device is still on the bus.
+  pub fn is_present(&self) -> bool {
+    Path::new(&self.path("")).exists()
+  }
+
+  /// Returns the path of an attribute of the device.
+  fn path(&self, attribute: &str) -> String {
+    format!("{}/{}/{}", DEVICES_PATH, self.id, attribute)
+  }
+}
+
+/// A DS18B20 temperature probe.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DS18B20 {
+  device: W1Device,
+}
+
+impl DS18B20 {
+  /// Talks to the DS18B20 with the ID `id`, e.g. "28-0316a2791aff".
+  ///
+  /// # Errors
+  ///
+  /// Fails if `id` isn't a DS18B20's.
+  pub fn new(id: &str) -> Result<DS18B20> {
+    let device = W1Device::new(id)?;
+    if device.family() != DS18B20_FAMILY {
+      bail!(format!("1-Wire device {} isn't a DS18B20", id));
+    }
+    Ok(DS18B20 { device })
+  }
+
+  /// Returns the DS18B20s on all buses.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the devices can't be listed.
+  pub fn all() -> Result<Vec<DS18B20>> {
+    Ok(devices()?.into_iter()
+                 .filter(|device| device.family() == DS18B20_FAMILY)
+                 .map(|device| DS18B20 { device })
+                 .collect())
+  }
+
+  /// Returns the ID.
+  pub fn id(&self) -> &str {
+    self.device.id()
+  }
+
+  /// Returns the device.
+  pub fn device(&self) -> &W1Device {
+    &self.device
+  }
+
+  /// Reads the temperature in °C, converting it first unless `convert_all()`
+  /// just did.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the probe doesn't answer, e.g. because it was unplugged, or
+  /// if the CRC of its answer doesn't match.
+  pub fn read_celsius(&self) -> Result<f32> {
+    let temperature = self.device.path("temperature");
+    if Path::new(&temperature).exists() {
+      let millis = temperature.as_str()
+                              .read_file()
+                              .chain_err(|| format!("Failed to read DS18B20 {}", self.id()))?
+                              .trim()
+                              .parse::<i32>()
+                              .chain_err(|| format!("Failed to parse the temperature of DS18B20 {}", self.id()))?;
+      return Ok(millis as f32 / 1000.0);
+    }
+    let w1_slave = self.device
+                       .path("w1_slave")
+                       .as_str()
+                       .read_file()
+                       .chain_err(|| format!("Failed to read DS18B20 {}", self.id()))?;
+    parse_w1_slave(&w1_slave).chain_err(|| format!("Failed to read DS18B20 {}", self.id()))
+  }
+
+  /// Sets the resolution of the conversions, 9 to 12 bits, where 9 bits
+  /// convert in 94ms, in steps of 0.5°C, and 12 bits in 750ms, in steps of
+  /// 0.0625°C.
+  ///
+  /// # Errors
+  ///
+  /// Fails if `bits` is out of range, or if the kernel can't set the
+  /// resolution, which takes 5.6 or later.
+  pub fn set_resolution(&self, bits: u8) -> Result<()> {
+    if !(9..=12).contains(&bits) {
+      bail!(format!("A DS18B20 converts at 9 to 12 bits, not {}", bits));
+    }
+    self.device
+        .path("resolution")
+        .as_str()
+        .write_file(&bits.to_string())
+        .chain_err(|| format!("Failed to set the resolution of DS18B20 {}", self.id()))
+  }
+}
@@ -0,0 +1,430 @@
+//! The XBee module.
+//!
+//! XBee radio modules are a popular wireless link for BeagleBone projects.
+//! In API mode, everything exchanged with the module over the UART is wrapped
+//! in frames with a length and a checksum, which lets the application address
+//! individual remote nodes and configure the module with AT commands without
+//! leaving data mode.
+//!
+//! This module implements the frame format (including the escaping used by
+//! API mode 2) and the most common frame types.
+//! Set the module's `AP` parameter to 1 or 2 (using XCTU or `ATAP`) before
+//! use.
+
+use errors::*;
+use uart::UART;
+
+/// The byte that starts every API frame.
+const START_DELIMITER: u8 = 0x7E;
+
+/// The byte that marks an escaped byte in API mode 2.
+const ESCAPE: u8 = 0x7D;
+
+/// Escaped bytes are XORed with this value.
+const ESCAPE_XOR: u8 = 0x20;
+
+/// Bytes that have to be escaped in API mode 2.
+const ESCAPED_BYTES: [u8; 4] = [START_DELIMITER, ESCAPE, 0x11, 0x13];
+
+// Frame type identifiers.
+const AT_COMMAND: u8 = 0x08;
+const TRANSMIT_REQUEST: u8 = 0x10;
+const AT_COMMAND_RESPONSE: u8 = 0x88;
+const TRANSMIT_STATUS: u8 = 0x8B;
+const RECEIVE_PACKET: u8 = 0x90;
+
+/// The 64-bit address used to broadcast to all nodes.
+pub const BROADCAST_ADDRESS: u64 = 0xFFFF;
+
+/// The 16-bit network address used when it's unknown.
+pub const UNKNOWN_NETWORK_ADDRESS: u16 = 0xFFFE;
+
+/// An XBee API frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame {
+  /// Queries or sets a parameter of the local module.
+  ATCommand {
+    /// Identifies the response, 0 for no response.
+    frame_id: u8,
+    /// The two-letter command, e.g. `*b"NI"`.
+    command: [u8; 2],
+    /// The new parameter value, empty to query it.
+    parameter: Vec<u8>,
+  },
+  /// The local module's response to an `ATCommand`.
+  ATCommandResponse {
+    /// The `frame_id` of the command.
+    frame_id: u8,
+    /// The two-letter command.
+    command: [u8; 2],
+    /// 0 if the command succeeded.
+    status: u8,
+    /// The queried parameter value, if any.
+    data: Vec<u8>,
+  },
+  /// Sends data to a remote node.
+  TransmitRequest {
+    /// Identifies the transmit status, 0 for no status.
+    frame_id: u8,
+    /// The 64-bit address of the destination.
+    destination: u64,
+    /// The 16-bit network address of the destination, if known.
+    destination_network: u16,
+    /// The maximum number of hops for a broadcast, 0 for the maximum.
+    broadcast_radius: u8,
+    /// Transmit options.
+    options: u8,
+    /// The payload.
+    data: Vec<u8>,
+  },
+  /// Reports whether a `TransmitRequest` was delivered.
+  TransmitStatus {
+    /// The `frame_id` of the request.
+    frame_id: u8,
+    /// The 16-bit network address the data was delivered to.
+    destination_network: u16,
+    /// The number of retries that were needed.
+    retry_count: u8,
+    /// 0 if the data was delivered.
+    delivery_status: u8,
+    /// Whether route or address discovery was needed.
+    discovery_status: u8,
+  },
+  /// Data received from a remote node.
+  ReceivePacket {
+    /// The 64-bit address of the sender.
+    source: u64,
+    /// The 16-bit network address of the sender.
+    source_network: u16,
+    /// Receive options, e.g. whether the packet was a broadcast.
+    options: u8,
+    /// The payload.
+    data: Vec<u8>,
+  },
+  /// Any other frame type, given as its type identifier and raw contents.
+  Other {
+    /// The frame type identifier.
+    frame_type: u8,
+    /// The frame contents following the type identifier.
+    data: Vec<u8>,
+  },
+}
+
+impl Frame {
+  /// Returns the frame data, i.e. the type identifier followed by the frame's
+  /// contents.
+  pub fn frame_data(&self) -> Vec<u8> {
+    let mut out = Vec::new();
+    match *self {
+      Frame::ATCommand { frame_id, command, ref parameter } => {
+        out.extend_from_slice(&[AT_COMMAND, frame_id, command[0], command[1]]);
+        out.extend_from_slice(parameter);
+      }
+      Frame::ATCommandResponse { frame_id, command, status, ref data } => {
+        out.extend_from_slice(&[AT_COMMAND_RESPONSE, frame_id, command[0], command[1], status]);
+        out.extend_from_slice(data);
+      }
+      Frame::TransmitRequest { frame_id,
+                               destination,
+                               destination_network,
+                               broadcast_radius,
+                               options,
+                               ref data, } => {
+        out.extend_from_slice(&[TRANSMIT_REQUEST, frame_id]);
+        out.extend_from_slice(&destination.to_be_bytes());
+        out.extend_from_slice(&destination_network.to_be_bytes());
+        out.extend_from_slice(&[broadcast_radius, options]);
+        out.extend_from_slice(data);
+      }
+      Frame::TransmitStatus { frame_id,
+                              destination_network,
+                              retry_count,
+                              delivery_status,
+                              discovery_status, } => {
+        out.extend_from_slice(&[TRANSMIT_STATUS, frame_id]);
+        out.extend_from_slice(&destination_network.to_be_bytes());
+        out.extend_from_slice(&[retry_count, delivery_status, discovery_status]);
+      }
+      Frame::ReceivePacket { source, source_network, options, ref data } => {
+        out.push(RECEIVE_PACKET);
+        out.extend_from_slice(&source.to_be_bytes());
+        out.extend_from_slice(&source_network.to_be_bytes());
+        out.push(options);
+        out.extend_from_slice(data);
+      }
+      Frame::Other { frame_type, ref data } => {
+        out.push(frame_type);
+        out.extend_from_slice(data);
+      }
+    }
+    out
+  }
+
+  /// Parses frame data, i.e. the type identifier followed by the frame's
+  /// contents.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the frame data is too short for its frame type.
+  pub fn from_frame_data(frame_data: &[u8]) -> Result<Frame> {
+    let (frame_type, d) = match frame_data.split_first() {
+      Some((&frame_type, d)) => (frame_type, d),
+      None => bail!("XBee frame is empty"),
+    };
+    let min_len = match frame_type {
+      AT_COMMAND => 3,
+      AT_COMMAND_RESPONSE => 4,
+      TRANSMIT_REQUEST => 13,
+      TRANSMIT_STATUS => 6,
+      RECEIVE_PACKET => 11,
+      _ => 0,
+    };
+    if d.len() < min_len {
+      bail!(format!("XBee frame of type 0x{:02X} is too short", frame_type));
+    }
+
+    Ok(match frame_type {
+      AT_COMMAND => Frame::ATCommand {
+        frame_id: d[0],
+        command: [d[1], d[2]],
+        parameter: d[3..].to_vec(),
+      },
+      AT_COMMAND_RESPONSE => Frame::ATCommandResponse {
+        frame_id: d[0],
+        command: [d[1], d[2]],
+        status: d[3],
+        data: d[4..].to_vec(),
+      },
+      TRANSMIT_REQUEST => Frame::TransmitRequest {
+        frame_id: d[0],
+        destination: be_u64(&d[1..9]),
+        destination_network: be_u16(&d[9..11]),
+        broadcast_radius: d[11],
+        options: d[12],
+        data: d[13..].to_vec(),
+      },
+      TRANSMIT_STATUS => Frame::TransmitStatus {
+        frame_id: d[0],
+        destination_network: be_u16(&d[1..3]),
+        retry_count: d[3],
+        delivery_status: d[4],
+        discovery_status: d[5],
+      },
+      RECEIVE_PACKET => Frame::ReceivePacket {
+        source: be_u64(&d[0..8]),
+        source_network: be_u16(&d[8..10]),
+        options: d[10],
+        data: d[11..].to_vec(),
+      },
+      _ => Frame::Other {
+        frame_type,
+        data: d.to_vec(),
+      },
+    })
+  }
+
+  /// Encodes the frame for transmission, including delimiter, length and
+  /// checksum.
+  ///
+  /// If `escaped` is true, the frame is escaped as needed for API mode 2.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use libbeaglebone::xbee::Frame;
+  ///
+  /// let frame = Frame::ATCommand { frame_id: 1, command: *b"NJ", parameter: vec![] };
+  /// assert_eq!(frame.encode(false), vec![0x7E, 0x00, 0x04, 0x08, 0x01, 0x4E, 0x4A, 0x5E]);
+  /// ```
+  pub fn encode(&self, escaped: bool) -> Vec<u8> {
+    let frame_data = self.frame_data();
+    let len = frame_data.len() as u16;
+
+    let mut unescaped = Vec::with_capacity(frame_data.len() + 3);
+    unescaped.extend_from_slice(&len.to_be_bytes());
+    unescaped.extend_from_slice(&frame_data);
+    unescaped.push(checksum(&frame_data));
+
+    let mut out = vec![START_DELIMITER];
+    for &b in &unescaped {
+      if escaped && ESCAPED_BYTES.contains(&b) {
+        out.push(ESCAPE);
+        out.push(b ^ ESCAPE_XOR);
+      } else {
+        out.push(b);
+      }
+    }
+    out
+  }
+}
+
+/// Computes the checksum of the frame data.
+pub fn checksum(frame_data: &[u8]) -> u8 {
+  0xFF - frame_data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+/// Represents an XBee module in API mode connected to a UART.
+#[derive(Debug)]
+pub struct XBee {
+  uart: UART,
+  escaped: bool,
+  next_frame_id: u8,
+}
+
+impl XBee {
+  /// Creates a new XBee on a UART.
+  ///
+  /// `escaped` selects API mode 2 (`AP` = 2) instead of API mode 1.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  /// use libbeaglebone::xbee::XBee;
+  ///
+  /// let xbee = XBee::new(UART::new(1).unwrap(), true);
+  /// ```
+  pub fn new(uart: UART, escaped: bool) -> XBee {
+    XBee {
+      uart,
+      escaped,
+      next_frame_id: 1,
+    }
+  }
+
+  /// Sends a frame to the module.
+  pub fn send(&mut self, frame: &Frame) -> Result<()> {
+    self.uart
+        .write_bytes(&frame.encode(self.escaped))
+        .chain_err(|| "Failed to send XBee frame")
+  }
+
+  /// Waits for the next valid frame from the module.
+  ///
+  /// Bytes before the start delimiter are skipped, as are frames with a bad
+  /// checksum.
+  ///
+  /// # Errors
+  ///
+  /// Fails if no frame arrives within the UART's timeout.
+  pub fn receive(&mut self) -> Result<Frame> {
+    loop {
+      if self.read_raw_byte()? != START_DELIMITER {
+        continue;
+      }
+
+      let len = u16::from(self.read_byte()?) << 8 | u16::from(self.read_byte()?);
+      let mut frame_data = vec![0; len as usize];
+      for b in &mut frame_data {
+        *b = self.read_byte()?;
+      }
+      if self.read_byte()? == checksum(&frame_data) {
+        return Frame::from_frame_data(&frame_data);
+      }
+    }
+  }
+
+  /// Transmits data to a remote node, returning the frame ID of the transmit
+  /// status the module will report.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  /// use libbeaglebone::xbee::{BROADCAST_ADDRESS, XBee};
+  ///
+  /// let mut xbee = XBee::new(UART::new(1).unwrap(), true);
+  /// xbee.transmit(BROADCAST_ADDRESS, b"hello!").unwrap();
+  /// ```
+  pub fn transmit(&mut self, destination: u64, data: &[u8]) -> Result<u8> {
+    let frame_id = self.frame_id();
+    self.send(&Frame::TransmitRequest {
+      frame_id,
+      destination,
+      destination_network: UNKNOWN_NETWORK_ADDRESS,
+      broadcast_radius: 0,
+      options: 0,
+      data: data.to_vec(),
+    })?;
+    Ok(frame_id)
+  }
+
+  /// Runs an AT command on the local module and returns the response data.
+  ///
+  /// Frames other than the command's response that arrive in the meantime
+  /// are discarded.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use libbeaglebone::prelude::*;
+  /// use libbeaglebone::xbee::XBee;
+  ///
+  /// let mut xbee = XBee::new(UART::new(1).unwrap(), true);
+  ///
+  /// // Read the node identifier.
+  /// let name = xbee.at_command(*b"NI", &[]).unwrap();
+  /// println!("{}", String::from_utf8_lossy(&name));
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Fails if no response arrives within the UART's timeout, or if the
+  /// module reports an error status.
+  pub fn at_command(&mut self, command: [u8; 2], parameter: &[u8]) -> Result<Vec<u8>> {
+    let frame_id = self.frame_id();
+    self.send(&Frame::ATCommand {
+      frame_id,
+      command,
+      parameter: parameter.to_vec(),
+    })?;
+
+    loop {
+      if let Frame::ATCommandResponse { frame_id: id, status, data, .. } = self.receive()? {
+        if id != frame_id {
+          continue;
+        }
+        if status != 0 {
+          bail!(format!(
+            "XBee AT command {} failed with status {}",
+            String::from_utf8_lossy(&command),
+            status
+          ));
+        }
+        return Ok(data);
+      }
+    }
+  }
+
+  /// Returns the next frame ID, skipping 0 which disables responses.
+  fn frame_id(&mut self) -> u8 {
+    let frame_id = self.next_frame_id;
+    self.next_frame_id = self.next_frame_id.wrapping_add(1).max(1);
+    frame_id
+  }
+
+  /// Reads a single byte without unescaping it.
+  fn read_raw_byte(&mut self) -> Result<u8> {
+    let mut buf = [0];
+    self.uart.read_exact(&mut buf)?;
+    Ok(buf[0])
+  }
+
+  /// Reads a single, unescaped byte of a frame.
+  fn read_byte(&mut self) -> Result<u8> {
+    let b = self.read_raw_byte()?;
+    if self.escaped && b == ESCAPE {
+      Ok(self.read_raw_byte()? ^ ESCAPE_XOR)
+    } else {
+      Ok(b)
+    }
+  }
+}
+
+fn be_u16(bytes: &[u8]) -> u16 {
+  u16::from(bytes[0]) << 8 | u16::from(bytes[1])
+}
+
+fn be_u64(bytes: &[u8]) -> u64 {
+  bytes.iter().fold(0, |value, &b| value << 8 | u64::from(b))
+}